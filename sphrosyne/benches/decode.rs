@@ -0,0 +1,51 @@
+//! How fast the states clients send are decoded, which every state goes through.
+//! Run with `cargo bench -p sphrosyne --bench decode`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use serde::Deserialize;
+use sphrosyne::wire::{decode_frame, decode_json_state};
+use vigem_client_c::{X360Buttons, X360State};
+
+/// Text messages the way they were decoded before bare states had a way around it, trying
+/// every typed message before a bare state and buffering the JSON to do so
+#[derive(Deserialize)]
+#[serde(untagged)]
+#[allow(dead_code)]
+enum Untagged {
+    Tagged(Tagged),
+    State(X360State),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Tagged {
+    Attach,
+}
+
+fn decode(c: &mut Criterion) {
+    let state = X360State::builder()
+        .press(X360Buttons::A | X360Buttons::DPAD_LEFT)
+        .right_trigger(200)
+        .left_stick(-12_000, 30_000)
+        .build();
+    let mut binary = vec![1];
+    binary.extend_from_slice(&state.to_bytes());
+    binary.extend_from_slice(&1234.5_f64.to_le_bytes());
+    let json = serde_json::to_string(&state).unwrap();
+
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("binary", |b| {
+        b.iter(|| decode_frame(black_box(&binary)).unwrap())
+    });
+    group.bench_function("json", |b| {
+        b.iter(|| decode_json_state(black_box(&json)).unwrap())
+    });
+    group.bench_function("json, untagged", |b| {
+        b.iter(|| serde_json::from_str::<Untagged>(black_box(&json)).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
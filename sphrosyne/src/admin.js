@@ -0,0 +1,60 @@
+// @ts-check
+
+/**
+ * POST to one of a pad's admin routes and show what the server said
+ * @param {number} id
+ * @param {string} action
+ * @param {HTMLElement} output
+ */
+async function padAction(id, action, output) {
+  try {
+    const response = await fetch(`/admin/pads/${id}/${action}`, { method: "POST" });
+    const text = await response.text();
+    output.textContent = text || `${response.status} ${response.statusText}`;
+  } catch (error) {
+    output.textContent = `${error}`;
+  }
+}
+
+/**
+ * Make a button running one of a pad's admin routes
+ * @param {string} label
+ * @param {number} id
+ * @param {string} action
+ * @param {HTMLElement} output
+ */
+function actionButton(label, id, action, output) {
+  const button = document.createElement("button");
+  button.textContent = label;
+  button.addEventListener("click", () => padAction(id, action, output));
+  return button;
+}
+
+window.addEventListener("DOMContentLoaded", async function () {
+  const pads = /** @type {HTMLElement} */ (document.getElementById("pads"));
+  /** @type {{ id: number; ip: string; }[]} */
+  let clients;
+  try {
+    clients = await (await fetch("/admin/pads")).json();
+  } catch (error) {
+    pads.textContent = `Couldn't list the pads: ${error}`;
+    return;
+  }
+  if (clients.length === 0) {
+    pads.textContent = "No pads are in use.";
+    return;
+  }
+  for (const { id, ip } of clients) {
+    const row = document.createElement("p");
+    const output = document.createElement("span");
+    row.append(
+      `Pad ${id} (${ip}) `,
+      actionButton("Test rumble", id, "test-rumble", output),
+      " ",
+      actionButton("Test input", id, "test-input", output),
+      " ",
+      output
+    );
+    pads.append(row);
+  }
+});
@@ -0,0 +1,242 @@
+//! Which addresses may talk to us at all, so that only your own devices on a shared network can
+//! get a pad
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use eyre::{format_err, Report};
+
+/// A network in CIDR notation, e.g. `192.168.1.0/24`, or a single address without the prefix
+/// length
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+/// An IPv4 address mapped into IPv6, as `::ffff:a.b.c.d`, as the IPv4 address it stands for.
+/// Dual stack sockets report IPv4 clients that way.
+fn unmap(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => match v6.octets() {
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a, b, c, d] => {
+                IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+            }
+            _ => addr,
+        },
+        IpAddr::V4(_) => addr,
+    }
+}
+
+impl Cidr {
+    const fn v4(a: u8, b: u8, c: u8, d: u8, prefix: u8) -> Self {
+        Self {
+            addr: IpAddr::V4(Ipv4Addr::new(a, b, c, d)),
+            prefix,
+        }
+    }
+
+    /// Check whether the address is in this network
+    pub(crate) fn contains(&self, addr: IpAddr) -> bool {
+        // The high bits of an address, as many as the prefix is long
+        fn masked(bits: u128, width: u8, prefix: u8) -> u128 {
+            match u32::from(width - prefix) {
+                128 => 0,
+                shift => bits >> shift,
+            }
+        }
+
+        match (self.addr, unmap(addr)) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                let (network, addr) = (u32::from(network).into(), u32::from(addr).into());
+                masked(network, 32, self.prefix) == masked(addr, 32, self.prefix)
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                let (network, addr) = (u128::from(network), u128::from(addr));
+                masked(network, 128, self.prefix) == masked(addr, 128, self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Report> {
+        let invalid = || {
+            format_err!(
+                "{:?} is neither an address nor a network like 10.0.0.0/8",
+                s
+            )
+        };
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let width = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| invalid())?,
+            None => width,
+        };
+        if prefix > width {
+            return Err(invalid());
+        }
+
+        // Networks within the IPv4 addresses mapped into IPv6 are IPv4 networks really, which
+        // is what IPv4 clients are compared against
+        match (addr, unmap(addr)) {
+            (IpAddr::V6(_), addr @ IpAddr::V4(_)) if prefix >= 96 => Ok(Self {
+                addr,
+                prefix: prefix - 96,
+            }),
+            _ => Ok(Self { addr, prefix }),
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// The networks allowed when none are given: loopback and the private ones of RFC 1918
+const PRIVATE: [Cidr; 5] = [
+    Cidr::v4(127, 0, 0, 0, 8),
+    Cidr {
+        addr: IpAddr::V6(Ipv6Addr::LOCALHOST),
+        prefix: 128,
+    },
+    Cidr::v4(10, 0, 0, 0, 8),
+    Cidr::v4(172, 16, 0, 0, 12),
+    Cidr::v4(192, 168, 0, 0, 16),
+];
+
+/// Which addresses may send us requests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Allowlist {
+    /// Anybody who can reach us
+    All,
+
+    /// Only the addresses in these networks
+    Only(Vec<Cidr>),
+}
+
+impl Default for Allowlist {
+    fn default() -> Self {
+        Self::Only(PRIVATE.to_vec())
+    }
+}
+
+impl Allowlist {
+    /// Check whether the address may send us requests
+    pub(crate) fn allows(&self, addr: IpAddr) -> bool {
+        match self {
+            Self::All => true,
+            Self::Only(networks) => networks.iter().any(|network| network.contains(addr)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(cidr("10.1.2.3"), Cidr::v4(10, 1, 2, 3, 32));
+        assert_eq!(cidr("10.0.0.0/8"), Cidr::v4(10, 0, 0, 0, 8));
+        assert_eq!(cidr("::1").to_string(), "::1/128");
+        assert_eq!(cidr("fd00::/8").to_string(), "fd00::/8");
+        assert_eq!(cidr("::ffff:192.168.0.0/112"), Cidr::v4(192, 168, 0, 0, 16));
+        for invalid in &[
+            "",
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0/8",
+            "10.0.0.0/",
+            "10.0.0.0/-1",
+        ] {
+            assert!(invalid.parse::<Cidr>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_contains() {
+        let network = cidr("192.168.1.0/24");
+        assert!(network.contains(ip("192.168.1.0")));
+        assert!(network.contains(ip("192.168.1.255")));
+        assert!(!network.contains(ip("192.168.2.1")));
+        assert!(!network.contains(ip("::1")));
+
+        // A single address only contains itself
+        let single = cidr("192.168.1.7/32");
+        assert!(single.contains(ip("192.168.1.7")));
+        assert!(!single.contains(ip("192.168.1.6")));
+        assert!(!single.contains(ip("192.168.1.8")));
+        let single = cidr("fe80::1/128");
+        assert!(single.contains(ip("fe80::1")));
+        assert!(!single.contains(ip("fe80::2")));
+
+        // While the whole address space contains everything of its family
+        let everything = cidr("0.0.0.0/0");
+        assert!(everything.contains(ip("0.0.0.0")));
+        assert!(everything.contains(ip("255.255.255.255")));
+        assert!(!everything.contains(ip("::2")));
+        let everything = cidr("::/0");
+        assert!(everything.contains(ip("::")));
+        assert!(everything.contains(ip("ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff")));
+
+        // Odd prefix lengths cut through the middle of a byte
+        let network = cidr("172.16.0.0/12");
+        assert!(network.contains(ip("172.31.255.255")));
+        assert!(!network.contains(ip("172.32.0.0")));
+        assert!(!network.contains(ip("172.15.255.255")));
+    }
+
+    #[test]
+    fn test_mapped() {
+        // IPv4 clients of a dual stack socket show up as mapped addresses
+        assert!(cidr("10.0.0.0/8").contains(ip("::ffff:10.1.2.3")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("::ffff:11.1.2.3")));
+        assert!(cidr("::ffff:10.0.0.0/104").contains(ip("10.1.2.3")));
+        assert!(cidr("::ffff:10.0.0.0/104").contains(ip("::ffff:10.1.2.3")));
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let default = Allowlist::default();
+        for allowed in &[
+            "127.0.0.1",
+            "::1",
+            "::ffff:127.0.0.1",
+            "10.20.30.40",
+            "172.20.0.1",
+            "192.168.0.10",
+        ] {
+            assert!(default.allows(ip(allowed)), "{}", allowed);
+        }
+        for denied in &["8.8.8.8", "172.32.0.1", "2001:db8::1", "::ffff:8.8.8.8"] {
+            assert!(!default.allows(ip(denied)), "{}", denied);
+        }
+
+        let only = Allowlist::Only(vec![cidr("192.168.1.7")]);
+        assert!(only.allows(ip("192.168.1.7")));
+        assert!(!only.allows(ip("192.168.1.8")));
+        assert!(Allowlist::All.allows(ip("8.8.8.8")));
+        assert!(!Allowlist::Only(Vec::new()).allows(ip("127.0.0.1")));
+    }
+}
@@ -0,0 +1,361 @@
+//! Command line arguments
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use eyre::{format_err, Result};
+use vigem_client_c::{client::Preset, SocdPolicy};
+
+use crate::{
+    allow::{Allowlist, Cidr},
+    tls::Tls,
+};
+
+const HELP: &str = "\
+sphrosyne - use your phone as an Xbox 360 controller
+
+USAGE:
+  sphrosyne [OPTIONS]
+
+OPTIONS:
+  --bind ADDRESS     The address to listen on [default: 0.0.0.0]
+  --port PORT        The port to listen on [default: a random free port]
+  --hostname HOST    The host phones should connect to [default: this machine's IP address]
+  --name NAME        The name clients are shown, to tell machines running sphrosyne apart
+                     [default: this machine's hostname]
+  --allow CIDR       Only serve addresses in this network, can be repeated [default: loopback and
+                     the private networks 10.0.0.0/8, 172.16.0.0/12 and 192.168.0.0/16]
+  --allow-all        Serve any address which can reach us
+  --idle-timeout S   Seconds of silence after which a client loses its pad [default: 30]
+  --max-pads N       How many pads can be connected at once [default: 4]
+  --xinput-only      Refuse xbox 360 pads past the four XInput slots rather than warn about them
+  --players N        Create N pads at startup, which keep their player number across clients
+  --players-only     Refuse clients once the pads created at startup are all taken
+  --reclaim-grace S  Seconds a disconnected client has to get its pad back [default: 30]
+  --rate-limit N     Messages per second each client may send, 0 for no limit [default: 250]
+  --neutral-after MS Milliseconds without a state after which a pad lets go of everything, 0 for
+                     never, which clients can change for their own pads [default: 500]
+  --socd POLICY      What to do about a dpad held both ways at once: neutral, last_input or
+                     up_priority, which clients can change for their own pads [default: nothing]
+  --tick-rate N      Send pads' states to the bus N times per second rather than as they come, up
+                     to 1000, 0 for as they come [default: 0]
+  --pad-preset NAME  Give every pad the vendor and product ids of this controller, e.g.
+                     logitech_f310 [default: each pad type's own]
+  --latency-log S    Seconds between logging each pad's latency percentiles, 0 for never [default: 10]
+  --tls              Serve over HTTPS with a self-signed certificate, generated on the first run
+  --cert PATH        Serve over HTTPS with this PEM certificate, needs --key
+  --key PATH         The PEM private key of the certificate given with --cert
+  --record PATH      Record every update pads receive to a file
+  --replay PATH      Replay a recording through new pads instead of starting the server
+  --profiles F       Where to keep what's remembered of each device
+                     [default: sphrosyne-profiles.toml]
+  --snapshot PATH    Save who has which pad to PATH when shutting down, and give them their pads
+                     back after starting again with it
+  --keymap PATH      Which keys do what in keyboard mode [default: the built-in keymap]
+  --remaps PATH      The remapping profiles clients can pick by name [default: the built-in ones]
+  --layouts DIR      Where to keep the layouts clients arrange themselves
+                     [default: sphrosyne-layouts]
+  --assets DIR       Serve the pages' scripts and styles from DIR, re-reading them on every request
+  --no-mdns          Don't advertise the server on the local network over mDNS
+  --gui              Show a window with the QR code and the pads, needs the gui feature
+  --async            Serve with tokio and hyper rather than a thread per client, without TLS,
+                     needs the async-server feature
+  -h, --help         Print this message
+";
+
+/// The options sphrosyne was started with
+#[derive(Debug, Clone)]
+pub struct Args {
+    /// The address to bind the server to
+    pub(crate) bind: IpAddr,
+
+    /// The port to bind the server to, with 0 meaning any free port
+    pub(crate) port: u16,
+
+    /// The host put into the URLs we hand out, if not our own addresses
+    pub(crate) hostname: Option<String>,
+
+    /// The name we tell clients we go by, if not our own hostname
+    pub(crate) name: Option<String>,
+
+    /// Which addresses may send us requests
+    pub(crate) allow: Allowlist,
+
+    /// How long a client can go without sending us anything before it loses its pad
+    pub(crate) idle_timeout: Duration,
+
+    /// How many pads we let clients have at once, regardless of how many the bus could take
+    pub(crate) max_pads: usize,
+
+    /// Whether to refuse xbox 360 pads once the bus has no user index left to give them, as
+    /// games using XInput can't see them
+    pub(crate) xinput_only: bool,
+
+    /// How many pads to create at startup and keep around for clients to take
+    pub(crate) players: usize,
+
+    /// Whether to create pads for clients once the reserved ones are all taken
+    pub(crate) dynamic_pads: bool,
+
+    /// How long a pad is kept around after its client disconnects, waiting for it to come back
+    pub(crate) reclaim_grace: Duration,
+
+    /// How many messages per second a client may send, with 0 meaning as many as it likes
+    pub(crate) rate_limit: u32,
+
+    /// How many times per second to send pads' latest states to the bus, with 0 meaning as soon
+    /// as they come
+    pub(crate) tick_rate: u32,
+
+    /// How often to log each pad's latency percentiles, with 0 meaning never
+    pub(crate) latency_log: Duration,
+
+    /// How long a pad can go without a state from its client before it's made neutral, unless
+    /// the client asked for another interval, with 0 meaning never
+    pub(crate) neutral_after: Duration,
+
+    /// What's done about a pad's dpad being held both ways at once, unless its client asked for
+    /// something else, if anything
+    pub(crate) socd: Option<SocdPolicy>,
+
+    /// The controller every pad passes for, if not the default for its type
+    pub(crate) pad_preset: Option<Preset>,
+
+    /// Where our certificate comes from, if we're serving over HTTPS
+    pub(crate) tls: Option<Tls>,
+
+    /// Where to record the updates pads receive, if anywhere
+    pub(crate) record: Option<PathBuf>,
+
+    /// The recording to replay instead of starting the server, if any
+    pub(crate) replay: Option<PathBuf>,
+
+    /// The file each device's profile is kept in
+    pub(crate) profiles: PathBuf,
+
+    /// Where to save the pads when shutting down and restore them from when starting, if
+    /// anywhere
+    pub(crate) snapshot: Option<PathBuf>,
+
+    /// The keymap for clients in keyboard mode, if not the built-in one
+    pub(crate) keymap: Option<PathBuf>,
+
+    /// The remapping profiles clients can pick by name, if not the built-in ones
+    pub(crate) remaps: Option<PathBuf>,
+
+    /// The directory custom layouts are kept in
+    pub(crate) layouts: PathBuf,
+
+    /// The directory to read the pages' scripts and styles from, if not the built-in ones
+    pub(crate) assets: Option<PathBuf>,
+
+    /// Whether to advertise the server over mDNS
+    pub(crate) mdns: bool,
+
+    /// Whether to show the window, rather than only logging to the console
+    pub(crate) gui: bool,
+
+    /// Whether to serve with the async server rather than the blocking one
+    pub(crate) async_server: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            bind: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 0,
+            hostname: None,
+            name: None,
+            allow: Allowlist::default(),
+            idle_timeout: Duration::from_secs(30),
+            max_pads: 4,
+            xinput_only: false,
+            players: 0,
+            dynamic_pads: true,
+            reclaim_grace: Duration::from_secs(30),
+            rate_limit: 250,
+            tick_rate: 0,
+            latency_log: Duration::from_secs(10),
+            neutral_after: Duration::from_millis(500),
+            socd: None,
+            pad_preset: None,
+            tls: None,
+            record: None,
+            replay: None,
+            profiles: PathBuf::from("sphrosyne-profiles.toml"),
+            snapshot: None,
+            keymap: None,
+            remaps: None,
+            layouts: PathBuf::from("sphrosyne-layouts"),
+            assets: None,
+            mdns: true,
+            gui: false,
+            async_server: false,
+        }
+    }
+}
+
+impl Args {
+    /// Parse the arguments the program was started with, exiting after printing the help if asked to.
+    pub fn from_env() -> Result<Self> {
+        let mut args = pico_args::Arguments::from_env();
+
+        if args.contains(["-h", "--help"]) {
+            print!("{}", HELP);
+            std::process::exit(0);
+        }
+
+        let defaults = Self::default();
+        let self_signed = args.contains("--tls");
+        let cert: Option<PathBuf> = args.opt_value_from_str("--cert")?;
+        let key: Option<PathBuf> = args.opt_value_from_str("--key")?;
+        let allow_all = args.contains("--allow-all");
+        let allowed: Vec<Cidr> = args.values_from_str("--allow")?;
+        let parsed = Self {
+            bind: args.opt_value_from_str("--bind")?.unwrap_or(defaults.bind),
+            port: args.opt_value_from_str("--port")?.unwrap_or(defaults.port),
+            hostname: args.opt_value_from_str("--hostname")?,
+            name: args.opt_value_from_str("--name")?,
+            allow: match (allow_all, allowed.is_empty()) {
+                (true, true) => Allowlist::All,
+                (false, true) => defaults.allow,
+                (false, false) => Allowlist::Only(allowed),
+                (true, false) => {
+                    return Err(format_err!(
+                        "--allow and --allow-all can not be used together"
+                    ))
+                }
+            },
+            idle_timeout: args
+                .opt_value_from_str("--idle-timeout")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.idle_timeout),
+            max_pads: args
+                .opt_value_from_str("--max-pads")?
+                .unwrap_or(defaults.max_pads),
+            xinput_only: args.contains("--xinput-only"),
+            players: args
+                .opt_value_from_str("--players")?
+                .unwrap_or(defaults.players),
+            dynamic_pads: !args.contains("--players-only"),
+            reclaim_grace: args
+                .opt_value_from_str("--reclaim-grace")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.reclaim_grace),
+            rate_limit: args
+                .opt_value_from_str("--rate-limit")?
+                .unwrap_or(defaults.rate_limit),
+            tick_rate: args
+                .opt_value_from_str("--tick-rate")?
+                .unwrap_or(defaults.tick_rate),
+            latency_log: args
+                .opt_value_from_str("--latency-log")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.latency_log),
+            neutral_after: args
+                .opt_value_from_str("--neutral-after")?
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.neutral_after),
+            socd: args
+                .opt_value_from_fn("--socd", parse_socd)?
+                .or(defaults.socd),
+            pad_preset: args
+                .opt_value_from_fn("--pad-preset", parse_preset)?
+                .or(defaults.pad_preset),
+            tls: match (cert, key) {
+                (Some(cert), Some(key)) => Some(Tls::Provided { cert, key }),
+                (None, None) if self_signed => Some(Tls::SelfSigned),
+                (None, None) => defaults.tls,
+                _ => return Err(format_err!("--cert and --key have to be given together")),
+            },
+            record: args.opt_value_from_str("--record")?,
+            replay: args.opt_value_from_str("--replay")?,
+            profiles: args
+                .opt_value_from_str("--profiles")?
+                .unwrap_or(defaults.profiles),
+            snapshot: args.opt_value_from_str("--snapshot")?,
+            keymap: args.opt_value_from_str("--keymap")?,
+            remaps: args.opt_value_from_str("--remaps")?,
+            layouts: args
+                .opt_value_from_str("--layouts")?
+                .unwrap_or(defaults.layouts),
+            assets: args.opt_value_from_str("--assets")?,
+            mdns: !args.contains("--no-mdns"),
+            gui: args.contains("--gui"),
+            async_server: args.contains("--async"),
+        };
+        if parsed.record.is_some() && parsed.replay.is_some() {
+            return Err(format_err!(
+                "--record and --replay can not be used together"
+            ));
+        }
+
+        let remaining = args.finish();
+        if !remaining.is_empty() {
+            return Err(format_err!("Unexpected arguments {:?}", remaining));
+        }
+
+        Ok(parsed)
+    }
+
+    /// The recording to replay instead of starting the server, if any
+    pub fn replay(&self) -> Option<&Path> {
+        self.replay.as_deref()
+    }
+
+    /// Whether to show the window, rather than only logging to the console
+    pub fn gui(&self) -> bool {
+        self.gui
+    }
+
+    /// The address to bind the server to
+    pub(crate) fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind, self.port)
+    }
+
+    /// The host to put into our certificate, and into the URLs we hand out to clients if we can't
+    /// tell our addresses
+    pub(crate) fn public_host(&self) -> Result<String> {
+        match &self.hostname {
+            Some(hostname) => Ok(hostname.clone()),
+            None => own_hostname(),
+        }
+    }
+
+    /// The name we tell clients we go by, which tells us apart from other machines running
+    /// sphrosyne
+    pub(crate) fn instance_name(&self) -> Result<String> {
+        match &self.name {
+            Some(name) => Ok(name.clone()),
+            None => own_hostname(),
+        }
+    }
+}
+
+/// This machine's hostname
+fn own_hostname() -> Result<String> {
+    let host = gethostname::gethostname();
+    host.into_string()
+        .map_err(|host| format_err!("Invalid hostname {:?}", host))
+}
+
+/// A preset for `--pad-preset`, by its name
+fn parse_preset(name: &str) -> Result<Preset> {
+    Preset::find(name).ok_or_else(|| {
+        let names: Vec<_> = Preset::all().map(Preset::name).collect();
+        format_err!(
+            "Unknown pad preset {:?}, pick one of {}",
+            name,
+            names.join(", ")
+        )
+    })
+}
+
+/// A policy for `--socd`, named the same as in the clients' messages
+fn parse_socd(name: &str) -> Result<SocdPolicy> {
+    serde_json::from_value(serde_json::Value::from(name))
+        .map_err(|_| format_err!("Unknown SOCD policy {:?}", name))
+}
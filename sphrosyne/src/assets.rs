@@ -0,0 +1,187 @@
+//! The scripts and styles of our pages, which are built in unless `--assets` points us to a
+//! directory to read them from on every request, so that they can be worked on without
+//! rebuilding
+
+use std::{
+    borrow::Cow,
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::layout::{self, Layout};
+
+/// The stylesheet shared by every page
+pub(crate) const STYLE: &str = include_str!("style.css");
+
+/// The script running the controller page, besides its layout
+pub(crate) const CONTROLLER: &str = include_str!("controller.js");
+
+/// The script listing the pads on the index page, for this machine alone to test them from
+pub(crate) const ADMIN: &str = include_str!("admin.js");
+
+/// The URL path pages link to the stylesheet under, when it isn't inlined
+pub(crate) const STYLE_PATH: &str = "/style.css";
+
+/// The URL path pages link to the controller script under, when it isn't inlined
+pub(crate) const CONTROLLER_PATH: &str = "/controller.js";
+
+/// The URL path the index page links to the admin script under, when it isn't inlined
+pub(crate) const ADMIN_PATH: &str = "/admin.js";
+
+/// Where our pages' scripts and styles come from
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Assets {
+    /// The directory to read them from, if they're not the built in ones
+    dir: Option<PathBuf>,
+}
+
+impl Assets {
+    pub(crate) fn new(dir: Option<PathBuf>) -> Self {
+        Self { dir }
+    }
+
+    /// Whether pages should link to the assets rather than inline them, which they do when
+    /// they're read from disk so that reloading a page picks up changes to them
+    pub(crate) fn linked(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    /// The URL path pages link to a layout's script under, when it isn't inlined
+    pub(crate) fn layout_path(layout: &Layout) -> String {
+        format!("/layouts/{}.js", layout.name)
+    }
+
+    /// The asset served at the given URL path along with its content type, or `None` if
+    /// there's no such asset
+    pub(crate) fn get(&self, path: &str) -> io::Result<Option<(Cow<'static, str>, &'static str)>> {
+        let content_type = match content_type(path) {
+            Some(content_type) => content_type,
+            None => return Ok(None),
+        };
+
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => return Ok(embedded(path).map(|data| (Cow::Borrowed(data), content_type))),
+        };
+        let file = match file_in(dir, path) {
+            Some(file) if file.is_file() => file,
+            _ => return Ok(None),
+        };
+        match fs::read_to_string(file) {
+            Ok(data) => Ok(Some((Cow::Owned(data), content_type))),
+            // It may have been removed since we checked
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+/// The built in asset served at the given URL path, if there's one
+fn embedded(path: &str) -> Option<&'static str> {
+    match path {
+        STYLE_PATH => Some(STYLE),
+        CONTROLLER_PATH => Some(CONTROLLER),
+        ADMIN_PATH => Some(ADMIN),
+        _ => {
+            let name = path.strip_prefix("/layouts/")?.strip_suffix(".js")?;
+            match name {
+                "custom" => Some(layout::CUSTOM.script),
+                _ => Layout::find(name).map(|layout| layout.script),
+            }
+        }
+    }
+}
+
+/// The content type of the asset at the given URL path, going by its extension, or `None` if
+/// it's not a kind of file we serve
+fn content_type(path: &str) -> Option<&'static str> {
+    match Path::new(path).extension()?.to_str()? {
+        "js" => Some("application/javascript"),
+        "css" => Some("text/css"),
+        "html" => Some("text/html"),
+        _ => None,
+    }
+}
+
+/// The file the given URL path points to inside `dir`, or `None` if it could point outside of
+/// it. Every segment of the path has to be a plain name, so that neither `..` nor a drive or
+/// an absolute path can get out of the directory.
+fn file_in(dir: &Path, path: &str) -> Option<PathBuf> {
+    let mut file = dir.to_path_buf();
+    for segment in path.strip_prefix('/')?.split('/') {
+        let mut components = Path::new(segment).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), None) if name == segment => file.push(name),
+            _ => return None,
+        }
+    }
+    Some(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_in() {
+        let dir = Path::new("assets");
+        assert_eq!(
+            file_in(dir, "/layouts/standard.js"),
+            Some(dir.join("layouts").join("standard.js"))
+        );
+        assert_eq!(file_in(dir, "/style.css"), Some(dir.join("style.css")));
+
+        assert_eq!(file_in(dir, "/../secret.js"), None);
+        assert_eq!(file_in(dir, "/layouts/../../secret.js"), None);
+        assert_eq!(file_in(dir, "/./style.css"), None);
+        assert_eq!(file_in(dir, "//etc/passwd.js"), None);
+        assert_eq!(file_in(dir, "/layouts//standard.js"), None);
+        assert_eq!(file_in(dir, "style.css"), None);
+    }
+
+    #[test]
+    fn test_embedded() {
+        let assets = Assets::default();
+        assert!(!assets.linked());
+
+        let (style, content_type) = assets.get(STYLE_PATH).unwrap().unwrap();
+        assert_eq!(style, STYLE);
+        assert_eq!(content_type, "text/css");
+
+        let layout = Layout::default();
+        let (script, content_type) = assets.get(&Assets::layout_path(layout)).unwrap().unwrap();
+        assert_eq!(script, layout.script);
+        assert_eq!(content_type, "application/javascript");
+
+        let (script, _) = assets.get("/layouts/custom.js").unwrap().unwrap();
+        assert_eq!(script, layout::CUSTOM.script);
+
+        assert!(assets.get("/layouts/nope.js").unwrap().is_none());
+        assert!(assets.get("/Cargo.toml").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_from_disk() {
+        let dir = std::env::temp_dir().join(format!("sphrosyne-assets-{}", std::process::id()));
+        fs::create_dir_all(dir.join("folder.js")).unwrap();
+        fs::write(dir.join("controller.js"), "// edited").unwrap();
+        fs::write(dir.join("notes.txt"), "not an asset").unwrap();
+
+        let assets = Assets::new(Some(dir.clone()));
+        let controller = assets.get(CONTROLLER_PATH).unwrap();
+        let style = assets.get(STYLE_PATH).unwrap();
+        let notes = assets.get("/notes.txt").unwrap();
+        let folder = assets.get("/folder.js").unwrap();
+        fs::remove_dir_all(dir).unwrap();
+
+        assert!(assets.linked());
+        assert_eq!(
+            controller,
+            Some((Cow::Borrowed("// edited"), "application/javascript"))
+        );
+        // Files missing from the directory aren't replaced by the built in ones
+        assert_eq!(style, None);
+        assert_eq!(notes, None);
+        assert_eq!(folder, None);
+    }
+}
@@ -0,0 +1,499 @@
+//! The async server, picked with `--async`, which serves the same pages and websockets as the
+//! blocking one in [mainloop](crate::server::mainloop) with hyper on a tokio runtime rather than
+//! a thread per client.
+//!
+//! Requests are routed by the same [Router] and websockets handled by the same [Handler], so
+//! clients can't tell the two apart. Routing waits on the pads, so it's done on the runtime's
+//! blocking threads, one request at a time like the blocking server does. Websockets send their
+//! requests for the pads through a [bridge] instead of straight to them, as the pads' queue
+//! blocks whoever sends to it while it's full.
+//!
+//! Websockets can be written to whenever there's something for them here, rather than only
+//! after their client sent a message, so feedback reaches clients within [FLUSH_INTERVAL] and
+//! they're closed as soon as they have to go. There's no TLS yet.
+
+use std::{
+    convert::Infallible,
+    mem,
+    net::{self, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::SyncSender,
+        Arc, Mutex as StdMutex,
+    },
+    time::Duration,
+};
+
+use eyre::{format_err, Result};
+use futures_util::{stream::SplitSink, SinkExt, StreamExt};
+use hyper::{
+    body::HttpBody,
+    server::conn::Http,
+    service::service_fn,
+    upgrade::{OnUpgrade, Upgraded},
+    Body, Method, Request, Response, StatusCode,
+};
+use slog::{debug, info, warn, Logger};
+use tiny_http::Header;
+use tokio::{
+    net::TcpListener,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        Mutex,
+    },
+    task::{spawn_blocking, JoinHandle, JoinSet},
+    time::{interval, interval_at, timeout, Instant, MissedTickBehavior},
+};
+use tokio_tungstenite::WebSocketStream;
+use tungstenite::{protocol::Role, Message};
+
+use crate::{
+    args::Args,
+    auth::Token,
+    close::CloseReason,
+    custom_layout::MAX_LAYOUT_SIZE,
+    keymap::Keymap,
+    metrics::Metrics,
+    remap::RemapProfiles,
+    request::PadRequest,
+    server::{
+        bind_error, convert_key, websocket_config, Frontend, Handler, Incoming, PadSender, Reply,
+        Routed, Router, Session, Step, Upgrade, PING_INTERVAL, SHUTDOWN_POLL_INTERVAL,
+        SHUTDOWN_TIMEOUT,
+    },
+};
+
+/// How often websockets are checked for something to write, which is how late feedback can be
+const FLUSH_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Where a websocket writes its messages
+type Sink = SplitSink<WebSocketStream<Upgraded>, Message>;
+
+/// What every connection shares
+struct Shared {
+    logger: Logger,
+    router: Mutex<Router>,
+
+    /// Where websockets send their requests for the pads, until we stop bridging them as we
+    /// shut down
+    bridge: StdMutex<Option<UnboundedSender<PadRequest>>>,
+
+    /// The websockets' tasks, which we wait on as we shut down
+    websockets: StdMutex<JoinSet<()>>,
+
+    /// Why we have to stop serving, if we do
+    failed: StdMutex<Option<eyre::Report>>,
+
+    shutdown: Arc<AtomicBool>,
+}
+
+impl Shared {
+    /// Stop serving because of the given error, which [mainloop] returns, answering the request
+    /// that ran into it with a 500
+    fn fail(&self, error: eyre::Report) -> Response<Body> {
+        self.failed.lock().unwrap().get_or_insert(error);
+        response(Reply::status(500))
+    }
+}
+
+/// Pass on the requests websockets send to the returned sender to the pads, until every clone
+/// of it is gone
+fn bridge(tx: SyncSender<PadRequest>) -> (UnboundedSender<PadRequest>, JoinHandle<()>) {
+    let (bridge_tx, mut bridge_rx) = unbounded_channel();
+    let task = spawn_blocking(move || {
+        while let Some(request) = bridge_rx.blocking_recv() {
+            if tx.send(request).is_err() {
+                return;
+            }
+        }
+    });
+    (bridge_tx, task)
+}
+
+/// Convert a reply into hyper's kind of response
+fn response(reply: Reply) -> Response<Body> {
+    let mut response = Response::builder().status(reply.status);
+    for (field, value) in reply.headers {
+        response = response.header(field, value);
+    }
+    response.body(Body::from(reply.body)).unwrap()
+}
+
+/// The response completing the handshake of a request that wants to become a websocket,
+/// agreeing to the `echo` subprotocol if there's one
+fn handshake(headers: &[Header], echo: Option<String>) -> Result<Response<Body>> {
+    let key = &headers
+        .iter()
+        .find(|h| h.field.equiv("Sec-WebSocket-Key"))
+        .ok_or_else(|| format_err!("no websocket key"))?
+        .value;
+
+    let mut response = Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header("Upgrade", "websocket")
+        .header("Connection", "Upgrade")
+        .header("Sec-WebSocket-Accept", convert_key(key.as_str()));
+    // Browsers refuse the connection unless we agree to the subprotocol they offered
+    if let Some(echo) = echo {
+        response = response.header("Sec-WebSocket-Protocol", echo);
+    }
+    Ok(response.body(Body::empty())?)
+}
+
+/// Become a websocket once the handshake's response is sent
+async fn upgraded(on_upgrade: OnUpgrade) -> Result<WebSocketStream<Upgraded>> {
+    let upgraded = on_upgrade.await?;
+    Ok(WebSocketStream::from_raw_socket(upgraded, Role::Server, Some(websocket_config())).await)
+}
+
+/// Closing a websocket which is closed already is fine by us
+fn closed(result: tungstenite::Result<()>) -> Result<()> {
+    match result {
+        Ok(())
+        | Err(tungstenite::Error::ConnectionClosed)
+        | Err(tungstenite::Error::AlreadyClosed) => Ok(()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Tell a client there's no pad for it like the blocking server does, by accepting its
+/// connection only to close it right away
+async fn refuse_full(on_upgrade: OnUpgrade) -> Result<()> {
+    let mut ws = upgraded(on_upgrade).await?;
+    closed(ws.close(Some(CloseReason::ServerFull.frame())).await)
+}
+
+/// Watch a websocket's session like the blocking server's watchdog does, until `wake` is
+/// closed along with the websocket's handler
+async fn watch_session(
+    logger: Logger,
+    session: Arc<Session>,
+    req_tx: PadSender,
+    idle_timeout: Duration,
+    mut wake: UnboundedReceiver<()>,
+) {
+    let mut wait = PING_INTERVAL.min(idle_timeout);
+    loop {
+        if let Ok(None) = timeout(wait, wake.recv()).await {
+            return;
+        }
+        wait = match session.watch(&logger, &req_tx, idle_timeout) {
+            Some(wait) => wait,
+            None => return,
+        };
+    }
+}
+
+/// Write out whatever was queued for the client, e.g. the shutdown notice, and close the
+/// websocket for the given reason
+async fn close(handler: &mut Handler, sink: &mut Sink, reason: CloseReason) -> Result<()> {
+    for message in handler.outbox.drain() {
+        sink.feed(message).await?;
+    }
+    closed(sink.send(Message::Close(Some(reason.frame()))).await)
+}
+
+/// Handle a websocket's messages with its handler, writing out whatever there is for the client
+/// as soon as there's something
+async fn handle_websocket(handler: &mut Handler, ws: WebSocketStream<Upgraded>) -> Result<()> {
+    let (mut sink, mut stream) = ws.split();
+    for message in handler.greeting()? {
+        sink.feed(message).await?;
+    }
+    sink.flush().await?;
+
+    let mut pings = interval_at(Instant::now() + PING_INTERVAL, PING_INTERVAL);
+    let mut flushes = interval(FLUSH_INTERVAL);
+    flushes.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        tokio::select! {
+            msg = stream.next() => {
+                let msg = match msg {
+                    None | Some(Err(tungstenite::Error::ConnectionClosed)) => return Ok(()),
+                    Some(Err(error @ tungstenite::Error::Capacity(_))) => {
+                        handler.too_large();
+                        return Err(error.into());
+                    }
+                    Some(Err(error)) => return Err(error.into()),
+                    Some(Ok(msg)) => msg,
+                };
+                match handler.receive(msg)? {
+                    Step::Continue => {}
+                    Step::Attach(reply_rx) => {
+                        let reply = spawn_blocking(move || reply_rx.recv()).await??;
+                        sink.send(handler.attached(reply)?).await?;
+                    }
+                    Step::Close(reason) => return close(handler, &mut sink, reason).await,
+                    // Reading the close frame has queued our reply to it, so we just need to send it
+                    Step::Closed => return closed(sink.close().await),
+                }
+            }
+            _ = pings.tick() => sink.feed(Message::Ping(Vec::new())).await?,
+            // Clients which went quiet have to go as well, without waiting for their next message
+            _ = flushes.tick() => {
+                if let Some(reason) = handler.away()? {
+                    info!(handler.logger, "ws.close.away"; "reason" => %reason);
+                    return close(handler, &mut sink, reason).await;
+                }
+            }
+        }
+
+        handler.feedback();
+        for message in handler.outbox.drain() {
+            sink.feed(message).await?;
+        }
+        sink.flush().await?;
+    }
+}
+
+/// Start handling a request which got a pad as a websocket, returning the handshake's response
+fn upgrade(
+    shared: &Shared,
+    upgrade: Box<Upgrade>,
+    on_upgrade: OnUpgrade,
+    headers: &[Header],
+) -> Response<Body> {
+    let Upgrade {
+        logger,
+        connection,
+        pad,
+        protocol,
+        echo,
+        settings,
+    } = *upgrade;
+    let bridge = match shared.bridge.lock().unwrap().clone() {
+        Some(bridge) => bridge,
+        // We're shutting down, so the pad is let go of along with the rest
+        None => return response(Reply::status(503)),
+    };
+    let (wake_tx, wake_rx) = unbounded_channel();
+    let mut handler = Handler::new(
+        logger.clone(),
+        connection,
+        pad,
+        PadSender::Bridged(bridge.clone()),
+        protocol,
+        settings,
+        move || {
+            let _ = wake_tx.send(());
+        },
+    );
+
+    let handshake = match handshake(headers, echo) {
+        Ok(handshake) => handshake,
+        Err(error) => {
+            handler.finish(Err(error));
+            return response(Reply::status(400));
+        }
+    };
+    let watchdog = watch_session(
+        logger,
+        Arc::clone(&handler.session),
+        PadSender::Bridged(bridge),
+        handler.idle_timeout(),
+        wake_rx,
+    );
+    shared.websockets.lock().unwrap().spawn(async move {
+        let session = async move {
+            let result = match upgraded(on_upgrade).await {
+                Ok(ws) => handle_websocket(&mut handler, ws).await,
+                Err(error) => Err(error),
+            };
+            handler.finish(result);
+        };
+        // The watchdog stops as soon as the handler, and with it its waker, is dropped
+        tokio::join!(session, watchdog);
+    });
+    handshake
+}
+
+/// Route a request and respond to it, making it a websocket if it got a pad
+async fn respond(
+    shared: Arc<Shared>,
+    remote: SocketAddr,
+    mut req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    debug!(shared.logger, "req"; "method" => %req.method(), "url" => %req.uri(), "remote" => remote, "headers" => ?req.headers());
+
+    // Routing looks at headers the same way whichever server got them
+    let headers: Vec<Header> = req
+        .headers()
+        .iter()
+        .filter_map(|(field, value)| Header::from_bytes(field.as_str(), value.as_bytes()).ok())
+        .collect();
+    // Only layouts are ever PUT, so that's the only body worth reading
+    let mut body = Vec::new();
+    if req.method() == Method::PUT {
+        while let Some(chunk) = req.body_mut().data().await {
+            match chunk {
+                Ok(chunk) if body.len() <= MAX_LAYOUT_SIZE => body.extend_from_slice(&chunk),
+                // Routing only needs to know it's too large
+                Ok(_) => break,
+                Err(error) => {
+                    debug!(shared.logger, "req.body_error"; "error" => %error);
+                    return Ok(response(Reply::status(400)));
+                }
+            }
+        }
+    }
+    let routed = {
+        let (shared, headers) = (Arc::clone(&shared), headers.clone());
+        let url = req.uri().path_and_query().map_or("/", |url| url.as_str());
+        let url = url.to_string();
+        // Both servers route by tiny_http's methods, which every method hyper took parses as
+        let method = req
+            .method()
+            .as_str()
+            .parse()
+            .unwrap_or(tiny_http::Method::Get);
+        spawn_blocking(move || {
+            shared.router.blocking_lock().route(&Incoming {
+                remote,
+                method,
+                url: &url,
+                headers: &headers,
+                body: &body,
+            })
+        })
+        .await
+    };
+    let routed = match routed {
+        Ok(Ok(routed)) => routed,
+        Ok(Err(error)) => return Ok(shared.fail(error)),
+        Err(error) => return Ok(shared.fail(error.into())),
+    };
+
+    Ok(match routed {
+        Routed::Respond(reply) => response(reply),
+        Routed::RefuseFull { logger, echo } => match handshake(&headers, echo) {
+            Ok(handshake) => {
+                let on_upgrade = hyper::upgrade::on(&mut req);
+                tokio::spawn(async move {
+                    if let Err(error) = refuse_full(on_upgrade).await {
+                        debug!(logger, "ws.error"; "error" => #%error);
+                    }
+                });
+                handshake
+            }
+            Err(error) => {
+                debug!(logger, "ws.error"; "error" => #%error);
+                response(Reply::status(400))
+            }
+        },
+        Routed::Upgrade(routed) => upgrade(&shared, routed, hyper::upgrade::on(&mut req), &headers),
+    })
+}
+
+/// Serve connections until `shutdown` is set or routing fails, then close the websockets and
+/// tell the pads to shut down like the blocking server does
+async fn serve(listener: TcpListener, shared: Arc<Shared>, bridged: JoinHandle<()>) -> Result<()> {
+    let mut connections = JoinSet::new();
+    let mut polls = interval(SHUTDOWN_POLL_INTERVAL);
+    while !shared.shutdown.load(Ordering::SeqCst) && shared.failed.lock().unwrap().is_none() {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, remote)) => {
+                    let shared = Arc::clone(&shared);
+                    let service = service_fn(move |req| respond(Arc::clone(&shared), remote, req));
+                    connections.spawn(async move {
+                        // Clients going away halfway through a request is their own business
+                        let _ = Http::new()
+                            .http1_only(true)
+                            .serve_connection(stream, service)
+                            .with_upgrades()
+                            .await;
+                    });
+                }
+                Err(error) => warn!(shared.logger, "server.accept_error"; "error" => %error),
+            },
+            _ = polls.tick() => {
+                // Forget about the connections which are already done, so that they don't pile up
+                while connections.try_join_next().is_some() {}
+                // Requests hold on to the router until the pads answer them, which is no
+                // reason to wait for them here
+                if let Ok(mut router) = shared.router.try_lock() {
+                    router.rotate_if_asked();
+                }
+            }
+        }
+    }
+    if let Some(error) = shared.failed.lock().unwrap().take() {
+        return Err(error);
+    }
+
+    // Stop taking requests, including those of connections kept alive
+    connections.shutdown().await;
+    let mut websockets = mem::take(&mut *shared.websockets.lock().unwrap());
+    shared.router.lock().await.notify_shutdown(websockets.len());
+    let _ = timeout(SHUTDOWN_TIMEOUT, async {
+        while websockets.join_next().await.is_some() {}
+    })
+    .await;
+    let running = websockets.len();
+    websockets.shutdown().await;
+
+    // Whatever the websockets asked of the pads has to reach them before they're told to shut
+    // down, which the bridge is done passing on once the websockets are gone
+    drop(shared.bridge.lock().unwrap().take());
+    if timeout(SHUTDOWN_TIMEOUT, bridged).await.is_err() {
+        warn!(shared.logger, "shutdown.bridge");
+    }
+    spawn_blocking(move || shared.router.blocking_lock().finish(running)).await?
+}
+
+/// Serve pages and websockets like [mainloop](crate::server::mainloop) does, taking the same
+/// arguments, on a runtime of our own
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn mainloop(
+    logger: Logger,
+    args: Args,
+    token: Token,
+    keymap: Keymap,
+    remaps: RemapProfiles,
+    tx: SyncSender<PadRequest>,
+    shutdown: Arc<AtomicBool>,
+    metrics: Arc<Metrics>,
+    driver_missing: Arc<AtomicBool>,
+    frontend: Frontend,
+) -> Result<()> {
+    if args.tls.is_some() {
+        return Err(format_err!(
+            "the async server can't serve over HTTPS yet, leave out --async to use TLS"
+        ));
+    }
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async move {
+        let host = args.public_host()?;
+        let addr = args.addr();
+        let listener = net::TcpListener::bind(addr).map_err(|error| bind_error(addr, &error))?;
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        let (bridge, bridged) = bridge(tx.clone());
+        let router = Router::new(
+            logger.clone(),
+            args,
+            token,
+            keymap,
+            remaps,
+            tx,
+            Arc::clone(&shutdown),
+            metrics,
+            driver_missing,
+            frontend,
+            host,
+            listener.local_addr()?,
+        )?;
+        let shared = Arc::new(Shared {
+            logger,
+            router: Mutex::new(router),
+            bridge: StdMutex::new(Some(bridge)),
+            websockets: StdMutex::default(),
+            failed: StdMutex::default(),
+            shutdown,
+        });
+        serve(listener, shared, bridged).await
+    })
+}
@@ -0,0 +1,57 @@
+//! The session token keeping unwanted devices on the network from controlling our pads
+
+use std::fmt;
+
+use rand::Rng;
+
+/// A random token which clients need to present to be let in, or to get their pad back
+#[derive(Debug, Clone)]
+pub(crate) struct Token(String);
+
+impl Token {
+    /// Generate a new random token
+    pub(crate) fn generate() -> Self {
+        let bytes: [u8; 16] = rand::thread_rng().gen();
+        Self(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+    }
+
+    /// Check whether the given candidate is this token.
+    /// This takes the same time no matter where the first mismatch is, to avoid leaking the token.
+    pub(crate) fn matches(&self, candidate: &str) -> bool {
+        let (token, candidate) = (self.0.as_bytes(), candidate.as_bytes());
+        token.len() == candidate.len()
+            && token
+                .iter()
+                .zip(candidate)
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+}
+
+impl From<String> for Token {
+    /// Bring back a token given out before, e.g. by a previous run
+    fn from(token: String) -> Self {
+        Self(token)
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let token = Token::generate();
+        assert!(token.matches(&token.to_string()));
+        assert!(!token.matches(""));
+        assert!(!token.matches(&token.to_string()[1..]));
+        assert!(!token.matches(&format!("{}0", token)));
+        assert!(!token.matches(&Token::generate().to_string()));
+    }
+}
@@ -0,0 +1,181 @@
+//! Per-pad calibration of the states clients send
+
+use std::time::Instant;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use vigem_client_c::X360State;
+
+use crate::transform::Transformer;
+
+/// The longest device identifier we keep profiles for
+const MAX_DEVICE_LEN: usize = 64;
+
+/// Which stick axes to flip
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub(crate) struct Inversion {
+    pub(crate) left_x: bool,
+    pub(crate) left_y: bool,
+    pub(crate) right_x: bool,
+    pub(crate) right_y: bool,
+}
+
+/// How to clean up the states a pad receives before they're sent to the bus
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub(crate) struct Calibration {
+    /// The radial deadzone of both sticks, as a percentage of their range
+    pub(crate) deadzone: f32,
+
+    /// How far the triggers have to be pulled before they register at all, as a percentage
+    pub(crate) trigger_threshold: f32,
+
+    // Tables have to come after plain values in TOML, so this goes last
+    pub(crate) invert: Inversion,
+}
+
+impl Calibration {
+    /// Bring the percentages into the 0 to 100 range, treating nonsense as 0
+    pub(crate) fn clamped(self) -> Self {
+        let percentage = |value: f32| {
+            if value.is_nan() {
+                0.0
+            } else {
+                value.clamp(0.0, 100.0)
+            }
+        };
+        Self {
+            deadzone: percentage(self.deadzone),
+            trigger_threshold: percentage(self.trigger_threshold),
+            invert: self.invert,
+        }
+    }
+
+    /// Calibrate a state, flipping the axes before applying the deadzone and trigger threshold
+    pub(crate) fn apply(&self, mut state: X360State) -> X360State {
+        let flip = |axis: &mut i16, invert: bool| {
+            if invert {
+                *axis = axis.saturating_neg();
+            }
+        };
+        flip(&mut state.left_thumbstick.x, self.invert.left_x);
+        flip(&mut state.left_thumbstick.y, self.invert.left_y);
+        flip(&mut state.right_thumbstick.x, self.invert.right_x);
+        flip(&mut state.right_thumbstick.y, self.invert.right_y);
+
+        state.apply_deadzone(self.deadzone / 100.0);
+
+        let threshold = self.trigger_threshold / 100.0 * f32::from(u8::MAX);
+        for trigger in [&mut state.left_trigger, &mut state.right_trigger] {
+            if f32::from(*trigger) < threshold {
+                *trigger = 0;
+            }
+        }
+        state
+    }
+}
+
+/// A calibration is the built-in deadzone transformer, which clients can put in a pad's
+/// pipeline besides the calibration every pad already has
+impl Transformer for Calibration {
+    fn transform(&mut self, state: X360State, _: Instant) -> X360State {
+        self.apply(state)
+    }
+}
+
+/// Whether a device identifier a client presents is fit to key profiles by
+pub(crate) fn valid_device(device: &str) -> bool {
+    !device.is_empty()
+        && device.len() <= MAX_DEVICE_LEN
+        && device
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stick(x: i16) -> X360State {
+        X360State::builder().left_stick(x, 0).build()
+    }
+
+    #[test]
+    fn test_deadzone_boundaries() {
+        let calibration = |deadzone| Calibration {
+            deadzone,
+            ..Calibration::default()
+        };
+
+        // No deadzone leaves everything alone, a full one centers everything
+        assert_eq!(calibration(0.0).apply(stick(1)), stick(1));
+        assert_eq!(calibration(100.0).apply(stick(i16::MAX)), stick(0));
+
+        // Just inside the deadzone is centered, just outside it barely moves, and the far ends
+        // stay put
+        assert_eq!(calibration(50.0).apply(stick(16383)), stick(0));
+        assert_eq!(calibration(50.0).apply(stick(16385)), stick(3));
+        assert_eq!(calibration(50.0).apply(stick(i16::MAX)), stick(i16::MAX));
+        assert_eq!(calibration(50.0).apply(stick(i16::MIN)), stick(i16::MIN));
+    }
+
+    #[test]
+    fn test_clamped() {
+        let clamped = |deadzone, trigger_threshold| {
+            let calibration = Calibration {
+                deadzone,
+                trigger_threshold,
+                ..Calibration::default()
+            }
+            .clamped();
+            (calibration.deadzone, calibration.trigger_threshold)
+        };
+        assert_eq!(clamped(-5.0, 150.0), (0.0, 100.0));
+        assert_eq!(clamped(f32::NAN, 25.0), (0.0, 25.0));
+    }
+
+    #[test]
+    fn test_inversion() {
+        let calibration = Calibration {
+            invert: Inversion {
+                left_x: true,
+                right_y: true,
+                ..Inversion::default()
+            },
+            ..Calibration::default()
+        };
+        let state = X360State::builder()
+            .left_stick(i16::MIN, 100)
+            .right_stick(100, 0)
+            .build();
+        let inverted = calibration.apply(state);
+        assert_eq!(inverted.left_thumbstick, (i16::MAX, 100));
+        assert_eq!(inverted.right_thumbstick, (100, 0));
+    }
+
+    #[test]
+    fn test_trigger_threshold() {
+        let calibration = Calibration {
+            trigger_threshold: 10.0,
+            ..Calibration::default()
+        };
+        let triggers = |left, right| {
+            X360State::builder()
+                .left_trigger(left)
+                .right_trigger(right)
+                .build()
+        };
+        // 10% of 255 is 25.5
+        assert_eq!(calibration.apply(triggers(25, 26)), triggers(0, 26));
+        assert_eq!(calibration.apply(triggers(255, 0)), triggers(255, 0));
+    }
+
+    #[test]
+    fn test_valid_device() {
+        assert!(valid_device("0123abcd-ef_GH"));
+        assert!(!valid_device(""));
+        assert!(!valid_device("a b"));
+        assert!(!valid_device(&"a".repeat(MAX_DEVICE_LEN + 1)));
+    }
+}
@@ -0,0 +1,122 @@
+//! Why we close clients' connections, which is what the reasons of our close frames say.
+//!
+//! Clients shouldn't have to make sense of prose to know whether to reconnect, so each reason has
+//! a name which won't change, the same as it's serialized as.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
+
+/// Why we closed a client's connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseReason {
+    /// There was no pad left for the client, so it was closed right after connecting. Trying
+    /// again later might get one.
+    ServerFull,
+
+    /// We're shutting down, right after telling the client whether we'll be back
+    ShuttingDown,
+
+    /// The client's pads were taken away from it, e.g. from the window, and it shouldn't try to
+    /// get them back
+    Kicked,
+
+    /// The client went quiet for so long that its pads were given up on
+    IdleTimeout,
+
+    /// The bus kept refusing the client's pad its states, so it was taken away. Reconnecting
+    /// gets a new one.
+    PadFailed,
+}
+
+impl CloseReason {
+    /// Every reason, in no particular order
+    pub const ALL: [Self; 5] = [
+        Self::ServerFull,
+        Self::ShuttingDown,
+        Self::Kicked,
+        Self::IdleTimeout,
+        Self::PadFailed,
+    ];
+
+    /// The reason's name, which is what it's serialized as
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ServerFull => "server_full",
+            Self::ShuttingDown => "shutting_down",
+            Self::Kicked => "kicked",
+            Self::IdleTimeout => "idle_timeout",
+            Self::PadFailed => "pad_failed",
+        }
+    }
+
+    /// The reason with the given name, if there's one
+    pub fn find(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|reason| reason.name() == name)
+    }
+
+    /// The close frame saying so, which asks to try again later if the server is full, says
+    /// something went wrong if the pad failed and otherwise says we're going away
+    pub(crate) fn frame(self) -> CloseFrame<'static> {
+        CloseFrame {
+            code: match self {
+                Self::ServerFull => CloseCode::Again,
+                Self::PadFailed => CloseCode::Error,
+                Self::ShuttingDown | Self::Kicked | Self::IdleTimeout => CloseCode::Away,
+            },
+            reason: self.name().into(),
+        }
+    }
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names() {
+        let names: Vec<_> = CloseReason::ALL.iter().map(|r| r.name()).collect();
+        assert_eq!(
+            names,
+            [
+                "server_full",
+                "shutting_down",
+                "kicked",
+                "idle_timeout",
+                "pad_failed"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        for &reason in &CloseReason::ALL {
+            let json = serde_json::to_string(&reason).unwrap();
+            assert_eq!(json, format!("\"{}\"", reason.name()));
+            assert_eq!(serde_json::from_str::<CloseReason>(&json).unwrap(), reason);
+            assert_eq!(CloseReason::find(reason.name()), Some(reason));
+        }
+        assert!(serde_json::from_str::<CloseReason>("\"ShuttingDown\"").is_err());
+        assert_eq!(CloseReason::find("shutting down"), None);
+    }
+
+    #[test]
+    fn test_frame() {
+        let frame = CloseReason::ServerFull.frame();
+        assert_eq!(frame.code, CloseCode::Again);
+        assert_eq!(frame.reason, "server_full");
+        assert_eq!(CloseReason::Kicked.frame().code, CloseCode::Away);
+        assert_eq!(CloseReason::PadFailed.frame().code, CloseCode::Error);
+    }
+}
@@ -40,6 +40,35 @@ function copyTouch({ clientX, clientY, identifier }) {
   return { clientX, clientY, identifier };
 }
 
+/**
+ * Encode a pad state in the server's compact binary format
+ * @param {{ buttons: number; left_trigger: number; right_trigger: number; left_thumbstick: number[]; right_thumbstick: number[]; }} state
+ */
+function encodeState(state) {
+  // Followed by when we sent it, so that the server can tell how long it took to get there
+  const view = new DataView(new ArrayBuffer(20));
+  view.setUint16(0, state.buttons, true);
+  view.setUint8(2, state.left_trigger);
+  view.setUint8(3, state.right_trigger);
+  view.setInt16(4, state.left_thumbstick[0], true);
+  view.setInt16(6, state.left_thumbstick[1], true);
+  view.setInt16(8, state.right_thumbstick[0], true);
+  view.setInt16(10, state.right_thumbstick[1], true);
+  view.setFloat64(12, performance.now(), true);
+  return view.buffer;
+}
+
+/**
+ * Find a connected physical gamepad which uses the standard mapping, if there is one
+ * @returns {Gamepad | undefined}
+ */
+function standardGamepad() {
+  if (!("getGamepads" in navigator)) return undefined;
+  return Array.from(navigator.getGamepads()).find(
+    (gamepad) => gamepad && gamepad.connected && gamepad.mapping === "standard"
+  );
+}
+
 class Joystick {
   /**
    * @param {number} x
@@ -197,6 +226,17 @@ class Buttons {
     this.state = 0;
   }
 
+  /**
+   * Construct a set of buttons which have already been placed
+   * @param {{ x: number; y: number; r: number; color: string; mask: number; } []} buttons
+   */
+  static from(buttons) {
+    const group = Object.create(Buttons.prototype);
+    group.buttons = buttons;
+    group.state = 0;
+    return group;
+  }
+
   /**
    * @param {CanvasRenderingContext2D} ctx
    * @param {Map<number, Touch>} ongoingTouches
@@ -215,52 +255,52 @@ class Buttons {
   }
 }
 
+class Trigger {
+  /**
+   * Construct a new trigger, which is fully pulled while touched
+   * @param {{ x: number; y: number; r: number; }} circle
+   * @param {string} color
+   * @param {"left" | "right"} side
+   */
+  constructor(circle, color, side) {
+    this.circle = circle;
+    this.color = color;
+    this.side = side;
+    this.value = 0;
+  }
+
+  /**
+   * @param {CanvasRenderingContext2D} ctx
+   * @param {Map<number, Touch>} ongoingTouches
+   */
+  draw(ctx, ongoingTouches) {
+    const pressed = Array.from(ongoingTouches.values()).some((touch) =>
+      intersects(this.circle, touch)
+    );
+    ctx.fillStyle = ctx.strokeStyle = this.color;
+    drawCircle(ctx, this.circle, pressed);
+
+    this.value = pressed ? 255 : 0;
+  }
+}
+
 window.addEventListener("DOMContentLoaded", function () {
   const canvas = document.createElement("canvas");
   const ctx = canvas.getContext("2d");
   document.body.append(canvas);
   ctx.lineWidth *= 2;
 
-  let leftJoystick, rightJoystick, leftButtons, rightButtons;
+  /**
+   * The elements of the current layout, as built by the layout's `buildLayout` function
+   * @type {{ leftJoystick?: Joystick; rightJoystick?: Joystick; buttons?: Buttons[]; triggers?: Trigger[]; }}
+   */
+  let scene;
 
   function buildScene() {
     const width = (canvas.width = innerWidth);
     const height = (canvas.height = innerHeight);
-    const buttonRadius = width / 24;
-    const rightPivot = {
-      x: width / 2 + width / 8,
-      y: height / 4 + 10,
-      r: buttonRadius / 2,
-    };
-    const leftPivot = {
-      x: width / 2 - width / 8,
-      y: height / 4 + 10,
-      r: buttonRadius / 2,
-    };
-    leftJoystick = new Joystick(
-      width / 4 + 10,
-      height - height / 4,
-      height / 4 - 10,
-      4
-    );
-    rightJoystick = new Joystick(
-      width - (width / 4 + 10),
-      height - height / 4,
-      height / 4 - 10,
-      4
-    );
-    leftButtons = new Buttons(leftPivot, buttonRadius, [
-      { color: "orange", mask: 0x0001 },
-      { color: "orange", mask: 0x0008 },
-      { color: "orange", mask: 0x0004 },
-      { color: "orange", mask: 0x0002 },
-    ]);
-    rightButtons = new Buttons(rightPivot, buttonRadius, [
-      { color: "gold", mask: 0x8000 },
-      { color: "green", mask: 0x1000 },
-      { color: "blue", mask: 0x4000 },
-      { color: "red", mask: 0x2000 },
-    ]);
+    // @ts-ignore
+    scene = buildLayout(width, height);
   }
 
   buildScene();
@@ -269,38 +309,208 @@ window.addEventListener("DOMContentLoaded", function () {
 
   const ongoingTouches = new Map();
 
+  /**
+   * The `code` of every key held down, which the server maps to a state while we're in keyboard mode
+   * @type {Set<string>}
+   */
+  const keys = new Set();
+  // Typing on a keyboard switches to keyboard mode, touching the screen switches back
+  let keyboardMode = false;
+
   // @ts-ignore
   const url = document.getElementById("url").value;
-  const ws = new WebSocket(url);
+  /** @type {WebSocket} */
+  let ws;
+  /**
+   * Which player our pad is, if the server could tell us
+   * @type {number | null}
+   */
+  let player = null;
+  /**
+   * How the server says to show our player, e.g. "P5 (no XInput slot)" past the four players
+   * XInput games can see
+   * @type {string | null}
+   */
+  let label = null;
+  /**
+   * The color a game set our dualshock 4 pad's lightbar to, if it did
+   * @type {string | null}
+   */
+  let lightbar = null;
+  // The background of each player's page, dark enough for the controls to stand out
+  const PLAYER_COLORS = ["#0b3d0b", "#4a0b0b", "#0b1f4a", "#4a3d0b"];
+
+  // Identifies this device across restarts, so that the server remembers its profile. The
+  // server gives us one the first time we connect.
+  const params = new URLSearchParams(location.search);
+
+  function connect() {
+    const device = localStorage.getItem("device");
+    let query = device ? `&device=${encodeURIComponent(device)}` : "";
+    // If we had a pad before, ask for it back
+    const reclaim = sessionStorage.getItem("reclaim");
+    if (reclaim) query += `&reclaim=${encodeURIComponent(reclaim)}`;
+    // The kind of pad to show up as, which the page's own ?type= picks
+    const type = params.get("type");
+    if (type) query += `&type=${encodeURIComponent(type)}`;
+    // The layout we're showing, which the server remembers for next time
+    const layout = params.get("layout");
+    if (layout) query += `&layout=${encodeURIComponent(layout)}`;
+    ws = new WebSocket(
+      url + query,
+      ["sphrosyne.v2.binary", "sphrosyne.v1.json"]
+    );
+    ws.binaryType = "arraybuffer";
+    ws.addEventListener("message", (event) => {
+      // The second version of the protocol sends feedback as the large motor, small motor, LED and pad bytes,
+      // which the haptic messages already tell us how to vibrate for
+      if (event.data instanceof ArrayBuffer) return;
+
+      const message = JSON.parse(event.data);
+      if (message.type === "hello") {
+        if (message.device) localStorage.setItem("device", message.device);
+      } else if (message.type === "clock") {
+        // The server wants to know what our clock says, to time the states we send
+        ws.send(JSON.stringify({ type: "clock", n: message.n, t: performance.now() }));
+      } else if (message.type === "haptic") {
+        // Our pad rumbles, or stopped to, as a pattern lasting until the next one
+        if (message.pad === 0 && "vibrate" in navigator) navigator.vibrate(message.pattern);
+      } else if (message.type === "player") {
+        // A game changed which player our pad's LED shows
+        if (message.pad === 0) {
+          player = message.n;
+          label = null;
+        }
+      } else if (message.type === "lightbar") {
+        // Dimmed, for the same reason the players' colors are dark
+        if (message.pad === 0)
+          lightbar = `rgb(${message.r / 4}, ${message.g / 4}, ${message.b / 4})`;
+      } else if (message.type === "shutdown") {
+        // The server's pads are going away with it, so there's nothing left to reclaim unless
+        // it's saving them for when it's back
+        if (!message.restarting) sessionStorage.removeItem("reclaim");
+        player = null;
+        label = null;
+        lightbar = null;
+      } else if (message.type === "ready") {
+        player = message.player;
+        label = message.label || null;
+        // A page opened without a layout shows the one this device used last
+        if (message.layout && !params.has("layout")) {
+          params.set("layout", message.layout);
+          location.replace(`${location.pathname}?${params}`);
+        }
+      } else if ("reclaim" in message) {
+        sessionStorage.setItem("reclaim", message.reclaim);
+      } else if ("player" in message) {
+        player = message.player;
+        label = message.label || null;
+      }
+    });
+    ws.addEventListener("close", () => setTimeout(connect, 1000));
+  }
+
+  connect();
+
+  // Keep the screen on while we're playing, which browsers only let us ask for while we're
+  // visible, and let go of it for us whenever we're not
+  function keepAwake() {
+    if (!("wakeLock" in navigator) || document.visibilityState !== "visible") return;
+    // @ts-ignore
+    navigator.wakeLock.request("screen").catch(() => {});
+  }
+
+  keepAwake();
+
+  // Whatever is touched while the phone is locked or in a pocket is no input, so the server
+  // keeps our pads neutral until we're visible again
+  document.addEventListener("visibilitychange", () => {
+    const hidden = document.visibilityState === "hidden";
+    if (ws.readyState === ws.OPEN)
+      ws.send(JSON.stringify({ type: hidden ? "background" : "foreground" }));
+    if (!hidden) keepAwake();
+  });
 
   function mainloop() {
-    ctx.fillStyle = "black";
+    // Tint the background with our lightbar's or player's color, so that players can tell their
+    // phones apart
+    if (lightbar !== null) ctx.fillStyle = lightbar;
+    else
+      ctx.fillStyle =
+        player === null ? "black" : PLAYER_COLORS[(player - 1) % PLAYER_COLORS.length];
     ctx.fillRect(0, 0, canvas.width, canvas.height);
 
-    leftJoystick.draw(ctx);
-    rightJoystick.draw(ctx);
-    leftButtons.draw(ctx, ongoingTouches);
-    rightButtons.draw(ctx, ongoingTouches);
+    if (player !== null) {
+      ctx.fillStyle = "white";
+      ctx.font = "bold 24px sans-serif";
+      ctx.textAlign = "center";
+      ctx.textBaseline = "top";
+      ctx.fillText(label || `Player ${player}`, canvas.width / 2, 8);
+    }
 
-    if (ws.readyState === ws.OPEN)
-      ws.send(
-        JSON.stringify({
-          buttons: leftButtons.state | rightButtons.state,
-          left_trigger: 0,
-          right_trigger: 0,
-          left_thumbstick: leftJoystick.stickValue,
-          right_thumbstick: rightJoystick.stickValue,
-        })
-      );
+    const { leftJoystick, rightJoystick, buttons = [], triggers = [] } = scene;
+    const state = {
+      buttons: 0,
+      left_trigger: 0,
+      right_trigger: 0,
+      left_thumbstick: [0, 0],
+      right_thumbstick: [0, 0],
+    };
+
+    if (leftJoystick) {
+      leftJoystick.draw(ctx);
+      state.left_thumbstick = leftJoystick.stickValue;
+    }
+    if (rightJoystick) {
+      rightJoystick.draw(ctx);
+      state.right_thumbstick = rightJoystick.stickValue;
+    }
+    for (const group of buttons) {
+      group.draw(ctx, ongoingTouches);
+      state.buttons |= group.state;
+    }
+    for (const trigger of triggers) {
+      trigger.draw(ctx, ongoingTouches);
+      state[trigger.side + "_trigger"] = trigger.value;
+    }
+
+    if (ws.readyState === ws.OPEN) {
+      // A physical controller plugged into this device takes over from the touch controls
+      const gamepad = standardGamepad();
+      if (gamepad)
+        ws.send(
+          JSON.stringify({
+            type: "gamepad",
+            buttons: gamepad.buttons.map((button) => button.value),
+            axes: gamepad.axes,
+          })
+        );
+      else if (keyboardMode)
+        ws.send(JSON.stringify({ type: "keys", down: Array.from(keys) }));
+      else ws.send(encodeState(state));
+    }
 
     requestAnimationFrame(mainloop);
   }
 
+  window.addEventListener("keydown", (event) => {
+    if (event.repeat || event.ctrlKey || event.metaKey) return;
+    event.preventDefault();
+    keyboardMode = true;
+    keys.add(event.code);
+  });
+  window.addEventListener("keyup", (event) => {
+    keys.delete(event.code);
+  });
+  // Keys released while we're in the background never get a keyup
+  window.addEventListener("blur", () => keys.clear());
+
   canvas.addEventListener("touchstart", (event) => {
     event.preventDefault();
+    keyboardMode = false;
 
-    leftJoystick.ontouchstart(event);
-    rightJoystick.ontouchstart(event);
+    if (scene.leftJoystick) scene.leftJoystick.ontouchstart(event);
+    if (scene.rightJoystick) scene.rightJoystick.ontouchstart(event);
     for (const touch of event.changedTouches) {
       ongoingTouches.set(touch.identifier, copyTouch(touch));
     }
@@ -308,8 +518,8 @@ window.addEventListener("DOMContentLoaded", function () {
   canvas.addEventListener("touchmove", (event) => {
     event.preventDefault();
 
-    leftJoystick.ontouchmove(event);
-    rightJoystick.ontouchmove(event);
+    if (scene.leftJoystick) scene.leftJoystick.ontouchmove(event);
+    if (scene.rightJoystick) scene.rightJoystick.ontouchmove(event);
     for (const touch of event.changedTouches) {
       ongoingTouches.set(touch.identifier, copyTouch(touch));
     }
@@ -317,8 +527,8 @@ window.addEventListener("DOMContentLoaded", function () {
   canvas.addEventListener("touchend", (event) => {
     event.preventDefault();
 
-    leftJoystick.ontouchend(event);
-    rightJoystick.ontouchend(event);
+    if (scene.leftJoystick) scene.leftJoystick.ontouchend(event);
+    if (scene.rightJoystick) scene.rightJoystick.ontouchend(event);
     for (const touch of event.changedTouches) {
       ongoingTouches.delete(touch.identifier);
     }
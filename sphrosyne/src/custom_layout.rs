@@ -0,0 +1,424 @@
+//! Layouts whose controls clients arrange themselves, kept as JSON files in the directory given
+//! with `--layouts`, one per layout
+
+use std::{
+    collections::BTreeSet,
+    convert::TryFrom,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use vigem_client_c::X360Buttons;
+
+use crate::layout::Layout;
+
+/// The longest name a custom layout can have
+const MAX_NAME_LEN: usize = 64;
+
+/// How many controls a custom layout can have, which is plenty for every input twice over
+const MAX_CONTROLS: usize = 64;
+
+/// The largest layout we'll read from a request, in bytes
+pub(crate) const MAX_LAYOUT_SIZE: usize = 64 * 1024;
+
+/// What a control looks like and how it's used
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ControlKind {
+    Button,
+    Stick,
+    Trigger,
+}
+
+/// The input a control drives, written as a single button's name, e.g. `A` or `DPAD_UP`, or as
+/// `left_stick`, `right_stick`, `left_trigger` or `right_trigger`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) enum Binding {
+    Button(X360Buttons),
+    LeftStick,
+    RightStick,
+    LeftTrigger,
+    RightTrigger,
+}
+
+impl TryFrom<String> for Binding {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, String> {
+        Ok(match name.as_str() {
+            "left_stick" => Self::LeftStick,
+            "right_stick" => Self::RightStick,
+            "left_trigger" => Self::LeftTrigger,
+            "right_trigger" => Self::RightTrigger,
+            _ => {
+                let button = name
+                    .parse::<X360Buttons>()
+                    .map_err(|error| error.to_string())?;
+                if button.bits().count_ones() != 1 {
+                    return Err(format!("{:?} is not a single button", name));
+                }
+                Self::Button(button)
+            }
+        })
+    }
+}
+
+impl From<Binding> for String {
+    fn from(binding: Binding) -> Self {
+        binding.to_string()
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Button(button) => write!(f, "{}", button),
+            Self::LeftStick => f.write_str("left_stick"),
+            Self::RightStick => f.write_str("right_stick"),
+            Self::LeftTrigger => f.write_str("left_trigger"),
+            Self::RightTrigger => f.write_str("right_trigger"),
+        }
+    }
+}
+
+impl Binding {
+    /// The kind of control which can drive this input
+    fn kind(self) -> ControlKind {
+        match self {
+            Self::Button(_) => ControlKind::Button,
+            Self::LeftStick | Self::RightStick => ControlKind::Stick,
+            Self::LeftTrigger | Self::RightTrigger => ControlKind::Trigger,
+        }
+    }
+}
+
+/// A control on a custom layout, placed by the top left corner and size of the box it fills,
+/// all as fractions of the page's width and height
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Control {
+    pub(crate) kind: ControlKind,
+    pub(crate) binding: Binding,
+    pub(crate) x: f32,
+    pub(crate) y: f32,
+    pub(crate) width: f32,
+    pub(crate) height: f32,
+}
+
+/// A layout a client arranged itself, which the controller page draws with `custom.js`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct CustomLayout {
+    pub(crate) controls: Vec<Control>,
+}
+
+/// Why a custom layout can't be used, which is told to whoever sent it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InvalidLayout(pub(crate) String);
+
+impl fmt::Display for InvalidLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for InvalidLayout {}
+
+impl CustomLayout {
+    /// Check that the layout has at least one control and not too many, that every control
+    /// fits on the page and has a binding of its kind, and that no two controls drive the same
+    /// input
+    pub(crate) fn validate(&self) -> Result<(), InvalidLayout> {
+        let invalid = |reason: String| Err(InvalidLayout(reason));
+        if self.controls.is_empty() {
+            return invalid("a layout needs at least one control".to_string());
+        }
+        if self.controls.len() > MAX_CONTROLS {
+            return invalid(format!(
+                "a layout can have at most {} controls, not {}",
+                MAX_CONTROLS,
+                self.controls.len()
+            ));
+        }
+        let mut bound = BTreeSet::new();
+        for (index, control) in self.controls.iter().enumerate() {
+            if control.binding.kind() != control.kind {
+                return invalid(format!(
+                    "control {} is a {:?} but drives {}, which needs a {:?}",
+                    index,
+                    control.kind,
+                    control.binding,
+                    control.binding.kind()
+                ));
+            }
+            let fraction = |value: f32| (0.0..=1.0).contains(&value);
+            let geometry = [control.x, control.y, control.width, control.height];
+            if !geometry.iter().all(|&value| fraction(value))
+                || control.width == 0.0
+                || control.height == 0.0
+                || !fraction(control.x + control.width)
+                || !fraction(control.y + control.height)
+            {
+                return invalid(format!(
+                    "control {} doesn't fit on the page: x, y, width and height have to be \
+                     fractions of it from 0 to 1, its width and height can't be 0 and it can't \
+                     reach past the right or bottom edge",
+                    index
+                ));
+            }
+            if !bound.insert(control.binding) {
+                return invalid(format!(
+                    "control {} drives {}, which another control already does",
+                    index, control.binding
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a custom layout can go by the given name: it has to be made of ASCII letters,
+/// digits, `-` and `_`, so that it's safe as a file name and in a URL, and can't be a built-in
+/// layout's
+pub(crate) fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+        && Layout::find(name).is_none()
+}
+
+/// The custom layouts, kept in a directory of their own which is created once the first one is
+/// saved
+#[derive(Debug, Clone)]
+pub(crate) struct LayoutStore {
+    dir: PathBuf,
+}
+
+impl LayoutStore {
+    pub(crate) fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// The file a layout is kept in, if it can go by the given name
+    fn path(&self, name: &str) -> Option<PathBuf> {
+        valid_name(name).then(|| self.dir.join(format!("{}.json", name)))
+    }
+
+    /// The layout with the given name, if one was saved
+    pub(crate) fn get(&self, name: &str) -> Result<Option<CustomLayout>> {
+        let path = match self.path(name) {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let data = match fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => {
+                return Err(error).wrap_err_with(|| format!("Could not read {}", path.display()))
+            }
+        };
+        serde_json::from_str(&data)
+            .map(Some)
+            .wrap_err_with(|| format!("Invalid layout in {}", path.display()))
+    }
+
+    /// Whether a layout was saved with the given name
+    pub(crate) fn exists(&self, name: &str) -> bool {
+        self.path(name).is_some_and(|path| path.is_file())
+    }
+
+    /// Save a layout under the given name, which has to be [valid](valid_name), replacing the
+    /// file only once it's all written
+    pub(crate) fn put(&self, name: &str, layout: &CustomLayout) -> Result<()> {
+        let path = self
+            .path(name)
+            .ok_or_else(|| eyre::eyre!("{:?} is not a valid layout name", name))?;
+        fs::create_dir_all(&self.dir)
+            .wrap_err_with(|| format!("Could not create {}", self.dir.display()))?;
+        let partial = path.with_extension("partial");
+        fs::write(&partial, serde_json::to_string_pretty(layout)?)
+            .wrap_err_with(|| format!("Could not write {}", partial.display()))?;
+        fs::rename(&partial, &path).wrap_err_with(|| format!("Could not write {}", path.display()))
+    }
+
+    /// The directory the layouts are kept in
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn control(kind: ControlKind, binding: Binding, x: f32, y: f32) -> Control {
+        Control {
+            kind,
+            binding,
+            x,
+            y,
+            width: 0.2,
+            height: 0.2,
+        }
+    }
+
+    fn layout(controls: &[Control]) -> CustomLayout {
+        CustomLayout {
+            controls: controls.to_vec(),
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("sphrosyne-layouts-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_parse() {
+        let parsed: CustomLayout = serde_json::from_str(
+            r#"{"controls":[
+                {"kind":"stick","binding":"left_stick","x":0,"y":0.5,"width":0.5,"height":0.5},
+                {"kind":"button","binding":"dpad_up","x":0.5,"y":0,"width":0.1,"height":0.1},
+                {"kind":"trigger","binding":"right_trigger","x":0.9,"y":0,"width":0.1,"height":0.1}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            parsed.controls[1].binding,
+            Binding::Button(X360Buttons::DPAD_UP)
+        );
+        assert_eq!(parsed.controls[2].binding, Binding::RightTrigger);
+        assert!(parsed.validate().is_ok());
+
+        // Bindings are written back the way they're read
+        let written = serde_json::to_string(&parsed).unwrap();
+        assert_eq!(
+            serde_json::from_str::<CustomLayout>(&written).unwrap(),
+            parsed
+        );
+
+        for binding in [r#""a|b""#, r#""""#, r#""turbo""#, r#""left_thumbstick""#] {
+            assert!(
+                serde_json::from_str::<Binding>(binding).is_err(),
+                "{}",
+                binding
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let a = control(
+            ControlKind::Button,
+            Binding::Button(X360Buttons::A),
+            0.0,
+            0.0,
+        );
+        let stick = control(ControlKind::Stick, Binding::LeftStick, 0.5, 0.5);
+        assert_eq!(layout(&[a, stick]).validate(), Ok(()));
+
+        // Controls can reach right up to the edges
+        let edge = Control {
+            x: 0.8,
+            y: 0.75,
+            height: 0.25,
+            ..control(ControlKind::Trigger, Binding::LeftTrigger, 0.0, 0.0)
+        };
+        assert_eq!(layout(&[edge]).validate(), Ok(()));
+
+        let reason = |controls: &[Control]| layout(controls).validate().unwrap_err().0;
+        assert_eq!(reason(&[]), "a layout needs at least one control");
+        assert!(reason(&[a; MAX_CONTROLS + 1]).contains("at most 64"));
+        assert_eq!(
+            reason(&[stick, a, a]),
+            "control 2 drives A, which another control already does"
+        );
+        assert_eq!(
+            reason(&[Control {
+                kind: ControlKind::Button,
+                ..stick
+            }]),
+            "control 0 is a Button but drives left_stick, which needs a Stick"
+        );
+
+        for (x, y, width, height) in [
+            (-0.1, 0.0, 0.2, 0.2),
+            (0.0, 1.1, 0.2, 0.2),
+            (0.9, 0.0, 0.2, 0.2),
+            (0.0, 0.9, 0.2, 0.2),
+            (0.0, 0.0, 0.0, 0.2),
+            (0.0, 0.0, 0.2, -0.2),
+            (f32::NAN, 0.0, 0.2, 0.2),
+            (0.0, 0.0, f32::INFINITY, 0.2),
+        ] {
+            let control = Control {
+                x,
+                y,
+                width,
+                height,
+                ..a
+            };
+            assert!(
+                reason(&[control]).starts_with("control 0 doesn't fit on the page"),
+                "{:?}",
+                control
+            );
+        }
+    }
+
+    #[test]
+    fn test_names() {
+        assert!(valid_name("mine"));
+        assert!(valid_name("Left-handed_2"));
+        assert!(valid_name(&"a".repeat(MAX_NAME_LEN)));
+        for name in [
+            "",
+            "../../evil",
+            "..",
+            "a/b",
+            "a\\b",
+            "evil.json",
+            "%2e%2e",
+            "with space",
+            "ünïcode",
+            "standard",
+        ] {
+            assert!(!valid_name(name), "{:?}", name);
+        }
+        assert!(!valid_name(&"a".repeat(MAX_NAME_LEN + 1)));
+    }
+
+    #[test]
+    fn test_store() {
+        let dir = temp_dir("store");
+        let store = LayoutStore::new(dir.clone());
+        assert!(store.get("mine").unwrap().is_none());
+        assert!(!store.exists("mine"));
+
+        let saved = layout(&[control(ControlKind::Stick, Binding::RightStick, 0.1, 0.1)]);
+        store.put("mine", &saved).unwrap();
+        assert_eq!(store.get("mine").unwrap(), Some(saved.clone()));
+        assert!(dir.join("mine.json").is_file());
+        assert!(store.exists("mine"));
+
+        // Names which would reach outside the directory aren't read or written at all
+        let parent = dir.parent().unwrap();
+        fs::write(parent.join("evil.json"), "{}").unwrap();
+        assert!(store.get("../evil").unwrap().is_none());
+        assert!(store.put("../../evil", &saved).is_err());
+        assert!(store.put("standard", &saved).is_err());
+        assert_eq!(
+            fs::read_dir(&dir).unwrap().count(),
+            1,
+            "only mine.json should be there"
+        );
+
+        let _ = fs::remove_file(parent.join("evil.json"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,64 @@
+//! Advertising the server over mDNS, so that companion apps can find it without a QR code
+
+use eyre::{Result, WrapErr};
+use libmdns::{Responder, Service};
+
+/// The service type we're advertised as
+pub(crate) const SERVICE_TYPE: &str = "_sphrosyne._tcp";
+
+/// The longest a DNS label, and so an instance name, can be
+const MAX_NAME_LEN: usize = 63;
+
+/// Keeps the server advertised for as long as it's alive, withdrawing it once dropped
+pub(crate) struct Advertisement {
+    /// The instance name we're advertised under
+    pub(crate) name: String,
+
+    // Fields are dropped in order, so the service is withdrawn while the responder still runs
+    _service: Service,
+    _responder: Responder,
+}
+
+impl Advertisement {
+    /// Advertise the server going by the given name on the given port, with the given TXT
+    /// record entries
+    pub(crate) fn start(name: &str, port: u16, txt: &[String]) -> Result<Self> {
+        let responder = Responder::new().wrap_err("Could not start the mDNS responder")?;
+        let name = instance_name(name, port);
+        let txt: Vec<_> = txt.iter().map(String::as_str).collect();
+        let service = responder.register(SERVICE_TYPE.to_string(), name.clone(), port, &txt);
+        Ok(Self {
+            name,
+            _service: service,
+            _responder: responder,
+        })
+    }
+}
+
+/// The instance name of the server going by the given name on the given port, which tells
+/// apart several instances on the same network
+fn instance_name(name: &str, port: u16) -> String {
+    let mut name = format!("sphrosyne on {}:{}", name, port);
+    if name.len() > MAX_NAME_LEN {
+        let mut end = MAX_NAME_LEN;
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name.truncate(end);
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instance_name() {
+        assert_eq!(instance_name("desktop", 8080), "sphrosyne on desktop:8080");
+
+        let long = instance_name(&"é".repeat(40), 8080);
+        assert!(long.len() <= MAX_NAME_LEN);
+        assert!(long.starts_with("sphrosyne on é"));
+    }
+}
@@ -0,0 +1,375 @@
+//! Running the server inside a program of your own, which is all the sphrosyne binary does too
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, sync_channel, Receiver, SyncSender},
+        Arc,
+    },
+    thread::{spawn, JoinHandle},
+};
+
+use eyre::{format_err, Result};
+use slog::{info, o, Discard, Logger};
+
+use crate::{
+    args::Args,
+    auth::Token,
+    keymap::Keymap,
+    metrics::Metrics,
+    pads::{self, handle_pads},
+    profiles::Profiles,
+    recorder::{self, Recorder},
+    remap::RemapProfiles,
+    request::{self, PadRequest, PadType},
+    server::{self, Frontend, Invite, STATUS_TIMEOUT},
+    snapshot::Snapshot,
+    status::Status,
+};
+
+/// Something that happened to one of the server's pads
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PadEvent {
+    /// A client got a pad, either a new one or the one it had before losing its connection
+    Connected {
+        id: usize,
+        pad_type: PadType,
+
+        /// The pad's player number, counting from 1, if the bus gave it one right away
+        player: Option<u32>,
+
+        /// The address the client connected from
+        ip: IpAddr,
+    },
+
+    /// A pad's client lost its connection or let go of the pad
+    Disconnected { id: usize },
+}
+
+/// What's called with every [PadEvent], on the thread handling the pads
+type PadEvents = Box<dyn Fn(PadEvent) + Send>;
+
+/// Configures a server before [starting](Self::start) it, with the same defaults as the binary's
+/// options unless said otherwise
+pub struct SphrosyneServer {
+    args: Args,
+    logger: Logger,
+
+    /// The token clients need, if not one restored from the snapshot or a random one
+    token: Option<String>,
+    on_pad_event: Option<PadEvents>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl Default for SphrosyneServer {
+    fn default() -> Self {
+        Self::from_args(Args::default())
+    }
+}
+
+impl SphrosyneServer {
+    /// A server configured like the binary without any options, which logs nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A server configured like the binary with the given options
+    pub fn from_args(args: Args) -> Self {
+        Self {
+            args,
+            logger: Logger::root(Discard, o!()),
+            token: None,
+            on_pad_event: None,
+            shutdown: Arc::default(),
+        }
+    }
+
+    /// Listen on the given address, with port 0 meaning any free port, which
+    /// [ServerHandle::port] tells
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.args.bind = addr.ip();
+        self.args.port = addr.port();
+        self
+    }
+
+    /// Tell clients the server goes by this name, rather than the machine's hostname
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.args.name = Some(name.into());
+        self
+    }
+
+    /// Let clients have at most this many pads at once
+    pub fn max_pads(mut self, max_pads: usize) -> Self {
+        self.args.max_pads = max_pads;
+        self
+    }
+
+    /// Whether to refuse xbox 360 pads past the four XInput slots, rather than let clients have
+    /// them with a warning, which is the default
+    pub fn xinput_only(mut self, xinput_only: bool) -> Self {
+        self.args.xinput_only = xinput_only;
+        self
+    }
+
+    /// Keep the layouts clients arrange themselves in the given directory, rather than
+    /// `sphrosyne-layouts` in the working directory
+    pub fn layouts(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.args.layouts = dir.into();
+        self
+    }
+
+    /// Let in the clients presenting this token, rather than a random one
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Whether to advertise the server on the local network over mDNS, which it is by default
+    pub fn mdns(mut self, mdns: bool) -> Self {
+        self.args.mdns = mdns;
+        self
+    }
+
+    /// Whether to serve with tokio and hyper rather than a thread per client, which needs the
+    /// async-server feature and isn't the case by default
+    pub fn async_server(mut self, async_server: bool) -> Self {
+        self.args.async_server = async_server;
+        self
+    }
+
+    /// Log to the given logger, rather than nowhere
+    pub fn logger(mut self, logger: Logger) -> Self {
+        self.logger = logger;
+        self
+    }
+
+    /// Call `on_pad_event` whenever a client gets a pad or lets go of one. It's called on the
+    /// thread handling the pads, which it holds up until it returns.
+    pub fn on_pad_event(mut self, on_pad_event: impl Fn(PadEvent) + Send + 'static) -> Self {
+        self.on_pad_event = Some(Box::new(on_pad_event));
+        self
+    }
+
+    /// Shut down once `shutdown` is set, e.g. by a Ctrl-C handler, just like
+    /// [ServerHandle::shutdown] does
+    pub fn shutdown_on(mut self, shutdown: Arc<AtomicBool>) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Start serving and handling pads on threads of their own, returning once the server is
+    /// bound. Nothing is plugged into the bus until ViGEmBus is there, which clients are told.
+    pub fn start(self) -> Result<ServerHandle> {
+        let Self {
+            args,
+            logger,
+            token,
+            on_pad_event,
+            shutdown,
+        } = self;
+
+        #[cfg(not(feature = "async-server"))]
+        if args.async_server {
+            return Err(format_err!(
+                "sphrosyne was built without the async server, rebuild it with --features async-server"
+            ));
+        }
+
+        let profiles = Profiles::load(&logger, args.profiles.clone());
+        let (restored_token, restored) = match &args.snapshot {
+            Some(path) => match Snapshot::load(&logger, path)? {
+                Some(snapshot) => {
+                    info!(logger, "snapshot.restore"; "path" => %path.display(), "pads" => snapshot.pads.len());
+                    (Some(Token::from(snapshot.token)), snapshot.pads)
+                }
+                None => (None, Vec::new()),
+            },
+            None => (None, Vec::new()),
+        };
+        let token = token
+            .map(Token::from)
+            .or(restored_token)
+            .unwrap_or_else(Token::generate);
+        let keymap = Keymap::load(args.keymap.as_deref())?;
+        let remaps = RemapProfiles::load(args.remaps.as_deref())?;
+        let recorder = match &args.record {
+            Some(path) => {
+                info!(logger, "record.start"; "path" => %path.display());
+                Some(Recorder::create(path)?)
+            }
+            None => None,
+        };
+
+        let metrics = Arc::new(Metrics::default());
+        let driver_missing = Arc::new(AtomicBool::new(false));
+        let rotate_token = Arc::new(AtomicBool::new(false));
+        let (invites_tx, invites) = channel();
+        let (bound_tx, bound) = channel();
+        let frontend = Frontend {
+            rotate_token: Arc::clone(&rotate_token),
+            // Only the window ever shows the invites
+            invites: cfg!(feature = "gui").then_some(invites_tx),
+            bound: Some(bound_tx),
+        };
+        let (req_tx, req_rx) = sync_channel(request::QUEUE_SIZE);
+        let server = {
+            let (logger, args, metrics) = (logger.clone(), args.clone(), Arc::clone(&metrics));
+            let (req_tx, shutdown) = (req_tx.clone(), Arc::clone(&shutdown));
+            let driver_missing = Arc::clone(&driver_missing);
+            // Both servers serve the same routes and websockets, so either takes the same things
+            #[cfg(feature = "async-server")]
+            let mainloop = if args.async_server {
+                crate::async_server::mainloop
+            } else {
+                server::mainloop
+            };
+            #[cfg(not(feature = "async-server"))]
+            let mainloop = server::mainloop;
+            spawn(move || {
+                mainloop(
+                    logger,
+                    args,
+                    token,
+                    keymap,
+                    remaps,
+                    req_tx,
+                    shutdown,
+                    metrics,
+                    driver_missing,
+                    frontend,
+                )
+            })
+        };
+        let pads = {
+            let logger = logger.clone();
+            spawn(move || {
+                let on_pad_event = |event| {
+                    if let Some(on_pad_event) = &on_pad_event {
+                        on_pad_event(event);
+                    }
+                };
+                handle_pads(
+                    logger,
+                    &args,
+                    recorder,
+                    profiles,
+                    restored,
+                    req_rx,
+                    &metrics,
+                    &driver_missing,
+                    &on_pad_event,
+                )
+            })
+        };
+
+        let handle = ServerHandle {
+            addr: SocketAddr::from(([0, 0, 0, 0], 0)),
+            logger,
+            req_tx,
+            shutdown,
+            rotate_token,
+            invites,
+            server,
+            pads,
+        };
+        match bound.recv() {
+            Ok(addr) => Ok(ServerHandle { addr, ..handle }),
+            // The server gave up before binding, which waiting on it tells why
+            Err(_) => Err(handle
+                .wait()
+                .err()
+                .unwrap_or_else(|| format_err!("The server stopped before binding"))),
+        }
+    }
+}
+
+/// A running server, which keeps running until it's [shut down](Self::shutdown)
+pub struct ServerHandle {
+    /// The address the server is bound to
+    addr: SocketAddr,
+    logger: Logger,
+
+    /// Where requests for the pads go, the same way the server sends them
+    req_tx: SyncSender<PadRequest>,
+    shutdown: Arc<AtomicBool>,
+
+    /// What the window needs to show how to connect, which only it uses
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    rotate_token: Arc<AtomicBool>,
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    invites: Receiver<Invite>,
+    server: JoinHandle<Result<()>>,
+    pads: JoinHandle<Result<()>>,
+}
+
+impl ServerHandle {
+    /// The address the server is bound to
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The port the server is bound to, which is the one it was asked to unless that was 0
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// A snapshot of the bus and pads' state, the same as served at `/status`. Fails while the
+    /// pads are waiting for ViGEmBus, as they can't answer meanwhile.
+    pub fn stats(&self) -> Result<Status> {
+        let (reply_tx, reply_rx) = channel();
+        self.req_tx
+            .send(PadRequest::Status(reply_tx))
+            .map_err(|_| format_err!("The pads are gone"))?;
+        reply_rx
+            .recv_timeout(STATUS_TIMEOUT)
+            .map_err(|_| format_err!("The pads did not answer"))
+    }
+
+    /// Close every connection, remove every pad from the bus and wait for both to be done
+    pub fn shutdown(self) -> Result<()> {
+        info!(self.logger, "shutdown.requested");
+        self.shutdown.store(true, Ordering::SeqCst);
+        self.wait()
+    }
+
+    /// Wait for the server to shut down, e.g. once the flag given to
+    /// [SphrosyneServer::shutdown_on] is set
+    pub fn wait(self) -> Result<()> {
+        let served = self
+            .server
+            .join()
+            .map_err(|_| format_err!("The thread serving clients panicked"))?;
+        // The pads only stop by themselves once the server tells them to after shutting down
+        if served.is_err() {
+            let _ = self.req_tx.send(PadRequest::Shutdown);
+        }
+        self.pads
+            .join()
+            .map_err(|_| format_err!("The thread handling the pads panicked"))??;
+        served
+    }
+
+    /// Show the window on this thread, which has to be the main one on some platforms. Closing
+    /// it shuts the server down, returning once that's done.
+    #[cfg(feature = "gui")]
+    pub fn show_window(mut self) -> Result<()> {
+        let invites = std::mem::replace(&mut self.invites, channel().1);
+        let window = crate::gui::Window::new(
+            self.logger.clone(),
+            self.req_tx.clone(),
+            invites,
+            Arc::clone(&self.rotate_token),
+            Arc::clone(&self.shutdown),
+        );
+        crate::gui::run(window, self)
+    }
+}
+
+/// Replay a recording made with `--record` through new pads, rather than starting the server
+pub fn replay(logger: &Logger, path: &Path) -> Result<()> {
+    let client = pads::connect_client(logger)?;
+    recorder::replay(logger, &client, path)
+}
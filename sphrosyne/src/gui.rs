@@ -0,0 +1,369 @@
+//! The native window shown with `--gui`, for when sphrosyne runs on a PC nobody sits at
+
+use std::{
+    convert::TryFrom,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, SyncSender, TryRecvError, TrySendError},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use eframe::egui::{self, Color32, ColorImage, TextureHandle, TextureOptions};
+use eyre::{format_err, Result};
+use qrcodegen::{QrCode, QrCodeEcc};
+use slog::{info, warn, Logger};
+
+use crate::{
+    embed::ServerHandle,
+    request::{PadRequest, PadType},
+    server::Invite,
+    status::{PadStatus, Status, UserIndexStatus},
+};
+
+/// How often the pads are asked for their status, and the ages of their updates redrawn
+const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How many modules of white go around the QR code, which is what readers expect
+const QR_BORDER: usize = 4;
+
+/// How large the QR code is drawn, in points
+const QR_SIZE: f32 = 280.0;
+
+/// The window, showing the QR codes to scan and the pads, which can be kicked from it
+pub(crate) struct Window {
+    logger: Logger,
+
+    /// Where requests for the pads go, the same way the server sends them
+    tx: SyncSender<PadRequest>,
+
+    /// How clients can reach us, whenever that changes
+    invites: Receiver<Invite>,
+    invite: Option<Invite>,
+
+    /// Which layout's QR code is shown, by index into the invite's controllers
+    layout: usize,
+
+    /// The QR code of the layout which is shown, until the invite or the layout changes
+    qr: Option<TextureHandle>,
+
+    /// The last status the pads replied with
+    status: Option<Status>,
+
+    /// Where the pads reply to our last request for their status, until they do
+    pending: Option<Receiver<Status>>,
+
+    /// When we last asked the pads for their status
+    polled: Option<Instant>,
+
+    /// Set to have the server rotate the token
+    rotate_token: Arc<AtomicBool>,
+
+    /// Set once we're shutting down, which closes the window if it's not closed already
+    shutdown: Arc<AtomicBool>,
+}
+
+impl Window {
+    pub(crate) fn new(
+        logger: Logger,
+        tx: SyncSender<PadRequest>,
+        invites: Receiver<Invite>,
+        rotate_token: Arc<AtomicBool>,
+        shutdown: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            logger,
+            tx,
+            invites,
+            invite: None,
+            layout: 0,
+            qr: None,
+            status: None,
+            pending: None,
+            polled: None,
+            rotate_token,
+            shutdown,
+        }
+    }
+
+    /// Take the pads' reply to our last request for their status if it's in, and ask them again
+    /// if it's time to. Returns whether the pads are still around to ask.
+    fn poll_status(&mut self) -> bool {
+        if let Some(reply_rx) = &self.pending {
+            match reply_rx.try_recv() {
+                Ok(status) => self.status = Some(status),
+                Err(TryRecvError::Empty) => return true,
+                // The pads went away without replying, which we find out below
+                Err(TryRecvError::Disconnected) => {}
+            }
+            self.pending = None;
+        }
+        if self
+            .polled
+            .is_some_and(|at| at.elapsed() < REFRESH_INTERVAL)
+        {
+            return true;
+        }
+
+        let (reply_tx, reply_rx) = channel();
+        match self.tx.try_send(PadRequest::Status(reply_tx)) {
+            Ok(()) => {
+                self.pending = Some(reply_rx);
+                self.polled = Some(Instant::now());
+                true
+            }
+            // Waiting on the pads would freeze the window, so just ask again next time
+            Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    /// Have the pad with the given index taken away from its client
+    fn kick(&self, id: usize) {
+        info!(self.logger, "gui.kick"; "id" => id);
+        if let Err(error) = self.tx.try_send(PadRequest::Discard(id)) {
+            warn!(self.logger, "gui.kick"; "id" => id, "error" => %error);
+        }
+    }
+
+    /// The QR code of the layout which is shown, made if it hasn't been already
+    fn qr(&mut self, ctx: &egui::Context) -> Option<TextureHandle> {
+        if self.qr.is_none() {
+            let (_, url) = self.invite.as_ref()?.controllers.get(self.layout)?;
+            match qr_image(url) {
+                Ok(image) => self.qr = Some(ctx.load_texture("qr", image, TextureOptions::NEAREST)),
+                Err(error) => warn!(self.logger, "gui.qr"; "error" => %error),
+            }
+        }
+        self.qr.clone()
+    }
+
+    /// Show the QR code to scan along with the link and token it contains, and the button to
+    /// rotate the token
+    fn show_invite(&mut self, ctx: &egui::Context, ui: &mut egui::Ui) {
+        let invite = match &self.invite {
+            Some(invite) => invite.clone(),
+            None => {
+                ui.label("Starting the server...");
+                return;
+            }
+        };
+
+        ui.horizontal(|ui| {
+            for (index, (layout, _)) in invite.controllers.iter().enumerate() {
+                if ui
+                    .selectable_value(&mut self.layout, index, layout.name)
+                    .changed()
+                {
+                    self.qr = None;
+                }
+            }
+        });
+        let (layout, url) = &invite.controllers[self.layout];
+        ui.label(layout.description);
+        if let Some(qr) = self.qr(ctx) {
+            ui.add(egui::Image::new(&qr).fit_to_exact_size(egui::vec2(QR_SIZE, QR_SIZE)));
+        }
+        ui.hyperlink(url);
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("Token:");
+            ui.monospace(&invite.token);
+        });
+        if ui
+            .button("New token")
+            .on_hover_text("Phones which haven't connected yet have to scan the new QR code")
+            .clicked()
+        {
+            info!(self.logger, "gui.rotate");
+            self.rotate_token.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Show the pads, each with a button to kick its client
+    fn show_pads(&self, ui: &mut egui::Ui) {
+        let status = match &self.status {
+            Some(status) => status,
+            None => {
+                ui.label("Waiting for the pads...");
+                return;
+            }
+        };
+
+        if status.bus_connected {
+            ui.label("ViGEmBus is connected.");
+        } else {
+            ui.colored_label(Color32::RED, "ViGEmBus went away, reconnecting...");
+        }
+        if status.pads.is_empty() {
+            ui.label("Nobody is connected yet.");
+            return;
+        }
+
+        let now = SystemTime::now();
+        let mut kicked = None;
+        egui::Grid::new("pads")
+            .striped(true)
+            .num_columns(5)
+            .show(ui, |ui| {
+                for header in ["Player", "Type", "Client", "Last update", ""] {
+                    ui.strong(header);
+                }
+                ui.end_row();
+
+                for pad in &status.pads {
+                    ui.label(player(pad));
+                    ui.label(match pad.pad_type {
+                        PadType::X360 => "xbox 360",
+                        PadType::DS4 => "dualshock 4",
+                    });
+                    ui.label(if pad.free {
+                        "none"
+                    } else if pad.detached {
+                        "disconnected"
+                    } else if pad.suspended {
+                        "in background"
+                    } else {
+                        "connected"
+                    });
+                    ui.label(describe_age(pad.last_update_ms, now));
+                    if ui
+                        .add_enabled(!pad.free, egui::Button::new("Kick"))
+                        .clicked()
+                    {
+                        kicked = Some(pad.id);
+                    }
+                    ui.end_row();
+                }
+            });
+        if let Some(id) = kicked {
+            self.kick(id);
+        }
+    }
+}
+
+impl eframe::App for Window {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        // Ctrl-C closes the window as well, and so does losing the pads
+        if !self.poll_status() || self.shutdown.load(Ordering::SeqCst) {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        }
+        if let Some(invite) = self.invites.try_iter().last() {
+            self.layout = self.layout.min(invite.controllers.len().saturating_sub(1));
+            self.invite = Some(invite);
+            self.qr = None;
+        }
+
+        egui::SidePanel::left("invite")
+            .resizable(false)
+            .show(ctx, |ui| self.show_invite(ctx, ui));
+        egui::CentralPanel::default().show(ctx, |ui| self.show_pads(ui));
+
+        // Nothing else wakes us up when the pads change
+        ctx.request_repaint_after(REFRESH_INTERVAL);
+    }
+}
+
+/// Show the window on this thread, which has to be the main one on some platforms, while the
+/// server runs on threads of its own. Closing the window shuts us down just like Ctrl-C does,
+/// returning once the pads are gone.
+pub(crate) fn run(window: Window, server: ServerHandle) -> Result<()> {
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_inner_size([760.0, 460.0]),
+        ..Default::default()
+    };
+    let shown = eframe::run_native(
+        "Sphrosyne",
+        options,
+        Box::new(move |_| Ok(Box::new(window))),
+    );
+
+    server.shutdown()?;
+    shown.map_err(|error| format_err!("Could not show the window: {}", error))
+}
+
+/// Draw a QR code of the given text, a pixel per module, to be scaled up without smoothing
+fn qr_image(text: &str) -> Result<ColorImage> {
+    let qr = QrCode::encode_text(text, QrCodeEcc::Low)?;
+    let modules = usize::try_from(qr.size())?;
+    let side = modules + 2 * QR_BORDER;
+    let mut image = ColorImage::new([side, side], Color32::WHITE);
+    for y in 0..qr.size() {
+        for x in 0..qr.size() {
+            if qr.get_module(x, y) {
+                let (x, y) = (x as usize + QR_BORDER, y as usize + QR_BORDER);
+                image.pixels[y * side + x] = Color32::BLACK;
+            }
+        }
+    }
+    Ok(image)
+}
+
+/// Which player a pad is, as shown in the window
+fn player(pad: &PadStatus) -> String {
+    if let Some(label) = &pad.player_label {
+        return label.clone();
+    }
+    match pad.user_index {
+        UserIndexStatus::Assigned(index) => (index + 1).to_string(),
+        UserIndexStatus::Pending => "...".to_string(),
+        UserIndexStatus::Unknown => "-".to_string(),
+    }
+}
+
+/// How long before `now` a pad's client last sent an update, given as in
+/// [PadStatus::last_update_ms]
+fn describe_age(last_update_ms: Option<u128>, now: SystemTime) -> String {
+    let last = match last_update_ms {
+        Some(ms) => UNIX_EPOCH + Duration::from_millis(u64::try_from(ms).unwrap_or(u64::MAX)),
+        None => return "never".to_string(),
+    };
+    // Our clock may have gone back since
+    let age = now.duration_since(last).unwrap_or_default().as_secs_f64();
+    if age < 60.0 {
+        format!("{:.1}s ago", age)
+    } else if age < 3600.0 {
+        format!("{:.0}m ago", (age / 60.0).floor())
+    } else {
+        format!("{:.0}h ago", (age / 3600.0).floor())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_qr_image() {
+        let image = qr_image("http://localhost:1234/controller?token=0123").unwrap();
+        let side = image.size[0];
+        assert_eq!(image.size, [side, side]);
+        let pixel = |x: usize, y: usize| image.pixels[y * side + x];
+
+        // The border is white all around, and the finder pattern starts right inside it
+        for i in 0..side {
+            for j in 0..QR_BORDER {
+                assert_eq!(pixel(i, j), Color32::WHITE);
+                assert_eq!(pixel(j, i), Color32::WHITE);
+                assert_eq!(pixel(i, side - 1 - j), Color32::WHITE);
+                assert_eq!(pixel(side - 1 - j, i), Color32::WHITE);
+            }
+        }
+        assert_eq!(pixel(QR_BORDER, QR_BORDER), Color32::BLACK);
+        assert_eq!(pixel(QR_BORDER + 1, QR_BORDER + 1), Color32::WHITE);
+    }
+
+    #[test]
+    fn test_describe_age() {
+        let now = UNIX_EPOCH + Duration::from_secs(10_000);
+        let ago = |secs: f64| Some((10_000.0 - secs) as u128 * 1000);
+        assert_eq!(describe_age(None, now), "never");
+        assert_eq!(describe_age(ago(3.0), now), "3.0s ago");
+        assert_eq!(describe_age(ago(150.0), now), "2m ago");
+        assert_eq!(describe_age(ago(7300.0), now), "2h ago");
+        // Updates from what's the future to our clock just happened
+        assert_eq!(describe_age(Some(20_000_000), now), "0.0s ago");
+    }
+}
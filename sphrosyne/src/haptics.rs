@@ -0,0 +1,112 @@
+//! Turning rumble into vibration patterns for phones, whose browsers can only switch their
+//! vibration motor on and off rather than set how strongly it vibrates
+
+use std::time::{Duration, Instant};
+
+use vigem_client_c::client::X360NotificationData;
+
+/// How long each pattern lasts, which is also how often a client is sent one while its pad rumbles
+pub(crate) const HAPTIC_WINDOW: Duration = Duration::from_millis(100);
+
+/// How long each on and off pulse within a pattern takes together, in milliseconds, which is short
+/// enough for the pulses to be felt as a weaker vibration rather than as separate buzzes
+const PULSE_PERIOD_MS: u32 = 20;
+
+/// A pattern for `navigator.vibrate` lasting [HAPTIC_WINDOW] which is on for as much of it as
+/// `intensity` is of the strongest rumble, as alternating on and off durations in milliseconds.
+/// No rumble is an empty pattern, which stops the vibration, and the strongest one is on
+/// throughout.
+pub(crate) fn pattern(intensity: u8) -> Vec<u32> {
+    let window = HAPTIC_WINDOW.as_millis() as u32;
+    match intensity {
+        0 => Vec::new(),
+        u8::MAX => vec![window],
+        _ => {
+            let on = (PULSE_PERIOD_MS * u32::from(intensity) / u32::from(u8::MAX)).max(1);
+            let off = PULSE_PERIOD_MS - on;
+            (0..window / PULSE_PERIOD_MS)
+                .flat_map(|_| [on, off])
+                .collect()
+        }
+    }
+}
+
+/// Keeps track of how strongly a pad rumbles, to send its client a new pattern every
+/// [HAPTIC_WINDOW] for as long as it does
+#[derive(Debug, Default)]
+pub(crate) struct Haptics {
+    /// How strongly the pad rumbles according to the last notification
+    intensity: u8,
+
+    /// The intensity of the last pattern sent, and when it was sent
+    sent: Option<(u8, Instant)>,
+}
+
+impl Haptics {
+    /// Take note of how strongly a notification says the pad rumbles, which is whichever of its
+    /// motors is the stronger
+    pub(crate) fn note(&mut self, data: &X360NotificationData) {
+        self.intensity = data.large_motor.max(data.small_motor);
+    }
+
+    /// The pattern to send the client at `now`, if the last one is over and the pad rumbles, or
+    /// stopped rumbling since the last one was sent
+    pub(crate) fn due(&mut self, now: Instant) -> Option<Vec<u32>> {
+        match self.sent {
+            Some((_, at)) if now.duration_since(at) < HAPTIC_WINDOW => return None,
+            Some((0, _)) | None if self.intensity == 0 => return None,
+            _ => {}
+        }
+        self.sent = Some((self.intensity, now));
+        Some(pattern(self.intensity))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rumble(large_motor: u8, small_motor: u8) -> X360NotificationData {
+        X360NotificationData {
+            large_motor,
+            small_motor,
+            led_number: 0,
+        }
+    }
+
+    #[test]
+    fn test_pattern() {
+        assert_eq!(pattern(0), Vec::<u32>::new());
+        assert_eq!(pattern(u8::MAX), [100]);
+        assert_eq!(pattern(128), [10; 10]);
+
+        // Weaker rumbles are on for less of each pulse, but never not at all
+        let weak = pattern(64);
+        assert_eq!(weak[..2], [5, 15]);
+        assert_eq!(pattern(1)[..2], [1, 19]);
+        for pattern in [weak, pattern(200)] {
+            assert_eq!(pattern.iter().sum::<u32>(), 100);
+        }
+    }
+
+    #[test]
+    fn test_haptics() {
+        let mut haptics = Haptics::default();
+        let start = Instant::now();
+        assert_eq!(haptics.due(start), None);
+
+        // Rumbling sends a pattern right away, and another once it's over
+        haptics.note(&rumble(0, u8::MAX));
+        assert_eq!(haptics.due(start), Some(vec![100]));
+        assert_eq!(haptics.due(start + HAPTIC_WINDOW / 2), None);
+        assert_eq!(haptics.due(start + HAPTIC_WINDOW), Some(vec![100]));
+
+        // Stopping is sent once, after which nothing is until the pad rumbles again
+        haptics.note(&rumble(0, 0));
+        assert_eq!(haptics.due(start + HAPTIC_WINDOW * 3 / 2), None);
+        assert_eq!(haptics.due(start + HAPTIC_WINDOW * 2), Some(Vec::new()));
+        assert_eq!(haptics.due(start + HAPTIC_WINDOW * 4), None);
+        haptics.note(&rumble(128, 0));
+        assert_eq!(haptics.due(start + HAPTIC_WINDOW * 4), Some(vec![10; 10]));
+    }
+}
@@ -0,0 +1,162 @@
+//! Which of this machine's addresses to put in the QR codes, since plenty of phones can't resolve
+//! its hostname
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, UdpSocket},
+};
+
+/// Where to pretend to send to, to find out which address the default route goes out of. It's
+/// reserved for documentation, and connecting a UDP socket doesn't send anything anyway.
+const DEFAULT_ROUTE_PROBE: (Ipv4Addr, u16) = (Ipv4Addr::new(192, 0, 2, 1), 9);
+
+/// An IPv4 address of one of this machine's network interfaces
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Address {
+    /// The interface's name, e.g. `Wi-Fi` or `eth0`
+    pub(crate) interface: String,
+    pub(crate) ip: Ipv4Addr,
+}
+
+/// The addresses phones could reach us at, best first. Only the one on the default route is
+/// given if there's one, and every other one otherwise, as there's no telling which network the
+/// phones are on. Binding to a single address leaves only that one.
+pub(crate) fn local_addresses(bind: IpAddr) -> io::Result<Vec<Address>> {
+    let addresses = if_addrs::get_if_addrs()?
+        .into_iter()
+        .filter_map(|interface| match interface.ip() {
+            IpAddr::V4(ip) => Some(Address {
+                interface: interface.name,
+                ip,
+            }),
+            IpAddr::V6(_) => None,
+        })
+        .collect();
+    Ok(pick(addresses, default_route(), bind))
+}
+
+/// The address we'd send from to reach the internet, if we can
+fn default_route() -> Option<Ipv4Addr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect(DEFAULT_ROUTE_PROBE).ok()?;
+    match socket.local_addr().ok()?.ip() {
+        IpAddr::V4(ip) => Some(ip),
+        IpAddr::V6(_) => None,
+    }
+}
+
+/// Pick which of the given addresses phones could reach us at, the way [local_addresses] does
+fn pick(addresses: Vec<Address>, default_route: Option<Ipv4Addr>, bind: IpAddr) -> Vec<Address> {
+    let mut candidates: Vec<Address> = Vec::new();
+    for address in addresses {
+        let ip = address.ip;
+        // Phones can't get to these, or they're the same as one we already have
+        if ip.is_loopback()
+            || ip.is_link_local()
+            || ip.is_unspecified()
+            || candidates.iter().any(|candidate| candidate.ip == ip)
+        {
+            continue;
+        }
+        if !bind.is_unspecified() && IpAddr::V4(ip) != bind {
+            continue;
+        }
+        candidates.push(address);
+    }
+
+    if let Some(address) =
+        default_route.and_then(|route| candidates.iter().find(|candidate| candidate.ip == route))
+    {
+        return vec![address.clone()];
+    }
+    // Home networks are private, so those are likelier to be the one the phones are on
+    candidates.sort_by_key(|candidate| !candidate.ip.is_private());
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(interface: &str, ip: [u8; 4]) -> Address {
+        Address {
+            interface: interface.to_string(),
+            ip: Ipv4Addr::from(ip),
+        }
+    }
+
+    fn interfaces() -> Vec<Address> {
+        vec![
+            address("lo", [127, 0, 0, 1]),
+            address("Ethernet", [203, 0, 113, 7]),
+            address("Wi-Fi", [192, 168, 1, 10]),
+            address("APIPA", [169, 254, 3, 4]),
+            address("vEthernet", [172, 17, 0, 1]),
+        ]
+    }
+
+    const ANY: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+
+    #[test]
+    fn test_default_route() {
+        assert_eq!(
+            pick(interfaces(), Some(Ipv4Addr::new(192, 168, 1, 10)), ANY),
+            [address("Wi-Fi", [192, 168, 1, 10])]
+        );
+    }
+
+    #[test]
+    fn test_ambiguous() {
+        // Without a default route, or with one out of an address phones can't reach, every
+        // candidate is given, the private ones first
+        let expected = [
+            address("Wi-Fi", [192, 168, 1, 10]),
+            address("vEthernet", [172, 17, 0, 1]),
+            address("Ethernet", [203, 0, 113, 7]),
+        ];
+        assert_eq!(pick(interfaces(), None, ANY), expected);
+        assert_eq!(
+            pick(interfaces(), Some(Ipv4Addr::new(127, 0, 0, 1)), ANY),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let unreachable = vec![
+            address("lo", [127, 0, 0, 1]),
+            address("APIPA", [169, 254, 3, 4]),
+            address("down", [0, 0, 0, 0]),
+        ];
+        assert_eq!(pick(unreachable, None, ANY), []);
+        assert_eq!(pick(Vec::new(), None, ANY), []);
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let addresses = vec![
+            address("Wi-Fi", [192, 168, 1, 10]),
+            address("Wi-Fi 2", [192, 168, 1, 10]),
+        ];
+        assert_eq!(
+            pick(addresses, None, ANY),
+            [address("Wi-Fi", [192, 168, 1, 10])]
+        );
+    }
+
+    #[test]
+    fn test_bind() {
+        let bind = IpAddr::V4(Ipv4Addr::new(172, 17, 0, 1));
+        let vethernet = [address("vEthernet", [172, 17, 0, 1])];
+        assert_eq!(pick(interfaces(), None, bind), vethernet);
+        // The default route doesn't matter if we can't be reached through it
+        assert_eq!(
+            pick(interfaces(), Some(Ipv4Addr::new(192, 168, 1, 10)), bind),
+            vethernet
+        );
+        assert_eq!(
+            pick(interfaces(), None, IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            []
+        );
+    }
+}
@@ -0,0 +1,191 @@
+//! Mapping keyboard keys to pad inputs, for devices without a touchscreen
+
+use std::{collections::HashMap, f32::consts::FRAC_1_SQRT_2, fs, path::Path};
+
+use eyre::{Result, WrapErr};
+use serde::Deserialize;
+use vigem_client_c::{axis_from_f32, StickPosition, X360Buttons, X360State};
+
+/// The keymap used unless another one is given with `--keymap`
+const DEFAULT_KEYMAP: &str = include_str!("keymap.toml");
+
+/// What holding down a key does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Action {
+    A,
+    B,
+    X,
+    Y,
+    LeftShoulder,
+    RightShoulder,
+    Back,
+    Start,
+    Guide,
+    LeftThumb,
+    RightThumb,
+    DpadUp,
+    DpadDown,
+    DpadLeft,
+    DpadRight,
+    LeftTrigger,
+    RightTrigger,
+    LeftStickUp,
+    LeftStickDown,
+    LeftStickLeft,
+    LeftStickRight,
+    RightStickUp,
+    RightStickDown,
+    RightStickLeft,
+    RightStickRight,
+}
+
+impl Action {
+    /// The button this action presses, if it's a button
+    fn button(self) -> Option<X360Buttons> {
+        Some(match self {
+            Self::A => X360Buttons::A,
+            Self::B => X360Buttons::B,
+            Self::X => X360Buttons::X,
+            Self::Y => X360Buttons::Y,
+            Self::LeftShoulder => X360Buttons::LEFT_SHOULDER,
+            Self::RightShoulder => X360Buttons::RIGHT_SHOULDER,
+            Self::Back => X360Buttons::BACK,
+            Self::Start => X360Buttons::START,
+            Self::Guide => X360Buttons::GUIDE,
+            Self::LeftThumb => X360Buttons::LEFT_THUMB,
+            Self::RightThumb => X360Buttons::RIGHT_THUMB,
+            Self::DpadUp => X360Buttons::DPAD_UP,
+            Self::DpadDown => X360Buttons::DPAD_DOWN,
+            Self::DpadLeft => X360Buttons::DPAD_LEFT,
+            Self::DpadRight => X360Buttons::DPAD_RIGHT,
+            _ => return None,
+        })
+    }
+}
+
+/// Which of a stick's directions are held
+#[derive(Debug, Clone, Copy, Default)]
+struct Directions {
+    up: bool,
+    down: bool,
+    left: bool,
+    right: bool,
+}
+
+impl Directions {
+    /// Where the stick points. Opposite directions cancel out, and diagonals are as far from
+    /// the center as straight directions rather than at full deflection on both axes.
+    fn stick(self) -> StickPosition {
+        let x = f32::from(i8::from(self.right) - i8::from(self.left));
+        let y = f32::from(i8::from(self.up) - i8::from(self.down));
+        let scale = if x != 0.0 && y != 0.0 {
+            FRAC_1_SQRT_2
+        } else {
+            1.0
+        };
+        StickPosition::new(axis_from_f32(x * scale), axis_from_f32(y * scale))
+    }
+}
+
+/// Which keys do what, by their `KeyboardEvent.code`
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Keymap {
+    keys: HashMap<String, Action>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        toml::from_str(DEFAULT_KEYMAP).expect("the built-in keymap is invalid")
+    }
+}
+
+impl Keymap {
+    /// Load the keymap at the given path, or the built-in one if there's no path
+    pub(crate) fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+        let data = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Could not read {}", path.display()))?;
+        toml::from_str(&data).wrap_err_with(|| format!("Invalid keymap in {}", path.display()))
+    }
+
+    /// The state of a pad whose keys are the given ones held down, ignoring unmapped keys
+    pub(crate) fn state<'a>(&self, down: impl IntoIterator<Item = &'a str>) -> X360State {
+        let mut state = X360State::default();
+        let (mut left, mut right) = (Directions::default(), Directions::default());
+        for action in down.into_iter().filter_map(|key| self.keys.get(key)) {
+            if let Some(button) = action.button() {
+                state.press(button);
+                continue;
+            }
+            match action {
+                Action::LeftTrigger => state.left_trigger = u8::MAX,
+                Action::RightTrigger => state.right_trigger = u8::MAX,
+                Action::LeftStickUp => left.up = true,
+                Action::LeftStickDown => left.down = true,
+                Action::LeftStickLeft => left.left = true,
+                Action::LeftStickRight => left.right = true,
+                Action::RightStickUp => right.up = true,
+                Action::RightStickDown => right.down = true,
+                Action::RightStickLeft => right.left = true,
+                Action::RightStickRight => right.right = true,
+                _ => unreachable!("buttons are handled above"),
+            }
+        }
+        state.left_thumbstick = left.stick();
+        state.right_thumbstick = right.stick();
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default() {
+        let keymap = Keymap::default();
+        assert_eq!(keymap.state(vec![]), X360State::default());
+        assert_eq!(
+            keymap.state(vec!["Space", "ArrowUp", "ShiftLeft", "Nope"]),
+            X360State::builder()
+                .press(X360Buttons::A | X360Buttons::DPAD_UP)
+                .left_trigger(u8::MAX)
+                .build()
+        );
+    }
+
+    #[test]
+    fn test_sticks() {
+        let keymap = Keymap::default();
+        assert_eq!(keymap.state(vec!["KeyW"]).left_thumbstick, (0, i16::MAX));
+        assert_eq!(keymap.state(vec!["KeyJ"]).right_thumbstick, (i16::MIN, 0));
+
+        // Opposite directions cancel out
+        assert_eq!(keymap.state(vec!["KeyA", "KeyD"]).left_thumbstick, (0, 0));
+
+        let diagonal = axis_from_f32(FRAC_1_SQRT_2);
+        assert_eq!(diagonal, 23170);
+        assert_eq!(
+            keymap.state(vec!["KeyW", "KeyD"]).left_thumbstick,
+            (diagonal, diagonal)
+        );
+        assert_eq!(
+            keymap.state(vec!["KeyK", "KeyJ"]).right_thumbstick,
+            (-diagonal, -diagonal)
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        let keymap: Keymap = toml::from_str("[keys]\nKeyZ = \"right_shoulder\"").unwrap();
+        assert_eq!(
+            keymap.state(vec!["KeyZ", "Space"]).buttons,
+            X360Buttons::RIGHT_SHOULDER
+        );
+        assert!(toml::from_str::<Keymap>("[keys]\nKeyZ = \"turbo\"").is_err());
+    }
+}
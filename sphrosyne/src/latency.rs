@@ -0,0 +1,280 @@
+//! Where the time between a client sending a state and the bus being updated with it goes: on
+//! its way to us, waiting for the pads, and in the bus itself.
+//!
+//! Clients may stamp their states with their own clock, `performance.now()` in the browser,
+//! which we can only compare with ours once we know how far apart the two are. That's estimated
+//! from a few rounds of clock pings at the start of every connection.
+
+use std::{collections::VecDeque, fmt, sync::Mutex, time::Duration};
+
+use serde::Serialize;
+
+/// How many clock pings a client is sent to estimate how far its clock is from ours
+pub(crate) const CLOCK_ROUNDS: u32 = 5;
+
+/// How many of the latest samples of each kind of delay the percentiles are computed over
+const WINDOW: usize = 512;
+
+/// Estimates how far ahead of ours a client's clock is, the way NTP does.
+///
+/// Every round, the client stamps a ping we sent with its clock before answering it, which is
+/// assumed to have happened halfway between us sending the ping and getting the answer. That's
+/// off by at most half the round trip, so the estimate comes from the quickest round.
+/// Times are in milliseconds, each side's since whenever it likes.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ClockOffset {
+    /// The round trip time and the offset it gave of the quickest round so far
+    best: Option<(f64, f64)>,
+}
+
+impl ClockOffset {
+    /// Take note of a ping we sent at `sent` and got the answer to at `answered`, which the
+    /// client stamped `stamped` by its clock. Rounds which make no sense are ignored.
+    pub(crate) fn round(&mut self, sent: f64, stamped: f64, answered: f64) {
+        let round_trip = answered - sent;
+        if !(round_trip >= 0.0 && stamped.is_finite()) {
+            return;
+        }
+        let offset = stamped - (sent + answered) / 2.0;
+        if self.best.is_none_or(|(best, _)| round_trip < best) {
+            self.best = Some((round_trip, offset));
+        }
+    }
+
+    /// How far ahead of ours the client's clock is, once a round went through
+    pub(crate) fn offset(&self) -> Option<f64> {
+        self.best.map(|(_, offset)| offset)
+    }
+
+    /// How long it took a message the client stamped `stamped` to reach us at `received`.
+    /// The estimate can be off by up to half a round trip, so this never goes below zero.
+    pub(crate) fn delay(&self, stamped: f64, received: f64) -> Option<Duration> {
+        let sent = stamped - self.offset()?;
+        Duration::try_from_secs_f64((received - sent).max(0.0) / 1000.0).ok()
+    }
+}
+
+/// The latest samples of one kind of delay
+#[derive(Debug, Default)]
+pub(crate) struct Window {
+    samples: VecDeque<Duration>,
+
+    /// How many samples were ever recorded, including those which fell out of the window
+    recorded: u64,
+}
+
+impl Window {
+    pub(crate) fn record(&mut self, sample: Duration) {
+        if self.samples.len() == WINDOW {
+            let _ = self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+        self.recorded += 1;
+    }
+
+    pub(crate) fn percentiles(&self) -> Option<Percentiles> {
+        let mut sorted: Vec<_> = self.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        // The nearest rank, i.e. the smallest sample which at least that share of them is under
+        let percentile = |share: f64| {
+            let rank = (share * sorted.len() as f64).ceil() as usize;
+            sorted[rank.saturating_sub(1)].as_secs_f64() * 1000.0
+        };
+        if sorted.is_empty() {
+            return None;
+        }
+        Some(Percentiles {
+            p50_ms: percentile(0.50),
+            p95_ms: percentile(0.95),
+            p99_ms: percentile(0.99),
+        })
+    }
+}
+
+/// The 50th, 95th and 99th percentiles of a kind of delay, in milliseconds
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Percentiles {
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+}
+
+impl fmt::Display for Percentiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "p50 {:.2}ms p95 {:.2}ms p99 {:.2}ms",
+            self.p50_ms, self.p95_ms, self.p99_ms
+        )
+    }
+}
+
+/// Where a pad's time goes, shared between its websocket handler and the pads
+#[derive(Debug, Default)]
+pub(crate) struct Latency {
+    network: Mutex<Window>,
+    queue: Mutex<Window>,
+    update: Mutex<Window>,
+}
+
+/// The percentiles of each kind of delay a pad's states go through, each of which is missing
+/// until a sample of it was recorded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct LatencySummary {
+    /// From the client sending a state to us having parsed it, for clients stamping their states
+    pub network: Option<Percentiles>,
+
+    /// From us having parsed a state to the pads taking it
+    pub queue: Option<Percentiles>,
+
+    /// How long sending a state to the bus took
+    pub update: Option<Percentiles>,
+}
+
+impl Latency {
+    /// Record how long a state took to reach us
+    pub(crate) fn record_network(&self, delay: Duration) {
+        self.network.lock().unwrap().record(delay);
+    }
+
+    /// Record how long a state waited for the pads to take it
+    pub(crate) fn record_queue(&self, delay: Duration) {
+        self.queue.lock().unwrap().record(delay);
+    }
+
+    /// Record how long sending a state to the bus took
+    pub(crate) fn record_update(&self, duration: Duration) {
+        self.update.lock().unwrap().record(duration);
+    }
+
+    /// How many samples of any kind were ever recorded, to tell whether there are new ones
+    pub(crate) fn recorded(&self) -> u64 {
+        [&self.network, &self.queue, &self.update]
+            .iter()
+            .map(|window| window.lock().unwrap().recorded)
+            .sum()
+    }
+
+    pub(crate) fn summary(&self) -> LatencySummary {
+        LatencySummary {
+            network: self.network.lock().unwrap().percentiles(),
+            queue: self.queue.lock().unwrap().percentiles(),
+            update: self.update.lock().unwrap().percentiles(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A client whose clock reads `offset` milliseconds ahead of ours and runs `rate` times as
+    /// fast, answering a ping sent at `sent` which takes `there` to reach it and `back` to come
+    /// back. Returns the ping's round as passed to [ClockOffset::round].
+    fn round(offset: f64, rate: f64, sent: f64, there: f64, back: f64) -> (f64, f64, f64) {
+        let stamped = offset + (sent + there) * rate;
+        (sent, stamped, sent + there + back)
+    }
+
+    fn estimate(rounds: &[(f64, f64, f64)]) -> ClockOffset {
+        let mut clock = ClockOffset::default();
+        for &(sent, stamped, answered) in rounds {
+            clock.round(sent, stamped, answered);
+        }
+        clock
+    }
+
+    #[test]
+    fn test_symmetric() {
+        assert_eq!(ClockOffset::default().offset(), None);
+        assert_eq!(ClockOffset::default().delay(1.0, 2.0), None);
+
+        // The client's clock may be ahead or behind, by however much
+        for &offset in &[123_456.789, -98_765.4, 0.0] {
+            let clock = estimate(&[round(offset, 1.0, 1000.0, 20.0, 20.0)]);
+            assert!((clock.offset().unwrap() - offset).abs() < 1e-6);
+
+            // A state the client stamped at 5000 by our clock which came in 12ms later
+            let delay = clock.delay(offset + 5000.0, 5012.0).unwrap();
+            assert!((delay.as_secs_f64() - 0.012).abs() < 1e-9, "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn test_quickest_round() {
+        // Lopsided round trips throw the estimate off by up to half of them
+        let offset = -5000.0;
+        let rounds = [
+            round(offset, 1.0, 0.0, 90.0, 10.0),
+            round(offset, 1.0, 200.0, 3.0, 1.0),
+            round(offset, 1.0, 400.0, 10.0, 70.0),
+        ];
+        let clock = estimate(&rounds);
+        assert_eq!(clock.offset(), Some(offset + 1.0));
+
+        // The order the rounds come in doesn't matter
+        let mut reversed = rounds;
+        reversed.reverse();
+        assert_eq!(estimate(&reversed).offset(), clock.offset());
+    }
+
+    #[test]
+    fn test_drift() {
+        // A client clock running 100ppm fast is still estimated within a millisecond over the
+        // handshake's few seconds
+        let offset = 42_000.0;
+        let rate = 1.0001;
+        let rounds: Vec<_> = (0..CLOCK_ROUNDS)
+            .map(|i| {
+                let one_way = 10.0 - f64::from(i);
+                round(offset, rate, f64::from(i) * 500.0, one_way, one_way)
+            })
+            .collect();
+        let clock = estimate(&rounds);
+        let error = clock.offset().unwrap() - offset;
+        assert!(error.abs() < 1.0, "{}", error);
+    }
+
+    #[test]
+    fn test_nonsense_rounds() {
+        let mut clock = ClockOffset::default();
+        // Answered before it was sent, or stamped with garbage
+        clock.round(100.0, 50.0, 90.0);
+        clock.round(100.0, f64::NAN, 110.0);
+        clock.round(f64::NAN, 50.0, 110.0);
+        assert_eq!(clock.offset(), None);
+
+        // A delay which seems negative since the estimate was off is no delay at all
+        clock.round(0.0, 1000.0, 100.0);
+        assert_eq!(clock.delay(1100.0, 90.0), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let latency = Latency::default();
+        assert_eq!(latency.summary(), LatencySummary::default());
+        for ms in 1..=200 {
+            latency.record_queue(Duration::from_millis(ms));
+        }
+        latency.record_update(Duration::from_micros(1500));
+        assert_eq!(latency.recorded(), 201);
+
+        let summary = latency.summary();
+        assert_eq!(summary.network, None);
+        assert_eq!(
+            summary.queue,
+            Some(Percentiles {
+                p50_ms: 100.0,
+                p95_ms: 190.0,
+                p99_ms: 198.0,
+            })
+        );
+        assert_eq!(summary.update.map(|update| update.p99_ms), Some(1.5));
+
+        // Only the latest samples count
+        for _ in 0..WINDOW {
+            latency.record_queue(Duration::from_millis(1));
+        }
+        assert_eq!(latency.summary().queue.unwrap().p99_ms, 1.0);
+    }
+}
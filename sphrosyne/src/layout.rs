@@ -0,0 +1,67 @@
+//! The controller layouts which can be served from `/controller`
+
+/// A button arrangement for the controller page
+#[derive(Debug)]
+pub(crate) struct Layout {
+    /// The name used to pick this layout with the `layout` query parameter
+    pub(crate) name: &'static str,
+
+    /// A short description shown next to this layout's QR code
+    pub(crate) description: &'static str,
+
+    /// The script defining this layout's `buildLayout` function
+    pub(crate) script: &'static str,
+}
+
+/// Every layout we know about, the first of which is the default
+pub(crate) static LAYOUTS: &[Layout] = &[
+    Layout {
+        name: "standard",
+        description: "Two sticks, the dpad and the face buttons",
+        script: include_str!("layouts/standard.js"),
+    },
+    Layout {
+        name: "racing",
+        description: "A steering stick with brake and throttle pedals",
+        script: include_str!("layouts/racing.js"),
+    },
+    Layout {
+        name: "fight",
+        description: "A dpad with six attack buttons",
+        script: include_str!("layouts/fight.js"),
+    },
+];
+
+/// The layout drawing the [custom layouts](crate::custom_layout) clients arrange themselves, which
+/// isn't offered with the others as it needs one to draw
+pub(crate) static CUSTOM: Layout = Layout {
+    name: "custom",
+    description: "The controls a client arranged itself",
+    script: include_str!("layouts/custom.js"),
+};
+
+impl Layout {
+    /// The layout used when none is asked for
+    pub(crate) fn default() -> &'static Self {
+        &LAYOUTS[0]
+    }
+
+    /// Find a layout by its name
+    pub(crate) fn find(name: &str) -> Option<&'static Self> {
+        LAYOUTS.iter().find(|layout| layout.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        assert_eq!(Layout::default().name, "standard");
+        for layout in LAYOUTS {
+            assert!(std::ptr::eq(Layout::find(layout.name).unwrap(), layout));
+        }
+        assert!(Layout::find("nope").is_none());
+    }
+}
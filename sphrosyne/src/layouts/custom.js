@@ -0,0 +1,72 @@
+// @ts-check
+
+/**
+ * The masks of the buttons controls can be bound to, by the names the server writes them with
+ * @type {Record<string, number>}
+ */
+const BUTTON_MASKS = {
+  DPAD_UP: 0x0001,
+  DPAD_DOWN: 0x0002,
+  DPAD_LEFT: 0x0004,
+  DPAD_RIGHT: 0x0008,
+  START: 0x0010,
+  BACK: 0x0020,
+  LEFT_THUMB: 0x0040,
+  RIGHT_THUMB: 0x0080,
+  LEFT_SHOULDER: 0x0100,
+  RIGHT_SHOULDER: 0x0200,
+  GUIDE: 0x0400,
+  A: 0x1000,
+  B: 0x2000,
+  X: 0x4000,
+  Y: 0x8000,
+};
+
+/**
+ * The colors of the face buttons, which match the ones on a real pad
+ * @type {Record<string, string>}
+ */
+const BUTTON_COLORS = { A: "green", B: "red", X: "blue", Y: "gold", GUIDE: "white" };
+
+/**
+ * A layout arranged by a client, which the server defines as `CUSTOM_LAYOUT` before this script.
+ * Each control is the largest circle fitting in its box.
+ * @param {number} width
+ * @param {number} height
+ */
+function buildLayout(width, height) {
+  /** @type {{ controls: { kind: string; binding: string; x: number; y: number; width: number; height: number; }[] }} */
+  // @ts-ignore
+  const layout = CUSTOM_LAYOUT;
+
+  /** @type {{ leftJoystick?: any; rightJoystick?: any; buttons: any[]; triggers: any[]; }} */
+  const scene = { buttons: [], triggers: [] };
+  const buttons = [];
+  for (const control of layout.controls) {
+    const circle = {
+      x: (control.x + control.width / 2) * width,
+      y: (control.y + control.height / 2) * height,
+      r: Math.min(control.width * width, control.height * height) / 2,
+    };
+    if (control.kind === "stick") {
+      // @ts-ignore
+      const joystick = new Joystick(circle.x, circle.y, circle.r, 4);
+      if (control.binding === "left_stick") scene.leftJoystick = joystick;
+      else scene.rightJoystick = joystick;
+    } else if (control.kind === "trigger") {
+      const side = control.binding === "left_trigger" ? "left" : "right";
+      // @ts-ignore
+      scene.triggers.push(new Trigger(circle, "crimson", side));
+    } else {
+      buttons.push(
+        Object.assign(circle, {
+          color: BUTTON_COLORS[control.binding] || "orange",
+          mask: BUTTON_MASKS[control.binding],
+        })
+      );
+    }
+  }
+  // @ts-ignore
+  scene.buttons.push(Buttons.from(buttons));
+  return scene;
+}
@@ -0,0 +1,68 @@
+// @ts-check
+
+/**
+ * A big dpad on the left and six attack buttons on the right, arcade stick style
+ * @param {number} width
+ * @param {number} height
+ */
+function buildLayout(width, height) {
+  const dpadRadius = width / 14;
+  const buttonRadius = width / 18;
+  const punchesY = height / 2 - buttonRadius - 5;
+  const kicksY = height / 2 + buttonRadius + 5;
+  /** @param {number} i */
+  const column = (i) => width / 2 + (i * 2 + 1) * (buttonRadius + 5);
+  /**
+   * @param {number} i
+   * @param {number} y
+   * @param {string} color
+   * @param {number} mask
+   */
+  const button = (i, y, color, mask) => ({
+    x: column(i),
+    y,
+    r: buttonRadius,
+    color,
+    mask,
+  });
+
+  return {
+    buttons: [
+      // @ts-ignore
+      new Buttons(
+        { x: width / 4, y: height / 2, r: dpadRadius / 2 },
+        dpadRadius,
+        [
+          { color: "orange", mask: 0x0001 },
+          { color: "orange", mask: 0x0008 },
+          { color: "orange", mask: 0x0004 },
+          { color: "orange", mask: 0x0002 },
+        ]
+      ),
+      // The face buttons and bumpers, as two rows of three
+      // @ts-ignore
+      Buttons.from([
+        button(0, punchesY, "blue", 0x4000),
+        button(1, punchesY, "gold", 0x8000),
+        button(2, punchesY, "white", 0x0200),
+        button(0, kicksY, "green", 0x1000),
+        button(1, kicksY, "red", 0x2000),
+        button(2, kicksY, "white", 0x0100),
+      ]),
+    ],
+    triggers: [
+      // @ts-ignore
+      new Trigger(
+        { x: column(3), y: punchesY, r: buttonRadius },
+        "purple",
+        "right"
+      ),
+      // @ts-ignore
+      new Trigger(
+        { x: column(3), y: kicksY, r: buttonRadius },
+        "purple",
+        "left"
+      ),
+    ],
+  };
+}
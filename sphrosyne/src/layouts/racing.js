@@ -0,0 +1,56 @@
+// @ts-check
+
+/**
+ * A steering stick on the left, with brake and throttle pedals on the right
+ * @param {number} width
+ * @param {number} height
+ */
+function buildLayout(width, height) {
+  const buttonRadius = width / 24;
+  const pedalRadius = height / 5;
+
+  return {
+    // @ts-ignore
+    leftJoystick: new Joystick(
+      width / 4 + 10,
+      height / 2,
+      height / 3,
+      4
+    ),
+    buttons: [
+      // @ts-ignore
+      new Buttons(
+        { x: width / 2, y: height / 4, r: buttonRadius / 2 },
+        buttonRadius,
+        [
+          { color: "gold", mask: 0x8000 },
+          { color: "red", mask: 0x2000 },
+          { color: "blue", mask: 0x4000 },
+          { color: "green", mask: 0x1000 },
+        ]
+      ),
+    ],
+    triggers: [
+      // @ts-ignore
+      new Trigger(
+        {
+          x: width - 3 * pedalRadius - 20,
+          y: height - pedalRadius - 10,
+          r: pedalRadius,
+        },
+        "crimson",
+        "left"
+      ),
+      // @ts-ignore
+      new Trigger(
+        {
+          x: width - pedalRadius - 10,
+          y: height - pedalRadius - 10,
+          r: pedalRadius,
+        },
+        "limegreen",
+        "right"
+      ),
+    ],
+  };
+}
@@ -0,0 +1,64 @@
+// @ts-check
+
+/**
+ * Two sticks, the dpad and the face buttons
+ * @param {number} width
+ * @param {number} height
+ */
+function buildLayout(width, height) {
+  const buttonRadius = width / 24;
+  const rightPivot = {
+    x: width / 2 + width / 8,
+    y: height / 4 + 10,
+    r: buttonRadius / 2,
+  };
+  const leftPivot = {
+    x: width / 2 - width / 8,
+    y: height / 4 + 10,
+    r: buttonRadius / 2,
+  };
+
+  return {
+    // @ts-ignore
+    leftJoystick: new Joystick(
+      width / 4 + 10,
+      height - height / 4,
+      height / 4 - 10,
+      4
+    ),
+    // @ts-ignore
+    rightJoystick: new Joystick(
+      width - (width / 4 + 10),
+      height - height / 4,
+      height / 4 - 10,
+      4
+    ),
+    buttons: [
+      // The guide button, between the sticks, opens Steam or the Xbox Game Bar
+      // @ts-ignore
+      Buttons.from([
+        {
+          x: width / 2,
+          y: height / 2,
+          r: buttonRadius * 0.75,
+          color: "white",
+          mask: 0x0400,
+        },
+      ]),
+      // @ts-ignore
+      new Buttons(leftPivot, buttonRadius, [
+        { color: "orange", mask: 0x0001 },
+        { color: "orange", mask: 0x0008 },
+        { color: "orange", mask: 0x0004 },
+        { color: "orange", mask: 0x0002 },
+      ]),
+      // @ts-ignore
+      new Buttons(rightPivot, buttonRadius, [
+        { color: "gold", mask: 0x8000 },
+        { color: "green", mask: 0x1000 },
+        { color: "blue", mask: 0x4000 },
+        { color: "red", mask: 0x2000 },
+      ]),
+    ],
+  };
+}
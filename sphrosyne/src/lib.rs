@@ -0,0 +1,102 @@
+//! Turning phones into gamepads, by serving them a controller page whose inputs are plugged into
+//! ViGEmBus as virtual pads.
+//!
+//! [SphrosyneServer] runs the whole server inside a program of your own, just like the sphrosyne
+//! binary does. The rest is the parts which can be built upon without changing it, for forks and
+//! binaries of your own which do something else with pads' states on their way to the bus.
+
+#[cfg(not(any(feature = "ffi", feature = "mock")))]
+compile_error!(
+    "sphrosyne needs a bus to plug pads into, enable either the `ffi` or `mock` feature"
+);
+
+mod allow;
+
+mod args;
+
+mod assets;
+
+#[cfg(feature = "async-server")]
+mod async_server;
+
+mod auth;
+
+mod calibration;
+
+mod custom_layout;
+
+pub mod close;
+
+mod discovery;
+
+mod embed;
+
+#[cfg(feature = "gui")]
+mod gui;
+
+mod haptics;
+
+mod interfaces;
+
+mod keymap;
+
+mod latency;
+
+mod layout;
+
+mod macros;
+
+mod mapping;
+
+mod metrics;
+
+mod motion;
+
+mod outbox;
+
+mod pads;
+
+mod profiles;
+
+mod protocol;
+
+mod ramp;
+
+mod ratelimit;
+
+mod recorder;
+
+mod remap;
+
+mod request;
+
+mod server;
+
+mod snapshot;
+
+mod status;
+
+mod synthesis;
+
+mod test_input;
+
+mod ticker;
+
+mod tls;
+
+mod touch;
+
+pub mod transform;
+
+mod turbo;
+
+pub mod wire;
+
+pub use crate::{
+    args::Args,
+    embed::{replay, PadEvent, ServerHandle, SphrosyneServer},
+    latency::{LatencySummary, Percentiles},
+    request::PadType,
+    status::{PadStatus, Status, UserIndexStatus},
+    ticker::TickStatus,
+};
@@ -0,0 +1,243 @@
+//! Macros: short sequences of states a client records on one of its pads and plays back later
+//! with the same timing, e.g. a fighting game combo, merged with whatever it's holding meanwhile
+
+use std::{
+    convert::TryFrom,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use vigem_client_c::{StickPosition, X360State};
+
+/// The longest a macro can be, past which whatever the client does isn't recorded
+pub(crate) const MAX_MACRO_LENGTH: Duration = Duration::from_secs(10);
+
+/// The most states a macro can hold, which is plenty for clients sending 60 a second for as
+/// long as a macro can be
+pub(crate) const MAX_MACRO_STEPS: usize = 1024;
+
+/// How many macros a device can keep at once, in slots numbered from 0
+pub(crate) const MACRO_SLOTS: u8 = 8;
+
+/// A state of a macro, and when it comes after the macro started
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct MacroStep {
+    pub(crate) at_ms: u32,
+    pub(crate) state: X360State,
+}
+
+/// A recorded sequence of states, kept in the device's profile under its slot
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Macro {
+    pub(crate) slot: u8,
+
+    /// How long the macro lasts, holding on to its last state until then
+    pub(crate) length_ms: u32,
+
+    /// The macro's states in order of when they come
+    pub(crate) steps: Vec<MacroStep>,
+}
+
+impl Macro {
+    fn length(&self) -> Duration {
+        Duration::from_millis(self.length_ms.into())
+    }
+}
+
+/// The states a pad's client is recording, starting from what it was holding when it started
+#[derive(Debug, Clone)]
+pub(crate) struct Recording {
+    started: Instant,
+    steps: Vec<MacroStep>,
+}
+
+impl Recording {
+    pub(crate) fn new(held: X360State, now: Instant) -> Self {
+        Self {
+            started: now,
+            steps: vec![MacroStep {
+                at_ms: 0,
+                state: held,
+            }],
+        }
+    }
+
+    /// Record a state the client sent at `now`, unless the macro is as long as it can be
+    pub(crate) fn record(&mut self, state: X360State, now: Instant) {
+        let at = now.saturating_duration_since(self.started);
+        if at >= MAX_MACRO_LENGTH || self.steps.len() >= MAX_MACRO_STEPS {
+            return;
+        }
+        self.steps.push(MacroStep {
+            at_ms: millis(at),
+            state,
+        });
+    }
+
+    /// The macro recorded by `now`, to be kept in the given slot
+    pub(crate) fn finish(self, slot: u8, now: Instant) -> Macro {
+        let length = now
+            .saturating_duration_since(self.started)
+            .min(MAX_MACRO_LENGTH);
+        Macro {
+            slot,
+            length_ms: millis(length),
+            steps: self.steps,
+        }
+    }
+}
+
+/// A macro a pad is playing, timed from when it started
+#[derive(Debug, Clone)]
+pub(crate) struct Playback {
+    started: Instant,
+    played: Macro,
+}
+
+impl Playback {
+    pub(crate) fn new(played: Macro, now: Instant) -> Self {
+        Self {
+            started: now,
+            played,
+        }
+    }
+
+    /// The state the macro is at by `now`, or `None` once it's over
+    pub(crate) fn state(&self, now: Instant) -> Option<X360State> {
+        let at = now.saturating_duration_since(self.started);
+        if at >= self.played.length() {
+            return None;
+        }
+        let at_ms = millis(at);
+        let step = self
+            .played
+            .steps
+            .iter()
+            .take_while(|step| step.at_ms <= at_ms)
+            .last();
+        Some(step.map(|step| step.state).unwrap_or_default())
+    }
+
+    /// When the macro moves on to its next state, or ends
+    pub(crate) fn next_step(&self, now: Instant) -> Instant {
+        let at_ms = millis(now.saturating_duration_since(self.started));
+        let next = self
+            .played
+            .steps
+            .iter()
+            .map(|step| step.at_ms)
+            .find(|&step| step > at_ms)
+            .unwrap_or(self.played.length_ms);
+        self.started + Duration::from_millis(next.into())
+    }
+}
+
+/// Merge a macro's state into what the client holds: buttons held by either are held, each
+/// trigger is pulled as far as the further of the two pulls it, and each stick is where the
+/// macro moves it, or else where the client does
+pub(crate) fn merge(live: X360State, played: X360State) -> X360State {
+    let stick = |live: StickPosition, played: StickPosition| {
+        if played == StickPosition::default() {
+            live
+        } else {
+            played
+        }
+    };
+    X360State {
+        buttons: live.buttons | played.buttons,
+        left_trigger: live.left_trigger.max(played.left_trigger),
+        right_trigger: live.right_trigger.max(played.right_trigger),
+        left_thumbstick: stick(live.left_thumbstick, played.left_thumbstick),
+        right_thumbstick: stick(live.right_thumbstick, played.right_thumbstick),
+    }
+}
+
+fn millis(duration: Duration) -> u32 {
+    u32::try_from(duration.as_millis()).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use vigem_client_c::X360Buttons;
+
+    use super::*;
+
+    fn pressed(buttons: X360Buttons) -> X360State {
+        X360State::builder().press(buttons).build()
+    }
+
+    #[test]
+    fn test_merge() {
+        let live = X360State::builder()
+            .press(X360Buttons::A)
+            .left_stick(100, -100)
+            .right_stick(5, 5)
+            .left_trigger(200)
+            .build();
+        let played = X360State::builder()
+            .press(X360Buttons::B)
+            .right_stick(-300, 300)
+            .left_trigger(50)
+            .right_trigger(80)
+            .build();
+        let merged = merge(live, played);
+        assert_eq!(merged.buttons, X360Buttons::A | X360Buttons::B);
+        assert_eq!((merged.left_trigger, merged.right_trigger), (200, 80));
+        // The macro leaves the left stick centered, so the client's goes through
+        assert_eq!(merged.left_thumbstick, (100, -100));
+        assert_eq!(merged.right_thumbstick, (-300, 300));
+
+        assert_eq!(merge(live, X360State::default()), live);
+        assert_eq!(merge(X360State::default(), played), played);
+    }
+
+    #[test]
+    fn test_record_and_play() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut recording = Recording::new(pressed(X360Buttons::X), start);
+        recording.record(pressed(X360Buttons::A), at(100));
+        recording.record(X360State::default(), at(250));
+        let recorded = recording.finish(3, at(400));
+        assert_eq!(recorded.slot, 3);
+        assert_eq!(recorded.length_ms, 400);
+        assert_eq!(recorded.steps.len(), 3);
+
+        // Played back later with the same timing, holding the last state until the end
+        let start = at(1000);
+        let at = |ms| start + Duration::from_millis(ms);
+        let playback = Playback::new(recorded, start);
+        assert_eq!(playback.state(at(0)), Some(pressed(X360Buttons::X)));
+        assert_eq!(playback.state(at(99)), Some(pressed(X360Buttons::X)));
+        assert_eq!(playback.state(at(100)), Some(pressed(X360Buttons::A)));
+        assert_eq!(playback.state(at(399)), Some(X360State::default()));
+        assert_eq!(playback.state(at(400)), None);
+
+        assert_eq!(playback.next_step(at(0)), at(100));
+        assert_eq!(playback.next_step(at(100)), at(250));
+        assert_eq!(playback.next_step(at(300)), at(400));
+    }
+
+    #[test]
+    fn test_max_length() {
+        let start = Instant::now();
+        let mut recording = Recording::new(X360State::default(), start);
+        let last = MAX_MACRO_LENGTH - Duration::from_millis(1);
+        recording.record(pressed(X360Buttons::A), start + last);
+        // Whatever comes after the longest a macro can be isn't recorded
+        recording.record(pressed(X360Buttons::B), start + MAX_MACRO_LENGTH);
+        recording.record(pressed(X360Buttons::Y), start + 2 * MAX_MACRO_LENGTH);
+        let recorded = recording.finish(0, start + 3 * MAX_MACRO_LENGTH);
+        assert_eq!(recorded.length_ms, millis(MAX_MACRO_LENGTH));
+        assert_eq!(recorded.steps.len(), 2);
+        assert_eq!(recorded.steps[1].state, pressed(X360Buttons::A));
+
+        // Nor is anything past the most states a macro can hold
+        let mut recording = Recording::new(X360State::default(), start);
+        for ms in 1..2 * MAX_MACRO_STEPS as u64 {
+            recording.record(pressed(X360Buttons::A), start + Duration::from_millis(ms));
+        }
+        assert_eq!(recording.steps.len(), MAX_MACRO_STEPS);
+    }
+}
@@ -1,14 +1,13 @@
-use std::{
-    sync::mpsc::{channel, Receiver, Sender},
-    thread::spawn,
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
 };
 
+#[cfg(not(feature = "gui"))]
+use eyre::format_err;
 use eyre::Result;
-use slab::Slab;
-use slog::{info, trace, Logger};
-use vigem_client_c::client::{Client, Target};
-
-use crate::request::PadRequest;
+use slog::{info, Logger};
+use sphrosyne::{Args, SphrosyneServer};
 
 fn setup_logging() -> Logger {
     use slog::Drain;
@@ -18,43 +17,46 @@ fn setup_logging() -> Logger {
     Logger::root(drain, slog::o!())
 }
 
-mod request;
-
-mod server;
-
-fn handle_pads(logger: Logger, req_rx: Receiver<PadRequest>, id_tx: Sender<usize>) -> Result<()> {
-    let client = Client::new()?;
-
-    let mut pads = Slab::<Target<_>>::new();
-
-    loop {
-        match req_rx.recv()? {
-            PadRequest::NewID => {
-                let id = pads.insert(client.connect_x360_pad()?);
-                info!(logger, "pad.id.request"; "id" => id);
-                id_tx.send(id)?;
-            }
+fn main() -> Result<()> {
+    let args = Args::from_env()?;
+    let logger = setup_logging();
+    #[cfg(not(feature = "gui"))]
+    {
+        if args.gui() {
+            return Err(format_err!(
+                "sphrosyne was built without the window, rebuild it with --features gui"
+            ));
+        }
+    }
 
-            PadRequest::Discard(id) => {
-                info!(logger, "pad.id.discard"; "id" => id);
-                pads.remove(id);
-            }
+    if let Some(path) = args.replay() {
+        return sphrosyne::replay(&logger, path);
+    }
 
-            PadRequest::Update(id, state) => {
-                trace!(logger, "pad.update"; "id" => id, "state" => ?state);
-                pads[id].update(state)?;
+    // The first Ctrl-C shuts down gracefully, while the second one gives up on that
+    let shutdown = Arc::new(AtomicBool::new(false));
+    {
+        let (logger, shutdown) = (logger.clone(), Arc::clone(&shutdown));
+        ctrlc::set_handler(move || {
+            if shutdown.swap(true, Ordering::SeqCst) {
+                std::process::exit(1);
             }
-        }
+            info!(logger, "shutdown.requested");
+        })?;
     }
-}
 
-fn main() -> Result<()> {
-    let logger = setup_logging();
-    let (msg_tx, msg_rx) = channel();
-    let (id_tx, id_rx) = channel();
+    let gui = args.gui();
+    let server = SphrosyneServer::from_args(args)
+        .logger(logger)
+        .shutdown_on(shutdown)
+        .start()?;
+    #[cfg(feature = "gui")]
     {
-        let logger = logger.clone();
-        spawn(move || server::mainloop(logger, msg_tx, id_rx));
+        if gui {
+            return server.show_window();
+        }
     }
-    handle_pads(logger, msg_rx, id_tx)
+    #[cfg(not(feature = "gui"))]
+    let _ = gui;
+    server.wait()
 }
@@ -0,0 +1,125 @@
+//! Conversions from other controllers' states to ours
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use vigem_client_c::{X360Buttons, X360State};
+
+/// How far an analog button has to be pushed to count as pressed
+const BUTTON_THRESHOLD: f32 = 0.5;
+
+/// How far a trigger has to be pulled before we report it at all, to ignore worn out springs
+const TRIGGER_THRESHOLD: f32 = 0.05;
+
+/// The digital buttons of the Gamepad API's standard mapping, by index
+const STANDARD_BUTTONS: &[(usize, X360Buttons)] = &[
+    (0, X360Buttons::A),
+    (1, X360Buttons::B),
+    (2, X360Buttons::X),
+    (3, X360Buttons::Y),
+    (4, X360Buttons::LEFT_SHOULDER),
+    (5, X360Buttons::RIGHT_SHOULDER),
+    (8, X360Buttons::BACK),
+    (9, X360Buttons::START),
+    (10, X360Buttons::LEFT_THUMB),
+    (11, X360Buttons::RIGHT_THUMB),
+    (12, X360Buttons::DPAD_UP),
+    (13, X360Buttons::DPAD_DOWN),
+    (14, X360Buttons::DPAD_LEFT),
+    (15, X360Buttons::DPAD_RIGHT),
+    (16, X360Buttons::GUIDE),
+];
+
+/// The state of a physical gamepad, as reported by a browser's Gamepad API with the standard mapping
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub(crate) struct GamepadApiState {
+    pub(crate) buttons: Vec<f32>,
+    pub(crate) axes: Vec<f32>,
+}
+
+impl From<GamepadApiState> for X360State {
+    fn from(state: GamepadApiState) -> Self {
+        gamepad_api_to_x360(&state.buttons, &state.axes)
+    }
+}
+
+/// Convert the button values and axes of a standard-mapped gamepad to a state.
+///
+/// Buttons count as pressed once they're at least halfway down, while the triggers (buttons 6
+/// and 7) keep their analog value. The Y axes point down in the Gamepad API, so they are
+/// inverted. Anything missing from the slices is treated as released or centered.
+pub(crate) fn gamepad_api_to_x360(buttons: &[f32], axes: &[f32]) -> X360State {
+    let button = |i: usize| buttons.get(i).copied().unwrap_or(0.0);
+    let axis = |i: usize| axes.get(i).copied().unwrap_or(0.0);
+    let trigger = |i: usize| {
+        let value = button(i);
+        if value < TRIGGER_THRESHOLD {
+            0.0
+        } else {
+            value
+        }
+    };
+
+    let mut state = X360State::default();
+    for &(i, flag) in STANDARD_BUTTONS {
+        if button(i) >= BUTTON_THRESHOLD {
+            state.press(flag);
+        }
+    }
+    state.set_triggers_f32(trigger(6), trigger(7));
+    state.set_left_stick_f32(axis(0), -axis(1));
+    state.set_right_stick_f32(axis(2), -axis(3));
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buttons() {
+        let mut buttons = [0.0; 17];
+        buttons[0] = 1.0;
+        buttons[3] = 0.5;
+        buttons[5] = 0.49;
+        buttons[12] = 1.0;
+        buttons[15] = 1.0;
+        buttons[16] = 1.0;
+
+        let state = gamepad_api_to_x360(&buttons, &[]);
+        assert_eq!(
+            state.buttons,
+            X360Buttons::A
+                | X360Buttons::Y
+                | X360Buttons::DPAD_UP
+                | X360Buttons::DPAD_RIGHT
+                | X360Buttons::GUIDE
+        );
+    }
+
+    #[test]
+    fn test_triggers() {
+        let mut buttons = [0.0; 17];
+        buttons[6] = 0.04;
+        buttons[7] = 1.0;
+        let state = gamepad_api_to_x360(&buttons, &[]);
+        assert_eq!((state.left_trigger, state.right_trigger), (0, 255));
+        // Triggers don't double as buttons
+        assert_eq!(state.buttons, X360Buttons::empty());
+
+        buttons[6] = 0.05;
+        buttons[7] = 0.5;
+        let state = gamepad_api_to_x360(&buttons, &[]);
+        assert_eq!((state.left_trigger, state.right_trigger), (13, 128));
+    }
+
+    #[test]
+    fn test_axes() {
+        let state = gamepad_api_to_x360(&[], &[1.0, 1.0, -1.0, -1.0]);
+        assert_eq!(state.left_thumbstick, (i16::MAX, i16::MIN));
+        assert_eq!(state.right_thumbstick, (i16::MIN, i16::MAX));
+
+        let state = gamepad_api_to_x360(&[], &[0.5]);
+        assert_eq!(state.left_thumbstick, (16384, 0));
+        assert_eq!(state.right_thumbstick, (0, 0));
+    }
+}
@@ -0,0 +1,298 @@
+//! Counters for keeping an eye on how we're doing, served at `/metrics` in Prometheus' text
+//! format when built with the `metrics` feature
+
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use vigem_client_c::Error;
+
+/// The upper bounds of the update latency histogram's buckets, in seconds
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0,
+];
+
+/// What the server and the pads count as they go, shared between them. Counting is only a
+/// handful of atomics, so it goes on even when there's no `/metrics` to read the counts from.
+#[derive(Debug)]
+pub(crate) struct Metrics {
+    /// When we started, which only the uptime is counted from
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    started: Instant,
+
+    /// How many updates were sent to the bus, by pad id
+    updates: Mutex<BTreeMap<usize, u64>>,
+
+    /// How many errors the bus gave us, by the name of their variant
+    errors: Mutex<BTreeMap<&'static str, u64>>,
+
+    /// How many websocket connections were accepted
+    ws_connections: AtomicU64,
+
+    /// How many pads have a client, which excludes both free and detached pads
+    active_pads: AtomicUsize,
+
+    /// How long it took from receiving a state to the bus being updated with it
+    latency: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            updates: Mutex::default(),
+            errors: Mutex::default(),
+            ws_connections: AtomicU64::default(),
+            active_pads: AtomicUsize::default(),
+            latency: Histogram::default(),
+        }
+    }
+}
+
+/// A histogram over [LATENCY_BUCKETS]
+#[derive(Debug, Default)]
+struct Histogram {
+    /// How many observations fell in each bucket and no lower one, with the last counting
+    /// those past every bucket
+    counts: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+
+    /// The sum of every observation, in nanoseconds
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| secs <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        let _ = self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        let _ = self.sum_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+}
+
+/// The name of an error's variant, without whatever it holds
+fn variant_name(error: &Error) -> &'static str {
+    match error {
+        Error::NoVigemAlloc => "NoVigemAlloc",
+        Error::NoX360PadAlloc => "NoX360PadAlloc",
+        Error::NoDS4PadAlloc => "NoDS4PadAlloc",
+        Error::BusNotFound => "BusNotFound",
+        Error::NoFreeSlot => "NoFreeSlot",
+        Error::InvalidTarget => "InvalidTarget",
+        Error::RemovalFailed => "RemovalFailed",
+        Error::AlreadyConnected => "AlreadyConnected",
+        Error::TargetUninitialized => "TargetUninitialized",
+        Error::TargetNotPluggedIn => "TargetNotPluggedIn",
+        Error::BusVersionMismatch => "BusVersionMismatch",
+        Error::BusAccessFailed => "BusAccessFailed",
+        Error::CallbackAlreadyRegistered => "CallbackAlreadyRegistered",
+        Error::AlreadyHasCallback => "AlreadyHasCallback",
+        Error::CallbackNotFound => "CallbackNotFound",
+        Error::BusAlreadyConnected => "BusAlreadyConnected",
+        Error::BusInvalidHandle => "BusInvalidHandle",
+        Error::UserIndexOutOfRange => "UserIndexOutOfRange",
+        Error::InvalidParameter => "InvalidParameter",
+        Error::NotSupported => "NotSupported",
+        Error::UnknownError(_) => "UnknownError",
+    }
+}
+
+impl Metrics {
+    /// Count an update sent to the bus for the pad with the given id, `latency` after the
+    /// state it was sent for came in
+    pub(crate) fn updated(&self, id: usize, latency: Duration) {
+        *self.updates.lock().unwrap().entry(id).or_default() += 1;
+        self.latency.observe(latency);
+    }
+
+    /// Count an error the bus gave us
+    pub(crate) fn error(&self, error: &Error) {
+        *self
+            .errors
+            .lock()
+            .unwrap()
+            .entry(variant_name(error))
+            .or_default() += 1;
+    }
+
+    /// Count a newly accepted websocket connection
+    pub(crate) fn connected(&self) {
+        let _ = self.ws_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Note how many pads have a client right now
+    pub(crate) fn set_active_pads(&self, count: usize) {
+        self.active_pads.store(count, Ordering::Relaxed);
+    }
+
+    /// Write out every metric in Prometheus' text exposition format
+    #[cfg(feature = "metrics")]
+    pub(crate) fn render(&self) -> String {
+        use std::fmt::Write;
+
+        let mut out = String::new();
+        let header = |out: &mut String, name: &str, kind: &str, help: &str| {
+            let _ = writeln!(out, "# HELP sphrosyne_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE sphrosyne_{} {}", name, kind);
+        };
+
+        header(
+            &mut out,
+            "updates_total",
+            "counter",
+            "Updates sent to the bus, by pad",
+        );
+        for (id, count) in &*self.updates.lock().unwrap() {
+            let _ = writeln!(out, "sphrosyne_updates_total{{pad=\"{}\"}} {}", id, count);
+        }
+
+        header(
+            &mut out,
+            "errors_total",
+            "counter",
+            "Errors the bus gave us, by kind",
+        );
+        for (kind, count) in &*self.errors.lock().unwrap() {
+            let _ = writeln!(out, "sphrosyne_errors_total{{kind=\"{}\"}} {}", kind, count);
+        }
+
+        header(
+            &mut out,
+            "ws_connections_total",
+            "counter",
+            "Websocket connections accepted",
+        );
+        let _ = writeln!(
+            out,
+            "sphrosyne_ws_connections_total {}",
+            self.ws_connections.load(Ordering::Relaxed)
+        );
+
+        header(&mut out, "active_pads", "gauge", "Pads which have a client");
+        let _ = writeln!(
+            out,
+            "sphrosyne_active_pads {}",
+            self.active_pads.load(Ordering::Relaxed)
+        );
+
+        header(
+            &mut out,
+            "uptime_seconds",
+            "gauge",
+            "How long we've been running",
+        );
+        let _ = writeln!(
+            out,
+            "sphrosyne_uptime_seconds {}",
+            self.started.elapsed().as_secs_f64()
+        );
+
+        header(
+            &mut out,
+            "update_latency_seconds",
+            "histogram",
+            "Time from receiving a state to the bus being updated with it",
+        );
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(&self.latency.counts) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "sphrosyne_update_latency_seconds_bucket{{le=\"{}\"}} {}",
+                bound, cumulative
+            );
+        }
+        cumulative += self.latency.counts[LATENCY_BUCKETS.len()].load(Ordering::Relaxed);
+        let _ = writeln!(
+            out,
+            "sphrosyne_update_latency_seconds_bucket{{le=\"+Inf\"}} {}",
+            cumulative
+        );
+        let _ = writeln!(
+            out,
+            "sphrosyne_update_latency_seconds_sum {}",
+            Duration::from_nanos(self.latency.sum_nanos.load(Ordering::Relaxed)).as_secs_f64()
+        );
+        let _ = writeln!(out, "sphrosyne_update_latency_seconds_count {}", cumulative);
+
+        out
+    }
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::*;
+
+    /// The lines of the rendered metrics which aren't comments
+    fn samples(metrics: &Metrics) -> Vec<String> {
+        metrics
+            .render()
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.connected();
+        metrics.connected();
+        metrics.set_active_pads(1);
+        metrics.updated(0, Duration::from_micros(300));
+        metrics.updated(0, Duration::from_millis(3));
+        metrics.updated(2, Duration::from_secs(2));
+        metrics.error(&Error::BusNotFound);
+        metrics.error(&Error::UnknownError(0xdead));
+        metrics.error(&Error::BusNotFound);
+
+        let samples = samples(&metrics);
+        let expected = [
+            r#"sphrosyne_updates_total{pad="0"} 2"#,
+            r#"sphrosyne_updates_total{pad="2"} 1"#,
+            r#"sphrosyne_errors_total{kind="BusNotFound"} 2"#,
+            r#"sphrosyne_errors_total{kind="UnknownError"} 1"#,
+            "sphrosyne_ws_connections_total 2",
+            "sphrosyne_active_pads 1",
+            r#"sphrosyne_update_latency_seconds_bucket{le="0.0005"} 1"#,
+            r#"sphrosyne_update_latency_seconds_bucket{le="0.0025"} 1"#,
+            r#"sphrosyne_update_latency_seconds_bucket{le="0.005"} 2"#,
+            r#"sphrosyne_update_latency_seconds_bucket{le="1"} 2"#,
+            r#"sphrosyne_update_latency_seconds_bucket{le="+Inf"} 3"#,
+            "sphrosyne_update_latency_seconds_sum 2.0033",
+            "sphrosyne_update_latency_seconds_count 3",
+        ];
+        for line in &expected {
+            assert!(samples.iter().any(|sample| sample == line), "{}", line);
+        }
+        assert!(samples
+            .iter()
+            .any(|sample| sample.starts_with("sphrosyne_uptime_seconds ")));
+    }
+
+    #[test]
+    fn test_empty() {
+        // Every metric is described even before anything was counted
+        let rendered = Metrics::default().render();
+        for name in &[
+            "updates_total",
+            "errors_total",
+            "ws_connections_total",
+            "active_pads",
+            "uptime_seconds",
+            "update_latency_seconds",
+        ] {
+            assert!(rendered.contains(&format!("# TYPE sphrosyne_{} ", name)));
+        }
+        assert!(rendered.contains("sphrosyne_update_latency_seconds_count 0"));
+    }
+}
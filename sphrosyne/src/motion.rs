@@ -0,0 +1,251 @@
+//! Aiming with the phone's motion, which moves the right stick by how far the phone turned
+//! from where it was centered
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use vigem_client_c::{axis_from_f32, StickPosition, X360Buttons, X360State};
+
+/// How many degrees the phone has to turn to push the stick all the way at a sensitivity of 1
+const FULL_DEFLECTION: f32 = 30.0;
+
+/// The highest sensitivity, past which the slightest tremble pushes the stick all the way
+const MAX_SENSITIVITY: f32 = 10.0;
+
+/// Which way the phone is facing, as given by a `DeviceOrientationEvent`, in degrees.
+/// Its `gamma`, the tilt from side to side, isn't used.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub(crate) struct Orientation {
+    /// The rotation around the axis going through the screen, from 0 to 360, which moves the
+    /// stick left and right
+    pub(crate) alpha: f32,
+
+    /// The tilt from front to back, from -180 to 180, which moves the stick up and down
+    pub(crate) beta: f32,
+}
+
+impl Orientation {
+    /// How far this orientation is turned from the given center, in degrees on both axes,
+    /// taking the short way around so that going past ±180 degrees doesn't flip the stick
+    fn turned_from(self, center: Self) -> (f32, f32) {
+        let turned = |angle: f32, center: f32| (angle - center + 180.0).rem_euclid(360.0) - 180.0;
+        (
+            turned(self.alpha, center.alpha),
+            turned(self.beta, center.beta),
+        )
+    }
+}
+
+/// How a pad is aimed with motion
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub(crate) struct MotionConfig {
+    /// Whether motion moves the right stick, replacing whatever the client's touch state says
+    pub(crate) enabled: bool,
+
+    /// How much the stick moves for the same turn, 1 pushing it all the way at 30 degrees
+    pub(crate) sensitivity: f32,
+
+    /// The buttons which make the phone's current orientation the center when pressed
+    pub(crate) recenter: X360Buttons,
+}
+
+impl Default for MotionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            sensitivity: 1.0,
+            recenter: X360Buttons::RIGHT_THUMB,
+        }
+    }
+}
+
+impl MotionConfig {
+    /// Bring the sensitivity between 0 and [MAX_SENSITIVITY], treating nonsense as 0
+    pub(crate) fn clamped(self) -> Self {
+        Self {
+            sensitivity: if self.sensitivity.is_nan() {
+                0.0
+            } else {
+                self.sensitivity.clamp(0.0, MAX_SENSITIVITY)
+            },
+            ..self
+        }
+    }
+}
+
+/// Moves a pad's right stick with its phone's motion
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Motion {
+    config: MotionConfig,
+
+    /// The orientation the stick is centered at, which is the first one received until the
+    /// client recenters
+    center: Option<Orientation>,
+
+    /// The latest orientation received, if any was
+    latest: Option<Orientation>,
+
+    /// Whether a recenter button was pressed in the last state, so that holding it down only
+    /// recenters once
+    recentering: bool,
+}
+
+impl Motion {
+    /// Change how motion is mapped, keeping the center
+    pub(crate) fn configure(&mut self, config: MotionConfig) {
+        self.config = config;
+    }
+
+    /// Take note of which way the phone is facing now
+    pub(crate) fn orient(&mut self, orientation: Orientation) {
+        self.center.get_or_insert(orientation);
+        self.latest = Some(orientation);
+    }
+
+    /// Merge the motion into a state the client sent, replacing its right stick if motion is
+    /// enabled and the client sent an orientation. Recenters first if a recenter button was
+    /// just pressed.
+    pub(crate) fn apply(&mut self, mut state: X360State) -> X360State {
+        let recentering = state.buttons.intersects(self.config.recenter);
+        if recentering && !self.recentering {
+            self.center = self.latest;
+        }
+        self.recentering = recentering;
+
+        if let (true, Some(latest), Some(center)) = (self.config.enabled, self.latest, self.center)
+        {
+            let (yaw, pitch) = latest.turned_from(center);
+            let scale = self.config.sensitivity / FULL_DEFLECTION;
+            // Turning left makes alpha go up, while the stick goes left as it goes down
+            state.right_thumbstick = StickPosition::new(
+                axis_from_f32((-yaw * scale).clamp(-1.0, 1.0)),
+                axis_from_f32((pitch * scale).clamp(-1.0, 1.0)),
+            );
+        }
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn orientation(alpha: f32, beta: f32) -> Orientation {
+        Orientation { alpha, beta }
+    }
+
+    /// The right stick of a neutral state after motion is applied
+    fn stick(motion: &mut Motion) -> (i16, i16) {
+        motion.apply(X360State::default()).right_thumbstick.into()
+    }
+
+    #[test]
+    fn test_wrap_around() {
+        assert_eq!(
+            orientation(1.0, 0.0).turned_from(orientation(359.0, 0.0)),
+            (2.0, 0.0)
+        );
+        assert_eq!(
+            orientation(359.0, 0.0).turned_from(orientation(1.0, 0.0)),
+            (-2.0, 0.0)
+        );
+        assert_eq!(
+            orientation(0.0, -179.0).turned_from(orientation(0.0, 179.0)),
+            (0.0, 2.0)
+        );
+        assert_eq!(
+            orientation(0.0, 179.0).turned_from(orientation(0.0, -179.0)),
+            (0.0, -2.0)
+        );
+        assert_eq!(
+            orientation(90.0, 10.0).turned_from(orientation(90.0, 10.0)),
+            (0.0, 0.0)
+        );
+
+        // Turning a little past ±180 degrees moves the stick a little rather than flipping it
+        let mut motion = Motion::default();
+        motion.orient(orientation(0.0, 179.0));
+        motion.orient(orientation(0.0, -178.0));
+        assert_eq!(
+            stick(&mut motion),
+            (0, axis_from_f32(3.0 / FULL_DEFLECTION))
+        );
+    }
+
+    #[test]
+    fn test_stick() {
+        let mut motion = Motion::default();
+        assert_eq!(stick(&mut motion), (0, 0));
+
+        // The first orientation is the center
+        motion.orient(orientation(100.0, 20.0));
+        assert_eq!(stick(&mut motion), (0, 0));
+
+        motion.orient(orientation(85.0, 35.0));
+        assert_eq!(stick(&mut motion), (axis_from_f32(0.5), axis_from_f32(0.5)));
+
+        // Turning further than full deflection is clamped
+        motion.orient(orientation(190.0, -70.0));
+        assert_eq!(stick(&mut motion), (i16::MIN, i16::MIN));
+
+        motion.configure(MotionConfig {
+            sensitivity: 2.0,
+            ..MotionConfig::default()
+        });
+        motion.orient(orientation(92.5, 20.0));
+        assert_eq!(stick(&mut motion), (axis_from_f32(0.5), 0));
+    }
+
+    #[test]
+    fn test_recenter() {
+        let mut motion = Motion::default();
+        motion.orient(orientation(0.0, 0.0));
+        motion.orient(orientation(15.0, 0.0));
+        assert_eq!(stick(&mut motion), (axis_from_f32(-0.5), 0));
+
+        let pressed = X360State::builder().press(X360Buttons::RIGHT_THUMB).build();
+        let recentered = motion.apply(pressed);
+        assert_eq!(recentered.right_thumbstick, (0, 0));
+        assert_eq!(recentered.buttons, X360Buttons::RIGHT_THUMB);
+
+        // Holding the button down doesn't keep recentering
+        motion.orient(orientation(30.0, 0.0));
+        assert_eq!(
+            motion.apply(pressed).right_thumbstick,
+            (axis_from_f32(-0.5), 0)
+        );
+        assert_eq!(stick(&mut motion), (axis_from_f32(-0.5), 0));
+
+        // But pressing it again does
+        assert_eq!(motion.apply(pressed).right_thumbstick, (0, 0));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut motion = Motion::default();
+        motion.configure(MotionConfig {
+            enabled: false,
+            ..MotionConfig::default()
+        });
+        motion.orient(orientation(0.0, 0.0));
+        motion.orient(orientation(40.0, 40.0));
+        let state = X360State::builder().right_stick(123, -456).build();
+        assert_eq!(motion.apply(state), state);
+    }
+
+    #[test]
+    fn test_clamped() {
+        let clamped = |sensitivity| {
+            MotionConfig {
+                sensitivity,
+                ..MotionConfig::default()
+            }
+            .clamped()
+            .sensitivity
+        };
+        assert_eq!(clamped(f32::NAN), 0.0);
+        assert_eq!(clamped(-1.0), 0.0);
+        assert_eq!(clamped(100.0), MAX_SENSITIVITY);
+        assert_eq!(clamped(2.5), 2.5);
+    }
+}
@@ -0,0 +1,360 @@
+//! Messages queued for clients, which their websocket's handler writes out whenever it gets to.
+//!
+//! The upgraded stream can't be split into halves, nor given a read timeout, so a handler
+//! blocked reading is the only one who can write to its client. Anybody else queues their
+//! messages here instead, addressed by one of the client's pad ids, and the handler writes them
+//! out after the next message the client sends, which is every frame while it's in use. The
+//! async server can write whenever it likes, and drains its outboxes every so often instead.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    io::{self, Read, Write},
+    sync::{
+        mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+};
+
+use eyre::Result;
+use tungstenite::{protocol::CloseFrame, Message, WebSocket};
+
+/// How many messages can be queued for a client before more are thrown away
+const OUTBOX_SIZE: usize = 64;
+
+/// Where to queue messages for the clients controlling each pad
+#[derive(Debug, Default)]
+pub(crate) struct Outboxes(Mutex<BTreeMap<usize, Route>>);
+
+/// Where the messages for a pad go
+#[derive(Debug, Clone)]
+struct Route {
+    /// The id of the first pad of the connection this pad belongs to, which tells apart
+    /// connections controlling several pads
+    connection: usize,
+
+    /// The pad's index among its connection's pads, which messages about it are addressed by
+    index: usize,
+
+    tx: SyncSender<Message>,
+}
+
+impl Outboxes {
+    /// Queue a message for the client controlling the pad with the given id. Returns whether it
+    /// was queued, which it isn't if nobody controls the pad or its client's outbox is full.
+    pub(crate) fn send(&self, id: usize, message: Message) -> bool {
+        self.send_to_pad(id, |_| message)
+    }
+
+    /// Queue a message about the pad with the given id for the client controlling it, made from
+    /// the pad's index among the client's pads. Returns whether it was queued, like
+    /// [Outboxes::send].
+    pub(crate) fn send_to_pad(&self, id: usize, message: impl FnOnce(usize) -> Message) -> bool {
+        let route = match self.0.lock().unwrap().get(&id) {
+            Some(route) => route.clone(),
+            None => return false,
+        };
+        match route.tx.try_send(message(route.index)) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    /// Queue a message for every client, once each no matter how many pads it controls.
+    /// Returns how many clients it was queued for.
+    pub(crate) fn broadcast(&self, message: Message) -> usize {
+        let connections: BTreeSet<_> = self
+            .0
+            .lock()
+            .unwrap()
+            .values()
+            .map(|route| route.connection)
+            .collect();
+        connections
+            .into_iter()
+            .filter(|&connection| self.send(connection, message.clone()))
+            .count()
+    }
+}
+
+/// A connection's queue of messages, opened by its handler with [Outbox::open]. Its pads stop
+/// being reachable through the [Outboxes] once it's dropped.
+#[derive(Debug)]
+pub(crate) struct Outbox {
+    outboxes: Arc<Outboxes>,
+
+    /// The connection's pads, the first of which identifies it
+    pads: Vec<usize>,
+
+    tx: SyncSender<Message>,
+    rx: Receiver<Message>,
+
+    /// The messages taken out of the queue which the websocket had no room for yet
+    pending: VecDeque<Message>,
+}
+
+impl Outbox {
+    /// Open an outbox for a new connection controlling the pad with the given id
+    pub(crate) fn open(outboxes: Arc<Outboxes>, id: usize) -> Self {
+        let (tx, rx) = sync_channel(OUTBOX_SIZE);
+        let outbox = Self {
+            outboxes,
+            pads: vec![id],
+            tx,
+            rx,
+            pending: VecDeque::new(),
+        };
+        outbox.route(id, id, 0);
+        outbox
+    }
+
+    fn route(&self, id: usize, connection: usize, index: usize) {
+        let route = Route {
+            connection,
+            index,
+            tx: self.tx.clone(),
+        };
+        let _ = self.outboxes.0.lock().unwrap().insert(id, route);
+    }
+
+    /// Make messages for another pad the connection now controls come here too
+    pub(crate) fn add_pad(&mut self, id: usize) {
+        self.route(id, self.pads[0], self.pads.len());
+        self.pads.push(id);
+    }
+
+    /// Queue a message from the handler itself, after those already queued
+    pub(crate) fn push(&mut self, message: Message) {
+        self.pending.extend(self.rx.try_iter());
+        self.pending.push_back(message);
+    }
+
+    /// Write out as many queued messages as the websocket has room for, keeping the rest for
+    /// the next flush
+    pub(crate) fn flush<S: Read + Write>(&mut self, ws: &mut WebSocket<S>) -> Result<()> {
+        self.pending.extend(self.rx.try_iter());
+        while let Some(message) = self.pending.pop_front() {
+            match ws.write_message(message) {
+                Ok(()) => {}
+                Err(tungstenite::Error::SendQueueFull(message)) => {
+                    self.pending.push_front(message);
+                    break;
+                }
+                // The message was queued by the websocket, which only couldn't send it yet
+                Err(tungstenite::Error::Io(error)) if error.kind() == io::ErrorKind::WouldBlock => {
+                    break
+                }
+                Err(error) => return Err(error.into()),
+            }
+        }
+        match ws.write_pending() {
+            Ok(()) => Ok(()),
+            Err(tungstenite::Error::Io(error)) if error.kind() == io::ErrorKind::WouldBlock => {
+                Ok(())
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Take every queued message out, for a handler which writes them out itself
+    #[cfg(feature = "async-server")]
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = Message> + '_ {
+        self.pending.extend(self.rx.try_iter());
+        self.pending.drain(..)
+    }
+
+    /// Write out every queued message and close the connection with the given frame. Messages
+    /// the websocket has no room for even then are thrown away, as the client is going away.
+    pub(crate) fn close<S: Read + Write>(
+        &mut self,
+        ws: &mut WebSocket<S>,
+        frame: CloseFrame<'static>,
+    ) -> Result<()> {
+        self.flush(ws)?;
+        ws.close(Some(frame))?;
+        match ws.write_pending() {
+            Ok(()) | Err(tungstenite::Error::ConnectionClosed) => Ok(()),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+impl Drop for Outbox {
+    fn drop(&mut self) {
+        let mut routes = self.outboxes.0.lock().unwrap();
+        for id in &self.pads {
+            // The pad may have been taken over by another connection since we let go of it
+            if matches!(routes.get(id), Some(route) if route.connection == self.pads[0]) {
+                let _ = routes.remove(id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tungstenite::protocol::{frame::coding::CloseCode, Role, WebSocketConfig};
+
+    use super::*;
+
+    /// A stream which keeps what's written to it, unless it's blocked
+    #[derive(Debug, Default)]
+    struct Pipe {
+        written: Vec<u8>,
+        blocked: bool,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            if self.blocked {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.written.extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A websocket writing into a [Pipe], with room for `send_queue` messages it couldn't send
+    fn websocket(send_queue: Option<usize>) -> WebSocket<Pipe> {
+        let config = WebSocketConfig {
+            max_send_queue: send_queue,
+            ..WebSocketConfig::default()
+        };
+        WebSocket::from_raw_socket(Pipe::default(), Role::Server, Some(config))
+    }
+
+    /// Read back every message a websocket from [websocket] wrote
+    fn written(mut ws: WebSocket<Pipe>) -> Vec<Message> {
+        let data = std::mem::take(&mut ws.get_mut().written);
+        let mut ws = WebSocket::from_raw_socket(io::Cursor::new(data), Role::Client, None);
+        let mut messages = Vec::new();
+        while let Ok(message) = ws.read_message() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    fn text(text: &str) -> Message {
+        Message::Text(text.to_string())
+    }
+
+    #[test]
+    fn test_send_by_pad() {
+        let outboxes = Arc::new(Outboxes::default());
+        let mut first = Outbox::open(Arc::clone(&outboxes), 0);
+        first.add_pad(2);
+        let mut second = Outbox::open(Arc::clone(&outboxes), 1);
+
+        assert!(outboxes.send(2, text("attached")));
+        assert!(outboxes.send(1, text("second")));
+        assert!(!outboxes.send(3, text("nobody")));
+        first.push(text("own"));
+
+        let mut ws = websocket(None);
+        first.flush(&mut ws).unwrap();
+        assert_eq!(written(ws), [text("attached"), text("own")]);
+        let mut ws = websocket(None);
+        second.flush(&mut ws).unwrap();
+        assert_eq!(written(ws), [text("second")]);
+
+        // The pads are unreachable once their connection is gone
+        drop(first);
+        assert!(!outboxes.send(0, text("gone")));
+        assert!(!outboxes.send(2, text("gone")));
+        assert!(outboxes.send(1, text("still here")));
+    }
+
+    #[test]
+    fn test_send_to_pad() {
+        let outboxes = Arc::new(Outboxes::default());
+        let mut outbox = Outbox::open(Arc::clone(&outboxes), 4);
+        outbox.add_pad(1);
+
+        // Messages about a pad are addressed by its index among the connection's pads
+        assert!(outboxes.send_to_pad(1, |index| text(&format!("pad {}", index))));
+        assert!(outboxes.send_to_pad(4, |index| text(&format!("pad {}", index))));
+        assert!(!outboxes.send_to_pad(0, |_| unreachable!()));
+
+        let mut ws = websocket(None);
+        outbox.flush(&mut ws).unwrap();
+        assert_eq!(written(ws), [text("pad 1"), text("pad 0")]);
+    }
+
+    #[test]
+    fn test_broadcast() {
+        let outboxes = Arc::new(Outboxes::default());
+        let mut first = Outbox::open(Arc::clone(&outboxes), 0);
+        first.add_pad(1);
+        first.add_pad(2);
+        let mut second = Outbox::open(Arc::clone(&outboxes), 3);
+
+        // Once per connection, not per pad
+        assert_eq!(outboxes.broadcast(text("bye")), 2);
+        for outbox in &mut [&mut first, &mut second] {
+            let mut ws = websocket(None);
+            outbox.flush(&mut ws).unwrap();
+            assert_eq!(written(ws), [text("bye")]);
+        }
+    }
+
+    #[test]
+    fn test_full() {
+        let outboxes = Arc::new(Outboxes::default());
+        let mut outbox = Outbox::open(Arc::clone(&outboxes), 0);
+        let messages: Vec<_> = (0..OUTBOX_SIZE).map(|i| text(&i.to_string())).collect();
+        for message in &messages {
+            assert!(outboxes.send(0, message.clone()));
+        }
+        assert!(!outboxes.send(0, text("too many")));
+
+        // Whatever the websocket has no room for while the client isn't keeping up is kept
+        // for the next flush, in order
+        let mut ws = websocket(Some(1));
+        ws.get_mut().blocked = true;
+        outbox.flush(&mut ws).unwrap();
+        outbox.flush(&mut ws).unwrap();
+        assert!(ws.get_ref().written.is_empty());
+        assert!(!outbox.pending.is_empty());
+
+        ws.get_mut().blocked = false;
+        outbox.flush(&mut ws).unwrap();
+        assert!(outbox.pending.is_empty());
+        assert_eq!(written(ws), messages);
+    }
+
+    #[test]
+    fn test_close_drains() {
+        let outboxes = Arc::new(Outboxes::default());
+        let mut outbox = Outbox::open(Arc::clone(&outboxes), 0);
+        assert!(outboxes.send(0, text("notice")));
+
+        let mut ws = websocket(None);
+        outbox
+            .close(
+                &mut ws,
+                CloseFrame {
+                    code: CloseCode::Away,
+                    reason: "shutting down".into(),
+                },
+            )
+            .unwrap();
+        match written(ws).as_slice() {
+            [notice, Message::Close(Some(frame))] => {
+                assert_eq!(notice, &text("notice"));
+                assert_eq!(frame.code, CloseCode::Away);
+            }
+            messages => panic!("{:?}", messages),
+        }
+        drop(outbox);
+        assert!(!outboxes.send(0, text("gone")));
+    }
+}
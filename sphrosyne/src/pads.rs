@@ -0,0 +1,3220 @@
+//! Plugging pads into the bus and feeding them the states their clients send, which happens on
+//! a thread of its own that the websockets send their requests to
+
+use std::{
+    collections::{BTreeSet, VecDeque},
+    sync::{
+        atomic::AtomicU64,
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        mpsc::{channel, Receiver, RecvTimeoutError, SendError, Sender},
+        Arc, Mutex,
+    },
+    thread::sleep,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use eyre::Result;
+use slab::Slab;
+use slog::{debug, error, info, trace, warn, Logger, Record, Serializer, KV};
+use vigem_client_c::{
+    client::{
+        Client, DS4NotificationData, OwnedTarget, Preset, UserIndex, X360NotificationData, DS4,
+        X360,
+    },
+    Error, SocdPolicy, StateDiff, X360State,
+};
+
+use crate::{
+    args::Args,
+    auth::Token,
+    calibration::Calibration,
+    close::CloseReason,
+    embed::PadEvent,
+    latency::{self, Latency},
+    macros::{self, Macro, Playback, Recording, MACRO_SLOTS},
+    metrics::Metrics,
+    motion::{Motion, Orientation},
+    profiles::{ProfileChange, Profiles},
+    ratelimit::Throttled,
+    recorder::Recorder,
+    remap::Remap,
+    request::{
+        Connection, LatestState, NewPad, NewPadReply, Notification, PadRequest, PadType,
+        PipelineEdit, Player, TestInputReply, NO_LED, XINPUT_SLOTS,
+    },
+    snapshot::{ClientSnapshot, PadSnapshot},
+    status::{self, ClientStatus, PadStatus, Status},
+    test_input::{TestInput, CLIENT_PRECEDENCE},
+    ticker::Ticker,
+    transform::{Pipeline, Transformer},
+    turbo::{Turbo, TurboConfig},
+};
+
+/// Where to download ViGEmBus from
+pub(crate) const DRIVER_URL: &str = "https://github.com/nefarius/ViGEmBus/releases/latest";
+
+/// How long to wait between attempts at connecting to the bus while ViGEmBus is missing
+pub(crate) const DRIVER_POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Connect to the bus, waiting for as long as it takes for ViGEmBus to become available.
+pub(crate) fn connect_client(logger: &Logger) -> Result<Arc<Client>> {
+    wait_for_bus(
+        logger,
+        &AtomicBool::new(false),
+        DRIVER_POLL_INTERVAL,
+        Client::new,
+    )
+}
+
+/// Like [connect_client], connecting with `connect` every `poll` until it stops failing because
+/// ViGEmBus is missing, which `driver_missing` is set for meanwhile. Any other error is returned
+/// right away.
+fn wait_for_bus(
+    logger: &Logger,
+    driver_missing: &AtomicBool,
+    poll: Duration,
+    mut connect: impl FnMut() -> Result<Client, Error>,
+) -> Result<Arc<Client>> {
+    loop {
+        match connect() {
+            Ok(client) => {
+                if driver_missing.swap(false, Ordering::SeqCst) {
+                    info!(logger, "bus.driver_found");
+                }
+                if cfg!(feature = "mock") {
+                    warn!(logger, "bus.mock"; "msg" => "pads are only plugged into an in-memory bus");
+                }
+                info!(logger, "bus.connected");
+                return Ok(Arc::new(client));
+            }
+            Err(error) if error.is_driver_missing() => {
+                // The first time around says where to get the driver, since it's most likely a
+                // first-time user who hasn't installed it yet
+                if !driver_missing.swap(true, Ordering::SeqCst) {
+                    warn!(logger, "bus.waiting"; "msg" => "ViGEmBus couldn't be reached, install it if it isn't and it'll be picked up without a restart", "url" => DRIVER_URL, "error" => %error);
+                } else {
+                    debug!(logger, "bus.waiting"; "error" => %error);
+                }
+                sleep(poll);
+            }
+            Err(error) => return Err(error.into()),
+        }
+    }
+}
+
+/// The notification callback registered on every pad, forwarding notifications to its websocket
+type FeedbackCallback = Box<dyn Fn(Notification) + std::panic::RefUnwindSafe + Send + Sync>;
+
+/// How many updates for a pad were sent to the bus, how many were skipped for being identical
+/// to the last one sent, and how many the bus refused
+#[derive(Debug, Clone, Default)]
+struct UpdateStats {
+    sent: u64,
+    skipped: u64,
+    errors: u64,
+
+    /// How many updates the bus refused since it last took one
+    failures: u32,
+
+    /// When the updates received within the last [RATE_WINDOW] arrived
+    recent: VecDeque<Instant>,
+
+    /// When the last update was received
+    last_update: Option<SystemTime>,
+}
+
+/// The window over which update rates are computed
+const RATE_WINDOW: Duration = Duration::from_secs(status::RATE_WINDOW_SECS);
+
+impl UpdateStats {
+    /// Record that an update was received
+    fn record(&mut self) {
+        let now = Instant::now();
+        self.recent.push_back(now);
+        self.last_update = Some(SystemTime::now());
+        self.prune(now);
+    }
+
+    /// Forget about the updates which are no longer within the window
+    fn prune(&mut self, now: Instant) {
+        while matches!(self.recent.front(), Some(&at) if now.duration_since(at) > RATE_WINDOW) {
+            let _ = self.recent.pop_front();
+        }
+    }
+
+    /// How many updates per second were received over the last [RATE_WINDOW]
+    fn rate(&mut self) -> f64 {
+        self.prune(Instant::now());
+        self.recent.len() as f64 / RATE_WINDOW.as_secs_f64()
+    }
+}
+
+/// A pad plugged into the bus, of whichever type its client asked for
+pub(crate) trait Target {
+    fn pad_type(&self) -> PadType;
+
+    /// The target's index on the bus
+    fn index(&self) -> u32;
+
+    /// The target's vendor and product ids
+    fn ids(&self) -> (u16, u16);
+
+    /// Send the target a state, laid out on a dualshock 4 if that's what the target is
+    fn update(&self, state: X360State) -> Result<(), Error>;
+
+    /// The target's user index, which only xbox 360 pads have
+    fn user_index(&self) -> Result<UserIndex, Error>;
+
+    /// Wait for the bus to give the target a user index, which only xbox 360 pads get
+    fn wait_for_user_index(&self, timeout: Duration) -> Result<u32, Error>;
+}
+
+/// What plugs pads into the bus, which is a [Client] outside of tests
+pub(crate) trait TargetFactory {
+    /// Plug in a target of the given type, passing for the preset's controller if there's one,
+    /// whose notifications are forwarded to `feedback`
+    fn connect(
+        &self,
+        pad_type: PadType,
+        preset: Option<Preset>,
+        feedback: &Arc<Feedback>,
+    ) -> Result<Box<dyn Target>>;
+
+    /// Whether the bus is still there
+    fn is_connected(&self) -> bool;
+
+    /// Connect to the bus anew after it went away, waiting for as long as that takes
+    fn reconnect(&mut self, logger: &Logger) -> Result<()>;
+}
+
+/// A pad's target on a real bus
+enum AnyTarget {
+    X360(OwnedTarget<X360>),
+    DS4(OwnedTarget<DS4>),
+}
+
+impl Target for AnyTarget {
+    fn pad_type(&self) -> PadType {
+        match self {
+            Self::X360(_) => PadType::X360,
+            Self::DS4(_) => PadType::DS4,
+        }
+    }
+
+    fn index(&self) -> u32 {
+        match self {
+            Self::X360(target) => target.index(),
+            Self::DS4(target) => target.index(),
+        }
+    }
+
+    fn ids(&self) -> (u16, u16) {
+        match self {
+            Self::X360(target) => (target.vendor_id(), target.product_id()),
+            Self::DS4(target) => (target.vendor_id(), target.product_id()),
+        }
+    }
+
+    fn update(&self, state: X360State) -> Result<(), Error> {
+        match self {
+            Self::X360(target) => target.update(state),
+            Self::DS4(target) => target.update(state.into()),
+        }
+    }
+
+    fn user_index(&self) -> Result<UserIndex, Error> {
+        match self {
+            Self::X360(target) => target.user_index(),
+            Self::DS4(_) => Err(Error::NotSupported),
+        }
+    }
+
+    fn wait_for_user_index(&self, timeout: Duration) -> Result<u32, Error> {
+        match self {
+            Self::X360(target) => target.wait_for_user_index(timeout),
+            Self::DS4(_) => Err(Error::NotSupported),
+        }
+    }
+}
+
+/// Where a pad's notification callback forwards feedback to, which also remembers the LED the
+/// game last lit on the pad, as that's the player number the game itself goes by
+pub(crate) struct Feedback {
+    tx: Mutex<Sender<Notification>>,
+    led: AtomicU8,
+}
+
+impl Feedback {
+    fn new(tx: Sender<Notification>) -> Self {
+        Self {
+            tx: Mutex::new(tx),
+            led: AtomicU8::new(NO_LED),
+        }
+    }
+
+    /// Forward a notification to the pad's client, if it has one
+    fn send(&self, notification: Notification) {
+        if notification.data.led_number != NO_LED {
+            self.led
+                .store(notification.data.led_number, Ordering::Relaxed);
+        }
+        if let Ok(tx) = self.tx.lock() {
+            let _ = tx.send(notification);
+        }
+    }
+
+    /// Forward the pad's notifications to a new client from now on
+    fn replace(&self, tx: Sender<Notification>) {
+        *self.tx.lock().unwrap() = tx;
+    }
+
+    /// The LED the game last lit on the pad, if it did
+    fn led(&self) -> Option<u8> {
+        Some(self.led.load(Ordering::Relaxed)).filter(|&led| led != NO_LED)
+    }
+}
+
+/// A pad along with where its notification callback forwards feedback to
+struct Pad {
+    target: Box<dyn Target>,
+
+    /// The controller the pad passes for, if not the default for its type, which it keeps doing
+    /// when it's plugged in anew
+    preset: Option<Preset>,
+
+    /// Where feedback for this pad goes, which changes whenever the pad is reclaimed
+    feedback: Arc<Feedback>,
+
+    /// The player number the pad goes by if the bus didn't give it a user index for having
+    /// none left, past the [XINPUT_SLOTS]
+    overflow: Option<u32>,
+
+    /// The token a client has to present to get this pad back after losing its connection
+    reclaim: Token,
+
+    /// When the pad's client lost its connection, if it did
+    detached_at: Option<Instant>,
+
+    /// The last state sent to the bus, if any has been since the target was created
+    last_state: Option<X360State>,
+    stats: UpdateStats,
+
+    /// How many of the messages clients sent for this pad the rate limit kept from it
+    throttled: Arc<Throttled>,
+
+    /// The latest state the pad's client sent, which we haven't sent to the bus yet
+    latest: Arc<LatestState>,
+
+    /// Where the time goes between the pad's client sending a state and it reaching the bus
+    latency: Arc<Latency>,
+
+    /// How many latency samples there were when they were last logged
+    latency_logged: u64,
+
+    /// The connection the pad's client came in on, unless the pad is free or was just restored
+    connection: Option<Connection>,
+
+    /// The identifier of the device controlling this pad, which its calibration is saved under
+    device: Option<String>,
+    calibration: Calibration,
+
+    /// The state the client last sent as it sent it, and what changed from the one before
+    received: X360State,
+    last_change: StateDiff,
+
+    /// The calibrated state the client last sent with its motion merged in, which turbo buttons
+    /// keep pulsing from
+    held: X360State,
+    turbo: Turbo,
+    motion: Motion,
+
+    /// How the pad's inputs are rearranged on their way to the bus, after turbo buttons pulse
+    remap: Remap,
+
+    /// What the pad's client put its states through last, after they're remapped
+    transformers: Pipeline,
+
+    /// When the pad's client last sent a state, until the pad is made neutral for going
+    /// without one for too long
+    last_update: Option<Instant>,
+
+    /// How long the pad can go without a state before it's made neutral, if its client asked
+    /// for something other than the server's interval
+    neutral_after: Option<Duration>,
+
+    /// What's done about the dpad being held both ways at once, if the pad's client asked for
+    /// something other than the server's policy
+    socd: Option<SocdPolicy>,
+
+    /// Whether the pad's page is in the background, in which case it's kept neutral and the
+    /// states its client sends are remembered but never sent
+    suspended: bool,
+
+    /// The test input the pad is playing, if it is, which it sends instead of what its client
+    /// holds until it's over or the client sends a state
+    test_input: Option<TestInput>,
+
+    /// The states the pad's client is recording for a macro, if it is
+    recording: Option<Recording>,
+
+    /// What the pad's client recorded last, until it keeps it in one of its device's slots
+    recorded: Option<Macro>,
+
+    /// The macros of the pad's device, which it got from its profile
+    macros: Vec<Macro>,
+
+    /// The macro the pad is playing, if it is, which is merged into what its client holds
+    playing: Option<Playback>,
+
+    /// Which direction of each pair of opposites the client last held on its own, which
+    /// [SocdPolicy::LastInput] goes by
+    last_dpad: X360State,
+
+    /// Whether the pad was created at startup, in which case it's reset rather than removed
+    /// once its client is done with it, so that it keeps its player number
+    reserved: bool,
+}
+
+/// The connection a pad's client came in on, if it has one, as logged along with the pad
+struct PadClient<'a>(Option<&'a Connection>);
+
+impl KV for PadClient<'_> {
+    fn serialize(&self, record: &Record, serializer: &mut dyn Serializer) -> slog::Result {
+        match self.0 {
+            Some(connection) => connection.serialize(record, serializer),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Plugs pads into a real bus. Dualshock 4 notifications are forwarded as xbox 360 ones, keeping
+/// their rumble and leaving out their lightbar's color, with [NO_LED] as their LED number.
+impl TargetFactory for Arc<Client> {
+    fn connect(
+        &self,
+        pad_type: PadType,
+        preset: Option<Preset>,
+        feedback: &Arc<Feedback>,
+    ) -> Result<Box<dyn Target>> {
+        connect_target(self, pad_type, preset, feedback).map(|target| Box::new(target) as _)
+    }
+
+    fn is_connected(&self) -> bool {
+        Client::is_connected(self)
+    }
+
+    fn reconnect(&mut self, logger: &Logger) -> Result<()> {
+        *self = connect_client(logger)?;
+        Ok(())
+    }
+}
+
+/// Create a target of the given type, passing for the preset's controller if there's one, whose
+/// notifications are forwarded to `feedback`
+fn connect_target(
+    client: &Arc<Client>,
+    pad_type: PadType,
+    preset: Option<Preset>,
+    feedback: &Arc<Feedback>,
+) -> Result<AnyTarget> {
+    let feedback = Arc::clone(feedback);
+    let callback: FeedbackCallback = Box::new(move |data| feedback.send(data));
+    // The target unregisters the callback by itself once it's dropped
+    match pad_type {
+        PadType::X360 => {
+            let mut builder = client.x360_pad_owned();
+            if let Some(preset) = preset {
+                builder = builder.preset(preset);
+            }
+            let mut target = builder.connect()?;
+            let _ = target
+                .register_notification(move |data: X360NotificationData| callback(data.into()))?;
+            Ok(AnyTarget::X360(target))
+        }
+        PadType::DS4 => {
+            let mut builder = client.ds4_pad_owned();
+            if let Some(preset) = preset {
+                builder = builder.preset(preset);
+            }
+            let mut target = builder.connect()?;
+            let _ = target.register_notification(move |data: DS4NotificationData| {
+                callback(Notification {
+                    data: X360NotificationData {
+                        large_motor: data.large_motor,
+                        small_motor: data.small_motor,
+                        led_number: NO_LED,
+                    },
+                    lightbar: Some(data.lightbar_color),
+                })
+            })?;
+            Ok(AnyTarget::DS4(target))
+        }
+    }
+}
+
+impl Pad {
+    fn new(
+        bus: &impl TargetFactory,
+        pad_type: PadType,
+        preset: Option<Preset>,
+        feedback_tx: Sender<Notification>,
+    ) -> Result<Self> {
+        let feedback = Arc::new(Feedback::new(feedback_tx));
+        Ok(Self {
+            target: bus.connect(pad_type, preset, &feedback)?,
+            preset,
+            feedback,
+            overflow: None,
+            reclaim: Token::generate(),
+            detached_at: None,
+            last_state: None,
+            stats: UpdateStats::default(),
+            throttled: Arc::default(),
+            latest: Arc::default(),
+            latency: Arc::default(),
+            latency_logged: 0,
+            connection: None,
+            device: None,
+            calibration: Calibration::default(),
+            received: X360State::default(),
+            last_change: StateDiff::default(),
+            held: X360State::default(),
+            turbo: Turbo::default(),
+            motion: Motion::default(),
+            remap: Remap::default(),
+            transformers: Pipeline::default(),
+            last_update: None,
+            neutral_after: None,
+            socd: None,
+            suspended: false,
+            test_input: None,
+            recording: None,
+            recorded: None,
+            macros: Vec::new(),
+            playing: None,
+            last_dpad: X360State::default(),
+            reserved: false,
+        })
+    }
+
+    /// What's logged about the pad's client along with the pad
+    fn client(&self) -> PadClient<'_> {
+        PadClient(self.connection.as_ref())
+    }
+
+    /// Which player the pad is: the LED the game lit on it if it did, else its user index, else
+    /// the number it was given past the XInput slots. Dualshock 4 pads aren't numbered at all.
+    fn player(&self) -> Option<Player> {
+        let index = self.target.user_index().ok()?.assigned();
+        let number = self
+            .feedback
+            .led()
+            .map(|led| u32::from(led) + 1)
+            .or_else(|| index.map(|index| index + 1))
+            .or(self.overflow)?;
+        Some(Player {
+            number,
+            xinput: index.is_some(),
+        })
+    }
+
+    /// Hand the pad to a new client with its own feedback channel and reclaim token, using the
+    /// calibration in its device's profile if there is one
+    fn assign(
+        &mut self,
+        id: usize,
+        profiles: &Profiles,
+        connection: Connection,
+        device: Option<String>,
+    ) -> NewPad {
+        let (feedback_tx, feedback) = channel();
+        self.feedback.replace(feedback_tx);
+        self.reclaim = Token::generate();
+        self.detached_at = None;
+        self.connection = Some(connection);
+        // Whatever the previous client sent last is no concern of the new one
+        self.latest.reset();
+        self.received = X360State::default();
+        self.last_change = StateDiff::default();
+        self.last_update = None;
+        self.neutral_after = None;
+        self.socd = None;
+        self.suspended = false;
+        self.test_input = None;
+        self.recording = None;
+        self.recorded = None;
+        self.playing = None;
+        self.last_dpad = X360State::default();
+        let profile = device
+            .as_deref()
+            .and_then(|device| profiles.get(device))
+            .cloned();
+        self.calibration = profile
+            .as_ref()
+            .and_then(|profile| profile.calibration)
+            .map(Calibration::clamped)
+            .unwrap_or_default();
+        self.macros = profile
+            .as_ref()
+            .map(|profile| profile.macros.clone())
+            .unwrap_or_default();
+        self.device = device.clone();
+        NewPad {
+            id,
+            feedback,
+            reclaim: self.reclaim.to_string(),
+            player: self.player(),
+            device,
+            profile,
+            pad_type: self.target.pad_type(),
+            throttled: Arc::clone(&self.throttled),
+            latest: Arc::clone(&self.latest),
+            latency: Arc::clone(&self.latency),
+        }
+    }
+
+    /// Bring a reserved pad back to how it was at startup for the next client, with every
+    /// button released and both sticks centered
+    fn reset(&mut self) -> Result<bool, Error> {
+        // Nobody is listening for feedback until the pad is assigned again
+        self.feedback.replace(channel().0);
+        self.detached_at = None;
+        self.connection = None;
+        self.device = None;
+        self.calibration = Calibration::default();
+        self.turbo = Turbo::default();
+        self.motion = Motion::default();
+        self.remap = Remap::default();
+        self.transformers.clear();
+        self.last_update = None;
+        self.neutral_after = None;
+        self.socd = None;
+        self.suspended = false;
+        self.test_input = None;
+        self.recording = None;
+        self.recorded = None;
+        self.macros.clear();
+        self.playing = None;
+        self.last_dpad = X360State::default();
+        self.received = X360State::default();
+        self.last_change = StateDiff::default();
+        self.held = X360State::default();
+        self.send(Instant::now())
+    }
+
+    /// Who is controlling this pad, to be served at `/admin/pads`, if it has a client
+    fn client_status(&self, id: usize) -> Option<ClientStatus> {
+        let connection = self.connection.as_ref()?;
+        Some(ClientStatus {
+            id,
+            ip: connection.ip,
+            connected_ms: epoch_millis(connection.since).unwrap_or_default(),
+            last_update_ms: self.stats.last_update.and_then(epoch_millis),
+            buttons: self.received.buttons.bits(),
+            detached: self.detached_at.is_some(),
+            suspended: self.suspended,
+        })
+    }
+
+    /// A snapshot of this pad's stats, to be served at `/status`
+    fn status(&mut self, id: usize, free: bool) -> PadStatus {
+        PadStatus {
+            id,
+            pad_type: self.target.pad_type(),
+            vendor_id: self.target.ids().0,
+            product_id: self.target.ids().1,
+            user_index: self.target.user_index().into(),
+            player: self.player().map(|player| player.number),
+            player_label: self.player().map(|player| player.to_string()),
+            detached: self.detached_at.is_some(),
+            suspended: self.suspended,
+            free,
+            updates_per_second: self.stats.rate(),
+            last_update_ms: self.stats.last_update.and_then(epoch_millis),
+            sent: self.stats.sent,
+            skipped: self.stats.skipped,
+            errors: self.stats.errors,
+            coalesced: load(&self.throttled.coalesced),
+            dropped: load(&self.throttled.dropped),
+            latency: self.latency.summary(),
+            last_change: self.last_change.to_string(),
+        }
+    }
+
+    /// What it takes to create this pad again after a restart, leaving out its client if it's
+    /// free
+    fn snapshot(&self, free: bool) -> PadSnapshot {
+        PadSnapshot {
+            pad_type: self.target.pad_type(),
+            reserved: self.reserved,
+            client: (!free).then(|| ClientSnapshot {
+                reclaim: self.reclaim.to_string(),
+                device: self.device.clone(),
+                calibration: self.calibration,
+                remap: self.remap.clone(),
+            }),
+        }
+    }
+
+    /// Replace this pad's target with a new one on the given bus, keeping everything else
+    fn reconnect(&mut self, bus: &impl TargetFactory) -> Result<()> {
+        self.target = bus.connect(self.target.pad_type(), self.preset, &self.feedback)?;
+        // The new target starts out neutral, so the next update has to go through no matter what
+        self.last_state = None;
+        Ok(())
+    }
+
+    /// Settle the dpad according to the pad's policy or else the server's, calibrate a state and
+    /// send it to the bus with its turbo buttons pulsed, unless it's the same as the last one we
+    /// sent. Returns whether the state was actually sent.
+    fn update(&mut self, mut state: X360State, socd: Option<SocdPolicy>) -> Result<bool, Error> {
+        self.test_input = None;
+        self.last_change = self.received.diff(&state);
+        self.received = state;
+        self.stats.record();
+        self.last_update = Some(Instant::now());
+        state.remember_dpad(&mut self.last_dpad);
+        if let Some(policy) = self.socd.or(socd) {
+            state.sanitize(policy, Some(&self.last_dpad));
+        }
+        self.held = self.motion.apply(self.calibration.apply(state));
+        if let Some(recording) = &mut self.recording {
+            recording.record(self.held, Instant::now());
+        }
+        self.send(Instant::now())
+    }
+
+    /// Send the last state the client sent again if its turbo buttons are due to be pressed or
+    /// released at `now`, or its transformers are due to make something else of it, e.g. to
+    /// ramp a trigger. This isn't an update the client sent, so it's not counted as one.
+    fn pulse(&mut self, now: Instant) -> Result<bool, Error> {
+        let due =
+            self.turbo.next_toggle(now).is_some() || self.transformers.next_due(now).is_some();
+        if !due || self.test_input.is_some() {
+            return Ok(false);
+        }
+        let state = self.transform(now);
+        if self.last_state == Some(state) {
+            return Ok(false);
+        }
+        self.send_state(state)
+    }
+
+    /// When the pad is due to be made neutral for going without a state, if it's holding on to
+    /// one, given the server's interval
+    fn neutral_at(&self, neutral_after: Duration) -> Option<Instant> {
+        let neutral_after = self.neutral_after.unwrap_or(neutral_after);
+        if neutral_after.is_zero() {
+            return None;
+        }
+        Some(self.last_update? + neutral_after)
+    }
+
+    /// Let go of every button and center both sticks, as if the client had sent a neutral
+    /// state, until it sends another one
+    fn neutralize(&mut self) -> Result<bool, Error> {
+        self.last_update = None;
+        self.held = X360State::default();
+        self.send(Instant::now())
+    }
+
+    /// Change the pad's turbo buttons, releasing the current ones rather than leaving them
+    /// pressed if they were mid-pulse
+    fn set_turbo(&mut self, config: TurboConfig) -> Result<bool, Error> {
+        self.held = self.turbo.release(self.held);
+        self.turbo = Turbo::new(config);
+        self.send(Instant::now())
+    }
+
+    /// Move the right stick to where the phone is facing now, if motion is enabled and the
+    /// page isn't in the background, where the phone is likely in a pocket
+    fn orient(&mut self, orientation: Orientation) -> Result<bool, Error> {
+        if self.suspended {
+            return Ok(false);
+        }
+        self.motion.orient(orientation);
+        self.held = self.motion.apply(self.held);
+        self.send(Instant::now())
+    }
+
+    /// Change how the pad's inputs are rearranged, sending what the client holds through the
+    /// new remap right away rather than waiting for its next update
+    fn set_remap(&mut self, remap: Remap) -> Result<bool, Error> {
+        self.remap = remap;
+        self.send(Instant::now())
+    }
+
+    /// Change the transformers the pad's states go through last, sending what the client holds
+    /// through them right away. Returns whether the edit could be made at all, and whether the
+    /// state was sent.
+    fn edit_pipeline(&mut self, edit: PipelineEdit) -> Result<(bool, bool), Error> {
+        let edited = match edit {
+            PipelineEdit::Insert(index, transformer) => {
+                self.transformers.insert(index, transformer);
+                true
+            }
+            PipelineEdit::Remove(index) => self.transformers.remove(index).is_some(),
+            PipelineEdit::Move(from, to) => self.transformers.move_to(from, to),
+        };
+        Ok((edited, self.send(Instant::now())?))
+    }
+
+    /// The state the client last sent merged with the macro it's playing, with its turbo
+    /// buttons as they should be at `now`, its inputs remapped and put through its transformers
+    fn transform(&mut self, now: Instant) -> X360State {
+        let held = match self.playing.as_ref().and_then(|playing| playing.state(now)) {
+            Some(played) => macros::merge(self.held, played),
+            None => self.held,
+        };
+        let state = self.remap.transform(self.turbo.apply(held, now), now);
+        self.transformers.apply(state, now)
+    }
+
+    /// Start recording the client's states for a macro, from what it holds now, or stop
+    /// recording them and keep what was recorded until it's saved
+    fn record(&mut self, recording: bool, now: Instant) {
+        if recording {
+            self.recording = Some(Recording::new(self.held, now));
+        } else if let Some(recording) = self.recording.take() {
+            self.recorded = Some(recording.finish(0, now));
+        }
+    }
+
+    /// Keep what the client recorded last as the macro in the given slot, replacing the one
+    /// there if there's one. Returns whether there was a recording to keep.
+    fn save_macro(&mut self, slot: u8, now: Instant) -> bool {
+        self.record(false, now);
+        let recorded = match self.recorded.take() {
+            Some(recorded) => Macro { slot, ..recorded },
+            None => return false,
+        };
+        self.macros.retain(|kept| kept.slot != slot);
+        self.macros.push(recorded);
+        self.macros.sort_by_key(|kept| kept.slot);
+        true
+    }
+
+    /// Start playing the macro in the given slot, from the top if it's playing already.
+    /// Returns whether there's such a macro, and whether the state was sent.
+    fn play_macro(&mut self, slot: u8, now: Instant) -> Result<(bool, bool), Error> {
+        let played = match self.macros.iter().find(|kept| kept.slot == slot) {
+            Some(played) => played.clone(),
+            None => return Ok((false, false)),
+        };
+        self.playing = Some(Playback::new(played, now));
+        Ok((true, self.send(now)?))
+    }
+
+    /// Send the state the macro the pad is playing is at by `now`, merged with what the client
+    /// holds, or just what the client holds once it's over
+    fn step_macro(&mut self, now: Instant) -> Result<bool, Error> {
+        match &self.playing {
+            Some(playing) if playing.state(now).is_none() => self.playing = None,
+            Some(_) => {}
+            None => return Ok(false),
+        }
+        self.send(now)
+    }
+
+    /// Whether the pad's client sent a state recently enough to take precedence over the test
+    /// input
+    fn receiving(&self, now: Instant) -> bool {
+        self.last_update
+            .is_some_and(|at| now.saturating_duration_since(at) < CLIENT_PRECEDENCE)
+    }
+
+    /// Send the state the test input is at by `now`, or what the client holds once it's over
+    fn play_test_input(&mut self, now: Instant) -> Result<bool, Error> {
+        let test_input = match &self.test_input {
+            Some(test_input) => test_input,
+            None => return Ok(false),
+        };
+        match test_input.state(now) {
+            Some(state) if self.last_state == Some(state) => Ok(false),
+            Some(state) => self.send_state(state),
+            None => {
+                self.test_input = None;
+                self.send(now)
+            }
+        }
+    }
+
+    /// Send the state from [Pad::transform] to the bus, unless that's the same as the last state
+    /// we sent or the pad is playing the test input
+    fn send(&mut self, now: Instant) -> Result<bool, Error> {
+        if self.test_input.is_some() {
+            return Ok(false);
+        }
+        let state = self.transform(now);
+        if self.last_state == Some(state) {
+            self.stats.skipped += 1;
+            return Ok(false);
+        }
+        self.send_state(state)
+    }
+
+    /// Send a state to the bus, whether or not it's the same as the last one, trying again a
+    /// few times if the bus is only briefly unable to take it
+    fn send_state(&mut self, state: X360State) -> Result<bool, Error> {
+        let started = Instant::now();
+        let mut backoff = UPDATE_RETRY_BACKOFF;
+        for _ in 0..UPDATE_RETRIES {
+            match self.target.update(state) {
+                Err(error) if is_transient(&error) => {
+                    sleep(backoff);
+                    backoff *= 2;
+                }
+                result => return self.sent(state, started, result),
+            }
+        }
+        let result = self.target.update(state);
+        self.sent(state, started, result)
+    }
+
+    /// Record how sending a state to the bus went
+    fn sent(
+        &mut self,
+        state: X360State,
+        started: Instant,
+        result: Result<(), Error>,
+    ) -> Result<bool, Error> {
+        result?;
+        self.latency.record_update(started.elapsed());
+        self.last_state = Some(state);
+        self.stats.sent += 1;
+        self.stats.failures = 0;
+        Ok(true)
+    }
+}
+
+/// Whether the bus may well take an update it refused with this error if asked again shortly,
+/// e.g. while it's enumerating its devices anew
+fn is_transient(error: &Error) -> bool {
+    matches!(error, Error::TargetNotPluggedIn | Error::BusAccessFailed)
+}
+
+/// Whether a pad whose update the bus refused with this error will never take one again, which
+/// has it discarded right away
+fn is_permanent(error: &Error) -> bool {
+    matches!(error, Error::InvalidTarget)
+}
+
+/// Read one of the counters shared with the websocket handlers
+fn load(counter: &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+}
+
+/// There's no room for another pad, which clients are told by closing their connection with
+/// [CloseReason::ServerFull](crate::close::CloseReason::ServerFull)
+#[derive(Debug)]
+pub(crate) struct ServerFull;
+
+impl std::fmt::Display for ServerFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("server full")
+    }
+}
+
+impl std::error::Error for ServerFull {}
+
+/// How many times to try an update again when the bus is briefly unable to take it, waiting
+/// [UPDATE_RETRY_BACKOFF] before the first retry and twice as long before each next one. This
+/// holds up every pad, so it's kept to a few milliseconds in all.
+const UPDATE_RETRIES: u32 = 3;
+const UPDATE_RETRY_BACKOFF: Duration = Duration::from_millis(1);
+
+/// How many updates in a row the bus can refuse a pad before it's discarded
+const MAX_FAILURES: u32 = 5;
+
+/// How long to wait for the bus to assign a new pad its player number before going on without it
+const USER_INDEX_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often to check for detached pads whose grace period is over
+const RECLAIM_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A request named a pad which isn't there anymore, e.g. a state put in for a pad which was
+/// released before it was taken out. It's logged rather than taking the other pads down.
+#[derive(Debug)]
+pub(crate) struct UnknownPad(pub(crate) usize);
+
+impl std::fmt::Display for UnknownPad {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no pad {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownPad {}
+
+/// How many milliseconds after the unix epoch the given time is, unless it's before it
+fn epoch_millis(at: SystemTime) -> Option<u128> {
+    at.duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|since| since.as_millis())
+}
+
+/// The pad with the given id, if it's still there
+fn pad_mut(pads: &mut Slab<Pad>, id: usize) -> Result<&mut Pad> {
+    pads.get_mut(id).ok_or_else(|| UnknownPad(id).into())
+}
+
+/// The pads plugged into the bus and everything that's done with them, which [PadManager::run]
+/// does as the websockets ask
+pub(crate) struct PadManager<'a, B: TargetFactory> {
+    logger: Logger,
+    args: &'a Args,
+    bus: B,
+    pads: Slab<Pad>,
+
+    /// The reserved pads nobody is using
+    free: BTreeSet<usize>,
+    recorder: Option<Recorder>,
+    profiles: Profiles,
+    metrics: &'a Metrics,
+    on_event: &'a dyn Fn(PadEvent),
+    started: Instant,
+
+    /// When each pad's latency percentiles were last logged
+    latency_logged: Instant,
+
+    /// When to send the pads' latest states to the bus, if not as soon as they come
+    ticker: Option<Ticker>,
+}
+
+impl<'a, B: TargetFactory> PadManager<'a, B> {
+    /// A manager without any pads yet, plugging them into `bus`
+    pub(crate) fn new(
+        logger: Logger,
+        args: &'a Args,
+        bus: B,
+        recorder: Option<Recorder>,
+        profiles: Profiles,
+        metrics: &'a Metrics,
+        on_event: &'a dyn Fn(PadEvent),
+    ) -> Self {
+        let started = Instant::now();
+        Self {
+            logger,
+            args,
+            bus,
+            pads: Slab::new(),
+            free: BTreeSet::new(),
+            recorder,
+            profiles,
+            metrics,
+            on_event,
+            started,
+            latency_logged: started,
+            ticker: Ticker::new(args.tick_rate, started),
+        }
+    }
+
+    /// Connect to the bus anew after it went away, recreating every pad so that their ids stay
+    /// valid
+    fn reconnect(&mut self) -> Result<()> {
+        self.bus.reconnect(&self.logger)?;
+        for (_, pad) in self.pads.iter_mut() {
+            pad.reconnect(&self.bus)?;
+        }
+        info!(self.logger, "bus.reconnected"; "pads" => self.pads.len());
+        Ok(())
+    }
+
+    /// Deal with the bus refusing to update a pad: reconnect if the bus went away, and otherwise
+    /// count it against the pad, which is discarded if it failed for good or more than
+    /// [MAX_FAILURES] times in a row, closing its connection with [CloseReason::PadFailed].
+    /// Only failing to reconnect is returned, so that one pad can't take the others down.
+    fn failed(&mut self, id: usize, error: Error) -> Result<()> {
+        self.metrics.error(&error);
+        if !self.bus.is_connected() {
+            error!(self.logger, "bus.lost"; "error" => %error);
+            return self.reconnect();
+        }
+        let pad = pad_mut(&mut self.pads, id)?;
+        pad.stats.errors += 1;
+        pad.stats.failures += 1;
+        let failures = pad.stats.failures;
+        warn!(self.logger, "pad.id.error"; "id" => id, "error" => %error, "failures" => failures, pad.client());
+        if is_permanent(&error) || failures > MAX_FAILURES {
+            pad.stats.failures = 0;
+            self.discard(id, CloseReason::PadFailed)?;
+        }
+        Ok(())
+    }
+
+    /// Deal with the bus refusing to update any number of pads at once, which stops at the
+    /// first which had the bus reconnect, as that makes every pad neutral anyway
+    fn all_failed(&mut self, failures: Vec<(usize, Error)>) -> Result<()> {
+        for (id, error) in failures {
+            let lost = !self.bus.is_connected();
+            self.failed(id, error)?;
+            if lost {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Create the pads which are kept around for clients to take, waiting for each to get its
+    /// player number so that they're numbered in order
+    pub(crate) fn reserve(&mut self, players: usize) -> Result<()> {
+        for _ in 0..players {
+            let mut pad = Pad::new(&self.bus, PadType::X360, self.args.pad_preset, channel().0)?;
+            pad.reserved = true;
+            self.number(&mut pad);
+            let player = pad.player().map(|player| player.number);
+            let id = self.pads.insert(pad);
+            let _ = self.free.insert(id);
+            info!(self.logger, "pad.id.reserve"; "id" => id, "player" => player);
+        }
+        Ok(())
+    }
+
+    /// Create the pads we had before restarting again, in the same order so that they get the
+    /// same player numbers. Those which had a client are detached, waiting for it to reclaim
+    /// them.
+    pub(crate) fn restore(&mut self, restored: Vec<PadSnapshot>) -> Result<()> {
+        for snapshot in restored {
+            let mut pad = Pad::new(
+                &self.bus,
+                snapshot.pad_type,
+                self.args.pad_preset,
+                channel().0,
+            )?;
+            pad.reserved = snapshot.reserved;
+            if snapshot.pad_type == PadType::X360 {
+                self.number(&mut pad);
+            }
+            let player = pad.player().map(|player| player.number);
+            let claimed = snapshot.client.is_some();
+            if let Some(client) = snapshot.client {
+                pad.reclaim = Token::from(client.reclaim);
+                pad.device = client.device;
+                pad.calibration = client.calibration.clamped();
+                pad.remap = client.remap;
+                pad.detached_at = Some(Instant::now());
+            }
+            let id = self.pads.insert(pad);
+            if !claimed {
+                let _ = self.free.insert(id);
+            }
+            info!(self.logger, "pad.id.restore"; "id" => id, "type" => ?snapshot.pad_type, "player" => player, "claimed" => claimed);
+        }
+        Ok(())
+    }
+
+    /// Give a client the reserved pad its device had last if nobody is using it, else the one
+    /// with the lowest player number which nobody is using, or a new pad if they're all taken
+    /// and we're allowed to make more. Reserved pads are all xbox 360 ones, so clients asking
+    /// for a dualshock 4 always get a new pad.
+    fn acquire(
+        &mut self,
+        connection: Connection,
+        device: Option<String>,
+        pad_type: PadType,
+    ) -> NewPadReply {
+        let slot = device
+            .as_deref()
+            .and_then(|device| self.profiles.get(device)?.slot)
+            .filter(|slot| self.free.contains(slot));
+        let free = slot
+            .or_else(|| self.free.iter().next().copied())
+            .filter(|_| pad_type == PadType::X360);
+        if let Some(id) = free {
+            let _ = self.free.remove(&id);
+            info!(self.logger, "pad.id.assign"; "id" => id, "slot" => slot.is_some(), &connection);
+            let new_pad = self.pads[id].assign(id, &self.profiles, connection, device);
+            self.remember_slot(&new_pad);
+            return Ok(new_pad);
+        }
+        if self.args.players > 0 && !self.args.dynamic_pads {
+            warn!(self.logger, "pad.full"; "players" => self.args.players, &connection);
+            return Err(ServerFull.into());
+        }
+        self.create_pad(connection, device, pad_type)
+    }
+
+    /// Create a new pad, unless there are already too many of them.
+    ///
+    /// Failing to make a pad, even because the bus went away and couldn't be reconnected to,
+    /// only concerns the client asking for it, so that's all reported in the reply.
+    pub(crate) fn create_pad(
+        &mut self,
+        connection: Connection,
+        device: Option<String>,
+        pad_type: PadType,
+    ) -> NewPadReply {
+        let max_pads = self.args.max_pads;
+        if self.pads.len() >= max_pads {
+            warn!(self.logger, "pad.full"; "max_pads" => max_pads, &connection);
+            return Err(ServerFull.into());
+        }
+        if pad_type == PadType::X360 && self.args.xinput_only && self.slots_full() {
+            warn!(self.logger, "pad.full"; "xinput_slots" => XINPUT_SLOTS, &connection);
+            return Err(ServerFull.into());
+        }
+
+        let pad = match Pad::new(&self.bus, pad_type, self.args.pad_preset, channel().0) {
+            Err(error) if !self.bus.is_connected() => {
+                error!(self.logger, "bus.lost"; "error" => %error);
+                if let Err(error) = self.reconnect() {
+                    error!(self.logger, "bus.reconnect.error"; "error" => %error);
+                    return Err(error);
+                }
+                Pad::new(&self.bus, pad_type, self.args.pad_preset, channel().0)
+            }
+            result => result,
+        };
+        match pad {
+            Ok(mut pad) => {
+                let bus_index = pad.target.index();
+                if pad_type == PadType::X360 {
+                    self.number(&mut pad);
+                }
+                let entry = self.pads.vacant_entry();
+                let id = entry.key();
+                let new_pad = entry
+                    .insert(pad)
+                    .assign(id, &self.profiles, connection, device);
+                info!(self.logger, "pad.id.request"; "id" => id, "bus_index" => bus_index, "type" => ?pad_type, "player" => new_pad.player.map(|player| player.number), self.pads[id].client());
+                Ok(new_pad)
+            }
+            Err(error) => {
+                error!(self.logger, "pad.id.error"; "error" => %error, &connection);
+                match error.downcast_ref::<Error>() {
+                    Some(Error::NoFreeSlot) => Err(ServerFull.into()),
+                    _ => Err(error),
+                }
+            }
+        }
+    }
+
+    /// Whether every XInput slot is taken by one of our pads, so that the bus won't give another
+    /// one a user index
+    fn slots_full(&self) -> bool {
+        let assigned = self
+            .pads
+            .iter()
+            .filter(|(_, pad)| matches!(pad.target.user_index(), Ok(UserIndex::Assigned(_))))
+            .count();
+        assigned >= XINPUT_SLOTS as usize
+    }
+
+    /// Wait for a new xbox 360 pad to get its player number from the bus, unless there are no
+    /// XInput slots left for it, in which case it's numbered past them with the lowest number
+    /// no other pad has. Games only see such pads through something other than XInput, which
+    /// is warned about.
+    fn number(&self, pad: &mut Pad) {
+        let bus_index = pad.target.index();
+        if !self.slots_full() {
+            if let Err(error) = pad.target.wait_for_user_index(USER_INDEX_TIMEOUT) {
+                warn!(self.logger, "pad.id.player"; "bus_index" => bus_index, "error" => %error);
+            }
+            return;
+        }
+        let taken: BTreeSet<u32> = self
+            .pads
+            .iter()
+            .filter_map(|(_, pad)| pad.overflow)
+            .collect();
+        let number = (XINPUT_SLOTS + 1..)
+            .find(|number| !taken.contains(number))
+            .unwrap();
+        pad.overflow = Some(number);
+        warn!(self.logger, "pad.id.no_slot"; "bus_index" => bus_index, "player" => number);
+    }
+
+    /// Remember which reserved pad a device was given, to give it the same one next time. New
+    /// pads are removed once they're let go of, so which of those it had doesn't matter.
+    fn remember_slot(&mut self, pad: &NewPad) {
+        if let Some(device) = &pad.device {
+            let id = pad.id;
+            if let Err(error) = self
+                .profiles
+                .update(device, |profile| profile.slot = Some(id))
+            {
+                error!(self.logger, "profiles.error"; "error" => %error);
+            }
+        }
+    }
+
+    /// Remember something of a device's in its profile, which not being able to save is no
+    /// reason to stop over
+    fn remember(&mut self, device: &str, change: ProfileChange) {
+        if let Err(error) = self
+            .profiles
+            .update(device, |profile| profile.apply(change))
+        {
+            error!(self.logger, "profiles.error"; "error" => %error);
+        }
+    }
+
+    /// Give a detached pad back to the client presenting its reclaim token on a new connection,
+    /// if there is such a pad
+    fn reclaim(&mut self, token: &str, connection: &Connection) -> Option<NewPad> {
+        let (id, pad) = self
+            .pads
+            .iter_mut()
+            .find(|(_, pad)| pad.detached_at.is_some() && pad.reclaim.matches(token))?;
+        let (feedback_tx, feedback) = channel();
+        pad.feedback.replace(feedback_tx);
+        pad.detached_at = None;
+        // The old connection is logged too, to follow the client from one to the other
+        let previous = pad.connection.replace(connection.clone());
+        info!(self.logger, "pad.id.reclaim"; "id" => id, "previous_conn" => previous.map(|previous| previous.id), connection);
+        Some(NewPad {
+            id,
+            feedback,
+            reclaim: pad.reclaim.to_string(),
+            player: pad.player(),
+            device: pad.device.clone(),
+            profile: None,
+            pad_type: pad.target.pad_type(),
+            throttled: Arc::clone(&pad.throttled),
+            latest: Arc::clone(&pad.latest),
+            latency: Arc::clone(&pad.latency),
+        })
+    }
+
+    /// Hand a pad to whoever asked for it, detaching it if they stopped waiting so that it's
+    /// eventually removed like any other abandoned pad
+    fn send_reply(&mut self, reply_tx: &Sender<NewPadReply>, reply: NewPadReply) {
+        let connected = reply.as_ref().ok().and_then(|pad| {
+            let connection = self.pads[pad.id].connection.as_ref()?;
+            Some(PadEvent::Connected {
+                id: pad.id,
+                pad_type: pad.pad_type,
+                player: pad.player.map(|player| player.number),
+                ip: connection.ip,
+            })
+        });
+        match reply_tx.send(reply) {
+            Ok(()) => connected.into_iter().for_each(self.on_event),
+            Err(SendError(Ok(pad))) => {
+                info!(self.logger, "pad.id.detach"; "id" => pad.id, "reason" => "abandoned", self.pads[pad.id].client());
+                self.pads[pad.id].detached_at = Some(Instant::now());
+            }
+            Err(SendError(Err(_))) => {}
+        }
+    }
+
+    /// Release a pad whose client is done with it, resetting it and putting it back on the free
+    /// list if it's reserved and removing it otherwise
+    fn release(&mut self, id: usize) -> Result<()> {
+        let pad = pad_mut(&mut self.pads, id)?;
+        info!(self.logger, "pad.id.release"; "id" => id, "sent" => pad.stats.sent, "skipped" => pad.stats.skipped, "coalesced" => load(&pad.throttled.coalesced), "dropped" => load(&pad.throttled.dropped), pad.client());
+        if !pad.reserved {
+            let _ = self.pads.remove(id);
+            return Ok(());
+        }
+
+        // If the bus went away the pad is neutral anyway once it's reconnected
+        let connection = pad.connection.clone();
+        if let Err(error) = pad.reset() {
+            warn!(self.logger, "pad.id.reset"; "id" => id, "error" => %error, PadClient(connection.as_ref()));
+        }
+        let _ = self.free.insert(id);
+        Ok(())
+    }
+
+    /// Take a pad away from its client. Pads whose client is gone are released right away, while
+    /// those whose client is still connected are reset and discarded, for the client's
+    /// connection to release them and close with the given reason once it notices, throwing
+    /// away whatever it sends meanwhile.
+    ///
+    /// Discarding a pad again before its connection lets go of it changes nothing, while
+    /// discarding one which is gone fails with [UnknownPad].
+    pub(crate) fn discard(&mut self, id: usize, reason: CloseReason) -> Result<()> {
+        // The pad may well have been released since whoever asked saw it
+        if self.free.contains(&id) {
+            return Ok(());
+        }
+        let pad = pad_mut(&mut self.pads, id)?;
+        if pad.latest.is_discarded() {
+            debug!(self.logger, "pad.id.discard"; "id" => id, "already" => true);
+            return Ok(());
+        }
+        info!(self.logger, "pad.id.discard"; "id" => id, "reason" => %reason, "detached" => pad.detached_at.is_some(), pad.client());
+        if pad.detached_at.is_some() {
+            return self.release(id);
+        }
+
+        pad.latest.discard(reason);
+        let connection = pad.connection.clone();
+        if let Err(error) = pad.reset() {
+            warn!(self.logger, "pad.id.reset"; "id" => id, "error" => %error, PadClient(connection.as_ref()));
+        }
+        Ok(())
+    }
+
+    /// Send a pad the state its client sent at `received`, recording it if we're recording,
+    /// unless the pad is suspended
+    pub(crate) fn update(&mut self, id: usize, state: X360State, received: Instant) -> Result<()> {
+        let pad = pad_mut(&mut self.pads, id)?;
+        pad.latency.record_queue(received.elapsed());
+        if pad.suspended {
+            // Whatever a phone in a pocket presses is no input, but is still what it holds
+            trace!(self.logger, "pad.update.suspended"; "id" => id, "change" => %pad.received.diff(&state), pad.client());
+            pad.last_change = pad.received.diff(&state);
+            pad.received = state;
+            return Ok(());
+        }
+        trace!(self.logger, "pad.update"; "id" => id, "change" => %pad.received.diff(&state), pad.client());
+        if pad.test_input.is_some() {
+            info!(self.logger, "pad.id.test_input.cancel"; "id" => id, pad.client());
+        }
+        if let Some(Err(error)) = self
+            .recorder
+            .as_mut()
+            .map(|recorder| recorder.record(id, state))
+        {
+            // Losing the recording is no reason to take the pads down with it
+            error!(self.logger, "record.error"; "error" => %error);
+            self.recorder = None;
+        }
+        let pad = &mut self.pads[id];
+        match pad.update(state, self.args.socd) {
+            Ok(true) => self.metrics.updated(id, received.elapsed()),
+            Ok(false) => trace!(self.logger, "pad.update.skip"; "id" => id, pad.client()),
+            Err(error) => self.failed(id, error)?,
+        }
+        Ok(())
+    }
+
+    /// Do whatever is due at `now` without a request asking for it: logging latencies, releasing
+    /// the pads whose clients didn't come back in time, making idle pads neutral, pulsing turbo
+    /// buttons, ramping triggers, moving on with macros and the test input and sending the
+    /// states which waited for the tick
+    pub(crate) fn tick(&mut self, now: Instant) -> Result<()> {
+        self.log_latency(now);
+        self.sweep_detached(now);
+        self.neutralize_idle(now)?;
+        self.pulse_turbo(now)?;
+        self.step_macros(now)?;
+        self.play_test_input(now)?;
+        self.send_ticked(now)
+    }
+
+    /// Send every pad's latest state to the bus if a tick is due, which is all of them that came
+    /// since the last tick but the latest thrown away, as [LatestState] only keeps that one
+    fn send_ticked(&mut self, now: Instant) -> Result<()> {
+        if !self.ticker.as_mut().is_some_and(|ticker| ticker.due(now)) {
+            return Ok(());
+        }
+        let pending: Vec<_> = self
+            .pads
+            .iter()
+            .filter_map(|(id, pad)| {
+                pad.latest
+                    .take()
+                    .map(|(state, received)| (id, state, received))
+            })
+            .collect();
+        for (id, state, received) in pending {
+            self.update(id, state, received)?;
+        }
+        Ok(())
+    }
+
+    /// When [tick](Self::tick) next has something to do, if it's before the next sweep for
+    /// detached pads
+    fn next_due(&self, now: Instant) -> Option<Instant> {
+        let pulse = self
+            .pads
+            .iter()
+            .filter_map(|(_, pad)| pad.turbo.next_toggle(now));
+        let transformers = self
+            .pads
+            .iter()
+            .filter_map(|(_, pad)| pad.transformers.next_due(now));
+        let neutral = self
+            .pads
+            .iter()
+            .filter_map(|(_, pad)| pad.neutral_at(self.args.neutral_after));
+        let playing = self
+            .pads
+            .iter()
+            .filter_map(|(_, pad)| pad.playing.as_ref().map(|playing| playing.next_step(now)));
+        let test_input = self
+            .pads
+            .iter()
+            .filter_map(|(_, pad)| pad.test_input.map(|test_input| test_input.next_step(now)));
+        let tick = self.ticker.as_ref().map(Ticker::next);
+        pulse
+            .min()
+            .into_iter()
+            .chain(transformers.min())
+            .chain(neutral.min())
+            .chain(playing.min())
+            .chain(test_input.min())
+            .chain(tick)
+            .min()
+    }
+
+    /// Make the pads which went without a state for too long neutral, so that a client which
+    /// froze mid-press doesn't keep its buttons held forever
+    fn neutralize_idle(&mut self, now: Instant) -> Result<()> {
+        let neutral_after = self.args.neutral_after;
+        let idle: Vec<_> = self
+            .pads
+            .iter()
+            .filter(|(_, pad)| matches!(pad.neutral_at(neutral_after), Some(at) if at <= now))
+            .map(|(id, _)| id)
+            .collect();
+        let mut failures = Vec::new();
+        for id in idle {
+            let pad = &mut self.pads[id];
+            let idle_ms = pad.last_update.map(|at| now.duration_since(at).as_millis());
+            info!(self.logger, "pad.neutralized"; "id" => id, "idle_ms" => idle_ms, pad.client());
+            if let Err(error) = pad.neutralize() {
+                failures.push((id, error));
+            }
+        }
+        self.all_failed(failures)
+    }
+
+    /// Press or release the turbo buttons which are due to be, and send the states the pads'
+    /// transformers are due to make something else of
+    fn pulse_turbo(&mut self, now: Instant) -> Result<()> {
+        let failures = self
+            .pads
+            .iter_mut()
+            .filter_map(|(id, pad)| pad.pulse(now).err().map(|error| (id, error)))
+            .collect();
+        self.all_failed(failures)
+    }
+
+    /// Send the states the pads playing macros are at, or what their clients hold once the
+    /// macros are over
+    fn step_macros(&mut self, now: Instant) -> Result<()> {
+        let playing: Vec<_> = self
+            .pads
+            .iter()
+            .filter(|(_, pad)| pad.playing.is_some())
+            .map(|(id, _)| id)
+            .collect();
+        let mut failures = Vec::new();
+        for id in playing {
+            let pad = &mut self.pads[id];
+            if let Err(error) = pad.step_macro(now) {
+                failures.push((id, error));
+            } else if pad.playing.is_none() {
+                debug!(self.logger, "pad.id.macro.done"; "id" => id, pad.client());
+            }
+        }
+        self.all_failed(failures)
+    }
+
+    /// Send the states the pads playing the test input are at
+    fn play_test_input(&mut self, now: Instant) -> Result<()> {
+        let playing: Vec<_> = self
+            .pads
+            .iter()
+            .filter(|(_, pad)| pad.test_input.is_some())
+            .map(|(id, _)| id)
+            .collect();
+        let mut failures = Vec::new();
+        for id in playing {
+            let pad = &mut self.pads[id];
+            let result = pad.play_test_input(now);
+            if pad.test_input.is_none() {
+                info!(self.logger, "pad.id.test_input.done"; "id" => id, pad.client());
+            }
+            if let Err(error) = result {
+                failures.push((id, error));
+            }
+        }
+        self.all_failed(failures)
+    }
+
+    /// Release the pads whose clients didn't come back for them within the grace period
+    fn sweep_detached(&mut self, now: Instant) {
+        let grace = self.args.reclaim_grace;
+        let expired: Vec<_> = self
+            .pads
+            .iter()
+            .filter(|(_, pad)| {
+                let detached_for = pad.detached_at.map(|at| now.saturating_duration_since(at));
+                matches!(detached_for, Some(detached_for) if detached_for >= grace)
+            })
+            .map(|(id, _)| id)
+            .collect();
+        for id in expired {
+            let _ = self.release(id);
+        }
+    }
+
+    /// Log each pad's latency percentiles if it's been long enough since they were last logged,
+    /// for the pads which got new samples since
+    fn log_latency(&mut self, now: Instant) {
+        let interval = self.args.latency_log;
+        if interval.is_zero() || now.saturating_duration_since(self.latency_logged) < interval {
+            return;
+        }
+        self.latency_logged = now;
+        for (id, pad) in self.pads.iter_mut() {
+            let recorded = pad.latency.recorded();
+            if recorded == pad.latency_logged {
+                continue;
+            }
+            pad.latency_logged = recorded;
+            let summary = pad.latency.summary();
+            let show =
+                |percentiles: Option<latency::Percentiles>| percentiles.map(|p| p.to_string());
+            info!(self.logger, "pad.id.latency"; "id" => id, "network" => show(summary.network), "queue" => show(summary.queue), "update" => show(summary.update), pad.client());
+        }
+    }
+
+    /// How many pads a connected client is using
+    fn active(&self) -> usize {
+        self.pads
+            .iter()
+            .filter(|(id, pad)| !self.free.contains(id) && pad.detached_at.is_none())
+            .count()
+    }
+
+    /// Handle requests until told to shut down, or until the requests stop coming because the
+    /// server is gone. Requests for pads which aren't there anymore are logged and skipped.
+    pub(crate) fn run(&mut self, req_rx: Receiver<PadRequest>) -> Result<()> {
+        loop {
+            self.metrics.set_active_pads(self.active());
+
+            // Turbo buttons have to be pulsed on time and idle pads made neutral on time even if
+            // no requests come in meanwhile
+            let timeout = self
+                .next_due(Instant::now())
+                .map_or(RECLAIM_SWEEP_INTERVAL, |at| {
+                    at.saturating_duration_since(Instant::now())
+                        .min(RECLAIM_SWEEP_INTERVAL)
+                });
+            let request = match req_rx.recv_timeout(timeout) {
+                Ok(request) => request,
+                Err(RecvTimeoutError::Timeout) => {
+                    self.tick(Instant::now())?;
+                    continue;
+                }
+                Err(error) => return Err(error.into()),
+            };
+            self.tick(Instant::now())?;
+
+            let shutdown = matches!(request, PadRequest::Shutdown);
+            match self.handle(request) {
+                Ok(()) => {}
+                Err(error) => match error.downcast_ref::<UnknownPad>() {
+                    Some(&UnknownPad(id)) => warn!(self.logger, "pad.id.unknown"; "id" => id),
+                    None => return Err(error),
+                },
+            }
+            if shutdown {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Handle a single request, which is all there is to do for it unless it's
+    /// [PadRequest::Shutdown], after which [run](Self::run) returns
+    fn handle(&mut self, request: PadRequest) -> Result<()> {
+        match request {
+            PadRequest::Acquire(connection, device, pad_type, reply_tx) => {
+                let reply = self.acquire(connection, device, pad_type);
+                self.send_reply(&reply_tx, reply);
+            }
+
+            PadRequest::Reclaim(token, connection, device, pad_type, reply_tx) => {
+                let reply = match self.reclaim(&token, &connection) {
+                    Some(pad) => Ok(pad),
+                    // The pad is gone, so the next best thing is another one
+                    None => self.acquire(connection, device, pad_type),
+                };
+                self.send_reply(&reply_tx, reply);
+            }
+
+            PadRequest::Detach(id) => {
+                let pad = pad_mut(&mut self.pads, id)?;
+                info!(self.logger, "pad.id.detach"; "id" => id, pad.client());
+                pad.detached_at = Some(Instant::now());
+                (self.on_event)(PadEvent::Disconnected { id });
+            }
+
+            PadRequest::Release(id) => {
+                // Clients which lost their connection were already said to be gone
+                if pad_mut(&mut self.pads, id)?.detached_at.is_none() {
+                    (self.on_event)(PadEvent::Disconnected { id });
+                }
+                self.release(id)?;
+            }
+
+            PadRequest::Discard(id) => self.discard(id, CloseReason::Kicked)?,
+
+            PadRequest::Kick(id, reply_tx) => {
+                let kicked = !self.free.contains(&id)
+                    && self
+                        .pads
+                        .get(id)
+                        .is_some_and(|pad| pad.connection.is_some());
+                if kicked {
+                    self.discard(id, CloseReason::Kicked)?;
+                }
+                let _ = reply_tx.send(kicked);
+            }
+
+            PadRequest::TestInput(id, reply_tx) => {
+                let now = Instant::now();
+                let reply = match self.pads.get_mut(id) {
+                    Some(_) if self.free.contains(&id) => TestInputReply::NoSuchPad,
+                    None => TestInputReply::NoSuchPad,
+                    Some(pad) if pad.receiving(now) => TestInputReply::Busy,
+                    Some(pad) => {
+                        info!(self.logger, "pad.id.test_input"; "id" => id, pad.client());
+                        pad.test_input = Some(TestInput::new(now));
+                        TestInputReply::Started
+                    }
+                };
+                let _ = reply_tx.send(reply);
+                self.play_test_input(now)?;
+            }
+
+            PadRequest::Clients(reply_tx) => {
+                let clients = self
+                    .pads
+                    .iter()
+                    .filter(|(id, _)| !self.free.contains(id))
+                    .filter_map(|(id, pad)| pad.client_status(id))
+                    .collect();
+                let _ = reply_tx.send(clients);
+            }
+
+            PadRequest::Status(reply_tx) => {
+                let free = &self.free;
+                let status = Status {
+                    uptime_secs: self.started.elapsed().as_secs_f64(),
+                    bus_connected: self.bus.is_connected(),
+                    pads: self
+                        .pads
+                        .iter_mut()
+                        .map(|(id, pad)| pad.status(id, free.contains(&id)))
+                        .collect(),
+                    tick: self.ticker.as_ref().map(Ticker::status),
+                };
+                // The server may have given up on waiting for us, which is fine
+                let _ = reply_tx.send(status);
+            }
+
+            PadRequest::Snapshot(reply_tx) => {
+                let snapshot = self
+                    .pads
+                    .iter()
+                    .map(|(id, pad)| pad.snapshot(self.free.contains(&id)))
+                    .collect();
+                let _ = reply_tx.send(snapshot);
+            }
+
+            PadRequest::Calibrate(id, calibration) => {
+                let pad = pad_mut(&mut self.pads, id)?;
+                pad.calibration = calibration.clamped();
+                info!(self.logger, "pad.id.calibrate"; "id" => id, "calibration" => ?pad.calibration, pad.client());
+                if let Some(device) = &pad.device {
+                    let calibration = pad.calibration;
+                    // Not being able to save it is no reason to stop using it
+                    if let Err(error) = self
+                        .profiles
+                        .update(device, |profile| profile.calibration = Some(calibration))
+                    {
+                        error!(self.logger, "profiles.error"; "error" => %error);
+                    }
+                }
+            }
+
+            PadRequest::Turbo(id, config) => {
+                let config = config.clamped();
+                let pad = pad_mut(&mut self.pads, id)?;
+                info!(self.logger, "pad.id.turbo"; "id" => id, "turbo" => ?config, pad.client());
+                if let Err(error) = pad.set_turbo(config) {
+                    self.failed(id, error)?;
+                }
+            }
+
+            PadRequest::Record(id, recording) => {
+                let pad = pad_mut(&mut self.pads, id)?;
+                info!(self.logger, "pad.id.record"; "id" => id, "recording" => recording, pad.client());
+                pad.record(recording, Instant::now());
+            }
+
+            PadRequest::SaveMacro(id, slot) if slot >= MACRO_SLOTS => {
+                warn!(self.logger, "pad.id.macro.no_slot"; "id" => id, "slot" => slot);
+            }
+
+            PadRequest::SaveMacro(id, slot) => {
+                let pad = pad_mut(&mut self.pads, id)?;
+                if !pad.save_macro(slot, Instant::now()) {
+                    warn!(self.logger, "pad.id.macro.unrecorded"; "id" => id, "slot" => slot, pad.client());
+                    return Ok(());
+                }
+                info!(self.logger, "pad.id.macro.save"; "id" => id, "slot" => slot, pad.client());
+                if let Some(device) = &pad.device {
+                    let macros = pad.macros.clone();
+                    // Not being able to save it is no reason to stop using it
+                    if let Err(error) = self
+                        .profiles
+                        .update(device, |profile| profile.macros = macros)
+                    {
+                        error!(self.logger, "profiles.error"; "error" => %error);
+                    }
+                }
+            }
+
+            PadRequest::PlayMacro(id, slot) => {
+                let pad = pad_mut(&mut self.pads, id)?;
+                match pad.play_macro(slot, Instant::now()) {
+                    Ok((true, _)) => {
+                        debug!(self.logger, "pad.id.macro.play"; "id" => id, "slot" => slot, pad.client())
+                    }
+                    Ok((false, _)) => {
+                        warn!(self.logger, "pad.id.macro.no_macro"; "id" => id, "slot" => slot, pad.client())
+                    }
+                    Err(error) => self.failed(id, error)?,
+                }
+            }
+
+            PadRequest::Remap(id, remap) => {
+                let pad = pad_mut(&mut self.pads, id)?;
+                info!(self.logger, "pad.id.remap"; "id" => id, "remap" => ?remap, pad.client());
+                if let Err(error) = pad.set_remap(remap) {
+                    self.failed(id, error)?;
+                }
+            }
+
+            PadRequest::Remember(device, change) => {
+                debug!(self.logger, "profiles.remember"; "device" => &device, "change" => ?change);
+                self.remember(&device, change);
+            }
+
+            PadRequest::Motion(id, orientation) => {
+                let pad = pad_mut(&mut self.pads, id)?;
+                trace!(self.logger, "pad.motion"; "id" => id, "orientation" => ?orientation, pad.client());
+                if let Err(error) = pad.orient(orientation) {
+                    self.failed(id, error)?;
+                }
+            }
+
+            PadRequest::MotionConfig(id, config) => {
+                let config = config.clamped();
+                let pad = pad_mut(&mut self.pads, id)?;
+                info!(self.logger, "pad.id.motion"; "id" => id, "motion" => ?config, pad.client());
+                pad.motion.configure(config);
+            }
+
+            PadRequest::Suspend(id) => {
+                let pad = pad_mut(&mut self.pads, id)?;
+                if !pad.suspended {
+                    info!(self.logger, "pad.id.suspend"; "id" => id, pad.client());
+                    pad.suspended = true;
+                    // A state still waiting for the tick may well be the pocket's doing already
+                    let _ = pad.latest.take();
+                    if let Err(error) = pad.neutralize() {
+                        self.failed(id, error)?;
+                    }
+                }
+            }
+
+            PadRequest::Resume(id) => {
+                let pad = pad_mut(&mut self.pads, id)?;
+                if pad.suspended {
+                    info!(self.logger, "pad.id.resume"; "id" => id, pad.client());
+                }
+                pad.suspended = false;
+            }
+
+            PadRequest::Neutral(id, neutral_after) => {
+                let pad = pad_mut(&mut self.pads, id)?;
+                pad.neutral_after = neutral_after;
+                info!(self.logger, "pad.id.neutral_after"; "id" => id, "neutral_after_ms" => neutral_after.map(|after| after.as_millis()), pad.client());
+            }
+
+            PadRequest::Socd(id, socd) => {
+                let server_policy = self.args.socd;
+                let pad = pad_mut(&mut self.pads, id)?;
+                if pad.socd != socd {
+                    info!(self.logger, "pad.id.socd"; "id" => id, "policy" => ?socd, "server_policy" => ?server_policy, pad.client());
+                }
+                pad.socd = socd;
+            }
+
+            PadRequest::Pipeline(id, edit) => {
+                match pad_mut(&mut self.pads, id)?.edit_pipeline(edit) {
+                    Ok((edited, _)) => {
+                        let pad = &self.pads[id];
+                        let len = pad.transformers.len();
+                        if edited {
+                            info!(self.logger, "pad.id.pipeline"; "id" => id, "transformers" => len, pad.client());
+                        } else {
+                            warn!(self.logger, "pad.id.pipeline"; "id" => id, "error" => "no such position", "transformers" => len, pad.client());
+                        }
+                    }
+                    Err(error) => self.failed(id, error)?,
+                }
+            }
+
+            // With a tick the state waits for it, and states sent until then replace it
+            PadRequest::Update(_) if self.ticker.is_some() => {}
+
+            PadRequest::Update(id) => {
+                // The state is thrown away if the pad changed hands since it was put in
+                if let Some((state, received)) = pad_mut(&mut self.pads, id)?.latest.take() {
+                    self.update(id, state, received)?;
+                }
+            }
+
+            PadRequest::Shutdown => {
+                let count = self.pads.len();
+                // Dropping the pads removes them from the bus
+                self.pads.clear();
+                info!(self.logger, "shutdown.complete"; "pads" => count);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Handle pad requests until told to shut down, starting out with the pads we had before
+/// restarting if there are any to restore. Nothing is handled until we're connected to the bus,
+/// with `driver_missing` set for as long as ViGEmBus is missing.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn handle_pads(
+    logger: Logger,
+    args: &Args,
+    recorder: Option<Recorder>,
+    profiles: Profiles,
+    restored: Vec<PadSnapshot>,
+    req_rx: Receiver<PadRequest>,
+    metrics: &Metrics,
+    driver_missing: &AtomicBool,
+    on_event: &dyn Fn(PadEvent),
+) -> Result<()> {
+    let client = wait_for_bus(&logger, driver_missing, DRIVER_POLL_INTERVAL, Client::new)?;
+    let mut manager = PadManager::new(logger, args, client, recorder, profiles, metrics, on_event);
+    let already_reserved = restored.iter().filter(|pad| pad.reserved).count();
+    manager.restore(restored)?;
+    manager.reserve(args.players.saturating_sub(already_reserved))?;
+    manager.run(req_rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        fs,
+        net::Ipv4Addr,
+        sync::{
+            atomic::AtomicUsize,
+            mpsc::{sync_channel, SyncSender},
+        },
+        thread::{spawn, JoinHandle},
+    };
+
+    use slog::{o, Discard};
+    use vigem_client_c::{StickPosition, X360Buttons};
+
+    use super::*;
+    use crate::{
+        macros::MacroStep,
+        ramp::{DigitalTriggers, DigitalTriggersConfig, Ramp},
+    };
+
+    /// Spawn a thread handling pad requests with the given arguments
+    fn spawn_pads(args: Args) -> (SyncSender<PadRequest>, JoinHandle<Result<()>>) {
+        restore(args, Vec::new())
+    }
+
+    /// Profiles which don't have any device's, and are saved somewhere of their own which
+    /// doesn't matter
+    fn no_profiles() -> Profiles {
+        static MANAGERS: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "sphrosyne-no-profiles-{}-{}.toml",
+            std::process::id(),
+            MANAGERS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_file(&path);
+        Profiles::load(&Logger::root(Discard, o!()), path)
+    }
+
+    /// Like [spawn_pads], restoring the given pads from a snapshot
+    fn restore(
+        args: Args,
+        restored: Vec<PadSnapshot>,
+    ) -> (SyncSender<PadRequest>, JoinHandle<Result<()>>) {
+        let (req_tx, req_rx) = sync_channel(crate::request::QUEUE_SIZE);
+        let pads = spawn(move || {
+            let logger = Logger::root(Discard, o!());
+            handle_pads(
+                logger,
+                &args,
+                None,
+                no_profiles(),
+                restored,
+                req_rx,
+                &Metrics::default(),
+                &AtomicBool::new(false),
+                &|_| {},
+            )
+        });
+        (req_tx, pads)
+    }
+
+    fn connection() -> Connection {
+        Connection::new(Ipv4Addr::LOCALHOST.into())
+    }
+
+    fn acquire_typed(req_tx: &SyncSender<PadRequest>, pad_type: PadType) -> NewPadReply {
+        let (reply_tx, reply_rx) = channel();
+        req_tx
+            .send(PadRequest::Acquire(connection(), None, pad_type, reply_tx))
+            .unwrap();
+        reply_rx.recv().unwrap()
+    }
+
+    fn acquire(req_tx: &SyncSender<PadRequest>) -> NewPadReply {
+        acquire_typed(req_tx, PadType::X360)
+    }
+
+    #[test]
+    fn test_wait_for_bus() {
+        let logger = Logger::root(Discard, o!());
+        let driver_missing = AtomicBool::new(false);
+
+        // The driver shows up on the third attempt, having been missing until then
+        let mut attempts = 0;
+        let client = wait_for_bus(&logger, &driver_missing, Duration::ZERO, || {
+            attempts += 1;
+            match attempts {
+                1 => Err(Error::BusNotFound),
+                2 => {
+                    assert!(driver_missing.load(Ordering::SeqCst));
+                    Err(Error::BusAccessFailed)
+                }
+                _ => Client::new(),
+            }
+        });
+        assert!(client.unwrap().is_connected());
+        assert_eq!(attempts, 3);
+        assert!(!driver_missing.load(Ordering::SeqCst));
+
+        // Anything else is no reason to wait
+        let result = wait_for_bus(&logger, &driver_missing, Duration::ZERO, || {
+            Err(Error::BusVersionMismatch)
+        });
+        assert!(result.is_err());
+        assert!(!driver_missing.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_concurrent_new_ids() {
+        const REQUESTS: usize = 16;
+
+        let (req_tx, pads) = spawn_pads(Args {
+            max_pads: REQUESTS,
+            ..Args::default()
+        });
+
+        // Every requester tags its request with its own device, which it has to get back
+        let requesters: Vec<_> = (0..REQUESTS)
+            .map(|i| {
+                let req_tx = req_tx.clone();
+                spawn(move || {
+                    let device = format!("device-{}", i);
+                    let (reply_tx, reply_rx) = channel();
+                    req_tx
+                        .send(PadRequest::Acquire(
+                            connection(),
+                            Some(device.clone()),
+                            PadType::X360,
+                            reply_tx,
+                        ))
+                        .unwrap();
+                    let pad = reply_rx.recv().unwrap().unwrap();
+                    assert_eq!(pad.device, Some(device));
+                    pad.id
+                })
+            })
+            .collect();
+        let ids: HashSet<_> = requesters
+            .into_iter()
+            .map(|requester| requester.join().unwrap())
+            .collect();
+        assert_eq!(ids.len(), REQUESTS);
+
+        // Nobody can send requests anymore, which stops handle_pads
+        drop(req_tx);
+        assert!(pads.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_reserved_pads() {
+        let (req_tx, pads) = spawn_pads(Args {
+            players: 2,
+            dynamic_pads: false,
+            ..Args::default()
+        });
+
+        let first = acquire(&req_tx).unwrap();
+        let second = acquire(&req_tx).unwrap();
+        assert_eq!((first.id, second.id), (0, 1));
+        match acquire(&req_tx) {
+            Err(reason) => assert!(reason.is::<ServerFull>(), "{}", reason),
+            Ok(pad) => panic!("got pad {} with every reserved pad taken", pad.id),
+        }
+
+        // Released pads stay around, and go to the next client in order
+        req_tx.send(PadRequest::Release(first.id)).unwrap();
+        let (reply_tx, reply_rx) = channel();
+        req_tx.send(PadRequest::Status(reply_tx)).unwrap();
+        let status = reply_rx.recv().unwrap();
+        assert_eq!(status.pads.len(), 2);
+        assert!(status.pads[0].free && !status.pads[1].free);
+        assert_eq!(acquire(&req_tx).unwrap().id, first.id);
+
+        drop(req_tx);
+        assert!(pads.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_reserved_pads_profile() {
+        let (req_tx, pads) = spawn_pads(Args {
+            players: 3,
+            dynamic_pads: false,
+            ..Args::default()
+        });
+        let acquire_device = |device: &str| {
+            let (reply_tx, reply_rx) = channel();
+            let device = Some(device.to_string());
+            req_tx
+                .send(PadRequest::Acquire(
+                    connection(),
+                    device,
+                    PadType::X360,
+                    reply_tx,
+                ))
+                .unwrap();
+            reply_rx.recv().unwrap().unwrap()
+        };
+
+        // A device we haven't seen gets the first free pad, and nothing else
+        let first = acquire_device("phone");
+        assert_eq!((first.id, first.profile), (0, None));
+        let calibration = Calibration {
+            deadzone: 20.0,
+            ..Calibration::default()
+        };
+        req_tx
+            .send(PadRequest::Calibrate(first.id, calibration))
+            .unwrap();
+        let layout = ProfileChange::Layout("racing".to_string());
+        req_tx
+            .send(PadRequest::Remember("phone".to_string(), layout))
+            .unwrap();
+        req_tx.send(PadRequest::Release(first.id)).unwrap();
+
+        // Once its pad is taken it gets another, which it's given back from then on even when
+        // there's one with a lower player number
+        let other = acquire(&req_tx).unwrap();
+        let second = acquire_device("phone");
+        assert_eq!((other.id, second.id), (0, 1));
+        req_tx.send(PadRequest::Release(other.id)).unwrap();
+        req_tx.send(PadRequest::Release(second.id)).unwrap();
+        let third = acquire_device("phone");
+        assert_eq!(third.id, 1);
+        let profile = third.profile.unwrap();
+        assert_eq!(profile.calibration, Some(calibration));
+        assert_eq!(profile.layout.as_deref(), Some("racing"));
+        assert_eq!(acquire(&req_tx).unwrap().id, 0);
+
+        drop(req_tx);
+        assert!(pads.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_reserved_pads_fall_back() {
+        let (req_tx, pads) = spawn_pads(Args {
+            players: 1,
+            ..Args::default()
+        });
+
+        let reserved = acquire(&req_tx).unwrap();
+        let created = acquire(&req_tx).unwrap();
+        assert_eq!((reserved.id, created.id), (0, 1));
+
+        // Pads created on the fly are removed once released, unlike reserved ones
+        req_tx.send(PadRequest::Release(created.id)).unwrap();
+        req_tx.send(PadRequest::Release(reserved.id)).unwrap();
+        let (reply_tx, reply_rx) = channel();
+        req_tx.send(PadRequest::Status(reply_tx)).unwrap();
+        let status = reply_rx.recv().unwrap();
+        assert_eq!(status.pads.len(), 1);
+        assert!(status.pads[0].free);
+
+        drop(req_tx);
+        assert!(pads.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_ds4_pads() {
+        let (req_tx, pads) = spawn_pads(Args {
+            players: 1,
+            ..Args::default()
+        });
+
+        // The reserved pad is an xbox 360 one, so it's left for the next client asking for that
+        let ds4 = acquire_typed(&req_tx, PadType::DS4).unwrap();
+        assert_eq!((ds4.id, ds4.pad_type, ds4.player), (1, PadType::DS4, None));
+        let x360 = acquire(&req_tx).unwrap();
+        assert_eq!((x360.id, x360.pad_type), (0, PadType::X360));
+
+        let (reply_tx, reply_rx) = channel();
+        req_tx.send(PadRequest::Status(reply_tx)).unwrap();
+        let status = reply_rx.recv().unwrap();
+        assert_eq!(status.pads[1].pad_type, PadType::DS4);
+        assert_eq!(status.pads[1].user_index, status::UserIndexStatus::Unknown);
+
+        drop(req_tx);
+        assert!(pads.join().unwrap().is_err());
+
+        // Without dynamic pads there's only ever the reserved ones
+        let (req_tx, pads) = spawn_pads(Args {
+            players: 1,
+            dynamic_pads: false,
+            ..Args::default()
+        });
+        match acquire_typed(&req_tx, PadType::DS4) {
+            Err(reason) => assert!(reason.is::<ServerFull>(), "{}", reason),
+            Ok(pad) => panic!("got dualshock 4 pad {} with only reserved pads", pad.id),
+        }
+
+        drop(req_tx);
+        assert!(pads.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_pad_preset() {
+        let (req_tx, pads) = spawn_pads(Args {
+            players: 1,
+            ..Args::default()
+        });
+        let ds4 = acquire_typed(&req_tx, PadType::DS4).unwrap();
+        let (reply_tx, reply_rx) = channel();
+        req_tx.send(PadRequest::Status(reply_tx)).unwrap();
+        let status = reply_rx.recv().unwrap();
+        let ids = |id: usize| (status.pads[id].vendor_id, status.pads[id].product_id);
+        // Each type of pad passes for its own controller by default
+        assert_eq!(ids(0), (0x045E, 0x028E));
+        assert_eq!(ids(ds4.id), (0x054C, 0x05C4));
+        drop(req_tx);
+        assert!(pads.join().unwrap().is_err());
+
+        let (req_tx, pads) = spawn_pads(Args {
+            players: 1,
+            pad_preset: Some(Preset::LogitechF310),
+            ..Args::default()
+        });
+        let ds4 = acquire_typed(&req_tx, PadType::DS4).unwrap();
+        let (reply_tx, reply_rx) = channel();
+        req_tx.send(PadRequest::Status(reply_tx)).unwrap();
+        let status = reply_rx.recv().unwrap();
+        let ids = |id: usize| (status.pads[id].vendor_id, status.pads[id].product_id);
+        assert_eq!(ids(0), (0x046D, 0xC21D));
+        assert_eq!(ids(ds4.id), (0x046D, 0xC21D));
+        drop(req_tx);
+        assert!(pads.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let (req_tx, pads) = spawn_pads(Args::default());
+        let pad = acquire(&req_tx).unwrap();
+        let sent = || {
+            let (reply_tx, reply_rx) = channel();
+            req_tx.send(PadRequest::Status(reply_tx)).unwrap();
+            let status = reply_rx.recv().unwrap();
+            (status.pads[pad.id].sent, status.pads[pad.id].skipped)
+        };
+        let (sent_before, skipped_before) = sent();
+
+        // Editing the pipeline sends what the client holds through it right away, unless that
+        // doesn't change anything
+        let press_a = |mut state: X360State, _| {
+            state.buttons.insert(X360Buttons::A);
+            state
+        };
+        for edit in [
+            PipelineEdit::Insert(0, Box::new(press_a)),
+            PipelineEdit::Move(0, 1),
+            PipelineEdit::Remove(0),
+        ] {
+            req_tx.send(PadRequest::Pipeline(pad.id, edit)).unwrap();
+        }
+        assert_eq!(sent(), (sent_before + 2, skipped_before + 1));
+
+        drop(req_tx);
+        assert!(pads.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_neutralize() {
+        let (req_tx, pads) = spawn_pads(Args {
+            neutral_after: Duration::from_millis(50),
+            ..Args::default()
+        });
+        let sent = || {
+            let (reply_tx, reply_rx) = channel();
+            req_tx.send(PadRequest::Status(reply_tx)).unwrap();
+            let status = reply_rx.recv().unwrap();
+            status.pads.iter().map(|pad| pad.sent).collect::<Vec<_>>()
+        };
+        let idle = acquire(&req_tx).unwrap();
+        let sparse = acquire(&req_tx).unwrap();
+        req_tx
+            .send(PadRequest::Neutral(sparse.id, Some(Duration::ZERO)))
+            .unwrap();
+        let press = |pad: &NewPad| {
+            let state = X360State::builder().press(X360Buttons::A).build();
+            assert!(pad.latest.put(state, Instant::now()));
+            req_tx.send(PadRequest::Update(pad.id)).unwrap();
+        };
+        press(&idle);
+        press(&sparse);
+        let before = sent();
+
+        // Only the pad going by the server's interval lets go, and only once
+        sleep(Duration::from_millis(250));
+        let after = sent();
+        assert_eq!(after[idle.id], before[idle.id] + 1);
+        assert_eq!(after[sparse.id], before[sparse.id]);
+
+        // Until its client sends something again
+        press(&idle);
+        assert_eq!(sent()[idle.id], after[idle.id] + 1);
+
+        drop(req_tx);
+        assert!(pads.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_socd() {
+        let (req_tx, pads) = spawn_pads(Args {
+            socd: Some(SocdPolicy::Neutral),
+            ..Args::default()
+        });
+        let sent = |pad: &NewPad| {
+            let (reply_tx, reply_rx) = channel();
+            req_tx.send(PadRequest::Status(reply_tx)).unwrap();
+            reply_rx.recv().unwrap().pads[pad.id].sent
+        };
+        let send = |pad: &NewPad, buttons| {
+            let state = X360State::builder().press(buttons).build();
+            assert!(pad.latest.put(state, Instant::now()));
+            req_tx.send(PadRequest::Update(pad.id)).unwrap();
+        };
+        let both = X360Buttons::DPAD_UP | X360Buttons::DPAD_DOWN;
+
+        // Holding up and down is sent as holding neither by the server's policy, so letting go
+        // of both changes nothing
+        let server = acquire(&req_tx).unwrap();
+        send(&server, both);
+        let before = sent(&server);
+        send(&server, X360Buttons::empty());
+        assert_eq!(sent(&server), before);
+
+        // Whereas a client can have up win instead
+        let client = acquire(&req_tx).unwrap();
+        req_tx
+            .send(PadRequest::Socd(client.id, Some(SocdPolicy::UpPriority)))
+            .unwrap();
+        send(&client, both);
+        let before = sent(&client);
+        send(&client, X360Buttons::DPAD_UP);
+        assert_eq!(sent(&client), before);
+        send(&client, X360Buttons::empty());
+        assert_eq!(sent(&client), before + 1);
+
+        drop(req_tx);
+        assert!(pads.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_discard() {
+        let (req_tx, pads) = spawn_pads(Args {
+            players: 1,
+            ..Args::default()
+        });
+        let status = || {
+            let (reply_tx, reply_rx) = channel();
+            req_tx.send(PadRequest::Status(reply_tx)).unwrap();
+            reply_rx.recv().unwrap()
+        };
+        let connected = acquire(&req_tx).unwrap();
+        let detached = acquire(&req_tx).unwrap();
+        assert_eq!((connected.id, detached.id), (0, 1));
+
+        // Pads whose client is gone are released right away
+        req_tx.send(PadRequest::Detach(detached.id)).unwrap();
+        req_tx.send(PadRequest::Discard(detached.id)).unwrap();
+        assert_eq!(status().pads.len(), 1);
+
+        // Whereas connected clients have to let go of theirs, and can't use them meanwhile
+        req_tx.send(PadRequest::Discard(connected.id)).unwrap();
+        assert!(!status().pads[0].free);
+        assert!(connected.latest.is_discarded());
+        assert!(!connected.latest.put(X360State::default(), Instant::now()));
+        req_tx.send(PadRequest::Release(connected.id)).unwrap();
+        assert!(status().pads[0].free);
+
+        // Pads which are already free or gone are left alone
+        req_tx.send(PadRequest::Discard(connected.id)).unwrap();
+        req_tx.send(PadRequest::Discard(detached.id)).unwrap();
+        assert!(status().pads[0].free);
+        let next = acquire(&req_tx).unwrap();
+        assert_eq!(next.id, connected.id);
+        assert!(!next.latest.is_discarded());
+
+        drop(req_tx);
+        assert!(pads.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_restore() {
+        let remap = Remap {
+            swap_sticks: true,
+            ..Remap::default()
+        };
+        let restored = vec![
+            PadSnapshot {
+                pad_type: PadType::X360,
+                reserved: true,
+                client: Some(ClientSnapshot {
+                    reclaim: "0123".to_string(),
+                    device: Some("phone".to_string()),
+                    calibration: Calibration::default(),
+                    remap: remap.clone(),
+                }),
+            },
+            PadSnapshot {
+                pad_type: PadType::DS4,
+                reserved: false,
+                client: Some(ClientSnapshot {
+                    reclaim: "4567".to_string(),
+                    device: None,
+                    calibration: Calibration::default(),
+                    remap: Remap::default(),
+                }),
+            },
+        ];
+        let (req_tx, pads) = restore(
+            Args {
+                players: 2,
+                ..Args::default()
+            },
+            restored.clone(),
+        );
+
+        // The pads wait for their clients, besides the reserved one the snapshot didn't have
+        let (reply_tx, reply_rx) = channel();
+        req_tx.send(PadRequest::Status(reply_tx)).unwrap();
+        let status = reply_rx.recv().unwrap();
+        let pad_states: Vec<_> = status
+            .pads
+            .iter()
+            .map(|pad| (pad.pad_type, pad.detached, pad.free))
+            .collect();
+        assert_eq!(
+            pad_states,
+            [
+                (PadType::X360, true, false),
+                (PadType::DS4, true, false),
+                (PadType::X360, false, true)
+            ]
+        );
+
+        let (reply_tx, reply_rx) = channel();
+        req_tx
+            .send(PadRequest::Reclaim(
+                "4567".to_string(),
+                connection(),
+                None,
+                PadType::X360,
+                reply_tx,
+            ))
+            .unwrap();
+        let reclaimed = reply_rx.recv().unwrap().unwrap();
+        assert_eq!((reclaimed.id, reclaimed.pad_type), (1, PadType::DS4));
+
+        // Saving them again gives back what was restored, along with the new reserved pad
+        let (reply_tx, reply_rx) = channel();
+        req_tx.send(PadRequest::Snapshot(reply_tx)).unwrap();
+        let snapshot = reply_rx.recv().unwrap();
+        assert_eq!(snapshot[..2], restored[..]);
+        assert_eq!(
+            snapshot[2],
+            PadSnapshot {
+                pad_type: PadType::X360,
+                reserved: true,
+                client: None,
+            }
+        );
+
+        drop(req_tx);
+        assert!(pads.join().unwrap().is_err());
+    }
+
+    /// A bus which keeps the states its targets are sent, and which can go away
+    #[derive(Clone, Default)]
+    struct FakeBus {
+        /// The bus index of the target each state was sent to, and the state
+        sent: Arc<Mutex<Vec<(u32, X360State)>>>,
+        plugged: Arc<AtomicU64>,
+        lost: Arc<AtomicBool>,
+
+        /// What the next updates fail with, in order, before they go through again
+        failures: Arc<Mutex<VecDeque<Error>>>,
+
+        /// The user indices given to xbox 360 targets which are still plugged in
+        user_indices: Arc<Mutex<BTreeSet<u32>>>,
+
+        /// Where each target's notifications go, by bus index
+        feedbacks: Arc<Mutex<Vec<Arc<Feedback>>>>,
+    }
+
+    struct FakeTarget {
+        index: u32,
+        pad_type: PadType,
+        preset: Option<Preset>,
+
+        /// The lowest user index nobody had when the target was plugged in, like the bus gives
+        /// xbox 360 targets, if there was one left
+        user_index: Option<u32>,
+        bus: FakeBus,
+    }
+
+    impl Drop for FakeTarget {
+        fn drop(&mut self) {
+            if let Some(index) = self.user_index {
+                let _ = self.bus.user_indices.lock().unwrap().remove(&index);
+            }
+        }
+    }
+
+    impl Target for FakeTarget {
+        fn pad_type(&self) -> PadType {
+            self.pad_type
+        }
+
+        fn index(&self) -> u32 {
+            self.index
+        }
+
+        fn ids(&self) -> (u16, u16) {
+            let preset = self.preset.unwrap_or(match self.pad_type {
+                PadType::X360 => Preset::MicrosoftX360Wired,
+                PadType::DS4 => Preset::SonyDualShock4,
+            });
+            (preset.vendor_id(), preset.product_id())
+        }
+
+        fn update(&self, state: X360State) -> Result<(), Error> {
+            if self.bus.lost.load(Ordering::SeqCst) {
+                return Err(Error::BusNotFound);
+            }
+            if let Some(error) = self.bus.failures.lock().unwrap().pop_front() {
+                return Err(error);
+            }
+            self.bus.sent.lock().unwrap().push((self.index, state));
+            Ok(())
+        }
+
+        fn user_index(&self) -> Result<UserIndex, Error> {
+            match (self.pad_type, self.user_index) {
+                (PadType::X360, Some(index)) => Ok(UserIndex::Assigned(index)),
+                (PadType::X360, None) => Ok(UserIndex::Unassigned),
+                (PadType::DS4, _) => Err(Error::NotSupported),
+            }
+        }
+
+        fn wait_for_user_index(&self, _timeout: Duration) -> Result<u32, Error> {
+            match self.user_index()? {
+                UserIndex::Assigned(index) => Ok(index),
+                _ => Err(Error::UserIndexOutOfRange),
+            }
+        }
+    }
+
+    impl TargetFactory for FakeBus {
+        fn connect(
+            &self,
+            pad_type: PadType,
+            preset: Option<Preset>,
+            feedback: &Arc<Feedback>,
+        ) -> Result<Box<dyn Target>> {
+            if self.lost.load(Ordering::SeqCst) {
+                return Err(Error::BusNotFound.into());
+            }
+            let index = self.plugged.fetch_add(1, Ordering::SeqCst) as u32;
+            self.feedbacks.lock().unwrap().push(Arc::clone(feedback));
+            let user_index = match pad_type {
+                PadType::X360 => {
+                    let mut taken = self.user_indices.lock().unwrap();
+                    let free = (0..XINPUT_SLOTS).find(|index| !taken.contains(index));
+                    taken.extend(free);
+                    free
+                }
+                PadType::DS4 => None,
+            };
+            Ok(Box::new(FakeTarget {
+                index,
+                pad_type,
+                preset,
+                user_index,
+                bus: self.clone(),
+            }))
+        }
+
+        fn is_connected(&self) -> bool {
+            !self.lost.load(Ordering::SeqCst)
+        }
+
+        fn reconnect(&mut self, _logger: &Logger) -> Result<()> {
+            self.lost.store(false, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    impl FakeBus {
+        /// The last state sent to any target
+        fn last(&self) -> Option<(u32, X360State)> {
+            self.sent.lock().unwrap().last().copied()
+        }
+
+        /// Fail the next updates with the given errors
+        fn fail(&self, errors: &[Error]) {
+            self.failures.lock().unwrap().extend(errors);
+        }
+
+        /// Light the given LED on the target with the given bus index, like a game would
+        fn light(&self, index: u32, led_number: u8) {
+            self.feedbacks.lock().unwrap()[index as usize].send(
+                X360NotificationData {
+                    large_motor: 0,
+                    small_motor: 0,
+                    led_number,
+                }
+                .into(),
+            );
+        }
+    }
+
+    fn manager<'a>(
+        args: &'a Args,
+        bus: &FakeBus,
+        metrics: &'a Metrics,
+        on_event: &'a dyn Fn(PadEvent),
+    ) -> PadManager<'a, FakeBus> {
+        let logger = Logger::root(Discard, o!());
+        let profiles = no_profiles();
+        PadManager::new(logger, args, bus.clone(), None, profiles, metrics, on_event)
+    }
+
+    fn is_unknown(result: Result<()>, id: usize) -> bool {
+        matches!(result.unwrap_err().downcast_ref::<UnknownPad>(), Some(&UnknownPad(unknown)) if unknown == id)
+    }
+
+    #[test]
+    fn test_manager_unknown_pad() {
+        let (args, bus, metrics) = (Args::default(), FakeBus::default(), Metrics::default());
+        let on_event = |_| {};
+        let mut manager = manager(&args, &bus, &metrics, &on_event);
+        let state = X360State::builder().press(X360Buttons::A).build();
+
+        assert!(is_unknown(manager.discard(3, CloseReason::Kicked), 3));
+        assert!(is_unknown(manager.update(3, state, Instant::now()), 3));
+        assert!(is_unknown(manager.handle(PadRequest::Detach(3)), 3));
+        assert_eq!(bus.last(), None);
+    }
+
+    #[test]
+    fn test_manager_ids() {
+        let (args, bus, metrics) = (Args::default(), FakeBus::default(), Metrics::default());
+        let on_event = |_| {};
+        let mut manager = manager(&args, &bus, &metrics, &on_event);
+        let mut create = || {
+            manager
+                .create_pad(connection(), None, PadType::X360)
+                .unwrap()
+                .id
+        };
+        assert_eq!((create(), create(), create()), (0, 1, 2));
+
+        // Releasing a pad leaves the others' ids alone, and its id goes to the next pad
+        manager.handle(PadRequest::Release(1)).unwrap();
+        let state = X360State::builder().press(X360Buttons::B).build();
+        manager.update(2, state, Instant::now()).unwrap();
+        assert_eq!(bus.last(), Some((2, state)));
+        let (reply_tx, reply_rx) = channel();
+        manager.handle(PadRequest::Status(reply_tx)).unwrap();
+        let status = reply_rx.recv().unwrap();
+        assert_eq!(status.pads[1].id, 2);
+        assert_eq!(status.pads[1].last_change, "+B");
+        assert_eq!(
+            manager
+                .create_pad(connection(), None, PadType::DS4)
+                .unwrap()
+                .id,
+            1
+        );
+        assert!(is_unknown(manager.discard(3, CloseReason::Kicked), 3));
+    }
+
+    #[test]
+    fn test_manager_overflow() {
+        let args = Args {
+            max_pads: 8,
+            ..Args::default()
+        };
+        let (bus, metrics) = (FakeBus::default(), Metrics::default());
+        let on_event = |_| {};
+        let mut manager = manager(&args, &bus, &metrics, &on_event);
+        let mut create = || {
+            manager
+                .create_pad(connection(), None, PadType::X360)
+                .unwrap()
+                .player
+                .unwrap()
+                .to_string()
+        };
+        let players: Vec<_> = (0..6).map(|_| create()).collect();
+        assert_eq!(
+            players,
+            [
+                "P1",
+                "P2",
+                "P3",
+                "P4",
+                "P5 (no XInput slot)",
+                "P6 (no XInput slot)"
+            ]
+        );
+
+        // Numbers past the slots are handed out again once their pad is gone, like the slots
+        manager.handle(PadRequest::Release(4)).unwrap();
+        manager.handle(PadRequest::Release(0)).unwrap();
+        let mut create = || {
+            manager
+                .create_pad(connection(), None, PadType::X360)
+                .unwrap()
+                .player
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            (create(), create()),
+            ("P1".into(), "P5 (no XInput slot)".into())
+        );
+
+        // The LED the game lit is the player the pad is, whatever its user index
+        bus.light(1, 2);
+        let (reply_tx, reply_rx) = channel();
+        manager.handle(PadRequest::Status(reply_tx)).unwrap();
+        let status = reply_rx.recv().unwrap();
+        assert_eq!(status.pads[1].id, 1);
+        assert_eq!(
+            status.pads[1].user_index,
+            status::UserIndexStatus::Assigned(1)
+        );
+        assert_eq!(status.pads[1].player, Some(3));
+        assert_eq!(
+            status.pads[5].player_label.as_deref(),
+            Some("P6 (no XInput slot)")
+        );
+    }
+
+    #[test]
+    fn test_manager_xinput_only() {
+        let args = Args {
+            max_pads: 8,
+            xinput_only: true,
+            ..Args::default()
+        };
+        let (bus, metrics) = (FakeBus::default(), Metrics::default());
+        let on_event = |_| {};
+        let mut manager = manager(&args, &bus, &metrics, &on_event);
+        for _ in 0..4 {
+            assert!(manager
+                .create_pad(connection(), None, PadType::X360)
+                .is_ok());
+        }
+        match manager.create_pad(connection(), None, PadType::X360) {
+            Ok(_) => panic!("a fifth xbox 360 pad was created"),
+            Err(reason) => assert!(reason.is::<ServerFull>(), "{}", reason),
+        }
+
+        // Dualshock 4 pads were never XInput's to begin with
+        let ds4 = manager
+            .create_pad(connection(), None, PadType::DS4)
+            .unwrap();
+        assert_eq!(ds4.player, None);
+    }
+
+    #[test]
+    fn test_manager_tick() {
+        let (args, bus, metrics) = (Args::default(), FakeBus::default(), Metrics::default());
+        let on_event = |_| {};
+        let mut manager = manager(&args, &bus, &metrics, &on_event);
+        let id = manager
+            .create_pad(connection(), None, PadType::X360)
+            .unwrap()
+            .id;
+        let config = TurboConfig {
+            buttons: X360Buttons::A,
+            frequency: 10.0,
+        };
+        manager.handle(PadRequest::Turbo(id, config)).unwrap();
+        let held = X360State::builder().press(X360Buttons::A).build();
+        let start = Instant::now();
+        manager.update(id, held, start).unwrap();
+        assert_eq!(bus.last(), Some((0, held)));
+
+        // Nothing is due yet, until the turbo button's release is
+        manager.tick(Instant::now()).unwrap();
+        assert_eq!(bus.last(), Some((0, held)));
+        manager.tick(start + Duration::from_millis(60)).unwrap();
+        assert_eq!(bus.last(), Some((0, X360State::default())));
+    }
+
+    #[test]
+    fn test_manager_tick_rate() {
+        let args = Args {
+            tick_rate: 100,
+            ..Args::default()
+        };
+        let (bus, metrics) = (FakeBus::default(), Metrics::default());
+        let on_event = |_| {};
+        let mut manager = manager(&args, &bus, &metrics, &on_event);
+        let pad = manager
+            .create_pad(connection(), None, PadType::X360)
+            .unwrap();
+        let first = X360State::builder().press(X360Buttons::A).build();
+        let second = X360State::builder().press(X360Buttons::B).build();
+
+        // States wait for the tick, with the latest replacing those before it
+        assert!(pad.latest.put(first, Instant::now()));
+        manager.handle(PadRequest::Update(pad.id)).unwrap();
+        assert!(!pad.latest.put(second, Instant::now()));
+        assert_eq!(bus.last(), None);
+        let due = manager.next_due(Instant::now()).unwrap();
+        manager.tick(due + Duration::from_millis(5)).unwrap();
+        assert_eq!(bus.sent.lock().unwrap().as_slice(), [(0, second)]);
+
+        // Nothing is sent on a tick without a new state
+        manager.tick(due + Duration::from_millis(15)).unwrap();
+        assert_eq!(bus.sent.lock().unwrap().len(), 1);
+        let (reply_tx, reply_rx) = channel();
+        manager.handle(PadRequest::Status(reply_tx)).unwrap();
+        let tick = reply_rx.recv().unwrap().tick.unwrap();
+        assert_eq!(tick.rate, 100);
+        assert_eq!(tick.intervals.unwrap().p99_ms, 10.0);
+    }
+
+    #[test]
+    fn test_manager_suspend() {
+        let (args, bus, metrics) = (Args::default(), FakeBus::default(), Metrics::default());
+        let on_event = |_| {};
+        let mut manager = manager(&args, &bus, &metrics, &on_event);
+        let pad = manager
+            .create_pad(connection(), None, PadType::X360)
+            .unwrap();
+        let held = X360State::builder()
+            .press(X360Buttons::A)
+            .right_trigger(255)
+            .build();
+        let pocket = X360State::builder().press(X360Buttons::B).build();
+        let status = |manager: &mut PadManager<FakeBus>| {
+            let (reply_tx, reply_rx) = channel();
+            manager.handle(PadRequest::Status(reply_tx)).unwrap();
+            reply_rx.recv().unwrap().pads[pad.id].clone()
+        };
+        manager.update(pad.id, held, Instant::now()).unwrap();
+        assert_eq!(bus.last(), Some((0, held)));
+
+        // Going into the background lets go of everything right away
+        manager.handle(PadRequest::Suspend(pad.id)).unwrap();
+        assert_eq!(bus.last(), Some((0, X360State::default())));
+        assert!(status(&mut manager).suspended);
+
+        // And whatever comes in meanwhile never reaches the target, however it comes in
+        let sent = bus.sent.lock().unwrap().len();
+        manager.update(pad.id, pocket, Instant::now()).unwrap();
+        assert!(pad.latest.put(pocket, Instant::now()));
+        manager.handle(PadRequest::Update(pad.id)).unwrap();
+        manager.handle(PadRequest::Suspend(pad.id)).unwrap();
+        assert_eq!(bus.sent.lock().unwrap().len(), sent);
+        // It's still remembered as what the client holds
+        let (reply_tx, reply_rx) = channel();
+        manager.handle(PadRequest::Clients(reply_tx)).unwrap();
+        let client = reply_rx.recv().unwrap().remove(0);
+        assert_eq!(client.buttons, X360Buttons::B.bits());
+        assert!(client.suspended);
+
+        // Until the page is back, after which the client's states go through again
+        manager.handle(PadRequest::Resume(pad.id)).unwrap();
+        assert!(!status(&mut manager).suspended);
+        assert_eq!(bus.sent.lock().unwrap().len(), sent);
+        manager.update(pad.id, held, Instant::now()).unwrap();
+        assert_eq!(bus.last(), Some((0, held)));
+    }
+
+    #[test]
+    fn test_manager_kick() {
+        let (args, bus, metrics) = (Args::default(), FakeBus::default(), Metrics::default());
+        let on_event = |_| {};
+        let mut manager = manager(&args, &bus, &metrics, &on_event);
+        let pad = manager
+            .create_pad(connection(), None, PadType::X360)
+            .unwrap();
+        let state = X360State::builder().press(X360Buttons::X).build();
+        manager.update(pad.id, state, Instant::now()).unwrap();
+        let clients = |manager: &mut PadManager<'_, FakeBus>| {
+            let (reply_tx, reply_rx) = channel();
+            manager.handle(PadRequest::Clients(reply_tx)).unwrap();
+            reply_rx.recv().unwrap()
+        };
+        let listed = clients(&mut manager);
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].ip, Ipv4Addr::LOCALHOST);
+        assert_eq!(listed[0].buttons, X360Buttons::X.bits());
+        assert!(listed[0].last_update_ms.is_some());
+
+        let mut kick = |id| {
+            let (reply_tx, reply_rx) = channel();
+            manager.handle(PadRequest::Kick(id, reply_tx)).unwrap();
+            reply_rx.recv().unwrap()
+        };
+        assert!(!kick(pad.id + 1));
+        assert!(kick(pad.id));
+        assert!(pad.latest.is_discarded());
+        assert_eq!(bus.last(), Some((0, X360State::default())));
+
+        // Once its connection lets go, there's nobody left to kick
+        manager.handle(PadRequest::Release(pad.id)).unwrap();
+        assert!(clients(&mut manager).is_empty());
+    }
+
+    #[test]
+    fn test_manager_test_input() {
+        let (args, bus, metrics) = (Args::default(), FakeBus::default(), Metrics::default());
+        let on_event = |_| {};
+        let mut manager = manager(&args, &bus, &metrics, &on_event);
+        let pad = manager
+            .create_pad(connection(), None, PadType::X360)
+            .unwrap();
+        let test_input = |manager: &mut PadManager<'_, FakeBus>, id| {
+            let (reply_tx, reply_rx) = channel();
+            manager.handle(PadRequest::TestInput(id, reply_tx)).unwrap();
+            reply_rx.recv().unwrap()
+        };
+        assert_eq!(
+            test_input(&mut manager, pad.id + 1),
+            TestInputReply::NoSuchPad
+        );
+
+        // The test input starts by pressing A, then goes around with the left stick
+        let start = Instant::now();
+        assert_eq!(test_input(&mut manager, pad.id), TestInputReply::Started);
+        let pressed = X360State::builder().press(X360Buttons::A).build();
+        assert_eq!(bus.last(), Some((0, pressed)));
+        assert!(manager.next_due(start).is_some());
+        manager.tick(start + Duration::from_secs(1)).unwrap();
+        let (_, turning) = bus.last().unwrap();
+        assert_eq!(turning.buttons, X360Buttons::empty());
+        assert_ne!(turning.left_thumbstick, StickPosition::default());
+        // Turbo doesn't get in its way either
+        manager.pulse_turbo(start + Duration::from_secs(1)).unwrap();
+        assert_eq!(bus.last(), Some((0, turning)));
+
+        // Once it's over the pad goes back to what the client holds
+        manager.tick(start + Duration::from_secs(3)).unwrap();
+        assert_eq!(bus.last(), Some((0, X360State::default())));
+        assert_eq!(manager.next_due(start + Duration::from_secs(3)), None);
+
+        // A state from the client cuts it short, and keeps it from starting for a while
+        assert_eq!(test_input(&mut manager, pad.id), TestInputReply::Started);
+        let held = X360State::builder().press(X360Buttons::B).build();
+        manager.update(pad.id, held, Instant::now()).unwrap();
+        assert_eq!(bus.last(), Some((0, held)));
+        manager
+            .tick(Instant::now() + Duration::from_millis(100))
+            .unwrap();
+        assert_eq!(bus.last(), Some((0, held)));
+        assert_eq!(test_input(&mut manager, pad.id), TestInputReply::Busy);
+
+        // Nobody is using a released pad to test
+        manager.handle(PadRequest::Release(pad.id)).unwrap();
+        assert_eq!(test_input(&mut manager, pad.id), TestInputReply::NoSuchPad);
+    }
+
+    #[test]
+    fn test_manager_ramp() {
+        let (args, bus, metrics) = (Args::default(), FakeBus::default(), Metrics::default());
+        let on_event = |_| {};
+        let mut manager = manager(&args, &bus, &metrics, &on_event);
+        let pad = manager
+            .create_pad(connection(), None, PadType::X360)
+            .unwrap();
+        let ramp = Ramp {
+            enabled: true,
+            attack_ms: 80,
+            release_ms: 40,
+        };
+        let triggers = DigitalTriggers::new(DigitalTriggersConfig {
+            left: Ramp::default(),
+            right: ramp,
+        });
+        manager
+            .handle(PadRequest::Pipeline(
+                pad.id,
+                PipelineEdit::Insert(0, Box::new(triggers)),
+            ))
+            .unwrap();
+
+        // The trigger starts ramping up once held, and keeps going with the ticks
+        let start = Instant::now();
+        let held = X360State::builder().right_trigger(255).build();
+        manager.update(pad.id, held, start).unwrap();
+        assert_eq!(bus.last(), Some((0, X360State::default())));
+        assert!(manager.next_due(start).is_some());
+        manager.tick(start + Duration::from_millis(40)).unwrap();
+        let (_, ramping) = bus.last().unwrap();
+        assert!(ramping.right_trigger > 0 && ramping.right_trigger < 255);
+        manager.tick(start + Duration::from_millis(200)).unwrap();
+        assert_eq!(bus.last(), Some((0, held)));
+    }
+
+    #[test]
+    fn test_manager_macros() {
+        let (args, bus, metrics) = (Args::default(), FakeBus::default(), Metrics::default());
+        let on_event = |_| {};
+        let mut manager = manager(&args, &bus, &metrics, &on_event);
+        let pad = manager
+            .create_pad(connection(), Some("phone".to_string()), PadType::X360)
+            .unwrap();
+        let pressed = X360State::builder().press(X360Buttons::A).build();
+
+        // Saving stops the recording, and keeps it in the device's profile
+        manager.handle(PadRequest::Record(pad.id, true)).unwrap();
+        manager.update(pad.id, pressed, Instant::now()).unwrap();
+        sleep(Duration::from_millis(30));
+        manager.handle(PadRequest::SaveMacro(pad.id, 1)).unwrap();
+        let macros = manager.profiles.get("phone").unwrap().macros.clone();
+        assert_eq!(macros.len(), 1);
+        assert_eq!(macros[0].slot, 1);
+        assert!(macros[0].length_ms >= 30);
+        let states: Vec<_> = macros[0].steps.iter().map(|step| step.state).collect();
+        assert_eq!(states, [X360State::default(), pressed]);
+        // Nothing is left to save once it's saved, and there are only so many slots
+        manager.handle(PadRequest::SaveMacro(pad.id, 2)).unwrap();
+        manager.handle(PadRequest::Record(pad.id, true)).unwrap();
+        manager
+            .handle(PadRequest::SaveMacro(pad.id, MACRO_SLOTS))
+            .unwrap();
+        assert_eq!(manager.profiles.get("phone").unwrap().macros, macros);
+
+        // Playing it merges it with what the client holds, until it's over
+        let live = X360State::builder()
+            .press(X360Buttons::B)
+            .left_stick(100, 200)
+            .build();
+        manager.update(pad.id, live, Instant::now()).unwrap();
+        manager.handle(PadRequest::PlayMacro(pad.id, 1)).unwrap();
+        let start = Instant::now();
+        assert!(manager.next_due(start).unwrap() <= start + Duration::from_millis(40));
+        let at = |step: &MacroStep| start + Duration::from_millis(step.at_ms.into());
+        manager.tick(at(&macros[0].steps[1])).unwrap();
+        let merged = X360State::builder()
+            .press(X360Buttons::A | X360Buttons::B)
+            .left_stick(100, 200)
+            .build();
+        assert_eq!(bus.last(), Some((0, merged)));
+        manager
+            .tick(start + Duration::from_millis(u64::from(macros[0].length_ms) + 10))
+            .unwrap();
+        assert_eq!(bus.last(), Some((0, live)));
+
+        // A slot without a macro plays nothing
+        let sent = bus.sent.lock().unwrap().len();
+        manager.handle(PadRequest::PlayMacro(pad.id, 5)).unwrap();
+        assert_eq!(bus.sent.lock().unwrap().len(), sent);
+    }
+
+    /// How many updates the bus refused each pad
+    fn errors(manager: &mut PadManager<'_, FakeBus>) -> Vec<u64> {
+        let (reply_tx, reply_rx) = channel();
+        manager.handle(PadRequest::Status(reply_tx)).unwrap();
+        let status = reply_rx.recv().unwrap();
+        status.pads.iter().map(|pad| pad.errors).collect()
+    }
+
+    #[test]
+    fn test_manager_transient_errors() {
+        let (args, bus, metrics) = (Args::default(), FakeBus::default(), Metrics::default());
+        let on_event = |_| {};
+        let mut manager = manager(&args, &bus, &metrics, &on_event);
+        let pad = manager
+            .create_pad(connection(), None, PadType::X360)
+            .unwrap();
+        let press = |buttons| X360State::builder().press(buttons).build();
+
+        // The bus only briefly being unable to take updates doesn't count
+        bus.fail(&[Error::TargetNotPluggedIn, Error::BusAccessFailed]);
+        manager
+            .update(pad.id, press(X360Buttons::A), Instant::now())
+            .unwrap();
+        assert_eq!(bus.last(), Some((0, press(X360Buttons::A))));
+        assert_eq!(errors(&mut manager), [0]);
+
+        // Unless it stays that way for longer than the retries wait
+        bus.fail(&[Error::TargetNotPluggedIn; UPDATE_RETRIES as usize + 1]);
+        manager
+            .update(pad.id, press(X360Buttons::B), Instant::now())
+            .unwrap();
+        assert_eq!(bus.last(), Some((0, press(X360Buttons::A))));
+        assert_eq!(errors(&mut manager), [1]);
+        assert!(!pad.latest.is_discarded());
+    }
+
+    #[test]
+    fn test_manager_failures() {
+        let (args, bus, metrics) = (Args::default(), FakeBus::default(), Metrics::default());
+        let on_event = |_| {};
+        let mut manager = manager(&args, &bus, &metrics, &on_event);
+        let failing = manager
+            .create_pad(connection(), None, PadType::X360)
+            .unwrap();
+        let other = manager
+            .create_pad(connection(), None, PadType::X360)
+            .unwrap();
+        let state = X360State::builder().press(X360Buttons::X).build();
+        // Each state is new, so that none of them is skipped
+        let fail_updates = |manager: &mut PadManager<'_, FakeBus>, count| {
+            for trigger in 1..=count {
+                bus.fail(&[Error::UnknownError(0xE000_0001)]);
+                let state = X360State::builder().left_trigger(trigger as u8).build();
+                manager.update(failing.id, state, Instant::now()).unwrap();
+            }
+        };
+
+        // An update going through in between starts the count over
+        fail_updates(&mut manager, MAX_FAILURES);
+        manager.update(failing.id, state, Instant::now()).unwrap();
+        fail_updates(&mut manager, MAX_FAILURES);
+        assert!(!failing.latest.is_discarded());
+        assert_eq!(errors(&mut manager), [10, 0]);
+
+        // One more in a row is one too many, which only the failing pad's client notices
+        fail_updates(&mut manager, 1);
+        assert_eq!(failing.latest.discarded(), Some(CloseReason::PadFailed));
+        manager.update(other.id, state, Instant::now()).unwrap();
+        assert_eq!(bus.last(), Some((1, state)));
+        assert!(!other.latest.is_discarded());
+    }
+
+    #[test]
+    fn test_manager_invalid_target() {
+        let (args, bus, metrics) = (Args::default(), FakeBus::default(), Metrics::default());
+        let on_event = |_| {};
+        let mut manager = manager(&args, &bus, &metrics, &on_event);
+        let pad = manager
+            .create_pad(connection(), None, PadType::X360)
+            .unwrap();
+        let state = X360State::builder().press(X360Buttons::Y).build();
+
+        // The target won't ever take an update again, so it's no use waiting
+        bus.fail(&[Error::InvalidTarget]);
+        manager.update(pad.id, state, Instant::now()).unwrap();
+        assert_eq!(pad.latest.discarded(), Some(CloseReason::PadFailed));
+        assert_eq!(errors(&mut manager), [1]);
+    }
+
+    #[test]
+    fn test_manager_bus_lost() {
+        let (args, bus, metrics) = (Args::default(), FakeBus::default(), Metrics::default());
+        let on_event = |_| {};
+        let mut manager = manager(&args, &bus, &metrics, &on_event);
+        let id = manager
+            .create_pad(connection(), None, PadType::X360)
+            .unwrap()
+            .id;
+
+        // The update is lost along with the bus, but the pad is plugged in again under its id
+        bus.lost.store(true, Ordering::SeqCst);
+        let state = X360State::builder().press(X360Buttons::Y).build();
+        manager.update(id, state, Instant::now()).unwrap();
+        assert_eq!(bus.last(), None);
+        manager.update(id, state, Instant::now()).unwrap();
+        assert_eq!(bus.last(), Some((1, state)));
+    }
+
+    #[test]
+    fn test_stale_update() {
+        let (req_tx, pads) = spawn_pads(Args::default());
+        let pad = acquire(&req_tx).unwrap();
+        let state = X360State::builder().press(X360Buttons::A).build();
+        assert!(pad.latest.put(state, Instant::now()));
+        req_tx.send(PadRequest::Release(pad.id)).unwrap();
+
+        // The pad is gone by the time its state is taken out, which the pads live through
+        req_tx.send(PadRequest::Update(pad.id)).unwrap();
+        req_tx.send(PadRequest::Discard(pad.id)).unwrap();
+        let (reply_tx, reply_rx) = channel();
+        req_tx.send(PadRequest::Status(reply_tx)).unwrap();
+        assert!(reply_rx.recv().unwrap().pads.is_empty());
+
+        drop(req_tx);
+        assert!(pads.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_update_after_discard() {
+        let (req_tx, pads) = spawn_pads(Args::default());
+        let sent = || {
+            let (reply_tx, reply_rx) = channel();
+            req_tx.send(PadRequest::Status(reply_tx)).unwrap();
+            let status = reply_rx.recv().unwrap();
+            status
+                .pads
+                .iter()
+                .map(|pad| (pad.id, pad.sent))
+                .collect::<Vec<_>>()
+        };
+        let press = |pad: &NewPad, buttons| {
+            let state = X360State::builder().press(buttons).build();
+            if pad.latest.put(state, Instant::now()) {
+                req_tx.send(PadRequest::Update(pad.id)).unwrap();
+            }
+        };
+        let discarded = acquire(&req_tx).unwrap();
+        let other = acquire(&req_tx).unwrap();
+
+        // The discarded pad's connection still had an update queued, and the window asked twice
+        press(&discarded, X360Buttons::A);
+        req_tx.send(PadRequest::Discard(discarded.id)).unwrap();
+        req_tx.send(PadRequest::Discard(discarded.id)).unwrap();
+        req_tx.send(PadRequest::Update(discarded.id)).unwrap();
+        req_tx.send(PadRequest::Release(discarded.id)).unwrap();
+        req_tx.send(PadRequest::Update(discarded.id)).unwrap();
+        req_tx.send(PadRequest::Discard(discarded.id)).unwrap();
+
+        // Which the other pad doesn't notice
+        let before = sent();
+        assert_eq!(before, [(other.id, 0)]);
+        press(&other, X360Buttons::B);
+        assert_eq!(sent(), [(other.id, 1)]);
+
+        drop(req_tx);
+        assert!(pads.join().unwrap().is_err());
+    }
+}
@@ -0,0 +1,290 @@
+//! What's remembered of each device from one connection to the next, kept in a TOML file
+
+use std::{collections::BTreeMap, fs, io, path::PathBuf};
+
+use eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use slog::{warn, Logger};
+
+use crate::{calibration::Calibration, macros::Macro};
+
+/// What's remembered of a device, which it gets back whenever it connects again
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Profile {
+    /// The name of the remap profile it picked last, if it picked one of the server's
+    pub(crate) remap: Option<String>,
+
+    /// The name of the layout it used last
+    pub(crate) layout: Option<String>,
+
+    /// The id of the pad it had last, which it gets again if nobody else is using it
+    pub(crate) slot: Option<usize>,
+
+    /// The macros it kept, each in its own slot
+    pub(crate) macros: Vec<Macro>,
+
+    // Tables have to come after plain values in TOML, so this goes last
+    pub(crate) calibration: Option<Calibration>,
+}
+
+/// Something a client did which its device's profile remembers, besides what the pads keep
+/// track of themselves
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ProfileChange {
+    /// It picked a remap profile by name, or rearranged its inputs itself if there's none
+    Remap(Option<String>),
+
+    /// It opened the controller page with the layout of the given name
+    Layout(String),
+}
+
+impl Profile {
+    /// Remember the given change
+    pub(crate) fn apply(&mut self, change: ProfileChange) {
+        match change {
+            ProfileChange::Remap(remap) => self.remap = remap,
+            ProfileChange::Layout(layout) => self.layout = Some(layout),
+        }
+    }
+}
+
+/// The layout of the profiles file. Fields it doesn't know of, e.g. from a newer version, are
+/// ignored rather than making it invalid.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ProfilesFile {
+    #[serde(default)]
+    devices: BTreeMap<String, Profile>,
+}
+
+/// The profiles of every device we've seen, kept in a TOML file
+#[derive(Debug)]
+pub(crate) struct Profiles {
+    path: PathBuf,
+    file: ProfilesFile,
+}
+
+impl Profiles {
+    /// Load the profiles kept at the given path, of which there are none if it doesn't exist.
+    ///
+    /// Profiles which can't be read are only a reason to start from scratch, so that's logged
+    /// rather than returned as an error. They're replaced once a device's profile changes.
+    pub(crate) fn load(logger: &Logger, path: PathBuf) -> Self {
+        let file = match fs::read_to_string(&path) {
+            Ok(data) => toml::from_str(&data).unwrap_or_else(|error| {
+                warn!(logger, "profiles.ignored"; "path" => %path.display(), "error" => %error);
+                ProfilesFile::default()
+            }),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => ProfilesFile::default(),
+            Err(error) => {
+                warn!(logger, "profiles.ignored"; "path" => %path.display(), "error" => %error);
+                ProfilesFile::default()
+            }
+        };
+        Self { path, file }
+    }
+
+    /// The profile of the given device, if we've seen it before
+    pub(crate) fn get(&self, device: &str) -> Option<&Profile> {
+        self.file.devices.get(device)
+    }
+
+    /// Change the given device's profile, saving it right away if that changed anything
+    pub(crate) fn update(&mut self, device: &str, change: impl FnOnce(&mut Profile)) -> Result<()> {
+        let profile = self.file.devices.entry(device.to_string()).or_default();
+        let before = profile.clone();
+        change(profile);
+        if *profile == before {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    /// Save every profile, replacing the file only once it's all written
+    fn save(&self) -> Result<()> {
+        // Going through a value puts each profile's calibration after its plain values
+        let data = toml::to_string(&toml::Value::try_from(&self.file)?)?;
+        let partial = self.path.with_extension("partial");
+        fs::write(&partial, data)
+            .wrap_err_with(|| format!("Could not write {}", partial.display()))?;
+        fs::rename(&partial, &self.path)
+            .wrap_err_with(|| format!("Could not write {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use slog::{o, Discard};
+
+    use super::*;
+    use vigem_client_c::{X360Buttons, X360State};
+
+    use crate::{calibration::Inversion, macros::MacroStep};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "sphrosyne-profiles-{}-{}.toml",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn logger() -> Logger {
+        Logger::root(Discard, o!())
+    }
+
+    fn calibration() -> Calibration {
+        Calibration {
+            deadzone: 12.5,
+            trigger_threshold: 5.0,
+            invert: Inversion {
+                left_y: true,
+                ..Inversion::default()
+            },
+        }
+    }
+
+    fn combo() -> Macro {
+        let step = |at_ms, buttons| MacroStep {
+            at_ms,
+            state: X360State::builder()
+                .press(buttons)
+                .left_stick(-5, 10)
+                .build(),
+        };
+        Macro {
+            slot: 1,
+            length_ms: 300,
+            steps: vec![step(0, X360Buttons::DPAD_DOWN), step(100, X360Buttons::X)],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = temp_path("round-trip");
+        let mut profiles = Profiles::load(&logger(), path.clone());
+        assert_eq!(profiles.get("phone"), None);
+        profiles
+            .update("phone", |profile| {
+                profile.calibration = Some(calibration());
+                profile.apply(ProfileChange::Remap(Some("nintendo".to_string())));
+                profile.apply(ProfileChange::Layout("racing".to_string()));
+                profile.slot = Some(2);
+                profile.macros = vec![combo()];
+            })
+            .unwrap();
+
+        let reloaded = Profiles::load(&logger(), path.clone());
+        fs::remove_file(path).unwrap();
+        assert_eq!(
+            reloaded.get("phone"),
+            Some(&Profile {
+                remap: Some("nintendo".to_string()),
+                layout: Some("racing".to_string()),
+                slot: Some(2),
+                macros: vec![combo()],
+                calibration: Some(calibration()),
+            })
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let path = temp_path("merge");
+        let mut profiles = Profiles::load(&logger(), path.clone());
+        profiles
+            .update("phone", |profile| profile.slot = Some(0))
+            .unwrap();
+        profiles
+            .update("tablet", |profile| profile.slot = Some(1))
+            .unwrap();
+        // Changing one part of a profile leaves the rest of it, and every other one, alone
+        profiles
+            .update("phone", |profile| {
+                profile.apply(ProfileChange::Layout("fight".to_string()))
+            })
+            .unwrap();
+        profiles
+            .update("phone", |profile| profile.apply(ProfileChange::Remap(None)))
+            .unwrap();
+
+        let reloaded = Profiles::load(&logger(), path.clone());
+        fs::remove_file(path).unwrap();
+        assert_eq!(
+            reloaded.get("phone"),
+            Some(&Profile {
+                layout: Some("fight".to_string()),
+                slot: Some(0),
+                ..Profile::default()
+            })
+        );
+        assert_eq!(
+            reloaded.get("tablet").and_then(|profile| profile.slot),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_unchanged() {
+        // Nothing is written until something changes
+        let path = temp_path("unchanged");
+        let mut profiles = Profiles::load(&logger(), path.clone());
+        profiles.update("phone", |_| {}).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_unknown_fields() {
+        let path = temp_path("unknown");
+        fs::write(
+            &path,
+            r#"
+            version = 3
+
+            [devices.phone]
+            layout = "racing"
+            theme = "dark"
+
+            [devices.phone.calibration]
+            deadzone = 10.0
+            curve = "squared"
+
+            [devices.phone.gestures]
+            swipe = true
+            "#,
+        )
+        .unwrap();
+        let profiles = Profiles::load(&logger(), path.clone());
+        fs::remove_file(path).unwrap();
+        assert_eq!(
+            profiles.get("phone"),
+            Some(&Profile {
+                layout: Some("racing".to_string()),
+                calibration: Some(Calibration {
+                    deadzone: 10.0,
+                    ..Calibration::default()
+                }),
+                ..Profile::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let path = temp_path("corrupt");
+        fs::write(&path, "[devices.phone\nslot = ").unwrap();
+        let mut profiles = Profiles::load(&logger(), path.clone());
+        assert_eq!(profiles.get("phone"), None);
+
+        // Starting fresh replaces the file once there's something to save
+        profiles
+            .update("phone", |profile| profile.slot = Some(1))
+            .unwrap();
+        let reloaded = Profiles::load(&logger(), path.clone());
+        fs::remove_file(path).unwrap();
+        assert_eq!(
+            reloaded.get("phone").and_then(|profile| profile.slot),
+            Some(1)
+        );
+    }
+}
@@ -0,0 +1,868 @@
+//! The JSON messages clients and the server send each other over the websocket, which `/protocol`
+//! serves the schema of for clients of other people's making
+
+use std::{borrow::Cow, collections::BTreeSet, fmt, sync::Arc};
+
+use schemars::{schema::RootSchema, schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
+use tungstenite::Message;
+use vigem_client_c::{SocdPolicy, X360State};
+
+use crate::{
+    calibration::Calibration,
+    mapping::GamepadApiState,
+    motion::{MotionConfig, Orientation},
+    ramp::{DigitalTriggers, DigitalTriggersConfig},
+    remap::Remap,
+    request::PipelineEdit,
+    synthesis::{DpadToStick, StickToDpad},
+    touch::{ResponseCurve, TouchCell, TouchForce, TouchTriggers},
+    turbo::TurboConfig,
+};
+
+/// The version of the messages described here, which goes up along with the newest of the
+/// websocket's subprotocols
+pub(crate) const PROTOCOL_VERSION: u32 = 2;
+
+/// A JSON message a client can send us, which says what it is via its `type` field
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(crate) enum ClientMessage {
+    /// The state of a physical gamepad connected to the client
+    Gamepad(GamepadApiState),
+
+    /// How the client wants the states of all its pads calibrated from now on
+    Calibrate(Calibration),
+
+    /// Which buttons of all its pads the client wants pulsed while held, and how fast
+    Turbo(TurboConfig),
+
+    /// How the client wants the inputs of the pad with the given index, or of all its pads,
+    /// rearranged from now on: like the server's profile with the given name if it has one,
+    /// and as described by the rest of the message otherwise
+    Remap {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pad: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
+        #[serde(flatten)]
+        remap: Remap,
+    },
+
+    /// Which way the phone is facing, which moves the right stick of its first pad
+    Motion(Orientation),
+
+    /// How the client wants its phone's motion to move the right stick of its first pad
+    #[serde(rename = "motion_config")]
+    MotionConfig(MotionConfig),
+
+    /// How many milliseconds all of the client's pads may go without a state before they're made
+    /// neutral, 0 being never and none being the server's interval
+    Neutral {
+        #[serde(default)]
+        after_ms: Option<u64>,
+    },
+
+    /// What's done about all of the client's pads' dpads being held both ways at once, none
+    /// being the server's policy
+    Socd {
+        #[serde(default)]
+        policy: Option<SocdPolicy>,
+    },
+
+    /// A change to the transformers the states of the pad with the given index, or of all its
+    /// pads, go through last
+    Transformer {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pad: Option<usize>,
+        #[serde(flatten)]
+        edit: TransformerEdit,
+    },
+
+    /// Start recording the states of the pad with the given index for a macro, or stop
+    /// recording them
+    Record {
+        #[serde(default)]
+        pad: usize,
+        recording: bool,
+    },
+
+    /// Keep what the pad with the given index recorded last as the macro in the given slot,
+    /// which the client's device gets back whenever it connects again
+    #[serde(rename = "save_macro")]
+    SaveMacro {
+        #[serde(default)]
+        pad: usize,
+        slot: u8,
+    },
+
+    /// Play the macro in the given slot through the pad with the given index, merged with
+    /// whatever the client holds meanwhile
+    Macro {
+        #[serde(default)]
+        pad: usize,
+        slot: u8,
+    },
+
+    /// Ask for another pad, whose index is sent back
+    Attach,
+
+    /// The client's page lost visibility, e.g. as the phone was locked, so all its pads should
+    /// be neutral until it's back in the foreground
+    Background,
+
+    /// The client's page is visible again
+    Foreground,
+
+    /// The state of the pad with the given index, 0 being the one the connection started with,
+    /// along with when the client sent it by its own clock and how hard its sticks are touched
+    /// if it says.
+    ///
+    /// Like every other message, fields we don't know about are ignored rather than refused, so
+    /// that clients can send more than older servers understand.
+    Update {
+        pad: usize,
+        state: X360State,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        t: Option<f64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        touch: Option<TouchForce>,
+    },
+
+    /// The answer to the clock ping with the given number, stamped with the client's clock
+    Clock { n: u32, t: f64 },
+
+    /// The `KeyboardEvent.code`s of every key held down, for clients in keyboard mode
+    Keys { down: BTreeSet<String> },
+}
+
+/// Why a client's message isn't one we understand
+#[derive(Debug)]
+pub(crate) enum InvalidMessage {
+    /// The message's `type` isn't any of [ClientMessage::TYPES], e.g. as it's from a newer
+    /// client
+    UnknownType(String),
+
+    /// The message isn't JSON, or isn't what its `type` says it is
+    Malformed(serde_json::Error),
+}
+
+impl fmt::Display for InvalidMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownType(kind) => write!(f, "unknown message type {:?}", kind),
+            Self::Malformed(error) => write!(f, "malformed message: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for InvalidMessage {}
+
+/// Just the `type` of a message, to tell which one it was meant to be once it failed to parse
+#[derive(Deserialize)]
+struct Type<'a> {
+    #[serde(rename = "type", borrow, default)]
+    kind: Option<Cow<'a, str>>,
+}
+
+impl ClientMessage {
+    /// The `type` of every message
+    pub(crate) const TYPES: &'static [&'static str] = &[
+        "gamepad",
+        "calibrate",
+        "turbo",
+        "remap",
+        "motion",
+        "motion_config",
+        "neutral",
+        "socd",
+        "transformer",
+        "record",
+        "save_macro",
+        "macro",
+        "attach",
+        "background",
+        "foreground",
+        "update",
+        "clock",
+        "keys",
+    ];
+
+    /// Parse a message, telling a `type` we don't know apart from a message that isn't what its
+    /// `type` says
+    pub(crate) fn parse(data: &str) -> Result<Self, InvalidMessage> {
+        serde_json::from_str(data).map_err(|error| match unknown_type(data) {
+            Some(kind) => InvalidMessage::UnknownType(kind),
+            None => InvalidMessage::Malformed(error),
+        })
+    }
+}
+
+/// The `type` of a message which isn't one of [ClientMessage::TYPES], if it has one
+fn unknown_type(data: &str) -> Option<String> {
+    let kind = serde_json::from_str::<Type>(data).ok()?.kind?;
+    (!ClientMessage::TYPES.contains(&&*kind)).then(|| kind.into_owned())
+}
+
+/// A JSON message a client speaking the first version of the protocol can send us
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub(crate) enum ClientText {
+    Typed(ClientMessage),
+
+    /// A pad state, as sent before messages had types
+    State(X360State),
+}
+
+impl ClientText {
+    /// Parse a message, which is a pad state unless it has a `type`
+    pub(crate) fn parse(data: &str) -> Result<Self, InvalidMessage> {
+        // That it's neither is less use than why it isn't the one it's meant to be
+        serde_json::from_str(data).or_else(|error| match serde_json::from_str::<Type>(data) {
+            Ok(Type { kind: Some(_) }) => ClientMessage::parse(data).map(Self::Typed),
+            Ok(Type { kind: None }) => serde_json::from_str(data)
+                .map(Self::State)
+                .map_err(InvalidMessage::Malformed),
+            Err(_) => Err(InvalidMessage::Malformed(error)),
+        })
+    }
+}
+
+/// A change to a pad's transformers, which says what it is via its `op` field
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub(crate) enum TransformerEdit {
+    /// Put a built-in transformer at the given position, or last if there's none
+    Insert {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        index: Option<usize>,
+        transformer: BuiltIn,
+    },
+    Remove {
+        index: usize,
+    },
+    Move {
+        from: usize,
+        to: usize,
+    },
+}
+
+/// The transformers clients can put in their pads' pipelines, which say what they are via their
+/// `kind` field
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub(crate) enum BuiltIn {
+    Deadzone(Calibration),
+    Remap(Remap),
+
+    /// Pressing the triggers by how hard the sticks are touched, with the given curve
+    Touch {
+        #[serde(default)]
+        curve: ResponseCurve,
+    },
+
+    #[serde(rename = "stick_to_dpad")]
+    StickToDpad(StickToDpad),
+    #[serde(rename = "dpad_to_stick")]
+    DpadToStick,
+
+    /// Ramping the triggers up and down when they're pulled all the way and let go
+    #[serde(rename = "digital_triggers")]
+    DigitalTriggers(DigitalTriggersConfig),
+}
+
+impl TransformerEdit {
+    /// The edit to make to a pad's pipeline, whose touch transformers read the touch forces the
+    /// client sends for it from `touch`
+    pub(crate) fn into_pipeline_edit(self, touch: &Arc<TouchCell>) -> PipelineEdit {
+        match self {
+            Self::Insert { index, transformer } => PipelineEdit::Insert(
+                index.unwrap_or(usize::MAX),
+                match transformer {
+                    BuiltIn::Deadzone(calibration) => Box::new(calibration),
+                    BuiltIn::Remap(remap) => Box::new(remap),
+                    BuiltIn::Touch { curve } => Box::new(TouchTriggers {
+                        curve,
+                        force: Arc::clone(touch),
+                    }),
+                    BuiltIn::StickToDpad(stick_to_dpad) => Box::new(stick_to_dpad),
+                    BuiltIn::DpadToStick => Box::new(DpadToStick),
+                    BuiltIn::DigitalTriggers(config) => Box::new(DigitalTriggers::new(config)),
+                },
+            ),
+            Self::Remove { index } => PipelineEdit::Remove(index),
+            Self::Move { from, to } => PipelineEdit::Move(from, to),
+        }
+    }
+}
+
+/// A JSON message we can send a client, which says what it is via its `type` field
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(crate) enum ServerMessage {
+    /// Which server the client is talking to, which is the first message of every connection.
+    /// `device` is the identifier we know the client's device by, which it should present from
+    /// then on to keep its profile.
+    Hello {
+        server: String,
+        version: String,
+        device: Option<String>,
+    },
+
+    /// The client's pad is ready for states, with the same player number and reclaim token as
+    /// the messages before it, and no player if the pad doesn't have one. `label` is how to show
+    /// the player, e.g. `P5 (no XInput slot)`, and `layout` is the one its device used last, if
+    /// we remember it.
+    Ready {
+        player: Option<u32>,
+        label: Option<String>,
+        session: String,
+        layout: Option<String>,
+    },
+
+    /// A game set the LED of the client's pad with the given index to player `n`, counted from 1
+    Player { n: u16, pad: usize },
+
+    /// A game set the lightbar of the client's dualshock 4 pad with the given index to the given
+    /// color
+    Lightbar { r: u8, g: u8, b: u8, pad: usize },
+
+    /// How to vibrate for the rumble of the client's pad with the given index, as a pattern
+    /// `navigator.vibrate` takes as is
+    Haptic { pattern: Vec<u32>, pad: usize },
+
+    /// What the client's clock says, which it answers with the same message along with its
+    /// clock's reading as `t`
+    Clock { n: u32 },
+
+    /// We're shutting down, right before the connection is closed. If we're `restarting` the
+    /// client's pad is waiting for it once we're back, so it should hold on to its reclaim token.
+    Shutdown { restarting: bool },
+
+    /// The client's last message was ignored, as it wasn't one we understand
+    Error { message: String },
+}
+
+/// A JSON message we send clients without a `type`, as clients predating types know them, which
+/// they tell apart by their fields
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub(crate) enum UntypedMessage {
+    /// The pad the client attached, with its index and player
+    Attached {
+        attached: usize,
+        player: Option<u32>,
+        label: Option<String>,
+    },
+
+    /// Why the client couldn't attach a pad
+    Refused { refused: String },
+
+    /// The token to reclaim the client's pad with once it reconnects
+    Reclaim { reclaim: String },
+
+    /// The rumble and LED of the client's pad with the given index, in the first version of the
+    /// protocol
+    Feedback {
+        large: u8,
+        small: u8,
+        led: u8,
+        pad: usize,
+    },
+
+    /// The player the client's first pad is, as known before it's ready
+    Player { player: u32, label: String },
+}
+
+/// A JSON message we can send a client
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub(crate) enum ServerText {
+    Typed(ServerMessage),
+    Untyped(UntypedMessage),
+}
+
+impl From<ServerMessage> for ServerText {
+    fn from(message: ServerMessage) -> Self {
+        Self::Typed(message)
+    }
+}
+
+impl From<UntypedMessage> for ServerText {
+    fn from(message: UntypedMessage) -> Self {
+        Self::Untyped(message)
+    }
+}
+
+impl From<ServerMessage> for Message {
+    fn from(message: ServerMessage) -> Self {
+        ServerText::from(message).into()
+    }
+}
+
+impl From<UntypedMessage> for Message {
+    fn from(message: UntypedMessage) -> Self {
+        ServerText::from(message).into()
+    }
+}
+
+impl From<ServerText> for Message {
+    fn from(message: ServerText) -> Self {
+        // Nothing we send has maps with keys other than strings, which is all that can fail
+        Message::Text(serde_json::to_string(&message).unwrap())
+    }
+}
+
+/// What's served at `/protocol`: the schemas of the messages either side sends, along with their
+/// version and the names of the subprotocols they're sent over
+#[derive(Debug, Serialize)]
+pub(crate) struct Document {
+    version: u32,
+
+    /// The subprotocols clients can offer, newest first
+    protocols: Vec<&'static str>,
+    client: RootSchema,
+    server: RootSchema,
+}
+
+impl Document {
+    pub(crate) fn new(protocols: Vec<&'static str>) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            protocols,
+            client: schema_for!(ClientText),
+            server: schema_for!(ServerText),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use vigem_client_c::X360Buttons;
+
+    use super::*;
+    use crate::ramp::Ramp;
+
+    /// Check that the message is sent as `expected`, and parsed back from it as the same message
+    fn assert_client_round_trip(message: ClientMessage, expected: Value) {
+        assert_eq!(serde_json::to_value(&message).unwrap(), expected);
+        assert_eq!(
+            ClientMessage::parse(&expected.to_string()).unwrap(),
+            message
+        );
+        assert_eq!(
+            ClientText::parse(&expected.to_string()).unwrap(),
+            ClientText::Typed(message)
+        );
+    }
+
+    fn assert_server_round_trip(message: impl Into<ServerText>, expected: Value) {
+        let message = message.into();
+        assert_eq!(serde_json::to_value(&message).unwrap(), expected);
+        assert_eq!(
+            serde_json::from_value::<ServerText>(expected).unwrap(),
+            message
+        );
+    }
+
+    #[test]
+    fn test_client_round_trip() {
+        let state = X360State::builder()
+            .press(X360Buttons::A)
+            .left_stick(1, -2)
+            .build();
+        assert_client_round_trip(
+            ClientMessage::Gamepad(GamepadApiState {
+                buttons: vec![1.0, 0.5],
+                axes: vec![-1.0, 0.25],
+            }),
+            json!({ "type": "gamepad", "buttons": [1.0, 0.5], "axes": [-1.0, 0.25] }),
+        );
+        assert_client_round_trip(
+            ClientMessage::Calibrate(Calibration {
+                deadzone: 10.0,
+                ..Calibration::default()
+            }),
+            json!({
+                "type": "calibrate",
+                "deadzone": 10.0,
+                "trigger_threshold": 0.0,
+                "invert": { "left_x": false, "left_y": false, "right_x": false, "right_y": false },
+            }),
+        );
+        assert_client_round_trip(
+            ClientMessage::Turbo(TurboConfig {
+                buttons: X360Buttons::A,
+                frequency: 10.0,
+            }),
+            json!({ "type": "turbo", "buttons": 0x1000, "frequency": 10.0 }),
+        );
+        let remap = Remap {
+            swap_sticks: true,
+            ..Remap::default()
+        };
+        assert_client_round_trip(
+            ClientMessage::Remap {
+                pad: Some(1),
+                profile: Some("racing".to_string()),
+                remap: remap.clone(),
+            },
+            json!({
+                "type": "remap",
+                "pad": 1,
+                "profile": "racing",
+                "buttons": {},
+                "swap_shoulders_and_triggers": false,
+                "swap_sticks": true,
+                "invert": { "left_x": false, "left_y": false, "right_x": false, "right_y": false },
+                "synthesis": null,
+            }),
+        );
+        assert_client_round_trip(
+            ClientMessage::Motion(Orientation {
+                alpha: 90.0,
+                beta: -45.0,
+            }),
+            json!({ "type": "motion", "alpha": 90.0, "beta": -45.0 }),
+        );
+        assert_client_round_trip(
+            ClientMessage::MotionConfig(MotionConfig {
+                enabled: true,
+                sensitivity: 2.0,
+                recenter: X360Buttons::RIGHT_THUMB,
+            }),
+            json!({ "type": "motion_config", "enabled": true, "sensitivity": 2.0, "recenter": 0x80 }),
+        );
+        assert_client_round_trip(
+            ClientMessage::Neutral {
+                after_ms: Some(500),
+            },
+            json!({ "type": "neutral", "after_ms": 500 }),
+        );
+        assert_client_round_trip(
+            ClientMessage::Socd {
+                policy: Some(SocdPolicy::LastInput),
+            },
+            json!({ "type": "socd", "policy": "last_input" }),
+        );
+        assert_client_round_trip(
+            ClientMessage::Transformer {
+                pad: Some(0),
+                edit: TransformerEdit::Insert {
+                    index: Some(1),
+                    transformer: BuiltIn::Touch {
+                        curve: ResponseCurve::Gamma(2.0),
+                    },
+                },
+            },
+            json!({
+                "type": "transformer",
+                "pad": 0,
+                "op": "insert",
+                "index": 1,
+                "transformer": { "kind": "touch", "curve": { "gamma": 2.0 } },
+            }),
+        );
+        assert_client_round_trip(
+            ClientMessage::Transformer {
+                pad: None,
+                edit: TransformerEdit::Insert {
+                    index: None,
+                    transformer: BuiltIn::StickToDpad(StickToDpad::new(30, 5)),
+                },
+            },
+            json!({
+                "type": "transformer",
+                "op": "insert",
+                "transformer": { "kind": "stick_to_dpad", "threshold": 30, "hysteresis": 5 },
+            }),
+        );
+        assert_client_round_trip(
+            ClientMessage::Transformer {
+                pad: Some(1),
+                edit: TransformerEdit::Insert {
+                    index: None,
+                    transformer: BuiltIn::DigitalTriggers(DigitalTriggersConfig {
+                        left: Ramp::default(),
+                        right: Ramp {
+                            enabled: true,
+                            attack_ms: 80,
+                            release_ms: 40,
+                        },
+                    }),
+                },
+            },
+            json!({
+                "type": "transformer",
+                "pad": 1,
+                "op": "insert",
+                "transformer": {
+                    "kind": "digital_triggers",
+                    "left": { "enabled": false, "attack_ms": 0, "release_ms": 0 },
+                    "right": { "enabled": true, "attack_ms": 80, "release_ms": 40 },
+                },
+            }),
+        );
+        assert_client_round_trip(
+            ClientMessage::Transformer {
+                pad: None,
+                edit: TransformerEdit::Move { from: 0, to: 2 },
+            },
+            json!({ "type": "transformer", "op": "move", "from": 0, "to": 2 }),
+        );
+        assert_client_round_trip(
+            ClientMessage::Record {
+                pad: 1,
+                recording: true,
+            },
+            json!({ "type": "record", "pad": 1, "recording": true }),
+        );
+        assert_client_round_trip(
+            ClientMessage::SaveMacro { pad: 0, slot: 2 },
+            json!({ "type": "save_macro", "pad": 0, "slot": 2 }),
+        );
+        assert_client_round_trip(
+            ClientMessage::Macro { pad: 0, slot: 2 },
+            json!({ "type": "macro", "pad": 0, "slot": 2 }),
+        );
+        assert_eq!(
+            ClientMessage::parse(r#"{"type":"macro","slot":5}"#).unwrap(),
+            ClientMessage::Macro { pad: 0, slot: 5 }
+        );
+        assert_client_round_trip(ClientMessage::Attach, json!({ "type": "attach" }));
+        assert_client_round_trip(ClientMessage::Background, json!({ "type": "background" }));
+        assert_client_round_trip(ClientMessage::Foreground, json!({ "type": "foreground" }));
+        assert_client_round_trip(
+            ClientMessage::Update {
+                pad: 1,
+                state,
+                t: Some(1234.5),
+                touch: Some(TouchForce {
+                    lforce: Some(0.5),
+                    rforce: None,
+                }),
+            },
+            json!({
+                "type": "update",
+                "pad": 1,
+                "state": state,
+                "t": 1234.5,
+                "touch": { "lforce": 0.5, "rforce": null },
+            }),
+        );
+        assert_client_round_trip(
+            ClientMessage::Update {
+                pad: 0,
+                state,
+                t: None,
+                touch: None,
+            },
+            json!({ "type": "update", "pad": 0, "state": state }),
+        );
+        assert_client_round_trip(
+            ClientMessage::Clock { n: 3, t: 99.5 },
+            json!({ "type": "clock", "n": 3, "t": 99.5 }),
+        );
+        assert_client_round_trip(
+            ClientMessage::Keys {
+                down: ["KeyA", "Space"]
+                    .iter()
+                    .map(|key| key.to_string())
+                    .collect(),
+            },
+            json!({ "type": "keys", "down": ["KeyA", "Space"] }),
+        );
+
+        // Bare states are what the first version's clients send the most of
+        assert_eq!(
+            serde_json::to_value(ClientText::State(state)).unwrap(),
+            serde_json::to_value(state).unwrap()
+        );
+        assert_eq!(
+            ClientText::parse(&serde_json::to_string(&state).unwrap()).unwrap(),
+            ClientText::State(state)
+        );
+    }
+
+    #[test]
+    fn test_types() {
+        // Every message's `type` is one we know, and every one we know is a message's
+        let schema = serde_json::to_value(schema_for!(ClientMessage)).unwrap();
+        let mut types = schema["oneOf"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|variant| {
+                variant["properties"]["type"]["enum"][0]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        types.sort();
+        let mut known = ClientMessage::TYPES.to_vec();
+        known.sort_unstable();
+        assert_eq!(types, known);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let invalid = |data| ClientMessage::parse(data).unwrap_err();
+        assert!(matches!(
+            invalid(r#"{"type":"lightbar","color":"red"}"#),
+            InvalidMessage::UnknownType(kind) if kind == "lightbar"
+        ));
+        assert!(matches!(
+            invalid(r#"{"type":"update","pad":"first"}"#),
+            InvalidMessage::Malformed(_)
+        ));
+        assert!(matches!(invalid("{"), InvalidMessage::Malformed(_)));
+        assert!(matches!(invalid("[1, 2]"), InvalidMessage::Malformed(_)));
+        assert_eq!(
+            invalid(r#"{"type":"lightbar"}"#).to_string(),
+            r#"unknown message type "lightbar""#
+        );
+
+        // Without a type it's a state, whose error says what's wrong with it
+        let error = ClientText::parse(r#"{"type":"lightbar"}"#).unwrap_err();
+        assert!(matches!(error, InvalidMessage::UnknownType(_)));
+        let error = ClientText::parse(r#"{"type":"clock","n":1}"#).unwrap_err();
+        assert!(error.to_string().contains("missing field `t`"), "{}", error);
+        let error = ClientText::parse(r#"{"buttons":1}"#).unwrap_err();
+        assert!(error.to_string().contains("missing field"), "{}", error);
+    }
+
+    #[test]
+    fn test_server_round_trip() {
+        assert_server_round_trip(
+            ServerMessage::Hello {
+                server: "Gaming PC".to_string(),
+                version: "1.0.0".to_string(),
+                device: None,
+            },
+            json!({ "type": "hello", "server": "Gaming PC", "version": "1.0.0", "device": null }),
+        );
+        assert_server_round_trip(
+            ServerMessage::Ready {
+                player: Some(5),
+                label: Some("P5 (no XInput slot)".to_string()),
+                session: "reclaim-me".to_string(),
+                layout: Some("racing".to_string()),
+            },
+            json!({
+                "type": "ready",
+                "player": 5,
+                "label": "P5 (no XInput slot)",
+                "session": "reclaim-me",
+                "layout": "racing",
+            }),
+        );
+        assert_server_round_trip(
+            ServerMessage::Player { n: 2, pad: 1 },
+            json!({ "type": "player", "n": 2, "pad": 1 }),
+        );
+        assert_server_round_trip(
+            ServerMessage::Lightbar {
+                r: 255,
+                g: 0,
+                b: 64,
+                pad: 0,
+            },
+            json!({ "type": "lightbar", "r": 255, "g": 0, "b": 64, "pad": 0 }),
+        );
+        assert_server_round_trip(
+            ServerMessage::Haptic {
+                pattern: vec![10, 20, 10],
+                pad: 0,
+            },
+            json!({ "type": "haptic", "pattern": [10, 20, 10], "pad": 0 }),
+        );
+        assert_server_round_trip(
+            ServerMessage::Clock { n: 7 },
+            json!({ "type": "clock", "n": 7 }),
+        );
+        assert_server_round_trip(
+            ServerMessage::Shutdown { restarting: true },
+            json!({ "type": "shutdown", "restarting": true }),
+        );
+        assert_server_round_trip(
+            ServerMessage::Error {
+                message: "unknown message type \"lightbar\"".to_string(),
+            },
+            json!({ "type": "error", "message": "unknown message type \"lightbar\"" }),
+        );
+
+        assert_server_round_trip(
+            UntypedMessage::Attached {
+                attached: 1,
+                player: Some(2),
+                label: Some("P2".to_string()),
+            },
+            json!({ "attached": 1, "player": 2, "label": "P2" }),
+        );
+        assert_server_round_trip(
+            UntypedMessage::Refused {
+                refused: "released".to_string(),
+            },
+            json!({ "refused": "released" }),
+        );
+        assert_server_round_trip(
+            UntypedMessage::Reclaim {
+                reclaim: "reclaim-me".to_string(),
+            },
+            json!({ "reclaim": "reclaim-me" }),
+        );
+        assert_server_round_trip(
+            UntypedMessage::Feedback {
+                large: 255,
+                small: 0,
+                led: 2,
+                pad: 1,
+            },
+            json!({ "large": 255, "small": 0, "led": 2, "pad": 1 }),
+        );
+        assert_server_round_trip(
+            UntypedMessage::Player {
+                player: 1,
+                label: "P1".to_string(),
+            },
+            json!({ "player": 1, "label": "P1" }),
+        );
+    }
+
+    #[test]
+    fn test_document() {
+        let document = serde_json::to_value(Document::new(vec![
+            "sphrosyne.v2.binary",
+            "sphrosyne.v1.json",
+        ]))
+        .unwrap();
+        assert_eq!(document["version"], PROTOCOL_VERSION);
+        assert_eq!(
+            document["protocols"],
+            json!(["sphrosyne.v2.binary", "sphrosyne.v1.json"])
+        );
+        // Both schemas describe what's nested in the messages too
+        for (side, definition) in [("client", "X360State"), ("server", "ServerMessage")] {
+            assert!(
+                document[side]["definitions"][definition].is_object(),
+                "{} {}",
+                side,
+                definition
+            );
+        }
+
+        // Remapped buttons are described by name, as they're sent
+        let remap = document["client"]["definitions"]["ClientMessage"]["oneOf"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|variant| variant["properties"]["type"]["enum"][0] == "remap")
+            .unwrap();
+        assert_eq!(
+            remap["properties"]["buttons"]["additionalProperties"],
+            json!({ "type": "string" })
+        );
+    }
+}
@@ -0,0 +1,299 @@
+//! Digital triggers, which turn a trigger pulled all the way, as a button on the page does,
+//! into a pull that ramps up and back down over a while, for racing games where a trigger going
+//! from nothing to full at once slams on the brakes
+
+use std::time::{Duration, Instant};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use vigem_client_c::X360State;
+
+use crate::transform::Transformer;
+
+/// How far a trigger has to be pulled to count as its button being held, which only a trigger
+/// button or the very end of a real trigger's travel reaches
+const HELD_ABOVE: u8 = 250;
+
+/// How often a ramping trigger moves on without the client sending anything, which is about as
+/// often as any game reads its inputs
+const RAMP_STEP: Duration = Duration::from_millis(8);
+
+/// How one trigger ramps while its button is held, and once it's let go
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub(crate) struct Ramp {
+    pub(crate) enabled: bool,
+
+    /// How long the trigger takes to go from released to pulled all the way, 0 being at once
+    pub(crate) attack_ms: u32,
+
+    /// How long it takes to go from pulled all the way to released
+    pub(crate) release_ms: u32,
+}
+
+/// Which of a pad's triggers ramp, and how
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub(crate) struct DigitalTriggersConfig {
+    pub(crate) left: Ramp,
+    pub(crate) right: Ramp,
+}
+
+/// Where one trigger is along its ramp
+#[derive(Debug, Clone, Copy, Default)]
+struct Ramping {
+    ramp: Ramp,
+
+    /// How far the trigger is pulled now, kept finer than the pad's steps so that short steps
+    /// of slow ramps add up
+    level: f32,
+
+    /// Whether the level comes from the button being held, rather than the client's own pull
+    digital: bool,
+
+    /// How far the client last pulled the trigger
+    pulled: u8,
+}
+
+impl Ramping {
+    fn new(ramp: Ramp) -> Self {
+        Self {
+            ramp,
+            ..Self::default()
+        }
+    }
+
+    /// Where the trigger is once the client pulls it by `pulled`, `elapsed` after it last
+    /// pulled it by something else.
+    ///
+    /// A held button ramps the trigger up from wherever it is, and letting go of it ramps it
+    /// back down to whatever the client pulls it by now. Pulls short of a held button go through
+    /// as they are, unless they're below where the trigger is ramping down from.
+    fn apply(&mut self, pulled: u8, elapsed: Duration) -> u8 {
+        if !self.ramp.enabled {
+            self.pulled = pulled;
+            return pulled;
+        }
+        self.advance(elapsed);
+        self.pulled = pulled;
+        self.advance(Duration::ZERO);
+        self.level.round() as u8
+    }
+
+    /// Move along the ramp for `elapsed` of the client pulling the trigger as it last did
+    fn advance(&mut self, elapsed: Duration) {
+        let pulled = self.pulled;
+        if pulled > HELD_ABOVE {
+            self.digital = true;
+            self.level = towards(self.level, u8::MAX, self.ramp.attack_ms, elapsed);
+        } else if self.digital && f32::from(pulled) < self.level {
+            self.level = towards(self.level, pulled, self.ramp.release_ms, elapsed);
+        } else {
+            self.digital = false;
+            self.level = pulled.into();
+        }
+    }
+
+    /// Whether the trigger is partway up or down its ramp, and so moves on even if the client
+    /// keeps pulling it the same. Whatever is left of a ramp too small to change what's sent
+    /// doesn't count.
+    fn is_ramping(&self) -> bool {
+        let target = if self.pulled > HELD_ABOVE {
+            u8::MAX
+        } else {
+            self.pulled
+        };
+        self.ramp.enabled && self.digital && self.level.round() != f32::from(target)
+    }
+}
+
+/// Move `level` towards `target` as far as a ramp taking `ramp_ms` for the whole of a trigger's
+/// travel goes in `elapsed`, without going past it
+fn towards(level: f32, target: u8, ramp_ms: u32, elapsed: Duration) -> f32 {
+    let target = f32::from(target);
+    if ramp_ms == 0 {
+        return target;
+    }
+    let step = f32::from(u8::MAX) * elapsed.as_secs_f32() * 1000.0 / ramp_ms as f32;
+    if level < target {
+        (level + step).min(target)
+    } else {
+        (level - step).max(target)
+    }
+}
+
+/// Ramps a pad's triggers instead of letting them jump to pulled all the way and back, timed by
+/// the states going through it
+#[derive(Debug, Clone)]
+pub(crate) struct DigitalTriggers {
+    left: Ramping,
+    right: Ramping,
+
+    /// When the last state went through, which the next one's ramp goes on from
+    last: Option<Instant>,
+}
+
+impl DigitalTriggers {
+    pub(crate) fn new(config: DigitalTriggersConfig) -> Self {
+        Self {
+            left: Ramping::new(config.left),
+            right: Ramping::new(config.right),
+            last: None,
+        }
+    }
+}
+
+impl Transformer for DigitalTriggers {
+    fn transform(&mut self, mut state: X360State, now: Instant) -> X360State {
+        let elapsed = self
+            .last
+            .map_or(Duration::ZERO, |last| now.saturating_duration_since(last));
+        self.last = Some(now);
+        state.left_trigger = self.left.apply(state.left_trigger, elapsed);
+        state.right_trigger = self.right.apply(state.right_trigger, elapsed);
+        state
+    }
+
+    fn next_due(&self, now: Instant) -> Option<Instant> {
+        if !self.left.is_ramping() && !self.right.is_ramping() {
+            return None;
+        }
+        Some(self.last.map_or(now, |last| last + RAMP_STEP).max(now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triggers(attack_ms: u32, release_ms: u32) -> DigitalTriggers {
+        let ramp = Ramp {
+            enabled: true,
+            attack_ms,
+            release_ms,
+        };
+        DigitalTriggers::new(DigitalTriggersConfig {
+            left: Ramp::default(),
+            right: ramp,
+        })
+    }
+
+    fn pulled(left: u8, right: u8) -> X360State {
+        X360State::builder()
+            .left_trigger(left)
+            .right_trigger(right)
+            .build()
+    }
+
+    #[test]
+    fn test_ramp() {
+        // Reaching 255 over 100ms, and letting go over 50ms
+        let mut triggers = triggers(100, 50);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert_eq!(triggers.transform(pulled(255, 255), at(0)), pulled(255, 0));
+        assert_eq!(triggers.next_due(at(0)), Some(at(8)));
+        assert_eq!(
+            triggers.transform(pulled(255, 255), at(40)),
+            pulled(255, 102)
+        );
+        assert_eq!(
+            triggers.transform(pulled(255, 255), at(100)),
+            pulled(255, 255)
+        );
+        assert_eq!(triggers.next_due(at(100)), None);
+
+        // The ramp down starts from when the button was let go
+        assert_eq!(triggers.transform(pulled(0, 0), at(100)), pulled(0, 255));
+        assert_eq!(triggers.next_due(at(100)), Some(at(108)));
+        assert_eq!(triggers.transform(pulled(0, 0), at(120)), pulled(0, 153));
+        assert_eq!(triggers.transform(pulled(0, 0), at(150)), pulled(0, 0));
+        assert_eq!(triggers.next_due(at(150)), None);
+    }
+
+    #[test]
+    fn test_interrupted() {
+        let mut triggers = triggers(100, 100);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // Letting go halfway up ramps back down from there rather than from the top
+        let _ = triggers.transform(pulled(0, 255), at(0));
+        assert_eq!(
+            triggers.transform(pulled(0, 255), at(40)).right_trigger,
+            102
+        );
+        assert_eq!(triggers.transform(pulled(0, 0), at(40)).right_trigger, 102);
+        assert_eq!(triggers.transform(pulled(0, 0), at(60)).right_trigger, 51);
+        // And holding it again halfway down ramps back up from there
+        assert_eq!(triggers.transform(pulled(0, 255), at(60)).right_trigger, 51);
+        assert_eq!(
+            triggers.transform(pulled(0, 255), at(100)).right_trigger,
+            153
+        );
+        assert_eq!(
+            triggers.transform(pulled(0, 255), at(200)).right_trigger,
+            255
+        );
+    }
+
+    #[test]
+    fn test_instant() {
+        let mut triggers = triggers(0, 0);
+        let now = Instant::now();
+        assert_eq!(triggers.transform(pulled(0, 255), now), pulled(0, 255));
+        assert_eq!(triggers.next_due(now), None);
+        assert_eq!(triggers.transform(pulled(0, 0), now), pulled(0, 0));
+        assert_eq!(triggers.next_due(now), None);
+    }
+
+    #[test]
+    fn test_analog() {
+        let mut triggers = triggers(100, 100);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // Clients pulling their triggers smoothly go through untouched
+        for (ms, pull) in (0..).zip(&[0, 30, 90, 180, 250, 120, 10, 0]) {
+            let state = pulled(*pull, *pull);
+            assert_eq!(triggers.transform(state, at(ms)), state);
+            assert_eq!(triggers.next_due(at(ms)), None);
+        }
+
+        // Held from partway, the ramp goes on from where the client had pulled it
+        let _ = triggers.transform(pulled(0, 200), at(100));
+        assert_eq!(
+            triggers.transform(pulled(0, 255), at(110)).right_trigger,
+            200
+        );
+        assert_eq!(
+            triggers.transform(pulled(0, 255), at(130)).right_trigger,
+            251
+        );
+        // Letting go to a pull past where the ramp down is goes through as is
+        let _ = triggers.transform(pulled(0, 0), at(200));
+        assert_eq!(triggers.transform(pulled(0, 0), at(260)).right_trigger, 102);
+        assert_eq!(
+            triggers.transform(pulled(0, 150), at(260)).right_trigger,
+            150
+        );
+        assert_eq!(triggers.next_due(at(260)), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        let config: DigitalTriggersConfig =
+            serde_json::from_str(r#"{"right":{"enabled":true,"attack_ms":80,"release_ms":40}}"#)
+                .unwrap();
+        assert_eq!(config.left, Ramp::default());
+        assert_eq!(
+            config.right,
+            Ramp {
+                enabled: true,
+                attack_ms: 80,
+                release_ms: 40
+            }
+        );
+    }
+}
@@ -0,0 +1,115 @@
+//! Limiting how fast clients' messages are passed on, so that a flood from one client can't
+//! starve everybody else's pads
+
+use std::{
+    sync::atomic::AtomicU64,
+    time::{Duration, Instant},
+};
+
+/// How long a burst of messages at full speed the bucket holds enough tokens for, unless a
+/// single token takes longer to earn
+const BURST: Duration = Duration::from_millis(100);
+
+/// A token bucket letting through `rate` messages per second on average, along with short
+/// bursts above that. Tokens are kept track of as the time it took to earn them, which keeps the
+/// arithmetic exact.
+#[derive(Debug, Clone)]
+pub(crate) struct TokenBucket {
+    /// How long it takes to earn a token, if there's a limit at all
+    interval: Option<Duration>,
+
+    /// How much time's worth of tokens the bucket holds at most
+    capacity: Duration,
+
+    /// How much time's worth of tokens the bucket holds
+    tokens: Duration,
+
+    /// When tokens were last added
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket letting through `rate` messages per second, or any number if it's 0
+    pub(crate) fn new(rate: u32, now: Instant) -> Self {
+        let interval = (rate > 0).then(|| Duration::from_nanos(1_000_000_000 / u64::from(rate)));
+        let capacity = interval.map_or(Duration::ZERO, |interval| interval.max(BURST));
+        Self {
+            interval,
+            capacity,
+            tokens: capacity,
+            refilled: now,
+        }
+    }
+
+    /// Add the tokens earned since the last refill
+    fn refill(&mut self, now: Instant) {
+        let earned = now.saturating_duration_since(self.refilled);
+        self.tokens = (self.tokens + earned).min(self.capacity);
+        self.refilled = now;
+    }
+
+    /// Take a token if there's one, returning whether a message may be passed on at `now`
+    pub(crate) fn try_take(&mut self, now: Instant) -> bool {
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None => return true,
+        };
+        self.refill(now);
+        match self.tokens.checked_sub(interval) {
+            Some(tokens) => {
+                self.tokens = tokens;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// How long after `now` there will be a token to take
+    pub(crate) fn next_token(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        self.interval.map_or(Duration::ZERO, |interval| {
+            interval.saturating_sub(self.tokens)
+        })
+    }
+}
+
+/// How many of a pad's messages the rate limit kept from it, to be served at `/status`
+#[derive(Debug, Default)]
+pub(crate) struct Throttled {
+    /// States which were held back, and replaced by a later one before they could be passed on
+    pub(crate) coalesced: AtomicU64,
+
+    /// Messages which were thrown away, for being too large or for coming in too fast while
+    /// not being states
+    pub(crate) dropped: AtomicU64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_then_rate() {
+        // 100 per second holds 10 tokens, and earns one every 10ms
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut bucket = TokenBucket::new(100, start);
+
+        assert_eq!((0..20).filter(|_| bucket.try_take(at(0))).count(), 10);
+        assert_eq!(bucket.next_token(at(0)), Duration::from_millis(10));
+        assert!(!bucket.try_take(at(5)));
+        assert!(bucket.try_take(at(10)));
+        assert!(!bucket.try_take(at(10)));
+
+        // Going quiet refills the bucket, but only up to its capacity
+        assert_eq!((0..20).filter(|_| bucket.try_take(at(1000))).count(), 10);
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(0, now);
+        assert!((0..10_000).all(|_| bucket.try_take(now)));
+        assert_eq!(bucket.next_token(now), Duration::ZERO);
+    }
+}
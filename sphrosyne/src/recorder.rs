@@ -0,0 +1,244 @@
+//! Recording the updates pads receive to a file, and replaying them later without any phones
+//!
+//! A recording starts with a header made of the magic bytes `SPHR`, a version byte and the number
+//! of pads in the recording as a little-endian `u16`. It's followed by one fixed-size record per
+//! update: the microseconds since the recording started as a `u64`, the pad it was for as a `u16`
+//! and the state in its [wire encoding](X360State::to_bytes), all little-endian.
+//!
+//! Pads are numbered in the order they first received an update, so a recording made while
+//! clients came and went still replays with as few pads as were ever in use.
+
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::Path,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use eyre::{format_err, Result, WrapErr};
+use slog::{info, Logger};
+use vigem_client_c::{client::Client, X360State};
+
+const MAGIC: &[u8; 4] = b"SPHR";
+const VERSION: u8 = 1;
+
+/// Where the pad count is in the header, as it's rewritten whenever a new pad shows up
+const PAD_COUNT_OFFSET: u64 = 5;
+
+const RECORD_SIZE: usize = 8 + 2 + X360State::WIRE_SIZE;
+
+/// Writes every update pads receive to a recording
+#[derive(Debug)]
+pub(crate) struct Recorder<W = File> {
+    out: W,
+    started: Instant,
+
+    /// The number each pad id got in the recording
+    pads: HashMap<usize, u16>,
+}
+
+impl Recorder {
+    /// Start a new recording at the given path, overwriting whatever was there.
+    pub(crate) fn create(path: &Path) -> Result<Self> {
+        let file =
+            File::create(path).wrap_err_with(|| format!("Could not create {}", path.display()))?;
+        Self::new(file)
+    }
+}
+
+impl<W: Write + Seek> Recorder<W> {
+    fn new(mut out: W) -> Result<Self> {
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+        out.write_all(&0u16.to_le_bytes())?;
+        Ok(Self {
+            out,
+            started: Instant::now(),
+            pads: HashMap::new(),
+        })
+    }
+
+    /// Record that the pad with the given id received the given state.
+    ///
+    /// Each update is written out right away, so that the recording survives sphrosyne being killed.
+    pub(crate) fn record(&mut self, id: usize, state: X360State) -> Result<()> {
+        let at = u64::try_from(self.started.elapsed().as_micros()).unwrap_or(u64::MAX);
+        let pad = match self.pads.get(&id) {
+            Some(&pad) => pad,
+            None => {
+                let pad = u16::try_from(self.pads.len())
+                    .map_err(|_| format_err!("Too many pads to record"))?;
+                let _ = self.pads.insert(id, pad);
+                let _ = self.out.seek(SeekFrom::Start(PAD_COUNT_OFFSET))?;
+                self.out.write_all(&(pad + 1).to_le_bytes())?;
+                let _ = self.out.seek(SeekFrom::End(0))?;
+                pad
+            }
+        };
+
+        let mut record = [0; RECORD_SIZE];
+        record[0..8].copy_from_slice(&at.to_le_bytes());
+        record[8..10].copy_from_slice(&pad.to_le_bytes());
+        record[10..].copy_from_slice(&state.to_bytes());
+        self.out.write_all(&record)?;
+        Ok(())
+    }
+}
+
+/// An update read back from a recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Record {
+    /// How long after the recording started the update was received
+    pub(crate) at: Duration,
+
+    /// The number the pad got in the recording, below the recording's pad count
+    pub(crate) pad: u16,
+
+    pub(crate) state: X360State,
+}
+
+/// A recording being read back, which iterates over its updates in order
+#[derive(Debug)]
+pub(crate) struct Recording<R> {
+    /// How many pads the recording needs
+    pub(crate) pads: u16,
+
+    input: R,
+}
+
+impl Recording<BufReader<File>> {
+    /// Open the recording at the given path
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).wrap_err_with(|| format!("Could not open {}", path.display()))?;
+        Self::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read> Recording<R> {
+    fn new(mut input: R) -> Result<Self> {
+        let mut header = [0; 7];
+        input
+            .read_exact(&mut header)
+            .map_err(|_| format_err!("Not a sphrosyne recording"))?;
+        if &header[0..4] != MAGIC {
+            return Err(format_err!("Not a sphrosyne recording"));
+        }
+        if header[4] != VERSION {
+            return Err(format_err!("Unsupported recording version {}", header[4]));
+        }
+        Ok(Self {
+            pads: u16::from_le_bytes([header[5], header[6]]),
+            input,
+        })
+    }
+}
+
+impl<R: Read> Iterator for Recording<R> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut record = [0; RECORD_SIZE];
+        match self.input.read_exact(&mut record) {
+            Ok(()) => {}
+            // A recording cut short by a crash just ends at its last whole record
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return None,
+            Err(error) => return Some(Err(error.into())),
+        }
+
+        let mut at = [0; 8];
+        at.copy_from_slice(&record[0..8]);
+        let pad = u16::from_le_bytes([record[8], record[9]]);
+        if pad >= self.pads {
+            return Some(Err(format_err!(
+                "Update for pad {} in a recording of {} pads",
+                pad,
+                self.pads
+            )));
+        }
+
+        Some(
+            X360State::from_bytes_strict(&record[10..])
+                .map(|state| Record {
+                    at: Duration::from_micros(u64::from_le_bytes(at)),
+                    pad,
+                    state,
+                })
+                .map_err(Into::into),
+        )
+    }
+}
+
+/// Create as many pads as the recording at the given path needs, and feed them its updates with
+/// their original timing.
+pub(crate) fn replay(logger: &Logger, client: &Client, path: &Path) -> Result<()> {
+    let recording = Recording::open(path)?;
+    let pads = (0..recording.pads)
+        .map(|_| client.connect_x360_pad())
+        .collect::<Result<Vec<_>, _>>()?;
+    info!(logger, "replay.start"; "path" => %path.display(), "pads" => pads.len());
+
+    let started = Instant::now();
+    let mut updates = 0u64;
+    for record in recording {
+        let record = record?;
+        if let Some(wait) = record.at.checked_sub(started.elapsed()) {
+            sleep(wait);
+        }
+        pads[usize::from(record.pad)].update(record.state)?;
+        updates += 1;
+    }
+
+    info!(logger, "replay.done"; "updates" => updates);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use vigem_client_c::X360Buttons;
+
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let a = X360State::builder().press(X360Buttons::A).build();
+        let b = X360State::builder().left_stick(100, -100).build();
+
+        let mut recorder = Recorder::new(Cursor::new(Vec::new())).unwrap();
+        recorder.record(3, a).unwrap();
+        recorder.record(0, b).unwrap();
+        recorder.record(3, b).unwrap();
+
+        let recording = Recording::new(Cursor::new(recorder.out.into_inner())).unwrap();
+        assert_eq!(recording.pads, 2);
+        let records = recording.collect::<Result<Vec<_>>>().unwrap();
+        let updates = records
+            .iter()
+            .map(|record| (record.pad, record.state))
+            .collect::<Vec<_>>();
+        assert_eq!(updates, [(0, a), (1, b), (0, b)]);
+        assert!(records.windows(2).all(|pair| pair[0].at <= pair[1].at));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut recorder = Recorder::new(Cursor::new(Vec::new())).unwrap();
+        recorder.record(0, X360State::default()).unwrap();
+        recorder.record(0, X360State::default()).unwrap();
+
+        let mut bytes = recorder.out.into_inner();
+        bytes.truncate(bytes.len() - 1);
+        assert_eq!(Recording::new(Cursor::new(bytes)).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Recording::new(Cursor::new(b"nope".to_vec())).is_err());
+        assert!(Recording::new(Cursor::new(b"SPHR\x02\x00\x00".to_vec())).is_err());
+    }
+}
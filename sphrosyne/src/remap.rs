@@ -0,0 +1,431 @@
+//! Remapping a pad's inputs before they're sent to the bus, for games expecting another layout
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, mem,
+    path::Path,
+    time::Instant,
+};
+
+use eyre::{Result, WrapErr};
+use schemars::JsonSchema;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+use vigem_client_c::{X360Buttons, X360State};
+
+use crate::{calibration::Inversion, synthesis::Synthesis, transform::Transformer};
+
+/// The profiles used unless others are given with `--remaps`
+const DEFAULT_PROFILES: &str = include_str!("remaps.toml");
+
+/// How far a trigger has to be pulled to press its shoulder button when they swap places, the
+/// same threshold XInput uses to consider a trigger pressed
+const TRIGGER_PRESSED: u8 = 30;
+
+/// How to rearrange a pad's inputs. The default leaves them as they are.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub(crate) struct Remap {
+    /// Which buttons each button presses, by name. Buttons not in here press themselves, and
+    /// mapping one to `""` disables it.
+    #[serde(deserialize_with = "button_map", serialize_with = "button_names")]
+    #[schemars(with = "BTreeMap<String, String>")]
+    pub(crate) buttons: BTreeMap<X360Buttons, X360Buttons>,
+
+    /// Whether the shoulder buttons pull the triggers all the way and the triggers press the
+    /// shoulder buttons, which happens before the buttons are mapped
+    pub(crate) swap_shoulders_and_triggers: bool,
+
+    /// Whether the left and right sticks trade places, which happens before they're inverted
+    pub(crate) swap_sticks: bool,
+
+    pub(crate) invert: Inversion,
+
+    /// Which of the dpad and left stick is made up from the other, if either, which happens
+    /// before anything else so that the rest of the remap treats it like what the client sent
+    pub(crate) synthesis: Option<Synthesis>,
+}
+
+/// Parse a map from single button names to the names of the buttons they press
+fn button_map<'de, D>(deserializer: D) -> Result<BTreeMap<X360Buttons, X360Buttons>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let names = BTreeMap::<String, String>::deserialize(deserializer)?;
+    names
+        .iter()
+        .map(|(from, to)| {
+            let button = from.parse::<X360Buttons>().map_err(D::Error::custom)?;
+            if button.bits().count_ones() != 1 {
+                return Err(D::Error::custom(format!(
+                    "{:?} is not a single button",
+                    from
+                )));
+            }
+            Ok((button, to.parse().map_err(D::Error::custom)?))
+        })
+        .collect()
+}
+
+/// Write out a map from buttons to the buttons they press by their names, as [button_map] reads
+fn button_names<S>(
+    buttons: &BTreeMap<X360Buttons, X360Buttons>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    buttons
+        .iter()
+        .map(|(from, to)| (from.to_string(), to.to_string()))
+        .collect::<BTreeMap<_, _>>()
+        .serialize(serializer)
+}
+
+/// Rearrange a state's inputs according to the given remap, leaving out its
+/// [synthesis](Remap::synthesis), which has to keep track of the states it's given. Every mapping
+/// reads the state as it was given, so that swapping two buttons doesn't chain one into the other.
+pub(crate) fn remap(state: X360State, remap: &Remap) -> X360State {
+    let mut remapped = state;
+
+    if remap.swap_shoulders_and_triggers {
+        let pull = |pressed: bool| if pressed { u8::MAX } else { 0 };
+        remapped.left_trigger = pull(state.buttons.contains(X360Buttons::LEFT_SHOULDER));
+        remapped.right_trigger = pull(state.buttons.contains(X360Buttons::RIGHT_SHOULDER));
+        remapped.buttons.set(
+            X360Buttons::LEFT_SHOULDER,
+            state.left_trigger >= TRIGGER_PRESSED,
+        );
+        remapped.buttons.set(
+            X360Buttons::RIGHT_SHOULDER,
+            state.right_trigger >= TRIGGER_PRESSED,
+        );
+    }
+
+    if !remap.buttons.is_empty() {
+        let pressed = remapped.buttons;
+        remapped.buttons = (0..16)
+            .filter_map(|bit| X360Buttons::from_bits(1 << bit))
+            .filter(|button| pressed.contains(*button))
+            .map(|button| remap.buttons.get(&button).copied().unwrap_or(button))
+            .fold(X360Buttons::empty(), |buttons, button| buttons | button);
+    }
+
+    if remap.swap_sticks {
+        mem::swap(
+            &mut remapped.left_thumbstick,
+            &mut remapped.right_thumbstick,
+        );
+    }
+    let flip = |axis: &mut i16, invert: bool| {
+        if invert {
+            *axis = axis.saturating_neg();
+        }
+    };
+    flip(&mut remapped.left_thumbstick.x, remap.invert.left_x);
+    flip(&mut remapped.left_thumbstick.y, remap.invert.left_y);
+    flip(&mut remapped.right_thumbstick.x, remap.invert.right_x);
+    flip(&mut remapped.right_thumbstick.y, remap.invert.right_y);
+
+    remapped
+}
+
+/// A remap is the built-in button remap transformer, which clients can put in a pad's pipeline
+/// besides the remap every pad already has
+impl Transformer for Remap {
+    fn transform(&mut self, state: X360State, now: Instant) -> X360State {
+        let state = match &mut self.synthesis {
+            Some(synthesis) => synthesis.transform(state, now),
+            None => state,
+        };
+        remap(state, self)
+    }
+}
+
+/// The remaps clients can pick by name
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct RemapProfiles {
+    profiles: HashMap<String, Remap>,
+}
+
+impl Default for RemapProfiles {
+    fn default() -> Self {
+        toml::from_str(DEFAULT_PROFILES).expect("the built-in remap profiles are invalid")
+    }
+}
+
+impl RemapProfiles {
+    /// Load the profiles at the given path, or the built-in ones if there's no path
+    pub(crate) fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+        let data = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Could not read {}", path.display()))?;
+        toml::from_str(&data)
+            .wrap_err_with(|| format!("Invalid remap profiles in {}", path.display()))
+    }
+
+    /// The profile with the given name, if there's one
+    pub(crate) fn get(&self, name: &str) -> Option<&Remap> {
+        self.profiles.get(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthesis::StickToDpad;
+
+    fn buttons(buttons: X360Buttons) -> X360State {
+        X360State::builder().press(buttons).build()
+    }
+
+    fn mapping(pairs: &[(X360Buttons, X360Buttons)]) -> Remap {
+        Remap {
+            buttons: pairs.iter().copied().collect(),
+            ..Remap::default()
+        }
+    }
+
+    #[test]
+    fn test_identity() {
+        let state = X360State::builder()
+            .press(X360Buttons::A | X360Buttons::LEFT_SHOULDER | X360Buttons::GUIDE)
+            .left_trigger(10)
+            .right_trigger(200)
+            .left_stick(-100, 100)
+            .right_stick(i16::MIN, i16::MAX)
+            .build();
+        assert_eq!(remap(state, &Remap::default()), state);
+        assert_eq!(
+            remap(X360State::default(), &Remap::default()),
+            X360State::default()
+        );
+    }
+
+    #[test]
+    fn test_swap() {
+        let remap_ab = mapping(&[
+            (X360Buttons::A, X360Buttons::B),
+            (X360Buttons::B, X360Buttons::A),
+        ]);
+        assert_eq!(
+            remap(buttons(X360Buttons::A), &remap_ab),
+            buttons(X360Buttons::B)
+        );
+        assert_eq!(
+            remap(buttons(X360Buttons::B), &remap_ab),
+            buttons(X360Buttons::A)
+        );
+        assert_eq!(
+            remap(buttons(X360Buttons::A | X360Buttons::B), &remap_ab),
+            buttons(X360Buttons::A | X360Buttons::B)
+        );
+        // Unmapped buttons are left alone
+        assert_eq!(
+            remap(buttons(X360Buttons::A | X360Buttons::START), &remap_ab),
+            buttons(X360Buttons::B | X360Buttons::START)
+        );
+    }
+
+    #[test]
+    fn test_chain() {
+        // A presses B and B presses X, but that doesn't make A press X
+        let chain = mapping(&[
+            (X360Buttons::A, X360Buttons::B),
+            (X360Buttons::B, X360Buttons::X),
+        ]);
+        assert_eq!(
+            remap(buttons(X360Buttons::A), &chain),
+            buttons(X360Buttons::B)
+        );
+        assert_eq!(
+            remap(buttons(X360Buttons::B), &chain),
+            buttons(X360Buttons::X)
+        );
+        assert_eq!(
+            remap(buttons(X360Buttons::A | X360Buttons::B), &chain),
+            buttons(X360Buttons::B | X360Buttons::X)
+        );
+    }
+
+    #[test]
+    fn test_many_to_one() {
+        let both = mapping(&[
+            (X360Buttons::A, X360Buttons::X),
+            (X360Buttons::B, X360Buttons::X),
+        ]);
+        assert_eq!(
+            remap(buttons(X360Buttons::A), &both),
+            buttons(X360Buttons::X)
+        );
+        assert_eq!(
+            remap(buttons(X360Buttons::B), &both),
+            buttons(X360Buttons::X)
+        );
+        assert_eq!(
+            remap(buttons(X360Buttons::A | X360Buttons::B), &both),
+            buttons(X360Buttons::X)
+        );
+        // X itself still presses X, and letting go of one of A and B keeps X pressed
+        assert_eq!(
+            remap(buttons(X360Buttons::X | X360Buttons::B), &both),
+            buttons(X360Buttons::X)
+        );
+    }
+
+    #[test]
+    fn test_one_to_many_and_none() {
+        let remap_guide = mapping(&[
+            (X360Buttons::GUIDE, X360Buttons::START | X360Buttons::BACK),
+            (X360Buttons::Y, X360Buttons::empty()),
+        ]);
+        assert_eq!(
+            remap(buttons(X360Buttons::GUIDE | X360Buttons::Y), &remap_guide),
+            buttons(X360Buttons::START | X360Buttons::BACK)
+        );
+    }
+
+    #[test]
+    fn test_shoulders_and_triggers() {
+        let swap = Remap {
+            swap_shoulders_and_triggers: true,
+            ..Remap::default()
+        };
+        let state = X360State::builder()
+            .press(X360Buttons::LEFT_SHOULDER | X360Buttons::A)
+            .left_trigger(TRIGGER_PRESSED - 1)
+            .right_trigger(TRIGGER_PRESSED)
+            .build();
+        let swapped = X360State::builder()
+            .press(X360Buttons::RIGHT_SHOULDER | X360Buttons::A)
+            .left_trigger(u8::MAX)
+            .build();
+        assert_eq!(remap(state, &swap), swapped);
+
+        // The shoulder buttons pressed by the triggers go on to be mapped
+        let chained = Remap {
+            buttons: [(X360Buttons::RIGHT_SHOULDER, X360Buttons::Y)]
+                .iter()
+                .copied()
+                .collect(),
+            ..swap
+        };
+        assert_eq!(
+            remap(state, &chained).buttons,
+            X360Buttons::Y | X360Buttons::A
+        );
+    }
+
+    #[test]
+    fn test_sticks() {
+        let state = X360State::builder()
+            .left_stick(1, 2)
+            .right_stick(i16::MIN, 4)
+            .build();
+        let southpaw = Remap {
+            swap_sticks: true,
+            invert: Inversion {
+                left_x: true,
+                right_y: true,
+                ..Inversion::default()
+            },
+            ..Remap::default()
+        };
+        let remapped = remap(state, &southpaw);
+        assert_eq!(remapped.left_thumbstick, (i16::MAX, 4));
+        assert_eq!(remapped.right_thumbstick, (1, -2));
+    }
+
+    #[test]
+    fn test_parse() {
+        let parsed: Remap = serde_json::from_str(
+            r#"{"buttons":{"a":"b","LB":"left_thumb|right_thumb","y":""},"swap_sticks":true}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            parsed,
+            Remap {
+                buttons: [
+                    (X360Buttons::A, X360Buttons::B),
+                    (
+                        X360Buttons::LEFT_SHOULDER,
+                        X360Buttons::LEFT_THUMB | X360Buttons::RIGHT_THUMB
+                    ),
+                    (X360Buttons::Y, X360Buttons::empty()),
+                ]
+                .iter()
+                .copied()
+                .collect(),
+                swap_sticks: true,
+                ..Remap::default()
+            }
+        );
+
+        assert!(serde_json::from_str::<Remap>(r#"{"buttons":{"a|b":"x"}}"#).is_err());
+        assert!(serde_json::from_str::<Remap>(r#"{"buttons":{"":"x"}}"#).is_err());
+        assert!(serde_json::from_str::<Remap>(r#"{"buttons":{"a":"turbo"}}"#).is_err());
+    }
+
+    #[test]
+    fn test_default_profiles() {
+        let profiles = RemapProfiles::default();
+        let nintendo = profiles.get("nintendo").unwrap();
+        assert_eq!(
+            remap(buttons(X360Buttons::A | X360Buttons::X), nintendo),
+            buttons(X360Buttons::B | X360Buttons::Y)
+        );
+        assert!(profiles.get("southpaw").unwrap().swap_sticks);
+        assert!(
+            profiles
+                .get("shoulders")
+                .unwrap()
+                .swap_shoulders_and_triggers
+        );
+        assert_eq!(
+            profiles.get("stick_dpad").unwrap().synthesis,
+            Some(Synthesis::StickToDpad(StickToDpad::default()))
+        );
+        assert_eq!(
+            profiles.get("dpad_stick").unwrap().synthesis,
+            Some(Synthesis::DpadToStick)
+        );
+        assert!(profiles.get("nope").is_none());
+    }
+
+    #[test]
+    fn test_synthesis() {
+        let now = Instant::now();
+
+        // What's made up goes through the rest of the remap like what the client sent
+        let mut remap = Remap {
+            swap_sticks: true,
+            synthesis: Some(Synthesis::DpadToStick),
+            ..Remap::default()
+        };
+        let state = remap.transform(buttons(X360Buttons::DPAD_LEFT), now);
+        assert_eq!(state.right_thumbstick, (i16::MIN, 0));
+        assert_eq!(state.left_thumbstick, (0, 0));
+
+        // A remap made up from the same config is the same one, however far along its
+        // synthesis is
+        let mut remap = Remap {
+            buttons: [(X360Buttons::DPAD_UP, X360Buttons::A)]
+                .iter()
+                .copied()
+                .collect(),
+            synthesis: Some(Synthesis::StickToDpad(StickToDpad::default())),
+            ..Remap::default()
+        };
+        let pushed = X360State::builder().left_stick(0, i16::MAX).build();
+        assert_eq!(remap.transform(pushed, now).buttons, X360Buttons::A);
+        assert_eq!(
+            remap,
+            Remap {
+                synthesis: Some(Synthesis::StickToDpad(StickToDpad::default())),
+                ..remap.clone()
+            }
+        );
+    }
+}
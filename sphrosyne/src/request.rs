@@ -1,7 +1,547 @@
-use vigem_client_c::X360State;
+use std::{
+    net::IpAddr,
+    sync::{
+        mpsc::{Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use slog::{Record, Serializer, KV};
+use vigem_client_c::{
+    client::{UserIndex, X360NotificationData},
+    SocdPolicy, X360State,
+};
+
+use crate::{
+    calibration::Calibration,
+    close::CloseReason,
+    latency::Latency,
+    motion::{MotionConfig, Orientation},
+    profiles::{Profile, ProfileChange},
+    ratelimit::Throttled,
+    remap::Remap,
+    snapshot::PadSnapshot,
+    status::{ClientStatus, Status},
+    transform::Transformer,
+    turbo::TurboConfig,
+};
+
+/// How many requests can be waiting for the pads at once, past which whoever sends one has to
+/// wait, so that a flood of updates slows down the connections sending it instead of eating up
+/// memory
+pub(crate) const QUEUE_SIZE: usize = 256;
 
 pub(crate) enum PadRequest {
-    NewID,
+    /// Get a pad of the given type for the connection, and for the device with the given
+    /// identifier if the client sent one, either one of the pads created at startup which
+    /// nobody is using or a new one
+    Acquire(Connection, Option<String>, PadType, Sender<NewPadReply>),
+
+    /// Get back the detached pad with the given reclaim token for the connection, or a new one
+    /// of the given type for the device with the given identifier if it's gone
+    Reclaim(
+        String,
+        Connection,
+        Option<String>,
+        PadType,
+        Sender<NewPadReply>,
+    ),
+
+    /// The pad's client lost its connection, so keep the pad around in case it comes back
+    Detach(usize),
+
+    /// The pad's client is done with it, so reset it for the next client if it was created at
+    /// startup and remove it otherwise
+    Release(usize),
+
+    /// A state came in for the pad, to be taken from its [LatestState]
+    Update(usize),
+
+    /// Change how the pad's states are calibrated, remembering it for the pad's device
+    Calibrate(usize, Calibration),
+
+    /// Change which of the pad's buttons pulse while held, and how fast
+    Turbo(usize, TurboConfig),
+
+    /// Change how the pad's inputs are rearranged, keeping whatever the client is holding
+    Remap(usize, Remap),
+
+    /// Start recording the pad's states for a macro, or stop recording them
+    Record(usize, bool),
+
+    /// Keep what the pad recorded last as the macro in the given slot, remembering it for the
+    /// pad's device
+    SaveMacro(usize, u8),
+
+    /// Play the macro in the given slot through the pad, merged with whatever its client holds
+    PlayMacro(usize, u8),
+
+    /// Remember something the client of the device with the given identifier did, for when it
+    /// connects again
+    Remember(String, ProfileChange),
+
+    /// The phone controlling the pad is now facing this way, which moves its right stick
+    Motion(usize, Orientation),
+
+    /// Change how the phone's motion moves the pad's right stick
+    MotionConfig(usize, MotionConfig),
+
+    /// The pad's page went into the background, e.g. as the phone was locked, so make the pad
+    /// neutral and keep it that way until [PadRequest::Resume]
+    Suspend(usize),
+
+    /// The pad's page is in the foreground again, so let its states through again
+    Resume(usize),
+
+    /// Change how long the pad can go without a state before it's made neutral, going back to
+    /// the server's interval if there's none
+    Neutral(usize, Option<Duration>),
+
+    /// Change what's done about the pad's dpad being held both ways at once, going back to the
+    /// server's policy if there's none
+    Socd(usize, Option<SocdPolicy>),
+
+    /// Change the transformers the pad's states go through last
+    Pipeline(usize, PipelineEdit),
+
+    /// Take the pad away from its client, e.g. from the window, which releases it right away
+    /// if the client is gone and has its connection released and closed otherwise
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
     Discard(usize),
-    Update(usize, X360State),
+
+    /// Take the pad away from its client like [PadRequest::Discard], e.g. from the admin
+    /// routes, replying whether it had one to take it from
+    Kick(usize, Sender<bool>),
+
+    /// Play the [test input](crate::test_input) through the pad, e.g. from the admin routes,
+    /// unless its client is sending states, replying whether it's playing
+    TestInput(usize, Sender<TestInputReply>),
+
+    /// Reply with a snapshot of the bus and pads' state
+    Status(Sender<Status>),
+
+    /// Reply with who is controlling each pad which has a client, for the admin routes
+    Clients(Sender<Vec<ClientStatus>>),
+
+    /// Reply with what it takes to create every pad again after a restart
+    Snapshot(Sender<Vec<PadSnapshot>>),
+
+    /// Remove every pad from the bus and stop handling requests
+    Shutdown,
+}
+
+/// A websocket connection, which everything logged about it and the pads it gets carries, so
+/// that a client can be followed from the server to the pads and back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Connection {
+    /// A short random identifier, which is only meant to tell connections apart in the logs
+    pub(crate) id: String,
+
+    /// The address the client connected from
+    pub(crate) ip: IpAddr,
+
+    /// When the client connected
+    pub(crate) since: SystemTime,
+}
+
+impl Connection {
+    pub(crate) fn new(ip: IpAddr) -> Self {
+        let id: u32 = rand::thread_rng().gen();
+        Self {
+            id: format!("{:08x}", id),
+            ip,
+            since: SystemTime::now(),
+        }
+    }
+}
+
+impl KV for Connection {
+    fn serialize(&self, _: &Record, serializer: &mut dyn Serializer) -> slog::Result {
+        serializer.emit_str("conn", &self.id)?;
+        serializer.emit_arguments("ip", &format_args!("{}", self.ip))
+    }
+}
+
+/// Which kind of controller a pad shows up as on the bus. Clients send xbox 360 states either
+/// way, which are laid out on a dualshock 4 on their way to the bus if that's what the pad is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PadType {
+    #[default]
+    X360,
+    DS4,
+}
+
+impl PadType {
+    /// Find a pad type by the name used for it in the `type` query parameter
+    pub(crate) fn find(name: &str) -> Option<Self> {
+        match name {
+            "x360" => Some(Self::X360),
+            "ds4" => Some(Self::DS4),
+            _ => None,
+        }
+    }
+}
+
+/// What came of asking for a pad to play the test input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TestInputReply {
+    Started,
+
+    /// There's no such pad, or nobody is using it
+    NoSuchPad,
+
+    /// The pad's client is sending states, which take precedence
+    Busy,
+}
+
+/// A change to a pad's [Pipeline](crate::transform::Pipeline) of transformers
+pub(crate) enum PipelineEdit {
+    /// Put a transformer at the given position, or last if it's past the end. It has to be
+    /// [Sync] as well for requests to be, though the pipeline only needs it to be [Send].
+    Insert(usize, Box<dyn Transformer + Send + Sync>),
+
+    /// Take out the transformer at the given position
+    Remove(usize),
+
+    /// Move the transformer at the first position to the second
+    Move(usize, usize),
+}
+
+/// The LED number in the feedback of dualshock 4 pads, which have a lightbar rather than player
+/// LEDs
+pub(crate) const NO_LED: u8 = u8::MAX;
+
+/// How many xbox 360 pads the bus gives a user index, which is what XInput games find them by
+pub(crate) const XINPUT_SLOTS: u32 = UserIndex::MAX + 1;
+
+/// Which player a pad is, counting from 1, by its LED if the game lit one, else by its user index.
+/// Pads beyond the [XINPUT_SLOTS] are numbered on from there, although XInput games can't see
+/// them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Player {
+    pub(crate) number: u32,
+
+    /// Whether the pad has a user index
+    pub(crate) xinput: bool,
+}
+
+impl std::fmt::Display for Player {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "P{}", self.number)?;
+        if !self.xinput {
+            f.write_str(" (no XInput slot)")?;
+        }
+        Ok(())
+    }
+}
+
+/// What a game told one of the pads, as forwarded to its client
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Notification {
+    /// The rumble, and the LED of xbox 360 pads, which dualshock 4 pads leave unlit
+    pub(crate) data: X360NotificationData,
+
+    /// The lightbar's red, green and blue, for dualshock 4 pads
+    pub(crate) lightbar: Option<(u8, u8, u8)>,
+}
+
+impl From<X360NotificationData> for Notification {
+    fn from(data: X360NotificationData) -> Self {
+        Self {
+            data,
+            lightbar: None,
+        }
+    }
+}
+
+/// The reply to a [PadRequest::Acquire] or [PadRequest::Reclaim], with the reason we couldn't get a pad if that's the case
+pub(crate) type NewPadReply = eyre::Result<NewPad>;
+
+/// A newly acquired or reclaimed pad
+pub(crate) struct NewPad {
+    pub(crate) id: usize,
+
+    /// Rumble, LED and lightbar notifications for the new pad
+    pub(crate) feedback: Receiver<Notification>,
+
+    /// The token to present in a [PadRequest::Reclaim] to get this pad back after a disconnect
+    pub(crate) reclaim: String,
+
+    /// Which player the pad is, if it's an xbox 360 pad
+    pub(crate) player: Option<Player>,
+
+    /// The identifier of the device the pad was created for, if it sent one
+    pub(crate) device: Option<String>,
+
+    /// What was remembered of the device, if the pad was newly assigned to one we've seen before
+    pub(crate) profile: Option<Profile>,
+
+    /// The kind of controller the pad is, which the pads the client attaches later are too
+    pub(crate) pad_type: PadType,
+
+    /// Where to count the messages for the pad which the rate limit kept from it
+    pub(crate) throttled: Arc<Throttled>,
+
+    /// Where to put the states the client sends for the pad
+    pub(crate) latest: Arc<LatestState>,
+
+    /// Where to record how long the client's states take to reach us
+    pub(crate) latency: Arc<Latency>,
+}
+
+/// The latest state a client sent for a pad, until the pads get around to sending it to the bus.
+///
+/// States are put in here rather than into the requests, so that those which come in while
+/// the pads are busy, e.g. waiting on a slow update, replace each other instead of queueing up
+/// behind it. Only the latest is sent once the pads catch up, which keeps the delay between a
+/// client sending a state and it reaching the bus down to about one update no matter the load.
+///
+/// Once the pad is [discarded](PadRequest::Discard) states are turned away until it goes to its
+/// next client, which is how its current one finds out, along with why.
+#[derive(Debug, Default)]
+pub(crate) struct LatestState {
+    state: Mutex<Option<(X360State, Instant)>>,
+    discarded: Mutex<Option<CloseReason>>,
+}
+
+impl LatestState {
+    /// Put in a state along with when its message came in, replacing the one the pads haven't
+    /// taken yet if there's one. Returns whether the pads have to be sent a
+    /// [PadRequest::Update] to take it, which they don't if they were already sent one for the
+    /// state it replaced, or if the pad was discarded and the state thrown away.
+    pub(crate) fn put(&self, state: X360State, received: Instant) -> bool {
+        if self.is_discarded() {
+            return false;
+        }
+        self.state
+            .lock()
+            .unwrap()
+            .replace((state, received))
+            .is_none()
+    }
+
+    /// Take the latest state and when its message came in, if one was put in since it was
+    /// last taken
+    pub(crate) fn take(&self) -> Option<(X360State, Instant)> {
+        self.state.lock().unwrap().take()
+    }
+
+    /// Throw away the state the pads haven't taken yet and turn away any later ones, for the
+    /// client to be told the given reason
+    pub(crate) fn discard(&self, reason: CloseReason) {
+        *self.discarded.lock().unwrap() = Some(reason);
+        let _ = self.take();
+    }
+
+    /// Why the pad was discarded, if it was and its client should let go of it
+    pub(crate) fn discarded(&self) -> Option<CloseReason> {
+        *self.discarded.lock().unwrap()
+    }
+
+    /// Whether the pad was discarded, and its client should let go of it
+    pub(crate) fn is_discarded(&self) -> bool {
+        self.discarded().is_some()
+    }
+
+    /// Forget about the previous client's state, and let states in again if the pad was
+    /// discarded, for the pad's next client
+    pub(crate) fn reset(&self) {
+        let _ = self.take();
+        *self.discarded.lock().unwrap() = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fmt,
+        sync::mpsc::{channel, sync_channel},
+        thread::{sleep, spawn},
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+
+    /// How long the simulated slow updates take
+    const SLOW_UPDATE: Duration = Duration::from_millis(5);
+
+    /// How many states clients send while the first slow update is underway
+    const BURST: u8 = 50;
+
+    fn state(i: u8) -> X360State {
+        X360State::builder().left_trigger(i).build()
+    }
+
+    #[test]
+    fn test_pad_type() {
+        assert_eq!(PadType::find("x360"), Some(PadType::X360));
+        assert_eq!(PadType::find("ds4"), Some(PadType::DS4));
+        assert_eq!(PadType::find("DS4"), None);
+        assert_eq!(PadType::find("xboxone"), None);
+        assert_eq!(serde_json::to_string(&PadType::DS4).unwrap(), r#""ds4""#);
+    }
+
+    #[test]
+    fn test_player() {
+        let player = Player {
+            number: 2,
+            xinput: true,
+        };
+        assert_eq!(player.to_string(), "P2");
+        let player = Player {
+            number: 5,
+            xinput: false,
+        };
+        assert_eq!(player.to_string(), "P5 (no XInput slot)");
+    }
+
+    /// Collects the keys and values of whatever is logged through it, context included
+    #[derive(Default)]
+    struct Collect(Mutex<Vec<(String, String)>>);
+
+    impl slog::Serializer for &Collect {
+        fn emit_arguments(&mut self, key: slog::Key, value: &fmt::Arguments) -> slog::Result {
+            self.0
+                .lock()
+                .unwrap()
+                .push((key.to_string(), value.to_string()));
+            Ok(())
+        }
+    }
+
+    impl slog::Drain for Collect {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(&self, record: &Record, values: &slog::OwnedKVList) -> Result<(), slog::Never> {
+            let mut serializer = self;
+            record.kv().serialize(record, &mut serializer).unwrap();
+            values.serialize(record, &mut serializer).unwrap();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_connection() {
+        let ip = IpAddr::from([192, 168, 1, 7]);
+        let (first, second) = (Connection::new(ip), Connection::new(ip));
+        assert_eq!(first.id.len(), 8);
+        assert!(first.id.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(first.id, second.id);
+
+        // Loggers made for the connection carry it in everything they log
+        let collect = Arc::new(Collect::default());
+        let logger = slog::Logger::root(Arc::clone(&collect), slog::o!());
+        slog::info!(logger.new(slog::o!(first.clone())), "test"; "id" => 3);
+        let mut logged = collect.0.lock().unwrap().clone();
+        logged.sort();
+        let pair = |key: &str, value: &str| (key.to_string(), value.to_string());
+        assert_eq!(
+            logged,
+            [
+                pair("conn", &first.id),
+                pair("id", "3"),
+                pair("ip", "192.168.1.7")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_latest_wins() {
+        let latest = LatestState::default();
+        let (first, second) = (Instant::now(), Instant::now() + Duration::from_millis(1));
+        assert_eq!(latest.take(), None);
+        assert!(latest.put(state(1), first));
+        assert!(!latest.put(state(2), second));
+        assert_eq!(latest.take(), Some((state(2), second)));
+        assert_eq!(latest.take(), None);
+        assert!(latest.put(state(3), first));
+    }
+
+    #[test]
+    fn test_discard() {
+        let latest = LatestState::default();
+        assert!(latest.put(state(1), Instant::now()));
+        latest.discard(CloseReason::PadFailed);
+        assert_eq!(latest.discarded(), Some(CloseReason::PadFailed));
+        assert_eq!(latest.take(), None);
+        assert!(!latest.put(state(2), Instant::now()));
+        assert_eq!(latest.take(), None);
+
+        // The next client gets to use the pad as usual
+        latest.reset();
+        assert!(!latest.is_discarded());
+        assert!(latest.put(state(3), Instant::now()));
+        assert_eq!(latest.take().map(|(state, _)| state), Some(state(3)));
+    }
+
+    /// Send a burst of states to a consumer whose updates are slow, either through a queue of
+    /// every state or through a [LatestState], returning how many updates the consumer made and
+    /// how long after the burst the last state reached it
+    fn burst(latest_wins: bool) -> (usize, Duration) {
+        let (req_tx, req_rx) = sync_channel(QUEUE_SIZE);
+        let (done_tx, done_rx) = channel();
+        let latest = Arc::new(LatestState::default());
+        let consumer = {
+            let latest = Arc::clone(&latest);
+            spawn(move || {
+                let mut updates = 0;
+                for state in req_rx {
+                    let state = if latest_wins {
+                        latest.take().map(|(state, _)| state)
+                    } else {
+                        Some(state)
+                    };
+                    if let Some(state) = state {
+                        sleep(SLOW_UPDATE);
+                        updates += 1;
+                        if state == self::state(BURST) {
+                            done_tx.send(Instant::now()).unwrap();
+                        }
+                    }
+                }
+                updates
+            })
+        };
+
+        for i in 1..=BURST {
+            if !latest_wins || latest.put(state(i), Instant::now()) {
+                req_tx.send(state(i)).unwrap();
+            }
+        }
+        let sent = Instant::now();
+        let done = done_rx.recv().unwrap();
+        drop(req_tx);
+        (
+            consumer.join().unwrap(),
+            done.saturating_duration_since(sent),
+        )
+    }
+
+    #[test]
+    fn test_bounded_latency() {
+        let (queued_updates, queued_latency) = burst(false);
+        let (latest_updates, latest_latency) = burst(true);
+
+        // Every queued state is sent, one slow update after the other
+        assert_eq!(queued_updates, usize::from(BURST));
+        assert!(queued_latency >= SLOW_UPDATE * u32::from(BURST - 1));
+
+        // Whereas the states coming in during an update are boiled down to the last one
+        assert!(
+            latest_updates < usize::from(BURST / 2),
+            "{} updates",
+            latest_updates
+        );
+        assert!(
+            latest_latency < queued_latency / 4,
+            "{:?} against {:?}",
+            latest_latency,
+            queued_latency
+        );
+    }
 }
@@ -1,187 +1,4213 @@
 use std::{
-    io::{self, Cursor},
-    sync::mpsc::{Receiver, Sender},
-    thread::spawn,
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+    fmt::{Display, Write},
+    io::{self, Cursor, Read},
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{sleep, spawn, JoinHandle},
+    time::{Duration, Instant},
 };
 
 use build_html::{Html, HtmlContainer, HtmlPage};
 use eyre::{format_err, Result};
-use image::GenericImage;
 use qrcodegen::{QrCode, QrCodeEcc};
-use slog::{debug, error, info, o, Logger};
-use tiny_http::{Header, Request, Response, Server, StatusCode};
-use tungstenite::{protocol::Role, Message, WebSocket};
-use vigem_client_c::X360State;
+use serde::Serialize;
+use slog::{debug, error, info, o, warn, Logger};
+use tiny_http::{Header, Method, ReadWrite, Request, Response, Server, StatusCode};
+use tungstenite::{
+    protocol::{Role, WebSocketConfig},
+    Message, WebSocket,
+};
+use vigem_client_c::{client::X360NotificationData, SocdPolicy, X360State};
+
+use crate::{
+    args::Args,
+    assets::{self, Assets},
+    auth::Token,
+    calibration::{valid_device, Calibration},
+    close::CloseReason,
+    custom_layout::{self, CustomLayout, LayoutStore, MAX_LAYOUT_SIZE},
+    discovery::{self, Advertisement},
+    haptics::Haptics,
+    interfaces::{self, Address},
+    keymap::Keymap,
+    latency::{ClockOffset, Latency, CLOCK_ROUNDS},
+    layout::{self, Layout, LAYOUTS},
+    metrics::Metrics,
+    motion::{MotionConfig, Orientation},
+    outbox::{Outbox, Outboxes},
+    pads::{ServerFull, DRIVER_POLL_INTERVAL, DRIVER_URL},
+    profiles::{Profile, ProfileChange},
+    protocol::{
+        ClientMessage, ClientText, Document, InvalidMessage, ServerMessage, TransformerEdit,
+        UntypedMessage,
+    },
+    ratelimit::{Throttled, TokenBucket},
+    remap::{Remap, RemapProfiles},
+    request::{
+        Connection, LatestState, NewPad, NewPadReply, Notification, PadRequest, PadType, Player,
+        TestInputReply, NO_LED,
+    },
+    snapshot::{Snapshot, SNAPSHOT_VERSION},
+    tls::Tls,
+    touch::{TouchCell, TouchForce},
+    turbo::TurboConfig,
+    wire,
+};
 
-use crate::request::PadRequest;
+/// How many pixels each module of the QR codes on the index page takes up
+const QR_SCALE: i32 = 16;
 
-const QR_SCALE: u32 = 16;
+/// How many modules of white go around the QR codes, which is what readers expect
+const QR_BORDER: i32 = 4;
 
 /// Convert a key into a Sec-Websocket-Accept header
-fn convert_key(key: &str) -> String {
+pub(crate) fn convert_key(key: &str) -> String {
     let mut key = key.to_string().into_bytes();
     key.extend("258EAFA5-E914-47DA-95CA-C5AB0DC85B11".as_bytes());
     base64::encode(sha1::Sha1::from(key).digest().bytes())
 }
 
-/// Given a request that wants to become a websocket, make it become one and handle pad updates coming from it.
-fn handle_websocket(logger: Logger, id: usize, req_tx: Sender<PadRequest>, request: Request) {
-    let result: Result<()> = (|| {
-        let key = &request
-            .headers()
-            .iter()
-            .find(|h| h.field.equiv("Sec-WebSocket-Key"))
-            .ok_or_else(|| format_err!("no websocket key"))?
-            .value;
+/// Complete the handshake of a request that wants to become a websocket, agreeing to the `echo`
+/// subprotocol if there's one
+fn accept(request: Request, echo: Option<String>) -> Result<WebSocket<Box<dyn ReadWrite + Send>>> {
+    let key = &request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Sec-WebSocket-Key"))
+        .ok_or_else(|| format_err!("no websocket key"))?
+        .value;
 
-        let response = Response::empty(StatusCode(101)).with_header(
-            Header::from_bytes("Sec-WebSocket-Accept", convert_key(key.as_str())).unwrap(),
-        );
+    let mut response = Response::empty(StatusCode(101)).with_header(
+        Header::from_bytes("Sec-WebSocket-Accept", convert_key(key.as_str())).unwrap(),
+    );
+    // Browsers refuse the connection unless we agree to the subprotocol they offered
+    if let Some(echo) = echo {
+        response.add_header(Header::from_bytes("Sec-WebSocket-Protocol", echo).unwrap());
+    }
 
-        let stream = request.upgrade("websocket", response);
-        let mut ws = WebSocket::from_raw_socket(stream, Role::Server, None);
+    let stream = request.upgrade("websocket", response);
+    Ok(WebSocket::from_raw_socket(
+        stream,
+        Role::Server,
+        Some(websocket_config()),
+    ))
+}
 
-        loop {
-            let msg = match ws.read_message() {
-                Ok(msg) => msg,
-                Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
-                Err(error) => return Err(error.into()),
-            };
-            let data = match msg {
-                Message::Text(data) => data.into_bytes(),
-                Message::Binary(data) => data,
-                Message::Ping(_) | Message::Pong(_) | Message::Close(_) => continue,
-            };
-            match serde_json::from_slice(&data) {
-                Ok(state) => req_tx.send(PadRequest::Update(id, state))?,
-                Err(error) => error!(logger, "ws.msg_error"; "error" => #%error),
+/// How our websockets are configured, which keeps clients' messages under [MAX_MESSAGE_SIZE]
+pub(crate) fn websocket_config() -> WebSocketConfig {
+    WebSocketConfig {
+        max_message_size: Some(MAX_MESSAGE_SIZE),
+        max_frame_size: Some(MAX_MESSAGE_SIZE),
+        ..WebSocketConfig::default()
+    }
+}
+
+/// Tell a client there's no pad for it by accepting its connection only to close it right away.
+///
+/// Browsers don't let pages see why a handshake failed, so refusing it outright would look no
+/// different from the server being down.
+fn refuse_full(request: Request, echo: Option<String>) -> Result<()> {
+    let mut ws = accept(request, echo)?;
+    ws.close(Some(CloseReason::ServerFull.frame()))?;
+    // Waiting for the client to answer would hold up everybody else's requests
+    match ws.write_pending() {
+        Ok(()) | Err(tungstenite::Error::ConnectionClosed) => Ok(()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Something a client wants done with its pads
+#[derive(Debug, PartialEq)]
+enum PadMessage {
+    /// The state of the pad with the given index, along with when the client sent it by its own
+    /// clock and how hard its sticks are touched, if it says
+    State(usize, X360State, Option<f64>, Option<TouchForce>),
+    Calibrate(Calibration),
+    Turbo(TurboConfig),
+    Remap {
+        pad: Option<usize>,
+        profile: Option<String>,
+        remap: Remap,
+    },
+    Motion(Orientation),
+    MotionConfig(MotionConfig),
+    Neutral(Option<Duration>),
+    Socd(Option<SocdPolicy>),
+    Transformer {
+        pad: Option<usize>,
+        edit: TransformerEdit,
+    },
+    Record {
+        pad: usize,
+        recording: bool,
+    },
+    SaveMacro {
+        pad: usize,
+        slot: u8,
+    },
+    Macro {
+        pad: usize,
+        slot: u8,
+    },
+    Attach,
+    Background,
+    Foreground,
+
+    /// The keys held down, which are mapped to a state for the first pad
+    Keys(BTreeSet<String>),
+
+    /// The answer to the clock ping with the given number, stamped with the client's clock
+    Clock {
+        n: u32,
+        t: f64,
+    },
+}
+
+impl From<ClientMessage> for PadMessage {
+    fn from(message: ClientMessage) -> Self {
+        match message {
+            ClientMessage::Gamepad(state) => Self::State(0, state.into(), None, None),
+            ClientMessage::Calibrate(calibration) => Self::Calibrate(calibration),
+            ClientMessage::Turbo(config) => Self::Turbo(config),
+            ClientMessage::Remap {
+                pad,
+                profile,
+                remap,
+            } => Self::Remap {
+                pad,
+                profile,
+                remap,
+            },
+            ClientMessage::Motion(orientation) => Self::Motion(orientation),
+            ClientMessage::MotionConfig(config) => Self::MotionConfig(config),
+            ClientMessage::Neutral { after_ms } => {
+                Self::Neutral(after_ms.map(Duration::from_millis))
             }
+            ClientMessage::Socd { policy } => Self::Socd(policy),
+            ClientMessage::Transformer { pad, edit } => Self::Transformer { pad, edit },
+            ClientMessage::Record { pad, recording } => Self::Record { pad, recording },
+            ClientMessage::SaveMacro { pad, slot } => Self::SaveMacro { pad, slot },
+            ClientMessage::Macro { pad, slot } => Self::Macro { pad, slot },
+            ClientMessage::Attach => Self::Attach,
+            ClientMessage::Background => Self::Background,
+            ClientMessage::Foreground => Self::Foreground,
+            ClientMessage::Update {
+                pad,
+                state,
+                t,
+                touch,
+            } => Self::State(pad, state, t, touch),
+            ClientMessage::Keys { down } => Self::Keys(down),
+            ClientMessage::Clock { n, t } => Self::Clock { n, t },
         }
-    })();
-
-    let _ = req_tx.send(PadRequest::Discard(id));
+    }
+}
 
-    if let Err(error) = result {
-        error!(logger, "ws.error"; "error" => #%error);
+impl From<ClientText> for PadMessage {
+    fn from(message: ClientText) -> Self {
+        match message {
+            ClientText::Typed(message) => message.into(),
+            ClientText::State(state) => Self::State(0, state, None, None),
+        }
     }
 }
 
-/// Generate a QR code from a given text and return it as a PNG data url
-fn qr_data_url(text: &str) -> Result<String> {
-    let qr = QrCode::encode_text(text, QrCodeEcc::Low)?;
+/// Decode a binary message, as laid out in [wire::decode_frame]
+fn decode_binary(data: &[u8]) -> Result<PadMessage> {
+    let frame = wire::decode_frame(data)?;
+    Ok(PadMessage::State(frame.pad, frame.state, frame.sent, None))
+}
 
-    let side = qr.size();
-    debug_assert!(<u32 as std::convert::TryFrom<i32>>::try_from(side).is_ok());
-    let mut img = image::DynamicImage::new_rgb8(QR_SCALE * side as u32, QR_SCALE * side as u32);
+/// The versions of the websocket protocol we speak, negotiated via `Sec-WebSocket-Protocol`.
+///
+/// Both versions take binary pad states in the wire encoding and the "disconnect" command, and
+/// send the reclaim token, player number, changes to it and replies to attaching pads as JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Protocol {
+    /// Pad states as JSON, along with binary ones for pages predating protocol negotiation,
+    /// and feedback as JSON
+    JsonV1,
 
-    for y in 0..side {
-        for x in 0..side {
-            if !qr.get_module(x, y) {
-                for i in 0..QR_SCALE {
-                    for j in 0..QR_SCALE {
-                        img.put_pixel(
-                            i + QR_SCALE * x as u32,
-                            j + QR_SCALE * y as u32,
-                            image::Rgba([0xFF, 0xFF, 0xFF, 0xFF]),
-                        );
-                    }
-                }
+    /// Pad states in the wire encoding, with JSON only for messages saying what they are via
+    /// their `type` field, and feedback as the large motor, small motor, LED and pad index bytes
+    BinaryV2,
+}
+
+impl Protocol {
+    const ALL: &'static [Self] = &[Self::JsonV1, Self::BinaryV2];
+
+    /// The name clients offer this protocol by
+    fn name(self) -> &'static str {
+        match self {
+            Self::JsonV1 => "sphrosyne.v1.json",
+            Self::BinaryV2 => "sphrosyne.v2.binary",
+        }
+    }
+
+    /// Pick the first protocol we speak out of the ones offered, which are in the client's order
+    /// of preference
+    fn negotiate<'a>(offered: impl IntoIterator<Item = &'a str>) -> Option<Self> {
+        offered.into_iter().find_map(|name| {
+            Self::ALL
+                .iter()
+                .copied()
+                .find(|protocol| protocol.name() == name)
+        })
+    }
+
+    /// Decode what a client sent in a text message. Bare states, which the first version's
+    /// clients send the most of, skip the untagged [ClientText] and the buffering it takes.
+    fn decode_text(self, data: &str) -> Result<PadMessage, InvalidMessage> {
+        Ok(match self {
+            Self::JsonV1 => match wire::decode_json_state(data) {
+                Some(state) => PadMessage::State(0, state, None, None),
+                None => ClientText::parse(data)?.into(),
+            },
+            Self::BinaryV2 => ClientMessage::parse(data)?.into(),
+        })
+    }
+
+    /// The names of every protocol, newest first
+    fn newest_first() -> Vec<&'static str> {
+        Self::ALL
+            .iter()
+            .rev()
+            .map(|protocol| protocol.name())
+            .collect()
+    }
+
+    /// The names of every protocol, newest first, as advertised over mDNS
+    fn names() -> String {
+        Self::newest_first().join(",")
+    }
+
+    /// Encode a rumble and LED notification for the client's pad with the given index
+    fn encode_feedback(self, pad: usize, data: X360NotificationData) -> Message {
+        match self {
+            Self::JsonV1 => UntypedMessage::Feedback {
+                large: data.large_motor,
+                small: data.small_motor,
+                led: data.led_number,
+                pad,
             }
+            .into(),
+            Self::BinaryV2 => Message::Binary(vec![
+                data.large_motor,
+                data.small_motor,
+                data.led_number,
+                u8::try_from(pad).unwrap_or(u8::MAX),
+            ]),
         }
     }
+}
 
-    let mut data = Vec::new();
-    img.write_to(&mut data, image::ImageOutputFormat::Png)?;
+/// Tell a client that a game set the LED of its pad with the given index to the given number, as
+/// `{"type":"player","n":1,"pad":0}` with players counted from 1
+fn player_message(pad: usize, led_number: u8) -> Message {
+    ServerMessage::Player {
+        n: u16::from(led_number) + 1,
+        pad,
+    }
+    .into()
+}
 
-    Ok(format!("data:image/png;base64,{}", base64::encode(data)))
+/// Tell a client that a game set the lightbar of its dualshock 4 pad with the given index to the
+/// given color, as `{"type":"lightbar","r":255,"g":0,"b":0,"pad":0}`
+fn lightbar_message(pad: usize, (r, g, b): (u8, u8, u8)) -> Message {
+    ServerMessage::Lightbar { r, g, b, pad }.into()
 }
 
-/// Return the HTML of the index page
-fn index_page(port: u16) -> Result<String> {
-    let host = gethostname::gethostname();
-    let host = host
-        .to_str()
-        .ok_or_else(|| format_err!("Invalid hostname {:?}", host))?;
-    let url = format!("http://{}:{}/controller", host, port);
+/// Tell a client how to vibrate for the rumble of its pad with the given index, as
+/// `{"type":"haptic","pattern":[10,10],"pad":0}` with a pattern `navigator.vibrate` takes as is
+fn haptic_message(pad: usize, pattern: &[u32]) -> Message {
+    ServerMessage::Haptic {
+        pattern: pattern.to_vec(),
+        pad,
+    }
+    .into()
+}
 
-    Ok(HtmlPage::new()
-        .add_title("Sphrosyne")
-        .add_meta(vec![
-            ("charset", "utf8"),
-            ("viewport", "width=device-width, initial-scale=1.0"),
-        ])
-        .add_style(include_str!("style.css"))
-        .add_paragraph("The server is running. Scan the following QR code to connect your device:")
-        .add_image(qr_data_url(&url)?, url)
-        .to_html_string())
-}
-
-// Return the HTML of the controller page
-fn controller_page(port: u16) -> Result<String> {
-    let host = gethostname::gethostname();
-    let host = host
-        .to_str()
-        .ok_or_else(|| format_err!("Invalid hostname {:?}", host))?;
-    let url = format!("ws://{}:{}/websocket", host, port);
-
-    Ok(HtmlPage::new()
-        .add_title("Sphrosyne Controller")
-        .add_meta(vec![
-            ("charset", "utf8"),
-            ("viewport", "width=device-width, initial-scale=1.0"),
-        ])
-        .add_style(include_str!("style.css"))
-        // We pass in our websocket URL as a hidden input on the page so our javascript can retrieve it
-        .add_raw(format_args!(
-            r#"<input type="hidden" id="url" value="{}">"#,
-            url
-        ))
-        .add_script_literal(include_str!("controller.js"))
-        .to_html_string())
+/// Ask a client what its clock says, as `{"type":"clock","n":0}`, which it answers with the same
+/// message along with its clock's reading as `t`
+fn clock_message(n: u32) -> Message {
+    ServerMessage::Clock { n }.into()
+}
+
+/// Tell a client that we're shutting down, as `{"type":"shutdown","restarting":false}`, which it
+/// gets right before its connection is closed. If we're `restarting` its pad is waiting for it
+/// once we're back, so it should hold on to its reclaim token.
+fn shutdown_message(restarting: bool) -> Message {
+    ServerMessage::Shutdown { restarting }.into()
+}
+
+/// Tell a client which server it's talking to, as
+/// `{"type":"hello","server":"Gaming PC","version":"1.0.0","device":"..."}` with our name and
+/// version, which is the first message of every connection. `device` is the identifier we know
+/// the client's device by, which it should present from then on to keep its profile.
+fn hello_message(name: &str, device: Option<&str>) -> Message {
+    ServerMessage::Hello {
+        server: name.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        device: device.map(str::to_string),
+    }
+    .into()
+}
+
+/// Tell a client that its pad is ready for states, as
+/// `{"type":"ready","player":1,"label":"P1","session":"...","layout":"racing"}` with the same
+/// player number and reclaim token as the messages before it, and a `null` player if the pad
+/// doesn't have one. `label` is how to show the player, e.g. `P5 (no XInput slot)`, and `layout`
+/// is the one its device used last, if we remember it.
+fn ready_message(player: Option<Player>, session: &str, layout: Option<&str>) -> Message {
+    ServerMessage::Ready {
+        player: player.map(|player| player.number),
+        label: player.map(|player| player.to_string()),
+        session: session.to_string(),
+        layout: layout.map(str::to_string),
+    }
+    .into()
+}
+
+/// Tell a client that its last message was ignored and why, as
+/// `{"type":"error","message":"unknown message type \"lightbar\""}`
+fn error_message(error: &str) -> Message {
+    ServerMessage::Error {
+        message: error.to_string(),
+    }
+    .into()
+}
+
+/// The vibration pattern a pad's client is sent when its rumble is tested from the index page
+const TEST_RUMBLE: &[u32] = &[200, 100, 200];
+
+/// How many times per second a client may be told about its pad's LED changing, since some games
+/// animate it
+const PLAYER_LED_RATE: u32 = 4;
+
+/// How many times per second a client may be told about its pad's lightbar changing, since some
+/// games fade it from one color to the next
+const LIGHTBAR_RATE: u32 = 4;
+
+/// Keeps track of something a game sets on a pad, e.g. which player its LED says it is, to tell
+/// the client whenever that changes
+struct Changes<T> {
+    /// The value the client was last told about
+    sent: Option<T>,
+
+    /// The value the client has yet to be told about, which the rate limit held back
+    pending: Option<T>,
+    bucket: TokenBucket,
+}
+
+impl<T: Copy + PartialEq> Changes<T> {
+    /// Tell the client about changes at most `rate` times per second
+    fn new(rate: u32, now: Instant) -> Self {
+        Self {
+            sent: None,
+            pending: None,
+            bucket: TokenBucket::new(rate, now),
+        }
+    }
+
+    /// Take note of the value a notification came with
+    fn note(&mut self, value: T) {
+        self.pending = Some(value).filter(|&value| self.sent != Some(value));
+    }
+
+    /// The value to tell the client about at `now`, if it changed and the rate limit allows
+    fn due(&mut self, now: Instant) -> Option<T> {
+        let value = self.pending?;
+        if !self.bucket.try_take(now) {
+            return None;
+        }
+        self.pending = None;
+        self.sent = Some(value);
+        Some(value)
+    }
+}
+
+/// A pad's notifications, along with what's due to be told about its LED, lightbar and rumble
+struct PadFeedback {
+    notifications: Receiver<Notification>,
+    player_led: Changes<u8>,
+    lightbar: Changes<(u8, u8, u8)>,
+    haptics: Haptics,
+}
+
+impl PadFeedback {
+    fn new(notifications: Receiver<Notification>, now: Instant) -> Self {
+        Self {
+            notifications,
+            player_led: Changes::new(PLAYER_LED_RATE, now),
+            lightbar: Changes::new(LIGHTBAR_RATE, now),
+            haptics: Haptics::default(),
+        }
+    }
+}
+
+/// How long to wait for the pads to report their status
+pub(crate) const STATUS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often to ping websocket clients, so that they have something to answer even when idle
+pub(crate) const PING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often to check whether we're shutting down while waiting for requests
+pub(crate) const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long to wait for websocket handlers to close their connections when shutting down
+pub(crate) const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The largest message clients may send, which is plenty for any message we understand, so
+/// that nobody can make us buffer megabytes before we even get to parse them
+const MAX_MESSAGE_SIZE: usize = 4096;
+
+/// What every websocket handler is configured with, regardless of its client
+pub(crate) struct WebsocketSettings {
+    /// How long a client can go without sending us anything before its pads are released
+    idle_timeout: Duration,
+
+    /// How keys are mapped to a state for clients in keyboard mode
+    keymap: Keymap,
+
+    /// The remaps clients can pick by name
+    remaps: RemapProfiles,
+
+    /// How many messages per second a client may send, with 0 meaning as many as it likes
+    rate_limit: u32,
+
+    /// Set once we're shutting down, which makes handlers close their connection
+    shutdown: Arc<AtomicBool>,
+
+    metrics: Arc<Metrics>,
+
+    /// Where messages for the clients are queued
+    outboxes: Arc<Outboxes>,
+
+    /// The name we tell clients we go by
+    name: String,
+}
+
+/// Where a websocket's handler sends its requests for the pads, which is straight to them in
+/// the blocking server and through a bridge to them in the async one
+#[derive(Clone)]
+pub(crate) enum PadSender {
+    Blocking(SyncSender<PadRequest>),
+    #[cfg(feature = "async-server")]
+    Bridged(tokio::sync::mpsc::UnboundedSender<PadRequest>),
+}
+
+impl PadSender {
+    /// Send a request to the pads, failing only if they're gone
+    pub(crate) fn send(&self, request: PadRequest) -> Result<()> {
+        match self {
+            PadSender::Blocking(tx) => tx.send(request)?,
+            #[cfg(feature = "async-server")]
+            PadSender::Bridged(tx) => tx
+                .send(request)
+                .map_err(|_| eyre::eyre!("the pads are gone"))?,
+        }
+        Ok(())
+    }
+}
+
+/// What a websocket's handler and its watchdog share
+pub(crate) struct Session {
+    /// The pads we're controlling, by index, if they haven't been released yet
+    pads: Mutex<Option<Vec<usize>>>,
+
+    /// Where to put the states for each of the pads, by index
+    latest: Mutex<Vec<Arc<LatestState>>>,
+
+    /// When we last heard from the client
+    last_seen: Mutex<Instant>,
+
+    /// Whether the watchdog released the pad because the client went quiet
+    timed_out: AtomicBool,
+
+    /// Why the pads were released if it was because one of them was
+    /// [discarded](PadRequest::Discard)
+    kicked: Mutex<Option<CloseReason>>,
+
+    /// Limits how fast the client's messages are passed on
+    bucket: Mutex<TokenBucket>,
+
+    /// The latest state the rate limit held back for each pad, by index, along with when its
+    /// message came in
+    held_back: Mutex<BTreeMap<usize, (X360State, Instant)>>,
 }
 
-fn html_response(data: impl Into<String>) -> Response<Cursor<Vec<u8>>> {
-    Response::from_string(data)
-        .with_header(Header::from_bytes(&b"Content-Type"[..], &b"text/html"[..]).unwrap())
+/// What became of a state a client sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Forwarded {
+    /// It was passed on right away
+    Sent,
+
+    /// The rate limit held it back, for the watchdog to pass on once it lets us
+    HeldBack,
+
+    /// Like [Forwarded::HeldBack], but it replaced a state held back earlier for the same pad
+    Coalesced,
 }
 
-pub(crate) fn mainloop(logger: Logger, tx: Sender<PadRequest>, rx: Receiver<usize>) -> Result<()> {
-    let server = Server::http("0.0.0.0:0").map_err(|err| format_err!("no server :< {}", err))?;
+impl Session {
+    /// Release the pads if nobody else has done so yet, the first with the given request, which
+    /// is either [PadRequest::Release] or [PadRequest::Detach]. Only the first pad can be
+    /// reclaimed, so any others are always released.
+    fn release(&self, req_tx: &PadSender, request: impl FnOnce(usize) -> PadRequest) -> Result<()> {
+        if let Some(pads) = self.pads.lock().unwrap().take() {
+            let mut pads = pads.into_iter();
+            if let Some(id) = pads.next() {
+                req_tx.send(request(id))?;
+            }
+            for id in pads {
+                req_tx.send(PadRequest::Release(id))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Release the pads for good if one of them was discarded since, returning why if they
+    /// were. Pads which were already released are no concern of ours anymore, even if their
+    /// next client's was discarded.
+    fn kick_if_discarded(&self, req_tx: &PadSender) -> Result<Option<CloseReason>> {
+        let discarded = if self.pads.lock().unwrap().is_some() {
+            self.latest
+                .lock()
+                .unwrap()
+                .iter()
+                .find_map(|latest| latest.discarded())
+        } else {
+            None
+        };
+        if let Some(reason) = discarded {
+            *self.kicked.lock().unwrap() = Some(reason);
+            self.release(req_tx, PadRequest::Release)?;
+        }
+        Ok(*self.kicked.lock().unwrap())
+    }
+
+    /// Put a state in the pad with the given index and id's [LatestState], letting the pads know
+    /// unless they already were about the state it replaced
+    fn put(
+        &self,
+        req_tx: &PadSender,
+        index: usize,
+        id: usize,
+        state: X360State,
+        received: Instant,
+    ) -> Result<()> {
+        if self.latest.lock().unwrap()[index].put(state, received) {
+            req_tx.send(PadRequest::Update(id))?;
+        }
+        Ok(())
+    }
+
+    /// Pass on a state the client sent for the pad with the given index in a message received
+    /// at `received`, unless the rate limit says otherwise or earlier states are still held
+    /// back, in which case it's held back too.
+    fn forward(
+        &self,
+        req_tx: &PadSender,
+        index: usize,
+        state: X360State,
+        received: Instant,
+    ) -> Result<Forwarded> {
+        let mut held_back = self.held_back.lock().unwrap();
+        if held_back.is_empty() && self.bucket.lock().unwrap().try_take(Instant::now()) {
+            if let Some(&id) = self
+                .pads
+                .lock()
+                .unwrap()
+                .as_ref()
+                .and_then(|pads| pads.get(index))
+            {
+                self.put(req_tx, index, id, state, received)?;
+            }
+            return Ok(Forwarded::Sent);
+        }
+        Ok(match held_back.insert(index, (state, received)) {
+            Some(_) => Forwarded::Coalesced,
+            None => Forwarded::HeldBack,
+        })
+    }
+
+    /// Pass on the states the rate limit held back for as long as it lets us, returning how
+    /// long until it lets us pass on the rest if there are any left
+    fn flush(&self, req_tx: &PadSender) -> Result<Option<Duration>> {
+        let mut held_back = self.held_back.lock().unwrap();
+        let pads = self.pads.lock().unwrap();
+        let pads = match &*pads {
+            Some(pads) => pads,
+            // The pads are gone, so there's nobody left to pass them on to
+            None => {
+                held_back.clear();
+                return Ok(None);
+            }
+        };
+
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        while let Some((index, (state, received))) = held_back.pop_first() {
+            if !bucket.try_take(now) {
+                let _ = held_back.insert(index, (state, received));
+                return Ok(Some(bucket.next_token(now)));
+            }
+            self.put(req_tx, index, pads[index], state, received)?;
+        }
+        Ok(None)
+    }
+
+    /// Do the watchdog's rounds: pass on the states the rate limit held back as soon as it lets
+    /// us, and release the pads once the client hasn't been heard from in `idle_timeout` or one
+    /// of them was discarded. Returns how long until the next round, or nothing once there's no
+    /// need for any.
+    pub(crate) fn watch(
+        &self,
+        logger: &Logger,
+        req_tx: &PadSender,
+        idle_timeout: Duration,
+    ) -> Option<Duration> {
+        let interval = PING_INTERVAL.min(idle_timeout);
+        let timeout = match self.flush(req_tx) {
+            Ok(next) => next.map_or(interval, |next| next.min(interval)),
+            Err(error) => {
+                error!(logger, "ws.error"; "error" => #%error);
+                return None;
+            }
+        };
+
+        match self.kick_if_discarded(req_tx) {
+            Ok(None) => {}
+            Ok(Some(reason)) => {
+                info!(logger, "ws.kicked"; "reason" => %reason);
+                return None;
+            }
+            Err(error) => {
+                error!(logger, "ws.error"; "error" => #%error);
+                return None;
+            }
+        }
 
-    let addr = server.server_addr();
-    let port = addr.port();
-    info!(logger, "server.bound"; "addr" => addr, "url" => format_args!("http://localhost:{}", port));
+        let idle = self.last_seen.lock().unwrap().elapsed();
+        if idle >= idle_timeout {
+            info!(logger, "ws.timeout"; "idle" => ?idle);
+            self.timed_out.store(true, Ordering::SeqCst);
+            if let Err(error) = self.release(req_tx, PadRequest::Detach) {
+                error!(logger, "ws.error"; "error" => #%error);
+            }
+            return None;
+        }
+        Some(timeout)
+    }
+}
 
+/// Watch a websocket's session with [Session::watch] until `wake` is disconnected, which the
+/// handler sends to whenever it holds a state back.
+///
+/// The upgraded stream does not let us set a read timeout, so a client which silently went away
+/// (e.g. a phone which locked its screen) leaves its handler blocked on a read that may never
+/// return; this makes sure its pads can be reclaimed, or freed for someone else, regardless.
+fn watch_session(
+    logger: Logger,
+    session: Arc<Session>,
+    req_tx: PadSender,
+    idle_timeout: Duration,
+    wake: Receiver<()>,
+) {
+    let mut timeout = PING_INTERVAL.min(idle_timeout);
     loop {
-        let req = server.recv()?;
-        debug!(logger, "req"; "req" => ?req, "headers" => ?req.headers());
+        if let Err(RecvTimeoutError::Disconnected) = wake.recv_timeout(timeout) {
+            return;
+        }
+        timeout = match session.watch(&logger, &req_tx, idle_timeout) {
+            Some(timeout) => timeout,
+            None => return,
+        };
+    }
+}
 
-        match req.url() {
-            "/" => req.respond(html_response(index_page(port)?))?,
+/// What a websocket's [Handler] does once it's handled one of its client's messages
+#[derive(Debug)]
+pub(crate) enum Step {
+    /// Carry on reading, once the feedback and whatever else is queued is written out
+    Continue,
 
-            "/controller" => req.respond(html_response(controller_page(port)?))?,
+    /// Wait for the pads' reply to an attach message, which goes to [Handler::attached]
+    Attach(Receiver<NewPadReply>),
 
-            "/websocket" => {
-                let logger = logger.clone();
-                tx.send(crate::PadRequest::NewID)?;
-                let req_tx = tx.clone();
+    /// Close the connection for the given reason, after whatever is queued
+    Close(CloseReason),
 
-                let id = rx.recv()?;
-                let logger = logger.new(o!("id" => id));
-                info!(logger, "ws.new");
-                spawn(move || handle_websocket(logger, id, req_tx.clone(), req));
+    /// The client closed the connection, whose close frame only needs answering
+    Closed,
+}
+
+/// Everything a websocket's handler keeps track of about its client, whichever server it's on.
+/// The handler reads the client's messages and writes out the ones it's given, while this
+/// decides what to make of them.
+pub(crate) struct Handler {
+    pub(crate) logger: Logger,
+    connection: Connection,
+
+    /// What the client is told when it connects
+    reclaim: String,
+    player: Option<Player>,
+    device: Option<String>,
+    profile: Option<Profile>,
+    pad_type: PadType,
+
+    req_tx: PadSender,
+    protocol: Protocol,
+    settings: Arc<WebsocketSettings>,
+    pub(crate) session: Arc<Session>,
+    pub(crate) outbox: Outbox,
+
+    /// Each pad's notifications, along with what's due to be told about them
+    feedbacks: Vec<PadFeedback>,
+    throttled: Vec<Arc<Throttled>>,
+    latencies: Vec<Arc<Latency>>,
+    touches: Vec<Arc<TouchCell>>,
+
+    /// The keys held down by a client in keyboard mode
+    keys: BTreeSet<String>,
+
+    /// Whether states are being held back, so that we only warn about it once in a row
+    limited: bool,
+    summary: SessionSummary,
+
+    /// Times are in milliseconds since this, by our clock
+    epoch: Instant,
+
+    /// How far the client's clock is from ours, to time the states it stamps
+    clock: ClockOffset,
+
+    /// The number of the clock ping we're waiting on the answer to and when we sent it
+    clock_ping: Option<(u32, f64)>,
+
+    /// Lets the watchdog know a state was held back, for it to pass on
+    wake: Box<dyn Fn() + Send>,
+
+    /// The id of the pad the client connected with
+    first: usize,
+}
+
+impl Handler {
+    /// Start handling a client which got the given pad, waking the watchdog of the returned
+    /// handler's [Session] with `wake`
+    pub(crate) fn new(
+        logger: Logger,
+        connection: Connection,
+        pad: NewPad,
+        req_tx: PadSender,
+        protocol: Protocol,
+        settings: Arc<WebsocketSettings>,
+        wake: impl Fn() + Send + 'static,
+    ) -> Self {
+        let NewPad {
+            id,
+            feedback,
+            reclaim,
+            player,
+            device,
+            profile,
+            pad_type,
+            throttled,
+            latest,
+            latency,
+        } = pad;
+        settings.metrics.connected();
+        let outbox = Outbox::open(Arc::clone(&settings.outboxes), id);
+        let session = Arc::new(Session {
+            pads: Mutex::new(Some(vec![id])),
+            latest: Mutex::new(vec![latest]),
+            last_seen: Mutex::new(Instant::now()),
+            timed_out: AtomicBool::new(false),
+            kicked: Mutex::default(),
+            bucket: Mutex::new(TokenBucket::new(settings.rate_limit, Instant::now())),
+            held_back: Mutex::default(),
+        });
+        Self {
+            logger,
+            connection,
+            reclaim,
+            player,
+            device,
+            profile,
+            pad_type,
+            req_tx,
+            protocol,
+            settings,
+            session,
+            outbox,
+            feedbacks: vec![PadFeedback::new(feedback, Instant::now())],
+            throttled: vec![throttled],
+            latencies: vec![latency],
+            touches: vec![Arc::default()],
+            keys: BTreeSet::new(),
+            limited: false,
+            summary: SessionSummary::new(Instant::now()),
+            epoch: Instant::now(),
+            clock: ClockOffset::default(),
+            clock_ping: None,
+            wake: Box::new(wake),
+            first: id,
+        }
+    }
+
+    /// The messages to send the client as soon as it's connected, ending with the first clock
+    /// ping
+    pub(crate) fn greeting(&mut self) -> Result<Vec<Message>> {
+        let mut messages = vec![
+            hello_message(&self.settings.name, self.device.as_deref()),
+            // Clients which predate the ready message only know these two
+            UntypedMessage::Reclaim {
+                reclaim: self.reclaim.clone(),
+            }
+            .into(),
+        ];
+        if let Some(player) = self.player {
+            messages.push(
+                UntypedMessage::Player {
+                    player: player.number,
+                    label: player.to_string(),
+                }
+                .into(),
+            );
+        }
+        let layout = self
+            .profile
+            .as_ref()
+            .and_then(|profile| profile.layout.as_deref());
+        messages.push(ready_message(self.player, &self.reclaim, layout));
+
+        // The device picks the same remap profile it did last time, if we still have it
+        if let Some(name) = self
+            .profile
+            .as_ref()
+            .and_then(|profile| profile.remap.as_deref())
+        {
+            match self.settings.remaps.get(name) {
+                Some(remap) => self
+                    .req_tx
+                    .send(PadRequest::Remap(self.first, remap.clone()))?,
+                None => warn!(self.logger, "ws.profile.remap_gone"; "profile" => name),
+            }
+        }
+
+        // Find out how far the client's clock is from ours, to time the states it stamps
+        self.epoch = Instant::now();
+        messages.push(clock_message(0));
+        self.clock_ping = Some((0, self.millis(Instant::now())));
+        Ok(messages)
+    }
+
+    /// How long the client can go without sending us anything before its pads are released
+    pub(crate) fn idle_timeout(&self) -> Duration {
+        self.settings.idle_timeout
+    }
+
+    /// Milliseconds since the connection started, by our clock
+    fn millis(&self, at: Instant) -> f64 {
+        at.duration_since(self.epoch).as_secs_f64() * 1000.0
+    }
+
+    /// Count a message which was too large to read, and which ends the connection
+    pub(crate) fn too_large(&self) {
+        self.throttled[0].dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Why the client has to go even though it's still connected, if it does: the pads were
+    /// taken away from it, or we're going away
+    pub(crate) fn away(&self) -> Result<Option<CloseReason>> {
+        Ok(if self.settings.shutdown.load(Ordering::SeqCst) {
+            Some(CloseReason::ShuttingDown)
+        } else if self.session.timed_out.load(Ordering::SeqCst) {
+            Some(CloseReason::IdleTimeout)
+        } else {
+            self.session.kick_if_discarded(&self.req_tx)?
+        })
+    }
+
+    /// Handle a message the client sent
+    pub(crate) fn receive(&mut self, msg: Message) -> Result<Step> {
+        let received = Instant::now();
+        *self.session.last_seen.lock().unwrap() = received;
+        self.summary.messages += 1;
+
+        // The client came back after its pad was taken away, or we're going away, so let it
+        // know its pads are gone
+        if let Some(reason) = self.away()? {
+            info!(self.logger, "ws.close.away"; "reason" => %reason);
+            return Ok(Step::Close(reason));
+        }
+
+        // Binary messages use the compact wire format, while text messages are JSON
+        let message: Result<PadMessage> = match msg {
+            // Let go of the pads without closing the connection, so that they can be used by someone else
+            Message::Text(data) if data == "disconnect" => {
+                info!(self.logger, "ws.disconnect");
+                self.session.release(&self.req_tx, PadRequest::Release)?;
+                return Ok(Step::Continue);
+            }
+            Message::Text(data) => self.protocol.decode_text(&data).map_err(Into::into),
+            Message::Binary(data) => decode_binary(&data),
+            Message::Close(frame) => {
+                info!(self.logger, "ws.close"; "frame" => ?frame);
+                return Ok(Step::Closed);
             }
+            Message::Ping(_) | Message::Pong(_) => return Ok(Step::Continue),
+        };
 
-            _ => {
-                let status_code = StatusCode(404);
-                req.respond(Response::new(
-                    status_code,
-                    vec![],
-                    io::Cursor::new(status_code.default_reason_phrase()),
-                    Some(status_code.default_reason_phrase().len()),
-                    None,
+        let pads = match self.session.pads.lock().unwrap().clone() {
+            Some(pads) => pads,
+            None => return Ok(Step::Continue),
+        };
+        let (logger, req_tx, session) = (&self.logger, &self.req_tx, &self.session);
+        let forwarded = match message {
+            Ok(PadMessage::State(index, state, sent, touch)) if index < pads.len() => {
+                self.touches[index].put(touch);
+                if let Some(delay) =
+                    sent.and_then(|sent| self.clock.delay(sent, self.millis(Instant::now())))
+                {
+                    self.latencies[index].record_network(delay);
+                }
+                Some((index, session.forward(req_tx, index, state, received)?))
+            }
+            Ok(PadMessage::State(index, ..)) => {
+                error!(logger, "ws.msg_error"; "error" => "no such pad", "pad" => index);
+                self.summary.last_error = Some(format!("no such pad {}", index));
+                None
+            }
+            Ok(PadMessage::Keys(down)) if down != self.keys => {
+                let state = self.settings.keymap.state(down.iter().map(String::as_str));
+                self.keys = down;
+                Some((0, session.forward(req_tx, 0, state, received)?))
+            }
+            Ok(PadMessage::Keys(_)) => None,
+            Ok(PadMessage::Clock { n, t }) => {
+                match self.clock_ping {
+                    Some((ping, sent)) if ping == n => {
+                        self.clock.round(sent, t, self.millis(received));
+                        self.clock_ping = if n + 1 < CLOCK_ROUNDS {
+                            self.outbox.push(clock_message(n + 1));
+                            Some((n + 1, self.millis(Instant::now())))
+                        } else {
+                            debug!(logger, "ws.clock"; "offset_ms" => self.clock.offset());
+                            None
+                        };
+                    }
+                    // Answers to pings we aren't waiting on are no use, e.g. once we have enough
+                    _ => {
+                        debug!(logger, "ws.msg_dropped"; "reason" => "unexpected clock answer")
+                    }
+                }
+                None
+            }
+            // Anything but states can't be held back without losing track of its order
+            Ok(_) if !session.bucket.lock().unwrap().try_take(Instant::now()) => {
+                debug!(logger, "ws.msg_dropped"; "reason" => "rate limit");
+                self.throttled[0].dropped.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            Ok(PadMessage::Calibrate(calibration)) => {
+                for &id in &pads {
+                    req_tx.send(PadRequest::Calibrate(id, calibration))?;
+                }
+                None
+            }
+            Ok(PadMessage::Turbo(config)) => {
+                for &id in &pads {
+                    req_tx.send(PadRequest::Turbo(id, config))?;
+                }
+                None
+            }
+            Ok(PadMessage::Remap {
+                pad,
+                profile,
+                remap,
+            }) => {
+                let picked = profile.clone();
+                let remap = match profile {
+                    Some(name) => self.settings.remaps.get(&name).cloned().ok_or(name),
+                    None => Ok(remap),
+                };
+                let remapped = match (remap, pad) {
+                    (Err(name), _) => {
+                        error!(logger, "ws.msg_error"; "error" => "no such remap profile", "profile" => &name);
+                        self.summary.last_error = Some(format!("no such remap profile {:?}", name));
+                        false
+                    }
+                    (Ok(_), Some(index)) if index >= pads.len() => {
+                        error!(logger, "ws.msg_error"; "error" => "no such pad", "pad" => index);
+                        self.summary.last_error = Some(format!("no such pad {}", index));
+                        false
+                    }
+                    (Ok(remap), Some(index)) => {
+                        req_tx.send(PadRequest::Remap(pads[index], remap))?;
+                        true
+                    }
+                    (Ok(remap), None) => {
+                        for &id in &pads {
+                            req_tx.send(PadRequest::Remap(id, remap.clone()))?;
+                        }
+                        true
+                    }
+                };
+                // The device gets the same profile the next time it connects
+                if let (true, Some(device)) = (remapped, &self.device) {
+                    let change = ProfileChange::Remap(picked);
+                    req_tx.send(PadRequest::Remember(device.clone(), change))?;
+                }
+                None
+            }
+            Ok(PadMessage::Motion(orientation)) => {
+                req_tx.send(PadRequest::Motion(pads[0], orientation))?;
+                None
+            }
+            Ok(PadMessage::MotionConfig(config)) => {
+                req_tx.send(PadRequest::MotionConfig(pads[0], config))?;
+                None
+            }
+            Ok(PadMessage::Neutral(neutral_after)) => {
+                for &id in &pads {
+                    req_tx.send(PadRequest::Neutral(id, neutral_after))?;
+                }
+                None
+            }
+            Ok(PadMessage::Socd(policy)) => {
+                for &id in &pads {
+                    req_tx.send(PadRequest::Socd(id, policy))?;
+                }
+                None
+            }
+            Ok(PadMessage::Background) => {
+                for &id in &pads {
+                    req_tx.send(PadRequest::Suspend(id))?;
+                }
+                None
+            }
+            Ok(PadMessage::Foreground) => {
+                for &id in &pads {
+                    req_tx.send(PadRequest::Resume(id))?;
+                }
+                None
+            }
+            Ok(PadMessage::Transformer { pad, edit }) => {
+                match pad {
+                    Some(index) if index >= pads.len() => {
+                        error!(logger, "ws.msg_error"; "error" => "no such pad", "pad" => index);
+                        self.summary.last_error = Some(format!("no such pad {}", index));
+                    }
+                    Some(index) => {
+                        let edit = edit.into_pipeline_edit(&self.touches[index]);
+                        req_tx.send(PadRequest::Pipeline(pads[index], edit))?;
+                    }
+                    None => {
+                        for (&id, touch) in pads.iter().zip(&self.touches) {
+                            let edit = edit.clone().into_pipeline_edit(touch);
+                            req_tx.send(PadRequest::Pipeline(id, edit))?;
+                        }
+                    }
+                }
+                None
+            }
+            Ok(
+                PadMessage::Record { pad, .. }
+                | PadMessage::SaveMacro { pad, .. }
+                | PadMessage::Macro { pad, .. },
+            ) if pad >= pads.len() => {
+                error!(logger, "ws.msg_error"; "error" => "no such pad", "pad" => pad);
+                self.summary.last_error = Some(format!("no such pad {}", pad));
+                None
+            }
+            Ok(PadMessage::Record { pad, recording }) => {
+                req_tx.send(PadRequest::Record(pads[pad], recording))?;
+                None
+            }
+            Ok(PadMessage::SaveMacro { pad, slot }) => {
+                req_tx.send(PadRequest::SaveMacro(pads[pad], slot))?;
+                None
+            }
+            Ok(PadMessage::Macro { pad, slot }) => {
+                req_tx.send(PadRequest::PlayMacro(pads[pad], slot))?;
+                None
+            }
+            Ok(PadMessage::Attach) => {
+                let (reply_tx, reply_rx) = channel();
+                req_tx.send(PadRequest::Acquire(
+                    self.connection.clone(),
+                    self.device.clone(),
+                    self.pad_type,
+                    reply_tx,
                 ))?;
+                return Ok(Step::Attach(reply_rx));
+            }
+            // Whatever the client meant is ignored, but it's told so
+            Err(error) => {
+                error!(logger, "ws.msg_error"; "error" => #%error);
+                let error = format!("{:#}", error);
+                self.outbox.push(error_message(&error));
+                self.summary.last_error = Some(error);
+                None
+            }
+        };
+
+        if let Some((_, Forwarded::Sent | Forwarded::HeldBack)) = forwarded {
+            self.summary.states += 1;
+        }
+        match forwarded {
+            Some((_, Forwarded::Sent)) => self.limited = false,
+            Some((index, forwarded)) => {
+                if !self.limited {
+                    warn!(logger, "ws.rate_limited"; "rate_limit" => self.settings.rate_limit);
+                    self.limited = true;
+                }
+                if forwarded == Forwarded::Coalesced {
+                    self.throttled[index]
+                        .coalesced
+                        .fetch_add(1, Ordering::Relaxed);
+                }
+                // The watchdog passes on what we held back, once it knows about it
+                (self.wake)();
             }
+            None => {}
         }
+        Ok(Step::Continue)
+    }
+
+    /// Take on the pad the pads replied with to an attach message, returning the reply to send
+    /// the client right away
+    pub(crate) fn attached(&mut self, reply: NewPadReply) -> Result<Message> {
+        let reply = match reply {
+            Ok(pad) => match self.session.pads.lock().unwrap().as_mut() {
+                Some(pads) => {
+                    pads.push(pad.id);
+                    self.session.latest.lock().unwrap().push(pad.latest);
+                    self.outbox.add_pad(pad.id);
+                    self.feedbacks
+                        .push(PadFeedback::new(pad.feedback, Instant::now()));
+                    self.throttled.push(pad.throttled);
+                    self.latencies.push(pad.latency);
+                    self.touches.push(Arc::default());
+                    info!(self.logger, "ws.attach"; "pad" => pads.len() - 1, "attached_id" => pad.id);
+                    UntypedMessage::Attached {
+                        attached: pads.len() - 1,
+                        player: pad.player.map(|player| player.number),
+                        label: pad.player.map(|player| player.to_string()),
+                    }
+                }
+                // Our pads were released while we waited for this one
+                None => {
+                    self.req_tx.send(PadRequest::Release(pad.id))?;
+                    UntypedMessage::Refused {
+                        refused: "released".to_string(),
+                    }
+                }
+            },
+            Err(reason) => UntypedMessage::Refused {
+                refused: reason.to_string(),
+            },
+        };
+        Ok(reply.into())
+    }
+
+    /// Queue what the pads' notifications have to tell the client, unless they were released
+    pub(crate) fn feedback(&mut self) {
+        if self.session.pads.lock().unwrap().is_none() {
+            return;
+        }
+        let now = Instant::now();
+        for (index, feedback) in self.feedbacks.iter_mut().enumerate() {
+            for Notification { data, lightbar } in feedback.notifications.try_iter() {
+                if data.led_number != NO_LED {
+                    feedback.player_led.note(data.led_number);
+                }
+                if let Some(color) = lightbar {
+                    feedback.lightbar.note(color);
+                }
+                feedback.haptics.note(&data);
+                self.outbox.push(self.protocol.encode_feedback(index, data));
+            }
+            if let Some(led_number) = feedback.player_led.due(now) {
+                self.outbox.push(player_message(index, led_number));
+            }
+            if let Some(color) = feedback.lightbar.due(now) {
+                self.outbox.push(lightbar_message(index, color));
+            }
+            if let Some(pattern) = feedback.haptics.due(now) {
+                self.outbox.push(haptic_message(index, &pattern));
+            }
+        }
+    }
+
+    /// Detach the pads, unless they were released already, and log a [SessionSummary] of the
+    /// connection, which ended with `result`
+    pub(crate) fn finish(mut self, result: Result<()>) {
+        let _ = self.session.release(&self.req_tx, PadRequest::Detach);
+
+        if let Err(error) = result {
+            error!(self.logger, "ws.error"; "error" => #%error);
+            self.summary.last_error = Some(format!("{:#}", error));
+        }
+        let (coalesced, dropped) =
+            self.throttled
+                .iter()
+                .fold((0, 0), |(coalesced, dropped), pad| {
+                    (
+                        coalesced + pad.coalesced.load(Ordering::Relaxed),
+                        dropped + pad.dropped.load(Ordering::Relaxed),
+                    )
+                });
+        let summary = &self.summary;
+        info!(self.logger, "ws.session"; "duration" => ?summary.started.elapsed(), "messages" => summary.messages, "states" => summary.states, "coalesced" => coalesced, "dropped" => dropped, "pads" => self.throttled.len(), "last_error" => &summary.last_error);
+    }
+}
+
+/// Given a request that wants to become a websocket, make it become one and handle pad updates coming from it.
+///
+/// Rumble and LED notifications for the pad are sent back to the client after each message it sends
+/// us, since reading blocks and the upgraded stream can not be split into separate halves, along
+/// with whatever else was queued for it in its [Outbox]. For the
+/// same reason pings are only sent after a message, whenever the last one is older than [PING_INTERVAL].
+/// Whenever a game changes which player a pad's LED shows, the client is told with a
+/// [player message](player_message) so that it can take on that player's colors, and for as long
+/// as a pad rumbles its client is sent a [haptic message](haptic_message) to vibrate with at most
+/// every [HAPTIC_WINDOW](crate::haptics::HAPTIC_WINDOW), followed by one stopping the vibration
+/// once the rumble stops.
+///
+/// The first message we send is a [hello](hello_message) saying which server we are, then the
+/// pad's reclaim token, which the client can present when reconnecting to get the same pad back,
+/// followed by its player number and label if it has one, and then both again in a
+/// [ready message](ready_message).
+/// Losing the connection only detaches the pad so that reclaiming it is possible, while the
+/// "disconnect" command releases it for good.
+///
+/// Clients can control more pads by sending `{"type":"attach"}`, to which we reply with the
+/// new pad's index as `{"attached":1,"player":2,"label":"P2"}` or why there isn't one as `{"refused":"..."}`,
+/// and then tagging their updates with that index. These pads can't be reclaimed, and are
+/// released along with the connection.
+///
+/// Clients in keyboard mode send the keys they hold down instead of states, which are mapped
+/// to one with the keymap whenever they change.
+///
+/// Clients may only send so many messages per second. States sent faster than that are held
+/// back, keeping only the latest one for each pad, and passed on by the watchdog as soon as the
+/// rate limit allows, while other messages are dropped. Messages larger than
+/// [MAX_MESSAGE_SIZE] end the connection.
+///
+/// Whenever we close the connection, its close frame's reason is one of the [CloseReason]s.
+/// Once we're shutting down the connection is closed after the next message, as reading blocks,
+/// right after the [shutdown notice](shutdown_message) queued for every client. The same goes
+/// for connections one of whose pads was [discarded](PadRequest::Discard), whose pads are all
+/// released for good.
+///
+/// `echo` is the subprotocol agreed to in the handshake, which is usually `protocol`'s name.
+///
+/// Once the connection is over, a [SessionSummary] of it is logged.
+#[allow(clippy::too_many_arguments)]
+fn handle_websocket(
+    logger: Logger,
+    connection: Connection,
+    pad: NewPad,
+    req_tx: SyncSender<PadRequest>,
+    protocol: Protocol,
+    echo: Option<String>,
+    settings: Arc<WebsocketSettings>,
+    request: Request,
+) {
+    let req_tx = PadSender::Blocking(req_tx);
+    // The watchdog stops as soon as the handler, and with it this sender, is dropped
+    let (wake_tx, wake_rx) = channel();
+    let mut handler = Handler::new(
+        logger.clone(),
+        connection,
+        pad,
+        req_tx.clone(),
+        protocol,
+        Arc::clone(&settings),
+        move || {
+            let _ = wake_tx.send(());
+        },
+    );
+    {
+        let session = Arc::clone(&handler.session);
+        let idle_timeout = handler.idle_timeout();
+        spawn(move || watch_session(logger, session, req_tx, idle_timeout, wake_rx));
+    }
+
+    let result: Result<()> = (|| {
+        let mut ws = accept(request, echo)?;
+        let mut last_ping = Instant::now();
+        for message in handler.greeting()? {
+            ws.write_message(message)?;
+        }
+
+        loop {
+            let msg = match ws.read_message() {
+                Ok(msg) => msg,
+                Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+                Err(error @ tungstenite::Error::Capacity(_)) => {
+                    handler.too_large();
+                    return Err(error.into());
+                }
+                Err(error) => return Err(error.into()),
+            };
+            match handler.receive(msg)? {
+                Step::Continue => {}
+                Step::Attach(reply_rx) => {
+                    let reply = handler.attached(reply_rx.recv()?)?;
+                    ws.write_message(reply)?;
+                }
+                // Whatever was queued for the client, e.g. the shutdown notice, goes out first
+                Step::Close(reason) => return handler.outbox.close(&mut ws, reason.frame()),
+                // Reading the close frame has queued our reply to it, so we just need to send it
+                Step::Closed => {
+                    return match ws.write_pending() {
+                        Ok(()) | Err(tungstenite::Error::ConnectionClosed) => Ok(()),
+                        Err(error) => Err(error.into()),
+                    }
+                }
+            }
+
+            if last_ping.elapsed() >= PING_INTERVAL {
+                ws.write_message(Message::Ping(Vec::new()))?;
+                last_ping = Instant::now();
+            }
+            handler.feedback();
+            handler.outbox.flush(&mut ws)?;
+        }
+    })();
+
+    handler.finish(result);
+}
+
+/// What a websocket connection amounted to, which is logged once it's over along with how many
+/// of its states the rate limit coalesced or dropped
+#[derive(Debug)]
+struct SessionSummary {
+    started: Instant,
+
+    /// How many messages the client sent, pings included
+    messages: u64,
+
+    /// How many states were passed on to the pads, right away or once the rate limit let them
+    states: u64,
+
+    /// The last thing which went wrong, with the client's messages or the connection itself
+    last_error: Option<String>,
+}
+
+impl SessionSummary {
+    fn new(started: Instant) -> Self {
+        Self {
+            started,
+            messages: 0,
+            states: 0,
+            last_error: None,
+        }
+    }
+}
+
+/// Where clients can reach us
+#[derive(Debug)]
+struct Origin {
+    /// The host put into the URLs we hand out, which is the best of `addresses` unless
+    /// `--hostname` says otherwise
+    host: String,
+
+    /// Every address phones could reach us at, which get a QR code each when there's more than
+    /// one
+    addresses: Vec<Address>,
+
+    port: u16,
+
+    /// Whether we're serving over HTTPS
+    secure: bool,
+
+    /// Whether our certificate is self-signed, and will make browsers complain
+    self_signed: bool,
+}
+
+impl Origin {
+    /// Ourselves as reached through the given host instead
+    fn at(&self, host: impl Into<String>) -> Self {
+        Self {
+            host: host.into(),
+            addresses: Vec::new(),
+            ..*self
+        }
+    }
+
+    /// Our URL for the given path and query
+    fn http(&self, path: impl Display) -> String {
+        let scheme = if self.secure { "https" } else { "http" };
+        format!("{}://{}:{}{}", scheme, self.host, self.port, path)
+    }
+
+    /// Our websocket URL for the given path and query
+    fn ws(&self, path: impl Display) -> String {
+        let scheme = if self.secure { "wss" } else { "ws" };
+        format!("{}://{}:{}{}", scheme, self.host, self.port, path)
+    }
+}
+
+/// How a frontend running next to the server, like the [window](crate::gui), drives it
+#[derive(Debug, Default)]
+pub(crate) struct Frontend {
+    /// Set to have the token rotated, like requesting `/rotate` does
+    pub(crate) rotate_token: Arc<AtomicBool>,
+
+    /// Where to send how clients can reach us, once we're bound and whenever the token changes
+    pub(crate) invites: Option<Sender<Invite>>,
+
+    /// Where to send the address we're bound to, which has the port we ended up with
+    pub(crate) bound: Option<Sender<SocketAddr>>,
+}
+
+impl Frontend {
+    /// Let the frontend know how clients can reach us now, if it wants to
+    fn invite(&self, origin: &Origin, token: &Token) {
+        if let Some(invites) = &self.invites {
+            let controllers = LAYOUTS
+                .iter()
+                .map(|layout| {
+                    let path = format!("/controller?token={}&layout={}", token, layout.name);
+                    (layout, origin.http(path))
+                })
+                .collect();
+            // The frontend may have gone away already, which is its own business
+            let _ = invites.send(Invite {
+                controllers,
+                token: token.to_string(),
+            });
+        }
+    }
+}
+
+/// How clients can reach us, as the index page shows it
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub(crate) struct Invite {
+    /// The URL of the controller page, token included, for each layout
+    pub(crate) controllers: Vec<(&'static Layout, String)>,
+
+    pub(crate) token: String,
+}
+
+/// Generate a QR code from a given text and return it as an SVG data url, drawing a square for
+/// every dark module
+fn qr_data_url(text: &str) -> Result<String> {
+    let qr = QrCode::encode_text(text, QrCodeEcc::Low)?;
+
+    let mut path = String::new();
+    for y in 0..qr.size() {
+        for x in 0..qr.size() {
+            if qr.get_module(x, y) {
+                write!(path, "M{},{}h1v1h-1z", x + QR_BORDER, y + QR_BORDER)?;
+            }
+        }
+    }
+    let side = qr.size() + 2 * QR_BORDER;
+    let svg = format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {side} {side}" width="{pixels}" height="{pixels}" shape-rendering="crispEdges"><rect width="{side}" height="{side}" fill="#fff"/><path d="{path}"/></svg>"##,
+        side = side,
+        pixels = QR_SCALE * side,
+        path = path,
+    );
+
+    Ok(format!("data:image/svg+xml;base64,{}", base64::encode(svg)))
+}
+
+/// Return the HTML of the index page, with a QR code for every layout, and for every address if
+/// there's no telling which one phones can reach us at. The QR codes carry the token, so only
+/// this machine gets them, when `local`, along with the pads in use to test them from.
+fn index_page(
+    origin: &Origin,
+    token: &Token,
+    name: &str,
+    advertised: Option<&str>,
+    assets: &Assets,
+    local: bool,
+) -> Result<String> {
+    let name = escape_html(name);
+    let page = HtmlPage::new()
+        .add_title(&format!("Sphrosyne on {}", name))
+        .add_meta(vec![
+            ("charset", "utf8"),
+            ("viewport", "width=device-width, initial-scale=1.0"),
+        ]);
+    let page = if assets.linked() {
+        page.add_stylesheet(assets::STYLE_PATH)
+    } else {
+        page.add_style(assets::STYLE)
+    };
+
+    // Anyone on the network can ask for this page, and whoever has the token gets a pad
+    if !local {
+        return Ok(page
+            .add_paragraph(format!(
+                "The server is running on {}. Open this page on that computer for the QR codes \
+                 to connect your device with.",
+                name
+            ))
+            .to_html_string());
+    }
+
+    let mut page = page.add_paragraph(format!(
+        "The server is running on {}. Scan one of the following QR codes to connect your device:",
+        name
+    ));
+
+    let origins = match origin.addresses.as_slice() {
+        [_, _, ..] => origin
+            .addresses
+            .iter()
+            .map(|address| {
+                let label = format!("{} ({})", escape_html(&address.interface), address.ip);
+                (Some(label), origin.at(address.ip.to_string()))
+            })
+            .collect(),
+        _ => vec![(None, origin.at(origin.host.as_str()))],
+    };
+    for (label, origin) in origins {
+        if let Some(label) = label {
+            page = page.add_header(2, label);
+        }
+        for layout in LAYOUTS {
+            let url = origin.http(format_args!(
+                "/controller?token={}&layout={}",
+                token, layout.name
+            ));
+            page = page
+                .add_paragraph(format!("{}: {}", layout.name, layout.description))
+                .add_image(qr_data_url(&url)?, &url);
+        }
+    }
+
+    if origin.self_signed {
+        page = page.add_paragraph(
+            "The server uses a self-signed certificate, so your browser will warn you that the \
+             connection is not private. Accept the warning once to continue to the controller.",
+        );
+    }
+
+    // Several of us could be running on the same network, so say which one this is
+    if let Some(name) = advertised {
+        page = page.add_paragraph(format!(
+            "This server is advertised on the local network as \"{}\".",
+            escape_html(name)
+        ));
+    }
+
+    let page = page
+        .add_paragraph(format!("Token: {}", token))
+        .add_header(2, "Pads")
+        .add_raw(r#"<div id="pads"></div>"#);
+    let page = if assets.linked() {
+        page.add_script_link(assets::ADMIN_PATH)
+    } else {
+        page.add_script_literal(assets::ADMIN)
+    };
+    Ok(page.to_html_string())
+}
+
+/// The TXT record entries we're advertised with over mDNS, besides our instance name and port
+fn txt_record(origin: &Origin, name: &str) -> Vec<String> {
+    vec![
+        format!("name={}", name),
+        format!("protocols={}", Protocol::names()),
+        // Every page but the index needs the token from the QR codes
+        "token=required".to_string(),
+        format!("scheme={}", if origin.secure { "https" } else { "http" }),
+    ]
+}
+
+// Return the HTML of the controller page. Custom layouts are drawn by the layout made to draw
+// them, [CUSTOM](layout::CUSTOM), which they're given to as `CUSTOM_LAYOUT`.
+fn controller_page(
+    origin: &Origin,
+    token: &Token,
+    name: &str,
+    layout: &Layout,
+    custom: Option<&CustomLayout>,
+    assets: &Assets,
+) -> Result<String> {
+    let url = origin.ws(format_args!("/websocket?token={}", token));
+
+    let page = HtmlPage::new()
+        .add_title(&format!("Sphrosyne Controller on {}", escape_html(name)))
+        .add_meta(vec![
+            ("charset", "utf8"),
+            ("viewport", "width=device-width, initial-scale=1.0"),
+        ])
+        // We pass in our websocket URL as a hidden input on the page so our javascript can retrieve it
+        .add_raw(format_args!(
+            r#"<input type="hidden" id="url" value="{}">"#,
+            url
+        ));
+    let page = match custom {
+        // Closing the script is all the layout could do to break out of it
+        Some(custom) => page.add_script_literal(format!(
+            "const CUSTOM_LAYOUT = {};",
+            serde_json::to_string(custom)?.replace("</", "<\\/")
+        )),
+        None => page,
+    };
+    // Assets read from disk are linked to, so that reloading the page picks up changes to them
+    let page = if assets.linked() {
+        page.add_stylesheet(assets::STYLE_PATH)
+            .add_script_link(assets::CONTROLLER_PATH)
+            .add_script_link(Assets::layout_path(layout))
+    } else {
+        page.add_style(assets::STYLE)
+            .add_script_literal(assets::CONTROLLER)
+            .add_script_literal(layout.script)
+    };
+    Ok(page.to_html_string())
+}
+
+/// The page served instead of every other while ViGEmBus is missing, telling whoever runs us
+/// where to get it
+fn driver_missing_page(assets: &Assets) -> String {
+    let page = HtmlPage::new()
+        .add_title("Sphrosyne: driver not installed")
+        .add_meta(vec![
+            ("charset", "utf8"),
+            ("viewport", "width=device-width, initial-scale=1.0"),
+        ]);
+    let page = if assets.linked() {
+        page.add_stylesheet(assets::STYLE_PATH)
+    } else {
+        page.add_style(assets::STYLE)
+    };
+    page.add_header(1, "ViGEmBus is not installed")
+        .add_paragraph(
+            "The server is running, but can't give out any pads without the ViGEmBus driver to \
+             plug them into. Install it on the machine running sphrosyne from:",
+        )
+        .add_link(DRIVER_URL, DRIVER_URL)
+        .add_paragraph(
+            "There's no need to restart sphrosyne afterwards. Reload this page once the driver \
+             is installed to get the QR codes.",
+        )
+        .to_html_string()
+}
+
+/// A response to a request which didn't become a websocket, which either server can send
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Reply {
+    pub(crate) status: u16,
+    pub(crate) headers: Vec<(&'static str, String)>,
+    pub(crate) body: String,
+}
+
+impl Reply {
+    /// A successful response with the given content type
+    fn new(content_type: &str, body: impl Into<String>) -> Self {
+        Self {
+            status: 200,
+            headers: vec![("Content-Type", content_type.to_string())],
+            body: body.into(),
+        }
+    }
+
+    fn text(body: impl Into<String>) -> Self {
+        Self::new("text/plain; charset=UTF-8", body)
+    }
+
+    fn html(body: impl Into<String>) -> Self {
+        Self::new("text/html", body)
+    }
+
+    fn json(data: &impl serde::Serialize) -> Result<Self> {
+        Ok(Self::new("application/json", serde_json::to_string(data)?))
+    }
+
+    /// A response with no content other than the status code's reason phrase
+    pub(crate) fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: StatusCode(status).default_reason_phrase().to_string(),
+        }
+    }
+
+    fn with_status(self, status: u16) -> Self {
+        Self { status, ..self }
+    }
+
+    fn with_header(mut self, field: &'static str, value: impl Display) -> Self {
+        self.headers.push((field, value.to_string()));
+        self
+    }
+
+    /// The response as the blocking server sends it
+    fn into_response(self) -> Response<Cursor<Vec<u8>>> {
+        let headers = self
+            .headers
+            .iter()
+            .map(|(field, value)| Header::from_bytes(*field, value.as_str()).unwrap())
+            .collect();
+        let length = self.body.len();
+        Response::new(
+            StatusCode(self.status),
+            headers,
+            Cursor::new(self.body.into_bytes()),
+            Some(length),
+            None,
+        )
+    }
+}
+
+/// Who we are, as served at `/whoami` for apps listing the servers on the network
+#[derive(Debug, Serialize)]
+struct WhoAmI<'a> {
+    /// The name we tell clients we go by
+    name: &'a str,
+
+    version: &'static str,
+
+    /// How many pads clients are using, unless the pads didn't say in time
+    active_pads: Option<usize>,
+
+    /// Whether clients need the token from the QR codes, which they always do for now
+    token_required: bool,
+}
+
+/// Text made safe to put in a page, like names which come from whoever runs us
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Split a request's URL into its path and its query string
+fn split_url(url: &str) -> (&str, &str) {
+    url.split_once('?').unwrap_or((url, ""))
+}
+
+/// The id of the pad an admin path like `/admin/pads/3/kick` is about and what to do with it, if
+/// it's one
+fn pad_action(path: &str) -> Option<(usize, &str)> {
+    let (id, action) = path.strip_prefix("/admin/pads/")?.split_once('/')?;
+    Some((id.parse().ok()?, action))
+}
+
+/// The name of the custom layout at the given path, which is any under `/layouts/` but the
+/// built-in layouts' scripts
+fn custom_layout_name(path: &str) -> Option<&str> {
+    path.strip_prefix("/layouts/")
+        .filter(|name| !name.ends_with(".js"))
+}
+
+/// Send the custom layout of the given name, or save it if it's being PUT
+fn layout_reply(
+    logger: &Logger,
+    layouts: &LayoutStore,
+    name: &str,
+    req: &Incoming,
+) -> Result<Reply> {
+    let reply = match req.method {
+        Method::Get => match layouts.get(name) {
+            Ok(Some(layout)) => Reply::json(&layout)?,
+            Ok(None) => Reply::status(404),
+            Err(error) => {
+                warn!(logger, "layouts.error"; "name" => name, "error" => %error);
+                Reply::status(500)
+            }
+        },
+        Method::Put if Layout::find(name).is_some() => {
+            Reply::text(format!("{} is a built-in layout", name)).with_status(409)
+        }
+        Method::Put if !custom_layout::valid_name(name) => {
+            Reply::text("layout names can only have ASCII letters, digits, dashes and underscores")
+                .with_status(400)
+        }
+        Method::Put if req.body.len() > MAX_LAYOUT_SIZE => Reply::status(413),
+        Method::Put => match serde_json::from_slice::<CustomLayout>(req.body) {
+            Err(error) => Reply::text(format!("invalid layout: {}", error)).with_status(400),
+            Ok(layout) => match layout.validate() {
+                Err(reason) => Reply::text(reason.to_string()).with_status(422),
+                Ok(()) => match layouts.put(name, &layout) {
+                    Ok(()) => {
+                        info!(logger, "layouts.saved"; "name" => name, "controls" => layout.controls.len());
+                        Reply::text(format!("saved layout {}", name))
+                    }
+                    Err(error) => {
+                        error!(logger, "layouts.error"; "dir" => %layouts.dir().display(), "error" => %error);
+                        Reply::status(500)
+                    }
+                },
+            },
+        },
+        _ => Reply::status(405),
+    };
+    Ok(reply)
+}
+
+/// Find the value of a parameter in a query string
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// How a request proved that it knows the token
+#[derive(Debug, PartialEq, Eq)]
+enum Authorization {
+    /// Via the `token` query parameter
+    Query,
+
+    /// Via a websocket subprotocol, which has to be echoed back to the client
+    Protocol(String),
+}
+
+/// The host a request was sent to, without its port, so that the pages it gets link back to
+/// wherever the client reached us. Hosts which aren't names or addresses are left out, as
+/// they'd end up in the page's HTML.
+fn request_host(headers: &[Header]) -> Option<&str> {
+    let host = headers
+        .iter()
+        .find(|h| h.field.equiv("Host"))?
+        .value
+        .as_str();
+    let host = match host.rsplit_once(':') {
+        // The colons of an IPv6 address are in brackets, before the port
+        Some((host, port)) if !port.contains(']') => host,
+        _ => host,
+    };
+    let valid = |c: char| c.is_ascii_alphanumeric() || ".-:[]".contains(c);
+    Some(host).filter(|host| !host.is_empty() && host.chars().all(valid))
+}
+
+/// The websocket subprotocols a request offers, in order of preference
+fn offered_protocols(headers: &[Header]) -> impl Iterator<Item = &str> {
+    headers
+        .iter()
+        .filter(|h| h.field.equiv("Sec-WebSocket-Protocol"))
+        .flat_map(|h| h.value.as_str().split(','))
+        .map(str::trim)
+}
+
+/// Check whether a request presents the token, either as the `token` query parameter or as
+/// one of the websocket subprotocols it offers.
+fn authorize(token: &Token, query: &str, headers: &[Header]) -> Option<Authorization> {
+    if query_param(query, "token").is_some_and(|candidate| token.matches(candidate)) {
+        return Some(Authorization::Query);
+    }
+
+    offered_protocols(headers)
+        .find(|candidate| token.matches(candidate))
+        .map(|protocol| Authorization::Protocol(protocol.to_string()))
+}
+
+/// Bind the server to the given address, explaining what went wrong if we couldn't.
+fn bind(logger: &Logger, addr: SocketAddr, tls: Option<&Tls>, host: &str) -> Result<Server> {
+    let server = match tls {
+        None => Server::http(addr),
+
+        #[cfg(feature = "tls")]
+        Some(tls) => Server::https(addr, tls.ssl_config(logger, host)?),
+
+        #[cfg(not(feature = "tls"))]
+        Some(_) => {
+            let _ = (logger, host);
+            return Err(format_err!(
+                "sphrosyne was built without TLS support, rebuild it with --features tls"
+            ));
+        }
+    };
+
+    server.map_err(|err| match err.downcast_ref::<io::Error>() {
+        Some(err) => bind_error(addr, err),
+        None => format_err!("Could not bind to {}: {}", addr, err),
+    })
+}
+
+/// Explain why we couldn't bind to the given address
+pub(crate) fn bind_error(addr: SocketAddr, err: &io::Error) -> eyre::Report {
+    match err.kind() {
+        io::ErrorKind::AddrInUse => format_err!(
+            "Port {} is already in use, pick another one with --port",
+            addr.port()
+        ),
+        io::ErrorKind::AddrNotAvailable => format_err!(
+            "{} is not an address of this machine, pick another one with --bind",
+            addr.ip()
+        ),
+        _ => format_err!("Could not bind to {}: {}", addr, err),
+    }
+}
+
+/// Wait for the given websocket handlers to finish, for up to [SHUTDOWN_TIMEOUT]. Returns how
+/// many are still running, whose clients haven't sent anything since we started shutting down.
+fn join_websockets(websockets: Vec<JoinHandle<()>>) -> usize {
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+    let mut websockets = websockets;
+    loop {
+        let (finished, running): (Vec<_>, Vec<_>) =
+            websockets.into_iter().partition(JoinHandle::is_finished);
+        for websocket in finished {
+            let _ = websocket.join();
+        }
+        if running.is_empty() || Instant::now() >= deadline {
+            return running.len();
+        }
+        websockets = running;
+        sleep(SHUTDOWN_POLL_INTERVAL);
+    }
+}
+
+/// Save the session token and the pads to the given path, for the next run to restore
+fn save_snapshot(tx: &SyncSender<PadRequest>, token: &Token, path: &Path) -> Result<usize> {
+    let (reply_tx, reply_rx) = channel();
+    tx.send(PadRequest::Snapshot(reply_tx))?;
+    let pads = reply_rx.recv_timeout(STATUS_TIMEOUT)?;
+    let count = pads.len();
+    Snapshot {
+        version: SNAPSHOT_VERSION,
+        token: token.to_string(),
+        pads,
+    }
+    .save(path)?;
+    Ok(count)
+}
+
+/// A request either server got, with as much of it as routing it needs
+pub(crate) struct Incoming<'a> {
+    pub(crate) remote: SocketAddr,
+    pub(crate) method: Method,
+    pub(crate) url: &'a str,
+    pub(crate) headers: &'a [Header],
+
+    /// The body of a PUT, which is the only method we read one for, cut off a byte past
+    /// [MAX_LAYOUT_SIZE] so that it's known to be too large without reading all of it
+    pub(crate) body: &'a [u8],
+}
+
+/// What to do with a request once it's been [routed](Router::route)
+pub(crate) enum Routed {
+    Respond(Reply),
+
+    /// Make it a websocket, for the client to control the pad it was given
+    Upgrade(Box<Upgrade>),
+
+    /// Make it a websocket only to [close it right away](refuse_full), as there's no pad for it
+    RefuseFull {
+        logger: Logger,
+        echo: Option<String>,
+    },
+}
+
+/// A request which got a pad, and becomes a websocket to control it
+pub(crate) struct Upgrade {
+    pub(crate) logger: Logger,
+    pub(crate) connection: Connection,
+    pub(crate) pad: NewPad,
+    pub(crate) protocol: Protocol,
+
+    /// The subprotocol agreed to in the handshake, which is usually `protocol`'s name
+    pub(crate) echo: Option<String>,
+
+    pub(crate) settings: Arc<WebsocketSettings>,
+}
+
+/// Everything the servers need to route requests, shared by the blocking one in [mainloop] and
+/// the async one, which only differ in how they read requests and handle websockets
+pub(crate) struct Router {
+    pub(crate) logger: Logger,
+    pub(crate) args: Args,
+    token: Token,
+    settings: Arc<WebsocketSettings>,
+    pub(crate) tx: SyncSender<PadRequest>,
+    driver_missing: Arc<AtomicBool>,
+    frontend: Frontend,
+    origin: Origin,
+    assets: Assets,
+    layouts: LayoutStore,
+
+    /// We're withdrawn from the network once this is dropped, as we shut down
+    advertisement: Option<Advertisement>,
+}
+
+impl Router {
+    /// Get ready to route requests to a server bound to `addr`, letting the frontend and the
+    /// network know how to reach it
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        logger: Logger,
+        args: Args,
+        token: Token,
+        keymap: Keymap,
+        remaps: RemapProfiles,
+        tx: SyncSender<PadRequest>,
+        shutdown: Arc<AtomicBool>,
+        metrics: Arc<Metrics>,
+        driver_missing: Arc<AtomicBool>,
+        frontend: Frontend,
+        host: String,
+        addr: SocketAddr,
+    ) -> Result<Self> {
+        let settings = Arc::new(WebsocketSettings {
+            idle_timeout: args.idle_timeout,
+            keymap,
+            remaps,
+            rate_limit: args.rate_limit,
+            shutdown,
+            metrics,
+            outboxes: Arc::default(),
+            name: args.instance_name()?,
+        });
+        let name = &settings.name;
+
+        // Plenty of phones can't resolve our hostname, so they're given our addresses instead
+        let addresses = match &args.hostname {
+            Some(_) => Vec::new(),
+            None => interfaces::local_addresses(args.bind).unwrap_or_else(|error| {
+                warn!(logger, "server.interfaces_error"; "error" => %error);
+                Vec::new()
+            }),
+        };
+        let origin = Origin {
+            host: addresses
+                .first()
+                .map_or(host, |address| address.ip.to_string()),
+            addresses,
+            port: addr.port(),
+            secure: args.tls.is_some(),
+            self_signed: args.tls == Some(Tls::SelfSigned),
+        };
+        info!(logger, "server.bound"; "addr" => addr, "url" => origin.http("/"));
+        if let Some(bound) = &frontend.bound {
+            let _ = bound.send(addr);
+        }
+
+        let advertisement = if args.mdns {
+            match Advertisement::start(name, origin.port, &txt_record(&origin, name)) {
+                Ok(advertisement) => {
+                    info!(logger, "mdns.advertised"; "name" => &advertisement.name, "type" => discovery::SERVICE_TYPE);
+                    Some(advertisement)
+                }
+                // Being discoverable is a convenience, so there's no need to stop over it
+                Err(error) => {
+                    warn!(logger, "mdns.error"; "error" => #%error);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        info!(logger, "server.token"; "token" => %token);
+        frontend.invite(&origin, &token);
+
+        let assets = Assets::new(args.assets.clone());
+        if let Some(dir) = &args.assets {
+            info!(logger, "server.assets"; "dir" => %dir.display());
+        }
+
+        let layouts = LayoutStore::new(args.layouts.clone());
+        Ok(Self {
+            logger,
+            args,
+            token,
+            settings,
+            tx,
+            driver_missing,
+            frontend,
+            origin,
+            assets,
+            layouts,
+            advertisement,
+        })
+    }
+
+    /// Whether we're shutting down, and should stop taking requests
+    pub(crate) fn shutting_down(&self) -> bool {
+        self.settings.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Rotate the token if the frontend asked us to since we last checked
+    pub(crate) fn rotate_if_asked(&mut self) {
+        if self.frontend.rotate_token.swap(false, Ordering::SeqCst) {
+            self.token = Token::generate();
+            info!(self.logger, "server.token"; "token" => %self.token, "by" => "frontend");
+            self.frontend.invite(&self.origin, &self.token);
+        }
+    }
+
+    /// Decide what to do with a request, getting a pad for it if it wants one
+    pub(crate) fn route(&mut self, req: &Incoming) -> Result<Routed> {
+        let (logger, tx, settings) = (&self.logger, &self.tx, &self.settings);
+        let (origin, assets, name) = (&self.origin, &self.assets, &settings.name);
+        let advertised = self
+            .advertisement
+            .as_ref()
+            .map(|advertisement| advertisement.name.as_str());
+
+        // Whoever we don't serve doesn't get to do anything, least of all get a pad
+        let ip = req.remote.ip();
+        if !self.args.allow.allows(ip) {
+            warn!(logger, "req.denied"; "ip" => %ip, "url" => req.url);
+            return Ok(Routed::Respond(Reply::status(403)));
+        }
+
+        let (path, query) = split_url(req.url);
+        let authorization = authorize(&self.token, query, req.headers);
+
+        // Asking the pads for one would only hang until the driver is there. Clients reconnecting
+        // are told when to try again instead of being sent a page.
+        if matches!(path, "/" | "/controller" | "/websocket")
+            && self.driver_missing.load(Ordering::SeqCst)
+        {
+            info!(logger, "req.driver_missing"; "path" => path);
+            let reply = if path == "/websocket" {
+                Reply::text(format!("ViGEmBus isn't installed, see {}", DRIVER_URL))
+                    .with_status(409)
+                    .with_header("Retry-After", DRIVER_POLL_INTERVAL.as_secs())
+            } else {
+                Reply::html(driver_missing_page(assets)).with_status(503)
+            };
+            return Ok(Routed::Respond(reply));
+        }
+
+        let reply = match path {
+            "/" => Reply::html(index_page(
+                origin,
+                &self.token,
+                name,
+                advertised,
+                assets,
+                ip.is_loopback(),
+            )?),
+
+            "/controller" | "/websocket" if authorization.is_none() => {
+                info!(logger, "req.unauthorized"; "addr" => req.remote, "path" => path);
+                Reply::status(403)
+            }
+
+            "/controller" => {
+                // Pages without a layout parameter predate layouts, so give them the default one.
+                // Names which aren't a built-in layout's are those of custom ones.
+                let (layout, custom) = match query_param(query, "layout") {
+                    None => (Some(Layout::default()), None),
+                    Some(name) => match Layout::find(name) {
+                        Some(layout) => (Some(layout), None),
+                        None => match self.layouts.get(name) {
+                            Ok(custom) => (custom.as_ref().map(|_| &layout::CUSTOM), custom),
+                            Err(error) => {
+                                warn!(logger, "layouts.error"; "name" => name, "error" => %error);
+                                return Ok(Routed::Respond(Reply::status(500)));
+                            }
+                        },
+                    },
+                };
+                match layout {
+                    Some(layout) => {
+                        // Phones connect their websocket wherever they got the page from, which
+                        // may not be the host we'd hand out
+                        let origin = match request_host(req.headers) {
+                            Some(host) => origin.at(host),
+                            None => origin.at(origin.host.as_str()),
+                        };
+                        let (token, custom) = (&self.token, custom.as_ref());
+                        Reply::html(controller_page(
+                            &origin, token, name, layout, custom, assets,
+                        )?)
+                    }
+                    None => Reply::status(404),
+                }
+            }
+
+            "/websocket" => {
+                let connection = Connection::new(req.remote.ip());
+                let logger = logger.new(o!(connection.clone()));
+                // Devices which don't have an identifier yet are given one, which they're told
+                // in the hello message
+                let device = query_param(query, "device")
+                    .filter(|device| valid_device(device))
+                    .map_or_else(|| Token::generate().to_string(), str::to_string);
+                let layout = query_param(query, "layout")
+                    .filter(|name| Layout::find(name).is_some() || self.layouts.exists(name));
+                if let Some(layout) = layout {
+                    let change = ProfileChange::Layout(layout.to_string());
+                    tx.send(PadRequest::Remember(device.clone(), change))?;
+                }
+                let device = Some(device);
+                // Refuse pad types we don't know about before getting a pad, let alone upgrading
+                let pad_type = match query_param(query, "type") {
+                    None => PadType::default(),
+                    Some(name) => match PadType::find(name) {
+                        Some(pad_type) => pad_type,
+                        None => {
+                            info!(logger, "ws.refused"; "type" => name);
+                            let reply = Reply::text(format!("unknown pad type {:?}", name));
+                            return Ok(Routed::Respond(reply.with_status(400)));
+                        }
+                    },
+                };
+                // Clients which lost their connection try to get their old pad back
+                let (reply_tx, reply_rx) = channel();
+                match query_param(query, "reclaim") {
+                    Some(reclaim) => tx.send(PadRequest::Reclaim(
+                        reclaim.to_string(),
+                        connection.clone(),
+                        device,
+                        pad_type,
+                        reply_tx,
+                    ))?,
+                    None => tx.send(PadRequest::Acquire(
+                        connection.clone(),
+                        device,
+                        pad_type,
+                        reply_tx,
+                    ))?,
+                }
+                let (protocol, echo) = match Protocol::negotiate(offered_protocols(req.headers)) {
+                    Some(protocol) => (protocol, Some(protocol.name().to_string())),
+                    None => {
+                        warn!(logger, "ws.protocol.default"; "protocol" => Protocol::JsonV1.name());
+                        // Browsers refuse the connection unless we agree to one of the
+                        // subprotocols they offered, so agree to the token if that's all we got
+                        let echo = match authorization {
+                            Some(Authorization::Protocol(token)) => Some(token),
+                            _ => None,
+                        };
+                        (Protocol::JsonV1, echo)
+                    }
+                };
+
+                let pad = match reply_rx.recv()? {
+                    Ok(pad) => pad,
+                    Err(reason) if reason.is::<ServerFull>() => {
+                        info!(logger, "ws.refused"; "reason" => %CloseReason::ServerFull);
+                        return Ok(Routed::RefuseFull { logger, echo });
+                    }
+                    Err(reason) => {
+                        info!(logger, "ws.refused"; "reason" => %reason);
+                        let reply = Reply::text(reason.to_string()).with_status(503);
+                        return Ok(Routed::Respond(reply));
+                    }
+                };
+                let logger = logger.new(o!("id" => pad.id));
+                info!(logger, "ws.new");
+                info!(logger, "ws.protocol"; "protocol" => protocol.name());
+                return Ok(Routed::Upgrade(Box::new(Upgrade {
+                    logger,
+                    connection,
+                    pad,
+                    protocol,
+                    echo,
+                    settings: Arc::clone(settings),
+                })));
+            }
+
+            // Apps listing the servers on the network can't know any of their tokens yet
+            "/whoami" => {
+                let active_pads = if self.driver_missing.load(Ordering::SeqCst) {
+                    None
+                } else {
+                    let (reply_tx, reply_rx) = channel();
+                    tx.send(PadRequest::Status(reply_tx))?;
+                    reply_rx
+                        .recv_timeout(STATUS_TIMEOUT)
+                        .ok()
+                        .map(|status| status.active_pads())
+                };
+                Reply::json(&WhoAmI {
+                    name,
+                    version: env!("CARGO_PKG_VERSION"),
+                    active_pads,
+                    token_required: true,
+                })?
+            }
+
+            // Nor can the people writing clients of their own
+            "/protocol" => Reply::json(&Document::new(Protocol::newest_first()))?,
+
+            // Monitoring from the machine we're running on doesn't need to know the token
+            "/status" if authorization.is_some() || ip.is_loopback() => {
+                let (reply_tx, reply_rx) = channel();
+                tx.send(PadRequest::Status(reply_tx))?;
+                // We won't get a reply while the pads are waiting for the bus to come back
+                match reply_rx.recv_timeout(STATUS_TIMEOUT) {
+                    Ok(status) => Reply::json(&status)?,
+                    Err(_) => Reply::status(503),
+                }
+            }
+
+            "/status" => Reply::status(403),
+
+            // Like the status, scrapers on other machines have to know the token
+            #[cfg(feature = "metrics")]
+            "/metrics" if authorization.is_some() || ip.is_loopback() => {
+                Reply::new("text/plain; version=0.0.4", settings.metrics.render())
+            }
+
+            #[cfg(feature = "metrics")]
+            "/metrics" => Reply::status(403),
+
+            // Only allow rotating the token from the machine we're running on
+            "/rotate" if !ip.is_loopback() => Reply::status(403),
+
+            // Any page this machine opens could link here, which mustn't lock everyone out
+            "/rotate" if req.method != Method::Post => Reply::status(405),
+
+            "/rotate" => {
+                self.token = Token::generate();
+                info!(logger, "server.token"; "token" => %self.token);
+                self.frontend.invite(origin, &self.token);
+                Reply::text(self.token.to_string())
+            }
+
+            // Shutting down to be started again, e.g. once updated, is just as local
+            "/admin/reload" if ip.is_loopback() => {
+                if self.args.snapshot.is_some() {
+                    info!(logger, "shutdown.reload");
+                    settings.shutdown.store(true, Ordering::SeqCst);
+                    Reply::text("saving the pads and shutting down")
+                } else {
+                    Reply::text("reloading needs --snapshot to save the pads to").with_status(409)
+                }
+            }
+
+            "/admin/reload" => Reply::status(403),
+
+            // Knowing the token doesn't make a phone an admin, so looking at the other clients
+            // and kicking them is just as local
+            _ if path.starts_with("/admin/pads") && !ip.is_loopback() => Reply::status(403),
+
+            "/admin/pads" => {
+                let (reply_tx, reply_rx) = channel();
+                tx.send(PadRequest::Clients(reply_tx))?;
+                match reply_rx.recv_timeout(STATUS_TIMEOUT) {
+                    Ok(clients) => Reply::json(&clients)?,
+                    Err(_) => Reply::status(503),
+                }
+            }
+
+            // Which layouts are there is as secret as the pages they're drawn on
+            _ if custom_layout_name(path).is_some() && authorization.is_none() => {
+                info!(logger, "req.unauthorized"; "addr" => req.remote, "path" => path);
+                Reply::status(403)
+            }
+
+            _ if custom_layout_name(path).is_some() => {
+                let name = custom_layout_name(path).unwrap_or_default();
+                layout_reply(logger, &self.layouts, name, req)?
+            }
+
+            _ if path.starts_with("/admin/pads/") => match pad_action(path)
+                .filter(|(_, action)| matches!(*action, "kick" | "test-rumble" | "test-input"))
+            {
+                None => Reply::status(404),
+                // Following a link, e.g. one a browser prefetches, mustn't do anything
+                Some(_) if req.method != Method::Post => Reply::status(405),
+                Some((id, "kick")) => {
+                    let (reply_tx, reply_rx) = channel();
+                    tx.send(PadRequest::Kick(id, reply_tx))?;
+                    match reply_rx.recv_timeout(STATUS_TIMEOUT) {
+                        Ok(true) => {
+                            info!(logger, "admin.kick"; "id" => id);
+                            Reply::text(format!("kicked pad {}", id))
+                        }
+                        Ok(false) => Reply::status(404),
+                        Err(_) => Reply::status(503),
+                    }
+                }
+                // The haptic message goes straight to the client, as the pad's rumble would
+                // only reach it once a game makes the pad rumble
+                Some((id, "test-rumble")) => {
+                    let queued = settings
+                        .outboxes
+                        .send_to_pad(id, |index| haptic_message(index, TEST_RUMBLE));
+                    info!(logger, "admin.test_rumble"; "id" => id, "queued" => queued);
+                    if queued {
+                        Reply::text(format!("rumbling pad {}", id))
+                    } else {
+                        Reply::status(404)
+                    }
+                }
+                Some((id, _)) => {
+                    let (reply_tx, reply_rx) = channel();
+                    tx.send(PadRequest::TestInput(id, reply_tx))?;
+                    let reply = reply_rx.recv_timeout(STATUS_TIMEOUT);
+                    info!(logger, "admin.test_input"; "id" => id, "reply" => ?reply);
+                    match reply {
+                        Ok(TestInputReply::Started) => {
+                            Reply::text(format!("playing the test input on pad {}", id))
+                        }
+                        Ok(TestInputReply::NoSuchPad) => Reply::status(404),
+                        Ok(TestInputReply::Busy) => Reply::text(format!(
+                            "pad {} is in use, and its client's input takes precedence",
+                            id
+                        ))
+                        .with_status(409),
+                        Err(_) => Reply::status(503),
+                    }
+                }
+            },
+
+            _ => match assets.get(path) {
+                Ok(Some((data, content_type))) => Reply::new(content_type, data),
+                Ok(None) => Reply::status(404),
+                Err(error) => {
+                    warn!(logger, "assets.error"; "path" => path, "error" => %error);
+                    Reply::status(500)
+                }
+            },
+        };
+        Ok(Routed::Respond(reply))
+    }
+
+    /// Let every client know we're shutting down, once we've stopped taking requests
+    pub(crate) fn notify_shutdown(&self, websockets: usize) {
+        let notified = self
+            .settings
+            .outboxes
+            .broadcast(shutdown_message(self.args.snapshot.is_some()));
+        info!(self.logger, "shutdown.start"; "websockets" => websockets, "notified" => notified);
+    }
+
+    /// Tell the pads to shut down, once they're saved if there's a snapshot to save them to.
+    /// `running` is how many websockets didn't close in time.
+    pub(crate) fn finish(&mut self, running: usize) -> Result<()> {
+        let logger = &self.logger;
+        if running > 0 {
+            warn!(logger, "shutdown.websockets"; "running" => running);
+        }
+        if let Some(path) = &self.args.snapshot {
+            // Players having to scan the QR code again is no reason not to shut down
+            match save_snapshot(&self.tx, &self.token, path) {
+                Ok(pads) => {
+                    info!(logger, "snapshot.saved"; "path" => %path.display(), "pads" => pads)
+                }
+                Err(error) => error!(logger, "snapshot.error"; "error" => %error),
+            }
+        }
+        // The process may well exit as soon as the pads are gone, so withdraw ourselves before that
+        drop(self.advertisement.take());
+        self.tx.send(PadRequest::Shutdown)?;
+        Ok(())
+    }
+}
+
+/// Serve pages and websockets until `shutdown` is set, after which the websockets are closed
+/// and the pads told to shut down with [PadRequest::Shutdown], once they're saved if there's a
+/// snapshot to save them to.
+///
+/// Clients have to present `token` to be let in. While `driver_missing` is set there are no pads
+/// to give them, so they're shown where to get ViGEmBus instead. `frontend` is told how to reach
+/// us, and may have the token rotated.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn mainloop(
+    logger: Logger,
+    args: Args,
+    token: Token,
+    keymap: Keymap,
+    remaps: RemapProfiles,
+    tx: SyncSender<PadRequest>,
+    shutdown: Arc<AtomicBool>,
+    metrics: Arc<Metrics>,
+    driver_missing: Arc<AtomicBool>,
+    frontend: Frontend,
+) -> Result<()> {
+    let host = args.public_host()?;
+    let server = bind(&logger, args.addr(), args.tls.as_ref(), &host)?;
+    let mut router = Router::new(
+        logger,
+        args,
+        token,
+        keymap,
+        remaps,
+        tx,
+        shutdown,
+        metrics,
+        driver_missing,
+        frontend,
+        host,
+        server.server_addr(),
+    )?;
+    let mut websockets = Vec::new();
+
+    while !router.shutting_down() {
+        // Forget about the websockets which are already done, so that they don't pile up
+        websockets.retain(|websocket: &JoinHandle<()>| !websocket.is_finished());
+        router.rotate_if_asked();
+
+        let mut req = match server.recv_timeout(SHUTDOWN_POLL_INTERVAL)? {
+            Some(req) => req,
+            None => continue,
+        };
+        debug!(router.logger, "req"; "req" => ?req, "headers" => ?req.headers());
+
+        // Only layouts are ever PUT, so that's the only body worth reading
+        let mut body = Vec::new();
+        if *req.method() == Method::Put {
+            let limit = MAX_LAYOUT_SIZE as u64 + 1;
+            if let Err(error) = req.as_reader().take(limit).read_to_end(&mut body) {
+                debug!(router.logger, "req.body_error"; "error" => %error);
+                continue;
+            }
+        }
+        let routed = router.route(&Incoming {
+            remote: *req.remote_addr(),
+            method: req.method().clone(),
+            url: req.url(),
+            headers: req.headers(),
+            body: &body,
+        })?;
+        match routed {
+            Routed::Respond(reply) => req.respond(reply.into_response())?,
+            Routed::RefuseFull { logger, echo } => {
+                if let Err(error) = refuse_full(req, echo) {
+                    debug!(logger, "ws.error"; "error" => #%error);
+                }
+            }
+            Routed::Upgrade(upgrade) => {
+                let Upgrade {
+                    logger,
+                    connection,
+                    pad,
+                    protocol,
+                    echo,
+                    settings,
+                } = *upgrade;
+                let req_tx = router.tx.clone();
+                websockets.push(spawn(move || {
+                    handle_websocket(
+                        logger, connection, pad, req_tx, protocol, echo, settings, req,
+                    )
+                }));
+            }
+        }
+    }
+
+    router.notify_shutdown(websockets.len());
+    let running = join_websockets(websockets);
+    router.finish(running)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpStream, sync::mpsc::sync_channel, thread::JoinHandle};
+
+    use slog::Discard;
+    use tungstenite::protocol::frame::coding::CloseCode;
+    use vigem_client_c::X360Buttons;
+
+    use super::*;
+    use crate::{
+        latency::Latency,
+        profiles::Profile,
+        protocol::BuiltIn,
+        ratelimit::Throttled,
+        request::PipelineEdit,
+        request::QUEUE_SIZE,
+        status::ClientStatus,
+        synthesis::{StickToDpad, Synthesis},
+    };
+
+    /// Spawn a server handling a single websocket for pad 0, returning a client connected to it
+    fn connect() -> (WebSocket<TcpStream>, Receiver<PadRequest>, JoinHandle<()>) {
+        connect_with_timeout(Duration::from_secs(60))
+    }
+
+    fn connect_with_timeout(
+        idle_timeout: Duration,
+    ) -> (WebSocket<TcpStream>, Receiver<PadRequest>, JoinHandle<()>) {
+        connect_with(
+            Arc::new(settings(idle_timeout)),
+            Arc::default(),
+            Arc::default(),
+            Arc::default(),
+        )
+    }
+
+    fn settings(idle_timeout: Duration) -> WebsocketSettings {
+        WebsocketSettings {
+            idle_timeout,
+            keymap: Keymap::default(),
+            remaps: RemapProfiles::default(),
+            rate_limit: 250,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            metrics: Arc::default(),
+            outboxes: Arc::default(),
+            name: "example".to_string(),
+        }
+    }
+
+    fn connect_with(
+        settings: Arc<WebsocketSettings>,
+        throttled: Arc<Throttled>,
+        latest: Arc<LatestState>,
+        latency: Arc<Latency>,
+    ) -> (WebSocket<TcpStream>, Receiver<PadRequest>, JoinHandle<()>) {
+        let (_feedback_tx, feedback) = channel();
+        let pad = NewPad {
+            id: 0,
+            feedback,
+            reclaim: "reclaim-me".to_string(),
+            player: None,
+            device: None,
+            profile: None,
+            pad_type: PadType::X360,
+            throttled,
+            latest,
+            latency,
+        };
+        connect_pad(settings, pad)
+    }
+
+    /// Like [connect_with], handing the client the given pad, whose reclaim token has to be
+    /// "reclaim-me"
+    fn connect_pad(
+        settings: Arc<WebsocketSettings>,
+        pad: NewPad,
+    ) -> (WebSocket<TcpStream>, Receiver<PadRequest>, JoinHandle<()>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let port = server.server_addr().port();
+        let (req_tx, req_rx) = sync_channel(QUEUE_SIZE);
+        let handle = spawn(move || {
+            let request = server.recv().unwrap();
+            let connection = Connection::new(request.remote_addr().ip());
+            handle_websocket(
+                Logger::root(Discard, o!()),
+                connection,
+                pad,
+                req_tx,
+                Protocol::JsonV1,
+                None,
+                settings,
+                request,
+            );
+        });
+
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let (mut ws, _) =
+            tungstenite::client(format!("ws://127.0.0.1:{}/websocket", port), stream).unwrap();
+        assert_eq!(ws.read_message().unwrap(), hello_message("example", None));
+        assert_eq!(
+            ws.read_message().unwrap(),
+            Message::Text(r#"{"reclaim":"reclaim-me"}"#.into())
+        );
+        assert_eq!(
+            ws.read_message().unwrap(),
+            ready_message(None, "reclaim-me", None)
+        );
+        assert_eq!(ws.read_message().unwrap(), clock_message(0));
+        (ws, req_rx, handle)
+    }
+
+    #[test]
+    fn test_close_detaches_once() {
+        let (mut ws, req_rx, handle) = connect();
+        ws.write_message(Message::Binary(X360State::default().to_bytes().to_vec()))
+            .unwrap();
+        ws.close(None).unwrap();
+        while ws.read_message().is_ok() {}
+        handle.join().unwrap();
+
+        let requests: Vec<_> = req_rx.iter().collect();
+        assert!(matches!(
+            requests.as_slice(),
+            [PadRequest::Update(0), PadRequest::Detach(0)]
+        ));
+    }
+
+    #[test]
+    fn test_shutdown_closes() {
+        let settings = Arc::new(settings(Duration::from_secs(60)));
+        let (mut ws, req_rx, handle) = connect_with(
+            Arc::clone(&settings),
+            Arc::default(),
+            Arc::default(),
+            Arc::default(),
+        );
+        settings.shutdown.store(true, Ordering::SeqCst);
+        assert_eq!(settings.outboxes.broadcast(shutdown_message(false)), 1);
+        ws.write_message(Message::Binary(X360State::default().to_bytes().to_vec()))
+            .unwrap();
+        // The notice goes out before the connection is closed
+        assert_eq!(ws.read_message().unwrap(), shutdown_message(false));
+        match ws.read_message().unwrap() {
+            Message::Close(Some(frame)) => assert_eq!(frame, CloseReason::ShuttingDown.frame()),
+            message => panic!("{:?} is not a close frame", message),
+        }
+        while ws.read_message().is_ok() {}
+        handle.join().unwrap();
+
+        let requests: Vec<_> = req_rx.iter().collect();
+        assert!(matches!(requests.as_slice(), [PadRequest::Detach(0)]));
+    }
+
+    #[test]
+    fn test_mainloop_shutdown() {
+        let (tx, rx) = sync_channel(QUEUE_SIZE);
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let args = Args {
+            bind: [127, 0, 0, 1].into(),
+            hostname: Some("localhost".to_string()),
+            mdns: false,
+            ..Args::default()
+        };
+        let server = {
+            let (shutdown, logger) = (Arc::clone(&shutdown), Logger::root(Discard, o!()));
+            spawn(move || {
+                let remaps = RemapProfiles::default();
+                mainloop(
+                    logger,
+                    args,
+                    Token::generate(),
+                    Keymap::default(),
+                    remaps,
+                    tx,
+                    shutdown,
+                    Arc::default(),
+                    Arc::default(),
+                    Frontend::default(),
+                )
+            })
+        };
+
+        shutdown.store(true, Ordering::SeqCst);
+        server.join().unwrap().unwrap();
+        assert!(matches!(rx.recv().unwrap(), PadRequest::Shutdown));
+    }
+
+    /// Any port which is free, since we have to know it to reach the server
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    /// Send a GET request for the given path to the server on the given port once it's up,
+    /// returning the whole response
+    /// Connect to a server on the given port, once it's listening
+    fn stream(port: u16) -> TcpStream {
+        loop {
+            match TcpStream::connect(("127.0.0.1", port)) {
+                Ok(stream) => return stream,
+                Err(_) => sleep(Duration::from_millis(10)),
+            }
+        }
+    }
+
+    fn get(port: u16, path: &str) -> String {
+        send(port, "GET", path)
+    }
+
+    /// Send a request without a body, returning the whole response
+    fn send(port: u16, method: &str, path: &str) -> String {
+        let mut stream = stream(port);
+        io::Write::write_all(
+            &mut stream,
+            format!(
+                "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                method, path
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        let mut response = String::new();
+        let _ = io::Read::read_to_string(&mut stream, &mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_driver_missing() {
+        let port = free_port();
+        let (tx, rx) = sync_channel(QUEUE_SIZE);
+        let args = Args {
+            bind: [127, 0, 0, 1].into(),
+            port,
+            hostname: Some("localhost".to_string()),
+            mdns: false,
+            ..Args::default()
+        };
+        let token = Token::generate();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let driver_missing = Arc::new(AtomicBool::new(true));
+        let server = {
+            let (logger, token) = (Logger::root(Discard, o!()), token.clone());
+            let (shutdown, driver_missing) = (Arc::clone(&shutdown), Arc::clone(&driver_missing));
+            spawn(move || {
+                mainloop(
+                    logger,
+                    args,
+                    token,
+                    Keymap::default(),
+                    RemapProfiles::default(),
+                    tx,
+                    shutdown,
+                    Arc::default(),
+                    driver_missing,
+                    Frontend::default(),
+                )
+            })
+        };
+
+        // Nobody gets a pad, or is left waiting for one, until the driver is there
+        for path in &["/".to_string(), format!("/controller?token={}", token)] {
+            let response = get(port, path);
+            assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+            assert!(response.contains(DRIVER_URL), "{}", response);
+        }
+        // Clients reconnecting are told when to try again
+        let response = get(port, &format!("/websocket?token={}", token));
+        assert!(response.starts_with("HTTP/1.1 409"), "{}", response);
+        assert!(response.contains("Retry-After: 3\r\n"), "{}", response);
+        assert!(response.contains(DRIVER_URL), "{}", response);
+        assert!(rx.try_recv().is_err());
+
+        driver_missing.store(false, Ordering::SeqCst);
+        let response = get(port, "/");
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(!response.contains(DRIVER_URL), "{}", response);
+
+        shutdown.store(true, Ordering::SeqCst);
+        server.join().unwrap().unwrap();
+        assert!(matches!(rx.recv().unwrap(), PadRequest::Shutdown));
+    }
+
+    #[test]
+    fn test_remote_peer() {
+        let (tx, _rx) = sync_channel(QUEUE_SIZE);
+        let args = Args {
+            hostname: Some("localhost".to_string()),
+            mdns: false,
+            ..Args::default()
+        };
+        let token = Token::generate();
+        let mut router = Router::new(
+            Logger::root(Discard, o!()),
+            args,
+            token.clone(),
+            Keymap::default(),
+            RemapProfiles::default(),
+            tx,
+            Arc::default(),
+            Arc::default(),
+            Arc::default(),
+            Frontend::default(),
+            "localhost".to_string(),
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+        )
+        .unwrap();
+        let mut route = |ip: [u8; 4], method: Method, url: &str| {
+            let routed = router
+                .route(&Incoming {
+                    remote: SocketAddr::from((ip, 50000)),
+                    method,
+                    url,
+                    headers: &[],
+                    body: &[],
+                })
+                .unwrap();
+            match routed {
+                Routed::Respond(reply) => reply,
+                _ => panic!("expected a reply to {}", url),
+            }
+        };
+        let (local, remote) = ([127, 0, 0, 1], [192, 168, 1, 50]);
+
+        // Phones on the network get the index page, but not the token it would give them
+        let index = route(remote, Method::Get, "/");
+        assert_eq!(index.status, 200);
+        assert!(!index.body.contains(&token.to_string()), "{}", index.body);
+        assert!(!index.body.contains("/controller?token="), "{}", index.body);
+        assert!(route(local, Method::Get, "/")
+            .body
+            .contains(&token.to_string()));
+
+        // Only this machine rotates the token, and not by following a link
+        assert_eq!(route(remote, Method::Post, "/rotate").status, 403);
+        assert_eq!(route(local, Method::Get, "/rotate").status, 405);
+        let rotated = route(local, Method::Post, "/rotate");
+        assert_eq!(rotated.status, 200);
+        assert_ne!(rotated.body, token.to_string());
+    }
+
+    #[test]
+    fn test_server_full() {
+        let port = free_port();
+        let (tx, rx) = sync_channel(QUEUE_SIZE);
+        let args = Args {
+            bind: [127, 0, 0, 1].into(),
+            port,
+            hostname: Some("localhost".to_string()),
+            mdns: false,
+            ..Args::default()
+        };
+        let token = Token::generate();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let server = {
+            let (logger, token) = (Logger::root(Discard, o!()), token.clone());
+            let shutdown = Arc::clone(&shutdown);
+            spawn(move || {
+                mainloop(
+                    logger,
+                    args,
+                    token,
+                    Keymap::default(),
+                    RemapProfiles::default(),
+                    tx,
+                    shutdown,
+                    Arc::default(),
+                    Arc::default(),
+                    Frontend::default(),
+                )
+            })
+        };
+        let pads = spawn(move || {
+            match rx.recv().unwrap() {
+                PadRequest::Acquire(.., reply_tx) => reply_tx.send(Err(ServerFull.into())).unwrap(),
+                _ => panic!("expected an acquire"),
+            }
+            rx
+        });
+
+        // The handshake goes through, for the page to see why it was closed
+        let url = format!("ws://127.0.0.1:{}/websocket?token={}", port, token);
+        let (mut ws, _) = tungstenite::client(url, stream(port)).unwrap();
+        match ws.read_message().unwrap() {
+            Message::Close(Some(frame)) => {
+                assert_eq!(frame.code, CloseCode::Again);
+                assert_eq!(
+                    CloseReason::find(&frame.reason),
+                    Some(CloseReason::ServerFull)
+                );
+            }
+            message => panic!("{:?} is not a close frame", message),
+        }
+        let rx = pads.join().unwrap();
+
+        shutdown.store(true, Ordering::SeqCst);
+        server.join().unwrap().unwrap();
+        assert!(matches!(rx.recv().unwrap(), PadRequest::Shutdown));
+    }
+
+    #[test]
+    fn test_admin_pads() {
+        let port = free_port();
+        let (tx, rx) = sync_channel(QUEUE_SIZE);
+        let args = Args {
+            bind: [127, 0, 0, 1].into(),
+            port,
+            hostname: Some("localhost".to_string()),
+            mdns: false,
+            ..Args::default()
+        };
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let server = {
+            let logger = Logger::root(Discard, o!());
+            let shutdown = Arc::clone(&shutdown);
+            spawn(move || {
+                mainloop(
+                    logger,
+                    args,
+                    Token::generate(),
+                    Keymap::default(),
+                    RemapProfiles::default(),
+                    tx,
+                    shutdown,
+                    Arc::default(),
+                    Arc::default(),
+                    Frontend::default(),
+                )
+            })
+        };
+        // Only pad 2 has a client to kick, who is sending states, and pad 3's client isn't
+        let pads = spawn(move || {
+            let mut kicked = Vec::new();
+            loop {
+                match rx.recv().unwrap() {
+                    PadRequest::Clients(reply_tx) => reply_tx
+                        .send(vec![ClientStatus {
+                            id: 2,
+                            ip: [127, 0, 0, 1].into(),
+                            connected_ms: 1000,
+                            last_update_ms: Some(2000),
+                            buttons: X360Buttons::A.bits(),
+                            detached: false,
+                            suspended: true,
+                        }])
+                        .unwrap(),
+                    PadRequest::Kick(id, reply_tx) => {
+                        kicked.push(id);
+                        reply_tx.send(id == 2).unwrap();
+                    }
+                    PadRequest::TestInput(id, reply_tx) => reply_tx
+                        .send(match id {
+                            2 => TestInputReply::Busy,
+                            3 => TestInputReply::Started,
+                            _ => TestInputReply::NoSuchPad,
+                        })
+                        .unwrap(),
+                    PadRequest::Shutdown => return kicked,
+                    _ => panic!("expected an admin request"),
+                }
+            }
+        });
+
+        let response = get(port, "/admin/pads");
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        let clients: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(
+            clients,
+            serde_json::json!([{
+                "id": 2,
+                "ip": "127.0.0.1",
+                "connected_ms": 1000,
+                "last_update_ms": 2000,
+                "buttons": 0x1000,
+                "detached": false,
+                "suspended": true,
+            }])
+        );
+
+        // Kicking takes a POST, for a pad which has a client
+        assert!(get(port, "/admin/pads/2/kick").starts_with("HTTP/1.1 405"));
+        assert!(send(port, "POST", "/admin/pads/2/kick").starts_with("HTTP/1.1 200"));
+        assert!(send(port, "POST", "/admin/pads/0/kick").starts_with("HTTP/1.1 404"));
+        assert!(send(port, "POST", "/admin/pads/two/kick").starts_with("HTTP/1.1 404"));
+        assert!(send(port, "POST", "/admin/pads/2/dance").starts_with("HTTP/1.1 404"));
+
+        // The test input waits for the client to stop sending states
+        assert!(get(port, "/admin/pads/3/test-input").starts_with("HTTP/1.1 405"));
+        assert!(send(port, "POST", "/admin/pads/3/test-input").starts_with("HTTP/1.1 200"));
+        assert!(send(port, "POST", "/admin/pads/2/test-input").starts_with("HTTP/1.1 409"));
+        assert!(send(port, "POST", "/admin/pads/0/test-input").starts_with("HTTP/1.1 404"));
+
+        // Nobody's websocket is there to rumble
+        assert!(get(port, "/admin/pads/2/test-rumble").starts_with("HTTP/1.1 405"));
+        assert!(send(port, "POST", "/admin/pads/2/test-rumble").starts_with("HTTP/1.1 404"));
+
+        shutdown.store(true, Ordering::SeqCst);
+        server.join().unwrap().unwrap();
+        assert_eq!(pads.join().unwrap(), [2, 0]);
+    }
+
+    #[test]
+    fn test_pad_action() {
+        assert_eq!(pad_action("/admin/pads/3/kick"), Some((3, "kick")));
+        assert_eq!(
+            pad_action("/admin/pads/3/test-rumble"),
+            Some((3, "test-rumble"))
+        );
+        assert_eq!(pad_action("/admin/pads/3"), None);
+        assert_eq!(pad_action("/admin/pads//kick"), None);
+        assert_eq!(pad_action("/admin/pads/-1/kick"), None);
+    }
+
+    #[test]
+    fn test_frontend() {
+        let port = free_port();
+        let (tx, rx) = sync_channel(QUEUE_SIZE);
+        let args = Args {
+            bind: [127, 0, 0, 1].into(),
+            port,
+            hostname: Some("localhost".to_string()),
+            mdns: false,
+            ..Args::default()
+        };
+        let (invites_tx, invites) = channel();
+        let (bound_tx, bound) = channel();
+        let rotate_token = Arc::new(AtomicBool::new(false));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let server = {
+            let frontend = Frontend {
+                rotate_token: Arc::clone(&rotate_token),
+                invites: Some(invites_tx),
+                bound: Some(bound_tx),
+            };
+            let (logger, shutdown) = (Logger::root(Discard, o!()), Arc::clone(&shutdown));
+            spawn(move || {
+                mainloop(
+                    logger,
+                    args,
+                    Token::generate(),
+                    Keymap::default(),
+                    RemapProfiles::default(),
+                    tx,
+                    shutdown,
+                    Arc::default(),
+                    Arc::default(),
+                    frontend,
+                )
+            })
+        };
+
+        // The frontend gets the same links as the index page
+        assert_eq!(bound.recv().unwrap().port(), port);
+        let first = invites.recv().unwrap();
+        assert_eq!(first.controllers.len(), LAYOUTS.len());
+        let (layout, url) = &first.controllers[0];
+        assert_eq!(layout.name, Layout::default().name);
+        assert_eq!(
+            *url,
+            format!(
+                "http://localhost:{}/controller?token={}&layout={}",
+                port, first.token, layout.name
+            )
+        );
+        assert!(get(port, "/").contains(&first.token));
+
+        // Rotating the token from the frontend locks out the old one, like `/rotate` does
+        rotate_token.store(true, Ordering::SeqCst);
+        let second = invites.recv().unwrap();
+        assert_ne!(second.token, first.token);
+        let controller = |token| get(port, &format!("/controller?token={}", token));
+        assert!(controller(&first.token).starts_with("HTTP/1.1 403"));
+        assert!(controller(&second.token).starts_with("HTTP/1.1 200"));
+        assert!(get(port, "/rotate").starts_with("HTTP/1.1 405"));
+        let third = send(port, "POST", "/rotate");
+        assert!(third.ends_with(&invites.recv().unwrap().token), "{}", third);
+
+        shutdown.store(true, Ordering::SeqCst);
+        server.join().unwrap().unwrap();
+        assert!(matches!(rx.recv().unwrap(), PadRequest::Shutdown));
+    }
+
+    #[test]
+    fn test_reload_snapshot() {
+        let path = std::env::temp_dir().join(format!(
+            "sphrosyne-reload-snapshot-{}.json",
+            std::process::id()
+        ));
+        let port = free_port();
+        let (tx, rx) = sync_channel(QUEUE_SIZE);
+        let args = Args {
+            bind: [127, 0, 0, 1].into(),
+            port,
+            hostname: Some("localhost".to_string()),
+            mdns: false,
+            snapshot: Some(path.clone()),
+            ..Args::default()
+        };
+        let token = Token::generate();
+        let server = {
+            let (logger, token) = (Logger::root(Discard, o!()), token.clone());
+            spawn(move || {
+                mainloop(
+                    logger,
+                    args,
+                    token,
+                    Keymap::default(),
+                    RemapProfiles::default(),
+                    tx,
+                    Arc::new(AtomicBool::new(false)),
+                    Arc::default(),
+                    Arc::default(),
+                    Frontend::default(),
+                )
+            })
+        };
+
+        let response = get(port, "/admin/reload");
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        // The pads are asked for what to save before they're told to shut down
+        let pads = vec![crate::snapshot::PadSnapshot {
+            pad_type: PadType::X360,
+            reserved: true,
+            client: None,
+        }];
+        match rx.recv().unwrap() {
+            PadRequest::Snapshot(reply_tx) => reply_tx.send(pads.clone()).unwrap(),
+            _ => panic!("the pads weren't asked for a snapshot"),
+        }
+        assert!(matches!(rx.recv().unwrap(), PadRequest::Shutdown));
+        server.join().unwrap().unwrap();
+
+        let logger = Logger::root(Discard, o!());
+        let snapshot = Snapshot::load(&logger, &path).unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(snapshot.token, token.to_string());
+        assert_eq!(snapshot.pads, pads);
+    }
+
+    #[test]
+    fn test_disconnect_command() {
+        let (mut ws, req_rx, handle) = connect();
+        ws.write_message(Message::Text("disconnect".into()))
+            .unwrap();
+        ws.write_message(Message::Binary(X360State::default().to_bytes().to_vec()))
+            .unwrap();
+        ws.close(None).unwrap();
+        while ws.read_message().is_ok() {}
+        handle.join().unwrap();
+
+        let requests: Vec<_> = req_rx.iter().collect();
+        assert!(matches!(requests.as_slice(), [PadRequest::Release(0)]));
+    }
+
+    #[test]
+    fn test_kicked() {
+        let latest = Arc::new(LatestState::default());
+        let (mut ws, req_rx, handle) = connect_with(
+            Arc::new(settings(Duration::from_secs(60))),
+            Arc::default(),
+            Arc::clone(&latest),
+            Arc::default(),
+        );
+        latest.discard(CloseReason::Kicked);
+        ws.write_message(Message::Binary(X360State::default().to_bytes().to_vec()))
+            .unwrap();
+        match ws.read_message().unwrap() {
+            Message::Close(Some(frame)) => {
+                assert_eq!(frame.code, CloseCode::Away);
+                assert_eq!(frame.reason, "kicked");
+            }
+            message => panic!("{:?} is not a close frame", message),
+        }
+        while ws.read_message().is_ok() {}
+        handle.join().unwrap();
+
+        // The pad is gone for good, so there's nothing to reclaim
+        let requests: Vec<_> = req_rx.iter().collect();
+        assert!(matches!(requests.as_slice(), [PadRequest::Release(0)]));
+    }
+
+    #[test]
+    fn test_pad_failed() {
+        let latest = Arc::new(LatestState::default());
+        let (mut ws, req_rx, handle) = connect_with(
+            Arc::new(settings(Duration::from_secs(60))),
+            Arc::default(),
+            Arc::clone(&latest),
+            Arc::default(),
+        );
+        latest.discard(CloseReason::PadFailed);
+        ws.write_message(Message::Binary(X360State::default().to_bytes().to_vec()))
+            .unwrap();
+        match ws.read_message().unwrap() {
+            Message::Close(Some(frame)) => {
+                assert_eq!(frame.code, CloseCode::Error);
+                assert_eq!(frame.reason, "pad_failed");
+            }
+            message => panic!("{:?} is not a close frame", message),
+        }
+        while ws.read_message().is_ok() {}
+        handle.join().unwrap();
+
+        // Reconnecting gets a new pad rather than this one back
+        let requests: Vec<_> = req_rx.iter().collect();
+        assert!(matches!(requests.as_slice(), [PadRequest::Release(0)]));
+    }
+
+    #[test]
+    fn test_attach() {
+        let attached = Arc::new(LatestState::default());
+        let (mut ws, req_rx, handle) = connect();
+        ws.write_message(Message::Text(r#"{"type":"attach"}"#.into()))
+            .unwrap();
+        match req_rx.recv().unwrap() {
+            PadRequest::Acquire(_, None, PadType::X360, reply_tx) => {
+                let (_feedback_tx, feedback) = channel();
+                let pad = NewPad {
+                    id: 7,
+                    feedback,
+                    reclaim: String::new(),
+                    player: Some(Player {
+                        number: 5,
+                        xinput: false,
+                    }),
+                    device: None,
+                    profile: None,
+                    pad_type: PadType::X360,
+                    throttled: Arc::default(),
+                    latest: Arc::clone(&attached),
+                    latency: Arc::default(),
+                };
+                reply_tx.send(Ok(pad)).unwrap();
+            }
+            _ => panic!("expected a request for a new pad"),
+        }
+        assert_eq!(
+            ws.read_message().unwrap(),
+            Message::Text(r#"{"attached":1,"player":5,"label":"P5 (no XInput slot)"}"#.into())
+        );
+
+        // Both the tagged JSON and the prefixed binary updates reach the attached pad, the
+        // second replacing the first as nobody took it in the meantime
+        let state = X360State::builder().press(X360Buttons::A).build();
+        let update = serde_json::json!({ "type": "update", "pad": 1, "state": state });
+        ws.write_message(Message::Text(update.to_string())).unwrap();
+        let mut prefixed = vec![1];
+        let second = X360State::builder()
+            .press(X360Buttons::A | X360Buttons::B)
+            .build();
+        prefixed.extend_from_slice(&second.to_bytes());
+        ws.write_message(Message::Binary(prefixed)).unwrap();
+        ws.close(None).unwrap();
+        while ws.read_message().is_ok() {}
+        handle.join().unwrap();
+
+        // Only the first pad can be reclaimed, so the attached one is released
+        let requests: Vec<_> = req_rx.iter().collect();
+        assert!(matches!(
+            requests.as_slice(),
+            [
+                PadRequest::Update(7),
+                PadRequest::Detach(0),
+                PadRequest::Release(7)
+            ]
+        ));
+        assert_eq!(attached.take().map(|(state, _)| state), Some(second));
+    }
+
+    #[test]
+    fn test_remap() {
+        let (mut ws, req_rx, handle) = connect();
+        for message in [
+            r#"{"type":"remap","profile":"nintendo"}"#,
+            r#"{"type":"remap","profile":"nope"}"#,
+            r#"{"type":"remap","pad":1,"swap_sticks":true}"#,
+            r#"{"type":"remap","pad":0,"buttons":{"a":"x"},"swap_sticks":true,"synthesis":{"kind":"dpad_to_stick"}}"#,
+        ] {
+            ws.write_message(Message::Text(message.into())).unwrap();
+        }
+        ws.close(None).unwrap();
+        while ws.read_message().is_ok() {}
+        handle.join().unwrap();
+
+        // Unknown profiles and pads are skipped
+        let requests: Vec<_> = req_rx.iter().collect();
+        match requests.as_slice() {
+            [PadRequest::Remap(0, nintendo), PadRequest::Remap(0, custom), PadRequest::Detach(0)] =>
+            {
+                assert_eq!(
+                    Some(nintendo),
+                    settings(Duration::ZERO).remaps.get("nintendo")
+                );
+                assert_eq!(custom.buttons.get(&X360Buttons::A), Some(&X360Buttons::X));
+                assert!(custom.swap_sticks);
+                assert_eq!(custom.synthesis, Some(Synthesis::DpadToStick));
+            }
+            _ => panic!("expected two remaps of pad 0"),
+        }
+    }
+
+    #[test]
+    fn test_profile() {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let port = server.server_addr().port();
+        let (req_tx, req_rx) = sync_channel(QUEUE_SIZE);
+        let (_feedback_tx, feedback) = channel();
+        let handle = spawn(move || {
+            let request = server.recv().unwrap();
+            let pad = NewPad {
+                id: 0,
+                feedback,
+                reclaim: "reclaim-me".to_string(),
+                player: None,
+                device: Some("phone".to_string()),
+                profile: Some(Profile {
+                    remap: Some("nintendo".to_string()),
+                    layout: Some("racing".to_string()),
+                    ..Profile::default()
+                }),
+                pad_type: PadType::X360,
+                throttled: Arc::default(),
+                latest: Arc::default(),
+                latency: Arc::default(),
+            };
+            handle_websocket(
+                Logger::root(Discard, o!()),
+                Connection::new(request.remote_addr().ip()),
+                pad,
+                req_tx,
+                Protocol::JsonV1,
+                None,
+                Arc::new(settings(Duration::from_secs(60))),
+                request,
+            );
+        });
+
+        // The device is told what it's known by and which layout it used last
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let (mut ws, _) =
+            tungstenite::client(format!("ws://127.0.0.1:{}/websocket", port), stream).unwrap();
+        assert_eq!(
+            ws.read_message().unwrap(),
+            hello_message("example", Some("phone"))
+        );
+        let _ = ws.read_message().unwrap();
+        assert_eq!(
+            ws.read_message().unwrap(),
+            ready_message(None, "reclaim-me", Some("racing"))
+        );
+        ws.write_message(Message::Text(
+            r#"{"type":"remap","swap_sticks":true}"#.into(),
+        ))
+        .unwrap();
+        ws.close(None).unwrap();
+        while ws.read_message().is_ok() {}
+        handle.join().unwrap();
+
+        // Its remap profile is picked for it again, and picking another is remembered
+        let requests: Vec<_> = req_rx.iter().collect();
+        match requests.as_slice() {
+            [PadRequest::Remap(0, nintendo), PadRequest::Remap(0, custom), PadRequest::Remember(device, ProfileChange::Remap(None)), PadRequest::Detach(0)] =>
+            {
+                assert_eq!(
+                    Some(nintendo),
+                    settings(Duration::ZERO).remaps.get("nintendo")
+                );
+                assert!(custom.swap_sticks);
+                assert_eq!(device, "phone");
+            }
+            _ => panic!("expected the profile's remap, then the client's"),
+        }
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        let (mut ws, req_rx, handle) = connect_with_timeout(Duration::from_millis(100));
+
+        // The pad is detached while the client is still connected, but silent
+        let request = req_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(request, PadRequest::Detach(0)));
+
+        // Once the client speaks up again it is told to go away
+        ws.write_message(Message::Binary(X360State::default().to_bytes().to_vec()))
+            .unwrap();
+        assert_eq!(
+            ws.read_message().unwrap(),
+            Message::Close(Some(CloseReason::IdleTimeout.frame()))
+        );
+        while ws.read_message().is_ok() {}
+        handle.join().unwrap();
+
+        assert_eq!(req_rx.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        // Twice per second only allows for one message at a time, every 500ms
+        let settings = Arc::new(WebsocketSettings {
+            rate_limit: 2,
+            ..settings(Duration::from_secs(60))
+        });
+        let throttled = Arc::new(Throttled::default());
+        let latest = Arc::new(LatestState::default());
+        let (mut ws, req_rx, handle) = connect_with(
+            settings,
+            Arc::clone(&throttled),
+            Arc::clone(&latest),
+            Arc::default(),
+        );
+
+        let state = |buttons| X360State::builder().press(buttons).build();
+        for buttons in [
+            X360Buttons::A,
+            X360Buttons::B,
+            X360Buttons::X,
+            X360Buttons::Y,
+        ] {
+            ws.write_message(Message::Binary(state(buttons).to_bytes().to_vec()))
+                .unwrap();
+        }
+        let calibrate = r#"{"type":"calibrate","deadzone":10}"#;
+        ws.write_message(Message::Text(calibrate.into())).unwrap();
+
+        // The first state goes through, while the others are held back and coalesced into the
+        // last one, which goes through once the rate limit allows
+        let timeout = Duration::from_secs(5);
+        for buttons in [X360Buttons::A, X360Buttons::Y] {
+            match req_rx.recv_timeout(timeout).unwrap() {
+                PadRequest::Update(0) => {
+                    assert_eq!(latest.take().map(|(state, _)| state), Some(state(buttons)))
+                }
+                _ => panic!("expected an update"),
+            }
+        }
+        ws.close(None).unwrap();
+        while ws.read_message().is_ok() {}
+        handle.join().unwrap();
+
+        let requests: Vec<_> = req_rx.iter().collect();
+        assert!(matches!(requests.as_slice(), [PadRequest::Detach(0)]));
+        assert_eq!(throttled.coalesced.load(Ordering::Relaxed), 2);
+        assert_eq!(throttled.dropped.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_message_too_large() {
+        let throttled = Arc::new(Throttled::default());
+        let (mut ws, req_rx, handle) = connect_with(
+            Arc::new(settings(Duration::from_secs(60))),
+            Arc::clone(&throttled),
+            Arc::default(),
+            Arc::default(),
+        );
+        ws.write_message(Message::Text(" ".repeat(MAX_MESSAGE_SIZE + 1)))
+            .unwrap();
+        while ws.read_message().is_ok() {}
+        handle.join().unwrap();
+
+        let requests: Vec<_> = req_rx.iter().collect();
+        assert!(matches!(requests.as_slice(), [PadRequest::Detach(0)]));
+        assert_eq!(throttled.dropped.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_unknown_buttons() {
+        // Bits which aren't a button's are dropped instead of failing the whole message
+        let mut data = X360State::builder()
+            .press(X360Buttons::B)
+            .right_trigger(9)
+            .build()
+            .to_bytes();
+        data[1] |= 0x0C;
+        match decode_binary(&data).unwrap() {
+            PadMessage::State(0, state, None, None) => {
+                assert_eq!(state.buttons, X360Buttons::B | X360Buttons::GUIDE);
+                assert_eq!(state.right_trigger, 9);
+            }
+            message => panic!("{:?} is not a state", message),
+        }
+    }
+
+    #[test]
+    fn test_touch() {
+        let state = X360State::builder().right_trigger(7).build();
+        let update = serde_json::json!({
+            "type": "update",
+            "pad": 0,
+            "state": state,
+            "touch": { "rforce": 0.5 },
+            "from_the_future": true,
+        });
+        let touch = TouchForce {
+            lforce: None,
+            rforce: Some(0.5),
+        };
+        assert_eq!(
+            Protocol::BinaryV2.decode_text(&update.to_string()).unwrap(),
+            PadMessage::State(0, state, None, Some(touch))
+        );
+
+        // The touch transformer reads the forces sent along with the pad's states
+        let (mut ws, req_rx, handle) = connect();
+        let insert = r#"{"type":"transformer","op":"insert","transformer":{"kind":"touch","curve":"squared"}}"#;
+        ws.write_message(Message::Text(insert.into())).unwrap();
+        let mut transformer = match req_rx.recv().unwrap() {
+            PadRequest::Pipeline(0, PipelineEdit::Insert(usize::MAX, transformer)) => transformer,
+            _ => panic!("expected the touch transformer"),
+        };
+        ws.write_message(Message::Text(update.to_string())).unwrap();
+        assert!(matches!(req_rx.recv().unwrap(), PadRequest::Update(0)));
+        let transformed = transformer.transform(state, Instant::now());
+        assert_eq!(transformed.right_trigger, 64);
+
+        // Without forces the triggers are the state's. The pads never took the first state, so
+        // the second isn't announced, but the message after it is.
+        let update = serde_json::json!({ "type": "update", "pad": 0, "state": state });
+        ws.write_message(Message::Text(update.to_string())).unwrap();
+        ws.write_message(Message::Text(r#"{"type":"neutral"}"#.into()))
+            .unwrap();
+        assert!(matches!(
+            req_rx.recv().unwrap(),
+            PadRequest::Neutral(0, None)
+        ));
+        assert_eq!(transformer.transform(state, Instant::now()), state);
+
+        // Pages going into the background and back suspend their pads and resume them
+        ws.write_message(Message::Text(r#"{"type":"background"}"#.into()))
+            .unwrap();
+        assert!(matches!(req_rx.recv().unwrap(), PadRequest::Suspend(0)));
+        ws.write_message(Message::Text(r#"{"type":"foreground"}"#.into()))
+            .unwrap();
+        assert!(matches!(req_rx.recv().unwrap(), PadRequest::Resume(0)));
+
+        ws.close(None).unwrap();
+        while ws.read_message().is_ok() {}
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_invalid_message() {
+        let (mut ws, req_rx, handle) = connect();
+
+        // Messages we don't understand are answered with why, and the connection goes on
+        ws.write_message(Message::Text(r#"{"type":"lightbar","color":"red"}"#.into()))
+            .unwrap();
+        assert_eq!(
+            ws.read_message().unwrap(),
+            error_message(r#"unknown message type "lightbar""#)
+        );
+        ws.write_message(Message::Text(r#"{"type":"clock","n":1}"#.into()))
+            .unwrap();
+        match ws.read_message().unwrap() {
+            Message::Text(reply) => {
+                let reply: serde_json::Value = serde_json::from_str(&reply).unwrap();
+                assert_eq!(reply["type"], "error");
+                assert!(
+                    reply["message"]
+                        .as_str()
+                        .unwrap()
+                        .contains("missing field `t`"),
+                    "{}",
+                    reply
+                );
+            }
+            message => panic!("unexpected {:?}", message),
+        }
+        ws.write_message(Message::Text(r#"{"type":"background"}"#.into()))
+            .unwrap();
+        assert!(matches!(req_rx.recv().unwrap(), PadRequest::Suspend(0)));
+
+        ws.close(None).unwrap();
+        while ws.read_message().is_ok() {}
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let state = X360State::builder().press(X360Buttons::Y).build();
+        let sent = 1234.5_f64;
+
+        let mut data = state.to_bytes().to_vec();
+        data.extend_from_slice(&sent.to_le_bytes());
+        assert_eq!(
+            decode_binary(&data).unwrap(),
+            PadMessage::State(0, state, Some(sent), None)
+        );
+        let mut prefixed = vec![2];
+        prefixed.extend_from_slice(&data);
+        assert_eq!(
+            decode_binary(&prefixed).unwrap(),
+            PadMessage::State(2, state, Some(sent), None)
+        );
+
+        let update = serde_json::json!({ "type": "update", "pad": 1, "state": state, "t": sent });
+        assert_eq!(
+            Protocol::BinaryV2.decode_text(&update.to_string()).unwrap(),
+            PadMessage::State(1, state, Some(sent), None)
+        );
+        let update = serde_json::json!({ "type": "update", "pad": 1, "state": state });
+        assert_eq!(
+            Protocol::BinaryV2.decode_text(&update.to_string()).unwrap(),
+            PadMessage::State(1, state, None, None)
+        );
+    }
+
+    #[test]
+    fn test_clock_handshake() {
+        let latency = Arc::new(Latency::default());
+        let (mut ws, _req_rx, handle) = connect_with(
+            Arc::new(settings(Duration::from_secs(60))),
+            Arc::default(),
+            Arc::default(),
+            Arc::clone(&latency),
+        );
+        // Our clock is way ahead of the server's
+        let epoch = Instant::now();
+        let now = || epoch.elapsed().as_secs_f64() * 1000.0 + 1e6;
+
+        // Every answer is followed by the next ping, until there were enough rounds
+        for n in 0..CLOCK_ROUNDS {
+            let answer = serde_json::json!({ "type": "clock", "n": n, "t": now() });
+            ws.write_message(Message::Text(answer.to_string())).unwrap();
+            if n + 1 < CLOCK_ROUNDS {
+                assert_eq!(ws.read_message().unwrap(), clock_message(n + 1));
+            }
+        }
+
+        // Now the states we stamp are timed
+        let mut data = X360State::default().to_bytes().to_vec();
+        data.extend_from_slice(&now().to_le_bytes());
+        ws.write_message(Message::Binary(data)).unwrap();
+        ws.close(None).unwrap();
+        while ws.read_message().is_ok() {}
+        handle.join().unwrap();
+
+        let network = latency.summary().network.unwrap();
+        assert!(network.p99_ms < 1000.0, "{}", network);
+    }
+
+    #[test]
+    fn test_text_message() {
+        let parse = |data: &str| match ClientText::parse(data).unwrap().into() {
+            PadMessage::State(0, state, None, None) => state,
+            message => panic!("{:?} is not a state", message),
+        };
+
+        let state = X360State::builder().press(X360Buttons::B).build();
+        assert_eq!(parse(&serde_json::to_string(&state).unwrap()), state);
+
+        let gamepad = parse(r#"{"type":"gamepad","buttons":[0,1],"axes":[0,-1]}"#);
+        assert_eq!(
+            gamepad,
+            X360State::builder()
+                .press(X360Buttons::B)
+                .left_stick(0, i16::MAX)
+                .build()
+        );
+
+        assert!(ClientText::parse(r#"{"type":"nope"}"#).is_err());
+
+        let state = r#"{"buttons":7168,"left_trigger":0,"right_trigger":0,"left_thumbstick":[0,0],"right_thumbstick":[0,0]}"#;
+        assert_eq!(parse(state).buttons, X360Buttons::A | X360Buttons::GUIDE);
+
+        let calibrate =
+            ClientText::parse(r#"{"type":"calibrate","deadzone":10,"invert":{"left_y":true}}"#)
+                .unwrap();
+        match calibrate.into() {
+            PadMessage::Calibrate(calibration) => {
+                assert_eq!(calibration.deadzone, 10.0);
+                assert!(calibration.invert.left_y && !calibration.invert.left_x);
+                assert_eq!(calibration.trigger_threshold, 0.0);
+            }
+            message => panic!("{:?} is not a calibration", message),
+        }
+
+        let keys = ClientText::parse(r#"{"type":"keys","down":["KeyW","Space","KeyW"]}"#).unwrap();
+        match keys.into() {
+            PadMessage::Keys(down) => assert_eq!(
+                down.iter().map(String::as_str).collect::<Vec<_>>(),
+                ["KeyW", "Space"]
+            ),
+            message => panic!("{:?} is not a set of keys", message),
+        }
+
+        let turbo = ClientText::parse(r#"{"type":"turbo","buttons":4096,"frequency":10}"#).unwrap();
+        assert_eq!(
+            PadMessage::from(turbo),
+            PadMessage::Turbo(TurboConfig {
+                buttons: X360Buttons::A,
+                frequency: 10.0
+            })
+        );
+
+        let motion =
+            ClientText::parse(r#"{"type":"motion","alpha":350.5,"beta":-20,"gamma":45}"#).unwrap();
+        assert_eq!(
+            PadMessage::from(motion),
+            PadMessage::Motion(Orientation {
+                alpha: 350.5,
+                beta: -20.0
+            })
+        );
+        let config = ClientText::parse(r#"{"type":"motion_config","sensitivity":2}"#).unwrap();
+        assert_eq!(
+            PadMessage::from(config),
+            PadMessage::MotionConfig(MotionConfig {
+                sensitivity: 2.0,
+                ..MotionConfig::default()
+            })
+        );
+
+        let parse = |data| PadMessage::from(ClientText::parse(data).unwrap());
+        assert_eq!(
+            parse(r#"{"type":"neutral","after_ms":2000}"#),
+            PadMessage::Neutral(Some(Duration::from_millis(2000)))
+        );
+        assert_eq!(parse(r#"{"type":"neutral"}"#), PadMessage::Neutral(None));
+        assert_eq!(parse(r#"{"type":"background"}"#), PadMessage::Background);
+        assert_eq!(parse(r#"{"type":"foreground"}"#), PadMessage::Foreground);
+        assert_eq!(
+            parse(r#"{"type":"socd","policy":"last_input"}"#),
+            PadMessage::Socd(Some(SocdPolicy::LastInput))
+        );
+        assert_eq!(
+            parse(r#"{"type":"socd","policy":null}"#),
+            PadMessage::Socd(None)
+        );
+        assert_eq!(
+            parse(
+                r#"{"type":"transformer","op":"insert","transformer":{"kind":"deadzone","deadzone":10}}"#
+            ),
+            PadMessage::Transformer {
+                pad: None,
+                edit: TransformerEdit::Insert {
+                    index: None,
+                    transformer: BuiltIn::Deadzone(Calibration {
+                        deadzone: 10.0,
+                        ..Calibration::default()
+                    }),
+                },
+            }
+        );
+        let mut remap = Remap::default();
+        let _ = remap.buttons.insert(X360Buttons::A, X360Buttons::B);
+        assert_eq!(
+            parse(
+                r#"{"type":"transformer","pad":1,"op":"insert","index":0,"transformer":{"kind":"remap","buttons":{"A":"B"}}}"#
+            ),
+            PadMessage::Transformer {
+                pad: Some(1),
+                edit: TransformerEdit::Insert {
+                    index: Some(0),
+                    transformer: BuiltIn::Remap(remap),
+                },
+            }
+        );
+        assert_eq!(
+            parse(
+                r#"{"type":"transformer","op":"insert","transformer":{"kind":"stick_to_dpad","hysteresis":5}}"#
+            ),
+            PadMessage::Transformer {
+                pad: None,
+                edit: TransformerEdit::Insert {
+                    index: None,
+                    transformer: BuiltIn::StickToDpad(StickToDpad::new(50, 5)),
+                },
+            }
+        );
+        assert_eq!(
+            parse(r#"{"type":"transformer","op":"move","from":1,"to":0}"#),
+            PadMessage::Transformer {
+                pad: None,
+                edit: TransformerEdit::Move { from: 1, to: 0 },
+            }
+        );
+        assert_eq!(
+            parse(r#"{"type":"transformer","op":"remove","index":2}"#),
+            PadMessage::Transformer {
+                pad: None,
+                edit: TransformerEdit::Remove { index: 2 },
+            }
+        );
+        for invalid in &[
+            r#"{"type":"transformer","op":"remove"}"#,
+            r#"{"type":"transformer","op":"insert","transformer":{"kind":"turbo"}}"#,
+            r#"{"type":"transformer","op":"shuffle"}"#,
+        ] {
+            assert!(ClientMessage::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_authorize() {
+        let token = Token::generate();
+        let protocol = |value: &str| Header::from_bytes("Sec-WebSocket-Protocol", value).unwrap();
+
+        assert_eq!(authorize(&token, "", &[]), None);
+        assert_eq!(authorize(&token, "token=", &[]), None);
+        assert_eq!(authorize(&token, "token=nope", &[]), None);
+        assert_eq!(authorize(&token, &format!("other={}", token), &[]), None);
+        assert_eq!(authorize(&token, "", &[protocol("nope, also-nope")]), None);
+
+        assert_eq!(
+            authorize(&token, &format!("layout=x&token={}", token), &[]),
+            Some(Authorization::Query)
+        );
+        assert_eq!(
+            authorize(&token, "", &[protocol(&format!("other, {}", token))]),
+            Some(Authorization::Protocol(token.to_string()))
+        );
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(Protocol::negotiate(vec![]), None);
+        assert_eq!(Protocol::negotiate(vec!["token", "sphrosyne.v3"]), None);
+        assert_eq!(
+            Protocol::negotiate(vec!["token", "sphrosyne.v1.json"]),
+            Some(Protocol::JsonV1)
+        );
+        assert_eq!(
+            Protocol::negotiate(vec!["sphrosyne.v2.binary", "sphrosyne.v1.json"]),
+            Some(Protocol::BinaryV2)
+        );
+    }
+
+    #[test]
+    fn test_protocol() {
+        let state = X360State::builder().press(X360Buttons::B).build();
+        let json = serde_json::to_string(&state).unwrap();
+        assert_eq!(
+            Protocol::JsonV1.decode_text(&json).unwrap(),
+            PadMessage::State(0, state, None, None)
+        );
+        // Bare states are binary only in the second version
+        assert!(Protocol::BinaryV2.decode_text(&json).is_err());
+        let gamepad = r#"{"type":"gamepad","buttons":[0,1],"axes":[]}"#;
+        assert_eq!(
+            Protocol::BinaryV2.decode_text(gamepad).unwrap(),
+            PadMessage::State(0, state, None, None)
+        );
+
+        let feedback = X360NotificationData {
+            large_motor: 1,
+            small_motor: 2,
+            led_number: 3,
+        };
+        assert_eq!(
+            Protocol::JsonV1.encode_feedback(1, feedback),
+            Message::Text(r#"{"large":1,"small":2,"led":3,"pad":1}"#.into())
+        );
+        assert_eq!(
+            Protocol::BinaryV2.encode_feedback(1, feedback),
+            Message::Binary(vec![1, 2, 3, 1])
+        );
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_lightbar() {
+        let logger = Logger::root(Discard, o!());
+        let bus = Arc::new(vigem_client_c::Client::new_mock().unwrap());
+        let (args, metrics) = (Args::default(), Metrics::default());
+        let profiles = crate::profiles::Profiles::load(
+            &logger,
+            std::env::temp_dir().join(format!("sphrosyne-lightbar-{}.toml", std::process::id())),
+        );
+        let on_event = |_| {};
+        let mut manager = crate::pads::PadManager::new(
+            logger,
+            &args,
+            bus.clone(),
+            None,
+            profiles,
+            &metrics,
+            &on_event,
+        );
+        let connection = Connection::new(std::net::Ipv4Addr::LOCALHOST.into());
+        let mut pad = manager.create_pad(connection, None, PadType::DS4).unwrap();
+        pad.reclaim = "reclaim-me".to_string();
+
+        // A game setting the lightbar of a dualshock 4 pad on the bus has its client told
+        let (mut ws, _req_rx, _handle) =
+            connect_pad(Arc::new(settings(Duration::from_secs(60))), pad);
+        ws.get_ref()
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mock_bus = bus.mock_bus();
+        assert!(mock_bus.notify_ds4(mock_bus.targets()[0], 0, 0, (255, 128, 0)));
+        // Feedback goes out whenever the client's next message comes in
+        ws.write_message(Message::Ping(Vec::new())).unwrap();
+        let lightbar = lightbar_message(0, (255, 128, 0));
+        while ws.read_message().unwrap() != lightbar {}
+    }
+
+    #[test]
+    fn test_player_led() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut player_led = Changes::new(PLAYER_LED_RATE, start);
+        assert_eq!(player_led.due(at(0)), None);
+
+        player_led.note(0);
+        assert_eq!(player_led.due(at(0)), Some(0));
+        player_led.note(0);
+        assert_eq!(player_led.due(at(0)), None);
+
+        // Changes faster than the rate limit are held back, keeping only the latest one
+        player_led.note(1);
+        player_led.note(2);
+        assert_eq!(player_led.due(at(100)), None);
+        assert_eq!(player_led.due(at(250)), Some(2));
+
+        // Going back to the number the client already knows about cancels the change
+        player_led.note(3);
+        player_led.note(2);
+        assert_eq!(player_led.due(at(1000)), None);
+
+        assert_eq!(
+            player_message(1, 2),
+            Message::Text(r#"{"type":"player","n":3,"pad":1}"#.into())
+        );
+        assert_eq!(
+            lightbar_message(0, (255, 128, 0)),
+            Message::Text(r#"{"type":"lightbar","r":255,"g":128,"b":0,"pad":0}"#.into())
+        );
+        assert_eq!(
+            haptic_message(0, &[10, 10]),
+            Message::Text(r#"{"type":"haptic","pattern":[10,10],"pad":0}"#.into())
+        );
+    }
+
+    #[test]
+    fn test_bind_port_in_use() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let logger = Logger::root(Discard, o!());
+        let err = bind(&logger, listener.local_addr().unwrap(), None, "localhost")
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("--port"), "{}", err);
+    }
+
+    #[test]
+    fn test_txt_record() {
+        let origin = Origin {
+            host: "example".to_string(),
+            addresses: Vec::new(),
+            port: 1234,
+            secure: true,
+            self_signed: true,
+        };
+        assert_eq!(
+            txt_record(&origin, "Gaming PC"),
+            [
+                "name=Gaming PC",
+                "protocols=sphrosyne.v2.binary,sphrosyne.v1.json",
+                "token=required",
+                "scheme=https"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_origin() {
+        let mut origin = Origin {
+            host: "example".to_string(),
+            addresses: Vec::new(),
+            port: 1234,
+            secure: false,
+            self_signed: false,
+        };
+        assert_eq!(origin.http("/"), "http://example:1234/");
+        assert_eq!(origin.ws("/websocket"), "ws://example:1234/websocket");
+
+        origin.secure = true;
+        assert_eq!(origin.http("/"), "https://example:1234/");
+        assert_eq!(origin.ws("/websocket"), "wss://example:1234/websocket");
+        assert_eq!(
+            origin.at("192.168.1.10").ws("/websocket"),
+            "wss://192.168.1.10:1234/websocket"
+        );
+    }
+
+    #[test]
+    fn test_request_host() {
+        let host = |value: &str| {
+            let headers = [Header::from_bytes("Host", value).unwrap()];
+            request_host(&headers).map(str::to_string)
+        };
+        assert_eq!(host("192.168.1.10:8080").as_deref(), Some("192.168.1.10"));
+        assert_eq!(host("example").as_deref(), Some("example"));
+        assert_eq!(host("[fe80::1]:8080").as_deref(), Some("[fe80::1]"));
+        assert_eq!(host("[fe80::1]").as_deref(), Some("[fe80::1]"));
+        // Anything which could break out of the page's HTML is left out
+        assert_eq!(host(r#"a"><script>:80"#), None);
+        assert_eq!(host(":8080"), None);
+        assert_eq!(request_host(&[]), None);
+    }
+
+    #[test]
+    fn test_qr_data_url() {
+        let url = qr_data_url("http://192.168.1.10:1234/controller?token=0123").unwrap();
+        let svg = url.strip_prefix("data:image/svg+xml;base64,").unwrap();
+        let svg = String::from_utf8(base64::decode(svg).unwrap()).unwrap();
+        // Version 3 is 29 modules on each side, which a border of 4 brings to 37
+        assert!(
+            svg.contains(r#"viewBox="0 0 37 37" width="592" height="592""#),
+            "{}",
+            svg
+        );
+        // The finder pattern in the top left corner starts right inside the border
+        assert!(svg.contains(r#"<path d="M4,4h1v1h-1z"#), "{}", svg);
+        assert!(url.len() < 10_000, "{}", url.len());
+    }
+
+    #[test]
+    fn test_index_page_addresses() {
+        let address = |interface: &str, ip: [u8; 4]| Address {
+            interface: interface.to_string(),
+            ip: ip.into(),
+        };
+        let mut origin = Origin {
+            host: "192.168.1.10".to_string(),
+            addresses: vec![address("Wi-Fi", [192, 168, 1, 10])],
+            port: 1234,
+            secure: false,
+            self_signed: false,
+        };
+        let token = Token::generate();
+        let qr_codes = |page: &str| page.matches("<img").count();
+
+        let page = index_page(&origin, &token, "example", None, &Assets::default(), true).unwrap();
+        assert_eq!(qr_codes(&page), LAYOUTS.len());
+        assert!(page.contains(r#"alt="http://192.168.1.10:1234/controller?token="#));
+        assert!(!page.contains("<h2>Wi-Fi"));
+
+        origin
+            .addresses
+            .push(address("Ethernet <2>", [10, 0, 0, 2]));
+        let page = index_page(&origin, &token, "example", None, &Assets::default(), true).unwrap();
+        assert_eq!(qr_codes(&page), 2 * LAYOUTS.len());
+        assert!(page.contains("<h2>Wi-Fi (192.168.1.10)</h2>"));
+        assert!(page.contains("<h2>Ethernet &lt;2&gt; (10.0.0.2)</h2>"));
+        assert!(page.contains(r#"alt="http://10.0.0.2:1234/controller?token="#));
+
+        // This machine gets to test the pads from the index page too
+        assert!(page.contains("<h2>Pads</h2>"));
+        assert!(page.contains(r#"<div id="pads"></div>"#));
+        assert!(page.contains(assets::ADMIN));
+
+        // While anyone else gets neither the QR codes nor the token
+        let page = index_page(&origin, &token, "example", None, &Assets::default(), false).unwrap();
+        assert_eq!(qr_codes(&page), 0);
+        assert!(!page.contains(&token.to_string()));
+        assert!(!page.contains(r#"<div id="pads">"#));
+    }
+    #[test]
+    fn test_controller_page_assets() {
+        let origin = Origin {
+            host: "example".to_string(),
+            addresses: Vec::new(),
+            port: 1234,
+            secure: false,
+            self_signed: false,
+        };
+        let token = Token::generate();
+        let layout = Layout::default();
+
+        let inlined =
+            controller_page(&origin, &token, "example", layout, None, &Assets::default()).unwrap();
+        assert!(inlined.contains(layout.script));
+        assert!(!inlined.contains(r#"src="/controller.js""#));
+
+        let linked = Assets::new(Some("assets".into()));
+        let linked = controller_page(&origin, &token, "example", layout, None, &linked).unwrap();
+        assert!(!linked.contains(layout.script));
+        assert!(linked.contains(r#"<script src="/controller.js"></script>"#));
+        assert!(linked.contains(r#"<script src="/layouts/standard.js"></script>"#));
+        assert!(linked.contains(r#"<link href="/style.css" rel="stylesheet">"#));
+
+        // Custom layouts are given to the script drawing them
+        let custom: CustomLayout = serde_json::from_str(
+            r#"{"controls":[{"kind":"stick","binding":"left_stick","x":0,"y":0,"width":1,"height":1}]}"#,
+        )
+        .unwrap();
+        let page = controller_page(
+            &origin,
+            &token,
+            "example",
+            &layout::CUSTOM,
+            Some(&custom),
+            &Assets::default(),
+        )
+        .unwrap();
+        assert!(page.contains(
+            r#"const CUSTOM_LAYOUT = {"controls":[{"kind":"stick","binding":"left_stick","x":0.0"#
+        ));
+        assert!(page.contains(layout::CUSTOM.script));
     }
 }
@@ -0,0 +1,179 @@
+//! Saving who has which pad when we shut down, and picking up from there when we start again,
+//! so that restarting sphrosyne, e.g. to update it, doesn't make every player scan the QR code
+//! again.
+//!
+//! The pads themselves go away with the process, but their replacements are created with the
+//! same reclaim tokens, detached, so that clients get them back by reconnecting like they would
+//! after losing their connection. Games put up with a controller disappearing for a moment far
+//! better than with every player being renumbered.
+
+use std::{fs, io, path::Path};
+
+use eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use slog::{warn, Logger};
+
+use crate::{calibration::Calibration, remap::Remap, request::PadType};
+
+/// The version of the snapshot format, which snapshots of any other version are ignored for
+pub(crate) const SNAPSHOT_VERSION: u32 = 1;
+
+/// Everything needed to pick up where we left off
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Snapshot {
+    pub(crate) version: u32,
+
+    /// The session token, which the pages clients already have open carry
+    pub(crate) token: String,
+
+    /// Every pad, in the order they're to be created again
+    pub(crate) pads: Vec<PadSnapshot>,
+}
+
+/// A pad as it was when we shut down
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct PadSnapshot {
+    pub(crate) pad_type: PadType,
+
+    /// Whether the pad was created at startup, and is kept around when nobody is using it
+    pub(crate) reserved: bool,
+
+    /// The client the pad belonged to, if any
+    pub(crate) client: Option<ClientSnapshot>,
+}
+
+/// What a pad's client set up, which it gets back along with the pad
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ClientSnapshot {
+    /// The token the client reclaims the pad with
+    pub(crate) reclaim: String,
+
+    pub(crate) device: Option<String>,
+    pub(crate) calibration: Calibration,
+    pub(crate) remap: Remap,
+}
+
+/// Just the version of a snapshot, to tell whether the rest can be read
+#[derive(Deserialize)]
+struct Version {
+    version: u32,
+}
+
+impl Snapshot {
+    /// Load the snapshot at the given path, if there's one we can use.
+    ///
+    /// A snapshot which is unreadable or of another version is only a reason to start from
+    /// scratch, so that's logged rather than returned as an error.
+    pub(crate) fn load(logger: &Logger, path: &Path) -> Result<Option<Self>> {
+        let data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => {
+                return Err(error).wrap_err_with(|| format!("Could not read {}", path.display()))
+            }
+        };
+        match serde_json::from_str::<Version>(&data) {
+            Ok(Version { version }) if version == SNAPSHOT_VERSION => {}
+            Ok(Version { version }) => {
+                warn!(logger, "snapshot.ignored"; "path" => %path.display(), "version" => version, "expected" => SNAPSHOT_VERSION);
+                return Ok(None);
+            }
+            Err(error) => {
+                warn!(logger, "snapshot.ignored"; "path" => %path.display(), "error" => %error);
+                return Ok(None);
+            }
+        }
+        match serde_json::from_str(&data) {
+            Ok(snapshot) => Ok(Some(snapshot)),
+            Err(error) => {
+                warn!(logger, "snapshot.ignored"; "path" => %path.display(), "error" => %error);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Save the snapshot to the given path, replacing whatever was there only once it's all
+    /// written
+    pub(crate) fn save(&self, path: &Path) -> Result<()> {
+        let partial = path.with_extension("partial");
+        fs::write(&partial, serde_json::to_string_pretty(self)?)
+            .wrap_err_with(|| format!("Could not write {}", partial.display()))?;
+        fs::rename(&partial, path).wrap_err_with(|| format!("Could not write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use slog::{o, Discard};
+    use vigem_client_c::X360Buttons;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "sphrosyne-snapshot-{}-{}.json",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn logger() -> Logger {
+        Logger::root(Discard, o!())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = temp_path("round-trip");
+        let mut remap = Remap::default();
+        let _ = remap.buttons.insert(X360Buttons::A, X360Buttons::B);
+        remap.swap_sticks = true;
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            token: "0123abcd".to_string(),
+            pads: vec![
+                PadSnapshot {
+                    pad_type: PadType::X360,
+                    reserved: true,
+                    client: None,
+                },
+                PadSnapshot {
+                    pad_type: PadType::DS4,
+                    reserved: false,
+                    client: Some(ClientSnapshot {
+                        reclaim: "4567ef".to_string(),
+                        device: Some("phone".to_string()),
+                        calibration: Calibration {
+                            deadzone: 12.5,
+                            ..Calibration::default()
+                        },
+                        remap,
+                    }),
+                },
+            ],
+        };
+
+        assert_eq!(Snapshot::load(&logger(), &path).unwrap(), None);
+        snapshot.save(&path).unwrap();
+        let loaded = Snapshot::load(&logger(), &path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, Some(snapshot));
+    }
+
+    #[test]
+    fn test_incompatible() {
+        let path = temp_path("incompatible");
+        for data in &[
+            r#"{"version":0,"token":"","pads":[]}"#,
+            r#"{"version":2,"something":"else"}"#,
+            r#"{"pads":[]}"#,
+            r#"{"version":1,"token":"","pads":[{"pad_type":"n64"}]}"#,
+            "not json",
+        ] {
+            fs::write(&path, data).unwrap();
+            assert_eq!(Snapshot::load(&logger(), &path).unwrap(), None, "{}", data);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,165 @@
+//! The snapshot of our state served at `/status`
+
+use std::net::IpAddr;
+
+use serde::{Serialize, Serializer};
+use vigem_client_c::{client::UserIndex, Error};
+
+use crate::{latency::LatencySummary, request::PadType, ticker::TickStatus};
+
+/// How long the window used to compute update rates is, in seconds
+pub const RATE_WINDOW_SECS: u64 = 5;
+
+/// What's going on with the bus and our pads
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    /// How long we've been running, in seconds
+    pub uptime_secs: f64,
+
+    /// Whether we're currently connected to ViGEmBus
+    pub bus_connected: bool,
+
+    pub pads: Vec<PadStatus>,
+
+    /// How steady the tick pads' states are sent on is, if they aren't sent as they come
+    pub tick: Option<TickStatus>,
+}
+
+impl Status {
+    /// How many pads a client is using, leaving out those waiting for one to take them or to
+    /// come back
+    pub fn active_pads(&self) -> usize {
+        self.pads
+            .iter()
+            .filter(|pad| !pad.free && !pad.detached)
+            .count()
+    }
+}
+
+/// What's going on with a single pad
+#[derive(Debug, Clone, Serialize)]
+pub struct PadStatus {
+    pub id: usize,
+
+    /// Which kind of controller the pad is
+    pub pad_type: PadType,
+
+    /// The vendor and product ids the pad was plugged in with, which `--pad-preset` picks
+    pub vendor_id: u16,
+    pub product_id: u16,
+
+    /// The pad's user index, which dualshock 4 pads never have
+    pub user_index: UserIndexStatus,
+
+    /// Which player the pad is, counting from 1: the LED the game lit on it if it did, else its
+    /// user index, else the number it was given past the four XInput slots
+    pub player: Option<u32>,
+
+    /// The player number as shown to the pad's client, e.g. `P5 (no XInput slot)`
+    pub player_label: Option<String>,
+
+    /// Whether the pad's client lost its connection and may still come back for it
+    pub detached: bool,
+
+    /// Whether the pad's page is in the background, which keeps the pad neutral
+    pub suspended: bool,
+
+    /// Whether the pad was created at startup and is waiting for a client to take it
+    pub free: bool,
+
+    /// How many updates the client sent per second, over the last [RATE_WINDOW_SECS] seconds
+    pub updates_per_second: f64,
+
+    /// When the client last sent an update, in milliseconds since the unix epoch
+    pub last_update_ms: Option<u128>,
+
+    /// How many updates were sent to the bus
+    pub sent: u64,
+
+    /// How many updates were skipped for being identical to the last one sent
+    pub skipped: u64,
+
+    /// How many updates the bus refused, after trying again if it was only briefly unable to
+    /// take them
+    pub errors: u64,
+
+    /// How many states the client sent too fast were replaced by later ones before being sent
+    pub coalesced: u64,
+
+    /// How many messages the client sent were thrown away, for being too large or too fast
+    pub dropped: u64,
+
+    /// The percentiles of the delays the client's latest states went through
+    pub latency: LatencySummary,
+
+    /// What changed between the client's last two states, e.g. `+A LT:0→255`
+    pub last_change: String,
+}
+
+/// Who is controlling a pad, as served to this machine alone at `/admin/pads`
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ClientStatus {
+    pub(crate) id: usize,
+
+    /// The address the client connected from
+    pub(crate) ip: IpAddr,
+
+    /// When the client connected, in milliseconds since the unix epoch
+    pub(crate) connected_ms: u128,
+
+    /// When the client last sent an update, in milliseconds since the unix epoch
+    pub(crate) last_update_ms: Option<u128>,
+
+    /// The buttons the client last said are held, as the bits of
+    /// [X360Buttons](vigem_client_c::X360Buttons)
+    pub(crate) buttons: u16,
+
+    /// Whether the client lost its connection and may still come back for the pad
+    pub(crate) detached: bool,
+
+    /// Whether the client's page is in the background, which keeps the pad neutral
+    pub(crate) suspended: bool,
+}
+
+/// A pad's user index as served at `/status`: the index once the bus gave the pad one,
+/// `"pending"` while it's yet to, and `null` if the bus can't tell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserIndexStatus {
+    Assigned(u32),
+    Pending,
+    Unknown,
+}
+
+impl From<Result<UserIndex, Error>> for UserIndexStatus {
+    fn from(user_index: Result<UserIndex, Error>) -> Self {
+        match user_index {
+            Ok(UserIndex::Assigned(index)) => Self::Assigned(index),
+            Ok(UserIndex::Unassigned) => Self::Pending,
+            Ok(UserIndex::Unknown(_)) | Err(_) => Self::Unknown,
+        }
+    }
+}
+
+impl Serialize for UserIndexStatus {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Assigned(index) => serializer.serialize_u32(*index),
+            Self::Pending => serializer.serialize_str("pending"),
+            Self::Unknown => serializer.serialize_none(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_index() {
+        let json = |user_index| serde_json::to_string(&UserIndexStatus::from(user_index)).unwrap();
+        assert_eq!(json(Ok(UserIndex::Assigned(2))), "2");
+        assert_eq!(json(Ok(UserIndex::Unassigned)), r#""pending""#);
+        assert_eq!(json(Ok(UserIndex::Unknown(0xDEADBEEF))), "null");
+        assert_eq!(json(Err(Error::BusNotFound)), "null");
+    }
+}
@@ -0,0 +1,317 @@
+//! Making up a dpad from the left stick or the other way around, for layouts which only have one
+//! of them playing games which read the other
+
+use std::{f32::consts::FRAC_PI_4, time::Instant};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use vigem_client_c::{StickPosition, X360Buttons, X360State};
+
+use crate::transform::Transformer;
+
+/// Every dpad button, which are what [StickToDpad] and [DpadToStick] read or write
+const DPAD: X360Buttons = X360Buttons::DPAD_UP
+    .union(X360Buttons::DPAD_DOWN)
+    .union(X360Buttons::DPAD_LEFT)
+    .union(X360Buttons::DPAD_RIGHT);
+
+/// The dpad buttons held for each eighth of the left stick's range, counterclockwise from
+/// straight right like [StickPosition::angle]
+const DIRECTIONS: [X360Buttons; 8] = [
+    X360Buttons::DPAD_RIGHT,
+    X360Buttons::DPAD_UP.union(X360Buttons::DPAD_RIGHT),
+    X360Buttons::DPAD_UP,
+    X360Buttons::DPAD_UP.union(X360Buttons::DPAD_LEFT),
+    X360Buttons::DPAD_LEFT,
+    X360Buttons::DPAD_DOWN.union(X360Buttons::DPAD_LEFT),
+    X360Buttons::DPAD_DOWN,
+    X360Buttons::DPAD_DOWN.union(X360Buttons::DPAD_RIGHT),
+];
+
+/// Holds the dpad in whichever of eight directions the left stick points once it's pushed past
+/// a threshold. The stick is left as it is, and so is the dpad while the client holds it itself.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub(crate) struct StickToDpad {
+    /// How far the stick has to be pushed to hold the dpad, as a percentage of its range
+    pub(crate) threshold: u8,
+
+    /// How much further in than the threshold the stick has to come back to let go of the dpad
+    /// again, as a percentage of its range, so that it doesn't flicker when held right at the
+    /// threshold
+    pub(crate) hysteresis: u8,
+
+    /// Whether the stick was last pushed far enough to hold the dpad
+    #[serde(skip)]
+    engaged: bool,
+}
+
+impl Default for StickToDpad {
+    fn default() -> Self {
+        Self::new(50, 10)
+    }
+}
+
+/// Stick to dpad synthesis configured the same way is the same, wherever the stick is now
+impl PartialEq for StickToDpad {
+    fn eq(&self, other: &Self) -> bool {
+        (self.threshold, self.hysteresis) == (other.threshold, other.hysteresis)
+    }
+}
+
+impl Eq for StickToDpad {}
+
+impl StickToDpad {
+    /// Holding the dpad past the given threshold, letting go of it the given hysteresis further
+    /// in, both as percentages
+    pub(crate) fn new(threshold: u8, hysteresis: u8) -> Self {
+        Self {
+            threshold,
+            hysteresis,
+            engaged: false,
+        }
+    }
+
+    /// Which dpad buttons the stick holds, given how far it has to be pushed for that now
+    fn direction(&mut self, stick: StickPosition) -> X360Buttons {
+        let threshold = f32::from(self.threshold.min(100)) / 100.0;
+        let release = threshold - f32::from(self.hysteresis.min(self.threshold)) / 100.0;
+        let magnitude = stick.magnitude();
+        self.engaged = if self.engaged {
+            magnitude >= release && magnitude > 0.0
+        } else {
+            magnitude >= threshold && magnitude > 0.0
+        };
+        if !self.engaged {
+            return X360Buttons::empty();
+        }
+        let eighth = (stick.angle() / FRAC_PI_4).round() as i32;
+        DIRECTIONS[eighth.rem_euclid(8) as usize]
+    }
+}
+
+impl Transformer for StickToDpad {
+    fn transform(&mut self, mut state: X360State, _: Instant) -> X360State {
+        // The hysteresis keeps track of the stick even while the client's dpad takes precedence
+        let direction = self.direction(state.left_thumbstick);
+        if !state.buttons.intersects(DPAD) {
+            state.buttons.insert(direction);
+        }
+        state
+    }
+}
+
+/// Pushes the left stick all the way in whichever direction the dpad is held, the diagonals as
+/// far as the straight directions. The dpad is left as it is, and so is the stick while the
+/// client pushes it itself.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct DpadToStick;
+
+impl Transformer for DpadToStick {
+    fn transform(&mut self, mut state: X360State, _: Instant) -> X360State {
+        if state.left_thumbstick != StickPosition::CENTER {
+            return state;
+        }
+        let axis = |positive: X360Buttons, negative: X360Buttons| {
+            i8::from(state.buttons.contains(positive)) - i8::from(state.buttons.contains(negative))
+        };
+        let x = axis(X360Buttons::DPAD_RIGHT, X360Buttons::DPAD_LEFT);
+        let y = axis(X360Buttons::DPAD_UP, X360Buttons::DPAD_DOWN);
+        if (x, y) != (0, 0) {
+            let angle = f32::from(y).atan2(f32::from(x));
+            state.left_thumbstick = StickPosition::from_polar(angle, 1.0);
+        }
+        state
+    }
+}
+
+/// Which of the dpad and the left stick a pad makes up from the other. There's only ever one,
+/// as with both the dpad would push the stick which holds the dpad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum Synthesis {
+    StickToDpad(StickToDpad),
+    DpadToStick,
+}
+
+impl Transformer for Synthesis {
+    fn transform(&mut self, state: X360State, now: Instant) -> X360State {
+        match self {
+            Self::StickToDpad(stick_to_dpad) => stick_to_dpad.transform(state, now),
+            Self::DpadToStick => DpadToStick.transform(state, now),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+
+    fn stick(x: i16, y: i16) -> X360State {
+        X360State::builder().left_stick(x, y).build()
+    }
+
+    fn dpad(buttons: X360Buttons) -> X360State {
+        X360State::builder().press(buttons).build()
+    }
+
+    #[test]
+    fn test_stick_to_dpad_directions() {
+        let now = Instant::now();
+        let mut synthesis = StickToDpad::default();
+        for (eighth, &direction) in DIRECTIONS.iter().enumerate() {
+            // Anywhere within an eighth holds the same buttons, not only its middle
+            for offset in [-0.3, 0.0, 0.3] {
+                let angle = (eighth as f32 + offset) * FRAC_PI_4;
+                let state = X360State {
+                    left_thumbstick: StickPosition::from_polar(angle, 1.0),
+                    ..X360State::default()
+                };
+                let transformed = synthesis.transform(state, now);
+                assert_eq!(transformed.buttons, direction, "{}", angle);
+                assert_eq!(transformed.left_thumbstick, state.left_thumbstick);
+            }
+        }
+
+        // Straight left is left whichever side of the x axis it's on
+        let left = StickPosition::from_polar(-PI, 1.0);
+        assert_eq!(
+            synthesis.transform(stick(left.x, left.y), now).buttons,
+            X360Buttons::DPAD_LEFT
+        );
+        assert_eq!(
+            synthesis.transform(stick(0, 0), now).buttons,
+            X360Buttons::empty()
+        );
+    }
+
+    #[test]
+    fn test_stick_to_dpad_hysteresis() {
+        let now = Instant::now();
+        let mut synthesis = StickToDpad {
+            threshold: 50,
+            hysteresis: 10,
+            ..StickToDpad::default()
+        };
+        let mut held = |percent: i32| {
+            let x = (f32::from(i16::MAX) * percent as f32 / 100.0) as i16;
+            synthesis.transform(stick(x, 0), now).buttons == X360Buttons::DPAD_RIGHT
+        };
+
+        // Within the band the dpad stays as it was, whichever way the stick came from
+        assert!(!held(45));
+        assert!(held(51));
+        assert!(held(45));
+        assert!(held(41));
+        assert!(!held(39));
+        assert!(!held(45));
+        assert!(held(55));
+
+        // Hysteresis wider than the threshold lets go only at the center
+        let mut synthesis = StickToDpad {
+            threshold: 20,
+            hysteresis: 50,
+            ..StickToDpad::default()
+        };
+        assert!(!synthesis
+            .transform(stick(1000, 0), now)
+            .buttons
+            .intersects(DPAD));
+        assert!(synthesis
+            .transform(stick(i16::MAX, 0), now)
+            .buttons
+            .intersects(DPAD));
+        assert!(synthesis
+            .transform(stick(1, 0), now)
+            .buttons
+            .intersects(DPAD));
+        assert!(!synthesis
+            .transform(stick(0, 0), now)
+            .buttons
+            .intersects(DPAD));
+    }
+
+    #[test]
+    fn test_dpad_to_stick() {
+        let now = Instant::now();
+        let mut synthesis = DpadToStick;
+        let pushed = |state| DpadToStick.transform(state, now).left_thumbstick;
+        assert_eq!(pushed(dpad(X360Buttons::DPAD_UP)), (0, i16::MAX));
+        assert_eq!(pushed(dpad(X360Buttons::DPAD_LEFT)), (i16::MIN, 0));
+        assert_eq!(pushed(dpad(X360Buttons::DPAD_DOWN)), (0, i16::MIN));
+
+        // Diagonals are as far out as straight directions, rather than in the corner
+        let diagonal = pushed(dpad(X360Buttons::DPAD_UP | X360Buttons::DPAD_RIGHT));
+        assert_eq!(diagonal, (23170, 23170));
+        assert!((diagonal.magnitude() - 1.0).abs() < 1e-4);
+
+        // Opposites cancel out
+        assert_eq!(
+            pushed(dpad(X360Buttons::DPAD_LEFT | X360Buttons::DPAD_RIGHT)),
+            StickPosition::CENTER
+        );
+        assert_eq!(
+            pushed(dpad(
+                X360Buttons::DPAD_LEFT | X360Buttons::DPAD_RIGHT | X360Buttons::DPAD_DOWN
+            )),
+            (0, i16::MIN)
+        );
+
+        let state = dpad(X360Buttons::DPAD_RIGHT | X360Buttons::A);
+        assert_eq!(synthesis.transform(state, now).buttons, state.buttons);
+        assert_eq!(
+            synthesis.transform(X360State::default(), now),
+            X360State::default()
+        );
+    }
+
+    #[test]
+    fn test_precedence() {
+        let now = Instant::now();
+
+        // The dpad the client holds wins over the one made up from the stick
+        let mut stick_to_dpad = Synthesis::StickToDpad(StickToDpad::default());
+        let mut state = stick(i16::MAX, 0);
+        state.buttons = X360Buttons::DPAD_DOWN;
+        assert_eq!(
+            stick_to_dpad.transform(state, now).buttons,
+            X360Buttons::DPAD_DOWN
+        );
+        // The stick was still followed meanwhile, so letting go of the dpad takes it up
+        // without waiting to cross the threshold again
+        let state = stick(i16::MAX / 2 - 2000, 0);
+        assert_eq!(
+            stick_to_dpad.transform(state, now).buttons,
+            X360Buttons::DPAD_RIGHT
+        );
+
+        // As does the stick the client pushes over the one made up from the dpad
+        let mut dpad_to_stick = Synthesis::DpadToStick;
+        let mut state = dpad(X360Buttons::DPAD_UP);
+        state.left_thumbstick = StickPosition::new(-100, -200);
+        assert_eq!(dpad_to_stick.transform(state, now), state);
+    }
+
+    #[test]
+    fn test_parse() {
+        let synthesis = |json| serde_json::from_str::<Synthesis>(json).unwrap();
+        assert_eq!(
+            synthesis(r#"{"kind":"stick_to_dpad"}"#),
+            Synthesis::StickToDpad(StickToDpad::default())
+        );
+        assert_eq!(
+            synthesis(r#"{"kind":"stick_to_dpad","threshold":30}"#),
+            Synthesis::StickToDpad(StickToDpad {
+                threshold: 30,
+                ..StickToDpad::default()
+            })
+        );
+        assert_eq!(
+            synthesis(r#"{"kind":"dpad_to_stick"}"#),
+            Synthesis::DpadToStick
+        );
+        assert!(serde_json::from_str::<Synthesis>(r#"{"kind":"both"}"#).is_err());
+    }
+}
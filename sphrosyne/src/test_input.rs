@@ -0,0 +1,97 @@
+//! The input a pad plays when asked to from the admin routes, so that whoever is at the machine
+//! can see whether a game reacts to the pad without anybody touching the phone controlling it
+
+use std::{
+    f32::consts::TAU,
+    time::{Duration, Instant},
+};
+
+use vigem_client_c::{StickPosition, X360Buttons, X360State};
+
+/// How long the test input lasts
+pub(crate) const TEST_INPUT_LENGTH: Duration = Duration::from_secs(2);
+
+/// How long A is held at the start of the test input, before the left stick goes around
+const PRESS_LENGTH: Duration = Duration::from_millis(500);
+
+/// How long the left stick takes to go around once
+const TURN_LENGTH: Duration = Duration::from_millis(500);
+
+/// How often the left stick moves while it goes around
+const STEP: Duration = Duration::from_millis(20);
+
+/// How recently a pad's client has to have sent a state for the pad not to play the test input,
+/// as the client's states take precedence
+pub(crate) const CLIENT_PRECEDENCE: Duration = Duration::from_secs(1);
+
+/// The test input a pad is playing: A held for a moment, and then the left stick going around
+/// all the way out a few times
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TestInput {
+    started: Instant,
+}
+
+impl TestInput {
+    pub(crate) fn new(now: Instant) -> Self {
+        Self { started: now }
+    }
+
+    /// The state to send at `now`, or `None` once the test input is over
+    pub(crate) fn state(&self, now: Instant) -> Option<X360State> {
+        let elapsed = now.saturating_duration_since(self.started);
+        if elapsed >= TEST_INPUT_LENGTH {
+            return None;
+        }
+        if elapsed < PRESS_LENGTH {
+            return Some(X360State::builder().press(X360Buttons::A).build());
+        }
+        let turns = (elapsed - PRESS_LENGTH).as_secs_f32() / TURN_LENGTH.as_secs_f32();
+        Some(X360State {
+            left_thumbstick: StickPosition::from_polar(turns * TAU, 1.0),
+            ..X360State::default()
+        })
+    }
+
+    /// When the state to send next is due, given that the last one was sent at `now`
+    pub(crate) fn next_step(&self, now: Instant) -> Instant {
+        (now + STEP).min(self.started + TEST_INPUT_LENGTH)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script() {
+        let started = Instant::now();
+        let test = TestInput::new(started);
+        let at = |ms| test.state(started + Duration::from_millis(ms));
+
+        let pressed = X360State::builder().press(X360Buttons::A).build();
+        assert_eq!(at(0), Some(pressed));
+        assert_eq!(at(499), Some(pressed));
+
+        // The stick starts out straight right and goes around counterclockwise, all the way out
+        let stick = |ms| at(ms).unwrap().left_thumbstick;
+        assert_eq!(at(500).unwrap().buttons, X360Buttons::empty());
+        assert_eq!(stick(500), (i16::MAX, 0));
+        assert_eq!(stick(625), (0, i16::MAX));
+        assert_eq!(stick(750), (i16::MIN, 0));
+        assert!((stick(1333).magnitude() - 1.0).abs() < 1e-3);
+
+        assert!(at(1999).is_some());
+        assert_eq!(at(2000), None);
+        assert_eq!(at(5000), None);
+    }
+
+    #[test]
+    fn test_next_step() {
+        let started = Instant::now();
+        let test = TestInput::new(started);
+        assert_eq!(test.next_step(started), started + STEP);
+        // The last step is when the test input ends, to go back to what the client holds
+        let end = started + TEST_INPUT_LENGTH;
+        assert_eq!(test.next_step(end - Duration::from_millis(5)), end);
+    }
+}
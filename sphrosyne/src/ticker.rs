@@ -0,0 +1,133 @@
+//! Sending pads' states to the bus on a fixed tick rather than as they come, for games which
+//! read their pads once a frame and would otherwise see states land at uneven points of it
+
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::latency::{Percentiles, Window};
+
+/// The most ticks per second, past which ticking is no steadier than sending states as they come
+pub(crate) const MAX_TICK_RATE: u32 = 1000;
+
+/// When the next tick is due, keeping to a fixed grid rather than counting from whenever the
+/// last tick happened, so that being late once doesn't push every later tick back
+#[derive(Debug)]
+pub(crate) struct Ticker {
+    rate: u32,
+    interval: Duration,
+    next: Instant,
+    last: Option<Instant>,
+
+    /// How long the latest ticks actually were apart
+    intervals: Window,
+}
+
+/// How steady the tick is
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct TickStatus {
+    /// How many ticks there are meant to be per second
+    pub rate: u32,
+
+    /// The percentiles of how long the latest ticks actually were apart, missing until there
+    /// were two
+    pub intervals: Option<Percentiles>,
+}
+
+impl Ticker {
+    /// Tick `rate` times per second from `now` on, up to [MAX_TICK_RATE], or not at all for 0
+    pub(crate) fn new(rate: u32, now: Instant) -> Option<Self> {
+        let rate = rate.min(MAX_TICK_RATE);
+        if rate == 0 {
+            return None;
+        }
+        let interval = Duration::from_secs(1) / rate;
+        Some(Self {
+            rate,
+            interval,
+            next: now + interval,
+            last: None,
+            intervals: Window::default(),
+        })
+    }
+
+    /// When the next tick is due
+    pub(crate) fn next(&self) -> Instant {
+        self.next
+    }
+
+    /// Whether a tick is due at `now`, moving on to the next one if so. Ticks which were missed
+    /// altogether are skipped rather than caught up on in a burst.
+    pub(crate) fn due(&mut self, now: Instant) -> bool {
+        if now < self.next {
+            return false;
+        }
+        if let Some(last) = self.last {
+            self.intervals.record(now.duration_since(last));
+        }
+        self.last = Some(now);
+
+        self.next += self.interval;
+        if self.next <= now {
+            let missed = now.duration_since(self.next).as_nanos() / self.interval.as_nanos() + 1;
+            self.next += self.interval * missed.min(u128::from(u32::MAX)) as u32;
+        }
+        true
+    }
+
+    pub(crate) fn status(&self) -> TickStatus {
+        TickStatus {
+            rate: self.rate,
+            intervals: self.intervals.percentiles(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_off() {
+        assert!(Ticker::new(0, Instant::now()).is_none());
+        let capped = Ticker::new(u32::MAX, Instant::now()).unwrap();
+        assert_eq!(capped.status().rate, MAX_TICK_RATE);
+    }
+
+    #[test]
+    fn test_drift() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut ticker = Ticker::new(100, start).unwrap();
+        assert_eq!(ticker.next(), at(10));
+        assert!(!ticker.due(at(9)));
+
+        // A late tick doesn't push the next one back
+        assert!(ticker.due(at(13)));
+        assert_eq!(ticker.next(), at(20));
+        assert!(!ticker.due(at(13)));
+        assert!(ticker.due(at(20)));
+        assert_eq!(ticker.next(), at(30));
+
+        // Ticks missed altogether are skipped, staying on the grid
+        assert!(ticker.due(at(55)));
+        assert_eq!(ticker.next(), at(60));
+        assert!(ticker.due(at(60)));
+        assert_eq!(ticker.next(), at(70));
+    }
+
+    #[test]
+    fn test_intervals() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut ticker = Ticker::new(100, start).unwrap();
+        assert!(ticker.due(at(10)));
+        assert_eq!(ticker.status().intervals, None);
+        for &ms in &[20, 30, 40, 52] {
+            assert!(ticker.due(at(ms)));
+        }
+        let intervals = ticker.status().intervals.unwrap();
+        assert_eq!(intervals.p50_ms, 10.0);
+        assert_eq!(intervals.p99_ms, 12.0);
+    }
+}
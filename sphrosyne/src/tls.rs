@@ -0,0 +1,61 @@
+//! Certificates for serving over HTTPS
+
+use std::path::PathBuf;
+
+/// Where the certificate we serve HTTPS with comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Tls {
+    /// A self-signed certificate, generated on the first run and kept next to us afterwards
+    SelfSigned,
+
+    /// A certificate and private key the user gave us, both PEM encoded
+    Provided { cert: PathBuf, key: PathBuf },
+}
+
+/// Where the generated self-signed certificate is kept
+#[cfg(feature = "tls")]
+const SELF_SIGNED_CERT: &str = "sphrosyne-cert.pem";
+
+/// Where the private key of the generated self-signed certificate is kept
+#[cfg(feature = "tls")]
+const SELF_SIGNED_KEY: &str = "sphrosyne-key.pem";
+
+#[cfg(feature = "tls")]
+impl Tls {
+    /// Load our certificate, generating a self-signed one for `host` if we don't have one yet.
+    pub(crate) fn ssl_config(
+        &self,
+        logger: &slog::Logger,
+        host: &str,
+    ) -> eyre::Result<tiny_http::SslConfig> {
+        use eyre::WrapErr;
+        use std::{fs, path::Path};
+
+        let (cert, key) = match self {
+            Self::Provided { cert, key } => (cert.as_path(), key.as_path()),
+
+            Self::SelfSigned => {
+                let (cert, key) = (Path::new(SELF_SIGNED_CERT), Path::new(SELF_SIGNED_KEY));
+                if !cert.exists() || !key.exists() {
+                    let generated = rcgen::generate_simple_self_signed(vec![
+                        host.to_string(),
+                        "localhost".to_string(),
+                    ])?;
+                    fs::write(cert, generated.serialize_pem()?)
+                        .wrap_err_with(|| format!("Could not write {}", cert.display()))?;
+                    fs::write(key, generated.serialize_private_key_pem())
+                        .wrap_err_with(|| format!("Could not write {}", key.display()))?;
+                    slog::info!(logger, "tls.generated"; "cert" => %cert.display(), "key" => %key.display());
+                }
+                (cert, key)
+            }
+        };
+
+        Ok(tiny_http::SslConfig {
+            certificate: fs::read(cert)
+                .wrap_err_with(|| format!("Could not read {}", cert.display()))?,
+            private_key: fs::read(key)
+                .wrap_err_with(|| format!("Could not read {}", key.display()))?,
+        })
+    }
+}
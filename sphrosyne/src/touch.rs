@@ -0,0 +1,188 @@
+//! Pressing the triggers by how hard the sticks are touched, for racing games on phones which
+//! report their touches' force
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use vigem_client_c::X360State;
+
+use crate::transform::Transformer;
+
+/// The lowest and highest gamma of a [ResponseCurve::Gamma], past which the curve is all but a
+/// step
+const GAMMA_RANGE: (f32, f32) = (0.1, 10.0);
+
+/// How hard the client's thumbs press on the left and right sticks, from 0 to 1, as sent along
+/// with an `update`. Phones which can't tell leave them out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub(crate) struct TouchForce {
+    #[serde(default)]
+    pub(crate) lforce: Option<f32>,
+    #[serde(default)]
+    pub(crate) rforce: Option<f32>,
+}
+
+/// How a touch's force maps to how far its trigger is pressed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ResponseCurve {
+    /// As hard as the touch is
+    #[default]
+    Linear,
+
+    /// Gentler for light touches, for finer throttle control
+    Squared,
+
+    /// The force to the given power, below 1 being more eager and above it gentler
+    Gamma(f32),
+}
+
+impl ResponseCurve {
+    /// How far a trigger is pressed by a touch of the given force, which is taken to be between
+    /// 0 and 1, with nonsense being 0
+    pub(crate) fn trigger(self, force: f32) -> u8 {
+        let force = if force.is_nan() {
+            0.0
+        } else {
+            force.clamp(0.0, 1.0)
+        };
+        let pressed = match self {
+            Self::Linear => force,
+            Self::Squared => force * force,
+            Self::Gamma(gamma) if gamma.is_nan() => force,
+            Self::Gamma(gamma) => force.powf(gamma.clamp(GAMMA_RANGE.0, GAMMA_RANGE.1)),
+        };
+        (pressed * f32::from(u8::MAX)).round() as u8
+    }
+}
+
+/// The latest touch force a client sent for one of its pads, which the connection puts in and
+/// the pad's [TouchTriggers] read
+#[derive(Debug, Default)]
+pub(crate) struct TouchCell(Mutex<Option<TouchForce>>);
+
+impl TouchCell {
+    /// Put in the force sent along with a state, or none if it came without one, in which case
+    /// the triggers are left as the state has them
+    pub(crate) fn put(&self, force: Option<TouchForce>) {
+        *self.0.lock().unwrap() = force;
+    }
+
+    fn get(&self) -> Option<TouchForce> {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// Presses a pad's triggers by how hard its sticks are touched, replacing whatever the states
+/// say for each stick whose force the client sent
+#[derive(Debug)]
+pub(crate) struct TouchTriggers {
+    pub(crate) curve: ResponseCurve,
+    pub(crate) force: Arc<TouchCell>,
+}
+
+impl Transformer for TouchTriggers {
+    fn transform(&mut self, mut state: X360State, _: Instant) -> X360State {
+        let force = self.force.get().unwrap_or_default();
+        if let Some(lforce) = force.lforce {
+            state.left_trigger = self.curve.trigger(lforce);
+        }
+        if let Some(rforce) = force.rforce {
+            state.right_trigger = self.curve.trigger(rforce);
+        }
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_curves() {
+        for &curve in &[
+            ResponseCurve::Linear,
+            ResponseCurve::Squared,
+            ResponseCurve::Gamma(2.2),
+        ] {
+            assert_eq!(curve.trigger(0.0), 0, "{:?}", curve);
+            assert_eq!(curve.trigger(1.0), 255, "{:?}", curve);
+        }
+        assert_eq!(ResponseCurve::Linear.trigger(0.5), 128);
+        assert_eq!(ResponseCurve::Squared.trigger(0.5), 64);
+        assert_eq!(ResponseCurve::Gamma(0.5).trigger(0.25), 128);
+        assert_eq!(
+            ResponseCurve::Gamma(2.0).trigger(0.3),
+            ResponseCurve::Squared.trigger(0.3)
+        );
+    }
+
+    #[test]
+    fn test_clamping() {
+        for &curve in &[ResponseCurve::Linear, ResponseCurve::Gamma(0.5)] {
+            assert_eq!(curve.trigger(-1.0), 0);
+            assert_eq!(curve.trigger(1.5), 255);
+            assert_eq!(curve.trigger(f32::NAN), 0);
+            assert_eq!(curve.trigger(f32::INFINITY), 255);
+        }
+        // Gammas out of range are brought back in, rather than making every touch full or none
+        assert_eq!(
+            ResponseCurve::Gamma(0.0).trigger(0.5),
+            ResponseCurve::Gamma(0.1).trigger(0.5)
+        );
+        assert_eq!(
+            ResponseCurve::Gamma(1000.0).trigger(0.9),
+            ResponseCurve::Gamma(10.0).trigger(0.9)
+        );
+        assert_eq!(ResponseCurve::Gamma(f32::NAN).trigger(0.5), 128);
+    }
+
+    #[test]
+    fn test_parse() {
+        let curve = |json| serde_json::from_str::<ResponseCurve>(json).unwrap();
+        assert_eq!(curve(r#""squared""#), ResponseCurve::Squared);
+        assert_eq!(curve(r#"{"gamma":1.8}"#), ResponseCurve::Gamma(1.8));
+        assert_eq!(
+            serde_json::from_str::<TouchForce>(r#"{"rforce":0.5}"#).unwrap(),
+            TouchForce {
+                lforce: None,
+                rforce: Some(0.5)
+            }
+        );
+    }
+
+    #[test]
+    fn test_transform() {
+        let now = Instant::now();
+        let cell = Arc::new(TouchCell::default());
+        let mut triggers = TouchTriggers {
+            curve: ResponseCurve::Linear,
+            force: Arc::clone(&cell),
+        };
+        let state = X360State {
+            left_trigger: 10,
+            right_trigger: 20,
+            ..X360State::default()
+        };
+
+        // States which came without touches keep their triggers
+        assert_eq!(triggers.transform(state, now), state);
+
+        cell.put(Some(TouchForce {
+            lforce: None,
+            rforce: Some(1.0),
+        }));
+        let transformed = triggers.transform(state, now);
+        assert_eq!(
+            (transformed.left_trigger, transformed.right_trigger),
+            (10, 255)
+        );
+
+        cell.put(None);
+        assert_eq!(triggers.transform(state, now), state);
+    }
+}
@@ -0,0 +1,247 @@
+//! Per-pad changes to the states clients send, applied one after the other right before each
+//! state is sent to the bus.
+//!
+//! Every pad has a [Pipeline] of [Transformer]s, which clients can add the built-in ones to and
+//! rearrange at runtime. Anything else implementing [Transformer] can be put in one too, by a
+//! fork or by a binary of your own.
+
+use std::{fmt, time::Instant};
+
+use vigem_client_c::X360State;
+
+/// Something which changes a pad's states on their way to the bus
+pub trait Transformer {
+    /// Change a state, which is being sent at `now`. Every call a transformer gets is at least
+    /// as late as the one before, so transformers keeping track of time, e.g. to pulse a
+    /// button, can rely on it never going backwards.
+    fn transform(&mut self, state: X360State, now: Instant) -> X360State;
+
+    /// When the transformer would make something else of the last state it was given, e.g.
+    /// partway through ramping a trigger, so that the pad sends it through again then even if
+    /// the client sends nothing new. Most transformers only change states as they come, so
+    /// they're never due.
+    fn next_due(&self, now: Instant) -> Option<Instant> {
+        let _ = now;
+        None
+    }
+}
+
+impl<F> Transformer for F
+where
+    F: FnMut(X360State, Instant) -> X360State,
+{
+    fn transform(&mut self, state: X360State, now: Instant) -> X360State {
+        self(state, now)
+    }
+}
+
+/// The transformers a pad's states go through, in order
+#[derive(Default)]
+pub struct Pipeline {
+    transformers: Vec<Box<dyn Transformer + Send>>,
+
+    /// The latest time the transformers were given, which they're never given an earlier one than
+    latest: Option<Instant>,
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("len", &self.transformers.len())
+            .field("latest", &self.latest)
+            .finish()
+    }
+}
+
+impl Pipeline {
+    /// How many transformers there are
+    pub fn len(&self) -> usize {
+        self.transformers.len()
+    }
+
+    /// Whether there are no transformers, in which case states go through unchanged
+    pub fn is_empty(&self) -> bool {
+        self.transformers.is_empty()
+    }
+
+    /// Put a transformer at the given position, or last if the position is past the end
+    pub fn insert(&mut self, index: usize, transformer: Box<dyn Transformer + Send>) {
+        let index = index.min(self.transformers.len());
+        self.transformers.insert(index, transformer);
+    }
+
+    /// Take out the transformer at the given position, if there's one
+    pub fn remove(&mut self, index: usize) -> Option<Box<dyn Transformer + Send>> {
+        if index < self.transformers.len() {
+            Some(self.transformers.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// Move the transformer at `from` to `to`, shifting those in between. Returns whether both
+    /// positions were in the pipeline, as nothing is moved otherwise.
+    pub fn move_to(&mut self, from: usize, to: usize) -> bool {
+        if from >= self.transformers.len() || to >= self.transformers.len() {
+            return false;
+        }
+        let transformer = self.transformers.remove(from);
+        self.transformers.insert(to, transformer);
+        true
+    }
+
+    /// Take out every transformer
+    pub fn clear(&mut self) {
+        self.transformers.clear();
+    }
+
+    /// Put a state through every transformer in order. A `now` earlier than one given before is
+    /// taken to be that one, so that the transformers never see time going backwards.
+    pub fn apply(&mut self, state: X360State, now: Instant) -> X360State {
+        let now = self.latest.map_or(now, |latest| latest.max(now));
+        self.latest = Some(now);
+        self.transformers
+            .iter_mut()
+            .fold(state, |state, transformer| {
+                transformer.transform(state, now)
+            })
+    }
+
+    /// When the earliest transformer is due to make something else of the last state, if any is
+    pub fn next_due(&self, now: Instant) -> Option<Instant> {
+        self.transformers
+            .iter()
+            .filter_map(|transformer| transformer.next_due(now))
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use vigem_client_c::X360Buttons;
+
+    use super::*;
+
+    fn press(buttons: X360Buttons) -> Box<dyn Transformer + Send> {
+        Box::new(move |mut state: X360State, _| {
+            state.buttons.insert(buttons);
+            state
+        })
+    }
+
+    /// Swaps A and B, like a remap would
+    fn swap_a_b() -> Box<dyn Transformer + Send> {
+        Box::new(|mut state: X360State, _| {
+            let (a, b) = (
+                state.buttons.contains(X360Buttons::A),
+                state.buttons.contains(X360Buttons::B),
+            );
+            state.buttons.set(X360Buttons::A, b);
+            state.buttons.set(X360Buttons::B, a);
+            state
+        })
+    }
+
+    #[test]
+    fn test_order() {
+        let now = Instant::now();
+        let neutral = X360State::default();
+        let mut pipeline = Pipeline::default();
+        assert_eq!(pipeline.apply(neutral, now), neutral);
+
+        // Pressing A and then swapping it ends up pressing B, while the other way around it's A
+        pipeline.insert(0, press(X360Buttons::A));
+        pipeline.insert(1, swap_a_b());
+        assert_eq!(pipeline.apply(neutral, now).buttons, X360Buttons::B);
+        assert!(pipeline.move_to(1, 0));
+        assert_eq!(pipeline.apply(neutral, now).buttons, X360Buttons::A);
+
+        // Positions past the end append, but can't be moved from or to
+        pipeline.insert(usize::MAX, press(X360Buttons::Y));
+        assert_eq!(pipeline.len(), 3);
+        assert!(!pipeline.move_to(0, 3));
+        assert!(!pipeline.move_to(3, 0));
+        assert_eq!(
+            pipeline.apply(neutral, now).buttons,
+            X360Buttons::A | X360Buttons::Y
+        );
+
+        assert!(pipeline.remove(3).is_none());
+        assert!(pipeline.remove(0).is_some());
+        assert_eq!(
+            pipeline.apply(neutral, now).buttons,
+            X360Buttons::A | X360Buttons::Y
+        );
+        pipeline.clear();
+        assert!(pipeline.is_empty());
+        assert_eq!(pipeline.apply(neutral, now), neutral);
+    }
+
+    /// Due 10ms after every state it's given, like a transformer which keeps ramping something
+    struct Ramping(Option<Instant>);
+
+    impl Transformer for Ramping {
+        fn transform(&mut self, state: X360State, now: Instant) -> X360State {
+            self.0 = Some(now);
+            state
+        }
+
+        fn next_due(&self, _: Instant) -> Option<Instant> {
+            Some(self.0? + Duration::from_millis(10))
+        }
+    }
+
+    #[test]
+    fn test_next_due() {
+        let now = Instant::now();
+        let mut pipeline = Pipeline::default();
+        pipeline.insert(0, press(X360Buttons::A));
+        assert_eq!(pipeline.next_due(now), None);
+
+        // The earliest of the transformers which are due is
+        pipeline.insert(1, Box::new(Ramping(Some(now))));
+        pipeline.insert(2, Box::new(Ramping(None)));
+        assert_eq!(
+            pipeline.next_due(now),
+            Some(now + Duration::from_millis(10))
+        );
+        let later = now + Duration::from_millis(5);
+        let _ = pipeline.apply(X360State::default(), later);
+        assert_eq!(
+            pipeline.next_due(later),
+            Some(later + Duration::from_millis(10))
+        );
+    }
+
+    #[test]
+    fn test_monotonic() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut pipeline = Pipeline::default();
+        {
+            let seen = Arc::clone(&seen);
+            pipeline.insert(
+                0,
+                Box::new(move |state, now| {
+                    seen.lock().unwrap().push(now);
+                    state
+                }),
+            );
+        }
+
+        // Times which come in out of order don't go backwards for the transformers
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        for &ms in &[0, 10, 5, 20, 20, 15, 30] {
+            let _ = pipeline.apply(X360State::default(), at(ms));
+        }
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [at(0), at(10), at(10), at(20), at(20), at(20), at(30)]
+        );
+    }
+}
@@ -0,0 +1,243 @@
+//! Turbo buttons, which pulse on and off for as long as they're held instead of staying down
+
+use std::{
+    convert::TryFrom,
+    time::{Duration, Instant},
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use vigem_client_c::{X360Buttons, X360State};
+
+use crate::transform::Transformer;
+
+/// The fastest turbo buttons may pulse, as no game reads its inputs much faster than this
+const MAX_FREQUENCY: f32 = 30.0;
+
+/// Which buttons of a pad are turbo buttons, and how fast they pulse
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub(crate) struct TurboConfig {
+    pub(crate) buttons: X360Buttons,
+
+    /// How many times per second the buttons are pressed, 0 turning turbo off
+    pub(crate) frequency: f32,
+}
+
+impl TurboConfig {
+    /// Bring the frequency between 0 and [MAX_FREQUENCY], treating nonsense as 0
+    pub(crate) fn clamped(self) -> Self {
+        Self {
+            buttons: self.buttons,
+            frequency: if self.frequency.is_nan() {
+                0.0
+            } else {
+                self.frequency.clamp(0.0, MAX_FREQUENCY)
+            },
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        !self.buttons.is_empty() && self.frequency > 0.0
+    }
+
+    /// How long the buttons stay pressed, and then released, in each pulse
+    fn half_period(&self) -> Duration {
+        Duration::from_secs_f64(0.5 / f64::from(self.frequency))
+    }
+}
+
+/// Pulses a pad's turbo buttons, timing the pulses from when they started being held
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Turbo {
+    config: TurboConfig,
+
+    /// When the client started holding any turbo button, if it's holding one
+    held_since: Option<Instant>,
+}
+
+impl Turbo {
+    pub(crate) fn new(config: TurboConfig) -> Self {
+        Self {
+            config,
+            held_since: None,
+        }
+    }
+
+    /// Which buttons are turbo buttons, if turbo is on at all
+    pub(crate) fn buttons(&self) -> X360Buttons {
+        if self.config.is_enabled() {
+            self.config.buttons
+        } else {
+            X360Buttons::empty()
+        }
+    }
+
+    /// The state to send at `now` for a pad the client wants in the given state.
+    ///
+    /// Held turbo buttons are pressed for the first half of every pulse and released for the
+    /// second, starting with a press. Everything else is left as is.
+    pub(crate) fn apply(&mut self, mut state: X360State, now: Instant) -> X360State {
+        let held = state.buttons & self.buttons();
+        if held.is_empty() {
+            self.held_since = None;
+            return state;
+        }
+
+        let since = *self.held_since.get_or_insert(now);
+        if self.half_periods(since, now) % 2 == 1 {
+            state.buttons.remove(held);
+        }
+        state
+    }
+
+    /// Release the turbo buttons of the given state, e.g. when turbo is turned off mid-pulse
+    pub(crate) fn release(&self, mut state: X360State) -> X360State {
+        state.buttons.remove(self.buttons());
+        state
+    }
+
+    /// When the held turbo buttons are next pressed or released, if any are held
+    pub(crate) fn next_toggle(&self, now: Instant) -> Option<Instant> {
+        let since = self.held_since?;
+        let nanos = (self.half_periods(since, now) + 1) * self.config.half_period().as_nanos();
+        since.checked_add(Duration::from_nanos(u64::try_from(nanos).ok()?))
+    }
+
+    /// How many half pulses went by between `since` and `now`
+    fn half_periods(&self, since: Instant, now: Instant) -> u128 {
+        now.duration_since(since).as_nanos() / self.config.half_period().as_nanos().max(1)
+    }
+}
+
+impl Transformer for Turbo {
+    fn transform(&mut self, state: X360State, now: Instant) -> X360State {
+        self.apply(state, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transform::Pipeline;
+
+    use super::*;
+
+    fn turbo(frequency: f32) -> Turbo {
+        Turbo::new(TurboConfig {
+            buttons: X360Buttons::A | X360Buttons::X,
+            frequency,
+        })
+    }
+
+    fn buttons(buttons: X360Buttons) -> X360State {
+        X360State::builder().press(buttons).build()
+    }
+
+    #[test]
+    fn test_pulse() {
+        // 10Hz means 50ms pressed and 50ms released
+        let mut turbo = turbo(10.0);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let held = X360State::builder()
+            .press(X360Buttons::A | X360Buttons::B)
+            .left_stick(100, -100)
+            .right_trigger(200)
+            .build();
+        let released = X360State {
+            buttons: X360Buttons::B,
+            ..held
+        };
+
+        assert_eq!(turbo.apply(held, at(0)), held);
+        assert_eq!(turbo.next_toggle(at(0)), Some(at(50)));
+        assert_eq!(turbo.apply(held, at(49)), held);
+        assert_eq!(turbo.apply(held, at(50)), released);
+        assert_eq!(turbo.next_toggle(at(50)), Some(at(100)));
+        assert_eq!(turbo.apply(held, at(99)), released);
+        assert_eq!(turbo.apply(held, at(100)), held);
+        assert_eq!(turbo.apply(held, at(175)), released);
+    }
+
+    #[test]
+    fn test_pulse_restarts() {
+        let mut turbo = turbo(10.0);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert_eq!(
+            turbo.apply(buttons(X360Buttons::A), at(0)),
+            buttons(X360Buttons::A)
+        );
+        assert_eq!(
+            turbo.apply(buttons(X360Buttons::B), at(60)),
+            buttons(X360Buttons::B)
+        );
+        assert_eq!(turbo.next_toggle(at(60)), None);
+
+        // Pressing a turbo button again starts a new pulse with a press, whatever the time
+        assert_eq!(
+            turbo.apply(buttons(X360Buttons::X), at(70)),
+            buttons(X360Buttons::X)
+        );
+        assert_eq!(turbo.next_toggle(at(70)), Some(at(120)));
+    }
+
+    #[test]
+    fn test_in_pipeline() {
+        let mut pipeline = Pipeline::default();
+        pipeline.insert(0, Box::new(turbo(10.0)));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let held = buttons(X360Buttons::A);
+        let released = X360State::default();
+
+        assert_eq!(pipeline.apply(held, at(0)), held);
+        assert_eq!(pipeline.apply(held, at(60)), released);
+        // A state sent with an earlier time is pulsed as of the latest one, rather than pressing
+        // the button again in the middle of its release
+        assert_eq!(pipeline.apply(held, at(30)), released);
+        assert_eq!(pipeline.apply(held, at(100)), held);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let state = buttons(X360Buttons::A);
+        let now = Instant::now();
+        let later = now + Duration::from_secs(1);
+
+        let mut off = turbo(0.0);
+        assert_eq!(off.apply(state, now), state);
+        assert_eq!(off.apply(state, later), state);
+        assert_eq!(off.next_toggle(later), None);
+
+        let mut none = Turbo::new(TurboConfig {
+            buttons: X360Buttons::empty(),
+            frequency: 10.0,
+        });
+        assert_eq!(none.apply(state, later), state);
+    }
+
+    #[test]
+    fn test_release() {
+        let state = buttons(X360Buttons::A | X360Buttons::B);
+        assert_eq!(turbo(10.0).release(state), buttons(X360Buttons::B));
+        assert_eq!(turbo(0.0).release(state), state);
+    }
+
+    #[test]
+    fn test_clamped() {
+        let frequency = |frequency| {
+            TurboConfig {
+                frequency,
+                ..TurboConfig::default()
+            }
+            .clamped()
+            .frequency
+        };
+        assert_eq!(frequency(-1.0), 0.0);
+        assert_eq!(frequency(f32::NAN), 0.0);
+        assert_eq!(frequency(1000.0), MAX_FREQUENCY);
+        assert_eq!(frequency(12.5), 12.5);
+    }
+}
@@ -0,0 +1,104 @@
+//! Decoding the pad states clients send, which every state goes through on its way to the bus.
+//!
+//! Neither of these allocate, unlike decoding any other message, so that pads updating at
+//! 120Hz don't keep the allocator busy.
+
+use vigem_client_c::{WireError, X360State};
+
+/// How long the client's timestamp at the end of a binary message is
+pub const TIMESTAMP_SIZE: usize = 8;
+
+/// A state a client sent in a binary message
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    /// The index of the client's pad the state is for, 0 being the one it started with
+    pub pad: usize,
+
+    pub state: X360State,
+
+    /// When the client sent the state by its own clock, in milliseconds, if it says
+    pub sent: Option<f64>,
+}
+
+/// Decode a binary message, which is a state in the wire encoding for the first pad, or one
+/// prefixed by the index of the pad it's for. Either may be followed by when the client sent it
+/// by its own clock, in milliseconds as a little endian `f64`.
+pub fn decode_frame(data: &[u8]) -> Result<Frame, WireError> {
+    let (data, sent) = match data.len() {
+        len if len == X360State::WIRE_SIZE + TIMESTAMP_SIZE
+            || len == X360State::WIRE_SIZE + 1 + TIMESTAMP_SIZE =>
+        {
+            let (data, sent) = data.split_at(len - TIMESTAMP_SIZE);
+            let mut bytes = [0; TIMESTAMP_SIZE];
+            bytes.copy_from_slice(sent);
+            (data, Some(f64::from_le_bytes(bytes)))
+        }
+        _ => (data, None),
+    };
+    let (pad, state) = match data.split_first() {
+        Some((&pad, state)) if data.len() == X360State::WIRE_SIZE + 1 => (usize::from(pad), state),
+        _ => (0, data),
+    };
+    Ok(Frame {
+        pad,
+        state: X360State::from_bytes(state)?,
+        sent,
+    })
+}
+
+/// Decode a text message if it's a state for the first pad as sent before messages had types,
+/// which most text messages of the first protocol still are. Messages with a `type` are left
+/// alone whatever else they have, for the caller to decode as whichever message they are.
+pub fn decode_json_state(data: &str) -> Option<X360State> {
+    if data.contains(r#""type""#) {
+        return None;
+    }
+    serde_json::from_str(data).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use vigem_client_c::X360Buttons;
+
+    use super::*;
+
+    #[test]
+    fn test_decode_frame() {
+        let state = X360State::builder()
+            .press(X360Buttons::X)
+            .left_stick(-5, 7)
+            .build();
+        let sent = 98.25_f64;
+        let mut data = vec![3];
+        data.extend_from_slice(&state.to_bytes());
+        data.extend_from_slice(&sent.to_le_bytes());
+
+        let frame = |pad, sent| Frame { pad, state, sent };
+        assert_eq!(decode_frame(&data), Ok(frame(3, Some(sent))));
+        assert_eq!(
+            decode_frame(&data[..data.len() - TIMESTAMP_SIZE]),
+            Ok(frame(3, None))
+        );
+        assert_eq!(decode_frame(&data[1..]), Ok(frame(0, Some(sent))));
+        assert_eq!(
+            decode_frame(&data[2..]),
+            Err(WireError::InvalidLength(X360State::WIRE_SIZE as u32 + 7))
+        );
+    }
+
+    #[test]
+    fn test_decode_json_state() {
+        let state = X360State::builder()
+            .press(X360Buttons::A)
+            .right_trigger(40)
+            .build();
+        let json = serde_json::to_string(&state).unwrap();
+        assert_eq!(decode_json_state(&json), Some(state));
+
+        // Anything else is for the caller to make sense of
+        assert_eq!(decode_json_state(r#"{"buttons":1}"#), None);
+        assert_eq!(decode_json_state("{"), None);
+        let typed = json.replacen('{', r#"{"type":"attach","#, 1);
+        assert_eq!(decode_json_state(&typed), None);
+    }
+}
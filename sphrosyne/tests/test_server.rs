@@ -0,0 +1,370 @@
+//! The server embedded like a launcher would, driving a pad on the in-memory bus end to end.
+//! Run with `cargo test -p sphrosyne --no-default-features --features mock`, adding the
+//! async-server feature to run every test against the async server too.
+#![cfg(feature = "mock")]
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::mpsc::channel,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use sphrosyne::{PadEvent, PadType, SphrosyneServer};
+use tungstenite::{protocol::frame::coding::CloseCode, stream::MaybeTlsStream, Message, WebSocket};
+use vigem_client_c::{X360Buttons, X360State};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Read messages until the ready one, which comes after everything a client is told on connecting
+fn read_until_ready(socket: &mut WebSocket<MaybeTlsStream<TcpStream>>) -> Vec<serde_json::Value> {
+    let mut messages = Vec::new();
+    loop {
+        let message = match socket.read_message().unwrap() {
+            Message::Text(text) => serde_json::from_str::<serde_json::Value>(&text).unwrap(),
+            message => panic!("unexpected {:?}", message),
+        };
+        let ready = message["type"] == "ready";
+        messages.push(message);
+        if ready {
+            return messages;
+        }
+    }
+}
+
+fn embedded(asynchronous: bool) {
+    let (events_tx, events) = channel();
+    let server = SphrosyneServer::new()
+        .async_server(asynchronous)
+        .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .mdns(false)
+        .max_pads(1)
+        .token("secret")
+        .on_pad_event(move |event| {
+            let _ = events_tx.send(event);
+        })
+        .start()
+        .unwrap();
+    assert_ne!(server.port(), 0);
+    let url = |token| format!("ws://127.0.0.1:{}/websocket?token={}", server.port(), token);
+
+    // Only clients with the token get a pad
+    assert!(tungstenite::connect(url("wrong")).is_err());
+    let (mut socket, _) = tungstenite::connect(url("secret")).unwrap();
+    let event = events.recv_timeout(TIMEOUT).unwrap();
+    assert!(
+        matches!(
+            event,
+            PadEvent::Connected {
+                id: 0,
+                pad_type: PadType::X360,
+                ..
+            }
+        ),
+        "{:?}",
+        event
+    );
+
+    // The states it sends make it to the bus
+    let sent = || server.stats().unwrap().pads[0].sent;
+    let before = sent();
+    let state = X360State::builder().press(X360Buttons::A).build();
+    socket
+        .write_message(Message::Text(serde_json::to_string(&state).unwrap()))
+        .unwrap();
+    let started = Instant::now();
+    while sent() == before {
+        assert!(
+            started.elapsed() < TIMEOUT,
+            "the state never reached the bus"
+        );
+        sleep(Duration::from_millis(10));
+    }
+
+    // Letting go of the pad is noticed too
+    socket.close(None).unwrap();
+    while socket.read_message().is_ok() {}
+    assert_eq!(
+        events.recv_timeout(TIMEOUT).unwrap(),
+        PadEvent::Disconnected { id: 0 }
+    );
+
+    server.shutdown().unwrap();
+}
+
+fn hello(asynchronous: bool) {
+    let server = SphrosyneServer::new()
+        .async_server(asynchronous)
+        .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .mdns(false)
+        .name("Gaming PC")
+        .token("secret")
+        .start()
+        .unwrap();
+
+    // Clients learn which machine they're on before anything else, and get their pad after
+    let url = format!("ws://127.0.0.1:{}/websocket?token=secret", server.port());
+    let (mut socket, _) = tungstenite::connect(url).unwrap();
+    let messages = read_until_ready(&mut socket);
+    // Devices which don't have an identifier yet are given one
+    let device = messages[0]["device"].as_str().unwrap().to_string();
+    assert_eq!(
+        messages[0],
+        serde_json::json!({
+            "type": "hello",
+            "server": "Gaming PC",
+            "version": env!("CARGO_PKG_VERSION"),
+            "device": device,
+        })
+    );
+    let reclaim = &messages[1]["reclaim"];
+    assert!(reclaim.is_string(), "{:?}", messages);
+    assert_eq!(&messages.last().unwrap()["session"], reclaim);
+
+    // Apps listing the servers can find out the same without a token
+    let mut stream = TcpStream::connect(("127.0.0.1", server.port())).unwrap();
+    stream
+        .write_all(b"GET /whoami HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    let _ = stream.read_to_string(&mut response).unwrap();
+    let body = response.split("\r\n\r\n").nth(1).unwrap();
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(body).unwrap(),
+        serde_json::json!({
+            "name": "Gaming PC",
+            "version": env!("CARGO_PKG_VERSION"),
+            "active_pads": 1,
+            "token_required": true,
+        })
+    );
+
+    socket.close(None).unwrap();
+    while socket.read_message().is_ok() {}
+    server.shutdown().unwrap();
+}
+
+fn server_full(asynchronous: bool) {
+    let server = SphrosyneServer::new()
+        .async_server(asynchronous)
+        .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .mdns(false)
+        .max_pads(1)
+        .token("secret")
+        .start()
+        .unwrap();
+    let url = format!("ws://127.0.0.1:{}/websocket?token=secret", server.port());
+    let (mut first, _) = tungstenite::connect(&url).unwrap();
+    let _ = read_until_ready(&mut first);
+
+    // Clients which are too many still connect, only to be told why they don't get a pad
+    let (mut second, _) = tungstenite::connect(&url).unwrap();
+    match second.read_message().unwrap() {
+        Message::Close(Some(frame)) => {
+            assert_eq!(frame.code, CloseCode::Again);
+            assert_eq!(frame.reason, "server_full");
+        }
+        message => panic!("unexpected {:?}", message),
+    }
+
+    first.close(None).unwrap();
+    while first.read_message().is_ok() {}
+    server.shutdown().unwrap();
+}
+
+/// Send a request over a connection of its own, returning the response's status code
+fn status(port: u16, request: &str) -> u16 {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    let _ = stream.read_to_string(&mut response).unwrap();
+    response.split(' ').nth(1).unwrap().parse().unwrap()
+}
+
+fn routes(asynchronous: bool) {
+    let server = SphrosyneServer::new()
+        .async_server(asynchronous)
+        .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .mdns(false)
+        .token("secret")
+        .start()
+        .unwrap();
+    let port = server.port();
+    let get = |path: &str| {
+        status(
+            port,
+            &format!(
+                "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                path
+            ),
+        )
+    };
+
+    assert_eq!(get("/"), 200);
+    assert_eq!(get("/controller"), 403);
+    assert_eq!(get("/controller?token=secret"), 200);
+    assert_eq!(get("/controller?token=secret&layout=nope"), 404);
+    assert_eq!(get("/nope"), 404);
+    // The protocol is no secret, as clients of other people's making are written from it
+    assert_eq!(get("/protocol"), 200);
+    assert_eq!(get("/admin/pads"), 200);
+    // Kicking takes a POST, so that following a link doesn't kick anybody
+    assert_eq!(get("/admin/pads/0/kick"), 405);
+    assert_eq!(
+        status(
+            port,
+            "POST /admin/pads/0/kick HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+        ),
+        404
+    );
+    assert_eq!(
+        status(
+            port,
+            "POST /admin/pads/0/test-input HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+        ),
+        404
+    );
+
+    server.shutdown().unwrap();
+}
+
+fn layouts(asynchronous: bool) {
+    let dir = std::env::temp_dir().join(format!(
+        "sphrosyne-test-layouts-{}-{}",
+        std::process::id(),
+        asynchronous
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    let server = SphrosyneServer::new()
+        .async_server(asynchronous)
+        .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .mdns(false)
+        .token("secret")
+        .layouts(&dir)
+        .start()
+        .unwrap();
+    let port = server.port();
+    let request = |method: &str, path: &str, body: &str| {
+        status(
+            port,
+            &format!(
+                "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                method,
+                path,
+                body.len(),
+                body
+            ),
+        )
+    };
+    let control = |binding: &str, x: f32| {
+        format!(
+            r#"{{"kind":"button","binding":"{}","x":{},"y":0.5,"width":0.1,"height":0.1}}"#,
+            binding, x
+        )
+    };
+    let layout = format!(
+        r#"{{"controls":[{},{}]}}"#,
+        control("A", 0.1),
+        control("B", 0.3)
+    );
+
+    assert_eq!(request("GET", "/layouts/mine?token=secret", ""), 404);
+    assert_eq!(request("PUT", "/layouts/mine", &layout), 403);
+    assert_eq!(request("PUT", "/layouts/mine?token=secret", &layout), 200);
+    assert_eq!(request("GET", "/layouts/mine?token=secret", ""), 200);
+    assert_eq!(request("GET", "/layouts/mine", ""), 403);
+    assert_eq!(
+        request("GET", "/controller?token=secret&layout=mine", ""),
+        200
+    );
+    assert_eq!(request("GET", "/layouts/custom.js", ""), 200);
+    assert_eq!(request("POST", "/layouts/mine?token=secret", &layout), 405);
+
+    // Layouts which couldn't be drawn or would reach outside the directory aren't saved
+    let duplicate = format!(
+        r#"{{"controls":[{},{}]}}"#,
+        control("A", 0.1),
+        control("A", 0.3)
+    );
+    assert_eq!(
+        request("PUT", "/layouts/mine?token=secret", &duplicate),
+        422
+    );
+    let outside = format!(r#"{{"controls":[{}]}}"#, control("A", 0.95));
+    assert_eq!(request("PUT", "/layouts/mine?token=secret", &outside), 422);
+    assert_eq!(request("PUT", "/layouts/mine?token=secret", "{"), 400);
+    assert_eq!(
+        request("PUT", "/layouts/../../evil?token=secret", &layout),
+        400
+    );
+    assert_eq!(
+        request("PUT", "/layouts/standard?token=secret", &layout),
+        409
+    );
+    let large = " ".repeat(70 * 1024) + &layout;
+    assert_eq!(request("PUT", "/layouts/large?token=secret", &large), 413);
+    assert_eq!(
+        std::fs::read_dir(&dir).unwrap().count(),
+        1,
+        "only mine.json should be there"
+    );
+
+    server.shutdown().unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_embedded() {
+    embedded(false);
+}
+
+#[test]
+fn test_hello() {
+    hello(false);
+}
+
+#[test]
+fn test_server_full() {
+    server_full(false);
+}
+
+#[test]
+fn test_routes() {
+    routes(false);
+}
+
+#[test]
+fn test_layouts() {
+    layouts(false);
+}
+
+#[cfg(feature = "async-server")]
+#[test]
+fn test_embedded_async() {
+    embedded(true);
+}
+
+#[cfg(feature = "async-server")]
+#[test]
+fn test_hello_async() {
+    hello(true);
+}
+
+#[cfg(feature = "async-server")]
+#[test]
+fn test_server_full_async() {
+    server_full(true);
+}
+
+#[cfg(feature = "async-server")]
+#[test]
+fn test_routes_async() {
+    routes(true);
+}
+
+#[cfg(feature = "async-server")]
+#[test]
+fn test_layouts_async() {
+    layouts(true);
+}
@@ -0,0 +1,89 @@
+//! Clients connecting and leaving over and over, checking that the server doesn't keep more
+//! handles or memory for them, e.g. for websocket threads nobody joins. It takes a while, so
+//! it's only run when asked for, with
+//! `cargo test -p sphrosyne --no-default-features --features mock --test test_soak -- --ignored`.
+#![cfg(feature = "mock")]
+
+use std::{env, net::SocketAddr, sync::mpsc::channel, time::Duration};
+
+use sphrosyne::{PadEvent, SphrosyneServer};
+use tungstenite::Message;
+use vigem_client_c::{
+    soak::{soak, Usage},
+    X360Buttons, X360State,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many connections to go through before measuring, unless `SOAK_ITERATIONS` says how many
+/// to measure
+const WARM_UP: usize = 50;
+const ITERATIONS: usize = 1000;
+
+/// How much the process may grow over all the connections
+const BOUND: Usage = Usage {
+    handles: 16,
+    private_bytes: 4 << 20,
+};
+
+fn connect_cycles(asynchronous: bool) {
+    let (events_tx, events) = channel();
+    let server = SphrosyneServer::new()
+        .async_server(asynchronous)
+        .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .mdns(false)
+        .token("secret")
+        .on_pad_event(move |event| {
+            let _ = events_tx.send(event);
+        })
+        .start()
+        .unwrap();
+    let url = format!("ws://127.0.0.1:{}/websocket?token=secret", server.port());
+    let iterations = env::var("SOAK_ITERATIONS")
+        .ok()
+        .and_then(|iterations| iterations.parse().ok())
+        .unwrap_or(ITERATIONS);
+
+    let growth = soak(WARM_UP, iterations, |_| {
+        let (mut socket, _) = tungstenite::connect(&url).unwrap();
+        assert!(matches!(
+            events.recv_timeout(TIMEOUT).unwrap(),
+            PadEvent::Connected { .. }
+        ));
+        let state = X360State::builder().press(X360Buttons::A).build();
+        socket
+            .write_message(Message::Text(serde_json::to_string(&state).unwrap()))
+            .unwrap();
+        // Letting go of the pad for good, rather than leaving it for the client to come back to
+        socket
+            .write_message(Message::Text("disconnect".to_string()))
+            .unwrap();
+        socket.close(None).unwrap();
+        while socket.read_message().is_ok() {}
+        assert!(matches!(
+            events.recv_timeout(TIMEOUT).unwrap(),
+            PadEvent::Disconnected { .. }
+        ));
+    })
+    .unwrap();
+    server.shutdown().unwrap();
+    assert!(
+        !growth.exceeds(&BOUND),
+        "grew by {:?} over {} connections",
+        growth,
+        iterations
+    );
+}
+
+#[test]
+#[ignore]
+fn test_connect_cycles() {
+    connect_cycles(false);
+}
+
+#[cfg(feature = "async-server")]
+#[test]
+#[ignore]
+fn test_connect_cycles_async() {
+    connect_cycles(true);
+}
@@ -1,48 +1,60 @@
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 // spell-checker: disable
 
-// Taken from ViGEmClient.vcxproj <AdditionalDependencies> as well as msvcrtd
+// Taken from ViGEmClient.vcxproj <AdditionalDependencies>, the CRT being linked separately
 const LIBS: &[&str] = &[
     "setupapi", "kernel32", "user32", "gdi32", "winspool", "comdlg32", "advapi32", "shell32",
     "ole32", "oleaut32", "uuid", "odbc32", "odbccp32",
 ];
 
+const SOLUTION: &str = "src/ViGEmClient/ViGEmClient.sln";
+
+// The only platform we build ViGEmClient for, as ViGEmBus itself only runs on x64
+const PLATFORM: &str = "x64";
+
+// Where to find a prebuilt ViGEmClient.lib, skipping msbuild entirely
+const LIB_DIR_VAR: &str = "VIGEM_CLIENT_LIB_DIR";
+
 fn main() {
-    // Find the finder by using environment variables.. kinda ironic
-    let vswhere =
-        env::var("PROGRAMFILES(X86)").unwrap() + r"\Microsoft Visual Studio\Installer\vswhere.exe";
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/wrapper.h");
+    println!("cargo:rerun-if-changed={}", SOLUTION);
+    println!("cargo:rerun-if-changed=src/ViGEmClient/include");
+    println!("cargo:rerun-if-changed=src/ViGEmClient/src");
+    println!("cargo:rerun-if-env-changed={}", LIB_DIR_VAR);
 
-    // Find msbuild using vswhere
-    let msbuild = String::from_utf8(
-        Command::new(vswhere)
-            .args(&[
-                "-latest",
-                "-prerelease",
-                "-products",
-                "*",
-                "-requires",
-                "Microsoft.Component.MSBuild",
-                "-find",
-                r"MSBuild\**\Bin\MSBuild.exe",
-            ])
-            .output()
-            .expect("could not locate msbuild")
-            .stdout,
-    )
-    .unwrap();
+    let release = env::var("PROFILE").is_ok_and(|profile| profile == "release");
+    let crt_static = env::var("CARGO_CFG_TARGET_FEATURE")
+        .is_ok_and(|features| features.split(',').any(|f| f == "crt-static"));
 
-    // Build ViGemClient and check status
-    let status = Command::new(msbuild.trim())
-        .arg("src/ViGEmClient/ViGEmClient.sln")
-        .status()
-        .unwrap();
-    assert!(status.success());
+    let lib_dir = match env::var_os(LIB_DIR_VAR) {
+        Some(dir) => PathBuf::from(dir),
+        None => build(configuration(release, crt_static)),
+    };
+    if !lib_dir.join("ViGEmClient.lib").is_file() {
+        panic!(
+            "{} has no ViGEmClient.lib{}",
+            lib_dir.display(),
+            if env::var_os(LIB_DIR_VAR).is_some() {
+                format!(", check {}", LIB_DIR_VAR)
+            } else {
+                String::new()
+            }
+        );
+    }
 
-    // Link msvcrt
-    println!("cargo:rustc-link-lib=msvcrtd");
+    // ViGEmClient has to link against the same CRT as it was built with, which is the one
+    // matching our profile and whether the CRT is linked statically
+    let crt = match (crt_static, release) {
+        (false, false) => "msvcrtd",
+        (false, true) => "msvcrt",
+        (true, false) => "libcmtd",
+        (true, true) => "libcmt",
+    };
+    println!("cargo:rustc-link-lib={}", crt);
 
     // Tell cargo to link all necessary windows libraries
     for lib in LIBS {
@@ -50,10 +62,7 @@ fn main() {
     }
 
     // Tell cargo to link ViGemClient and where to find it
-    println!(
-        "cargo:rustc-link-search={}/src/ViGemClient/lib/debug/x64",
-        env!("CARGO_MANIFEST_DIR")
-    );
+    println!("cargo:rustc-link-search={}", lib_dir.display());
     println!("cargo:rustc-link-lib=static=ViGEmClient");
 
     // Generate bindings for ViGemClient
@@ -72,3 +81,86 @@ fn main() {
         .write_to_file(out_dir.join("bindings.rs"))
         .expect("Couldn't write bindings!");
 }
+
+/// The solution configuration to build, which is the one linking the CRT statically when it's
+/// asked for and the solution has it, falling back to the regular one otherwise
+fn configuration(release: bool, crt_static: bool) -> String {
+    let configuration = if release { "Release" } else { "Debug" };
+    if !crt_static {
+        return configuration.to_owned();
+    }
+
+    let variant = format!("{} (static)", configuration);
+    let solution = std::fs::read_to_string(SOLUTION).unwrap_or_default();
+    if solution.contains(&format!("{}|{}", variant, PLATFORM)) {
+        variant
+    } else {
+        println!(
+            "cargo:warning=ViGEmClient has no {} configuration, linking the {} one against the static CRT",
+            variant, configuration
+        );
+        configuration.to_owned()
+    }
+}
+
+/// Build the given configuration of ViGEmClient with msbuild, returning where its lib ends up
+fn build(configuration: String) -> PathBuf {
+    if !Path::new(SOLUTION).is_file() {
+        panic!(
+            "{} is missing, either run `git submodule update --init` or point {} to a prebuilt ViGEmClient.lib",
+            SOLUTION, LIB_DIR_VAR
+        );
+    }
+
+    // Find the finder by using environment variables.. kinda ironic
+    let program_files = env::var("PROGRAMFILES(X86)")
+        .expect("PROGRAMFILES(X86) isn't set, which is where msbuild is looked for");
+    let vswhere = program_files + r"\Microsoft Visual Studio\Installer\vswhere.exe";
+
+    // Find msbuild using vswhere
+    let msbuild = String::from_utf8(
+        Command::new(vswhere)
+            .args([
+                "-latest",
+                "-prerelease",
+                "-products",
+                "*",
+                "-requires",
+                "Microsoft.Component.MSBuild",
+                "-find",
+                r"MSBuild\**\Bin\MSBuild.exe",
+            ])
+            .output()
+            .expect("could not locate msbuild")
+            .stdout,
+    )
+    .unwrap();
+    let msbuild = msbuild.lines().next().map(str::trim).unwrap_or_default();
+    if msbuild.is_empty() {
+        panic!(
+            "msbuild isn't installed, or point {} to a prebuilt ViGEmClient.lib",
+            LIB_DIR_VAR
+        );
+    }
+
+    // Where the lib lands depends on the project's OutDir, which differs between configurations
+    // in ways their names don't tell, so it's put where we can find it instead
+    let lib_dir = PathBuf::from(env::var("OUT_DIR").unwrap()).join("ViGEmClient");
+
+    // Build ViGemClient and check status
+    let status = Command::new(msbuild)
+        .arg(SOLUTION)
+        .arg(format!("/p:Configuration={}", configuration))
+        .arg(format!("/p:Platform={}", PLATFORM))
+        .arg(format!("/p:OutDir={}\\", lib_dir.display()))
+        .status()
+        .expect("could not run msbuild");
+    if !status.success() {
+        panic!(
+            "msbuild failed to build ViGEmClient's {}|{} configuration ({})",
+            configuration, PLATFORM, status
+        );
+    }
+
+    lib_dir
+}
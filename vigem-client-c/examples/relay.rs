@@ -0,0 +1,34 @@
+//! Mirrors the physical controller in XInput's first slot to a virtual xbox 360 pad, polling it
+//! 250 times per second, until it's unplugged.
+//!
+//! Run with `cargo run -p vigem-client-c --example relay --features xinput`.
+
+#[cfg(windows)]
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    use std::{sync::Arc, thread, time::Duration};
+
+    use vigem_client_c::{xinput::poll_xinput, Client};
+
+    const INTERVAL: Duration = Duration::from_millis(4);
+
+    // Read the physical pad before the virtual one is plugged in, which might take its slot
+    let mut last = poll_xinput(0)?;
+    let client = Arc::new(Client::new()?);
+    let target = client.connect_x360_pad_owned()?;
+    target.update(last.state)?;
+    println!("Relaying the controller in slot 0, unplug it to stop");
+
+    loop {
+        thread::sleep(INTERVAL);
+        let poll = poll_xinput(0)?;
+        if poll.packet_number != last.packet_number {
+            target.update(poll.state)?;
+            last = poll;
+        }
+    }
+}
+
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("XInput is only available on Windows");
+}
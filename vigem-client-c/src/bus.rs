@@ -0,0 +1,98 @@
+//! Finding the buses through SetupAPI, which ViGEmClient does too but doesn't let us in on.
+
+use std::{io, mem, mem::size_of, ptr, slice};
+
+use windows_sys::{
+    core::GUID,
+    Win32::{
+        Devices::DeviceAndDriverInstallation::{
+            SetupDiDestroyDeviceInfoList, SetupDiEnumDeviceInterfaces, SetupDiGetClassDevsW,
+            SetupDiGetDeviceInterfaceDetailW, DIGCF_DEVICEINTERFACE, DIGCF_PRESENT, HDEVINFO,
+            SP_DEVICE_INTERFACE_DATA, SP_DEVICE_INTERFACE_DETAIL_DATA_W,
+        },
+        Foundation::{
+            GetLastError, ERROR_INSUFFICIENT_BUFFER, ERROR_NO_MORE_ITEMS, INVALID_HANDLE_VALUE,
+        },
+    },
+};
+
+/// The interface ViGEmBus registers its bus device under, `GUID_DEVINTERFACE_BUSENUM_VIGEM`
+const BUS_INTERFACE: GUID = GUID::from_u128(0x96E42B22_F5E9_42F8_B043_ED0F932F014F);
+
+/// Get the device paths of the buses which are present, in the order ViGEmClient tries them in
+pub(crate) fn paths() -> io::Result<Vec<String>> {
+    let flags = DIGCF_PRESENT | DIGCF_DEVICEINTERFACE;
+    let devices = unsafe { SetupDiGetClassDevsW(&BUS_INTERFACE, ptr::null(), 0, flags) };
+    if devices == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+    let paths = interface_paths(devices);
+    let _ = unsafe { SetupDiDestroyDeviceInfoList(devices) };
+    paths
+}
+
+fn interface_paths(devices: HDEVINFO) -> io::Result<Vec<String>> {
+    let mut paths = Vec::new();
+    let mut index = 0;
+    loop {
+        let mut interface: SP_DEVICE_INTERFACE_DATA = unsafe { mem::zeroed() };
+        interface.cbSize = size_of::<SP_DEVICE_INTERFACE_DATA>() as u32;
+        let found = unsafe {
+            SetupDiEnumDeviceInterfaces(devices, ptr::null(), &BUS_INTERFACE, index, &mut interface)
+        };
+        if found == 0 {
+            return match unsafe { GetLastError() } {
+                ERROR_NO_MORE_ITEMS => Ok(paths),
+                code => Err(io::Error::from_raw_os_error(code as i32)),
+            };
+        }
+        paths.push(interface_path(devices, &interface)?);
+        index += 1;
+    }
+}
+
+fn interface_path(devices: HDEVINFO, interface: &SP_DEVICE_INTERFACE_DATA) -> io::Result<String> {
+    // Ask how big the detail is first, which "fails" with the size it needs
+    let mut size = 0;
+    let _ = unsafe {
+        SetupDiGetDeviceInterfaceDetailW(
+            devices,
+            interface,
+            ptr::null_mut(),
+            0,
+            &mut size,
+            ptr::null_mut(),
+        )
+    };
+    match unsafe { GetLastError() } {
+        ERROR_INSUFFICIENT_BUFFER => {}
+        code => return Err(io::Error::from_raw_os_error(code as i32)),
+    }
+
+    // The detail is a u32 followed by the path, so a buffer of u32s is aligned for it
+    let mut buffer = vec![0_u32; (size as usize).div_ceil(4)];
+    let detail = buffer
+        .as_mut_ptr()
+        .cast::<SP_DEVICE_INTERFACE_DETAIL_DATA_W>();
+    unsafe { (*detail).cbSize = size_of::<SP_DEVICE_INTERFACE_DETAIL_DATA_W>() as u32 };
+    let got = unsafe {
+        SetupDiGetDeviceInterfaceDetailW(
+            devices,
+            interface,
+            detail,
+            size,
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    };
+    if got == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: The path is NUL terminated within the size we were told about
+    let path = unsafe { ptr::addr_of!((*detail).DevicePath).cast::<u16>() };
+    let len = (0..).take_while(|&i| unsafe { *path.add(i) } != 0).count();
+    Ok(String::from_utf16_lossy(unsafe {
+        slice::from_raw_parts(path, len)
+    }))
+}
@@ -4,54 +4,454 @@
 // nothing more than a pointer to a device handle. The same can not be said for
 // `Target`, as that struct actually contains data that is manipulated by the different
 // functions.
+//
+// Every ViGEmClient function that talks to the bus issues its own DeviceIoControl with an
+// OVERLAPPED structure and event created for that call alone, so the client handle may be
+// used from any number of threads at once, which makes `Client` both `Send` and `Sync`.
+// A target's data on the other hand is written without any synchronization by the
+// functions adding, removing and configuring it, all of which take `&mut self`. Everything
+// taking `&self` only reads it: the update and user index functions read the target's
+// serial number and state before issuing their own DeviceIoControl, just like the client
+// functions above, and the getters read a single field. Concurrent reads being fine, a
+// `Target` is both `Send` and `Sync`. The notification thread ViGEmClient spawns for a
+// target only ever reads the callback and user data pointers, which are only changed while
+// registering or unregistering a notification, both of which require exclusive access to
+// the `Target`.
+// Since the target owns its notification callback and may drop it on whichever thread it
+// ends up on, callbacks have to be `Send` too.
+//
+// The handler behind `Target::events` is the one exception, as its `EventReceiver` may
+// unregister it without access to the target when it's dropped first. Both share an
+// `EventLink` whose lock is held while unregistering, and the target takes it too before
+// unregistering, registering anew or freeing the handler, so that the handler is unregistered
+// exactly once and only ever freed by the target, after it was unregistered.
+//
+// Asynchronous updates hand ViGEmClient a leaked box holding the completion callback, which
+// it passes back to the completion routine exactly once, and only if the update was
+// submitted. That routine runs on one of ViGEmClient's threads and reclaims the box, so
+// completion callbacks have to be `Send` and can't borrow anything. The target counts the
+// updates still in flight and waits for them to complete before it is freed, since their
+// completion routines are given the target's pointer.
 
 use std::{
     ffi::c_void,
     marker::PhantomData,
-    mem::forget,
+    ops::Deref,
     panic::{catch_unwind, RefUnwindSafe},
     ptr::NonNull,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex, PoisonError, Weak,
+    },
+    thread::sleep,
+    time::{Duration, Instant},
 };
-
-use vigem_client_c_sys as ffi;
+#[cfg(feature = "async-update")]
+use std::{panic::UnwindSafe, sync::Condvar};
 
 use crate::{
     error::{check, Error, Result},
-    gamepad_state::X360State,
+    ffi,
+    gamepad_state::{DS4State, DS4StateEx, X360State, XusbReport},
 };
 
 /// A connection to the bus
 #[derive(Debug)]
 pub struct Client {
     vigem: NonNull<ffi::_VIGEM_CLIENT_T>,
+    connected: AtomicBool,
 }
 
 /// A marker type representing a target being an xbox 360 controller
 #[derive(Debug, Clone, Copy)]
 pub enum X360 {}
 
+/// A marker type representing a target being a dualshock 4 controller
+#[derive(Debug, Clone, Copy)]
+pub enum DS4 {}
+
+/// The connection handed out by [Client::shared], for as long as anybody holds on to it
+static SHARED: Mutex<Option<Weak<Client>>> = Mutex::new(None);
+
 impl Client {
     /// Allocate a new client, connect it and return it.
     pub fn new() -> Result<Self> {
         let vigem = NonNull::new(unsafe { ffi::vigem_alloc() }).ok_or(Error::NoVigemAlloc)?;
-        check(unsafe { ffi::vigem_connect(vigem.as_ptr()) })?;
-        Ok(Self { vigem })
+        if let Err(error) = check(unsafe { ffi::vigem_connect(vigem.as_ptr()) }) {
+            unsafe { ffi::vigem_free(vigem.as_ptr()) };
+            return Err(error);
+        }
+        Ok(Self {
+            vigem,
+            connected: AtomicBool::new(true),
+        })
     }
 
-    /// Create and add a new xbox 360 gamepad target
+    /// Try to allocate and connect a new client up to `attempts` times, waiting `delay`
+    /// between each attempt.
+    ///
+    /// Only errors for which [Error::is_driver_missing] holds are retried, as those are the
+    /// errors returned when ViGEmBus is not installed or momentarily unavailable. Any other
+    /// error is returned immediately, as is the last error once all attempts are exhausted.
+    pub fn new_with_retry(attempts: u32, delay: Duration) -> Result<Self> {
+        let mut attempt = 1;
+        loop {
+            match Self::new() {
+                Err(error) if error.is_driver_missing() && attempt < attempts => {
+                    attempt += 1;
+                    sleep(delay);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Get the connection to the bus shared by the whole process, connecting it if needed.
+    ///
+    /// Some versions of ViGEmBus refuse a second connection from the same process with
+    /// [Error::BusAlreadyConnected], so code which doesn't need a connection of its own should
+    /// use this rather than [new](Self::new). The shared connection is closed once the last
+    /// [Arc] to it is dropped, after which this connects anew. The same happens if the shared
+    /// connection lost the bus.
+    pub fn shared() -> Result<Arc<Self>> {
+        let mut shared = SHARED.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(client) = shared.as_ref().and_then(Weak::upgrade) {
+            if client.is_connected() {
+                return Ok(client);
+            }
+        }
+
+        let client = Arc::new(Self::new()?);
+        *shared = Some(Arc::downgrade(&client));
+        Ok(client)
+    }
+
+    /// Get the device paths of the buses which are present, in the order [new](Self::new) tries
+    /// to connect to them.
+    #[cfg(all(feature = "enumerate", windows))]
+    pub fn enumerate_buses() -> std::io::Result<Vec<String>> {
+        crate::bus::paths()
+    }
+
+    /// Allocate a new client and connect it to the bus with the given device path, as returned
+    /// by [enumerate_buses](Self::enumerate_buses).
+    ///
+    /// Fails with [Error::BusNotFound] if there's no bus at `path` and with
+    /// [Error::BusAccessFailed] if we may not open it.
+    ///
+    /// ViGEmClient can only connect to the first bus which it manages to open, and doesn't take
+    /// a path, so any other bus fails with [Error::NotSupported] for now. This at least makes
+    /// sure the client is connected to the bus that was asked for rather than another one.
+    #[cfg(all(feature = "enumerate", windows))]
+    pub fn new_with_path(path: &str) -> Result<Self> {
+        use std::{fs::OpenOptions, io::ErrorKind};
+
+        // Opening it the way ViGEmClient does tells apart buses that aren't there from ones
+        // that are off limits, which connecting doesn't
+        let _ = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|error| match error.kind() {
+                ErrorKind::PermissionDenied => Error::BusAccessFailed,
+                _ => Error::BusNotFound,
+            })?;
+
+        let paths = Self::enumerate_buses().map_err(|_| Error::BusNotFound)?;
+        match paths.iter().position(|bus| bus.eq_ignore_ascii_case(path)) {
+            Some(0) => Self::new(),
+            Some(_) => Err(Error::NotSupported),
+            None => Err(Error::BusNotFound),
+        }
+    }
+
+    /// Allocate a new client connected to an in-memory bus of its own, which is what
+    /// [new](Self::new) does too with the `mock` feature. Its bus is reached through
+    /// [mock_bus](Self::mock_bus).
+    #[cfg(feature = "mock")]
+    pub fn new_mock() -> Result<Self> {
+        Self::new()
+    }
+
+    /// Get a handle to the in-memory bus this client is connected to
+    #[cfg(feature = "mock")]
+    pub fn mock_bus(&self) -> crate::mock::MockBus {
+        // SAFETY: The client is valid for as long as we are
+        unsafe { self.vigem.as_ref() }.bus.clone()
+    }
+
+    /// Check whether the bus is still believed to be reachable.
+    ///
+    /// ViGEmClient offers no way to actively probe the bus, so this reports whether any
+    /// operation on this client or its targets has failed with an error indicating that the
+    /// bus went away. Once that happens the client will not recover, and a new one has to be
+    /// created.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    /// Like [check], but takes note of errors which mean the bus is gone
+    fn check(&self, error: ffi::_VIGEM_ERRORS) -> Result<()> {
+        let result = check(error);
+        if let Err(Error::BusNotFound | Error::BusAccessFailed | Error::BusInvalidHandle) = result {
+            self.connected.store(false, Ordering::SeqCst);
+        }
+        result
+    }
+
+    /// Create and add a new xbox 360 gamepad target, with the ids of a wired xbox 360 controller
     pub fn connect_x360_pad(&self) -> Result<Target<'_, X360>> {
+        self.x360_pad().connect()
+    }
+
+    /// Create and add a new dualshock 4 gamepad target, with the ids of a dualshock 4
+    pub fn connect_ds4_pad(&self) -> Result<Target<'_, DS4>> {
+        self.ds4_pad().connect()
+    }
+
+    /// Configure a new xbox 360 gamepad target, to be added with [PadBuilder::connect]
+    pub fn x360_pad(&self) -> PadBuilder<'_, X360> {
+        PadBuilder::new(ClientRef::Borrowed(self))
+    }
+
+    /// Configure a new dualshock 4 gamepad target, to be added with [PadBuilder::connect]
+    pub fn ds4_pad(&self) -> PadBuilder<'_, DS4> {
+        PadBuilder::new(ClientRef::Borrowed(self))
+    }
+
+    /// Configure a new xbox 360 gamepad target which keeps the client alive by itself
+    pub fn x360_pad_owned(self: &Arc<Self>) -> PadBuilder<'static, X360> {
+        PadBuilder::new(ClientRef::Shared(Arc::clone(self)))
+    }
+
+    /// Configure a new dualshock 4 gamepad target which keeps the client alive by itself
+    pub fn ds4_pad_owned(self: &Arc<Self>) -> PadBuilder<'static, DS4> {
+        PadBuilder::new(ClientRef::Shared(Arc::clone(self)))
+    }
+
+    /// Create and add a new xbox 360 gamepad target which keeps the client alive by itself
+    pub fn connect_x360_pad_owned(self: &Arc<Self>) -> Result<OwnedTarget<X360>> {
+        self.x360_pad_owned().connect()
+    }
+
+    /// Create and add a new dualshock 4 gamepad target which keeps the client alive by itself
+    pub fn connect_ds4_pad_owned(self: &Arc<Self>) -> Result<OwnedTarget<DS4>> {
+        self.ds4_pad_owned().connect()
+    }
+}
+
+/// A controller a target can pass for by taking on its vendor and product ids, for games and
+/// anti-cheats which treat some controllers differently from others. Set one with
+/// [PadBuilder::preset].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Preset {
+    /// A wired xbox 360 controller, which is what xbox 360 targets are by default
+    MicrosoftX360Wired,
+    /// An xbox 360 controller through the wireless receiver for Windows
+    MicrosoftX360Wireless,
+    /// An original xbox one controller
+    MicrosoftXboxOne,
+    /// An xbox one S controller, plugged in
+    MicrosoftXboxOneS,
+    /// A Logitech F310 in XInput mode
+    LogitechF310,
+    /// A Logitech F510 in XInput mode
+    LogitechF510,
+    /// A Logitech F710 in XInput mode
+    LogitechF710,
+    /// The first dualshock 4, which is what dualshock 4 targets are by default
+    SonyDualShock4,
+    /// The second dualshock 4, with the light bar on the touchpad
+    SonyDualShock4V2,
+}
+
+/// Every preset's name, vendor id and product id, in the order of [Preset]'s variants
+const PRESETS: [(Preset, &str, u16, u16); 9] = [
+    (
+        Preset::MicrosoftX360Wired,
+        "microsoft_x360_wired",
+        0x045E,
+        0x028E,
+    ),
+    (
+        Preset::MicrosoftX360Wireless,
+        "microsoft_x360_wireless",
+        0x045E,
+        0x0719,
+    ),
+    (
+        Preset::MicrosoftXboxOne,
+        "microsoft_xbox_one",
+        0x045E,
+        0x02D1,
+    ),
+    (
+        Preset::MicrosoftXboxOneS,
+        "microsoft_xbox_one_s",
+        0x045E,
+        0x02EA,
+    ),
+    (Preset::LogitechF310, "logitech_f310", 0x046D, 0xC21D),
+    (Preset::LogitechF510, "logitech_f510", 0x046D, 0xC21E),
+    (Preset::LogitechF710, "logitech_f710", 0x046D, 0xC21F),
+    (Preset::SonyDualShock4, "sony_dual_shock4", 0x054C, 0x05C4),
+    (
+        Preset::SonyDualShock4V2,
+        "sony_dual_shock4_v2",
+        0x054C,
+        0x09CC,
+    ),
+];
+
+impl Preset {
+    /// Every preset there is
+    pub fn all() -> impl Iterator<Item = Self> {
+        PRESETS.iter().map(|&(preset, ..)| preset)
+    }
+
+    /// The preset with the given name, if there's one
+    pub fn find(name: &str) -> Option<Self> {
+        PRESETS
+            .iter()
+            .find(|&&(_, preset_name, ..)| preset_name == name)
+            .map(|&(preset, ..)| preset)
+    }
+
+    /// The preset's name, e.g. `logitech_f310`, which is what it's serialized as
+    pub const fn name(self) -> &'static str {
+        PRESETS[self as usize].1
+    }
+
+    /// The vendor id of the controller the preset passes for
+    pub const fn vendor_id(self) -> u16 {
+        PRESETS[self as usize].2
+    }
+
+    /// The product id of the controller the preset passes for
+    pub const fn product_id(self) -> u16 {
+        PRESETS[self as usize].3
+    }
+}
+
+/// A target which is yet to be added to the bus, created by [Client::x360_pad] or
+/// [Client::ds4_pad].
+///
+/// ViGEmBus only looks at a target's vendor and product ids when it's added, so this is where
+/// they have to be set for the OS to see them.
+///
+/// ```no_run
+/// # fn main() -> vigem_client_c::Result<()> {
+/// let client = vigem_client_c::Client::new()?;
+/// let pad = client.x360_pad().vendor_id(0x045E).product_id(0x02A1).connect()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct PadBuilder<'client, Type> {
+    client: ClientRef<'client>,
+    vendor_id: Option<u16>,
+    product_id: Option<u16>,
+    _marker: PhantomData<Type>,
+}
+
+impl<'client, Type> PadBuilder<'client, Type> {
+    fn new(client: ClientRef<'client>) -> Self {
+        Self {
+            client,
+            vendor_id: None,
+            product_id: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Give the target this vendor id instead of the default for its type
+    pub fn vendor_id(self, vendor_id: u16) -> Self {
+        Self {
+            vendor_id: Some(vendor_id),
+            ..self
+        }
+    }
+
+    /// Give the target this product id instead of the default for its type
+    pub fn product_id(self, product_id: u16) -> Self {
+        Self {
+            product_id: Some(product_id),
+            ..self
+        }
+    }
+
+    /// Give the target the vendor and product ids of the preset's controller
+    pub fn preset(self, preset: Preset) -> Self {
+        self.vendor_id(preset.vendor_id())
+            .product_id(preset.product_id())
+    }
+
+    /// Apply the ids to a freshly allocated target and add it to the bus
+    fn add(self, target: NonNull<ffi::_VIGEM_TARGET_T>) -> Result<Target<'client, Type>> {
+        unsafe {
+            if let Some(vendor_id) = self.vendor_id {
+                ffi::vigem_target_set_vid(target.as_ptr(), vendor_id);
+            }
+            if let Some(product_id) = self.product_id {
+                ffi::vigem_target_set_pid(target.as_ptr(), product_id);
+            }
+        }
+        add_target(self.client, target)
+    }
+}
+
+impl<'client> PadBuilder<'client, X360> {
+    /// Create the xbox 360 gamepad target and add it to the bus
+    pub fn connect(self) -> Result<Target<'client, X360>> {
         let target =
             NonNull::new(unsafe { ffi::vigem_target_x360_alloc() }).ok_or(Error::NoX360PadAlloc)?;
-        check(unsafe { ffi::vigem_target_add(self.vigem.as_ptr(), target.as_ptr()) })?;
-        Ok(Target {
-            client: self,
-            target,
-            has_notification: false,
-            _marker: PhantomData,
-        })
+        self.add(target)
     }
 }
 
+impl<'client> PadBuilder<'client, DS4> {
+    /// Create the dualshock 4 gamepad target and add it to the bus
+    pub fn connect(self) -> Result<Target<'client, DS4>> {
+        let target =
+            NonNull::new(unsafe { ffi::vigem_target_ds4_alloc() }).ok_or(Error::NoDS4PadAlloc)?;
+        self.add(target)
+    }
+}
+
+/// Add a freshly allocated target to the bus, freeing it if that fails since nothing else owns
+/// it yet
+fn add_target<Type>(
+    client: ClientRef<'_>,
+    target: NonNull<ffi::_VIGEM_TARGET_T>,
+) -> Result<Target<'_, Type>> {
+    let result =
+        client.check(unsafe { ffi::vigem_target_add(client.vigem.as_ptr(), target.as_ptr()) });
+    if let Err(error) = result {
+        unsafe { ffi::vigem_target_free(target.as_ptr()) };
+        return Err(error);
+    }
+    Ok(Target {
+        client,
+        target,
+        notification: None,
+        report_counter: 0,
+        #[cfg(feature = "async-update")]
+        pending_updates: Arc::default(),
+        removed: false,
+        _marker: PhantomData,
+    })
+}
+
+// SAFETY: See the comment at the top of the module
+unsafe impl Send for Client {}
+unsafe impl Sync for Client {}
+
 impl Drop for Client {
     fn drop(&mut self) {
         unsafe {
@@ -61,16 +461,61 @@ impl Drop for Client {
     }
 }
 
+/// How a target refers to the client it was added to
+#[derive(Debug, Clone)]
+enum ClientRef<'client> {
+    Borrowed(&'client Client),
+    Shared(Arc<Client>),
+}
+
+impl Deref for ClientRef<'_> {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        match self {
+            ClientRef::Borrowed(client) => client,
+            ClientRef::Shared(client) => client,
+        }
+    }
+}
+
+/// The kind of device a target is emulating
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetType {
+    Xbox360Wired,
+    DualShock4Wired,
+}
+
 /// A target. Could be an xbox 360 controller or a dualshock depending on the marker type.
 #[derive(Debug)]
 pub struct Target<'client, Type> {
-    client: &'client Client,
+    client: ClientRef<'client>,
     target: NonNull<ffi::_VIGEM_TARGET_T>,
-    has_notification: bool,
+    notification: Option<Notification>,
+    report_counter: u8,
+    #[cfg(feature = "async-update")]
+    pending_updates: Arc<PendingUpdates>,
+
+    /// Whether the target was removed and freed already, after which `target` dangles
+    removed: bool,
+
     _marker: PhantomData<Type>,
 }
 
+/// A target which holds on to its client via an [Arc] instead of borrowing it.
+///
+/// These are created via [Client::connect_x360_pad_owned] and [Client::connect_ds4_pad_owned], or
+/// configured first with [Client::x360_pad_owned] and [Client::ds4_pad_owned].
+/// The client is kept alive at least until the target has been removed from it.
+pub type OwnedTarget<Type> = Target<'static, Type>;
+
+// SAFETY: See the comment at the top of the module
+unsafe impl<Type> Send for Target<'_, Type> {}
+unsafe impl<Type> Sync for Target<'_, Type> {}
+
 impl<Type> Drop for Target<'_, Type> {
+    /// Remove and free the target, ignoring whether removing it worked as there's nobody to
+    /// tell. Use [Target::remove] to find out.
     fn drop(&mut self) {
         let _ = self.remove_internal();
     }
@@ -82,7 +527,11 @@ impl<Type> Target<'_, Type> {
         unsafe { ffi::vigem_target_get_vid(self.target.as_ptr()) }
     }
 
-    /// Set this target's vendor id
+    /// Set this target's vendor id.
+    ///
+    /// ViGEmBus only reads it when the target is added, which has already happened by the time
+    /// there's a [Target], so this changes what [Target::vendor_id] says but not what the OS
+    /// sees. Use [PadBuilder::vendor_id] to set it before the target is added.
     pub fn set_vendor_id(&mut self, vendor_id: u16) {
         unsafe { ffi::vigem_target_set_vid(self.target.as_ptr(), vendor_id) }
     }
@@ -92,26 +541,133 @@ impl<Type> Target<'_, Type> {
         unsafe { ffi::vigem_target_get_pid(self.target.as_ptr()) }
     }
 
-    /// Set this target's product id
+    /// Set this target's product id.
+    ///
+    /// Like [Target::set_vendor_id], this only takes effect for the OS if it's done with
+    /// [PadBuilder::product_id] before the target is added.
     pub fn set_product_id(&mut self, product_id: u16) {
         unsafe { ffi::vigem_target_set_pid(self.target.as_ptr(), product_id) }
     }
 
+    /// Get this target's index on the bus
+    pub fn index(&self) -> u32 {
+        unsafe { ffi::vigem_target_get_index(self.target.as_ptr()) }
+    }
+
+    /// Check whether this target is currently attached to the bus.
+    ///
+    /// This is what the client last heard from the bus: it's true from when the target is
+    /// added until it's removed, and stays true if the bus goes away in the meantime, as
+    /// nothing tells the client. A target which fails to be removed is freed all the same, so
+    /// there's never a [Target] to ask about afterwards.
+    pub fn is_attached(&self) -> bool {
+        unsafe { ffi::vigem_target_is_attached(self.target.as_ptr()) != 0 }
+    }
+
+    /// Get the kind of device this target is emulating
+    pub fn target_type(&self) -> TargetType {
+        match unsafe { ffi::vigem_target_get_type(self.target.as_ptr()) } {
+            ffi::_VIGEM_TARGET_TYPE_Xbox360Wired => TargetType::Xbox360Wired,
+            ffi::_VIGEM_TARGET_TYPE_DualShock4Wired => TargetType::DualShock4Wired,
+            // We only ever allocate targets of the types above
+            other => unreachable!("unknown target type {}", other),
+        }
+    }
+
+    /// Return [Error::TargetNotPluggedIn] if this target isn't attached to the bus
+    fn ensure_attached(&self) -> Result<()> {
+        if self.is_attached() {
+            Ok(())
+        } else {
+            Err(Error::TargetNotPluggedIn)
+        }
+    }
+
+    /// Unregister and free the notification callback, if there is one
+    fn release_notification(&mut self) {
+        if let Some(notification) = self.notification.take() {
+            unsafe {
+                match &notification.events {
+                    // Its receiver may have unregistered it already
+                    Some(events) => events.unregister(),
+                    None => (notification.unregister)(self.target.as_ptr()),
+                }
+                (notification.free)(notification.callback);
+            }
+        }
+    }
+
+    /// Whether a notification callback is registered, after freeing the handler of an
+    /// [EventReceiver] which was dropped
+    fn has_notification(&mut self) -> bool {
+        if let Some(Notification {
+            events: Some(events),
+            ..
+        }) = &self.notification
+        {
+            if events.is_unregistered() {
+                self.release_notification();
+            }
+        }
+        self.notification.is_some()
+    }
+
+    /// Take ownership of a callback which was just registered on this target
+    fn track_notification<F>(
+        &mut self,
+        callback: *mut F,
+        unregister: unsafe extern "C" fn(*mut ffi::_VIGEM_TARGET_T),
+    ) -> NotificationHandle<F> {
+        let id = NEXT_NOTIFICATION_ID.fetch_add(1, Ordering::Relaxed);
+        self.notification = Some(Notification {
+            id,
+            callback: callback as *mut c_void,
+            free: free_callback::<F>,
+            unregister,
+            events: None,
+        });
+        NotificationHandle {
+            id,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Unregister the notification callback the given handle was returned for
+    fn unregister_notification_with<F>(&mut self, handle: NotificationHandle<F>) -> Result<()> {
+        match &self.notification {
+            Some(notification) if notification.id == handle.id => {
+                self.release_notification();
+                Ok(())
+            }
+            _ => Err(Error::CallbackNotFound),
+        }
+    }
+
+    /// Remove the target from the bus and free it, exactly once no matter whether removing it
+    /// worked. Returns what removing it gave.
     fn remove_internal(&mut self) -> Result<()> {
-        check(unsafe {
+        if self.removed {
+            return Ok(());
+        }
+        self.removed = true;
+        self.release_notification();
+        let result = self.client.check(unsafe {
             ffi::vigem_target_remove(self.client.vigem.as_ptr(), self.target.as_ptr())
-        })?;
+        });
+        #[cfg(feature = "async-update")]
+        self.pending_updates.wait();
         unsafe {
             ffi::vigem_target_free(self.target.as_ptr());
         }
-        Ok(())
+        result
     }
 
-    /// Remove and deallocate this target
+    /// Remove this target from the bus and deallocate it.
+    ///
+    /// The target is deallocated even if removing it fails, e.g. since the bus went away, so
+    /// the error is only for finding out about it: there's nothing left to retry with.
     pub fn remove(mut self) -> Result<()> {
-        self.remove_internal()?;
-        forget(self);
-        Ok(())
+        self.remove_internal()
     }
 }
 
@@ -128,12 +684,239 @@ pub struct X360NotificationData {
     pub led_number: u8,
 }
 
+/// Represents an xbox 360 controller's user index, i.e. the XInput slot it was given
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserIndex {
+    /// The controller is in the given slot, ranging from 0 to 3
+    Assigned(u32),
+
+    /// The controller hasn't been given a slot yet, which is the case for a while after it was
+    /// plugged in
+    Unassigned,
+
+    /// The bus claimed to know the controller's slot, but handed us an index which isn't one.
+    /// Some versions of it report success without writing an index at all.
+    Unknown(u32),
+}
+
+impl UserIndex {
+    /// The highest user index there is, as XInput has four slots
+    pub const MAX: u32 = 3;
+
+    /// Make sense of the result of asking the bus for a user index, along with the index it
+    /// wrote if it succeeded. [Error::UserIndexOutOfRange] means the controller hasn't been
+    /// given a slot yet rather than that something went wrong, so it is [Unassigned](Self::Unassigned).
+    pub fn from_reply(result: Result<()>, index: u32) -> Result<Self> {
+        match result {
+            Ok(()) if index <= Self::MAX => Ok(Self::Assigned(index)),
+            Ok(()) => Ok(Self::Unknown(index)),
+            Err(Error::UserIndexOutOfRange) => Ok(Self::Unassigned),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// The slot the controller is in, if it was given one
+    pub fn assigned(self) -> Option<u32> {
+        match self {
+            Self::Assigned(index) => Some(index),
+            Self::Unassigned | Self::Unknown(_) => None,
+        }
+    }
+}
+
+/// Represents a notification from a dualshock 4 controller
+#[derive(Debug, Clone, Copy)]
+pub struct DS4NotificationData {
+    /// How much the large motor should be vibrating
+    pub large_motor: u8,
+
+    /// How much the small motor should be vibrating
+    pub small_motor: u8,
+
+    /// What color should the lightbar be, as a red, green and blue tuple
+    pub lightbar_color: (u8, u8, u8),
+}
+
 /// The handle to a notification callback
 ///
-/// This has no special usage, its usage is just to track the type and a pointer to the
-/// notification so that we can deallocate it properly once it is no longer needed.
+/// The callback itself is owned by the target it was registered on, which frees it once it is
+/// unregistered or the target is dropped. This is only a token identifying the callback, which
+/// can be used to unregister it early.
+///
+/// Every registration gets a handle of its own, which only unregisters that callback from the
+/// target it was registered on: passing it to another target, even one whose callback has the
+/// same type, gives [Error::CallbackNotFound] and leaves that target's callback alone. Handles
+/// can't be cloned and are used up by unregistering, so the same callback can't be unregistered
+/// twice.
+#[derive(Debug)]
+pub struct NotificationHandle<F> {
+    id: u64,
+    _marker: PhantomData<fn() -> F>,
+}
+
+/// A registered notification callback, with its type erased so that the target can free it
 #[derive(Debug)]
-pub struct NotificationHandle<F>(*mut F);
+struct Notification {
+    /// Identifies this callback, since callbacks of zero-sized types all share the same address
+    id: u64,
+    callback: *mut c_void,
+    free: unsafe fn(*mut c_void),
+    unregister: unsafe extern "C" fn(*mut ffi::_VIGEM_TARGET_T),
+
+    /// What the callback shares with its receiver, if it's the handler of [Target::events]
+    events: Option<Arc<EventLink>>,
+}
+
+/// Something a game told an xbox 360 target, as sent by [Target::events]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum X360Event {
+    /// The motors should vibrate this much, with both at 0 meaning they should stop
+    Rumble { large: u8, small: u8 },
+
+    /// The target was given another led, which is what tells which player it is
+    LedChanged(u8),
+}
+
+/// The events of an xbox 360 target, which derefs to the [Receiver] they come through.
+///
+/// The channel is closed once the target is dropped, after which receiving fails as soon as
+/// the events sent until then were received. Dropping the receiver first unregisters the
+/// handler sending them, freeing the target to register another.
+#[derive(Debug)]
+pub struct EventReceiver {
+    events: Receiver<X360Event>,
+    link: Arc<EventLink>,
+}
+
+impl Deref for EventReceiver {
+    type Target = Receiver<X360Event>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.events
+    }
+}
+
+impl Drop for EventReceiver {
+    fn drop(&mut self) {
+        self.link.unregister();
+    }
+}
+
+/// What the handler of [Target::events] shares with its [EventReceiver], so that whichever of
+/// the receiver and the target goes first unregisters it
+#[derive(Debug)]
+struct EventLink(Mutex<Option<Registered>>);
+
+/// The target a handler is still registered on
+#[derive(Debug)]
+struct Registered {
+    target: NonNull<ffi::_VIGEM_TARGET_T>,
+    unregister: unsafe extern "C" fn(*mut ffi::_VIGEM_TARGET_T),
+}
+
+// SAFETY: The target is only used to unregister the handler, with the link locked, which the
+// target also locks before doing anything else with its notification, see the comment at the
+// top of the module
+unsafe impl Send for Registered {}
+
+impl EventLink {
+    /// Unregister the handler, unless that was done already
+    fn unregister(&self) {
+        // The lock is held throughout, so that the target can't free the handler, or itself,
+        // while it's being unregistered
+        let mut registered = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(registered) = registered.take() {
+            unsafe { (registered.unregister)(registered.target.as_ptr()) };
+        }
+    }
+
+    fn is_unregistered(&self) -> bool {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_none()
+    }
+}
+
+/// Turns a target's notifications into [X360Event]s, leaving out what didn't change
+#[derive(Debug)]
+struct EventSender {
+    sender: Sender<X360Event>,
+
+    /// The motors' latest speeds, which start out still
+    rumble: (u8, u8),
+
+    /// The latest led, which is unknown until the first notification
+    led: Option<u8>,
+}
+
+impl EventSender {
+    fn notify(&mut self, data: X360NotificationData) {
+        if self.led != Some(data.led_number) {
+            self.led = Some(data.led_number);
+            let _ = self.sender.send(X360Event::LedChanged(data.led_number));
+        }
+        if self.rumble != (data.large_motor, data.small_motor) {
+            self.rumble = (data.large_motor, data.small_motor);
+            let _ = self.sender.send(X360Event::Rumble {
+                large: data.large_motor,
+                small: data.small_motor,
+            });
+        }
+    }
+}
+
+/// The id of the next notification callback to be registered, on any target
+static NEXT_NOTIFICATION_ID: AtomicU64 = AtomicU64::new(0);
+
+/// How many updates submitted with [Target::update_async] have yet to complete
+#[cfg(feature = "async-update")]
+#[derive(Debug, Default)]
+struct PendingUpdates {
+    count: Mutex<usize>,
+    completed: Condvar,
+}
+
+#[cfg(feature = "async-update")]
+impl PendingUpdates {
+    fn start(&self) {
+        *self.count.lock().unwrap_or_else(PoisonError::into_inner) += 1;
+    }
+
+    fn finish(&self) {
+        let mut count = self.count.lock().unwrap_or_else(PoisonError::into_inner);
+        *count -= 1;
+        if *count == 0 {
+            self.completed.notify_all();
+        }
+    }
+
+    /// Block until every pending update has completed
+    fn wait(&self) {
+        let mut count = self.count.lock().unwrap_or_else(PoisonError::into_inner);
+        while *count > 0 {
+            count = self
+                .completed
+                .wait(count)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+/// An update submitted with [Target::update_async], along with what to do once it completes
+#[cfg(feature = "async-update")]
+struct AsyncUpdate<F> {
+    on_complete: F,
+    pending: Arc<PendingUpdates>,
+}
+
+/// How often [Target::wait_for_user_index] asks the bus whether an index was assigned yet
+const USER_INDEX_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Free a callback registered on a target
+unsafe fn free_callback<F>(callback: *mut c_void) {
+    drop(unsafe { Box::from_raw(callback as *mut F) });
+}
 
 unsafe extern "C" fn x360_notification_handler<F>(
     _client: *mut ffi::_VIGEM_CLIENT_T,
@@ -155,10 +938,62 @@ unsafe extern "C" fn x360_notification_handler<F>(
     }
 }
 
-impl Target<'_, X360> {
+unsafe extern "C" fn ds4_notification_handler<F>(
+    _client: *mut ffi::_VIGEM_CLIENT_T,
+    _target: *mut ffi::_VIGEM_TARGET_T,
+    large_motor: u8,
+    small_motor: u8,
+    lightbar_color: ffi::_DS4_LIGHTBAR_COLOR,
+    userdata: *mut c_void,
+) where
+    F: RefUnwindSafe + Fn(DS4NotificationData),
+{
+    if let Some(f) = unsafe { (userdata as *mut F).as_ref() } {
+        let data = DS4NotificationData {
+            large_motor,
+            small_motor,
+            lightbar_color: (
+                lightbar_color.Red,
+                lightbar_color.Green,
+                lightbar_color.Blue,
+            ),
+        };
+        let _ = catch_unwind(move || f(data));
+    }
+}
+
+#[cfg(feature = "async-update")]
+unsafe extern "C" fn update_completion_handler<F>(
+    _client: *mut ffi::_VIGEM_CLIENT_T,
+    _target: *mut ffi::_VIGEM_TARGET_T,
+    error: ffi::VIGEM_ERROR,
+    userdata: *mut c_void,
+) where
+    F: FnOnce(Result<()>) + UnwindSafe,
+{
+    // SAFETY: This is only ever called once per submitted update, with the box leaked for it
+    let update = unsafe { Box::from_raw(userdata as *mut AsyncUpdate<F>) };
+    let AsyncUpdate {
+        on_complete,
+        pending,
+    } = *update;
+
+    // The update is done with the target by now. Finishing before running the callback lets
+    // it drop the target without waiting on itself.
+    pending.finish();
+    let _ = catch_unwind(move || on_complete(check(error)));
+}
+
+impl<'client> Target<'client, X360> {
     /// Update this controller's state
-    pub fn update(&mut self, state: X360State) -> Result<()> {
-        check(unsafe {
+    ///
+    /// This only needs shared access, and may be called from any number of threads at once,
+    /// including from a notification callback running on ViGEmClient's notification thread:
+    /// every update is submitted to the bus on its own, without touching the target's data.
+    /// Concurrent updates are applied in whichever order they reach the bus.
+    pub fn update(&self, state: X360State) -> Result<()> {
+        self.ensure_attached()?;
+        self.client.check(unsafe {
             ffi::vigem_target_x360_update(
                 self.client.vigem.as_ptr(),
                 self.target.as_ptr(),
@@ -167,17 +1002,119 @@ impl Target<'_, X360> {
         })
     }
 
+    /// Send the bus a report as it is, e.g. with bits of the buttons which aren't any of the
+    /// [X360Buttons](crate::X360Buttons).
+    ///
+    /// This bypasses all validation: whatever the report holds reaches the bus, and whichever
+    /// game reads the controller, unchanged. It can be called from any number of threads at
+    /// once, just like [update](Self::update).
+    pub fn update_raw(&self, report: XusbReport) -> Result<()> {
+        self.ensure_attached()?;
+        self.client.check(unsafe {
+            ffi::vigem_target_x360_update(
+                self.client.vigem.as_ptr(),
+                self.target.as_ptr(),
+                report.to_ffi(),
+            )
+        })
+    }
+
+    /// Submit an update of this controller's state without waiting for the bus to apply it,
+    /// calling `on_complete` with the outcome once it has.
+    ///
+    /// If the update can't be submitted at all the error is returned right away and
+    /// `on_complete` is dropped without being called. Otherwise it's called exactly once, on a
+    /// thread owned by ViGEmClient, so it must be [Send] and [UnwindSafe] for the same reasons
+    /// as [notification callbacks](Self::register_notification). Removing or dropping the
+    /// target blocks until every update submitted through it has completed.
+    ///
+    /// This needs a ViGEmClient patched to declare `vigem_target_x360_update_async` in
+    /// `Client.h` and export it, which upstream's doesn't, which is why it is gated behind the
+    /// `async-update` feature. With a stock ViGEmClient turning the feature on fails to build,
+    /// as the binding doesn't exist.
+    #[cfg(feature = "async-update")]
+    pub fn update_async<F>(&self, state: X360State, on_complete: F) -> Result<()>
+    where
+        F: FnOnce(Result<()>) + UnwindSafe + Send + 'static,
+    {
+        self.ensure_attached()?;
+        self.pending_updates.start();
+        let update = Box::into_raw(Box::new(AsyncUpdate {
+            on_complete,
+            pending: Arc::clone(&self.pending_updates),
+        }));
+        let result = self.client.check(unsafe {
+            ffi::vigem_target_x360_update_async(
+                self.client.vigem.as_ptr(),
+                self.target.as_ptr(),
+                state.to_xusb_report(),
+                Some(update_completion_handler::<F>),
+                update as *mut c_void,
+            )
+        });
+        if result.is_err() {
+            // The completion routine is only called for updates which were submitted
+            drop(unsafe { Box::from_raw(update) });
+            self.pending_updates.finish();
+        }
+        result
+    }
+
+    /// Move this controller from one state to another over `steps` updates, `interval` apart.
+    ///
+    /// See [X360State::interpolate] for how the intermediate states are computed. The first
+    /// update sent is already one step away from `from`, and the last one is exactly `to`.
+    /// This blocks until every update has been sent, unless `cancel` is set in the meantime,
+    /// in which case it stops early and returns `false`.
+    pub fn update_interpolated(
+        &self,
+        from: X360State,
+        to: X360State,
+        steps: u32,
+        interval: Duration,
+        cancel: &AtomicBool,
+    ) -> Result<bool> {
+        for step in 1..=steps.max(1) {
+            if cancel.load(Ordering::SeqCst) {
+                return Ok(false);
+            }
+            if step > 1 {
+                sleep(interval);
+            }
+            self.update(from.interpolate(to, step, steps))?;
+        }
+        Ok(true)
+    }
+
     /// Get this controller's user index
-    pub fn user_index(&self) -> Result<u32> {
+    pub fn user_index(&self) -> Result<UserIndex> {
+        // Out of range, so that a bus which doesn't write an index gives us an unknown one
         let mut index: u32 = 0xDEADBEEF;
-        check(unsafe {
+        let result = self.client.check(unsafe {
             ffi::vigem_target_x360_get_user_index(
                 self.client.vigem.as_ptr(),
                 self.target.as_ptr(),
                 (&mut index) as *mut _,
             )
-        })?;
-        Ok(index)
+        });
+        UserIndex::from_reply(result, index)
+    }
+
+    /// Wait for the bus to give this controller a user index, and return it.
+    ///
+    /// Right after being plugged in the controller is still being enumerated, during which
+    /// its index is [Unassigned](UserIndex::Unassigned); this usually lasts a few hundred
+    /// milliseconds. The index is polled until it's assigned or `timeout` elapses, in which
+    /// case [Error::TargetUninitialized] is returned.
+    pub fn wait_for_user_index(&self, timeout: Duration) -> Result<u32> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.user_index()? {
+                UserIndex::Assigned(index) => return Ok(index),
+                _ if Instant::now() < deadline => sleep(USER_INDEX_POLL_INTERVAL),
+                _ => return Err(Error::TargetUninitialized),
+            }
+        }
     }
 
     /// Register a notification callback for this target.
@@ -187,37 +1124,158 @@ impl Target<'_, X360> {
     /// panicking over the FFI boundary. This means that any panics in your handler will simply be eaten up.
     ///
     /// The callback must also be [Sync] as it will be called, by reference, in another
-    /// thread spawned by ViGEmClient.
+    /// thread spawned by ViGEmClient, and [Send] as the target may be dropped on any thread.
+    /// As it lives for as long as the target does, it may only borrow data which outlives the
+    /// target's client.
     ///
     /// Only one notification callback may be registered at a time.
-    /// You can unregister via [unregister_notification](Self::unregister_notification). Make sure to do so before dropping a target or memory may be leaked.
+    /// The callback is unregistered and dropped along with the target, or earlier via
+    /// [unregister_notification](Self::unregister_notification).
     pub fn register_notification<F>(&mut self, func: F) -> Result<NotificationHandle<F>>
     where
-        F: Fn(X360NotificationData) + RefUnwindSafe + Sync,
+        F: Fn(X360NotificationData) + RefUnwindSafe + Send + Sync + 'client,
     {
-        if self.has_notification {
+        if self.has_notification() {
             return Err(Error::AlreadyHasCallback);
         }
 
-        let ptr = Box::leak(Box::new(func));
-        check(unsafe {
+        let callback = Box::into_raw(Box::new(func));
+        let result = self.client.check(unsafe {
             ffi::vigem_target_x360_register_notification(
                 self.client.vigem.as_ptr(),
                 self.target.as_ptr(),
                 Some(x360_notification_handler::<F>),
-                ptr as *mut _ as *mut _,
+                callback as *mut _,
             )
+        });
+        if let Err(error) = result {
+            drop(unsafe { Box::from_raw(callback) });
+            return Err(error);
+        }
+        Ok(self.track_notification(callback, ffi::vigem_target_x360_unregister_notification))
+    }
+
+    /// Unregister the notification callback the given handle was returned for, dropping it.
+    ///
+    /// Returns [Error::CallbackNotFound] if the handle belongs to a callback which is no longer
+    /// registered on this target.
+    pub fn unregister_notification<F>(&mut self, handle: NotificationHandle<F>) -> Result<()> {
+        self.unregister_notification_with(handle)
+    }
+
+    /// Receive what games tell this target as [X360Event]s, rather than through a callback.
+    ///
+    /// Rumbles are only sent when the motors' speeds change, starting out still, and the led
+    /// whenever it changes, including the first time it's known.
+    ///
+    /// This registers a notification callback of its own, so it returns
+    /// [Error::AlreadyHasCallback] if one was [registered](Self::register_notification) or
+    /// another [EventReceiver] is still around, and registering a callback does too for as long
+    /// as the receiver is. The callback is unregistered once either the receiver or the target
+    /// is dropped.
+    pub fn events(&mut self) -> Result<EventReceiver> {
+        let (sender, events) = channel();
+        let sender = Mutex::new(EventSender {
+            sender,
+            rumble: (0, 0),
+            led: None,
+        });
+        let _handle = self.register_notification(move |data| {
+            sender
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .notify(data)
         })?;
-        self.has_notification = true;
-        Ok(NotificationHandle(ptr))
+
+        let link = Arc::new(EventLink(Mutex::new(Some(Registered {
+            target: self.target,
+            unregister: ffi::vigem_target_x360_unregister_notification,
+        }))));
+        if let Some(notification) = &mut self.notification {
+            notification.events = Some(Arc::clone(&link));
+        }
+        Ok(EventReceiver { events, link })
     }
+}
 
-    /// Unregister the current notification callback.
-    pub fn unregister_notification<F>(&mut self, handle: NotificationHandle<F>) {
-        unsafe {
-            ffi::vigem_target_x360_unregister_notification(self.target.as_ptr());
-            let _ = Box::from_raw(handle.0);
+impl<'client> Target<'client, DS4> {
+    /// Update this controller's state
+    ///
+    /// Like [the xbox 360 one](Target::<X360>::update), this may be called from any number of
+    /// threads at once.
+    pub fn update(&self, state: DS4State) -> Result<()> {
+        self.ensure_attached()?;
+        self.client.check(unsafe {
+            ffi::vigem_target_ds4_update(
+                self.client.vigem.as_ptr(),
+                self.target.as_ptr(),
+                state.to_ds4_report(),
+            )
+        })
+    }
+
+    /// Update this controller's extended state, which includes the touchpad and motion sensors.
+    ///
+    /// If the state does not specify a packet counter, one kept by this target is used and
+    /// incremented for every call.
+    pub fn update_ex(&mut self, state: DS4StateEx) -> Result<()> {
+        self.ensure_attached()?;
+        let counter = self.report_counter;
+        if state.packet_counter.is_none() {
+            self.report_counter = self.report_counter.wrapping_add(1);
+        }
+        self.client.check(unsafe {
+            ffi::vigem_target_ds4_update_ex(
+                self.client.vigem.as_ptr(),
+                self.target.as_ptr(),
+                state.to_ds4_report_ex(counter),
+            )
+        })
+    }
+
+    /// Register a notification callback for this target.
+    /// It will be called anytime there is a vibration request and/or the lightbar color changes.
+    ///
+    /// The callback must be [RefUnwindSafe] since we utilize [catch_unwind] to avoid
+    /// panicking over the FFI boundary. This means that any panics in your handler will simply be eaten up.
+    ///
+    /// The callback must also be [Sync] as it will be called, by reference, in another
+    /// thread spawned by ViGEmClient, and [Send] as the target may be dropped on any thread.
+    /// As it lives for as long as the target does, it may only borrow data which outlives the
+    /// target's client.
+    ///
+    /// Only one notification callback may be registered at a time.
+    /// The callback is unregistered and dropped along with the target, or earlier via
+    /// [unregister_notification](Self::unregister_notification).
+    pub fn register_notification<F>(&mut self, func: F) -> Result<NotificationHandle<F>>
+    where
+        F: Fn(DS4NotificationData) + RefUnwindSafe + Send + Sync + 'client,
+    {
+        if self.has_notification() {
+            return Err(Error::AlreadyHasCallback);
+        }
+
+        let callback = Box::into_raw(Box::new(func));
+        let result = self.client.check(unsafe {
+            ffi::vigem_target_ds4_register_notification(
+                self.client.vigem.as_ptr(),
+                self.target.as_ptr(),
+                Some(ds4_notification_handler::<F>),
+                callback as *mut _,
+            )
+        });
+        if let Err(error) = result {
+            drop(unsafe { Box::from_raw(callback) });
+            return Err(error);
         }
-        self.has_notification = false;
+        Ok(self.track_notification(callback, ffi::vigem_target_ds4_unregister_notification))
+    }
+
+    /// Unregister the notification callback the given handle was returned for, dropping it.
+    ///
+    /// Returns [Error::CallbackNotFound] if the handle belongs to a callback which is no longer
+    /// registered on this target.
+    pub fn unregister_notification<F>(&mut self, handle: NotificationHandle<F>) -> Result<()> {
+        self.unregister_notification_with(handle)
     }
 }
@@ -1,8 +1,10 @@
 //! Contains the error type of the library
 
+use core::fmt;
+
 use thiserror::Error;
 
-use vigem_client_c_sys as ffi;
+use crate::{client::Target, ffi};
 
 /// Represents all possible errors in the library
 #[derive(Error, Debug, Clone, Copy)]
@@ -13,6 +15,9 @@ pub enum Error {
     #[error("Failed to allocate xbox 360 pad")]
     NoX360PadAlloc,
 
+    #[error("Failed to allocate dualshock 4 pad")]
+    NoDS4PadAlloc,
+
     #[error("Bus not found")]
     BusNotFound,
 
@@ -65,7 +70,141 @@ pub enum Error {
     NotSupported,
 
     #[error("Unknown error code {0:x}")]
-    UnknownError(ffi::_VIGEM_ERRORS),
+    UnknownError(u32),
+}
+
+impl Error {
+    /// Check whether this error means ViGEmBus isn't installed, or at least can't be reached,
+    /// rather than something going wrong while talking to it. That's what [Client::new] gives
+    /// on machines which don't have the driver yet, which is worth telling users how to fix.
+    ///
+    /// [Client::new]: crate::client::Client::new
+    pub fn is_driver_missing(&self) -> bool {
+        matches!(self, Error::BusNotFound | Error::BusAccessFailed)
+    }
+
+    /// The `VIGEM_ERROR` code this error was created from, if it came from ViGEmClient.
+    ///
+    /// Errors which are detected by this library itself, such as failed allocations, have no code.
+    pub fn raw_code(&self) -> Option<u32> {
+        let code = match self {
+            Error::NoVigemAlloc
+            | Error::NoX360PadAlloc
+            | Error::NoDS4PadAlloc
+            | Error::AlreadyHasCallback => return None,
+
+            Error::BusNotFound => ffi::_VIGEM_ERRORS_VIGEM_ERROR_BUS_NOT_FOUND,
+            Error::NoFreeSlot => ffi::_VIGEM_ERRORS_VIGEM_ERROR_NO_FREE_SLOT,
+            Error::InvalidTarget => ffi::_VIGEM_ERRORS_VIGEM_ERROR_INVALID_TARGET,
+            Error::RemovalFailed => ffi::_VIGEM_ERRORS_VIGEM_ERROR_REMOVAL_FAILED,
+            Error::AlreadyConnected => ffi::_VIGEM_ERRORS_VIGEM_ERROR_ALREADY_CONNECTED,
+            Error::TargetUninitialized => ffi::_VIGEM_ERRORS_VIGEM_ERROR_TARGET_UNINITIALIZED,
+            Error::TargetNotPluggedIn => ffi::_VIGEM_ERRORS_VIGEM_ERROR_TARGET_NOT_PLUGGED_IN,
+            Error::BusVersionMismatch => ffi::_VIGEM_ERRORS_VIGEM_ERROR_BUS_VERSION_MISMATCH,
+            Error::BusAccessFailed => ffi::_VIGEM_ERRORS_VIGEM_ERROR_BUS_ACCESS_FAILED,
+            Error::CallbackAlreadyRegistered => {
+                ffi::_VIGEM_ERRORS_VIGEM_ERROR_CALLBACK_ALREADY_REGISTERED
+            }
+            Error::CallbackNotFound => ffi::_VIGEM_ERRORS_VIGEM_ERROR_CALLBACK_NOT_FOUND,
+            Error::BusAlreadyConnected => ffi::_VIGEM_ERRORS_VIGEM_ERROR_BUS_ALREADY_CONNECTED,
+            Error::BusInvalidHandle => ffi::_VIGEM_ERRORS_VIGEM_ERROR_BUS_INVALID_HANDLE,
+            Error::UserIndexOutOfRange => {
+                ffi::_VIGEM_ERRORS_VIGEM_ERROR_XUSB_USERINDEX_OUT_OF_RANGE
+            }
+            Error::InvalidParameter => ffi::_VIGEM_ERRORS_VIGEM_ERROR_INVALID_PARAMETER,
+            Error::NotSupported => ffi::_VIGEM_ERRORS_VIGEM_ERROR_NOT_SUPPORTED,
+
+            Error::UnknownError(code) => return Some(*code),
+        };
+        // The codes don't fit in an i32, which is what the C enum ends up as, so get the bits back
+        Some(code as u32)
+    }
+}
+
+/// What a [Target] was doing when it failed, as told by a [TargetError]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    Update,
+    Remove,
+    RegisterNotification,
+    GetUserIndex,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// An [Error] along with which target it happened to and what that target was doing, rendered
+/// like `Invalid target (vid=045E pid=028E index=2) during Update`.
+///
+/// Made with [Error::with_target_info], and turned back into the plain [Error] by `?` in
+/// functions returning that, which drops the context.
+#[derive(Error, Debug, Clone, Copy)]
+#[error("{source} (vid={vendor_id:04X} pid={product_id:04X} index={index}) during {operation}")]
+pub struct TargetError {
+    pub source: Error,
+    pub vendor_id: u16,
+    pub product_id: u16,
+
+    /// The target's index on the bus
+    pub index: u32,
+    pub operation: Operation,
+}
+
+impl Error {
+    /// Add which target this error happened to, as the target says right now, and what it was
+    /// doing
+    pub fn with_target_info<Type>(
+        self,
+        target: &Target<'_, Type>,
+        operation: Operation,
+    ) -> TargetError {
+        TargetError {
+            source: self,
+            vendor_id: target.vendor_id(),
+            product_id: target.product_id(),
+            index: target.index(),
+            operation,
+        }
+    }
+}
+
+impl From<TargetError> for Error {
+    fn from(error: TargetError) -> Self {
+        error.source
+    }
+}
+
+impl From<Error> for std::io::Error {
+    fn from(error: Error) -> Self {
+        use std::io::ErrorKind;
+
+        let kind = match error {
+            Error::NoVigemAlloc | Error::NoX360PadAlloc | Error::NoDS4PadAlloc => {
+                ErrorKind::OutOfMemory
+            }
+            Error::BusNotFound | Error::CallbackNotFound => ErrorKind::NotFound,
+            Error::BusAccessFailed => ErrorKind::PermissionDenied,
+            Error::AlreadyConnected
+            | Error::BusAlreadyConnected
+            | Error::CallbackAlreadyRegistered
+            | Error::AlreadyHasCallback => ErrorKind::AlreadyExists,
+            Error::TargetUninitialized | Error::TargetNotPluggedIn | Error::BusInvalidHandle => {
+                ErrorKind::NotConnected
+            }
+            Error::InvalidTarget | Error::InvalidParameter | Error::UserIndexOutOfRange => {
+                ErrorKind::InvalidInput
+            }
+            Error::NotSupported => ErrorKind::Unsupported,
+            Error::NoFreeSlot
+            | Error::RemovalFailed
+            | Error::BusVersionMismatch
+            | Error::UnknownError(_) => ErrorKind::Other,
+        };
+        std::io::Error::new(kind, error)
+    }
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -93,6 +232,6 @@ pub(crate) fn check(error: ffi::_VIGEM_ERRORS) -> Result<()> {
         ffi::_VIGEM_ERRORS_VIGEM_ERROR_INVALID_PARAMETER => Error::InvalidParameter,
         ffi::_VIGEM_ERRORS_VIGEM_ERROR_NOT_SUPPORTED => Error::NotSupported,
 
-        _ => Error::UnknownError(error),
+        _ => Error::UnknownError(error as u32),
     })
 }
@@ -1,8 +1,19 @@
 //! Contains structures and enums needed to represent a gamepad state
+//!
+//! Nothing in here needs ViGEmClient, so this module builds without the `ffi` feature and
+//! without `std`, e.g. to share states with a controller running in the browser. Only the
+//! conversions between floats and raw values need `std`, for its float math.
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{fmt, str::FromStr};
 
 use bitflags::bitflags;
 
-use vigem_client_c_sys as ffi;
+#[cfg(any(feature = "ffi", feature = "mock"))]
+use crate::ffi;
 
 bitflags! {
     /// Represents an xbox 360 controller's buttons
@@ -18,6 +29,10 @@ bitflags! {
         const RIGHT_THUMB = 0x0080;
         const LEFT_SHOULDER = 0x0100;
         const RIGHT_SHOULDER = 0x0200;
+        /// The xbox button in the middle, which ViGEmBus passes on like any other. Windows'
+        /// documented `XInputGetState` leaves it out, so only what reads it some other way, like
+        /// Steam or the Xbox Game Bar, sees it pressed.
+        const GUIDE = 0x0400;
         const A = 0x1000;
         const B = 0x2000;
         const X = 0x4000;
@@ -26,8 +41,9 @@ bitflags! {
 }
 
 /// Represents an xbox 360 controller's state
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct X360State {
     /// The controller's buttons
     pub buttons: X360Buttons,
@@ -38,42 +54,1219 @@ pub struct X360State {
     /// The controller's right analog trigger's value, ranging from 0 to 255
     pub right_trigger: u8,
 
-    /// The controller's left thumbstick axes.
+    /// The controller's left thumbstick axes
+    pub left_thumbstick: StickPosition,
+
+    /// The controller's right thumbstick axes
+    pub right_thumbstick: StickPosition,
+}
+
+/// Where an xbox 360 controller's thumbstick is, both axes covering all of `i16` with the Y axis
+/// growing upwards.
+///
+/// It converts to and from an `(x, y)` tuple, which it compares equal to, and is serialized as
+/// one, i.e. as `[x, y]` in JSON.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "(i16, i16)", into = "(i16, i16)")
+)]
+pub struct StickPosition {
+    /// The X axis, growing rightwards
+    pub x: i16,
+
+    /// The Y axis, growing upwards
+    pub y: i16,
+}
+
+bitflags! {
+    /// Represents a dualshock 4 controller's buttons, excluding the dpad
+    #[derive(Default)]
+    pub struct DS4Buttons: u16 {
+        const SQUARE = 0x0010;
+        const CROSS = 0x0020;
+        const CIRCLE = 0x0040;
+        const TRIANGLE = 0x0080;
+        const SHOULDER_LEFT = 0x0100;
+        const SHOULDER_RIGHT = 0x0200;
+        const TRIGGER_LEFT = 0x0400;
+        const TRIGGER_RIGHT = 0x0800;
+        const SHARE = 0x1000;
+        const OPTIONS = 0x2000;
+        const THUMB_LEFT = 0x4000;
+        const THUMB_RIGHT = 0x8000;
+    }
+}
+
+bitflags! {
+    /// Represents a dualshock 4 controller's special buttons
+    #[derive(Default)]
+    pub struct DS4SpecialButtons: u8 {
+        const PS = 0x01;
+        const TOUCHPAD = 0x02;
+    }
+}
+
+/// Represents a dualshock 4 controller's dpad, which is reported as a hat switch
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DS4Dpad {
+    North = 0,
+    NorthEast = 1,
+    East = 2,
+    SouthEast = 3,
+    South = 4,
+    SouthWest = 5,
+    West = 6,
+    NorthWest = 7,
+    #[default]
+    None = 8,
+}
+
+/// Represents a dualshock 4 controller's state
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DS4State {
+    /// The controller's buttons
+    pub buttons: DS4Buttons,
+
+    /// The controller's special buttons
+    pub special: DS4SpecialButtons,
+
+    /// The controller's dpad
+    pub dpad: DS4Dpad,
+
+    /// The controller's left analog trigger's value, ranging from 0 to 255
+    pub left_trigger: u8,
+
+    /// The controller's right analog trigger's value, ranging from 0 to 255
+    pub right_trigger: u8,
+
+    /// The controller's left thumbstick axes, where 0x80 is the center.
     /// The first element of the tuple is the X axis, while the second one is the Y AXis.
-    pub left_thumbstick: (i16, i16),
+    pub left_thumbstick: (u8, u8),
 
-    /// The controller's right thumbstick axes.
+    /// The controller's right thumbstick axes, where 0x80 is the center.
     /// The first element of the tuple is the X axis, while the second one is the Y AXis.
-    pub right_thumbstick: (i16, i16),
+    pub right_thumbstick: (u8, u8),
 }
 
+impl Default for DS4State {
+    fn default() -> Self {
+        Self {
+            buttons: DS4Buttons::empty(),
+            special: DS4SpecialButtons::empty(),
+            dpad: DS4Dpad::None,
+            left_trigger: 0,
+            right_trigger: 0,
+            left_thumbstick: (0x80, 0x80),
+            right_thumbstick: (0x80, 0x80),
+        }
+    }
+}
+
+/// A single touchpad contact point of a dualshock 4 controller
+///
+/// The coordinates are stored packed as the controller reports them, two 12-bit values in
+/// three bytes. Use [new](Self::new) and the [x](Self::x)/[y](Self::y) accessors to avoid
+/// dealing with the packing yourself.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DS4TouchPoint {
+    /// Whether the finger is currently touching the touchpad
+    pub active: bool,
+
+    /// The tracking id of this touch, ranging from 0 to 127
+    pub id: u8,
+
+    /// The packed X and Y coordinates of this touch
+    pub coordinates: [u8; 3],
+}
+
+impl DS4TouchPoint {
+    /// The largest X coordinate the touchpad reports
+    pub const MAX_X: u16 = 1919;
+
+    /// The largest Y coordinate the touchpad reports
+    pub const MAX_Y: u16 = 942;
+
+    /// Create an active touch point with the given tracking id and 12-bit coordinates.
+    /// Bits beyond the valid range of each argument are discarded.
+    pub const fn new(id: u8, x: u16, y: u16) -> Self {
+        let x = x & 0xFFF;
+        let y = y & 0xFFF;
+        Self {
+            active: true,
+            id: id & 0x7F,
+            coordinates: [x as u8, ((x >> 8) as u8) | ((y << 4) as u8), (y >> 4) as u8],
+        }
+    }
+
+    /// Create a touch point representing no finger on the touchpad
+    pub const fn inactive() -> Self {
+        Self {
+            active: false,
+            id: 0,
+            coordinates: [0; 3],
+        }
+    }
+
+    /// Get the unpacked X coordinate of this touch
+    pub const fn x(&self) -> u16 {
+        self.coordinates[0] as u16 | ((self.coordinates[1] as u16 & 0x0F) << 8)
+    }
+
+    /// Get the unpacked Y coordinate of this touch
+    pub const fn y(&self) -> u16 {
+        (self.coordinates[1] as u16 >> 4) | ((self.coordinates[2] as u16) << 4)
+    }
+}
+
+/// Represents a dualshock 4 controller's extended state, including the touchpad and motion sensors
+#[derive(Debug, Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DS4StateEx {
+    /// The basic controller state
+    pub state: DS4State,
+
+    /// The two touch points of the touchpad
+    pub touches: [DS4TouchPoint; 2],
+
+    /// The touch packet counter. If this is `None`, the target will fill in a counter which
+    /// is incremented on every extended update.
+    pub packet_counter: Option<u8>,
+
+    /// The gyroscope's X, Y and Z axes
+    pub gyro: (i16, i16, i16),
+
+    /// The accelerometer's X, Y and Z axes
+    pub accel: (i16, i16, i16),
+
+    /// The battery level
+    pub battery: u8,
+
+    /// The report's timestamp, in units of roughly 5.33 microseconds
+    pub timestamp: u16,
+}
+
+/// Implement serde support for a bitflags type by (de)serializing its raw bits, along with a
+/// schema saying as much.
+///
+/// Bits which aren't any flag's are dropped when deserializing, so that a client setting a
+/// reserved bit doesn't lose its whole update. [x360_buttons_strict] rejects them instead.
+macro_rules! impl_bits_serde {
+    ($name:ident, $bits:ty) => {
+        #[cfg(feature = "serde")]
+        impl serde::Serialize for $name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                self.bits().serialize(serializer)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let value = <$bits as serde::Deserialize<'de>>::deserialize(deserializer)?;
+                Ok(Self::from_bits_truncate(value))
+            }
+        }
+
+        #[cfg(feature = "schemars")]
+        impl schemars::JsonSchema for $name {
+            fn schema_name() -> alloc::string::String {
+                stringify!($name).into()
+            }
+
+            fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+                <$bits>::json_schema(gen)
+            }
+        }
+    };
+}
+
+impl_bits_serde!(X360Buttons, u16);
+impl_bits_serde!(DS4Buttons, u16);
+impl_bits_serde!(DS4SpecialButtons, u8);
+
+/// The canonical name of every xbox 360 button, in the order they're displayed
+const X360_BUTTON_NAMES: &[(&str, X360Buttons)] = &[
+    ("DPAD_UP", X360Buttons::DPAD_UP),
+    ("DPAD_DOWN", X360Buttons::DPAD_DOWN),
+    ("DPAD_LEFT", X360Buttons::DPAD_LEFT),
+    ("DPAD_RIGHT", X360Buttons::DPAD_RIGHT),
+    ("START", X360Buttons::START),
+    ("BACK", X360Buttons::BACK),
+    ("LEFT_THUMB", X360Buttons::LEFT_THUMB),
+    ("RIGHT_THUMB", X360Buttons::RIGHT_THUMB),
+    ("LEFT_SHOULDER", X360Buttons::LEFT_SHOULDER),
+    ("RIGHT_SHOULDER", X360Buttons::RIGHT_SHOULDER),
+    ("GUIDE", X360Buttons::GUIDE),
+    ("A", X360Buttons::A),
+    ("B", X360Buttons::B),
+    ("X", X360Buttons::X),
+    ("Y", X360Buttons::Y),
+];
+
+/// The other names xbox 360 buttons are parsed from
+const X360_BUTTON_ALIASES: &[(&str, X360Buttons)] = &[
+    ("LB", X360Buttons::LEFT_SHOULDER),
+    ("RB", X360Buttons::RIGHT_SHOULDER),
+    ("LS", X360Buttons::LEFT_THUMB),
+    ("RS", X360Buttons::RIGHT_THUMB),
+];
+
+/// Displays the buttons by their names separated by pipes, e.g. `DPAD_UP|START|A`, or as an
+/// empty string if none are pressed
+impl fmt::Display for X360Buttons {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = X360_BUTTON_NAMES
+            .iter()
+            .filter(|(_, button)| self.contains(*button))
+            .map(|(name, _)| *name);
+        for (i, name) in names.enumerate() {
+            if i > 0 {
+                f.write_str("|")?;
+            }
+            f.write_str(name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses pipe-separated button names, as displayed or by their aliases (`LB` and `RB` for the
+/// shoulders, `LS` and `RS` for the thumbsticks), ignoring case and whitespace around names.
+/// An empty string is no buttons at all.
+impl FromStr for X360Buttons {
+    type Err = ParseButtonsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().is_empty() {
+            return Ok(Self::empty());
+        }
+
+        s.split('|')
+            .map(str::trim)
+            .try_fold(Self::empty(), |buttons, name| {
+                X360_BUTTON_NAMES
+                    .iter()
+                    .chain(X360_BUTTON_ALIASES)
+                    .find(|(known, _)| known.eq_ignore_ascii_case(name))
+                    .map(|(_, button)| buttons | *button)
+                    .ok_or_else(|| ParseButtonsError(name.to_string()))
+            })
+    }
+}
+
+/// The error returned when parsing [X360Buttons] from a name which isn't a button's
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseButtonsError(String);
+
+impl fmt::Display for ParseButtonsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown button {:?}", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseButtonsError {}
+
+impl ParseButtonsError {
+    /// The name which isn't a button's, with the whitespace around it trimmed
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Serde support for [X360Buttons] as a string of pipe-separated names rather than raw bits, to
+/// be used via `#[serde(with = "vigem_client_c::gamepad_state::x360_buttons_names")]`
 #[cfg(feature = "serde")]
-impl serde::Serialize for X360Buttons {
-    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        self.bits().serialize(serializer)
+pub mod x360_buttons_names {
+    use alloc::string::String;
+
+    use super::X360Buttons;
+
+    /// Serialize the buttons as they're [displayed](X360Buttons#impl-Display)
+    pub fn serialize<S: serde::Serializer>(
+        buttons: &X360Buttons,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(buttons)
+    }
+
+    /// Deserialize the buttons by [parsing](X360Buttons#impl-FromStr) their names
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<X360Buttons, D::Error> {
+        let names = <String as serde::Deserialize<'de>>::deserialize(deserializer)?;
+        names.parse().map_err(serde::de::Error::custom)
     }
 }
 
+/// Serde support for [X360Buttons] as raw bits which fails on bits that aren't any button's,
+/// instead of dropping them, to be used via
+/// `#[serde(with = "vigem_client_c::gamepad_state::x360_buttons_strict")]`
 #[cfg(feature = "serde")]
-impl<'de> serde::Deserialize<'de> for X360Buttons {
-    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        let value = <u16 as serde::Deserialize<'de>>::deserialize(deserializer)?;
+pub mod x360_buttons_strict {
+    use super::X360Buttons;
+
+    /// Serialize the buttons as their raw bits, like [X360Buttons] does itself
+    pub fn serialize<S: serde::Serializer>(
+        buttons: &X360Buttons,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serde::Serialize::serialize(buttons, serializer)
+    }
+
+    /// Deserialize the buttons from their raw bits, failing if any bit isn't a button's
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<X360Buttons, D::Error> {
+        let bits = <u16 as serde::Deserialize<'de>>::deserialize(deserializer)?;
+        X360Buttons::from_bits(bits).ok_or_else(|| {
+            serde::de::Error::custom(alloc::format!("Invalid X360Buttons: {:#x}", bits))
+        })
+    }
+}
+
+impl StickPosition {
+    /// The thumbstick at rest
+    pub const CENTER: Self = Self::new(0, 0);
+
+    /// A thumbstick at the given X and Y axes
+    pub const fn new(x: i16, y: i16) -> Self {
+        Self { x, y }
+    }
+
+    /// How far the thumbstick is from the center, where `1.0` is the edge of the range straight
+    /// along either axis, up to about `1.414` in the corners of the square range.
+    ///
+    /// Axes are converted with [axis_to_f32], so `i16::MIN` is as far out as `i16::MAX` rather
+    /// than a step further.
+    #[cfg(feature = "std")]
+    pub fn magnitude(self) -> f32 {
+        axis_to_f32(self.x).hypot(axis_to_f32(self.y))
+    }
+
+    /// Which way the thumbstick points, in radians counterclockwise from straight right, from
+    /// `-π` to `π`. The center points straight right.
+    #[cfg(feature = "std")]
+    pub fn angle(self) -> f32 {
+        axis_to_f32(self.y).atan2(axis_to_f32(self.x))
+    }
+
+    /// The thumbstick pointing at `angle`, as given by [angle](Self::angle), `magnitude` away
+    /// from the center, as given by [magnitude](Self::magnitude).
+    ///
+    /// Axes are converted with [axis_from_f32], so they're clamped to the square range and a
+    /// magnitude of `1.0` reaches both `i16::MAX` and `i16::MIN`. NaN in either gives the center.
+    #[cfg(feature = "std")]
+    pub fn from_polar(angle: f32, magnitude: f32) -> Self {
+        if angle.is_nan() || magnitude.is_nan() {
+            return Self::CENTER;
+        }
+        let (sin, cos) = angle.sin_cos();
+        Self::new(
+            axis_from_f32(cos * magnitude),
+            axis_from_f32(sin * magnitude),
+        )
+    }
 
-        Self::from_bits(value)
-            .ok_or_else(|| serde::de::Error::custom(format!("Invalid X360Buttons: {:#x}", value)))
+    /// The thumbstick pulled in to the circular range a physical one covers, which games expect
+    /// rather than the square one, keeping its direction. Positions already within it are left
+    /// as they are.
+    #[cfg(feature = "std")]
+    pub fn clamped_circle(self) -> Self {
+        let magnitude = self.magnitude();
+        if magnitude <= 1.0 {
+            return self;
+        }
+        Self::new(
+            axis_from_f32(axis_to_f32(self.x) / magnitude),
+            axis_from_f32(axis_to_f32(self.y) / magnitude),
+        )
+    }
+
+    /// The position `t` of the way from this one to `other`, with `t` clamped to `0.0..=1.0`
+    /// and NaN treated as `0.0`. Each axis is rounded to the nearest integer, with halves
+    /// rounded away from zero.
+    #[cfg(feature = "std")]
+    pub fn lerp(self, other: Self, t: f32) -> Self {
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        let lerp = |from: i16, to: i16| {
+            let delta = (i32::from(to) - i32::from(from)) as f32;
+            (f32::from(from) + delta * t).round() as i16
+        };
+        Self::new(lerp(self.x, other.x), lerp(self.y, other.y))
+    }
+}
+
+impl From<(i16, i16)> for StickPosition {
+    fn from((x, y): (i16, i16)) -> Self {
+        Self::new(x, y)
+    }
+}
+
+impl From<StickPosition> for (i16, i16) {
+    fn from(stick: StickPosition) -> Self {
+        (stick.x, stick.y)
+    }
+}
+
+/// Described as the `[x, y]` it's serialized as
+#[cfg(feature = "schemars")]
+impl schemars::JsonSchema for StickPosition {
+    fn schema_name() -> alloc::string::String {
+        "StickPosition".into()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        <(i16, i16)>::json_schema(gen)
+    }
+}
+
+impl PartialEq<(i16, i16)> for StickPosition {
+    fn eq(&self, other: &(i16, i16)) -> bool {
+        (self.x, self.y) == *other
+    }
+}
+
+impl PartialEq<StickPosition> for (i16, i16) {
+    fn eq(&self, other: &StickPosition) -> bool {
+        other == self
     }
 }
 
+impl X360State {
+    /// Start building a state from a neutral one
+    pub const fn builder() -> X360StateBuilder {
+        X360StateBuilder::new()
+    }
+
+    /// Press the given buttons, leaving the others as they are
+    pub fn press(&mut self, buttons: X360Buttons) {
+        self.buttons.insert(buttons);
+    }
+
+    /// Release the given buttons, leaving the others as they are
+    pub fn release(&mut self, buttons: X360Buttons) {
+        self.buttons.remove(buttons);
+    }
+
+    /// Toggle the given buttons, leaving the others as they are
+    pub fn toggle(&mut self, buttons: X360Buttons) {
+        self.buttons.toggle(buttons);
+    }
+
+    /// Set the left thumbstick from coordinates in `-1.0..=1.0`.
+    ///
+    /// See [axis_from_f32] for how the coordinates are converted.
+    #[cfg(feature = "std")]
+    pub fn set_left_stick_f32(&mut self, x: f32, y: f32) {
+        self.left_thumbstick = StickPosition::new(axis_from_f32(x), axis_from_f32(y));
+    }
+
+    /// Set the right thumbstick from coordinates in `-1.0..=1.0`.
+    ///
+    /// See [axis_from_f32] for how the coordinates are converted.
+    #[cfg(feature = "std")]
+    pub fn set_right_stick_f32(&mut self, x: f32, y: f32) {
+        self.right_thumbstick = StickPosition::new(axis_from_f32(x), axis_from_f32(y));
+    }
+
+    /// Set the left and right triggers from values in `0.0..=1.0`.
+    ///
+    /// Values are clamped to the range, scaled to `0..=255` and rounded to the nearest integer.
+    /// NaN is treated as `0.0`.
+    #[cfg(feature = "std")]
+    pub fn set_triggers_f32(&mut self, left: f32, right: f32) {
+        self.left_trigger = trigger_from_f32(left);
+        self.right_trigger = trigger_from_f32(right);
+    }
+
+    /// Interpolate between this state and another, `step` steps out of `steps` of the way.
+    ///
+    /// Thumbsticks and triggers are interpolated linearly and rounded to the nearest integer,
+    /// with halves rounded away from this state. Buttons switch over to the other state's at the
+    /// midpoint, which for an odd number of steps is the first step past the middle. Step `0`
+    /// is exactly this state and step `steps` (or any step beyond it) is exactly `to`.
+    pub fn interpolate(self, to: Self, step: u32, steps: u32) -> Self {
+        if step >= steps {
+            return to;
+        }
+
+        let lerp = |from: i32, to: i32| -> i32 {
+            let (delta, step, steps) = (i64::from(to - from), i64::from(step), i64::from(steps));
+            // Round half away from zero, so that the result doesn't depend on the direction
+            let scaled = delta * step * 2;
+            let offset = (scaled + scaled.signum() * steps) / (steps * 2);
+            from + offset as i32
+        };
+        let lerp_i16 = |from: i16, to: i16| lerp(from.into(), to.into()) as i16;
+        let lerp_u8 = |from: u8, to: u8| lerp(from.into(), to.into()) as u8;
+        let lerp_stick = |from: StickPosition, to: StickPosition| {
+            StickPosition::new(lerp_i16(from.x, to.x), lerp_i16(from.y, to.y))
+        };
+
+        Self {
+            buttons: if step * 2 >= steps {
+                to.buttons
+            } else {
+                self.buttons
+            },
+            left_trigger: lerp_u8(self.left_trigger, to.left_trigger),
+            right_trigger: lerp_u8(self.right_trigger, to.right_trigger),
+            left_thumbstick: lerp_stick(self.left_thumbstick, to.left_thumbstick),
+            right_thumbstick: lerp_stick(self.right_thumbstick, to.right_thumbstick),
+        }
+    }
+
+    /// Apply a radial deadzone to both thumbsticks.
+    ///
+    /// A stick whose distance from the center is at most `radial` (as a fraction of the full
+    /// range) is centered. Outside of the deadzone the distance is rescaled so that the stick
+    /// still covers the full range, starting from zero right at the deadzone's edge, while
+    /// keeping its direction. Distances beyond `1.0`, as found in the corners of the square
+    /// range, are treated as `1.0`. A deadzone of `0.0` or less leaves the sticks untouched and
+    /// one of `1.0` or more always centers them.
+    #[cfg(feature = "std")]
+    pub fn apply_deadzone(&mut self, radial: f32) {
+        self.left_thumbstick = deadzone(self.left_thumbstick, radial);
+        self.right_thumbstick = deadzone(self.right_thumbstick, radial);
+    }
+
+    /// The size of a state encoded by [to_bytes](Self::to_bytes)
+    #[cfg(feature = "wire")]
+    pub const WIRE_SIZE: usize = 12;
+
+    /// Encode this state in a compact fixed-size binary format.
+    ///
+    /// The layout is the buttons as a `u16`, the left and right triggers as `u8`s, then the
+    /// left thumbstick's X and Y and the right thumbstick's X and Y as `i16`s, all little-endian.
+    #[cfg(feature = "wire")]
+    pub fn to_bytes(self) -> [u8; Self::WIRE_SIZE] {
+        let mut bytes = [0; Self::WIRE_SIZE];
+        bytes[0..2].copy_from_slice(&self.buttons.bits().to_le_bytes());
+        bytes[2] = self.left_trigger;
+        bytes[3] = self.right_trigger;
+        bytes[4..6].copy_from_slice(&self.left_thumbstick.x.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.left_thumbstick.y.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.right_thumbstick.x.to_le_bytes());
+        bytes[10..12].copy_from_slice(&self.right_thumbstick.y.to_le_bytes());
+        bytes
+    }
+
+    /// Decode a state encoded by [to_bytes](Self::to_bytes), dropping any bits of the buttons
+    /// which aren't a button's
+    #[cfg(feature = "wire")]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, WireError> {
+        Self::decode(bytes, |bits| Ok(X360Buttons::from_bits_truncate(bits)))
+    }
+
+    /// Decode a state encoded by [to_bytes](Self::to_bytes), failing with
+    /// [WireError::InvalidButtons] if any bit of the buttons isn't a button's
+    #[cfg(feature = "wire")]
+    pub fn from_bytes_strict(bytes: &[u8]) -> Result<Self, WireError> {
+        Self::decode(bytes, |bits| {
+            X360Buttons::from_bits(bits).ok_or(WireError::InvalidButtons(bits))
+        })
+    }
+
+    #[cfg(feature = "wire")]
+    fn decode(
+        bytes: &[u8],
+        buttons: impl FnOnce(u16) -> Result<X360Buttons, WireError>,
+    ) -> Result<Self, WireError> {
+        if bytes.len() != Self::WIRE_SIZE {
+            let length =
+                <u32 as core::convert::TryFrom<usize>>::try_from(bytes.len()).unwrap_or(u32::MAX);
+            return Err(WireError::InvalidLength(length));
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let i16_at = |i: usize| i16::from_le_bytes([bytes[i], bytes[i + 1]]);
+
+        Ok(Self {
+            buttons: buttons(u16_at(0))?,
+            left_trigger: bytes[2],
+            right_trigger: bytes[3],
+            left_thumbstick: StickPosition::new(i16_at(4), i16_at(6)),
+            right_thumbstick: StickPosition::new(i16_at(8), i16_at(10)),
+        })
+    }
+}
+
+/// An xbox 360 report exactly as ViGEmBus takes it, laid out like ViGEmClient's `XUSB_REPORT`.
+///
+/// Unlike an [X360State] any bits of the buttons may be set, including those which aren't
+/// one of the [X360Buttons], for games which read more than the buttons modelled here. This is
+/// what [update_raw](crate::client::Target::update_raw) sends without any checks.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct XusbReport {
+    /// The buttons' bits, which are those of [X360Buttons] where they're known
+    pub buttons: u16,
+    pub left_trigger: u8,
+    pub right_trigger: u8,
+    pub thumb_lx: i16,
+    pub thumb_ly: i16,
+    pub thumb_rx: i16,
+    pub thumb_ry: i16,
+}
+
+impl XusbReport {
+    /// The bits of the buttons which aren't any of the [X360Buttons], and which an [X360State]
+    /// made from this report doesn't have
+    pub const fn unknown_buttons(&self) -> u16 {
+        self.buttons & !X360Buttons::all().bits()
+    }
+}
+
+impl From<X360State> for XusbReport {
+    fn from(state: X360State) -> Self {
+        Self {
+            buttons: state.buttons.bits(),
+            left_trigger: state.left_trigger,
+            right_trigger: state.right_trigger,
+            thumb_lx: state.left_thumbstick.x,
+            thumb_ly: state.left_thumbstick.y,
+            thumb_rx: state.right_thumbstick.x,
+            thumb_ry: state.right_thumbstick.y,
+        }
+    }
+}
+
+impl X360State {
+    /// The state a report stands for, dropping any bits of the buttons which aren't a button's.
+    /// Those are still in the report, as its [unknown buttons](XusbReport::unknown_buttons).
+    pub const fn from_raw(report: XusbReport) -> Self {
+        Self {
+            buttons: X360Buttons::from_bits_truncate(report.buttons),
+            left_trigger: report.left_trigger,
+            right_trigger: report.right_trigger,
+            left_thumbstick: StickPosition::new(report.thumb_lx, report.thumb_ly),
+            right_thumbstick: StickPosition::new(report.thumb_rx, report.thumb_ry),
+        }
+    }
+}
+
+/// What [X360State::sanitize] does about a dpad held in opposite directions at once, which no
+/// real dpad can be and which games make nonsense of
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum SocdPolicy {
+    /// Hold neither direction
+    Neutral,
+
+    /// Hold whichever direction was pressed last, which the previous state tells, or neither
+    /// without one
+    LastInput,
+
+    /// Hold up over down, but neither left nor right, like most arcade sticks
+    UpPriority,
+}
+
+impl X360State {
+    /// The dpad's directions, paired with their opposites
+    const OPPOSITES: [(X360Buttons, X360Buttons); 2] = [
+        (X360Buttons::DPAD_UP, X360Buttons::DPAD_DOWN),
+        (X360Buttons::DPAD_LEFT, X360Buttons::DPAD_RIGHT),
+    ];
+
+    /// Settle the dpad being held in opposite directions at once according to `policy`, leaving
+    /// everything else as it is.
+    ///
+    /// [SocdPolicy::LastInput] goes by which direction of each pair `previous` holds: the other
+    /// one is the one pressed last. For that to hold up while both stay pressed, `previous`
+    /// should hold, for each pair, the direction last held on its own, or neither if both were
+    /// released since. The other policies ignore it.
+    pub fn sanitize(&mut self, policy: SocdPolicy, previous: Option<&X360State>) {
+        for &(first, second) in &Self::OPPOSITES {
+            let both = first | second;
+            if !self.buttons.contains(both) {
+                continue;
+            }
+            let held = match policy {
+                SocdPolicy::Neutral => X360Buttons::empty(),
+                SocdPolicy::UpPriority if first == X360Buttons::DPAD_UP => first,
+                SocdPolicy::UpPriority => X360Buttons::empty(),
+                SocdPolicy::LastInput => match previous.map(|previous| previous.buttons & both) {
+                    Some(last) if last == first => second,
+                    Some(last) if last == second => first,
+                    _ => X360Buttons::empty(),
+                },
+            };
+            self.buttons.remove(both);
+            self.buttons.insert(held);
+        }
+    }
+
+    /// Remember which direction of each pair of opposites this state holds on its own, on top
+    /// of `last`, to be the previous state [SocdPolicy::LastInput] goes by
+    pub fn remember_dpad(&self, last: &mut X360State) {
+        for &(first, second) in &Self::OPPOSITES {
+            let both = first | second;
+            if !self.buttons.contains(both) {
+                last.buttons.remove(both);
+                last.buttons.insert(self.buttons & both);
+            }
+        }
+    }
+}
+
+/// One of an xbox 360 controller's triggers or thumbstick axes
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum X360Axis {
+    LeftTrigger,
+    RightTrigger,
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+}
+
+impl X360Axis {
+    /// The axis' short name, e.g. `LT` for the left trigger or `RY` for the right thumbstick's
+    /// Y axis
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::LeftTrigger => "LT",
+            Self::RightTrigger => "RT",
+            Self::LeftX => "LX",
+            Self::LeftY => "LY",
+            Self::RightX => "RX",
+            Self::RightY => "RY",
+        }
+    }
+}
+
+/// An axis which moved from one state to the next, with its values in both. Triggers' values
+/// are the same as in the states, from 0 to 255.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AxisChange {
+    pub axis: X360Axis,
+    pub from: i16,
+    pub to: i16,
+}
+
+/// What changed from one xbox 360 state to another, as found by [X360State::diff]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StateDiff {
+    pub pressed: X360Buttons,
+    pub released: X360Buttons,
+
+    /// The axes which moved by more than the threshold, triggers first and then the left
+    /// thumbstick's and the right one's
+    pub axes: Vec<AxisChange>,
+}
+
+impl StateDiff {
+    /// How far a thumbstick axis has to move for [X360State::diff] to tell, which leaves out
+    /// the jitter of a thumb resting on a touchscreen stick
+    pub const DEFAULT_THRESHOLD: u16 = 256;
+
+    /// Whether nothing changed
+    pub fn is_empty(&self) -> bool {
+        self.pressed.is_empty() && self.released.is_empty() && self.axes.is_empty()
+    }
+}
+
+/// Displays the changes separated by spaces, e.g. `+A -DPAD_LEFT LT:0→255 LX:120→-3400`, with
+/// pressed buttons prefixed by `+` and released ones by `-`, or as an empty string if nothing
+/// changed
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let buttons = X360_BUTTON_NAMES.iter().filter_map(|&(name, button)| {
+            if self.pressed.contains(button) {
+                Some(('+', name))
+            } else if self.released.contains(button) {
+                Some(('-', name))
+            } else {
+                None
+            }
+        });
+        let mut separator = "";
+        for (sign, name) in buttons {
+            write!(f, "{}{}{}", separator, sign, name)?;
+            separator = " ";
+        }
+        for change in &self.axes {
+            write!(
+                f,
+                "{}{}:{}→{}",
+                separator,
+                change.axis.name(),
+                change.from,
+                change.to
+            )?;
+            separator = " ";
+        }
+        Ok(())
+    }
+}
+
+impl X360State {
+    /// What changed from this state to `other`, leaving out thumbstick axes which moved by no
+    /// more than [StateDiff::DEFAULT_THRESHOLD]
+    pub fn diff(&self, other: &X360State) -> StateDiff {
+        self.diff_beyond(other, StateDiff::DEFAULT_THRESHOLD)
+    }
+
+    /// What changed from this state to `other`, leaving out thumbstick axes which moved by no
+    /// more than `threshold` and triggers which moved by no more than as much of their range,
+    /// so that a threshold of 0 leaves out nothing that changed
+    pub fn diff_beyond(&self, other: &X360State, threshold: u16) -> StateDiff {
+        // A trigger's step is 257 of a thumbstick axis', their ranges being 255 and 65535 long
+        let trigger_threshold = threshold / 257;
+        let axes = [
+            (
+                X360Axis::LeftTrigger,
+                i16::from(self.left_trigger),
+                i16::from(other.left_trigger),
+                trigger_threshold,
+            ),
+            (
+                X360Axis::RightTrigger,
+                i16::from(self.right_trigger),
+                i16::from(other.right_trigger),
+                trigger_threshold,
+            ),
+            (
+                X360Axis::LeftX,
+                self.left_thumbstick.x,
+                other.left_thumbstick.x,
+                threshold,
+            ),
+            (
+                X360Axis::LeftY,
+                self.left_thumbstick.y,
+                other.left_thumbstick.y,
+                threshold,
+            ),
+            (
+                X360Axis::RightX,
+                self.right_thumbstick.x,
+                other.right_thumbstick.x,
+                threshold,
+            ),
+            (
+                X360Axis::RightY,
+                self.right_thumbstick.y,
+                other.right_thumbstick.y,
+                threshold,
+            ),
+        ];
+        StateDiff {
+            pressed: other.buttons - self.buttons,
+            released: self.buttons - other.buttons,
+            axes: axes
+                .iter()
+                .filter(|&&(_, from, to, threshold)| {
+                    (i32::from(to) - i32::from(from)).unsigned_abs() > u32::from(threshold)
+                })
+                .map(|&(axis, from, to, _)| AxisChange { axis, from, to })
+                .collect(),
+        }
+    }
+}
+
+/// Lay out an xbox 360 state on a dualshock 4, the way games show the buttons' counterparts:
+/// A is cross, B is circle, X is square, Y is triangle, back is share, start is options and the
+/// guide button is the PS button. Pulling a trigger at all also presses its digital button.
+impl From<X360State> for DS4State {
+    fn from(state: X360State) -> Self {
+        const BUTTONS: [(X360Buttons, DS4Buttons); 10] = [
+            (X360Buttons::A, DS4Buttons::CROSS),
+            (X360Buttons::B, DS4Buttons::CIRCLE),
+            (X360Buttons::X, DS4Buttons::SQUARE),
+            (X360Buttons::Y, DS4Buttons::TRIANGLE),
+            (X360Buttons::LEFT_SHOULDER, DS4Buttons::SHOULDER_LEFT),
+            (X360Buttons::RIGHT_SHOULDER, DS4Buttons::SHOULDER_RIGHT),
+            (X360Buttons::BACK, DS4Buttons::SHARE),
+            (X360Buttons::START, DS4Buttons::OPTIONS),
+            (X360Buttons::LEFT_THUMB, DS4Buttons::THUMB_LEFT),
+            (X360Buttons::RIGHT_THUMB, DS4Buttons::THUMB_RIGHT),
+        ];
+
+        let mut buttons = DS4Buttons::empty();
+        for &(x360, ds4) in &BUTTONS {
+            buttons.set(ds4, state.buttons.contains(x360));
+        }
+        buttons.set(DS4Buttons::TRIGGER_LEFT, state.left_trigger > 0);
+        buttons.set(DS4Buttons::TRIGGER_RIGHT, state.right_trigger > 0);
+
+        let mut special = DS4SpecialButtons::empty();
+        special.set(
+            DS4SpecialButtons::PS,
+            state.buttons.contains(X360Buttons::GUIDE),
+        );
+
+        // Opposite directions pressed together cancel each other out
+        let axis = |positive, negative| {
+            i8::from(state.buttons.contains(positive)) - i8::from(state.buttons.contains(negative))
+        };
+        let dpad = match (
+            axis(X360Buttons::DPAD_RIGHT, X360Buttons::DPAD_LEFT),
+            axis(X360Buttons::DPAD_UP, X360Buttons::DPAD_DOWN),
+        ) {
+            (0, 1) => DS4Dpad::North,
+            (1, 1) => DS4Dpad::NorthEast,
+            (1, 0) => DS4Dpad::East,
+            (1, -1) => DS4Dpad::SouthEast,
+            (0, -1) => DS4Dpad::South,
+            (-1, -1) => DS4Dpad::SouthWest,
+            (-1, 0) => DS4Dpad::West,
+            (-1, 1) => DS4Dpad::NorthWest,
+            _ => DS4Dpad::None,
+        };
+
+        // The dualshock's Y axes grow downwards, unlike the xbox 360 controller's
+        let axis = |value: i16| ((i32::from(value) >> 8) + 0x80) as u8;
+        let stick = |stick: StickPosition| (axis(stick.x), axis(stick.y.saturating_neg()));
+
+        Self {
+            buttons,
+            special,
+            dpad,
+            left_trigger: state.left_trigger,
+            right_trigger: state.right_trigger,
+            left_thumbstick: stick(state.left_thumbstick),
+            right_thumbstick: stick(state.right_thumbstick),
+        }
+    }
+}
+
+/// Convert a thumbstick coordinate in `-1.0..=1.0` to its raw value.
+///
+/// Values are clamped to the range and NaN is treated as `0.0`. Since `i16` has one more
+/// negative value than positive ones, positive values are scaled by `32767` and negative ones
+/// by `32768`, so that both `1.0` and `-1.0` reach the end of the range. The result is rounded
+/// to the nearest integer, with halves rounded away from zero.
+#[cfg(feature = "std")]
+pub fn axis_from_f32(value: f32) -> i16 {
+    let value = if value.is_nan() {
+        0.0
+    } else {
+        value.clamp(-1.0, 1.0)
+    };
+
+    if value >= 0.0 {
+        (value * f32::from(i16::MAX)).round() as i16
+    } else {
+        (value * -f32::from(i16::MIN)).round() as i16
+    }
+}
+
+/// Convert a raw thumbstick value to a coordinate in `-1.0..=1.0`, the inverse of [axis_from_f32]
+pub fn axis_to_f32(value: i16) -> f32 {
+    if value >= 0 {
+        f32::from(value) / f32::from(i16::MAX)
+    } else {
+        f32::from(value) / -f32::from(i16::MIN)
+    }
+}
+
+#[cfg(feature = "std")]
+fn trigger_from_f32(value: f32) -> u8 {
+    let value = if value.is_nan() {
+        0.0
+    } else {
+        value.clamp(0.0, 1.0)
+    };
+    (value * f32::from(u8::MAX)).round() as u8
+}
+
+#[cfg(feature = "std")]
+fn deadzone(stick: StickPosition, radial: f32) -> StickPosition {
+    if radial.is_nan() || radial <= 0.0 {
+        return stick;
+    }
+
+    let distance = stick.magnitude();
+    if distance <= radial || radial >= 1.0 {
+        return StickPosition::CENTER;
+    }
+
+    let scale = (distance.min(1.0) - radial) / (1.0 - radial) / distance;
+    StickPosition::new(
+        axis_from_f32(axis_to_f32(stick.x) * scale),
+        axis_from_f32(axis_to_f32(stick.y) * scale),
+    )
+}
+
+/// Represents the ways decoding a binary encoded state can fail
+#[cfg(feature = "wire")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireError {
+    InvalidLength(u32),
+    InvalidButtons(u16),
+}
+
+#[cfg(feature = "wire")]
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidLength(length) => write!(f, "Invalid state length {}", length),
+            Self::InvalidButtons(buttons) => write!(f, "Invalid X360Buttons: {:#x}", buttons),
+        }
+    }
+}
+
+#[cfg(all(feature = "wire", feature = "std"))]
+impl std::error::Error for WireError {}
+
+/// A builder for [X360State], created via [X360State::builder].
+///
+/// All of its methods are `const`, so it can be used to build states in statics.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct X360StateBuilder {
+    state: X360State,
+}
+
+impl X360StateBuilder {
+    /// Create a builder starting from a neutral state
+    pub const fn new() -> Self {
+        Self {
+            state: X360State {
+                buttons: X360Buttons::empty(),
+                left_trigger: 0,
+                right_trigger: 0,
+                left_thumbstick: StickPosition::CENTER,
+                right_thumbstick: StickPosition::CENTER,
+            },
+        }
+    }
+
+    /// Press the given buttons in addition to any already pressed ones
+    pub const fn press(mut self, buttons: X360Buttons) -> Self {
+        self.state.buttons =
+            X360Buttons::from_bits_truncate(self.state.buttons.bits() | buttons.bits());
+        self
+    }
+
+    /// Set the left trigger's value
+    pub const fn left_trigger(mut self, value: u8) -> Self {
+        self.state.left_trigger = value;
+        self
+    }
+
+    /// Set the right trigger's value
+    pub const fn right_trigger(mut self, value: u8) -> Self {
+        self.state.right_trigger = value;
+        self
+    }
+
+    /// Set the left thumbstick's X and Y axes
+    pub const fn left_stick(mut self, x: i16, y: i16) -> Self {
+        self.state.left_thumbstick = StickPosition::new(x, y);
+        self
+    }
+
+    /// Set the right thumbstick's X and Y axes
+    pub const fn right_stick(mut self, x: i16, y: i16) -> Self {
+        self.state.right_thumbstick = StickPosition::new(x, y);
+        self
+    }
+
+    /// Finish building the state
+    pub const fn build(self) -> X360State {
+        self.state
+    }
+}
+
+// Conversions to the reports ViGEmClient takes
+
+#[cfg(any(feature = "ffi", feature = "mock"))]
 impl X360State {
     pub(crate) fn to_xusb_report(self) -> ffi::_XUSB_REPORT {
+        XusbReport::from(self).to_ffi()
+    }
+}
+
+#[cfg(any(feature = "ffi", feature = "mock"))]
+impl XusbReport {
+    pub(crate) fn to_ffi(self) -> ffi::_XUSB_REPORT {
         ffi::_XUSB_REPORT {
-            wButtons: self.buttons.bits(),
+            wButtons: self.buttons,
             bLeftTrigger: self.left_trigger,
             bRightTrigger: self.right_trigger,
-            sThumbLX: self.left_thumbstick.0,
-            sThumbLY: self.left_thumbstick.1,
-            sThumbRX: self.right_thumbstick.0,
-            sThumbRY: self.right_thumbstick.1,
+            sThumbLX: self.thumb_lx,
+            sThumbLY: self.thumb_ly,
+            sThumbRX: self.thumb_rx,
+            sThumbRY: self.thumb_ry,
+        }
+    }
+}
+
+#[cfg(any(feature = "ffi", feature = "mock"))]
+impl DS4TouchPoint {
+    fn to_ds4_touch_bytes(self) -> (u8, [u8; 3]) {
+        // The top bit is set when the finger is *not* touching
+        let tracking = (self.id & 0x7F) | if self.active { 0 } else { 0x80 };
+        (tracking, self.coordinates)
+    }
+}
+
+#[cfg(any(feature = "ffi", feature = "mock"))]
+impl DS4State {
+    fn buttons_word(self) -> u16 {
+        self.buttons.bits() | self.dpad as u16
+    }
+
+    pub(crate) fn to_ds4_report(self) -> ffi::_DS4_REPORT {
+        ffi::_DS4_REPORT {
+            bThumbLX: self.left_thumbstick.0,
+            bThumbLY: self.left_thumbstick.1,
+            bThumbRX: self.right_thumbstick.0,
+            bThumbRY: self.right_thumbstick.1,
+            wButtons: self.buttons_word(),
+            bSpecial: self.special.bits(),
+            bTriggerL: self.left_trigger,
+            bTriggerR: self.right_trigger,
         }
     }
 }
+
+#[cfg(any(feature = "ffi", feature = "mock"))]
+impl DS4StateEx {
+    pub(crate) fn to_ds4_report_ex(self, packet_counter: u8) -> ffi::_DS4_REPORT_EX {
+        // SAFETY: The report is plain old data, for which all zeroes is a valid value
+        let mut report: ffi::_DS4_REPORT_EX = unsafe { std::mem::zeroed() };
+        // SAFETY: Both union variants are plain old data covering the same bytes
+        let r = unsafe { &mut report.__bindgen_anon_1.Report };
+
+        r.bThumbLX = self.state.left_thumbstick.0;
+        r.bThumbLY = self.state.left_thumbstick.1;
+        r.bThumbRX = self.state.right_thumbstick.0;
+        r.bThumbRY = self.state.right_thumbstick.1;
+        r.wButtons = self.state.buttons_word();
+        r.bSpecial = self.state.special.bits();
+        r.bTriggerL = self.state.left_trigger;
+        r.bTriggerR = self.state.right_trigger;
+        r.wTimestamp = self.timestamp;
+        r.bBatteryLvl = self.battery;
+        r.wGyroX = self.gyro.0;
+        r.wGyroY = self.gyro.1;
+        r.wGyroZ = self.gyro.2;
+        r.wAccelX = self.accel.0;
+        r.wAccelY = self.accel.1;
+        r.wAccelZ = self.accel.2;
+
+        let (tracking1, data1) = self.touches[0].to_ds4_touch_bytes();
+        let (tracking2, data2) = self.touches[1].to_ds4_touch_bytes();
+        r.bTouchPacketsN = 1;
+        r.sCurrentTouch = ffi::_DS4_TOUCH {
+            bPacketCounter: self.packet_counter.unwrap_or(packet_counter),
+            bIsUpTrackingNum1: tracking1,
+            bTouchData1: data1,
+            bIsUpTrackingNum2: tracking2,
+            bTouchData2: data2,
+        };
+
+        report
+    }
+}
@@ -1,5 +1,13 @@
 //! An opinionated client for the [Virtual Gamepad Emulation Framework](https://vigem.org/) utilizing [ViGEmClient](https://github.com/ViGEm/ViGEmClient/)
+//!
+//! Everything talking to ViGEmClient is behind the default `ffi` feature. Without it only the
+//! [gamepad states](gamepad_state) are left, which don't need `std` either unless its feature
+//! is on, so that they can be shared with e.g. a WebAssembly build of a controller.
+//!
+//! With the `mock` feature the client drives an [in-memory bus](mock::MockBus) instead of
+//! ViGEmBus, which lets code using it be tested anywhere, e.g. on CI runners without the driver.
 
+#![cfg_attr(not(feature = "std"), no_std)]
 #![warn(
     absolute_paths_not_starting_with_crate,
     elided_lifetimes_in_paths,
@@ -28,10 +36,39 @@
     variant_size_differences
 )]
 
+extern crate alloc;
+
+// Only used by the integration tests
+#[cfg(test)]
+use trybuild as _;
+
+#[cfg(all(feature = "enumerate", windows))]
+mod bus;
+#[cfg(any(feature = "ffi", feature = "mock"))]
 pub mod client;
+#[cfg(any(feature = "ffi", feature = "mock"))]
 pub mod error;
 pub mod gamepad_state;
+#[cfg(feature = "mock")]
+pub mod mock;
+#[cfg(feature = "soak")]
+pub mod soak;
+#[cfg(any(feature = "ffi", feature = "mock"))]
+pub mod stateful;
+#[cfg(feature = "xinput")]
+pub mod xinput;
+
+// The functions the client calls, which are ViGEmClient's unless they're the mock bus's
+#[cfg(feature = "mock")]
+use mock::ffi;
+#[cfg(all(feature = "ffi", not(feature = "mock")))]
+use vigem_client_c_sys as ffi;
+// Enabled alongside the mock bus by feature unification, but not called into
+#[cfg(all(feature = "ffi", feature = "mock"))]
+use vigem_client_c_sys as _;
 
+#[cfg(any(feature = "ffi", feature = "mock"))]
 pub use client::Client;
+#[cfg(any(feature = "ffi", feature = "mock"))]
 pub use error::*;
 pub use gamepad_state::*;
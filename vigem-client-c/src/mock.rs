@@ -0,0 +1,823 @@
+//! An in-memory bus standing in for ViGEmBus, so that code using the [Client](crate::Client) can be tested
+//! on machines without the driver, or without Windows at all.
+//!
+//! With the `mock` feature every [Client](crate::Client) is connected to a bus of its own, which keeps a log of
+//! the targets plugged into it and the reports they were sent, and lets tests send them
+//! notifications as a game would. Targets behave just like they do on a real bus, as it's the
+//! very same code driving them; only the functions it calls are swapped for the ones in here.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{
+    client::TargetType,
+    gamepad_state::{
+        DS4Buttons, DS4Dpad, DS4SpecialButtons, DS4State, StickPosition, X360Buttons, X360State,
+        XusbReport,
+    },
+};
+
+/// Something which happened on a [MockBus]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockEvent {
+    /// A target was plugged in with the given serial number
+    Added {
+        serial: u32,
+        target_type: TargetType,
+    },
+
+    /// The target with the given serial number was unplugged
+    Removed { serial: u32 },
+
+    /// An xbox 360 target was sent a state
+    X360Report { serial: u32, state: X360State },
+
+    /// A dualshock 4 target was sent a state. Extended reports are logged by their basic state.
+    DS4Report { serial: u32, state: DS4State },
+
+    /// The target which was last plugged in with the given serial number was deallocated,
+    /// whether or not it was removed from the bus first. Targets which failed to be plugged in
+    /// at all are freed with serial number 0.
+    Freed { serial: u32 },
+}
+
+/// A handle to the in-memory bus a [Client](crate::Client) is connected to with the `mock` feature
+#[derive(Debug, Clone, Default)]
+pub struct MockBus {
+    state: Arc<Mutex<BusState>>,
+
+    /// Held while a notification callback runs, so that it can't be unregistered and freed
+    /// in the meantime
+    notifying: Arc<Mutex<()>>,
+}
+
+#[derive(Debug, Default)]
+struct BusState {
+    /// Whether the bus went away, after which it fails every request like a real one would
+    lost: bool,
+
+    /// The targets plugged in, by serial number
+    targets: BTreeMap<u32, PluggedIn>,
+
+    events: Vec<MockEvent>,
+}
+
+/// A target plugged into a [MockBus]
+#[derive(Debug)]
+struct PluggedIn {
+    target_type: TargetType,
+
+    /// The vendor and product ids the target had when it was plugged in, which are the ones
+    /// a real bus shows the OS
+    ids: (u16, u16),
+
+    /// The XInput slot of an xbox 360 target, if it has one
+    user_index: Option<u32>,
+
+    /// The registered notification callback, if any
+    notification: Option<Notification>,
+
+    /// Every report an xbox 360 target was sent while plugged in, with all the bits its
+    /// [MockEvent::X360Report]s leave out
+    raw_reports: Vec<XusbReport>,
+}
+
+/// A notification callback registered on a target, along with what to pass it
+#[derive(Debug, Clone, Copy)]
+struct Notification {
+    callback: Callback,
+    client: usize,
+    target: usize,
+    userdata: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Callback {
+    X360(unsafe extern "C" fn(ffi::PVIGEM_CLIENT, ffi::PVIGEM_TARGET, u8, u8, u8, ffi::LPVOID)),
+    DS4(
+        unsafe extern "C" fn(
+            ffi::PVIGEM_CLIENT,
+            ffi::PVIGEM_TARGET,
+            u8,
+            u8,
+            ffi::_DS4_LIGHTBAR_COLOR,
+            ffi::LPVOID,
+        ),
+    ),
+}
+
+impl MockBus {
+    fn lock(&self) -> MutexGuard<'_, BusState> {
+        // Tests failing while holding the lock shouldn't take every later check down with them
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Everything which happened on this bus so far, oldest first
+    pub fn events(&self) -> Vec<MockEvent> {
+        self.lock().events.clone()
+    }
+
+    /// Every state the xbox 360 target with the given serial number was sent, oldest first
+    pub fn x360_reports(&self, serial: u32) -> Vec<X360State> {
+        self.lock()
+            .events
+            .iter()
+            .filter_map(|event| match *event {
+                MockEvent::X360Report { serial: s, state } if s == serial => Some(state),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Every report the xbox 360 target with the given serial number was sent since it was
+    /// plugged in, oldest first, exactly as they were sent
+    pub fn x360_raw_reports(&self, serial: u32) -> Vec<XusbReport> {
+        self.lock()
+            .targets
+            .get(&serial)
+            .map_or_else(Vec::new, |plugged_in| plugged_in.raw_reports.clone())
+    }
+
+    /// The serial numbers of the targets currently plugged in
+    pub fn targets(&self) -> Vec<u32> {
+        self.lock().targets.keys().copied().collect()
+    }
+
+    /// The vendor and product ids the target with the given serial number was plugged in with,
+    /// if it's plugged in. Changing them afterwards doesn't change these, just like on a real bus.
+    pub fn ids(&self, serial: u32) -> Option<(u16, u16)> {
+        self.lock().targets.get(&serial).map(|target| target.ids)
+    }
+
+    /// Give the xbox 360 target with the given serial number another XInput slot, or take its
+    /// slot away to act like it was just plugged in
+    pub fn set_user_index(&self, serial: u32, user_index: Option<u32>) {
+        if let Some(target) = self.lock().targets.get_mut(&serial) {
+            target.user_index = user_index;
+        }
+    }
+
+    /// Act like the bus went away, failing every later request with [Error::BusNotFound](crate::Error::BusNotFound)
+    pub fn unplug(&self) {
+        self.lock().lost = true;
+    }
+
+    /// Send the xbox 360 target with the given serial number a notification, as a game would.
+    /// Returns whether it has a callback which was called.
+    ///
+    /// The callback runs on the calling thread, and the target's callback can't be
+    /// unregistered until it returns.
+    pub fn notify_x360(
+        &self,
+        serial: u32,
+        large_motor: u8,
+        small_motor: u8,
+        led_number: u8,
+    ) -> bool {
+        self.notify(serial, |notification| match notification.callback {
+            Callback::X360(callback) => unsafe {
+                callback(
+                    notification.client as _,
+                    notification.target as _,
+                    large_motor,
+                    small_motor,
+                    led_number,
+                    notification.userdata as _,
+                )
+            },
+            Callback::DS4(_) => {}
+        })
+    }
+
+    /// Send the dualshock 4 target with the given serial number a notification, as a game
+    /// would. Returns whether it has a callback which was called.
+    ///
+    /// Like with [notify_x360](Self::notify_x360), the callback runs on the calling thread.
+    pub fn notify_ds4(
+        &self,
+        serial: u32,
+        large_motor: u8,
+        small_motor: u8,
+        (red, green, blue): (u8, u8, u8),
+    ) -> bool {
+        self.notify(serial, |notification| match notification.callback {
+            Callback::DS4(callback) => unsafe {
+                callback(
+                    notification.client as _,
+                    notification.target as _,
+                    large_motor,
+                    small_motor,
+                    ffi::_DS4_LIGHTBAR_COLOR {
+                        Red: red,
+                        Green: green,
+                        Blue: blue,
+                    },
+                    notification.userdata as _,
+                )
+            },
+            Callback::X360(_) => {}
+        })
+    }
+
+    fn notify(&self, serial: u32, call: impl FnOnce(Notification)) -> bool {
+        let _notifying = self
+            .notifying
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let notification = match self.lock().targets.get(&serial) {
+            Some(target) => target.notification,
+            None => None,
+        };
+        // SAFETY: Callbacks are only freed once unregistered, which waits for us to be done
+        match notification {
+            Some(notification) => {
+                call(notification);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Fail like a real bus would if it went away
+    fn check_connected(&self) -> Result<MutexGuard<'_, BusState>, ffi::VIGEM_ERROR> {
+        let state = self.lock();
+        if state.lost {
+            Err(ffi::_VIGEM_ERRORS_VIGEM_ERROR_BUS_NOT_FOUND)
+        } else {
+            Ok(state)
+        }
+    }
+}
+
+/// Turn a `VIGEM_ERROR` returning body into the code itself
+fn code(result: Result<(), ffi::VIGEM_ERROR>) -> ffi::VIGEM_ERROR {
+    match result {
+        Ok(()) => ffi::_VIGEM_ERRORS_VIGEM_ERROR_NONE,
+        Err(error) => error,
+    }
+}
+
+/// Stand-ins for the parts of ViGEmClient's bindings the client uses, named just like them,
+/// which drive a [MockBus] instead of ViGEmBus
+#[allow(
+    non_camel_case_types,
+    non_snake_case,
+    non_upper_case_globals,
+    clippy::upper_case_acronyms
+)]
+pub(crate) mod ffi {
+    use std::{ffi::c_void, sync::atomic::AtomicBool};
+
+    use super::{code, Callback, MockBus, MockEvent, Notification, PluggedIn};
+    use crate::client::TargetType;
+
+    pub(crate) type VIGEM_ERROR = i32;
+    pub(crate) type _VIGEM_ERRORS = VIGEM_ERROR;
+    pub(crate) const _VIGEM_ERRORS_VIGEM_ERROR_NONE: VIGEM_ERROR = 0x2000_0000;
+    pub(crate) const _VIGEM_ERRORS_VIGEM_ERROR_BUS_NOT_FOUND: VIGEM_ERROR = 0xE000_0001_u32 as i32;
+    pub(crate) const _VIGEM_ERRORS_VIGEM_ERROR_NO_FREE_SLOT: VIGEM_ERROR = 0xE000_0002_u32 as i32;
+    pub(crate) const _VIGEM_ERRORS_VIGEM_ERROR_INVALID_TARGET: VIGEM_ERROR = 0xE000_0003_u32 as i32;
+    pub(crate) const _VIGEM_ERRORS_VIGEM_ERROR_REMOVAL_FAILED: VIGEM_ERROR = 0xE000_0004_u32 as i32;
+    pub(crate) const _VIGEM_ERRORS_VIGEM_ERROR_ALREADY_CONNECTED: VIGEM_ERROR =
+        0xE000_0005_u32 as i32;
+    pub(crate) const _VIGEM_ERRORS_VIGEM_ERROR_TARGET_UNINITIALIZED: VIGEM_ERROR =
+        0xE000_0006_u32 as i32;
+    pub(crate) const _VIGEM_ERRORS_VIGEM_ERROR_TARGET_NOT_PLUGGED_IN: VIGEM_ERROR =
+        0xE000_0007_u32 as i32;
+    pub(crate) const _VIGEM_ERRORS_VIGEM_ERROR_BUS_VERSION_MISMATCH: VIGEM_ERROR =
+        0xE000_0008_u32 as i32;
+    pub(crate) const _VIGEM_ERRORS_VIGEM_ERROR_BUS_ACCESS_FAILED: VIGEM_ERROR =
+        0xE000_0009_u32 as i32;
+    pub(crate) const _VIGEM_ERRORS_VIGEM_ERROR_CALLBACK_ALREADY_REGISTERED: VIGEM_ERROR =
+        0xE000_0010_u32 as i32;
+    pub(crate) const _VIGEM_ERRORS_VIGEM_ERROR_CALLBACK_NOT_FOUND: VIGEM_ERROR =
+        0xE000_0011_u32 as i32;
+    pub(crate) const _VIGEM_ERRORS_VIGEM_ERROR_BUS_ALREADY_CONNECTED: VIGEM_ERROR =
+        0xE000_0012_u32 as i32;
+    pub(crate) const _VIGEM_ERRORS_VIGEM_ERROR_BUS_INVALID_HANDLE: VIGEM_ERROR =
+        0xE000_0013_u32 as i32;
+    pub(crate) const _VIGEM_ERRORS_VIGEM_ERROR_XUSB_USERINDEX_OUT_OF_RANGE: VIGEM_ERROR =
+        0xE000_0014_u32 as i32;
+    pub(crate) const _VIGEM_ERRORS_VIGEM_ERROR_INVALID_PARAMETER: VIGEM_ERROR =
+        0xE000_0015_u32 as i32;
+    pub(crate) const _VIGEM_ERRORS_VIGEM_ERROR_NOT_SUPPORTED: VIGEM_ERROR = 0xE000_0016_u32 as i32;
+
+    pub(crate) type VIGEM_TARGET_TYPE = i32;
+    pub(crate) const _VIGEM_TARGET_TYPE_Xbox360Wired: VIGEM_TARGET_TYPE = 0;
+    pub(crate) const _VIGEM_TARGET_TYPE_DualShock4Wired: VIGEM_TARGET_TYPE = 2;
+
+    pub(crate) type LPVOID = *mut c_void;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub(crate) struct _XUSB_REPORT {
+        pub(crate) wButtons: u16,
+        pub(crate) bLeftTrigger: u8,
+        pub(crate) bRightTrigger: u8,
+        pub(crate) sThumbLX: i16,
+        pub(crate) sThumbLY: i16,
+        pub(crate) sThumbRX: i16,
+        pub(crate) sThumbRY: i16,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub(crate) struct _DS4_REPORT {
+        pub(crate) bThumbLX: u8,
+        pub(crate) bThumbLY: u8,
+        pub(crate) bThumbRX: u8,
+        pub(crate) bThumbRY: u8,
+        pub(crate) wButtons: u16,
+        pub(crate) bSpecial: u8,
+        pub(crate) bTriggerL: u8,
+        pub(crate) bTriggerR: u8,
+    }
+
+    #[repr(C, packed)]
+    #[derive(Clone, Copy)]
+    pub(crate) struct _DS4_TOUCH {
+        pub(crate) bPacketCounter: u8,
+        pub(crate) bIsUpTrackingNum1: u8,
+        pub(crate) bTouchData1: [u8; 3],
+        pub(crate) bIsUpTrackingNum2: u8,
+        pub(crate) bTouchData2: [u8; 3],
+    }
+
+    #[repr(C, packed)]
+    #[derive(Clone, Copy)]
+    pub(crate) struct _DS4_REPORT_EX__bindgen_ty_1__bindgen_ty_1 {
+        pub(crate) bThumbLX: u8,
+        pub(crate) bThumbLY: u8,
+        pub(crate) bThumbRX: u8,
+        pub(crate) bThumbRY: u8,
+        pub(crate) wButtons: u16,
+        pub(crate) bSpecial: u8,
+        pub(crate) bTriggerL: u8,
+        pub(crate) bTriggerR: u8,
+        pub(crate) wTimestamp: u16,
+        pub(crate) bBatteryLvl: u8,
+        pub(crate) wGyroX: i16,
+        pub(crate) wGyroY: i16,
+        pub(crate) wGyroZ: i16,
+        pub(crate) wAccelX: i16,
+        pub(crate) wAccelY: i16,
+        pub(crate) wAccelZ: i16,
+        pub(crate) _bUnknown1: [u8; 5],
+        pub(crate) bBatteryLvlSpecial: u8,
+        pub(crate) _bUnknown2: [u8; 2],
+        pub(crate) bTouchPacketsN: u8,
+        pub(crate) sCurrentTouch: _DS4_TOUCH,
+        pub(crate) sPreviousTouch: [_DS4_TOUCH; 2],
+    }
+
+    #[repr(C, packed)]
+    #[derive(Clone, Copy)]
+    pub(crate) union _DS4_REPORT_EX__bindgen_ty_1 {
+        pub(crate) Report: _DS4_REPORT_EX__bindgen_ty_1__bindgen_ty_1,
+        pub(crate) ReportBuffer: [u8; 63],
+    }
+
+    #[repr(C, packed)]
+    #[derive(Clone, Copy)]
+    pub(crate) struct _DS4_REPORT_EX {
+        pub(crate) __bindgen_anon_1: _DS4_REPORT_EX__bindgen_ty_1,
+    }
+
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy)]
+    pub(crate) struct _DS4_LIGHTBAR_COLOR {
+        pub(crate) Red: u8,
+        pub(crate) Green: u8,
+        pub(crate) Blue: u8,
+    }
+
+    #[derive(Debug)]
+    pub(crate) struct _VIGEM_CLIENT_T {
+        pub(crate) bus: MockBus,
+        connected: AtomicBool,
+    }
+    pub(crate) type PVIGEM_CLIENT = *mut _VIGEM_CLIENT_T;
+
+    #[derive(Debug)]
+    pub(crate) struct _VIGEM_TARGET_T {
+        target_type: VIGEM_TARGET_TYPE,
+        vid: u16,
+        pid: u16,
+
+        /// The serial number the bus gave the target, 0 until it's plugged in
+        serial: u32,
+
+        /// The bus the target is plugged into, if it is
+        bus: Option<MockBus>,
+
+        /// The bus the target was last plugged into, or tried to be, which hears of it being
+        /// freed
+        added_to: Option<MockBus>,
+    }
+    pub(crate) type PVIGEM_TARGET = *mut _VIGEM_TARGET_T;
+
+    pub(crate) type PFN_VIGEM_X360_NOTIFICATION =
+        Option<unsafe extern "C" fn(PVIGEM_CLIENT, PVIGEM_TARGET, u8, u8, u8, LPVOID)>;
+    pub(crate) type PFN_VIGEM_DS4_NOTIFICATION = Option<
+        unsafe extern "C" fn(PVIGEM_CLIENT, PVIGEM_TARGET, u8, u8, _DS4_LIGHTBAR_COLOR, LPVOID),
+    >;
+    #[cfg(feature = "async-update")]
+    pub(crate) type PFN_VIGEM_TARGET_UPDATE_RESULT =
+        Option<unsafe extern "C" fn(PVIGEM_CLIENT, PVIGEM_TARGET, VIGEM_ERROR, LPVOID)>;
+
+    pub(crate) unsafe extern "C" fn vigem_alloc() -> PVIGEM_CLIENT {
+        Box::into_raw(Box::new(_VIGEM_CLIENT_T {
+            bus: MockBus::default(),
+            connected: AtomicBool::new(false),
+        }))
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_free(vigem: PVIGEM_CLIENT) {
+        drop(unsafe { Box::from_raw(vigem) });
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_connect(vigem: PVIGEM_CLIENT) -> VIGEM_ERROR {
+        let vigem = unsafe { &*vigem };
+        if vigem
+            .connected
+            .swap(true, std::sync::atomic::Ordering::SeqCst)
+        {
+            _VIGEM_ERRORS_VIGEM_ERROR_BUS_ALREADY_CONNECTED
+        } else {
+            _VIGEM_ERRORS_VIGEM_ERROR_NONE
+        }
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_disconnect(vigem: PVIGEM_CLIENT) {
+        let vigem = unsafe { &*vigem };
+        vigem
+            .connected
+            .store(false, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    fn alloc_target(target_type: VIGEM_TARGET_TYPE, vid: u16, pid: u16) -> PVIGEM_TARGET {
+        Box::into_raw(Box::new(_VIGEM_TARGET_T {
+            target_type,
+            vid,
+            pid,
+            serial: 0,
+            bus: None,
+            added_to: None,
+        }))
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_target_x360_alloc() -> PVIGEM_TARGET {
+        alloc_target(_VIGEM_TARGET_TYPE_Xbox360Wired, 0x045E, 0x028E)
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_target_ds4_alloc() -> PVIGEM_TARGET {
+        alloc_target(_VIGEM_TARGET_TYPE_DualShock4Wired, 0x054C, 0x05C4)
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_target_free(target: PVIGEM_TARGET) {
+        let target = unsafe { Box::from_raw(target) };
+        if let Some(bus) = &target.added_to {
+            bus.lock().events.push(MockEvent::Freed {
+                serial: target.serial,
+            });
+        }
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_target_add(
+        vigem: PVIGEM_CLIENT,
+        target: PVIGEM_TARGET,
+    ) -> VIGEM_ERROR {
+        let (vigem, target) = unsafe { (&*vigem, &mut *target) };
+        target.added_to = Some(vigem.bus.clone());
+        code((|| {
+            let mut state = vigem.bus.check_connected()?;
+            if target.bus.is_some() {
+                return Err(_VIGEM_ERRORS_VIGEM_ERROR_ALREADY_CONNECTED);
+            }
+
+            // Like ViGEmBus, hand out the lowest serial number and XInput slot which are free
+            let serial = (1..)
+                .find(|serial| !state.targets.contains_key(serial))
+                .unwrap();
+            let target_type = match target.target_type {
+                _VIGEM_TARGET_TYPE_Xbox360Wired => TargetType::Xbox360Wired,
+                _ => TargetType::DualShock4Wired,
+            };
+            let user_index = match target_type {
+                TargetType::Xbox360Wired => (0..=crate::client::UserIndex::MAX).find(|index| {
+                    state
+                        .targets
+                        .values()
+                        .all(|target| target.user_index != Some(*index))
+                }),
+                _ => None,
+            };
+            let _ = state.targets.insert(
+                serial,
+                PluggedIn {
+                    target_type,
+                    ids: (target.vid, target.pid),
+                    user_index,
+                    notification: None,
+                    raw_reports: Vec::new(),
+                },
+            );
+            state.events.push(MockEvent::Added {
+                serial,
+                target_type,
+            });
+
+            target.serial = serial;
+            target.bus = Some(vigem.bus.clone());
+            Ok(())
+        })())
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_target_remove(
+        _vigem: PVIGEM_CLIENT,
+        target: PVIGEM_TARGET,
+    ) -> VIGEM_ERROR {
+        let target = unsafe { &mut *target };
+        code((|| {
+            let bus = target
+                .bus
+                .as_ref()
+                .ok_or(_VIGEM_ERRORS_VIGEM_ERROR_TARGET_NOT_PLUGGED_IN)?;
+            // Without a bus to ask, a real client can't unplug the target either
+            if bus.lock().lost {
+                return Err(_VIGEM_ERRORS_VIGEM_ERROR_REMOVAL_FAILED);
+            }
+            let bus = target.bus.take().unwrap();
+            let mut state = bus.lock();
+            let _ = state.targets.remove(&target.serial);
+            state.events.push(MockEvent::Removed {
+                serial: target.serial,
+            });
+            Ok(())
+        })())
+    }
+
+    /// Run `f` on the entry of a plugged in target
+    fn with_plugged_in(
+        target: &_VIGEM_TARGET_T,
+        f: impl FnOnce(&mut PluggedIn, &mut Vec<MockEvent>) -> Result<(), VIGEM_ERROR>,
+    ) -> VIGEM_ERROR {
+        code((|| {
+            let bus = target
+                .bus
+                .as_ref()
+                .ok_or(_VIGEM_ERRORS_VIGEM_ERROR_TARGET_NOT_PLUGGED_IN)?;
+            let mut state = bus.check_connected()?;
+            let state = &mut *state;
+            let plugged_in = state
+                .targets
+                .get_mut(&target.serial)
+                .ok_or(_VIGEM_ERRORS_VIGEM_ERROR_INVALID_TARGET)?;
+            f(plugged_in, &mut state.events)
+        })())
+    }
+
+    fn register(
+        vigem: PVIGEM_CLIENT,
+        target: PVIGEM_TARGET,
+        callback: Callback,
+        userdata: LPVOID,
+    ) -> VIGEM_ERROR {
+        let notification = Notification {
+            callback,
+            client: vigem as usize,
+            target: target as usize,
+            userdata: userdata as usize,
+        };
+        with_plugged_in(unsafe { &*target }, |plugged_in, _| {
+            if plugged_in.notification.is_some() {
+                return Err(_VIGEM_ERRORS_VIGEM_ERROR_CALLBACK_ALREADY_REGISTERED);
+            }
+            plugged_in.notification = Some(notification);
+            Ok(())
+        })
+    }
+
+    fn unregister(target: PVIGEM_TARGET) {
+        let target = unsafe { &*target };
+        if let Some(bus) = &target.bus {
+            // Wait for a notification which is running the callback to be done with it
+            let _notifying = bus
+                .notifying
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            if let Some(plugged_in) = bus.lock().targets.get_mut(&target.serial) {
+                plugged_in.notification = None;
+            }
+        }
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_target_x360_register_notification(
+        vigem: PVIGEM_CLIENT,
+        target: PVIGEM_TARGET,
+        notification: PFN_VIGEM_X360_NOTIFICATION,
+        userdata: LPVOID,
+    ) -> VIGEM_ERROR {
+        match notification {
+            Some(notification) => register(vigem, target, Callback::X360(notification), userdata),
+            None => _VIGEM_ERRORS_VIGEM_ERROR_INVALID_PARAMETER,
+        }
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_target_ds4_register_notification(
+        vigem: PVIGEM_CLIENT,
+        target: PVIGEM_TARGET,
+        notification: PFN_VIGEM_DS4_NOTIFICATION,
+        userdata: LPVOID,
+    ) -> VIGEM_ERROR {
+        match notification {
+            Some(notification) => register(vigem, target, Callback::DS4(notification), userdata),
+            None => _VIGEM_ERRORS_VIGEM_ERROR_INVALID_PARAMETER,
+        }
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_target_x360_unregister_notification(
+        target: PVIGEM_TARGET,
+    ) {
+        unregister(target);
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_target_ds4_unregister_notification(
+        target: PVIGEM_TARGET,
+    ) {
+        unregister(target);
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_target_set_vid(target: PVIGEM_TARGET, vid: u16) {
+        unsafe { (*target).vid = vid };
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_target_set_pid(target: PVIGEM_TARGET, pid: u16) {
+        unsafe { (*target).pid = pid };
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_target_get_vid(target: PVIGEM_TARGET) -> u16 {
+        unsafe { (*target).vid }
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_target_get_pid(target: PVIGEM_TARGET) -> u16 {
+        unsafe { (*target).pid }
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_target_get_index(target: PVIGEM_TARGET) -> u32 {
+        unsafe { (*target).serial }
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_target_get_type(
+        target: PVIGEM_TARGET,
+    ) -> VIGEM_TARGET_TYPE {
+        unsafe { (*target).target_type }
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_target_is_attached(target: PVIGEM_TARGET) -> i32 {
+        i32::from(unsafe { (*target).bus.is_some() })
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_target_x360_update(
+        _vigem: PVIGEM_CLIENT,
+        target: PVIGEM_TARGET,
+        report: _XUSB_REPORT,
+    ) -> VIGEM_ERROR {
+        let target = unsafe { &*target };
+        with_plugged_in(target, |plugged_in, events| {
+            plugged_in.raw_reports.push(super::XusbReport {
+                buttons: report.wButtons,
+                left_trigger: report.bLeftTrigger,
+                right_trigger: report.bRightTrigger,
+                thumb_lx: report.sThumbLX,
+                thumb_ly: report.sThumbLY,
+                thumb_rx: report.sThumbRX,
+                thumb_ry: report.sThumbRY,
+            });
+            events.push(MockEvent::X360Report {
+                serial: target.serial,
+                state: super::x360_state(report),
+            });
+            Ok(())
+        })
+    }
+
+    #[cfg(feature = "async-update")]
+    pub(crate) unsafe extern "C" fn vigem_target_x360_update_async(
+        vigem: PVIGEM_CLIENT,
+        target: PVIGEM_TARGET,
+        report: _XUSB_REPORT,
+        result: PFN_VIGEM_TARGET_UPDATE_RESULT,
+        userdata: LPVOID,
+    ) -> VIGEM_ERROR {
+        let error = unsafe { vigem_target_x360_update(vigem, target, report) };
+        if error != _VIGEM_ERRORS_VIGEM_ERROR_NONE {
+            return error;
+        }
+
+        // Like ViGEmClient, complete the update on a thread of our own
+        let (vigem, target, userdata) = (vigem as usize, target as usize, userdata as usize);
+        let _ = std::thread::spawn(move || {
+            if let Some(result) = result {
+                unsafe {
+                    result(
+                        vigem as _,
+                        target as _,
+                        _VIGEM_ERRORS_VIGEM_ERROR_NONE,
+                        userdata as _,
+                    )
+                };
+            }
+        });
+        error
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_target_ds4_update(
+        _vigem: PVIGEM_CLIENT,
+        target: PVIGEM_TARGET,
+        report: _DS4_REPORT,
+    ) -> VIGEM_ERROR {
+        let target = unsafe { &*target };
+        with_plugged_in(target, |_, events| {
+            events.push(MockEvent::DS4Report {
+                serial: target.serial,
+                state: super::ds4_state(report),
+            });
+            Ok(())
+        })
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_target_ds4_update_ex(
+        vigem: PVIGEM_CLIENT,
+        target: PVIGEM_TARGET,
+        report: _DS4_REPORT_EX,
+    ) -> VIGEM_ERROR {
+        // SAFETY: Both union variants are plain old data covering the same bytes
+        let r = unsafe { report.__bindgen_anon_1.Report };
+        let basic = _DS4_REPORT {
+            bThumbLX: r.bThumbLX,
+            bThumbLY: r.bThumbLY,
+            bThumbRX: r.bThumbRX,
+            bThumbRY: r.bThumbRY,
+            wButtons: r.wButtons,
+            bSpecial: r.bSpecial,
+            bTriggerL: r.bTriggerL,
+            bTriggerR: r.bTriggerR,
+        };
+        unsafe { vigem_target_ds4_update(vigem, target, basic) }
+    }
+
+    pub(crate) unsafe extern "C" fn vigem_target_x360_get_user_index(
+        _vigem: PVIGEM_CLIENT,
+        target: PVIGEM_TARGET,
+        index: *mut u32,
+    ) -> VIGEM_ERROR {
+        with_plugged_in(unsafe { &*target }, |plugged_in, _| {
+            if plugged_in.target_type != TargetType::Xbox360Wired {
+                return Err(_VIGEM_ERRORS_VIGEM_ERROR_INVALID_TARGET);
+            }
+            let user_index = plugged_in
+                .user_index
+                .ok_or(_VIGEM_ERRORS_VIGEM_ERROR_XUSB_USERINDEX_OUT_OF_RANGE)?;
+            unsafe { *index = user_index };
+            Ok(())
+        })
+    }
+}
+
+/// The state an xbox 360 report was made from
+fn x360_state(report: ffi::_XUSB_REPORT) -> X360State {
+    X360State {
+        buttons: X360Buttons::from_bits_truncate(report.wButtons),
+        left_trigger: report.bLeftTrigger,
+        right_trigger: report.bRightTrigger,
+        left_thumbstick: StickPosition::new(report.sThumbLX, report.sThumbLY),
+        right_thumbstick: StickPosition::new(report.sThumbRX, report.sThumbRY),
+    }
+}
+
+/// The state a dualshock 4 report was made from
+fn ds4_state(report: ffi::_DS4_REPORT) -> DS4State {
+    let dpad = match report.wButtons & 0xF {
+        0 => DS4Dpad::North,
+        1 => DS4Dpad::NorthEast,
+        2 => DS4Dpad::East,
+        3 => DS4Dpad::SouthEast,
+        4 => DS4Dpad::South,
+        5 => DS4Dpad::SouthWest,
+        6 => DS4Dpad::West,
+        7 => DS4Dpad::NorthWest,
+        _ => DS4Dpad::None,
+    };
+    DS4State {
+        buttons: DS4Buttons::from_bits_truncate(report.wButtons),
+        special: DS4SpecialButtons::from_bits_truncate(report.bSpecial),
+        dpad,
+        left_trigger: report.bTriggerL,
+        right_trigger: report.bTriggerR,
+        left_thumbstick: (report.bThumbLX, report.bThumbLY),
+        right_thumbstick: (report.bThumbRX, report.bThumbRY),
+    }
+}
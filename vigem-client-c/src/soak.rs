@@ -0,0 +1,100 @@
+//! Measuring what the process holds on to, for soak tests which do the same thing over and over
+//! and check that it doesn't keep growing, like `tests/test_soak.rs` does with pads.
+//!
+//! On Windows that's the process's handles and private bytes, which is what Task Manager shows
+//! growing when something leaks. On Linux it's the open file descriptors and the anonymous
+//! resident memory, which the mock bus can be soaked against.
+
+use std::io;
+
+/// What the process holds on to at some point, or how much more than at another
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Usage {
+    /// How many handles, or file descriptors, the process has open
+    pub handles: u64,
+
+    /// How many bytes of memory the process has to itself
+    pub private_bytes: u64,
+}
+
+impl Usage {
+    /// Measure the process now
+    #[cfg(windows)]
+    pub fn current() -> io::Result<Self> {
+        use windows_sys::Win32::System::{
+            ProcessStatus::{
+                K32GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS, PROCESS_MEMORY_COUNTERS_EX,
+            },
+            Threading::{GetCurrentProcess, GetProcessHandleCount},
+        };
+
+        let process = unsafe { GetCurrentProcess() };
+        let mut handles = 0;
+        if unsafe { GetProcessHandleCount(process, &mut handles) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut counters = unsafe { std::mem::zeroed::<PROCESS_MEMORY_COUNTERS_EX>() };
+        let size = std::mem::size_of::<PROCESS_MEMORY_COUNTERS_EX>() as u32;
+        let counters_ptr = &mut counters as *mut _ as *mut PROCESS_MEMORY_COUNTERS;
+        if unsafe { K32GetProcessMemoryInfo(process, counters_ptr, size) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            handles: handles.into(),
+            private_bytes: counters.PrivateUsage as u64,
+        })
+    }
+
+    /// Measure the process now
+    #[cfg(target_os = "linux")]
+    pub fn current() -> io::Result<Self> {
+        let handles = std::fs::read_dir("/proc/self/fd")?.count() as u64;
+        let status = std::fs::read_to_string("/proc/self/status")?;
+        let kilobytes = status
+            .lines()
+            .find_map(|line| line.strip_prefix("RssAnon:"))
+            .and_then(|value| value.trim().strip_suffix("kB")?.trim().parse::<u64>().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no RssAnon"))?;
+        Ok(Self {
+            handles,
+            private_bytes: kilobytes * 1024,
+        })
+    }
+
+    /// Measure the process now, which only works on Windows and Linux
+    #[cfg(not(any(windows, target_os = "linux")))]
+    pub fn current() -> io::Result<Self> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// How much more this holds than `before`, with whatever shrank counting as 0
+    pub fn growth_since(&self, before: &Usage) -> Usage {
+        Usage {
+            handles: self.handles.saturating_sub(before.handles),
+            private_bytes: self.private_bytes.saturating_sub(before.private_bytes),
+        }
+    }
+
+    /// Whether this grew past `bound` in either way
+    pub fn exceeds(&self, bound: &Usage) -> bool {
+        self.handles > bound.handles || self.private_bytes > bound.private_bytes
+    }
+}
+
+/// Run `iteration` `warm_up` times, so that whatever is only allocated once has been, then
+/// `iterations` more times, and return how much the process grew over those. Each call is given
+/// its number, counting from 0 across both.
+pub fn soak(
+    warm_up: usize,
+    iterations: usize,
+    mut iteration: impl FnMut(usize),
+) -> io::Result<Usage> {
+    for i in 0..warm_up {
+        iteration(i);
+    }
+    let before = Usage::current()?;
+    for i in warm_up..warm_up + iterations {
+        iteration(i);
+    }
+    Ok(Usage::current()?.growth_since(&before))
+}
@@ -0,0 +1,148 @@
+//! Scripting an xbox 360 pad for automation, e.g. "press A for 50ms", on top of a state which is
+//! kept track of across calls so that the helpers compose with whatever else is held.
+//!
+//! The helpers sleep on the calling thread to time presses, so they're meant for scripts and
+//! tests, not for relaying a live controller, where every update has to go out as soon as it
+//! comes in.
+
+use std::{
+    mem,
+    ops::{Deref, DerefMut},
+    thread::sleep,
+    time::Duration,
+};
+
+use crate::{
+    client::{Target, X360},
+    Result, X360Buttons, X360State,
+};
+
+/// Which of the triggers to pull
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Left,
+    Right,
+}
+
+impl Trigger {
+    fn of(self, state: &mut X360State) -> &mut u8 {
+        match self {
+            Trigger::Left => &mut state.left_trigger,
+            Trigger::Right => &mut state.right_trigger,
+        }
+    }
+}
+
+/// An xbox 360 target along with the state it was last sent, which every change is made to
+#[derive(Debug)]
+pub struct StatefulPad<'client> {
+    target: Target<'client, X360>,
+    state: X360State,
+}
+
+impl<'client> StatefulPad<'client> {
+    /// Start keeping track of a target's state, which is assumed to be neutral. Nothing is
+    /// sent until the state changes.
+    pub fn new(target: Target<'client, X360>) -> Self {
+        Self {
+            target,
+            state: X360State::default(),
+        }
+    }
+
+    /// The state the target was last sent
+    pub fn state(&self) -> X360State {
+        self.state
+    }
+
+    /// The target being driven, e.g. to register for notifications
+    pub fn target(&mut self) -> &mut Target<'client, X360> {
+        &mut self.target
+    }
+
+    /// Stop keeping track of the state, giving the target back
+    pub fn into_target(self) -> Target<'client, X360> {
+        self.target
+    }
+
+    /// Change the state, sending it only if it's different. Returns whether it was sent.
+    pub fn modify<F>(&mut self, f: F) -> Result<bool>
+    where
+        F: FnOnce(&mut X360State),
+    {
+        let mut state = self.state;
+        f(&mut state);
+        if state == self.state {
+            return Ok(false);
+        }
+        self.target.update(state)?;
+        self.state = state;
+        Ok(true)
+    }
+
+    /// Press the buttons, wait for `hold`, and release the ones which weren't already pressed
+    pub fn tap(&mut self, buttons: X360Buttons, hold: Duration) -> Result<()> {
+        let held = self.hold(buttons)?;
+        sleep(hold);
+        held.release()
+    }
+
+    /// Press the buttons until the returned guard is dropped, which releases the ones which
+    /// weren't already pressed. The pad can still be used through the guard in the meantime.
+    pub fn hold(&mut self, buttons: X360Buttons) -> Result<Held<'_, 'client>> {
+        let pressed = buttons - self.state.buttons;
+        let _ = self.modify(|state| state.buttons.insert(pressed))?;
+        Ok(Held { pad: self, pressed })
+    }
+
+    /// Pull a trigger to `value` for `duration`, then put it back where it was
+    pub fn pulse_trigger(&mut self, side: Trigger, value: u8, duration: Duration) -> Result<()> {
+        let previous = *side.of(&mut self.state);
+        let _ = self.modify(|state| *side.of(state) = value)?;
+        sleep(duration);
+        let _ = self.modify(|state| *side.of(state) = previous)?;
+        Ok(())
+    }
+}
+
+/// Buttons pressed by [StatefulPad::hold], which are released when this is dropped
+#[derive(Debug)]
+pub struct Held<'pad, 'client> {
+    pad: &'pad mut StatefulPad<'client>,
+
+    /// The buttons this pressed, leaving out those which were already pressed
+    pressed: X360Buttons,
+}
+
+impl Held<'_, '_> {
+    /// Release the buttons now, finding out whether that worked unlike when this is dropped
+    pub fn release(mut self) -> Result<()> {
+        self.release_pressed()
+    }
+
+    fn release_pressed(&mut self) -> Result<()> {
+        let pressed = mem::replace(&mut self.pressed, X360Buttons::empty());
+        let _ = self.pad.modify(|state| state.buttons.remove(pressed))?;
+        Ok(())
+    }
+}
+
+impl<'client> Deref for Held<'_, 'client> {
+    type Target = StatefulPad<'client>;
+
+    fn deref(&self) -> &StatefulPad<'client> {
+        self.pad
+    }
+}
+
+impl<'client> DerefMut for Held<'_, 'client> {
+    fn deref_mut(&mut self) -> &mut StatefulPad<'client> {
+        self.pad
+    }
+}
+
+impl Drop for Held<'_, '_> {
+    fn drop(&mut self) {
+        let _ = self.release_pressed();
+    }
+}
@@ -0,0 +1,111 @@
+//! Reading physical controllers through XInput, e.g. to mirror one to a virtual pad that a game
+//! sees in another slot or through a remap.
+//!
+//! The conversion from XInput's layout builds everywhere, while polling only builds on Windows.
+
+use crate::gamepad_state::{StickPosition, X360Buttons, X360State};
+
+// The button bits of XINPUT_GAMEPAD, as named in XInput.h. The guide button has none, since
+// XInputGetState never reports it.
+const XINPUT_GAMEPAD_DPAD_UP: u16 = 0x0001;
+const XINPUT_GAMEPAD_DPAD_DOWN: u16 = 0x0002;
+const XINPUT_GAMEPAD_DPAD_LEFT: u16 = 0x0004;
+const XINPUT_GAMEPAD_DPAD_RIGHT: u16 = 0x0008;
+const XINPUT_GAMEPAD_START: u16 = 0x0010;
+const XINPUT_GAMEPAD_BACK: u16 = 0x0020;
+const XINPUT_GAMEPAD_LEFT_THUMB: u16 = 0x0040;
+const XINPUT_GAMEPAD_RIGHT_THUMB: u16 = 0x0080;
+const XINPUT_GAMEPAD_LEFT_SHOULDER: u16 = 0x0100;
+const XINPUT_GAMEPAD_RIGHT_SHOULDER: u16 = 0x0200;
+const XINPUT_GAMEPAD_A: u16 = 0x1000;
+const XINPUT_GAMEPAD_B: u16 = 0x2000;
+const XINPUT_GAMEPAD_X: u16 = 0x4000;
+const XINPUT_GAMEPAD_Y: u16 = 0x8000;
+
+/// Which of our buttons each of XInput's is
+const BUTTONS: [(u16, X360Buttons); 14] = [
+    (XINPUT_GAMEPAD_DPAD_UP, X360Buttons::DPAD_UP),
+    (XINPUT_GAMEPAD_DPAD_DOWN, X360Buttons::DPAD_DOWN),
+    (XINPUT_GAMEPAD_DPAD_LEFT, X360Buttons::DPAD_LEFT),
+    (XINPUT_GAMEPAD_DPAD_RIGHT, X360Buttons::DPAD_RIGHT),
+    (XINPUT_GAMEPAD_START, X360Buttons::START),
+    (XINPUT_GAMEPAD_BACK, X360Buttons::BACK),
+    (XINPUT_GAMEPAD_LEFT_THUMB, X360Buttons::LEFT_THUMB),
+    (XINPUT_GAMEPAD_RIGHT_THUMB, X360Buttons::RIGHT_THUMB),
+    (XINPUT_GAMEPAD_LEFT_SHOULDER, X360Buttons::LEFT_SHOULDER),
+    (XINPUT_GAMEPAD_RIGHT_SHOULDER, X360Buttons::RIGHT_SHOULDER),
+    (XINPUT_GAMEPAD_A, X360Buttons::A),
+    (XINPUT_GAMEPAD_B, X360Buttons::B),
+    (XINPUT_GAMEPAD_X, X360Buttons::X),
+    (XINPUT_GAMEPAD_Y, X360Buttons::Y),
+];
+
+/// A physical controller's state as XInput reports it, with the same fields as `XINPUT_GAMEPAD`
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct XInputGamepad {
+    pub buttons: u16,
+    pub left_trigger: u8,
+    pub right_trigger: u8,
+    pub thumb_lx: i16,
+    pub thumb_ly: i16,
+    pub thumb_rx: i16,
+    pub thumb_ry: i16,
+}
+
+/// Converts a controller's state button by button, dropping any bits XInput doesn't define
+impl From<XInputGamepad> for X360State {
+    fn from(gamepad: XInputGamepad) -> Self {
+        let mut buttons = X360Buttons::empty();
+        for &(bit, button) in &BUTTONS {
+            buttons.set(button, gamepad.buttons & bit != 0);
+        }
+        Self {
+            buttons,
+            left_trigger: gamepad.left_trigger,
+            right_trigger: gamepad.right_trigger,
+            left_thumbstick: StickPosition::new(gamepad.thumb_lx, gamepad.thumb_ly),
+            right_thumbstick: StickPosition::new(gamepad.thumb_rx, gamepad.thumb_ry),
+        }
+    }
+}
+
+/// What a poll of a controller read
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct XInputPoll {
+    /// Changes whenever the controller's state does, so that polls which read the same number
+    /// as the last one can be skipped
+    pub packet_number: u32,
+    pub state: X360State,
+}
+
+/// Read the state of the physical controller in the given slot, from 0 to 3.
+///
+/// Fails with the OS error XInput returned, which is `ERROR_DEVICE_NOT_CONNECTED` if there's no
+/// controller in that slot.
+#[cfg(windows)]
+pub fn poll_xinput(user_index: u32) -> std::io::Result<XInputPoll> {
+    use windows_sys::Win32::{
+        Foundation::ERROR_SUCCESS,
+        UI::Input::XboxController::{XInputGetState, XINPUT_STATE},
+    };
+
+    let mut state = unsafe { std::mem::zeroed::<XINPUT_STATE>() };
+    let result = unsafe { XInputGetState(user_index, &mut state) };
+    if result != ERROR_SUCCESS {
+        return Err(std::io::Error::from_raw_os_error(result as i32));
+    }
+    let gamepad = state.Gamepad;
+    Ok(XInputPoll {
+        packet_number: state.dwPacketNumber,
+        state: XInputGamepad {
+            buttons: gamepad.wButtons,
+            left_trigger: gamepad.bLeftTrigger,
+            right_trigger: gamepad.bRightTrigger,
+            thumb_lx: gamepad.sThumbLX,
+            thumb_ly: gamepad.sThumbLY,
+            thumb_rx: gamepad.sThumbRX,
+            thumb_ry: gamepad.sThumbRY,
+        }
+        .into(),
+    })
+}
@@ -0,0 +1,94 @@
+use vigem_client_c::X360Buttons;
+
+#[test]
+fn test_empty() {
+    assert_eq!("".parse(), Ok(X360Buttons::empty()));
+    assert_eq!("  ".parse(), Ok(X360Buttons::empty()));
+    assert_eq!(X360Buttons::empty().to_string(), "");
+}
+
+#[test]
+fn test_single() {
+    assert_eq!("A".parse(), Ok(X360Buttons::A));
+    assert_eq!("dpad_up".parse(), Ok(X360Buttons::DPAD_UP));
+    assert_eq!("Lb".parse(), Ok(X360Buttons::LEFT_SHOULDER));
+    assert_eq!("RS".parse(), Ok(X360Buttons::RIGHT_THUMB));
+    assert_eq!(X360Buttons::RIGHT_SHOULDER.to_string(), "RIGHT_SHOULDER");
+}
+
+#[test]
+fn test_round_trip() {
+    let buttons = X360Buttons::A | X360Buttons::START | X360Buttons::DPAD_UP;
+    assert_eq!(buttons.to_string(), "DPAD_UP|START|A");
+    assert_eq!("A|START|DPAD_UP".parse(), Ok(buttons));
+    assert_eq!(
+        X360Buttons::all().to_string().parse(),
+        Ok(X360Buttons::all())
+    );
+}
+
+#[test]
+fn test_whitespace() {
+    assert_eq!(
+        " a | lb\t|Y ".parse(),
+        Ok(X360Buttons::A | X360Buttons::LEFT_SHOULDER | X360Buttons::Y)
+    );
+}
+
+#[test]
+fn test_unknown() {
+    let error = "A| turbo |B".parse::<X360Buttons>().unwrap_err();
+    assert_eq!(error.name(), "turbo");
+    assert_eq!(error.to_string(), r#"Unknown button "turbo""#);
+
+    assert_eq!("A||B".parse::<X360Buttons>().unwrap_err().name(), "");
+    assert!("A,B".parse::<X360Buttons>().is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_names() {
+    use serde::de::{value::StrDeserializer, IntoDeserializer};
+    use vigem_client_c::gamepad_state::x360_buttons_names;
+
+    let deserializer: StrDeserializer<'_, serde::de::value::Error> = "b|x".into_deserializer();
+    assert_eq!(
+        x360_buttons_names::deserialize(deserializer).unwrap(),
+        X360Buttons::B | X360Buttons::X
+    );
+
+    let deserializer: StrDeserializer<'_, serde::de::value::Error> = "nope".into_deserializer();
+    assert!(x360_buttons_names::deserialize(deserializer).is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_unknown_bits() {
+    use serde::{
+        de::{value::U16Deserializer, IntoDeserializer},
+        Deserialize,
+    };
+    use vigem_client_c::gamepad_state::x360_buttons_strict;
+
+    let bits = |bits: u16| -> U16Deserializer<serde::de::value::Error> { bits.into_deserializer() };
+
+    // By default bits which aren't a button's are dropped
+    assert_eq!(
+        X360Buttons::deserialize(bits(0x1C00)).unwrap(),
+        X360Buttons::A | X360Buttons::GUIDE
+    );
+
+    // Strictly they're an error
+    let error = x360_buttons_strict::deserialize(bits(0x1C00)).unwrap_err();
+    assert_eq!(error.to_string(), "Invalid X360Buttons: 0x1c00");
+    assert_eq!(
+        x360_buttons_strict::deserialize(bits(0x1400)).unwrap(),
+        X360Buttons::A | X360Buttons::GUIDE
+    );
+}
+
+#[test]
+fn test_guide() {
+    assert_eq!("guide".parse(), Ok(X360Buttons::GUIDE));
+    assert_eq!((X360Buttons::GUIDE | X360Buttons::A).to_string(), "GUIDE|A");
+}
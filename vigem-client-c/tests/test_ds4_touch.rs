@@ -0,0 +1,17 @@
+use vigem_client_c::DS4TouchPoint;
+
+#[test]
+fn test_touch_packing() {
+    let touch = DS4TouchPoint::new(5, DS4TouchPoint::MAX_X, DS4TouchPoint::MAX_Y);
+    assert!(touch.active);
+    assert_eq!(touch.id, 5);
+    assert_eq!(touch.x(), DS4TouchPoint::MAX_X);
+    assert_eq!(touch.y(), DS4TouchPoint::MAX_Y);
+
+    let touch = DS4TouchPoint::new(0xFF, 0x123, 0xABC);
+    assert_eq!(touch.id, 0x7F);
+    assert_eq!(touch.coordinates, [0x23, 0xC1, 0xAB]);
+    assert_eq!((touch.x(), touch.y()), (0x123, 0xABC));
+
+    assert!(!DS4TouchPoint::inactive().active);
+}
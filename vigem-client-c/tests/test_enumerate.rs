@@ -0,0 +1,18 @@
+//! Finding the buses, which needs Windows but not ViGEmBus.
+//! Run with `cargo test -p vigem-client-c --features enumerate`.
+#![cfg(all(windows, feature = "enumerate"))]
+
+use vigem_client_c::{Client, Error};
+
+#[test]
+fn test_missing_path() {
+    let path = r"\\?\ROOT#SYSTEM#0000#{96e42b22-f5e9-42f8-b043-ed0f932f014f}\missing";
+    assert!(!Client::enumerate_buses()
+        .unwrap()
+        .iter()
+        .any(|bus| bus == path));
+    assert!(matches!(
+        Client::new_with_path(path),
+        Err(Error::BusNotFound)
+    ));
+}
@@ -0,0 +1,122 @@
+#![cfg(any(feature = "ffi", feature = "mock"))]
+
+use std::io;
+
+use vigem_client_c::{Error, Operation, TargetError};
+
+#[test]
+fn test_raw_code() {
+    assert_eq!(Error::BusNotFound.raw_code(), Some(0xE000_0001));
+    assert_eq!(Error::NotSupported.raw_code(), Some(0xE000_0016));
+    assert_eq!(
+        Error::UnknownError(0xE000_0019).raw_code(),
+        Some(0xE000_0019)
+    );
+    assert_eq!(Error::NoX360PadAlloc.raw_code(), None);
+    assert_eq!(Error::AlreadyHasCallback.raw_code(), None);
+}
+
+#[test]
+fn test_driver_missing() {
+    assert!(Error::BusNotFound.is_driver_missing());
+    assert!(Error::BusAccessFailed.is_driver_missing());
+    assert!(!Error::BusInvalidHandle.is_driver_missing());
+    assert!(!Error::NoFreeSlot.is_driver_missing());
+    assert!(!Error::UnknownError(0xE000_0001).is_driver_missing());
+}
+
+#[test]
+fn test_display() {
+    assert_eq!(Error::BusNotFound.to_string(), "Bus not found");
+    assert_eq!(
+        Error::UnknownError(0xE000_0019).to_string(),
+        "Unknown error code e0000019"
+    );
+}
+
+#[test]
+fn test_target_error() {
+    let error = TargetError {
+        source: Error::InvalidTarget,
+        vendor_id: 0x045E,
+        product_id: 0x028E,
+        index: 2,
+        operation: Operation::Update,
+    };
+    assert_eq!(
+        error.to_string(),
+        "Invalid target (vid=045E pid=028E index=2) during Update"
+    );
+    assert!(matches!(
+        std::error::Error::source(&error).and_then(|e| e.downcast_ref::<Error>()),
+        Some(Error::InvalidTarget)
+    ));
+
+    // `?` keeps working in functions returning the plain error, without the context
+    fn plain() -> Result<(), Error> {
+        Err(TargetError {
+            source: Error::RemovalFailed,
+            vendor_id: 0,
+            product_id: 0,
+            index: 0,
+            operation: Operation::Remove,
+        })?
+    }
+    assert!(matches!(plain(), Err(Error::RemovalFailed)));
+}
+
+#[cfg(feature = "mock")]
+#[test]
+fn test_with_target_info() {
+    use vigem_client_c::{client::Preset, Client, X360State};
+
+    let client = Client::new_mock().unwrap();
+    let mut target = client
+        .x360_pad()
+        .preset(Preset::LogitechF310)
+        .connect()
+        .unwrap();
+    let _other = client.connect_x360_pad().unwrap();
+    client.mock_bus().unplug();
+    let error = target.update(X360State::default()).unwrap_err();
+
+    let error = error.with_target_info(&target, Operation::Update);
+    assert!(matches!(error.source, Error::BusNotFound));
+    assert_eq!(
+        (error.vendor_id, error.product_id, error.index),
+        (target.vendor_id(), target.product_id(), target.index())
+    );
+    assert_eq!(error.operation, Operation::Update);
+
+    // The context is whatever the target says at the time
+    target.set_vendor_id(0x1234);
+    let error = Error::InvalidTarget.with_target_info(&target, Operation::GetUserIndex);
+    assert_eq!(error.vendor_id, 0x1234);
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "Invalid target (vid=1234 pid={:04X} index={}) during GetUserIndex",
+            target.product_id(),
+            target.index()
+        )
+    );
+}
+
+#[test]
+fn test_io_error() {
+    let kind = |error: Error| io::Error::from(error).kind();
+    assert_eq!(kind(Error::BusNotFound), io::ErrorKind::NotFound);
+    assert_eq!(
+        kind(Error::BusAccessFailed),
+        io::ErrorKind::PermissionDenied
+    );
+    assert_eq!(kind(Error::NotSupported), io::ErrorKind::Unsupported);
+    assert_eq!(kind(Error::NoFreeSlot), io::ErrorKind::Other);
+
+    // The original error is kept around as the source
+    let error = io::Error::from(Error::TargetNotPluggedIn);
+    assert!(matches!(
+        error.get_ref().and_then(|e| e.downcast_ref::<Error>()),
+        Some(Error::TargetNotPluggedIn)
+    ));
+}
@@ -0,0 +1,124 @@
+//! Receiving a target's notifications as events, against the in-memory bus.
+//! Run with `cargo test -p vigem-client-c --no-default-features --features mock`.
+#![cfg(feature = "mock")]
+
+use std::{
+    sync::mpsc::{RecvTimeoutError, TryRecvError},
+    thread,
+    time::Duration,
+};
+
+use vigem_client_c::{client::X360Event, Client, Error};
+
+#[test]
+fn test_dedup() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let mut pad = client.connect_x360_pad().unwrap();
+    let serial = pad.index();
+    let events = pad.events().unwrap();
+
+    // The first notification only tells the led, as the motors start out still
+    assert!(bus.notify_x360(serial, 0, 0, 2));
+    assert!(bus.notify_x360(serial, 10, 20, 2));
+    assert!(bus.notify_x360(serial, 10, 20, 2));
+    assert!(bus.notify_x360(serial, 10, 20, 3));
+    assert!(bus.notify_x360(serial, 0, 0, 3));
+    let received: Vec<_> = events.try_iter().collect();
+    assert_eq!(
+        received,
+        [
+            X360Event::LedChanged(2),
+            X360Event::Rumble {
+                large: 10,
+                small: 20
+            },
+            X360Event::LedChanged(3),
+            X360Event::Rumble { large: 0, small: 0 },
+        ]
+    );
+}
+
+#[test]
+fn test_exclusive() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let mut pad = client.connect_x360_pad().unwrap();
+
+    let handle = pad.register_notification(|_| {}).unwrap();
+    assert!(matches!(pad.events(), Err(Error::AlreadyHasCallback)));
+    pad.unregister_notification(handle).unwrap();
+
+    let events = pad.events().unwrap();
+    assert!(matches!(pad.events(), Err(Error::AlreadyHasCallback)));
+    assert!(matches!(
+        pad.register_notification(|_| {}),
+        Err(Error::AlreadyHasCallback)
+    ));
+
+    // Dropping the receiver unregisters its handler right away, and frees the target for another
+    drop(events);
+    assert!(!bus.notify_x360(pad.index(), 1, 1, 1));
+    let _handle = pad.register_notification(|_| {}).unwrap();
+}
+
+#[test]
+fn test_target_dropped_first() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let mut pad = client.connect_x360_pad().unwrap();
+    let serial = pad.index();
+    let events = pad.events().unwrap();
+
+    assert!(bus.notify_x360(serial, 0, 0, 1));
+    drop(pad);
+    assert_eq!(events.try_recv(), Ok(X360Event::LedChanged(1)));
+    assert_eq!(events.try_recv(), Err(TryRecvError::Disconnected));
+}
+
+#[test]
+fn test_drop_on_other_thread() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    for _ in 0..50 {
+        let mut pad = client.connect_x360_pad().unwrap();
+        let serial = pad.index();
+        let events = pad.events().unwrap();
+
+        // Whichever of the two is dropped first, the handler is unregistered and freed once
+        let receiver = thread::spawn(move || {
+            let _ = events.recv_timeout(Duration::from_millis(1));
+        });
+        let _ = bus.notify_x360(serial, 5, 5, 1);
+        drop(pad);
+        receiver.join().unwrap();
+        assert!(!bus.notify_x360(serial, 5, 5, 1));
+    }
+}
+
+#[test]
+fn test_blocking_recv() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let mut pad = client.connect_x360_pad().unwrap();
+    let serial = pad.index();
+    let events = pad.events().unwrap();
+
+    let notifier = thread::spawn(move || assert!(bus.notify_x360(serial, 0, 255, 4)));
+    assert_eq!(
+        events.recv_timeout(Duration::from_secs(5)),
+        Ok(X360Event::LedChanged(4))
+    );
+    notifier.join().unwrap();
+    assert_eq!(
+        events.recv(),
+        Ok(X360Event::Rumble {
+            large: 0,
+            small: 255
+        })
+    );
+    assert_eq!(
+        events.recv_timeout(Duration::from_millis(10)),
+        Err(RecvTimeoutError::Timeout)
+    );
+}
@@ -0,0 +1,25 @@
+#![cfg(any(feature = "ffi", feature = "mock"))]
+
+use std::{sync::atomic::AtomicBool, time::Duration};
+
+use vigem_client_c::{Client, X360Buttons, X360State};
+
+#[test]
+fn test_update_interpolated() {
+    let client = Client::new().unwrap();
+    let pad = client.connect_x360_pad().unwrap();
+    let to = X360State::builder()
+        .press(X360Buttons::B)
+        .left_stick(1000, -1000)
+        .build();
+
+    let cancel = AtomicBool::new(false);
+    assert!(pad
+        .update_interpolated(X360State::default(), to, 3, Duration::ZERO, &cancel)
+        .unwrap());
+
+    let cancel = AtomicBool::new(true);
+    assert!(!pad
+        .update_interpolated(to, X360State::default(), 3, Duration::ZERO, &cancel)
+        .unwrap());
+}
@@ -0,0 +1,341 @@
+//! The client against the in-memory bus, which runs on any OS.
+//! Run with `cargo test -p vigem-client-c --no-default-features --features mock`.
+#![cfg(feature = "mock")]
+
+use std::sync::{
+    atomic::{AtomicU8, Ordering::SeqCst},
+    mpsc,
+};
+
+use vigem_client_c::{
+    client::{TargetType, UserIndex},
+    mock::MockEvent,
+    Client, DS4Dpad, DS4State, Error, X360Buttons, X360State, XusbReport,
+};
+
+#[test]
+fn test_update_ordering() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let first = client.connect_x360_pad().unwrap();
+    let second = client.connect_x360_pad().unwrap();
+
+    let states: Vec<_> = (0..5)
+        .map(|i| X360State::builder().left_trigger(i * 50).build())
+        .collect();
+    for state in &states {
+        first.update(*state).unwrap();
+        second
+            .update(X360State::builder().press(X360Buttons::A).build())
+            .unwrap();
+    }
+
+    assert_eq!(bus.x360_reports(first.index()), states);
+    assert_eq!(bus.x360_reports(second.index()).len(), states.len());
+    assert_eq!(
+        bus.events()[..4],
+        [
+            MockEvent::Added {
+                serial: first.index(),
+                target_type: TargetType::Xbox360Wired
+            },
+            MockEvent::Added {
+                serial: second.index(),
+                target_type: TargetType::Xbox360Wired
+            },
+            MockEvent::X360Report {
+                serial: first.index(),
+                state: states[0]
+            },
+            MockEvent::X360Report {
+                serial: second.index(),
+                state: X360State::builder().press(X360Buttons::A).build()
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_guide_passes_through() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let pad = client.connect_x360_pad().unwrap();
+
+    let state = X360State::builder()
+        .press(X360Buttons::GUIDE | X360Buttons::A)
+        .build();
+    pad.update(state).unwrap();
+    assert_eq!(bus.x360_reports(pad.index()), [state]);
+}
+
+#[test]
+fn test_ds4_report() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let pad = client.connect_ds4_pad().unwrap();
+
+    let state = DS4State {
+        dpad: DS4Dpad::SouthWest,
+        left_trigger: 12,
+        ..DS4State::default()
+    };
+    pad.update(state).unwrap();
+    assert_eq!(
+        bus.events().last(),
+        Some(&MockEvent::DS4Report {
+            serial: pad.index(),
+            state
+        })
+    );
+}
+
+#[test]
+fn test_drop_removes() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let pad = client.connect_x360_pad().unwrap();
+    let serial = pad.index();
+    assert_eq!(bus.targets(), [serial]);
+
+    drop(pad);
+    assert!(bus.targets().is_empty());
+    assert!(bus
+        .events()
+        .ends_with(&[MockEvent::Removed { serial }, MockEvent::Freed { serial }]));
+
+    // The serial number is free to be handed out again
+    let pad = client.connect_ds4_pad().unwrap();
+    assert_eq!(pad.index(), serial);
+}
+
+#[test]
+fn test_notification() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let mut pad = client.connect_x360_pad().unwrap();
+    assert!(!bus.notify_x360(pad.index(), 1, 2, 3));
+
+    let (tx, rx) = mpsc::channel();
+    let tx = std::sync::Mutex::new(tx);
+    let handle = pad
+        .register_notification(move |data| tx.lock().unwrap().send(data).unwrap())
+        .unwrap();
+    assert!(bus.notify_x360(pad.index(), 255, 128, 2));
+    let data = rx.try_recv().unwrap();
+    assert_eq!(
+        (data.large_motor, data.small_motor, data.led_number),
+        (255, 128, 2)
+    );
+
+    pad.unregister_notification(handle).unwrap();
+    assert!(!bus.notify_x360(pad.index(), 1, 2, 3));
+}
+
+#[test]
+fn test_cross_unregister() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let mut first = client.connect_x360_pad().unwrap();
+    let mut second = client.connect_x360_pad().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let first_tx = std::sync::Mutex::new(tx.clone());
+    let second_tx = std::sync::Mutex::new(tx);
+    let first_handle = first
+        .register_notification(move |data| {
+            first_tx.lock().unwrap().send((1, data.led_number)).unwrap()
+        })
+        .unwrap();
+    let second_handle = second
+        .register_notification(move |data| {
+            second_tx
+                .lock()
+                .unwrap()
+                .send((2, data.led_number))
+                .unwrap()
+        })
+        .unwrap();
+
+    // Each handle only unregisters the callback it was returned for, even of the same type
+    assert!(matches!(
+        second.unregister_notification(first_handle),
+        Err(Error::CallbackNotFound)
+    ));
+    assert!(bus.notify_x360(first.index(), 0, 0, 0));
+    assert!(bus.notify_x360(second.index(), 0, 0, 1));
+    assert_eq!(rx.try_iter().collect::<Vec<_>>(), [(1, 0), (2, 1)]);
+
+    second.unregister_notification(second_handle).unwrap();
+    assert!(!bus.notify_x360(second.index(), 0, 0, 1));
+    assert!(bus.notify_x360(first.index(), 0, 0, 2));
+    assert_eq!(rx.try_iter().collect::<Vec<_>>(), [(1, 2)]);
+}
+
+#[test]
+fn test_ds4_notification() {
+    // The callback borrows this, so it has to outlive the pad
+    let red = AtomicU8::new(0);
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let mut pad = client.connect_ds4_pad().unwrap();
+
+    let _handle = pad
+        .register_notification(|data| red.store(data.lightbar_color.0, SeqCst))
+        .unwrap();
+    assert!(bus.notify_ds4(pad.index(), 0, 0, (200, 10, 20)));
+    assert_eq!(red.load(SeqCst), 200);
+}
+
+#[test]
+fn test_user_index() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let pads: Vec<_> = (0..5).map(|_| client.connect_x360_pad().unwrap()).collect();
+
+    // Only four slots go around
+    let indices: Vec<_> = pads.iter().map(|pad| pad.user_index().unwrap()).collect();
+    assert_eq!(
+        indices,
+        [
+            UserIndex::Assigned(0),
+            UserIndex::Assigned(1),
+            UserIndex::Assigned(2),
+            UserIndex::Assigned(3),
+            UserIndex::Unassigned
+        ]
+    );
+
+    bus.set_user_index(pads[4].index(), Some(1));
+    assert_eq!(pads[4].user_index().unwrap(), UserIndex::Assigned(1));
+}
+
+#[test]
+fn test_bus_lost() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let pad = client.connect_x360_pad().unwrap();
+
+    bus.unplug();
+    assert!(matches!(
+        pad.update(X360State::default()),
+        Err(Error::BusNotFound)
+    ));
+    assert!(!client.is_connected());
+    assert!(matches!(client.connect_ds4_pad(), Err(Error::BusNotFound)));
+}
+
+#[test]
+fn test_separate_buses() {
+    let first = Client::new_mock().unwrap();
+    let second = Client::new_mock().unwrap();
+    let _pad = first.connect_x360_pad().unwrap();
+    assert_eq!(first.mock_bus().targets().len(), 1);
+    assert!(second.mock_bus().targets().is_empty());
+}
+
+#[test]
+fn test_ids_at_add() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let mut pad = client.x360_pad().vendor_id(0x1234).connect().unwrap();
+    assert_eq!(bus.ids(pad.index()), Some((0x1234, 0x028E)));
+
+    // Setting them once the target is plugged in is too late for the bus
+    pad.set_product_id(0x5678);
+    assert_eq!(pad.product_id(), 0x5678);
+    assert_eq!(bus.ids(pad.index()), Some((0x1234, 0x028E)));
+}
+
+#[test]
+fn test_removal_failure_frees() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let pad = client.connect_x360_pad().unwrap();
+    let serial = pad.index();
+    assert!(pad.is_attached());
+
+    // Removing the target fails without a bus, yet it's freed, once
+    bus.unplug();
+    assert!(pad.is_attached());
+    assert!(matches!(pad.remove(), Err(Error::RemovalFailed)));
+    let freed = |bus: &vigem_client_c::mock::MockBus| {
+        bus.events()
+            .iter()
+            .filter(|event| **event == MockEvent::Freed { serial })
+            .count()
+    };
+    assert_eq!(freed(&bus), 1);
+    assert!(!bus.events().contains(&MockEvent::Removed { serial }));
+
+    // Just like when it's dropped
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let pad = client.connect_ds4_pad().unwrap();
+    bus.unplug();
+    drop(pad);
+    assert_eq!(freed(&bus), 1);
+}
+
+#[test]
+fn test_add_failure_frees() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    bus.unplug();
+
+    // Nothing owns a target which couldn't be plugged in, so it's freed right away
+    assert!(matches!(client.connect_x360_pad(), Err(Error::BusNotFound)));
+    assert!(matches!(client.connect_ds4_pad(), Err(Error::BusNotFound)));
+    assert_eq!(
+        bus.events(),
+        [
+            MockEvent::Freed { serial: 0 },
+            MockEvent::Freed { serial: 0 }
+        ]
+    );
+}
+
+#[test]
+fn test_remove_frees() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let pad = client.connect_x360_pad().unwrap();
+    let serial = pad.index();
+    pad.remove().unwrap();
+    assert_eq!(
+        bus.events()[1..],
+        [MockEvent::Removed { serial }, MockEvent::Freed { serial }]
+    );
+}
+
+#[test]
+fn test_update_raw() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let pad = client.connect_x360_pad().unwrap();
+
+    let report = XusbReport {
+        buttons: 0x0800 | X360Buttons::B.bits(),
+        left_trigger: 7,
+        thumb_ry: -300,
+        ..XusbReport::default()
+    };
+    pad.update_raw(report).unwrap();
+    pad.update(X360State::builder().press(X360Buttons::A).build())
+        .unwrap();
+
+    // The reserved bit reaches the bus, while the state is whatever's known of it
+    assert_eq!(
+        bus.x360_raw_reports(pad.index()),
+        [
+            report,
+            XusbReport {
+                buttons: X360Buttons::A.bits(),
+                ..XusbReport::default()
+            }
+        ]
+    );
+    assert_eq!(
+        bus.x360_reports(pad.index())[0],
+        X360State::from_raw(report)
+    );
+}
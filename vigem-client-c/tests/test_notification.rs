@@ -1,6 +1,8 @@
+#![cfg(any(feature = "ffi", feature = "mock"))]
+
 use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
 
-use vigem_client_c::Client;
+use vigem_client_c::{Client, Error};
 
 #[test]
 fn test_drop() {
@@ -14,17 +16,94 @@ fn test_drop() {
         }
     }
 
+    // The callback borrows the flag, so the flag has to outlive the pad
+    let flag = AtomicBool::new(false);
+    let _checker = DropChecker { flag: &flag };
     let client = Client::new().unwrap();
     let mut pad = client.connect_x360_pad().unwrap();
+
+    let handle = pad
+        .register_notification(move |_| {
+            let _checker = &_checker;
+        })
+        .unwrap();
+    assert!(!flag.load(SeqCst));
+    pad.unregister_notification(handle).unwrap();
+    assert!(flag.load(SeqCst));
+}
+
+#[test]
+fn test_drop_ds4() {
+    struct DropChecker<'flag> {
+        flag: &'flag AtomicBool,
+    }
+
+    impl Drop for DropChecker<'_> {
+        fn drop(&mut self) {
+            self.flag.store(true, SeqCst);
+        }
+    }
+
+    // The callback borrows the flag, so the flag has to outlive the pad
     let flag = AtomicBool::new(false);
     let _checker = DropChecker { flag: &flag };
+    let client = Client::new().unwrap();
+    let mut pad = client.connect_ds4_pad().unwrap();
 
     let handle = pad
         .register_notification(move |_| {
             let _checker = &_checker;
         })
         .unwrap();
+    assert!(matches!(
+        pad.register_notification(|_| {}),
+        Err(Error::AlreadyHasCallback)
+    ));
+    assert!(!flag.load(SeqCst));
+    pad.unregister_notification(handle).unwrap();
+    assert!(flag.load(SeqCst));
+}
+
+#[test]
+fn test_drop_target() {
+    struct DropChecker<'flag> {
+        flag: &'flag AtomicBool,
+    }
+
+    impl Drop for DropChecker<'_> {
+        fn drop(&mut self) {
+            self.flag.store(true, SeqCst);
+        }
+    }
+
+    let flag = AtomicBool::new(false);
+    let _checker = DropChecker { flag: &flag };
+    let client = Client::new().unwrap();
+    let mut pad = client.connect_x360_pad().unwrap();
+
+    let _handle = pad
+        .register_notification(move |_| {
+            let _checker = &_checker;
+        })
+        .unwrap();
     assert!(!flag.load(SeqCst));
-    pad.unregister_notification(handle);
+    drop(pad);
     assert!(flag.load(SeqCst));
 }
+
+#[test]
+fn test_stale_handle() {
+    let client = Client::new().unwrap();
+    let mut pad = client.connect_x360_pad().unwrap();
+
+    let handle = pad.register_notification(|_| {}).unwrap();
+    pad.unregister_notification(handle).unwrap();
+
+    let mut other = client.connect_x360_pad().unwrap();
+    let _other_handle = other.register_notification(|_| {}).unwrap();
+    let stale = pad.register_notification(|_| {}).unwrap();
+    assert!(matches!(
+        other.unregister_notification(stale),
+        Err(Error::CallbackNotFound)
+    ));
+}
@@ -0,0 +1,26 @@
+#![cfg(any(feature = "ffi", feature = "mock"))]
+
+use std::sync::Arc;
+
+use vigem_client_c::{client::OwnedTarget, client::X360, Client, X360State};
+
+#[test]
+fn test_owned_target_outlives_client_handle() {
+    struct Pads {
+        pads: Vec<OwnedTarget<X360>>,
+    }
+
+    let client = Arc::new(Client::new().unwrap());
+    let mut pads = Pads {
+        pads: vec![
+            client.connect_x360_pad_owned().unwrap(),
+            client.connect_x360_pad_owned().unwrap(),
+        ],
+    };
+    drop(client);
+
+    for pad in &mut pads.pads {
+        pad.update(X360State::default()).unwrap();
+    }
+    pads.pads.pop().unwrap().remove().unwrap();
+}
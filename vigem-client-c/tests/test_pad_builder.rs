@@ -0,0 +1,67 @@
+#![cfg(any(feature = "ffi", feature = "mock"))]
+
+use std::{collections::HashSet, sync::Arc};
+
+use vigem_client_c::{client::Preset, Client};
+
+#[test]
+fn test_configured_ids() {
+    let client = Client::new().unwrap();
+    let x360 = client
+        .x360_pad()
+        .vendor_id(0x1234)
+        .product_id(0x5678)
+        .connect()
+        .unwrap();
+    assert_eq!((x360.vendor_id(), x360.product_id()), (0x1234, 0x5678));
+
+    let ds4 = client.ds4_pad().product_id(0x09CC).connect().unwrap();
+    assert_eq!(ds4.product_id(), 0x09CC);
+}
+
+#[test]
+fn test_default_ids() {
+    let client = Client::new().unwrap();
+    let built = client.x360_pad().connect().unwrap();
+    let shortcut = client.connect_x360_pad().unwrap();
+    assert_eq!(
+        (built.vendor_id(), built.product_id()),
+        (shortcut.vendor_id(), shortcut.product_id())
+    );
+}
+
+#[test]
+fn test_presets_distinct() {
+    let presets: Vec<_> = Preset::all().collect();
+    let ids: HashSet<_> = presets
+        .iter()
+        .map(|preset| (preset.vendor_id(), preset.product_id()))
+        .collect();
+    assert_eq!(ids.len(), presets.len(), "two presets share their ids");
+
+    for &preset in &presets {
+        assert_eq!(Preset::find(preset.name()), Some(preset));
+    }
+    assert_eq!(Preset::find("logitech_f310"), Some(Preset::LogitechF310));
+    assert_eq!(Preset::find("Logitech F310"), None);
+}
+
+#[test]
+fn test_presets_connect() {
+    let client = Arc::new(Client::new().unwrap());
+    for preset in Preset::all() {
+        let ids = (preset.vendor_id(), preset.product_id());
+        let x360 = client.x360_pad().preset(preset).connect().unwrap();
+        assert_eq!((x360.vendor_id(), x360.product_id()), ids, "{:?}", preset);
+        let ds4 = client.ds4_pad_owned().preset(preset).connect().unwrap();
+        assert_eq!((ds4.vendor_id(), ds4.product_id()), ids, "{:?}", preset);
+    }
+
+    // Targets are what the presets for their type say by default
+    let x360 = client.connect_x360_pad().unwrap();
+    let wired = Preset::MicrosoftX360Wired;
+    assert_eq!(
+        (x360.vendor_id(), x360.product_id()),
+        (wired.vendor_id(), wired.product_id())
+    );
+}
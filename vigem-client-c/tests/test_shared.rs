@@ -0,0 +1,26 @@
+#![cfg(any(feature = "ffi", feature = "mock"))]
+
+use std::sync::Arc;
+
+use vigem_client_c::{Client, X360State};
+
+#[test]
+fn test_shared() {
+    let a = Client::shared().unwrap();
+    let b = Client::shared().unwrap();
+    assert!(Arc::ptr_eq(&a, &b));
+
+    let pad_a = a.connect_x360_pad_owned().unwrap();
+    let pad_b = b.connect_x360_pad_owned().unwrap();
+    pad_a.update(X360State::default()).unwrap();
+    pad_b.update(X360State::default()).unwrap();
+}
+
+#[test]
+fn test_shared_reconnect() {
+    drop(Client::shared().unwrap());
+
+    let client = Client::shared().unwrap();
+    let pad = client.connect_x360_pad().unwrap();
+    pad.update(X360State::default()).unwrap();
+}
@@ -0,0 +1,71 @@
+//! Connecting and removing pads over and over, checking that the process doesn't keep more
+//! handles or memory for it. It takes a while, so it's only run when asked for, e.g. with
+//! `cargo test -p vigem-client-c --no-default-features --features mock,soak --test test_soak -- --ignored`,
+//! or against ViGEmBus with `--features soak`.
+#![cfg(all(feature = "soak", any(feature = "ffi", feature = "mock")))]
+
+use std::env;
+
+use vigem_client_c::{
+    soak::{soak, Usage},
+    Client, X360Buttons, X360State,
+};
+
+/// How many times to go through a client's whole life before measuring, unless
+/// `SOAK_ITERATIONS` says how many to measure
+const WARM_UP: usize = 100;
+const ITERATIONS: usize = 5000;
+const UPDATES: usize = 1000;
+
+/// How much the process may grow over all the iterations, which is well below one handle or
+/// a few hundred bytes per iteration
+const BOUND: Usage = Usage {
+    handles: 16,
+    private_bytes: 4 << 20,
+};
+
+#[cfg(feature = "mock")]
+fn client() -> Client {
+    Client::new_mock().unwrap()
+}
+
+#[cfg(not(feature = "mock"))]
+fn client() -> Client {
+    Client::new().unwrap()
+}
+
+#[test]
+#[ignore]
+fn test_connect_cycles() {
+    let iterations = env::var("SOAK_ITERATIONS")
+        .ok()
+        .and_then(|iterations| iterations.parse().ok())
+        .unwrap_or(ITERATIONS);
+    let growth = soak(WARM_UP, iterations, |i| {
+        let client = client();
+        let mut pad = client.connect_x360_pad().unwrap();
+        let handle = pad.register_notification(|_| {}).unwrap();
+        pad.unregister_notification(handle).unwrap();
+        // The other half of the time the target frees the callback when it's removed
+        if i % 2 == 0 {
+            let _ = pad.register_notification(|_| {}).unwrap();
+        }
+        for n in 0..UPDATES {
+            let buttons = if n % 2 == 0 {
+                X360Buttons::A
+            } else {
+                X360Buttons::empty()
+            };
+            pad.update(X360State::builder().press(buttons).build())
+                .unwrap();
+        }
+        pad.remove().unwrap();
+    })
+    .unwrap();
+    assert!(
+        !growth.exceeds(&BOUND),
+        "grew by {:?} over {} iterations",
+        growth,
+        iterations
+    );
+}
@@ -0,0 +1,45 @@
+//! The gamepad states without ViGEmClient, as e.g. a WebAssembly controller would use them.
+//! Run with `cargo test -p vigem-client-c --no-default-features --features serde,wire`.
+#![cfg(not(any(feature = "ffi", feature = "mock")))]
+
+use vigem_client_c::{axis_to_f32, DS4State, DS4TouchPoint, X360Buttons, X360State};
+
+#[test]
+fn test_builder() {
+    let from = X360State::builder().press(X360Buttons::A).build();
+    let to = X360State::builder()
+        .press(X360Buttons::B)
+        .left_trigger(200)
+        .left_stick(i16::MAX, i16::MIN)
+        .build();
+
+    let halfway = from.interpolate(to, 1, 2);
+    assert_eq!(halfway.buttons, X360Buttons::B);
+    assert_eq!(halfway.left_trigger, 100);
+    assert_eq!(axis_to_f32(i16::MIN), -1.0);
+}
+
+#[test]
+fn test_names() {
+    let buttons = X360Buttons::A | X360Buttons::START;
+    assert_eq!(buttons.to_string(), "START|A");
+    assert_eq!("start|a".parse(), Ok(buttons));
+    assert!("TURBO".parse::<X360Buttons>().is_err());
+}
+
+#[test]
+fn test_ds4() {
+    let touch = DS4TouchPoint::new(3, 1000, 500);
+    assert_eq!((touch.x(), touch.y()), (1000, 500));
+    assert_eq!(DS4State::default().left_thumbstick, (0x80, 0x80));
+}
+
+#[cfg(feature = "wire")]
+#[test]
+fn test_wire() {
+    let state = X360State::builder()
+        .press(X360Buttons::Y)
+        .right_stick(-1, 1234)
+        .build();
+    assert_eq!(X360State::from_bytes(&state.to_bytes()), Ok(state));
+}
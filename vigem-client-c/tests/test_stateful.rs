@@ -0,0 +1,102 @@
+//! Run with `cargo test -p vigem-client-c --no-default-features --features mock`.
+#![cfg(feature = "mock")]
+
+use std::time::Duration;
+
+use vigem_client_c::{
+    stateful::{StatefulPad, Trigger},
+    Client, X360Buttons, X360State,
+};
+
+fn buttons(buttons: X360Buttons) -> X360State {
+    X360State::builder().press(buttons).build()
+}
+
+#[test]
+fn test_modify_diffs() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let mut pad = StatefulPad::new(client.connect_x360_pad().unwrap());
+    let serial = pad.target().index();
+
+    assert!(!pad.modify(|_| {}).unwrap());
+    assert!(pad.modify(|state| state.left_trigger = 10).unwrap());
+    assert!(!pad.modify(|state| state.left_trigger = 10).unwrap());
+    assert_eq!(
+        bus.x360_reports(serial),
+        [X360State::builder().left_trigger(10).build()]
+    );
+}
+
+#[test]
+fn test_tap() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let mut pad = StatefulPad::new(client.connect_x360_pad().unwrap());
+    let serial = pad.target().index();
+
+    pad.tap(X360Buttons::A, Duration::ZERO).unwrap();
+    assert_eq!(
+        bus.x360_reports(serial),
+        [buttons(X360Buttons::A), X360State::default()]
+    );
+}
+
+#[test]
+fn test_hold_composes() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let mut pad = StatefulPad::new(client.connect_x360_pad().unwrap());
+    let serial = pad.target().index();
+
+    let mut held = pad.hold(X360Buttons::LEFT_SHOULDER).unwrap();
+    // Tapping a button which is already held leaves it held
+    held.tap(X360Buttons::A | X360Buttons::LEFT_SHOULDER, Duration::ZERO)
+        .unwrap();
+    assert_eq!(held.state(), buttons(X360Buttons::LEFT_SHOULDER));
+    drop(held);
+    assert_eq!(pad.state(), X360State::default());
+
+    assert_eq!(
+        bus.x360_reports(serial),
+        [
+            buttons(X360Buttons::LEFT_SHOULDER),
+            buttons(X360Buttons::LEFT_SHOULDER | X360Buttons::A),
+            buttons(X360Buttons::LEFT_SHOULDER),
+            X360State::default(),
+        ]
+    );
+}
+
+#[test]
+fn test_pulse_trigger() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let mut pad = StatefulPad::new(client.connect_x360_pad().unwrap());
+    let serial = pad.target().index();
+
+    let _ = pad.modify(|state| state.right_trigger = 20).unwrap();
+    pad.pulse_trigger(Trigger::Right, 255, Duration::ZERO)
+        .unwrap();
+    assert_eq!(
+        bus.x360_reports(serial),
+        [
+            X360State::builder().right_trigger(20).build(),
+            X360State::builder().right_trigger(255).build(),
+            X360State::builder().right_trigger(20).build(),
+        ]
+    );
+}
+
+#[test]
+fn test_release_error() {
+    let client = Client::new_mock().unwrap();
+    let bus = client.mock_bus();
+    let mut pad = StatefulPad::new(client.connect_x360_pad().unwrap());
+
+    let held = pad.hold(X360Buttons::B).unwrap();
+    bus.unplug();
+    assert!(held.release().is_err());
+    // The state is what the bus was last sent
+    assert_eq!(pad.state(), buttons(X360Buttons::B));
+}
@@ -0,0 +1,164 @@
+#![cfg(feature = "std")]
+
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI, SQRT_2};
+
+use vigem_client_c::{StickPosition, X360State};
+
+const EXTREMES: [StickPosition; 8] = [
+    StickPosition::new(i16::MAX, 0),
+    StickPosition::new(i16::MAX, i16::MAX),
+    StickPosition::new(0, i16::MAX),
+    StickPosition::new(i16::MIN, i16::MAX),
+    StickPosition::new(i16::MIN, 0),
+    StickPosition::new(i16::MIN, i16::MIN),
+    StickPosition::new(0, i16::MIN),
+    StickPosition::new(i16::MAX, i16::MIN),
+];
+
+fn assert_close(actual: f32, expected: f32) {
+    assert!(
+        (actual - expected).abs() < 1e-4,
+        "{} isn't close to {}",
+        actual,
+        expected
+    );
+}
+
+#[test]
+fn test_tuples() {
+    let stick = StickPosition::from((1000, -2000));
+    assert_eq!((stick.x, stick.y), (1000, -2000));
+    assert_eq!(<(i16, i16)>::from(stick), (1000, -2000));
+    assert_eq!(stick, (1000, -2000));
+    assert_eq!((1000, -2000), stick);
+    assert_eq!(StickPosition::default(), StickPosition::CENTER);
+
+    let state = X360State::builder().left_stick(-1, 1).build();
+    assert_eq!(state.left_thumbstick, StickPosition::new(-1, 1));
+}
+
+#[test]
+fn test_magnitude() {
+    assert_eq!(StickPosition::CENTER.magnitude(), 0.0);
+    // i16::MIN is as far out as i16::MAX, although it's a step further from zero
+    assert_eq!(StickPosition::new(i16::MAX, 0).magnitude(), 1.0);
+    assert_eq!(StickPosition::new(i16::MIN, 0).magnitude(), 1.0);
+    assert_eq!(StickPosition::new(0, i16::MIN).magnitude(), 1.0);
+    assert_close(StickPosition::new(i16::MIN, i16::MIN).magnitude(), SQRT_2);
+    assert_close(StickPosition::new(i16::MAX, i16::MIN).magnitude(), SQRT_2);
+}
+
+#[test]
+fn test_angle() {
+    assert_eq!(StickPosition::CENTER.angle(), 0.0);
+    for (index, stick) in EXTREMES.iter().enumerate() {
+        let expected = index as f32 * FRAC_PI_4;
+        let expected = if expected > PI {
+            expected - 2.0 * PI
+        } else {
+            expected
+        };
+        assert_close(stick.angle(), expected);
+    }
+}
+
+#[test]
+fn test_from_polar() {
+    assert_eq!(StickPosition::from_polar(0.0, 0.0), StickPosition::CENTER);
+    assert_eq!(StickPosition::from_polar(0.0, 1.0), (i16::MAX, 0));
+    assert_eq!(StickPosition::from_polar(FRAC_PI_2, 1.0), (0, i16::MAX));
+    assert_eq!(StickPosition::from_polar(PI, 1.0), (i16::MIN, 0));
+    assert_eq!(StickPosition::from_polar(-PI, 1.0), (i16::MIN, 0));
+    assert_eq!(StickPosition::from_polar(-FRAC_PI_2, 1.0), (0, i16::MIN));
+    assert_eq!(StickPosition::from_polar(0.0, 0.5), (16384, 0));
+    assert_eq!(StickPosition::from_polar(PI, 0.5), (-16384, 0));
+
+    // Too far out is clamped to the square range
+    assert_eq!(StickPosition::from_polar(PI, 2.0), (i16::MIN, 0));
+    assert_eq!(
+        StickPosition::from_polar(-3.0 * FRAC_PI_4, 2.0),
+        (i16::MIN, i16::MIN)
+    );
+
+    assert_eq!(
+        StickPosition::from_polar(f32::NAN, 1.0),
+        StickPosition::CENTER
+    );
+    assert_eq!(
+        StickPosition::from_polar(PI, f32::NAN),
+        StickPosition::CENTER
+    );
+}
+
+#[test]
+fn test_polar_round_trip() {
+    for &stick in &EXTREMES {
+        assert_eq!(
+            StickPosition::from_polar(stick.angle(), stick.magnitude()),
+            stick
+        );
+    }
+    for &stick in &[
+        StickPosition::new(1000, -2000),
+        StickPosition::new(-12345, 6789),
+        StickPosition::new(i16::MIN + 1, 1),
+    ] {
+        let round_trip = StickPosition::from_polar(stick.angle(), stick.magnitude());
+        assert!(
+            (i32::from(round_trip.x) - i32::from(stick.x)).abs() <= 1
+                && (i32::from(round_trip.y) - i32::from(stick.y)).abs() <= 1,
+            "{:?} came back as {:?}",
+            stick,
+            round_trip
+        );
+    }
+}
+
+#[test]
+fn test_clamped_circle() {
+    // Positions within the circle are left alone
+    for &stick in &[
+        StickPosition::CENTER,
+        StickPosition::new(i16::MAX, 0),
+        StickPosition::new(i16::MIN, 0),
+        StickPosition::new(0, i16::MIN),
+        StickPosition::new(1000, -2000),
+    ] {
+        assert_eq!(stick.clamped_circle(), stick);
+    }
+
+    // The corners are pulled in to the edge, keeping their direction
+    for &stick in &[
+        StickPosition::new(i16::MAX, i16::MAX),
+        StickPosition::new(i16::MIN, i16::MIN),
+        StickPosition::new(i16::MIN, i16::MAX),
+        StickPosition::new(i16::MAX, i16::MIN),
+    ] {
+        let clamped = stick.clamped_circle();
+        assert_close(clamped.magnitude(), 1.0);
+        assert_close(clamped.angle(), stick.angle());
+    }
+    assert_eq!(
+        StickPosition::new(i16::MIN, i16::MIN).clamped_circle(),
+        (-23170, -23170)
+    );
+}
+
+#[test]
+fn test_lerp() {
+    let from = StickPosition::new(i16::MIN, i16::MAX);
+    let to = StickPosition::new(i16::MAX, i16::MIN);
+    assert_eq!(from.lerp(to, 0.0), from);
+    assert_eq!(from.lerp(to, 1.0), to);
+    assert_eq!(to.lerp(from, 1.0), from);
+    // Halves are rounded away from zero
+    assert_eq!(from.lerp(to, 0.5), (-1, -1));
+    assert_eq!(
+        StickPosition::CENTER.lerp(StickPosition::new(100, -100), 0.25),
+        (25, -25)
+    );
+
+    assert_eq!(from.lerp(to, 2.0), to);
+    assert_eq!(from.lerp(to, -1.0), from);
+    assert_eq!(from.lerp(to, f32::NAN), from);
+}
@@ -0,0 +1,54 @@
+#![cfg(any(feature = "ffi", feature = "mock"))]
+
+use std::{sync::Arc, thread::spawn};
+
+use vigem_client_c::{Client, X360Buttons, X360State};
+
+#[test]
+fn test_update_from_other_thread() {
+    let client = Arc::new(Client::new().unwrap());
+    let pad = client.connect_x360_pad_owned().unwrap();
+
+    spawn(move || {
+        let state = X360State::builder().press(X360Buttons::A).build();
+        pad.update(state).unwrap();
+        pad.remove().unwrap();
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+fn test_share_client_between_threads() {
+    let client = Client::new().unwrap();
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                let pad = client.connect_x360_pad().unwrap();
+                pad.update(X360State::default()).unwrap();
+            });
+        }
+    });
+}
+
+#[test]
+fn test_update_from_two_threads() {
+    let client = Client::new().unwrap();
+    let pad = client.connect_x360_pad().unwrap();
+    std::thread::scope(|scope| {
+        for buttons in [X360Buttons::A, X360Buttons::B] {
+            let pad = &pad;
+            scope.spawn(move || {
+                for _ in 0..100 {
+                    let state = X360State::builder().press(buttons).build();
+                    pad.update(state).unwrap();
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn test_ui() {
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,60 @@
+#![cfg(feature = "async-update")]
+
+use std::{
+    sync::{mpsc::channel, Arc},
+    time::Duration,
+};
+
+use vigem_client_c::{Client, X360Buttons, X360State};
+
+#[test]
+fn test_many_updates() {
+    const UPDATES: usize = 1000;
+
+    let client = Client::new().unwrap();
+    let pad = client.connect_x360_pad().unwrap();
+
+    // Every callback holds on to a clone, so the count drops back to one once they're all freed
+    let token = Arc::new(());
+    let (tx, rx) = channel();
+    for i in 0..UPDATES {
+        let state = X360State::builder()
+            .press(if i % 2 == 0 {
+                X360Buttons::A
+            } else {
+                X360Buttons::B
+            })
+            .build();
+        let (tx, token) = (tx.clone(), Arc::clone(&token));
+        pad.update_async(state, move |result| {
+            let _token = token;
+            tx.send(result).unwrap();
+        })
+        .unwrap();
+    }
+    drop(tx);
+
+    for _ in 0..UPDATES {
+        rx.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+    }
+    pad.remove().unwrap();
+    assert!(rx.recv().is_err());
+    assert_eq!(Arc::strong_count(&token), 1);
+}
+
+#[test]
+fn test_remove_waits() {
+    let client = Client::new().unwrap();
+    let pad = client.connect_x360_pad().unwrap();
+    let (tx, rx) = channel();
+    for _ in 0..10 {
+        let tx = tx.clone();
+        pad.update_async(X360State::default(), move |result| tx.send(result).unwrap())
+            .unwrap();
+    }
+    drop(tx);
+
+    // Removal only returns once every update has completed, and so sent its result
+    pad.remove().unwrap();
+    assert_eq!(rx.try_iter().count(), 10);
+}
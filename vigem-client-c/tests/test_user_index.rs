@@ -0,0 +1,39 @@
+#![cfg(any(feature = "ffi", feature = "mock"))]
+
+use std::time::Duration;
+
+use vigem_client_c::{client::UserIndex, Client, Error};
+
+#[test]
+fn test_wait_for_user_index() {
+    let client = Client::new().unwrap();
+    let pad = client.connect_x360_pad().unwrap();
+    let index = pad.wait_for_user_index(Duration::from_secs(1)).unwrap();
+    assert_eq!(pad.user_index().unwrap(), UserIndex::Assigned(index));
+}
+
+#[test]
+fn test_from_reply() {
+    let assigned = |index| UserIndex::from_reply(Ok(()), index).unwrap();
+    assert_eq!(assigned(0), UserIndex::Assigned(0));
+    assert_eq!(assigned(3), UserIndex::Assigned(3));
+    assert_eq!(assigned(4), UserIndex::Unknown(4));
+    assert_eq!(assigned(0xDEADBEEF), UserIndex::Unknown(0xDEADBEEF));
+
+    // Not having a slot yet is expected right after being plugged in, so it's no error
+    assert_eq!(
+        UserIndex::from_reply(Err(Error::UserIndexOutOfRange), 0xDEADBEEF).unwrap(),
+        UserIndex::Unassigned
+    );
+    assert!(matches!(
+        UserIndex::from_reply(Err(Error::TargetNotPluggedIn), 0),
+        Err(Error::TargetNotPluggedIn)
+    ));
+}
+
+#[test]
+fn test_assigned() {
+    assert_eq!(UserIndex::Assigned(2).assigned(), Some(2));
+    assert_eq!(UserIndex::Unassigned.assigned(), None);
+    assert_eq!(UserIndex::Unknown(7).assigned(), None);
+}
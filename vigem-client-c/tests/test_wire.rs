@@ -0,0 +1,63 @@
+#![cfg(feature = "wire")]
+
+use vigem_client_c::{WireError, X360Buttons, X360State};
+
+#[test]
+fn test_round_trip() {
+    let state = X360State::builder()
+        .press(X360Buttons::A | X360Buttons::DPAD_LEFT | X360Buttons::Y)
+        .left_trigger(12)
+        .right_trigger(255)
+        .left_stick(i16::MIN, i16::MAX)
+        .right_stick(-1, 1234)
+        .build();
+
+    let bytes = state.to_bytes();
+    assert_eq!(&bytes[..4], &[0x04, 0x90, 12, 255]);
+    assert_eq!(&bytes[4..6], &[0x00, 0x80]);
+
+    let decoded = X360State::from_bytes(&bytes).unwrap();
+    assert_eq!(decoded.buttons, state.buttons);
+    assert_eq!(decoded.left_trigger, state.left_trigger);
+    assert_eq!(decoded.right_trigger, state.right_trigger);
+    assert_eq!(decoded.left_thumbstick, state.left_thumbstick);
+    assert_eq!(decoded.right_thumbstick, state.right_thumbstick);
+}
+
+#[test]
+fn test_malformed() {
+    let bytes = X360State::default().to_bytes();
+    assert_eq!(
+        X360State::from_bytes(&bytes[..11]).unwrap_err(),
+        WireError::InvalidLength(11)
+    );
+    assert_eq!(
+        X360State::from_bytes(&[bytes.as_ref(), &[0]].concat()).unwrap_err(),
+        WireError::InvalidLength(13)
+    );
+    assert_eq!(
+        X360State::from_bytes(&[]).unwrap_err(),
+        WireError::InvalidLength(0)
+    );
+
+    let mut bytes = bytes;
+    bytes[1] = 0x0C;
+    assert_eq!(
+        X360State::from_bytes_strict(&bytes).unwrap_err(),
+        WireError::InvalidButtons(0x0C00)
+    );
+}
+
+#[test]
+fn test_unknown_buttons() {
+    let mut bytes = X360State::builder()
+        .press(X360Buttons::A)
+        .left_trigger(7)
+        .build()
+        .to_bytes();
+    bytes[1] |= 0x0C;
+
+    let state = X360State::from_bytes(&bytes).unwrap();
+    assert_eq!(state.buttons, X360Buttons::A | X360Buttons::GUIDE);
+    assert_eq!(state.left_trigger, 7);
+}
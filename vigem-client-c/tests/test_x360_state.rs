@@ -0,0 +1,421 @@
+#![cfg(feature = "std")]
+
+use vigem_client_c::{
+    axis_from_f32, axis_to_f32, AxisChange, SocdPolicy, StateDiff, StickPosition, X360Axis,
+    X360Buttons, X360State, XusbReport,
+};
+
+static STATE: X360State = X360State::builder()
+    .press(X360Buttons::A)
+    .left_trigger(255)
+    .build();
+
+#[test]
+fn test_builder_accumulates_presses() {
+    let state = X360State::builder()
+        .press(X360Buttons::A | X360Buttons::START)
+        .press(X360Buttons::B)
+        .left_trigger(255)
+        .left_stick(1000, -2000)
+        .right_stick(-1, 1)
+        .build();
+
+    assert_eq!(
+        state.buttons,
+        X360Buttons::A | X360Buttons::B | X360Buttons::START
+    );
+    assert_eq!(state.left_trigger, 255);
+    assert_eq!(state.right_trigger, 0);
+    assert_eq!(state.left_thumbstick, (1000, -2000));
+    assert_eq!(state.right_thumbstick, (-1, 1));
+
+    assert_eq!(STATE.buttons, X360Buttons::A);
+    assert_eq!(STATE.left_trigger, 255);
+}
+
+#[test]
+fn test_mutators() {
+    let mut state = X360State::default();
+    state.press(X360Buttons::A);
+    state.press(X360Buttons::X);
+    assert_eq!(state.buttons, X360Buttons::A | X360Buttons::X);
+
+    state.release(X360Buttons::A | X360Buttons::Y);
+    assert_eq!(state.buttons, X360Buttons::X);
+
+    state.toggle(X360Buttons::X | X360Buttons::B);
+    assert_eq!(state.buttons, X360Buttons::B);
+}
+
+#[test]
+fn test_axis_from_f32_boundaries() {
+    assert_eq!(axis_from_f32(1.0), i16::MAX);
+    assert_eq!(axis_from_f32(-1.0), i16::MIN);
+    assert_eq!(axis_from_f32(0.0), 0);
+    assert_eq!(axis_from_f32(-0.0), 0);
+    assert_eq!(axis_from_f32(2.0), i16::MAX);
+    assert_eq!(axis_from_f32(-2.0), i16::MIN);
+    assert_eq!(axis_from_f32(f32::NAN), 0);
+    assert_eq!(axis_from_f32(0.5), 16384);
+    assert_eq!(axis_from_f32(-0.5), -16384);
+
+    assert_eq!(axis_to_f32(i16::MAX), 1.0);
+    assert_eq!(axis_to_f32(i16::MIN), -1.0);
+    assert_eq!(axis_to_f32(0), 0.0);
+}
+
+#[test]
+fn test_set_f32() {
+    let mut state = X360State::default();
+    state.set_left_stick_f32(1.0, -1.0);
+    state.set_right_stick_f32(-1.0, 0.0);
+    state.set_triggers_f32(1.0, 0.5);
+    assert_eq!(state.left_thumbstick, (i16::MAX, i16::MIN));
+    assert_eq!(state.right_thumbstick, (i16::MIN, 0));
+    assert_eq!((state.left_trigger, state.right_trigger), (255, 128));
+
+    state.set_triggers_f32(-1.0, f32::NAN);
+    assert_eq!((state.left_trigger, state.right_trigger), (0, 0));
+}
+
+#[test]
+fn test_apply_deadzone() {
+    let mut state = X360State::default();
+    state.set_left_stick_f32(0.19, 0.0);
+    state.set_right_stick_f32(0.0, -0.21);
+    state.apply_deadzone(0.2);
+    assert_eq!(state.left_thumbstick, (0, 0));
+    let StickPosition { x, y } = state.right_thumbstick;
+    assert_eq!(x, 0);
+    assert!(y < 0 && y > -1000, "{}", y);
+
+    // The full range is still reachable, in every direction
+    state.set_left_stick_f32(1.0, 0.0);
+    state.set_right_stick_f32(0.0, -1.0);
+    state.apply_deadzone(0.2);
+    assert_eq!(state.left_thumbstick, (i16::MAX, 0));
+    assert_eq!(state.right_thumbstick, (0, i16::MIN));
+
+    // Rescaling keeps the direction of the stick
+    state.set_left_stick_f32(0.6, 0.6);
+    state.apply_deadzone(0.2);
+    let StickPosition { x, y } = state.left_thumbstick;
+    assert_eq!(x, y);
+
+    state.set_left_stick_f32(0.5, -0.5);
+    let before = state.left_thumbstick;
+    state.apply_deadzone(0.0);
+    assert_eq!(state.left_thumbstick, before);
+    state.apply_deadzone(1.0);
+    assert_eq!(state.left_thumbstick, (0, 0));
+}
+
+#[test]
+fn test_state_eq() {
+    let mut state = X360State::default();
+    assert_eq!(state, X360State::default());
+    state.press(X360Buttons::A);
+    assert_ne!(state, X360State::default());
+    state.release(X360Buttons::A);
+    assert_eq!(state, X360State::default());
+}
+
+#[test]
+fn test_interpolate() {
+    let from = X360State::builder()
+        .left_stick(i16::MIN, 0)
+        .right_stick(0, 100)
+        .left_trigger(0)
+        .right_trigger(255)
+        .build();
+    let to = X360State::builder()
+        .press(X360Buttons::A)
+        .left_stick(i16::MAX, 1)
+        .right_stick(0, -100)
+        .left_trigger(255)
+        .right_trigger(0)
+        .build();
+
+    assert_eq!(from.interpolate(to, 0, 3), from);
+    assert_eq!(from.interpolate(to, 3, 3), to);
+    assert_eq!(from.interpolate(to, 4, 3), to);
+    assert_eq!(from.interpolate(to, 0, 0), to);
+
+    // Buttons switch at the midpoint, which for odd steps is just past the middle
+    assert_eq!(from.interpolate(to, 1, 3).buttons, X360Buttons::empty());
+    assert_eq!(from.interpolate(to, 2, 3).buttons, X360Buttons::A);
+    assert_eq!(from.interpolate(to, 1, 2).buttons, X360Buttons::A);
+
+    // Halves are rounded away from the starting state, in both directions
+    let half = from.interpolate(to, 1, 2);
+    assert_eq!(half.left_thumbstick, (0, 1));
+    assert_eq!(half.right_thumbstick, (0, 0));
+    assert_eq!((half.left_trigger, half.right_trigger), (128, 127));
+
+    let third = from.interpolate(to, 1, 3);
+    assert_eq!(third.left_thumbstick, (-10923, 0));
+    assert_eq!(third.right_thumbstick, (0, 33));
+    assert_eq!((third.left_trigger, third.right_trigger), (85, 170));
+}
+
+#[test]
+fn test_into_ds4() {
+    use vigem_client_c::{DS4Buttons, DS4Dpad, DS4SpecialButtons, DS4State};
+
+    assert_eq!(DS4State::from(X360State::default()), DS4State::default());
+
+    let state = DS4State::from(
+        X360State::builder()
+            .press(X360Buttons::A | X360Buttons::Y | X360Buttons::START | X360Buttons::GUIDE)
+            .press(X360Buttons::DPAD_UP | X360Buttons::DPAD_LEFT)
+            .left_trigger(1)
+            .left_stick(i16::MIN, i16::MAX)
+            .right_stick(i16::MAX, i16::MIN)
+            .build(),
+    );
+    assert_eq!(
+        state.buttons,
+        DS4Buttons::CROSS | DS4Buttons::TRIANGLE | DS4Buttons::OPTIONS | DS4Buttons::TRIGGER_LEFT
+    );
+    assert_eq!(state.special, DS4SpecialButtons::PS);
+    assert_eq!(state.dpad, DS4Dpad::NorthWest);
+    assert_eq!((state.left_trigger, state.right_trigger), (1, 0));
+    // Up on the xbox 360 controller is down on the dualshock's Y axis
+    assert_eq!(state.left_thumbstick, (0x00, 0x00));
+    assert_eq!(state.right_thumbstick, (0xFF, 0xFF));
+
+    // Opposite directions cancel each other out
+    let state = DS4State::from(
+        X360State::builder()
+            .press(X360Buttons::DPAD_LEFT | X360Buttons::DPAD_RIGHT | X360Buttons::DPAD_DOWN)
+            .build(),
+    );
+    assert_eq!(state.dpad, DS4Dpad::South);
+}
+
+#[test]
+fn test_raw_report() {
+    // Laid out like ViGEmClient's XUSB_REPORT, which ViGEmBus takes as is
+    assert_eq!(core::mem::size_of::<XusbReport>(), 12);
+
+    let state = X360State::builder()
+        .press(X360Buttons::A | X360Buttons::GUIDE)
+        .right_trigger(200)
+        .left_stick(-1, 2)
+        .right_stick(3, i16::MIN)
+        .build();
+    let report = XusbReport::from(state);
+    assert_eq!(
+        report,
+        XusbReport {
+            buttons: 0x1400,
+            left_trigger: 0,
+            right_trigger: 200,
+            thumb_lx: -1,
+            thumb_ly: 2,
+            thumb_rx: 3,
+            thumb_ry: i16::MIN,
+        }
+    );
+    assert_eq!(report.unknown_buttons(), 0);
+    assert_eq!(X360State::from_raw(report), state);
+
+    // Bits which aren't a button's are dropped from the state, but not from the report
+    let report = XusbReport {
+        buttons: 0x0800 | 0x1000,
+        ..report
+    };
+    assert_eq!(report.unknown_buttons(), 0x0800);
+    assert_eq!(X360State::from_raw(report).buttons, X360Buttons::A);
+}
+
+#[test]
+fn test_sanitize() {
+    let dpad = |buttons| X360State::builder().press(buttons).build();
+    let all = X360Buttons::DPAD_UP
+        | X360Buttons::DPAD_DOWN
+        | X360Buttons::DPAD_LEFT
+        | X360Buttons::DPAD_RIGHT
+        | X360Buttons::A;
+    let sanitized = |policy, previous: Option<&X360State>| {
+        let mut state = dpad(all);
+        state.sanitize(policy, previous);
+        state.buttons
+    };
+
+    assert_eq!(sanitized(SocdPolicy::Neutral, None), X360Buttons::A);
+    assert_eq!(
+        sanitized(SocdPolicy::UpPriority, None),
+        X360Buttons::DPAD_UP | X360Buttons::A
+    );
+    // Last input priority can't tell which came last without a previous state
+    assert_eq!(sanitized(SocdPolicy::LastInput, None), X360Buttons::A);
+    assert_eq!(
+        sanitized(
+            SocdPolicy::LastInput,
+            Some(&dpad(X360Buttons::DPAD_UP | X360Buttons::DPAD_RIGHT))
+        ),
+        X360Buttons::DPAD_DOWN | X360Buttons::DPAD_LEFT | X360Buttons::A
+    );
+
+    // A dpad held in a single direction per pair is left alone
+    for policy in [
+        SocdPolicy::Neutral,
+        SocdPolicy::LastInput,
+        SocdPolicy::UpPriority,
+    ] {
+        let mut state = dpad(X360Buttons::DPAD_DOWN | X360Buttons::DPAD_LEFT);
+        state.sanitize(policy, Some(&dpad(X360Buttons::DPAD_UP)));
+        assert_eq!(state, dpad(X360Buttons::DPAD_DOWN | X360Buttons::DPAD_LEFT));
+    }
+}
+
+#[test]
+fn test_last_input_holds() {
+    let mut last = X360State::default();
+    let mut send = |buttons| {
+        let mut state = X360State::builder().press(buttons).build();
+        state.remember_dpad(&mut last);
+        state.sanitize(SocdPolicy::LastInput, Some(&last));
+        state.buttons
+    };
+    let both = X360Buttons::DPAD_LEFT | X360Buttons::DPAD_RIGHT;
+
+    // The direction pressed last keeps winning for as long as both are held
+    assert_eq!(send(X360Buttons::DPAD_LEFT), X360Buttons::DPAD_LEFT);
+    assert_eq!(send(both), X360Buttons::DPAD_RIGHT);
+    assert_eq!(send(both), X360Buttons::DPAD_RIGHT);
+    assert_eq!(send(X360Buttons::DPAD_RIGHT), X360Buttons::DPAD_RIGHT);
+    assert_eq!(send(both), X360Buttons::DPAD_LEFT);
+
+    // Pressing both at once after letting go wins neither
+    assert_eq!(send(X360Buttons::empty()), X360Buttons::empty());
+    assert_eq!(send(both), X360Buttons::empty());
+}
+
+#[test]
+fn test_diff_unchanged() {
+    let diff = STATE.diff(&STATE);
+    assert!(diff.is_empty());
+    assert_eq!(diff, StateDiff::default());
+    assert_eq!(diff.to_string(), "");
+}
+
+#[test]
+fn test_diff_buttons() {
+    let before = X360State::builder()
+        .press(X360Buttons::DPAD_LEFT | X360Buttons::B)
+        .build();
+    let after = X360State::builder()
+        .press(X360Buttons::A | X360Buttons::B)
+        .build();
+    let diff = before.diff(&after);
+    assert_eq!(diff.pressed, X360Buttons::A);
+    assert_eq!(diff.released, X360Buttons::DPAD_LEFT);
+    assert!(diff.axes.is_empty());
+    assert_eq!(diff.to_string(), "-DPAD_LEFT +A");
+}
+
+#[test]
+fn test_diff_axes() {
+    let before = X360State::builder().left_stick(120, 0).build();
+    let after = X360State::builder()
+        .left_trigger(255)
+        .left_stick(-3400, 0)
+        .build();
+    let diff = before.diff(&after);
+    assert!(diff.pressed.is_empty() && diff.released.is_empty());
+    assert_eq!(
+        diff.axes,
+        [
+            AxisChange {
+                axis: X360Axis::LeftTrigger,
+                from: 0,
+                to: 255
+            },
+            AxisChange {
+                axis: X360Axis::LeftX,
+                from: 120,
+                to: -3400
+            },
+        ]
+    );
+    assert_eq!(diff.to_string(), "LT:0→255 LX:120→-3400");
+
+    // Going back is the same changes the other way around
+    assert_eq!(after.diff(&before).to_string(), "LT:255→0 LX:-3400→120");
+}
+
+#[test]
+fn test_diff_combined() {
+    let before = X360State::builder()
+        .press(X360Buttons::DPAD_LEFT)
+        .right_stick(0, i16::MIN)
+        .build();
+    let after = X360State::builder()
+        .press(X360Buttons::A)
+        .left_trigger(255)
+        .right_stick(0, i16::MAX)
+        .build();
+    assert_eq!(
+        before.diff(&after).to_string(),
+        "-DPAD_LEFT +A LT:0→255 RY:-32768→32767"
+    );
+}
+
+#[test]
+fn test_diff_threshold() {
+    let resting = X360State::builder().left_stick(100, -100).build();
+    let jittered = X360State::builder()
+        .left_stick(100 + 256, -100 - 200)
+        .right_trigger(1)
+        .build();
+
+    // Jitter within the threshold is left out, but triggers move by whole steps
+    assert_eq!(resting.diff(&jittered).to_string(), "RT:0→1");
+    assert_eq!(
+        resting.diff_beyond(&jittered, 0).to_string(),
+        "RT:0→1 LX:100→356 LY:-100→-300"
+    );
+    // A threshold of a trigger's step or more leaves out its smallest moves too
+    assert!(resting.diff_beyond(&jittered, 257).is_empty());
+    assert_eq!(
+        resting
+            .diff(&X360State::builder().left_stick(100 + 257, -100).build())
+            .to_string(),
+        "LX:100→357"
+    );
+}
+
+#[cfg(feature = "schemars")]
+#[test]
+fn test_schema() {
+    use schemars::schema::{InstanceType, Schema, SingleOrVec};
+
+    let root = schemars::schema_for!(X360State);
+    let properties = &root.schema.object.as_ref().unwrap().properties;
+    assert_eq!(
+        properties.keys().collect::<Vec<_>>(),
+        [
+            "buttons",
+            "left_thumbstick",
+            "left_trigger",
+            "right_thumbstick",
+            "right_trigger"
+        ]
+    );
+
+    // Buttons are their bits and sticks are `[x, y]`, as they're serialized
+    let definition = |name: &str| match &root.definitions[name] {
+        Schema::Object(schema) => schema.instance_type.clone(),
+        Schema::Bool(_) => None,
+    };
+    assert_eq!(
+        definition("X360Buttons"),
+        Some(SingleOrVec::Single(Box::new(InstanceType::Integer)))
+    );
+    assert_eq!(
+        definition("StickPosition"),
+        Some(SingleOrVec::Single(Box::new(InstanceType::Array)))
+    );
+}
@@ -0,0 +1,58 @@
+//! Converting XInput's states, which builds anywhere.
+//! Run with `cargo test -p vigem-client-c --no-default-features --features xinput`.
+#![cfg(feature = "xinput")]
+
+use vigem_client_c::{xinput::XInputGamepad, X360Buttons, X360State};
+
+#[test]
+fn test_neutral() {
+    assert_eq!(
+        X360State::from(XInputGamepad::default()),
+        X360State::default()
+    );
+}
+
+#[test]
+fn test_buttons() {
+    let gamepad = XInputGamepad {
+        // DPAD_UP, DPAD_RIGHT, START, RIGHT_SHOULDER, A and Y
+        buttons: 0x0001 | 0x0008 | 0x0010 | 0x0200 | 0x1000 | 0x8000,
+        ..XInputGamepad::default()
+    };
+    assert_eq!(
+        X360State::from(gamepad).buttons,
+        X360Buttons::DPAD_UP
+            | X360Buttons::DPAD_RIGHT
+            | X360Buttons::START
+            | X360Buttons::RIGHT_SHOULDER
+            | X360Buttons::A
+            | X360Buttons::Y
+    );
+
+    let every = XInputGamepad {
+        buttons: 0xffff,
+        ..XInputGamepad::default()
+    };
+    assert_eq!(
+        X360State::from(every).buttons,
+        X360Buttons::all() - X360Buttons::GUIDE
+    );
+}
+
+#[test]
+fn test_axes() {
+    let gamepad = XInputGamepad {
+        buttons: 0,
+        left_trigger: 12,
+        right_trigger: 255,
+        thumb_lx: -32768,
+        thumb_ly: 32767,
+        thumb_rx: 1234,
+        thumb_ry: -5678,
+    };
+    let state = X360State::from(gamepad);
+    assert_eq!(state.left_trigger, 12);
+    assert_eq!(state.right_trigger, 255);
+    assert_eq!(state.left_thumbstick, (-32768, 32767));
+    assert_eq!(state.right_thumbstick, (1234, -5678));
+}
@@ -0,0 +1,10 @@
+use vigem_client_c::Client;
+
+fn main() {
+    let client = Client::new().unwrap();
+    let mut pad = client.connect_x360_pad().unwrap();
+    let handle = pad.register_notification(|_| {}).unwrap();
+    let forged = handle.clone();
+    pad.unregister_notification(handle).unwrap();
+    pad.unregister_notification(forged).unwrap();
+}
@@ -0,0 +1,9 @@
+use vigem_client_c::Client;
+
+fn main() {
+    let pad = {
+        let client = Client::new().unwrap();
+        client.connect_x360_pad().unwrap()
+    };
+    drop(pad);
+}